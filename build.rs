@@ -1,6 +1,8 @@
 fn main() {
     let target = std::env::var("TARGET").unwrap();
-    if !target.contains("wasm32") {
+    // only the SDL2 frontend needs SDL2; the core library builds without it
+    let frontend_sdl = std::env::var_os("CARGO_FEATURE_FRONTEND_SDL").is_some();
+    if !target.contains("wasm32") && frontend_sdl {
         println!("cargo:rustc-link-search=framework=/Library/Frameworks");
         println!("cargo:rustc-link-lib=SDL2");
     }
@@ -0,0 +1,87 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The revision of the core's observable behaviour: anything that changes what a game sees
+/// or what the core outputs for the same inputs (timing, register values, pixels, samples)
+/// bumps it, with an entry in ACCURACY_CHANGELOG. Recordings of exact output (input movies,
+/// save states, test baselines) store it, so replaying them on another revision fails
+/// loudly through check_accuracy_rev() instead of silently desyncing.
+pub const ACCURACY_REV: u32 = 4;
+
+/// The crate version, for telling builds apart in reports; unlike ACCURACY_REV it also
+/// changes for releases that emulate exactly like the last one.
+#[allow(dead_code)]
+pub const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What changed in every revision, oldest first (the last one is ACCURACY_REV).
+#[allow(dead_code)]
+pub const ACCURACY_CHANGELOG: [(u32, &str); 4] = [
+    (1, "First recorded revision: emulated boot, exact-rate resampled audio, \
+        whole-frame PPU mode 3 timing with object and window penalties"),
+    (2, "APU: channels with their DAC off output nothing instead of their lowest level, \
+        and the mix goes through the output capacitors' high-pass filter"),
+    (3, "APU: zombie mode volume changes on NRx2 writes, DMG length counters kept through \
+        power-off, and DMG wave RAM only corrupted by triggers"),
+    (4, "PPU: frames blank to palette colour 0 (white on CGB) while the LCD is off, \
+        instead of holding the last one"),
+];
+
+/// A recording made on a different behaviour revision than the one running.
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RevMismatch {
+    pub recorded: u32,
+    pub running: u32,
+}
+
+#[allow(dead_code)]
+impl RevMismatch {
+    /// Returns the changelog entries between the two revisions, which may explain a desync.
+    pub fn changes(&self) -> Vec<(u32, &'static str)> {
+        let (from, to) = (self.recorded.min(self.running), self.recorded.max(self.running));
+        ACCURACY_CHANGELOG.iter()
+            .filter(|(rev, _)| (from + 1..=to).contains(rev))
+            .copied()
+            .collect()
+    }
+}
+
+impl fmt::Display for RevMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let age = if self.recorded < self.running { "older" } else { "newer" };
+        write!(f, "Recorded on accuracy revision {} ({} than this core's {})", self.recorded, age, self.running)?;
+        for (rev, change) in self.changes() {
+            write!(f, "\n  rev {}: {}", rev, change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that something recorded on revision recorded replays the same on this core.
+#[allow(dead_code)]
+pub fn check_accuracy_rev(recorded: u32) -> Result<(), RevMismatch> {
+    match recorded {
+        ACCURACY_REV => Ok(()),
+        _ => Err(RevMismatch { recorded, running: ACCURACY_REV }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accuracy_rev_test() {
+        assert_eq!(ACCURACY_CHANGELOG.last().unwrap().0, ACCURACY_REV);
+        assert!(ACCURACY_CHANGELOG.windows(2).all(|revs| revs[0].0 + 1 == revs[1].0));
+
+        assert_eq!(check_accuracy_rev(ACCURACY_REV), Ok(()));
+
+        let mismatch = check_accuracy_rev(ACCURACY_REV + 1).unwrap_err();
+        assert!(mismatch.changes().is_empty());
+        assert!(mismatch.to_string().contains("newer"));
+        let mismatch = check_accuracy_rev(0).unwrap_err();
+        assert_eq!(mismatch.changes(), ACCURACY_CHANGELOG);
+        assert_eq!(check_accuracy_rev(1).unwrap_err().changes(), ACCURACY_CHANGELOG[1..]);
+    }
+}
@@ -1,4 +1,5 @@
 use super::{Envelope, LengthCounter};
+use crate::savestate::snapshot_fields;
 
 const LENGTH_TICKS: u32 = 64;
 
@@ -29,13 +30,13 @@ impl Noise {
         }
     }
 
-    pub fn power_off(&mut self) {
+    pub fn power_off(&mut self, keep_length: bool) {
         self.power_on = false;
         self.nr41 = 0;
         self.nr42 = 0;
         self.nr43 = 0;
         self.nr44 = 0;
-        self.length_counter = LengthCounter::new(LENGTH_TICKS);
+        self.length_counter.power_off(keep_length);
         self.lfsr = Lfsr::new();
         self.envelope = Envelope::new();
         self.dac_on = false;
@@ -45,12 +46,19 @@ impl Noise {
         self.power_on = true;
     }
 
-    pub fn make_sample(&mut self) -> u8 {
+    /// Advances the channel by an M-cycle.
+    pub fn step(&mut self) {
+        if self.length_counter.channel_on() && self.dac_on {
+            self.lfsr.step();
+        }
+    }
+
+    /// Returns the channel's current level (0-15).
+    pub fn output(&self) -> u8 {
         if !self.length_counter.channel_on() || !self.dac_on {
             return 0;
         }
-        let sample = self.lfsr.next_sample();
-        sample * self.envelope.volume()
+        self.lfsr.output() * self.envelope.volume()
     }
 
     pub fn frame_sequencer_step(&mut self) {
@@ -96,7 +104,7 @@ impl Noise {
     fn write_nr42(&mut self, byte: u8) {
         self.nr42 = byte;
 
-        self.envelope.set(byte);
+        self.envelope.set(byte, self.length_counter.channel_on());
         self.dac_on = byte & 0xF8 != 0;
 
         if !self.dac_on {
@@ -145,14 +153,16 @@ impl Lfsr {
         }
     }
 
-    pub fn next_sample(&mut self) -> u8 {
+    pub fn step(&mut self) {
         if self.shift_period == 0 {
             self.set_shift_period();
             self.do_shift()
         } else {
             self.shift_period -= 1;
         }
+    }
 
+    pub fn output(&self) -> u8 {
         (!self.shift_register as u8) & 0x01
     }
 
@@ -181,4 +191,7 @@ impl Lfsr {
 
         self.shift_period = divisor << self.shift_amount;
     }
-}
\ No newline at end of file
+}
+
+snapshot_fields!(Noise, nr41, nr42, nr43, nr44, length_counter, lfsr, envelope, dac_on, power_on);
+snapshot_fields!(Lfsr, shift_register, width, shift_period, divisor_code, shift_amount);
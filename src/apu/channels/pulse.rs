@@ -1,4 +1,5 @@
 use super::{Envelope, LengthCounter, Sweep, MAX_PERIOD};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
 
 
 
@@ -50,14 +51,14 @@ impl Pulse {
         }
     }
 
-    pub fn power_off(&mut self) {
+    pub fn power_off(&mut self, keep_length: bool) {
         self.power_on = false;
         self.nrx0 = 0;
         self.nrx1 = 0;
         self.nrx2 = 0;
         self.nrx3 = 0;
         self.nrx4 = 0;
-        self.length_counter = LengthCounter::new(LENGTH_TICKS);
+        self.length_counter.power_off(keep_length);
         self.envelope = Envelope::new();
         self.dac_on = false;
         self.duty_index = 0;
@@ -73,9 +74,10 @@ impl Pulse {
         self.power_on = true;
     }
 
-    pub fn make_sample(&mut self) -> u8 {
+    /// Advances the channel by an M-cycle.
+    pub fn step(&mut self) {
         if !self.length_counter.channel_on() || !self.dac_on {
-            return 0;
+            return;
         }
 
         if self.freq_counter >= (MAX_PERIOD - self.period_value()) {
@@ -83,6 +85,13 @@ impl Pulse {
             self.duty_index = (self.duty_index + 1) % DUTY_SAMPLE_SIZE;
         }
         self.freq_counter += 1;
+    }
+
+    /// Returns the channel's current level (0-15).
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.channel_on() || !self.dac_on {
+            return 0;
+        }
 
         let duty_select = (self.nrx1 as usize & 0xC0) >> 6;
         let sample = DUTY_TABLE[duty_select][self.duty_index];
@@ -152,7 +161,7 @@ impl Pulse {
     fn write_nrx2(&mut self, byte: u8) {
         self.nrx2 = byte;
 
-        self.envelope.set(byte);
+        self.envelope.set(byte, self.length_counter.channel_on());
         self.dac_on = byte & 0xF8 != 0;
 
         if !self.dac_on {
@@ -200,4 +209,44 @@ impl Pulse {
     fn period_value(&self) -> u32 {
         (self.nrx4 as u32 & 7) << 8 | self.nrx3 as u32
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Pulse {
+    fn save(&self, state: &mut StateWriter) {
+        self.nrx0.save(state);
+        self.nrx1.save(state);
+        self.nrx2.save(state);
+        self.nrx3.save(state);
+        self.nrx4.save(state);
+        self.length_counter.save(state);
+        self.envelope.save(state);
+        // only pulse 1 has a sweep, in every core
+        if let Some(sweep) = &self.sweep {
+            sweep.save(state);
+        }
+        self.dac_on.save(state);
+        self.duty_index.save(state);
+        self.freq_counter.save(state);
+        self.power_on.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.nrx0.load(state)?;
+        self.nrx1.load(state)?;
+        self.nrx2.load(state)?;
+        self.nrx3.load(state)?;
+        self.nrx4.load(state)?;
+        self.length_counter.load(state)?;
+        self.envelope.load(state)?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.load(state)?;
+        }
+        self.dac_on.load(state)?;
+        self.duty_index.load(state)?;
+        if self.duty_index >= DUTY_SAMPLE_SIZE {
+            return Err(StateError::Invalid("duty step"));
+        }
+        self.freq_counter.load(state)?;
+        self.power_on.load(state)
+    }
+}
@@ -1,6 +1,7 @@
 use crate::cpu::GBModel;
 
 use super::{LengthCounter, MAX_PERIOD, WAVE_RAM_START};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
 
 const WAVE_RAM_SIZE: usize = 16;
 const LENGTH_TICKS: u32 = 256;
@@ -42,7 +43,7 @@ impl Wave {
         }
     }
 
-    pub fn power_off(&mut self) {
+    pub fn power_off(&mut self, keep_length: bool) {
         self.power_on = false;
         self.nr30 = 0;
         self.nr31 = 0;
@@ -50,7 +51,7 @@ impl Wave {
         self.nr33 = 0;
         self.nr34 = 0;
         self.dac_on = false;
-        self.length_counter = LengthCounter::new(LENGTH_TICKS);
+        self.length_counter.power_off(keep_length);
         self.sample_index = 0;
         self.sample_buffer = 0;
         self.freq_counter = 0;
@@ -60,19 +61,12 @@ impl Wave {
         self.power_on = true;
     }
 
-    pub fn make_sample(&mut self) -> u8 {
+    /// Advances the channel by an M-cycle.
+    pub fn step(&mut self) {
         if !self.length_counter.channel_on() || !self.dac_on {
-            return 0;
+            return;
         }
 
-        let volume = match (self.nr32 & 0x60) >> 5 {
-            0 => 4,
-            1 => 0,
-            2 => 1,
-            3 => 2,
-            _ => unreachable!()
-        };
-
         if self.freq_counter <= 2 {
             if self.freq_counter == 2 {
                 self.wave_reads_0xff = false;
@@ -89,6 +83,21 @@ impl Wave {
             self.freq_counter -= 2;
             self.wave_reads_0xff = true;
         }
+    }
+
+    /// Returns the channel's current level (0-15).
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.channel_on() || !self.dac_on {
+            return 0;
+        }
+
+        let volume = match (self.nr32 & 0x60) >> 5 {
+            0 => 4,
+            1 => 0,
+            2 => 1,
+            3 => 2,
+            _ => unreachable!()
+        };
 
         let sample_nibble = if self.sample_index % 2 == 0 {
             (self.sample_buffer & 0xF0) >> 4
@@ -139,7 +148,7 @@ impl Wave {
             // channel is on will access the current sample buffer IF it was updated 
             // by wave RAM at most 2 cycles ago; 
             // otherwise reads return 0xFF and write does nothing.
-            if matches!(self.model, GBModel::DMG) && self.wave_reads_0xff {
+            if matches!(self.model, GBModel::DMG | GBModel::SGB) && self.wave_reads_0xff {
                0xFF
             } else {
                 self.sample_buffer
@@ -152,7 +161,7 @@ impl Wave {
     pub fn write_wave_ram(&mut self, addr: usize, byte: u8) {
         if self.channel_on() && self.dac_on() {
             // Obscure Behavior (DMG only): SEE ABOVE
-            if matches!(self.model, GBModel::DMG) && self.wave_reads_0xff {
+            if matches!(self.model, GBModel::DMG | GBModel::SGB) && self.wave_reads_0xff {
                 
             } else {
                 self.wave_ram[self.sample_index / 2] = byte;
@@ -183,7 +192,7 @@ impl Wave {
 
         // Obscure Behavior (DMG only): Triggering while sample byte is being processed
         // corrupts wave RAM based on the current sample's position.
-        if matches!(self.model, GBModel::DMG) {
+        if matches!(self.model, GBModel::DMG | GBModel::SGB) && byte & 0x80 != 0 {
             if self.channel_on() && self.dac_on && self.freq_counter <= 1 {
                 let next_byte_index = ((self.sample_index + 1) / 2) % WAVE_RAM_SIZE;
                 if next_byte_index < 4 {
@@ -210,4 +219,41 @@ impl Wave {
     fn period_value(&self) -> u32 {
        (self.nr34 as u32 & 7) << 8 | self.nr33 as u32
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Wave {
+    fn save(&self, state: &mut StateWriter) {
+        self.nr30.save(state);
+        self.nr31.save(state);
+        self.nr32.save(state);
+        self.nr33.save(state);
+        self.nr34.save(state);
+        self.wave_ram.save(state);
+        self.sample_buffer.save(state);
+        self.wave_reads_0xff.save(state);
+        self.dac_on.save(state);
+        self.length_counter.save(state);
+        self.sample_index.save(state);
+        self.freq_counter.save(state);
+        self.power_on.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.nr30.load(state)?;
+        self.nr31.load(state)?;
+        self.nr32.load(state)?;
+        self.nr33.load(state)?;
+        self.nr34.load(state)?;
+        self.wave_ram.load(state)?;
+        self.sample_buffer.load(state)?;
+        self.wave_reads_0xff.load(state)?;
+        self.dac_on.load(state)?;
+        self.length_counter.load(state)?;
+        self.sample_index.load(state)?;
+        if self.sample_index >= 2 * WAVE_RAM_SIZE {
+            return Err(StateError::Invalid("wave position"));
+        }
+        self.freq_counter.load(state)?;
+        self.power_on.load(state)
+    }
+}
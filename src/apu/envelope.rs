@@ -1,3 +1,5 @@
+use crate::savestate::snapshot_fields;
+
 pub struct Envelope {
     fs_ticks: u8,
     cur_volume: u8,
@@ -5,6 +7,8 @@ pub struct Envelope {
     initial_volume: u8,
     envelope_up: bool,
     sweep_ticks: u8,
+    // cleared once the volume can't go any further, which stops the envelope until a trigger
+    updating: bool,
 }
 
 impl Envelope {
@@ -16,6 +20,7 @@ impl Envelope {
             sweep_pace: 0,
             initial_volume: 0,
             envelope_up: false,
+            updating: false,
         }
     }
 
@@ -40,6 +45,8 @@ impl Envelope {
     
                 if 0x0 <= next_volume && next_volume <= 0xF {
                     self.cur_volume = next_volume as u8;
+                } else {
+                    self.updating = false;
                 }
             }
         }
@@ -48,9 +55,28 @@ impl Envelope {
     pub fn on_trigger(&mut self) {
         self.sweep_ticks = 0;
         self.cur_volume = self.initial_volume;
+        self.updating = true;
     }
 
-    pub fn set(&mut self, byte: u8) {
+    /// Sets the envelope from NRx2; channel_on is whether the channel is playing.
+    pub fn set(&mut self, byte: u8, channel_on: bool) {
+        // Obscure Behavior ("zombie mode"): writing NRx2 while the channel plays changes its
+        // volume right away: +1 if the envelope had no pace but was still running, otherwise
+        // +2 if it was going down; then flipped to 16 - volume if the direction changes
+        // (all wrapping around 4 bits)
+        if channel_on {
+            let mut volume = self.cur_volume;
+            if self.sweep_pace == 0 && self.updating {
+                volume += 1;
+            } else if !self.envelope_up {
+                volume += 2;
+            }
+            if self.envelope_up != (byte & 8 != 0) {
+                volume = 16u8.wrapping_sub(volume);
+            }
+            self.cur_volume = volume & 0xF;
+        }
+
         self.initial_volume = (byte & 0xF0) >> 4;
         self.envelope_up = byte & 8 != 0;
         self.sweep_pace = byte & 7;
@@ -61,3 +87,35 @@ impl Envelope {
     }
 }
 
+snapshot_fields!(Envelope, fs_ticks, cur_volume, sweep_pace, initial_volume, envelope_up, sweep_ticks, updating);
+
+#[cfg(test)]
+mod tests {
+    use super::Envelope;
+
+    #[test]
+    fn zombie_mode_test() {
+        // volume 8 going down, with no pace
+        let mut envelope = Envelope::new();
+        envelope.set(0x80, false);
+        envelope.on_trigger();
+        assert_eq!(envelope.volume(), 8);
+
+        // no pace but still running: +1
+        envelope.set(0x81, true);
+        assert_eq!(envelope.volume(), 9);
+        // going down: +2, then flipped by going up instead
+        envelope.set(0x89, true);
+        assert_eq!(envelope.volume(), 16 - 11);
+        // it wraps around
+        let mut envelope = Envelope::new();
+        envelope.set(0xF0, false);
+        envelope.on_trigger();
+        envelope.set(0xF1, true);
+        assert_eq!(envelope.volume(), 0);
+
+        // writes to a channel that isn't playing only set the next trigger's volume
+        envelope.set(0x30, false);
+        assert_eq!(envelope.volume(), 0);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::constants::T_CYCLE_HZ;
+use crate::cpu::GBModel;
+
+/// The capacitors in series with the Game Boy's left and right outputs. They block DC, so
+/// under a steady level (e.g. a channel whose DAC turned off) the output drifts back to the
+/// middle instead of staying offset, and hardware recordings never sit away from silence.
+pub struct HighPass {
+    // how much of their charge the capacitors keep every T-cycle, which depends on the model
+    t_cycle_charge: f64,
+    // how much they keep every output sample
+    charge: f32,
+    capacitors: [f32; 2],
+}
+
+impl HighPass {
+    pub fn new(model: GBModel, sample_rate: u32) -> Self {
+        let t_cycle_charge = match model {
+            GBModel::DMG | GBModel::SGB => 0.999958,
+            GBModel::CGB | GBModel::AGB => 0.998943,
+        };
+        let mut high_pass = HighPass { t_cycle_charge, charge: 0.0, capacitors: [0.0; 2] };
+        high_pass.set_sample_rate(sample_rate);
+        high_pass
+    }
+
+    pub fn set_sample_rate(&mut self, hz: u32) {
+        self.charge = libm::pow(self.t_cycle_charge, T_CYCLE_HZ as f64 / hz.max(1) as f64) as f32;
+    }
+
+    /// Filters a stereo sample; with every DAC off nothing drives the output, which is silent.
+    pub fn filter(&mut self, sample: [f32; 2], dacs_on: bool) -> [f32; 2] {
+        if !dacs_on {
+            return [0.0; 2];
+        }
+
+        let mut output = [0.0; 2];
+        for ((output, &input), capacitor) in output.iter_mut().zip(&sample).zip(&mut self.capacitors) {
+            *output = input - *capacitor;
+            *capacitor = input - *output * self.charge;
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HighPass;
+    use crate::cpu::GBModel;
+
+    #[test]
+    fn high_pass_test() {
+        let mut high_pass = HighPass::new(GBModel::DMG, 48000);
+
+        // a step goes through, then decays back to silence (a few ms on the DMG)
+        assert_eq!(high_pass.filter([1.0, -1.0], true), [1.0, -1.0]);
+        let outputs: Vec<[f32; 2]> = (0..4800).map(|_| high_pass.filter([1.0, -1.0], true)).collect();
+        assert!(outputs.windows(2).all(|pair| pair[1][0] <= pair[0][0] && pair[1][1] >= pair[0][1]));
+        assert!(outputs[4799][0].abs() < 0.01);
+
+        // the CGB's capacitor charges faster
+        let mut cgb = HighPass::new(GBModel::CGB, 48000);
+        let cgb_output = (0..100).map(|_| cgb.filter([1.0; 2], true)).last().unwrap();
+        let mut dmg = HighPass::new(GBModel::DMG, 48000);
+        let dmg_output = (0..100).map(|_| dmg.filter([1.0; 2], true)).last().unwrap();
+        assert!(cgb_output[0] < dmg_output[0]);
+
+        assert_eq!(high_pass.filter([1.0; 2], false), [0.0; 2]);
+    }
+}
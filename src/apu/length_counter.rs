@@ -1,3 +1,5 @@
+use crate::savestate::snapshot_fields;
+
 pub struct LengthCounter {
     fs_ticks: u8,
     channel_on: bool,
@@ -63,6 +65,16 @@ impl LengthCounter {
         }
     }
 
+    /// Resets the counter as the APU powers off; keep_length keeps the length that's left
+    /// (the DMG's counters aren't powered by the APU, so they're also writable while it's off).
+    pub fn power_off(&mut self, keep_length: bool) {
+        let ticks = self.ticks;
+        *self = LengthCounter::new(self.max_ticks);
+        if keep_length {
+            self.ticks = ticks;
+        }
+    }
+
     pub fn turn_off_channel(&mut self) {
         self.channel_on = false;
     }
@@ -74,4 +86,6 @@ impl LengthCounter {
     pub fn channel_on(&self) -> bool {
         self.channel_on
     }
-}
\ No newline at end of file
+}
+
+snapshot_fields!(LengthCounter, fs_ticks, channel_on, enabled, ticks, max_ticks);
@@ -1,21 +1,40 @@
 mod channels;
 mod envelope;
+mod high_pass;
 mod length_counter;
+mod resampler;
 mod sweep;
 
-use crate::config::{AUDIO_SAMPLES, SAMPLING_RATE_HZ};
-use crate::constants::M_CYCLE_HZ;
+use alloc::vec;
+use crate::config::{AUDIO_SAMPLES, HIGH_PASS, RESAMPLE_QUALITY, SAMPLING_RATE_HZ};
 use crate::cpu::GBModel;
+use crate::events::Timestamp;
 use self::channels::*;
 use envelope::Envelope;
+use high_pass::HighPass;
 use length_counter::LengthCounter;
+use resampler::Resampler;
+pub use resampler::ResampleQuality;
 use sweep::Sweep;
+use crate::savestate::snapshot_fields;
 
 const MAX_PERIOD: u32 = 2048;
 
 const WAVE_RAM_START: usize = 0xFF30;
 const WAVE_RAM_END: usize = 0xFF3F;
 
+/// The channels in the order set_channel_enabled() numbers them.
+#[allow(dead_code)]
+pub const CHANNEL_NAMES: [&str; 4] = ["Pulse 1", "Pulse 2", "Wave", "Noise"];
+
+/// A write to an APU register or wave RAM (see Cpu::set_apu_write_log).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ApuWrite {
+    pub time: Timestamp,
+    pub addr: u16,
+    pub value: u8,
+}
+
 pub struct Apu {
     model: GBModel,
     apu_on: bool,
@@ -25,7 +44,14 @@ pub struct Apu {
     noise: Noise,
     audio_buffer: [[f32; 2]; AUDIO_SAMPLES * 4],
     buffer_index: usize,
-    sample_gather: u32,
+    resampler: Resampler,
+    // channels left out of the mix (see set_channel_enabled)
+    channels_enabled: [bool; 4],
+    high_pass: HighPass,
+    // the output goes through high_pass (see set_high_pass)
+    high_pass_on: bool,
+    // samples are produced at all (see set_output_enabled)
+    output_enabled: bool,
     nr52: u8,
     nr51: u8,
     nr50: u8,
@@ -46,7 +72,11 @@ impl Apu {
             noise: Noise::new(),
             audio_buffer: [[0.0; 2]; AUDIO_SAMPLES * 4],
             buffer_index: 0,
-            sample_gather: 0,
+            resampler: Resampler::new(SAMPLING_RATE_HZ, RESAMPLE_QUALITY),
+            channels_enabled: [true; 4],
+            high_pass: HighPass::new(model, SAMPLING_RATE_HZ),
+            high_pass_on: HIGH_PASS,
+            output_enabled: true,
             nr52: 0,
             nr51: 0,
             nr50: 0,
@@ -76,35 +106,89 @@ impl Apu {
         for _ in 0..t_cycles {
             self.t_cycles = self.t_cycles.wrapping_add(1);
             if self.t_cycles % 4 == 0 {
-                
-                let pulse1_sample = self.pulse1.make_sample();
-                let pulse2_sample = self.pulse2.make_sample();
-                let wave_sample = self.wave.make_sample();
-                let noise_sample = self.noise.make_sample();
+                self.pulse1.step();
+                self.pulse2.step();
+                self.wave  .step();
+                self.noise .step();
+
+                if !self.model.is_cgb() && !self.output_enabled {
+                    continue;
+                }
+
+                let pulse1_sample = self.pulse1.output();
+                let pulse2_sample = self.pulse2.output();
+                let wave_sample = self.wave.output();
+                let noise_sample = self.noise.output();
 
-                if matches!(self.model, GBModel::CGB) {
+                if self.model.is_cgb() {
                     self.pcm12 = (pulse2_sample << 4) | pulse1_sample;
                     self.pcm34 = (noise_sample << 4) | wave_sample; 
                 }
-                
-                if self.sample_gather == (M_CYCLE_HZ / SAMPLING_RATE_HZ) {
-                    self.sample_gather = 0;
-                    self.push_samples_to_buffer(pulse1_sample, pulse2_sample, wave_sample, noise_sample)
+
+                if self.output_enabled {
+                    let levels = [pulse1_sample, pulse2_sample, wave_sample, noise_sample];
+                    if let Some(levels) = self.resampler.push(levels) {
+                        self.push_samples_to_buffer(levels)
+                    }
                 }
-                self.sample_gather += 1;
             }
         }
     }
 
-    fn push_samples_to_buffer(&mut self, pulse1_sample: u8, pulse2_sample: u8, wave_sample: u8, noise_sample: u8) {
+    /// Produces samples at hz from now on (SAMPLING_RATE_HZ by default), however odd the rate;
+    /// it can be changed at any time (e.g. by rate control) without a seam in the audio.
+    pub fn set_sample_rate(&mut self, hz: u32) {
+        self.resampler.set_output_hz(hz);
+        self.high_pass.set_sample_rate(hz);
+    }
+
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.resampler = Resampler::new(self.resampler.output_hz(), quality);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.output_hz()
+    }
+
+    /// Mutes (or unmutes) channel ch (0-3, see CHANNEL_NAMES) in the output, e.g. to rip one
+    /// instrument or hear what a homebrew channel is doing. The game can't tell: the channel
+    /// keeps running, and PCM12/PCM34 still read its output.
+    pub fn set_channel_enabled(&mut self, ch: usize, enabled: bool) {
+        self.channels_enabled[ch] = enabled;
+    }
+
+    pub fn channel_enabled(&self, ch: usize) -> bool {
+        self.channels_enabled[ch]
+    }
+
+    /// Stops (or starts again) producing samples, e.g. while the frontend's audio is muted
+    /// or hidden, which saves resampling and mixing them. The channels keep running as games
+    /// see them (lengths, envelopes, sweeps, NR52, PCM12/PCM34), only nothing is heard.
+    pub fn set_output_enabled(&mut self, enabled: bool) {
+        self.output_enabled = enabled;
+    }
+
+    /// Runs the output through the high-pass filter of the hardware's output capacitors
+    /// (see config::HIGH_PASS), or leaves it raw, offset by whatever DC the channels output.
+    pub fn set_high_pass(&mut self, on: bool) {
+        self.high_pass_on = on;
+    }
+
+    fn push_samples_to_buffer(&mut self, levels: [f32; 4]) {
         if self.buffer_index >= AUDIO_SAMPLES {
             self.buffer_index = 0;
         }
 
-        let pulse1_sample = Apu::to_analog(pulse1_sample);
-        let pulse2_sample = Apu::to_analog(pulse2_sample);
-        let wave_sample = Apu::to_analog(wave_sample);
-        let noise_sample = Apu::to_analog(noise_sample);
+        let dacs_on = [self.pulse1.dac_on(), self.pulse2.dac_on(), self.wave.dac_on(), self.noise.dac_on()];
+        let mut samples = levels.map(Apu::to_analog);
+        // DACs that are off output nothing, rather than their lowest level; muted channels
+        // are left out the same way, so the others keep their volume
+        for ((sample, &enabled), &dac_on) in samples.iter_mut().zip(&self.channels_enabled).zip(&dacs_on) {
+            if !enabled || !dac_on {
+                *sample = 0.0;
+            }
+        }
+        let [pulse1_sample, pulse2_sample, wave_sample, noise_sample] = samples;
 
         let mut right_sample = 0.0;
         if self.nr51 & 0x01 != 0 { right_sample += pulse1_sample }
@@ -122,11 +206,21 @@ impl Apu {
         left_sample /= (self.nr51 & 0xF0).count_ones() as f32;
         left_sample *= (((self.nr50 >> 4) & 7) + 1) as f32 / 8.0;
 
+        let [left_sample, right_sample] = match self.high_pass_on {
+            true => self.high_pass.filter([left_sample, right_sample], dacs_on.contains(&true)),
+            false => [left_sample, right_sample],
+        };
+
         self.audio_buffer[self.buffer_index][0] = left_sample;
         self.audio_buffer[self.buffer_index][1] = right_sample;
         self.buffer_index += 1;
     }
 
+    /// Returns how many samples are waiting in the buffer.
+    pub fn buffered_samples(&self) -> usize {
+        self.buffer_index
+    }
+
     pub fn get_audio_output(&mut self) -> Option<[[f32; 2]; AUDIO_SAMPLES]> {
         if self.buffer_index < AUDIO_SAMPLES {
             return None;
@@ -143,8 +237,8 @@ impl Apu {
         Some(res)
     }
 
-    pub fn to_analog(sample: u8) -> f32 {
-        -1.0 + (sample as f32 / 7.5)
+    pub fn to_analog(level: f32) -> f32 {
+        -1.0 + (level / 7.5)
     }
 
     pub fn read_io(&self, addr: usize) -> u8 {
@@ -167,8 +261,8 @@ impl Apu {
     pub fn write_io(&mut self, addr: usize, byte: u8) {
         // NR52 and (for DMG only) all length counters are preserved and writable while APU is powered off
         let apu_off_readable = match self.model {
-            GBModel::DMG => vec![0xFF26, 0xFF11, 0xFF16, 0xFF1B, 0xFF20],
-            GBModel::CGB => vec![0xFF26],
+            GBModel::DMG | GBModel::SGB => vec![0xFF26, 0xFF11, 0xFF16, 0xFF1B, 0xFF20],
+            GBModel::CGB | GBModel::AGB => vec![0xFF26],
         };
         if !self.apu_on && !apu_off_readable.contains(&addr) {
             return;
@@ -216,107 +310,190 @@ impl Apu {
         self.noise.power_on();
     }
 
+    /// Clears every register but NR52 and wave RAM; on the DMG, lengths are kept.
     fn turn_off_apu(&mut self) {
+        let keep_lengths = !self.model.is_cgb();
         self.apu_on = false;
         self.nr50 = 0;
         self.nr51 = 0;
-        self.pulse1.power_off();
-        self.pulse2.power_off();
-        self.wave.power_off();
-        self.noise.power_off();
+        self.pulse1.power_off(keep_lengths);
+        self.pulse2.power_off(keep_lengths);
+        self.wave.power_off(keep_lengths);
+        self.noise.power_off(keep_lengths);
     }
 
 }
 
+// the output buffer, resampler and filter keep going as they are, as the game never sees them
+snapshot_fields!(Apu, apu_on, pulse1, pulse2, wave, noise, nr52, nr51, nr50, t_cycles, pcm12, pcm34);
+
 #[cfg(test)]
 mod tests {
-    use crate::{bus::{RAM_END, RAM_START}, cartridge::Cartridge, cpu::Cpu};
+    use crate::{cartridge::Cartridge, cpu::Cpu};
+    use crate::config::{AUDIO_SAMPLES, SAMPLING_RATE_HZ};
+    use crate::constants::T_CYCLE_HZ;
+    use crate::cpu::{test_helpers::{make_test_rom, test_blargg_rom_memory}, GBModel};
 
     const DMG_SOUND: &str = "roms/tests/dmg_sound.gb";
     const CGB_SOUND: &str = "roms/tests/cgb_sound.gb";
-    const TEST_NUMS: [&str; 12] = [
-        "01:", "02:", "03:", "04:", 
-        "05:", "06:", "07:", "08:", 
-        "09:", "10:", "11:", "12:", 
-    ];
 
     #[test]
     fn apu_dmg_sound_test() {
-        let mut cartridge = Cartridge::from_file(DMG_SOUND, false);
-        for i in RAM_START..RAM_END {
-            cartridge.write_ram(i, 0);
-        }
-        let mut cpu = Cpu::new(cartridge, crate::cpu::GBModel::DMG);
-    
-        let mut cycles: u64 = 0;
-        let mut test_num = 1;
-        while cycles < (1 << 32) {
-            cycles += cpu.step() as u64;
-
-            if cycles % (1 << 18) == 0 {
-                let mut output = String::new();
-
-                for i in RAM_START..RAM_END {
-                    let byte = cpu.read_byte(i as u16);
-                    if byte != 0 {
-                        output.push(char::from(byte));
-                    }
-                }
+        test_blargg_rom_memory(DMG_SOUND, GBModel::DMG);
+    }
 
-                if output.contains(TEST_NUMS[test_num - 1]) {
-                    let mut pass_output = String::from(TEST_NUMS[test_num - 1]);
-                    pass_output.push_str("ok");
-                    if output.contains(&pass_output) {
-                        println!("dmg_sound: Test #{} Passed", test_num);
-                        test_num += 1;
-                        if test_num > 12 {
-                            break;
-                        }
-                    } else {
-                        panic!("dmg_sound: Test #{} Failed", test_num);
-                    }
-                }
+    #[test]
+    fn apu_cgb_sound_test() {
+        test_blargg_rom_memory(CGB_SOUND, GBModel::CGB);
+    }
+
+    // the VBlank (counting from power on) the A/V sync ROM starts its tone at
+    const TONE_FRAME: u8 = 10;
+
+    // A/V sync test ROM: resets the APU with pulse 1 set up but silent, counts VBlanks
+    // until TONE_FRAME, then plays a ~1 kHz square wave on pulse 1 from then on
+    const AV_SYNC_ROM: [u8; 50] = [
+        0xAF,             //       XOR A
+        0xE0, 0x26,       //       LDH (NR52), A    ; APU off, clearing its registers...
+        0x3E, 0x80,       //       LD A, $80
+        0xE0, 0x26,       //       LDH (NR52), A    ; ...and back on
+        0x3E, 0x77,       //       LD A, $77
+        0xE0, 0x24,       //       LDH (NR50), A    ; full volume
+        0x3E, 0x11,       //       LD A, $11
+        0xE0, 0x25,       //       LDH (NR51), A    ; pulse 1 on both sides
+        0x3E, 0x80,       //       LD A, $80
+        0xE0, 0x11,       //       LDH (NR11), A    ; 50% duty
+        0x3E, 0xF0,       //       LD A, $F0
+        0xE0, 0x12,       //       LDH (NR12), A    ; volume 15 (DAC on, not playing yet)
+        0x3E, 0x83,       //       LD A, $83
+        0xE0, 0x13,       //       LDH (NR13), A    ; period $783 (lower bits)
+        0x06, TONE_FRAME, //       LD B, TONE_FRAME
+        0xF0, 0x44,       // .next LDH A, (LY)      ; wait for a new frame...
+        0xFE, 0x90,       //       CP 144
+        0x28, 0xFA,       //       JR Z, .next
+        0xF0, 0x44,       // .wait LDH A, (LY)      ; ...then for its VBlank
+        0xFE, 0x90,       //       CP 144
+        0x20, 0xFA,       //       JR NZ, .wait
+        0x05,             //       DEC B
+        0x20, 0xF1,       //       JR NZ, .next
+        0x3E, 0x87,       //       LD A, $87
+        0xE0, 0x14,       //       LDH (NR14), A    ; trigger, period $783 (upper bits)
+        0x18, 0xFE,       // .done JR .done
+    ];
+
+    #[test]
+    fn av_sync_test() {
+        let sample_t_cycles = T_CYCLE_HZ as f64 / SAMPLING_RATE_HZ as f64;
+        // the tone's first high half comes at most a period (8 steps of $800 - $783 M-cycles) after it starts
+        let tone_latency = (8.0 * (0x800 - 0x783) as f64 * 4.0 / sample_t_cycles) as usize + 1;
+
+        let rom = make_test_rom(&AV_SYNC_ROM);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        // without the high-pass filter, only the tone's high halves are above silence
+        cpu.set_high_pass(false);
+
+        // run well past the tone, so the audio buffered behind the video catches up
+        let mut frame_timestamps = Vec::new();
+        let mut chunks = Vec::new();
+        while frame_timestamps.len() < TONE_FRAME as usize + 5 {
+            cpu.step();
+            if cpu.get_display_output().is_some() {
+                frame_timestamps.push(cpu.frame_timestamp().t_cycles());
+            }
+            if let Some(chunk) = cpu.get_audio_output() {
+                chunks.push((cpu.audio_timestamp().t_cycles(), chunk));
             }
-        } 
+        }
+
+        // chunks follow each other without gaps or overlaps
+        let stream_start = chunks[0].0;
+        for (i, (timestamp, _)) in chunks.iter().enumerate() {
+            let expected = stream_start + ((i * AUDIO_SAMPLES) as f64 * sample_t_cycles) as u64;
+            assert!((timestamp.abs_diff(expected) as f64) < sample_t_cycles, "chunk {} at {} instead of {}", i, timestamp, expected);
+        }
+
+        let samples: Vec<f32> = chunks.iter().flat_map(|(_, chunk)| chunk.iter().map(|sample| sample[0])).collect();
+        let tone_offset = samples.iter().position(|&sample| sample > 0.0).expect("no tone in the audio");
+
+        let frame_offset = ((frame_timestamps[TONE_FRAME as usize - 1] - stream_start) as f64 / sample_t_cycles) as usize;
+        assert!((frame_offset..=frame_offset + tone_latency).contains(&tone_offset),
+            "tone at sample {} but frame {} ends at sample {}", tone_offset, TONE_FRAME, frame_offset);
     }
 
     #[test]
-    fn apu_cgb_sound_test() {
-        let mut cartridge = Cartridge::from_file(CGB_SOUND, false);
-        for i in RAM_START..RAM_END {
-            cartridge.write_ram(i, 0);
+    fn channel_enabled_test() {
+        let mut apu = super::Apu::new(GBModel::DMG);
+        // every channel in both ears at full volume
+        apu.nr51 = 0xFF;
+        apu.nr50 = 0x77;
+        apu.set_high_pass(false);
+        // every DAC on
+        for (addr, byte) in [(0xFF12, 0xF0), (0xFF17, 0xF0), (0xFF1A, 0x80), (0xFF21, 0xF0)] {
+            apu.write_io(addr, byte);
         }
-        let mut cpu = Cpu::new(cartridge, crate::cpu::GBModel::CGB);
-    
-        let mut cycles: u64 = 0;
-        let mut test_num = 1;
-        while cycles < (1 << 32) {
-            cycles += cpu.step() as u64;
-
-            if cycles % (1 << 18) == 0 {
-                let mut output = String::new();
-
-                for i in RAM_START..RAM_END {
-                    let byte = cpu.read_byte(i as u16);
-                    if byte != 0 {
-                        output.push(char::from(byte));
-                    }
-                }
 
-                if output.contains(TEST_NUMS[test_num - 1]) {
-                    let mut pass_output = String::from(TEST_NUMS[test_num - 1]);
-                    pass_output.push_str("ok");
-                    if output.contains(&pass_output) {
-                        println!("cgb_sound: Test #{} Passed", test_num);
-                        test_num += 1;
-                        if test_num > 12 {
-                            break;
-                        }
-                    } else {
-                        panic!("cgb_sound: Test #{} Failed", test_num);
-                    }
-                }
+        apu.push_samples_to_buffer([15.0, 0.0, 0.0, 0.0]);
+        apu.set_channel_enabled(1, false);
+        apu.set_channel_enabled(2, false);
+        apu.set_channel_enabled(3, false);
+        apu.push_samples_to_buffer([15.0, 0.0, 0.0, 0.0]);
+        apu.set_channel_enabled(0, false);
+        apu.push_samples_to_buffer([15.0, 0.0, 0.0, 0.0]);
+
+        // pulse 1 alone keeps its share of the mix, and muting it too leaves silence
+        assert_eq!(apu.audio_buffer[0], [-0.5; 2]);
+        assert_eq!(apu.audio_buffer[1], [0.25; 2]);
+        assert_eq!(apu.audio_buffer[2], [0.0; 2]);
+        assert!(!apu.channel_enabled(0));
+
+        // a DAC that's off outputs nothing, not its lowest level
+        for ch in 0..4 {
+            apu.set_channel_enabled(ch, true);
+        }
+        apu.write_io(0xFF17, 0x00);
+        apu.push_samples_to_buffer([0.0; 4]);
+        assert_eq!(apu.audio_buffer[3], [-0.75; 2]);
+    }
+
+    #[test]
+    fn power_off_test() {
+        for (model, length_kept) in [(GBModel::DMG, true), (GBModel::CGB, false)] {
+            let mut apu = super::Apu::new(model);
+            apu.write_io(0xFF26, 0x80);
+            // pulse 1 with a length of 1 left
+            apu.write_io(0xFF11, 0x3F);
+            apu.write_io(0xFF26, 0x00);
+            assert_eq!(apu.read_io(0xFF11), 0x3F);
+            assert_eq!(apu.read_io(0xFF24), 0x00);
+
+            // triggered with lengths on, after powering back on
+            apu.write_io(0xFF26, 0x80);
+            apu.write_io(0xFF12, 0xF0);
+            apu.write_io(0xFF14, 0xC0);
+            assert_eq!(apu.read_io(0xFF26) & 1, 1);
+            apu.frame_sequencer_step();
+            assert_eq!(apu.read_io(0xFF26) & 1 == 0, length_kept, "{:?}", model);
+        }
+    }
+
+    #[test]
+    fn audio_output_test() {
+        let rom = make_test_rom(&AV_SYNC_ROM);
+        let mut heard = Box::new(Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::CGB));
+        let mut unheard = Box::new(Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::CGB));
+        unheard.set_audio_output(false);
+
+        // games see the same APU either way, before and while the tone plays
+        let mut chunks = 0;
+        for _ in 0..TONE_FRAME + 60 {
+            heard.run_until_vblank();
+            unheard.run_until_vblank();
+            chunks += heard.drain_audio().len();
+            assert!(unheard.drain_audio().is_empty());
+            for addr in [0xFF26, 0xFF76, 0xFF77] {
+                assert_eq!(heard.read_byte(addr), unheard.read_byte(addr), "${:04X} differs", addr);
             }
-        } 
+        }
+        assert!(chunks > 0);
     }
-}
\ No newline at end of file
+}
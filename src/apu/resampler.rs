@@ -0,0 +1,145 @@
+use alloc::{format, string::String};
+use core::str::FromStr;
+
+use crate::constants::M_CYCLE_HZ;
+
+/// How the channels' output (a level every M-cycle) is brought down to the host's sample rate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Interpolates between the two M-cycles around every output sample: cheap, but high
+    /// notes and noise alias into whistles that weren't there.
+    Linear,
+    /// Averages everything the channels output over each output sample (a box filter),
+    /// which band-limits the output and takes out most of that aliasing.
+    BandLimited,
+}
+
+/// Parses `linear` or `band-limited`.
+impl FromStr for ResampleQuality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(ResampleQuality::Linear),
+            "band-limited" => Ok(ResampleQuality::BandLimited),
+            _ => Err(format!("Unknown resampler '{}' (expected linear or band-limited)", s)),
+        }
+    }
+}
+
+/// Turns the levels (0-15) of the four channels, given every M-cycle, into levels at output_hz.
+///
+/// Time is kept in ticks, output_hz of them per M-cycle and M_CYCLE_HZ per output sample,
+/// so output samples come exactly output_hz times a second without any rounding building up,
+/// however odd the rate. Averaging in ticks also keeps steady levels exact.
+pub struct Resampler {
+    quality: ResampleQuality,
+    output_hz: u32,
+    // ticks since the last output sample
+    phase: u32,
+    // (BandLimited) every channel's levels since the last output sample, weighted by their ticks
+    sums: [u64; 4],
+    // (Linear) the levels of the M-cycle before
+    previous: [u8; 4],
+}
+
+impl Resampler {
+    pub fn new(output_hz: u32, quality: ResampleQuality) -> Self {
+        Resampler {
+            quality,
+            output_hz: output_hz.clamp(1, M_CYCLE_HZ),
+            phase: 0,
+            sums: [0; 4],
+            previous: [0; 4],
+        }
+    }
+
+    pub fn output_hz(&self) -> u32 {
+        self.output_hz
+    }
+
+    /// Changes the output rate from the next M-cycle on, without a seam in the output.
+    pub fn set_output_hz(&mut self, output_hz: u32) {
+        self.output_hz = output_hz.clamp(1, M_CYCLE_HZ);
+    }
+
+    /// Takes the channels' levels for one M-cycle; returns the next output sample's levels
+    /// (fractional, on the same 0-15 scale) once it's due.
+    pub fn push(&mut self, levels: [u8; 4]) -> Option<[f32; 4]> {
+        let phase = self.phase + self.output_hz;
+        if phase < M_CYCLE_HZ {
+            self.phase = phase;
+            if self.quality == ResampleQuality::BandLimited {
+                for (sum, &level) in self.sums.iter_mut().zip(&levels) {
+                    *sum += level as u64 * self.output_hz as u64;
+                }
+            }
+            self.previous = levels;
+            return None;
+        }
+
+        // the output sample is due this far into the M-cycle; the rest belongs to the next one
+        let before = M_CYCLE_HZ - self.phase;
+        self.phase = phase - M_CYCLE_HZ;
+
+        let mut output = [0.0; 4];
+        for i in 0..4 {
+            output[i] = match self.quality {
+                ResampleQuality::Linear => {
+                    let previous = self.previous[i] as f32;
+                    previous + (levels[i] as f32 - previous) * (before as f32 / self.output_hz as f32)
+                }
+                ResampleQuality::BandLimited => {
+                    let sum = self.sums[i] + levels[i] as u64 * before as u64;
+                    self.sums[i] = levels[i] as u64 * self.phase as u64;
+                    sum as f32 / M_CYCLE_HZ as f32
+                }
+            };
+        }
+        self.previous = levels;
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResampleQuality, Resampler};
+    use crate::constants::M_CYCLE_HZ;
+
+    #[test]
+    fn resampler_rate_test() {
+        for output_hz in [44100, 48000, 96000, 32768, 47999] {
+            for quality in [ResampleQuality::Linear, ResampleQuality::BandLimited] {
+                let mut resampler = Resampler::new(output_hz, quality);
+                // a second's worth of M-cycles gives exactly a second's worth of samples
+                let outputs = (0..M_CYCLE_HZ).filter_map(|_| resampler.push([7; 4])).count();
+                assert_eq!(outputs, output_hz as usize);
+            }
+        }
+
+        // a new rate carries on where the old one left off (as rate control nudges it)
+        let mut resampler = Resampler::new(48000, ResampleQuality::BandLimited);
+        let before = (0..M_CYCLE_HZ / 2).filter_map(|_| resampler.push([7; 4])).count();
+        resampler.set_output_hz(48240);
+        let after = (0..M_CYCLE_HZ / 2).filter_map(|_| resampler.push([7; 4])).count();
+        assert_eq!((before, after), (24000, 24120));
+    }
+
+    #[test]
+    fn resampler_levels_test() {
+        // steady levels come out as they went in
+        let mut resampler = Resampler::new(44100, ResampleQuality::BandLimited);
+        let outputs: Vec<[f32; 4]> = (0..10000).filter_map(|_| resampler.push([0, 5, 10, 15])).collect();
+        assert!(outputs.iter().all(|&output| output == [0.0, 5.0, 10.0, 15.0]));
+
+        // a square wave far above what the output can hold averages out to its middle...
+        let mut resampler = Resampler::new(48000, ResampleQuality::BandLimited);
+        let outputs: Vec<f32> = (0..10000).filter_map(|i| resampler.push([15 * (i % 2) as u8; 4])).map(|output| output[0]).collect();
+        assert!(outputs.iter().all(|&output| (output - 7.5).abs() < 0.5));
+
+        // ...but is picked up as full swings by linear interpolation
+        let mut resampler = Resampler::new(48000, ResampleQuality::Linear);
+        let outputs: Vec<f32> = (0..10000).filter_map(|i| resampler.push([15 * (i % 2) as u8; 4])).map(|output| output[0]).collect();
+        assert!(outputs.iter().any(|&output| (output - 7.5).abs() > 5.0));
+    }
+}
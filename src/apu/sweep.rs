@@ -1,3 +1,5 @@
+use crate::savestate::snapshot_fields;
+
 pub struct Sweep {
     fs_ticks: u8,
     cur_freq_period: u32,
@@ -113,4 +115,6 @@ impl Sweep {
     pub fn set_period(&mut self, period: u32) {
         self.cur_freq_period = period;
     }
-}
\ No newline at end of file
+}
+
+snapshot_fields!(Sweep, fs_ticks, cur_freq_period, shadow_freq_period, sweep_period, sweep_down, shift, enabled, sweep_timer, sweep_down_calc);
@@ -0,0 +1,218 @@
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io::ErrorKind;
+use std::path::Path;
+
+use sdl2::keyboard::Keycode;
+
+use crate::emulator::{
+    BUS_STATS_HOTKEY, CHANNEL_HOTKEYS, CHEAT_HOTKEY, FAST_FORWARD_HOTKEY, FRAME_DUMP_HOTKEY, FULLSCREEN_HOTKEY,
+    KEYMAPPINGS, LAYER_TINTS_HOTKEY, TURBO_KEYMAPPINGS, MAGNIFIER_HOTKEY, MENU_HOTKEY, PALETTE_HOTKEY, PROFILE_HOTKEY, RECORD_HOTKEY,
+    RERECORD_HOTKEY, REWIND_HOTKEY, SCREENSHOT_HOTKEY, SECOND_KEYMAPPINGS, SRAM_WAIT_HOTKEY, STRETCH_HOTKEY, VIDEO_HOTKEY, WARNINGS_HOTKEY,
+};
+
+// in the same order as KEYMAPPINGS
+const BUTTON_NAMES: [&str; 8] = ["start", "select", "b", "a", "down", "up", "left", "right"];
+// in the same order as TURBO_KEYMAPPINGS
+const TURBO_NAMES: [&str; 2] = ["turbo_b", "turbo_a"];
+
+/// Emulator actions that can be bound to a key (see Bindings).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    Quit,
+    Menu,
+    Cheat,
+    FastForward,
+    Rewind,
+    Screenshot,
+    Palette,
+    // the main core's sound channels, 0-3
+    Channel(usize),
+    LayerTints,
+    SramWait,
+    Magnifier,
+    Warnings,
+    BusStats,
+    FrameDump,
+    Profile,
+    Record,
+    Video,
+    Rerecord,
+    Fullscreen,
+    Stretch,
+}
+
+// every hotkey, with its name in the bindings file and its default key
+const HOTKEYS: [(Hotkey, &str, Keycode); 23] = [
+    (Hotkey::Quit, "quit", Keycode::Escape),
+    (Hotkey::Menu, "menu", MENU_HOTKEY),
+    (Hotkey::Cheat, "cheat", CHEAT_HOTKEY),
+    (Hotkey::FastForward, "fast_forward", FAST_FORWARD_HOTKEY),
+    (Hotkey::Rewind, "rewind", REWIND_HOTKEY),
+    (Hotkey::Screenshot, "screenshot", SCREENSHOT_HOTKEY),
+    (Hotkey::Palette, "palette", PALETTE_HOTKEY),
+    (Hotkey::Channel(0), "channel1", CHANNEL_HOTKEYS[0]),
+    (Hotkey::Channel(1), "channel2", CHANNEL_HOTKEYS[1]),
+    (Hotkey::Channel(2), "channel3", CHANNEL_HOTKEYS[2]),
+    (Hotkey::Channel(3), "channel4", CHANNEL_HOTKEYS[3]),
+    (Hotkey::LayerTints, "layer_tints", LAYER_TINTS_HOTKEY),
+    (Hotkey::SramWait, "sram_wait", SRAM_WAIT_HOTKEY),
+    (Hotkey::Magnifier, "magnifier", MAGNIFIER_HOTKEY),
+    (Hotkey::Warnings, "warnings", WARNINGS_HOTKEY),
+    (Hotkey::BusStats, "bus_stats", BUS_STATS_HOTKEY),
+    (Hotkey::FrameDump, "frame_dump", FRAME_DUMP_HOTKEY),
+    (Hotkey::Profile, "profile", PROFILE_HOTKEY),
+    (Hotkey::Record, "record", RECORD_HOTKEY),
+    (Hotkey::Video, "video", VIDEO_HOTKEY),
+    (Hotkey::Rerecord, "rerecord", RERECORD_HOTKEY),
+    (Hotkey::Fullscreen, "fullscreen", FULLSCREEN_HOTKEY),
+    (Hotkey::Stretch, "stretch", STRETCH_HOTKEY),
+];
+
+/// Which keys press the Game Boy buttons of both players (the second one being the second
+/// core, or the second SGB controller) and which trigger hotkeys. They're kept in a TOML file
+/// with a `[player1]`, `[player2]` and `[hotkeys]` table of `name = "Key"` lines, keys named
+/// as SDL names them (e.g. "Left Shift", "F1", "Keypad 4"); anything left out keeps its default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bindings {
+    // in the same order as KEYMAPPINGS
+    buttons: [[Keycode; 8]; 2],
+    // in the same order as TURBO_KEYMAPPINGS
+    turbo_buttons: [[Keycode; 2]; 2],
+    // in the same order as HOTKEYS
+    hotkeys: [Keycode; HOTKEYS.len()],
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings {
+            buttons: [KEYMAPPINGS, SECOND_KEYMAPPINGS],
+            turbo_buttons: TURBO_KEYMAPPINGS,
+            hotkeys: HOTKEYS.map(|(_, _, key)| key),
+        }
+    }
+}
+
+impl Bindings {
+    /// Reads the bindings file at path, writing one with the defaults there if there isn't any.
+    pub fn load(path: &str) -> Result<Self, String> {
+        match read_to_string(path) {
+            Ok(text) => Bindings::parse(&text).map_err(|e| format!("{}: {}", path, e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let bindings = Bindings::default();
+                if let Err(e) = bindings.save(path) {
+                    eprintln!("{}", e);
+                }
+                Ok(bindings)
+            },
+            Err(e) => Err(format!("Unable to read {}: {}", path, e)),
+        }
+    }
+
+    /// Writes the bindings to a file at path.
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        if let Some(dir) = Path::new(path).parent() {
+            create_dir_all(dir).map_err(|e| format!("Unable to create {}: {}", dir.display(), e))?;
+        }
+        write(path, self.to_toml()).map_err(|e| format!("Unable to write {}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut bindings = Bindings::default();
+        let mut table = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                table = Some(name.trim());
+                continue;
+            }
+
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let (name, value) = line.split_once('=').ok_or_else(|| error(format!("expected name = \"Key\", got '{}'", line)))?;
+            let (name, value) = (name.trim(), value.trim());
+            let key_name = value.strip_prefix('"').and_then(|value| value.strip_suffix('"'))
+                .ok_or_else(|| error(format!("key names go in quotes, e.g. \"{}\"", value)))?;
+            let key = Keycode::from_name(key_name).ok_or_else(|| error(format!("unknown key '{}'", key_name)))?;
+
+            match table {
+                Some(player @ ("player1" | "player2")) => {
+                    let player = (player == "player2") as usize;
+                    if let Some(i) = TURBO_NAMES.iter().position(|&button| button == name) {
+                        bindings.turbo_buttons[player][i] = key;
+                        continue;
+                    }
+                    let i = BUTTON_NAMES.iter().position(|&button| button == name).ok_or_else(|| error(format!(
+                        "unknown button '{}' (expected one of {}, {})", name, BUTTON_NAMES.join(", "), TURBO_NAMES.join(", "))))?;
+                    bindings.buttons[player][i] = key;
+                },
+                Some("hotkeys") => {
+                    let i = HOTKEYS.iter().position(|&(_, hotkey, _)| hotkey == name)
+                        .ok_or_else(|| error(format!("unknown hotkey '{}'", name)))?;
+                    bindings.hotkeys[i] = key;
+                },
+                _ => return Err(error(String::from("bindings go under [player1], [player2] or [hotkeys]"))),
+            }
+        }
+        Ok(bindings)
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut toml = String::from("# keys are named as SDL names them (e.g. \"Left Shift\", \"F1\", \"Keypad 4\")\n");
+        for (player, buttons) in self.buttons.iter().enumerate() {
+            toml += &format!("\n[player{}]\n", player + 1);
+            for (name, key) in BUTTON_NAMES.iter().zip(buttons) {
+                toml += &format!("{} = \"{}\"\n", name, key.name());
+            }
+            for (name, key) in TURBO_NAMES.iter().zip(&self.turbo_buttons[player]) {
+                toml += &format!("{} = \"{}\"\n", name, key.name());
+            }
+        }
+        toml += "\n[hotkeys]\n";
+        for ((_, name, _), key) in HOTKEYS.iter().zip(&self.hotkeys) {
+            toml += &format!("{} = \"{}\"\n", name, key.name());
+        }
+        toml
+    }
+
+    /// Returns player's (0 or 1) keys, in the same order as KEYMAPPINGS.
+    pub fn buttons(&self, player: usize) -> &[Keycode; 8] {
+        &self.buttons[player]
+    }
+
+    /// Returns player's (0 or 1) turbo B and turbo A keys.
+    pub fn turbo_buttons(&self, player: usize) -> &[Keycode; 2] {
+        &self.turbo_buttons[player]
+    }
+
+    /// Binds player's (0 or 1) turbo B (0) or turbo A (1) to key.
+    #[allow(dead_code)]
+    pub fn bind_turbo_button(&mut self, player: usize, button: usize, key: Keycode) {
+        self.turbo_buttons[player][button] = key;
+    }
+
+    /// Binds player's (0 or 1) Game Boy button (its position in KEYMAPPINGS) to key.
+    #[allow(dead_code)]
+    pub fn bind_button(&mut self, player: usize, button: usize, key: Keycode) {
+        self.buttons[player][button] = key;
+    }
+
+    /// Returns the hotkey bound to key, if any.
+    pub fn hotkey(&self, key: Keycode) -> Option<Hotkey> {
+        let i = self.hotkeys.iter().position(|&hotkey| hotkey == key)?;
+        Some(HOTKEYS[i].0)
+    }
+
+    /// Returns the key hotkey is bound to.
+    pub fn key(&self, hotkey: Hotkey) -> Keycode {
+        let i = HOTKEYS.iter().position(|&(h, _, _)| h == hotkey).unwrap();
+        self.hotkeys[i]
+    }
+
+    #[allow(dead_code)]
+    pub fn bind_hotkey(&mut self, hotkey: Hotkey, key: Keycode) {
+        let i = HOTKEYS.iter().position(|&(h, _, _)| h == hotkey).unwrap();
+        self.hotkeys[i] = key;
+    }
+}
@@ -1,11 +1,26 @@
-use crate::config::AUDIO_SAMPLES;
-use crate::constants::{LCD_BYTE_WIDTH, LCD_HEIGHT};
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+
+use crate::bus_stats::BusStats;
+use crate::cheats::{Cheat, Cheats};
+use crate::profiler::{Component, FrameProfile, HostClock, Profiler};
+use crate::config::{AUDIO_SAMPLES, BUS_STATS, OPEN_BUS, RANDOM_INITIAL_RAM, SRAM_WAIT, SRAM_WAIT_CYCLES};
+use crate::constants::{LCD_HEIGHT, LCD_WIDTH, T_CYCLE_HZ};
 use crate::joypad::Joypad;
-use crate::apu::Apu;
-use crate::ppu::Ppu;
+use crate::apu::{Apu, ApuWrite, ResampleQuality};
+use crate::ppu::{LcdOffPolicy, Ppu};
 use crate::timer::Timer;
+use crate::serial::{Serial, SerialLink};
 use crate::cartridge::Cartridge;
+use crate::cartridge::camera::ImageSource;
+use crate::cartridge::rumble::RumbleShaper;
 use crate::cpu::{GBModel, Interrupt};
+use crate::events::{CoreEvent, EventQueue, MemoryAccess, Timestamp};
+use crate::infrared::IrTransceiver;
+use crate::palette::{self, ColourCorrection, DmgPalette, COMBO_PALETTES};
+use crate::rng::Rng;
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::sgb::{self, Sgb, SGB_HEIGHT, SGB_WIDTH};
+use crate::warnings::Warnings;
 
 const WRAM_SIZE: usize = 0x1000;
 const HRAM_SIZE: usize = 0x0080;
@@ -28,7 +43,25 @@ const HRAM_START: usize = 0xFF80;
 const HRAM_END: usize = 0xFFFE;
 
 const DMA_M_CYCLES: u16 = 160;
+
+// Palette button combos are only checked for this many frames after power on,
+// around as long as the CGB boot ROM's logo animation
+const PALETTE_COMBO_FRAMES: u32 = 60;
 const HDMA_BLOCK_SIZE: usize = 0x10;
+// accesses to watched addresses past this many (if they're never taken) aren't recorded
+const MAX_MEMORY_ACCESSES: usize = 4096;
+
+/// What reads from unmapped addresses return.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(dead_code)]
+pub enum OpenBus {
+    High,
+    Low,
+    /// The last byte the CPU read or wrote, which is what real hardware leaves on the bus.
+    LastValue,
+    /// Random bytes from the core's seeded Rng.
+    Noise,
+}
 
 enum HDMAMode {
     GDMA,
@@ -40,13 +73,45 @@ pub struct Bus {
     model: GBModel,
     double_speed: bool,
     serial_output: String,
+    events: EventQueue,
+    warnings: Warnings,
+    frames_since_boot: u32,
+    // emulated time so far, and when the last frame and audio chunk handed out were produced
+    elapsed: Timestamp,
+    frame_timestamp: Timestamp,
+    audio_timestamp: Timestamp,
+    dmg_palette_index: Option<usize>,
+    open_bus: OpenBus,
+    last_bus_value: u8,
+    rumble: RumbleShaper,
+    rng: Rng,
+    cheats: Cheats,
+    stats: Option<BusStats>,
+    // addresses whose CPU accesses get recorded, and the accesses not yet taken
+    watched: Vec<u16>,
+    memory_accesses: Vec<MemoryAccess>,
+    profiler: Option<Profiler>,
+    // the CPU runs this many times faster than everything else (see set_turbo)
+    turbo: u32,
+    // M-cycles of CPU time not yet run by partial_step() and step() in turbo mode
+    turbo_partial_carry: u32,
+    turbo_step_carry: u32,
+    // extra M-cycles every CPU access to cartridge RAM takes (see set_sram_wait)
+    sram_wait: u32,
 
     cartridge: Cartridge,
     joypad: Joypad,
     apu: Apu,
+    // the last value written to every APU register and wave RAM byte ($FF10-$FF3F)
+    apu_registers: [u8; 0x30],
+    // writes to them since take_apu_writes(), while they're logged
+    apu_writes: Option<Vec<ApuWrite>>,
     ppu: Ppu,
+    // SGB ONLY
+    sgb: Option<Sgb>,
     wram: [[u8; WRAM_SIZE]; 8],
     timer: Timer, 
+    serial: Serial,
     hram: [u8; HRAM_SIZE],
     interrupt_enable: u8,
     interrupt_flag: u8,
@@ -54,6 +119,9 @@ pub struct Bus {
     dma_ticks: u16,
 
     // CGB ONLY
+    // selects DMG compatibility mode while the boot ROM is mapped (see lock_key0)
+    key0: u8,
+    dmg_compat: bool,
     key1: u8,
     hdma1: usize,
     hdma2: usize,
@@ -61,6 +129,8 @@ pub struct Bus {
     hdma4: usize,
     hdma5: u8,
     rp: u8,
+    // what the RP register's IR port faces (nothing if None)
+    ir: Option<Box<dyn IrTransceiver>>,
     svbk: u8,
     hdma_bytes: usize,
     hdma_mode: HDMAMode,
@@ -68,24 +138,62 @@ pub struct Bus {
 }
 
 impl Bus {
-    pub fn new(cartridge: Cartridge, model: GBModel) -> Self {
+    pub fn new(cartridge: Cartridge, model: GBModel, rng: Rng) -> Self {
+        let mut wram = [[0; WRAM_SIZE]; 8];
+        let mut hram = [0; HRAM_SIZE];
+        if RANDOM_INITIAL_RAM {
+            wram.iter_mut().for_each(|bank| rng.fill(bank));
+            rng.fill(&mut hram);
+        }
+
+        let mut warnings = Warnings::new();
+        if let Some(warning) = cartridge.check_save_size() {
+            warnings.warn(&warning, Timestamp(0));
+        }
+
         Bus {
             model,
             double_speed: false,
             serial_output: String::new(),
+            events: EventQueue::new(),
+            warnings,
+            frames_since_boot: 0,
+            elapsed: Timestamp(0),
+            frame_timestamp: Timestamp(0),
+            audio_timestamp: Timestamp(0),
+            dmg_palette_index: None,
+            open_bus: OPEN_BUS,
+            last_bus_value: 0xFF,
+            rumble: RumbleShaper::new(&cartridge.get_title()),
+            rng,
+            cheats: Cheats::new(),
+            stats: if BUS_STATS { Some(BusStats::new()) } else { None },
+            watched: Vec::new(),
+            memory_accesses: Vec::new(),
+            profiler: None,
+            turbo: 1,
+            turbo_partial_carry: 0,
+            turbo_step_carry: 0,
+            sram_wait: if SRAM_WAIT { SRAM_WAIT_CYCLES } else { 0 },
 
             cartridge,
             joypad: Joypad::new(),
             apu: Apu::new(model),
+            apu_registers: [0; 0x30],
+            apu_writes: None,
             ppu: Ppu::new(model),
+            sgb: matches!(model, GBModel::SGB).then(Sgb::new),
             timer: Timer::new(),
-            wram: [[0; WRAM_SIZE]; 8],
-            hram: [0; HRAM_SIZE],
+            serial: Serial::new(model),
+            wram,
+            hram,
             interrupt_enable: 0,
             interrupt_flag: 0xE0,
             dma_start: 0,
             dma_ticks: DMA_M_CYCLES,
 
+            key0: 0,
+            dmg_compat: false,
             key1: 0,
             hdma1: 0,
             hdma2: 0,
@@ -93,6 +201,7 @@ impl Bus {
             hdma4: 0,
             hdma5: 0xFF,
             rp: 0,
+            ir: None,
             svbk: 0,
             hdma_bytes: 0,
             hdma_mode: HDMAMode::None,
@@ -102,14 +211,27 @@ impl Bus {
 
     /// Steps through components that require M-cycle level accuracy;
     /// this should also be called AFTER and BETWEEN (right after reads/writes) instructions.
-    /// NOTE: This stepping is affected by double speed mode on CGB
+    /// NOTE: This stepping is affected by double speed mode on CGB (and turbo mode)
     pub fn partial_step(&mut self, t_cycles: u32) {
+        let t_cycles = turbo_scale(t_cycles, self.turbo, &mut self.turbo_partial_carry);
+        if t_cycles == 0 {
+            return;
+        }
+
+        let start = self.profile_start();
         self.step_oam_dma(t_cycles / 4);
+        self.profile(Component::Dma, start);
 
         let old_div = self.timer.read_div();
         if self.timer.step(t_cycles) {
             self.request_interrupt(Interrupt::Timer)
         }
+
+        if self.serial.step(t_cycles) {
+            self.request_interrupt(Interrupt::Serial)
+        }
+
+        self.cartridge.step(t_cycles);
         
         if self.double_speed {
             if old_div & 0x20 != 0 && self.timer.read_div() & 0x20 == 0 {
@@ -123,22 +245,51 @@ impl Bus {
     }
 
     /// Steps through other components to be done at the END OF EACH INTSTRUCTION.
-    /// Updates interrupt flags accordingly.
-    pub fn step(&mut self, t_cycles: u32) {
-        let mut t_cycles = t_cycles;
+    /// Updates interrupt flags accordingly. Returns how long the instruction took in
+    /// real time (fewer T-cycles than the CPU ran for in turbo mode).
+    pub fn step(&mut self, t_cycles: u32) -> u32 {
+        let mut t_cycles = turbo_scale(t_cycles, self.turbo, &mut self.turbo_step_carry);
+        let real_t_cycles = t_cycles;
 
         if self.double_speed {
             t_cycles /= 2;
         }
 
+        let start = self.profile_start();
         t_cycles += self.step_vram_dma();
+        self.profile(Component::Dma, start);
+        self.elapsed.0 += t_cycles as u64;
+        self.cartridge.step_clock(t_cycles);
 
+        let start = self.profile_start();
         self.apu.step(t_cycles);
-        
+        self.profile(Component::Apu, start);
+
+        let start = self.profile_start();
+        let lcd_off_frame = self.ppu.lcd_off_frame();
         self.ppu.step(t_cycles);
+        self.profile(Component::Ppu, start);
+
+        let lcd_on = self.ppu.read_io(0xFF40) & 0x80 != 0;
+        if self.ppu.entered_vblank() || !lcd_on {
+            self.latch_joypad();
+        }
 
         if self.ppu.entered_vblank() {
             self.request_interrupt(Interrupt::VBlank);
+            self.events.push(CoreEvent::VBlank);
+            self.frames_since_boot = self.frames_since_boot.saturating_add(1);
+            self.apply_cheats();
+            self.frame_timestamp = self.elapsed;
+            if let Some(profiler) = &mut self.profiler {
+                profiler.end_frame();
+            }
+            self.end_serial_frame();
+        }
+        // frames drawn while the LCD is off aren't VBlanks, but finish all the same
+        if !lcd_off_frame && self.ppu.lcd_off_frame() {
+            self.frame_timestamp = self.elapsed;
+            self.end_serial_frame();
         }
         if self.ppu.stat_triggered() {
             self.request_interrupt(Interrupt::Stat)
@@ -146,23 +297,42 @@ impl Bus {
         if self.joypad.interrupt_triggered() {
             self.request_interrupt(Interrupt::Joypad)
         }
+
+        if let Some(intensity) = self.rumble.step(t_cycles, self.cartridge.rumble_on()) {
+            self.events.push(CoreEvent::RumbleIntensity(intensity));
+        }
+
+        real_t_cycles
     }
 
-    /// Returns byte from specified address; returns 0xFF for unused addresses.
+    /// Returns byte from specified address, as a CPU or DMA read sees it; unused addresses
+    /// return the open bus value (drawing a byte from the Rng for OpenBus::Noise).
     pub fn read_byte(&self, addr: u16) -> u8 {
+        self.read_with(addr, || self.open_bus_value())
+    }
+
+    /// Reads addr like read_byte() without drawing from the Rng: unused addresses show the noise
+    /// the next CPU read will get instead. For anything looking at memory from outside the game
+    /// (debuggers, traces, scripts), so that looking doesn't change what the game does next.
+    pub fn inspect_byte(&self, addr: u16) -> u8 {
+        self.read_with(addr, || self.peek_open_bus_value())
+    }
+
+    fn read_with(&self, addr: u16, open_bus: impl Fn() -> u8) -> u8 {
         let addr = addr as usize;
 
         match addr {
-            ROM_START..=ROM_END     => self.cartridge.read_rom(addr),
-            VRAM_START..=VRAM_END   => self.ppu.read_vram(addr),
+            ROM_START..=ROM_END     => self.cheats.patch_rom(addr as u16, self.cartridge.read_rom(addr)),
+            VRAM_START..=VRAM_END   => self.read_vram(addr),
             RAM_START..=RAM_END     => self.cartridge.read_ram(addr),
             WRAM_START..=WRAM_END   => self.read_wram(addr),
             WRAM2_START..=WRAM2_END => self.read_wram(addr - 2*WRAM_SIZE),
-            OAM_START..=OAM_END     => self.ppu.read_oam(addr),
-            EMPTY_START..=EMPTY_END => 0xFF,
+            OAM_START..=OAM_END     => self.read_oam(addr),
+            EMPTY_START..=EMPTY_END => open_bus(),
 
             // IO Registers
             0xFF00          => self.joypad.read_joypad(),
+            0xFF01..=0xFF02 => self.serial.read_io(addr),
             0xFF04..=0xFF07 => self.timer.read_io(addr),
             0xFF0F          => self.interrupt_flag,
             0xFF10..=0xFF26 => self.apu.read_io(addr),
@@ -171,69 +341,325 @@ impl Bus {
             0xFF50          => self.cartridge.read_bank(),
 
             // CGB Registers
-            0xFF4D if self.is_cgb() => self.key1, 
-            0xFF4F if self.is_cgb() => self.ppu.read_io(addr),
-            0xFF55 if self.is_cgb() => self.read_hdma5(),
-            0xFF56 if self.is_cgb() => self.rp,
-            0xFF68..=0xFF6C if self.is_cgb() => self.ppu.read_io(addr),
-            0xFF70 if self.is_cgb() => self.svbk,
+            0xFF4C if self.key0_writable() => self.key0,
+            0xFF4D if self.cgb_mode() => self.key1, 
+            0xFF4F if self.cgb_mode() => self.ppu.read_io(addr),
+            0xFF55 if self.cgb_mode() => self.read_hdma5(),
+            0xFF56 if self.cgb_mode() => self.read_rp(),
+            0xFF68..=0xFF6B if self.cgb_mode() => self.ppu.read_io(addr),
+            0xFF6C if self.is_cgb() => self.ppu.read_io(addr),
+            0xFF70 if self.cgb_mode() => self.svbk,
             0xFF76 if self.is_cgb() => self.apu.read_io(addr),
             0xFF77 if self.is_cgb() => self.apu.read_io(addr),
         
             HRAM_START..=HRAM_END => self.hram[addr - HRAM_START],
             0xFFFF          => self.interrupt_enable,
-            _               => 0xFF
+            _               => open_bus(),
         }
     }
 
-    /// If specified address is writable, writes byte to it; MAY trigger an OAM DMA.
-    pub fn write_byte(&mut self, addr: u16, byte: u8) {
+    /// Reads addr like inspect_byte(), except VRAM and OAM read what they hold even while the
+    /// PPU has them locked; for tools watching memory from outside the game.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr as usize {
+            VRAM_START..=VRAM_END => self.ppu.read_vram(addr as usize),
+            OAM_START..=OAM_END   => self.ppu.read_oam(addr as usize),
+            _ => self.inspect_byte(addr),
+        }
+    }
+
+    /// Writes byte to the RAM at addr (VRAM and OAM even while the PPU has them locked,
+    /// cartridge RAM through its MBC) without a CPU write's side effects. Returns false, writing
+    /// nothing, if addr is ROM (where writes switch banks) or an IO register.
+    pub fn poke(&mut self, addr: u16, byte: u8) -> bool {
         let addr = addr as usize;
 
         match addr {
-            ROM_START..=ROM_END     => self.cartridge.write_rom(addr, byte),
             VRAM_START..=VRAM_END   => self.ppu.write_vram(addr, byte),
             RAM_START..=RAM_END     => self.cartridge.write_ram(addr, byte),
             WRAM_START..=WRAM_END   => self.write_wram(addr, byte),
             WRAM2_START..=WRAM2_END => self.write_wram(addr - 2*WRAM_SIZE, byte),
             OAM_START..=OAM_END     => self.ppu.write_oam(addr, byte),
+            HRAM_START..=HRAM_END   => self.hram[addr - HRAM_START] = byte,
+            _ => return false,
+        }
+        true
+    }
+
+    fn open_bus_value(&self) -> u8 {
+        match self.open_bus {
+            OpenBus::High => 0xFF,
+            OpenBus::Low => 0x00,
+            OpenBus::LastValue => self.last_bus_value,
+            OpenBus::Noise => self.rng.next_u8(),
+        }
+    }
+
+    fn peek_open_bus_value(&self) -> u8 {
+        match self.open_bus {
+            OpenBus::Noise => self.rng.peek_u8(),
+            _ => self.open_bus_value(),
+        }
+    }
+
+    /// Records byte as the last value driven on the bus by a CPU read or write.
+    pub fn latch_bus_value(&mut self, byte: u8) {
+        self.last_bus_value = byte;
+    }
+
+    /// Counts a CPU access to addr (if bus statistics are on), and records it if addr is watched.
+    pub fn record_access(&mut self, addr: u16, value: u8, write: bool) {
+        if let Some(stats) = &mut self.stats {
+            stats.record(addr, write);
+        }
+        if self.watched.contains(&addr) && self.memory_accesses.len() < MAX_MEMORY_ACCESSES {
+            self.memory_accesses.push(MemoryAccess { addr, value, write });
+        }
+    }
+
+    /// Starts or stops recording CPU accesses to addr.
+    pub fn watch_memory(&mut self, addr: u16, on: bool) {
+        self.watched.retain(|&watched| watched != addr);
+        if on {
+            self.watched.push(addr);
+        }
+    }
+
+    /// Returns the accesses to watched addresses since this was last called, oldest first.
+    pub fn take_memory_accesses(&mut self) -> Vec<MemoryAccess> {
+        core::mem::take(&mut self.memory_accesses)
+    }
+
+    /// Turns bus statistics on (starting from zero) or off.
+    #[allow(dead_code)]
+    pub fn set_bus_stats(&mut self, enabled: bool) {
+        self.stats = if enabled { Some(BusStats::new()) } else { None };
+    }
+
+    pub fn bus_stats(&self) -> Option<&BusStats> {
+        self.stats.as_ref()
+    }
+
+    /// Sets registers the boot ROM leaves behind that can't just be written: DIV's internal
+    /// counter (if known) and DMA, plus P1 with both button groups selected.
+    pub fn set_post_boot_registers(&mut self, div_counter: Option<u16>, dma: u8) {
+        if let Some(counter) = div_counter {
+            self.timer.set_div_counter(counter);
+        }
+        self.ppu.write_dma(dma);
+        // bypasses the SGB, which would take this as the start of a packet
+        self.joypad.write_joypad(0x00);
+    }
+
+    pub fn set_turbo(&mut self, multiplier: u32) {
+        self.turbo = multiplier.max(1);
+        self.turbo_partial_carry = 0;
+        self.turbo_step_carry = 0;
+    }
+
+    pub fn set_sram_wait(&mut self, m_cycles: u32) {
+        self.sram_wait = m_cycles;
+    }
+
+    pub fn sram_wait(&self) -> u32 {
+        self.sram_wait
+    }
+
+    /// Returns how many T-cycles the CPU stalls for when it accesses addr (see set_sram_wait).
+    pub fn access_wait(&self, addr: u16) -> u32 {
+        if (RAM_START..=RAM_END).contains(&(addr as usize)) { 4 * self.sram_wait } else { 0 }
+    }
+
+    pub fn set_profiling(&mut self, clock: Option<HostClock>) {
+        self.profiler = clock.map(Profiler::new);
+    }
+
+    pub fn take_frame_profiles(&mut self) -> Vec<FrameProfile> {
+        self.profiler.as_mut().map_or_else(Vec::new, Profiler::take_frames)
+    }
+
+    /// Returns when a profiled section starts (None if profiling is off).
+    pub fn profile_start(&self) -> Option<u64> {
+        self.profiler.as_ref().map(Profiler::start)
+    }
+
+    fn profile(&mut self, component: Component, start: Option<u64>) {
+        if let (Some(profiler), Some(start)) = (&mut self.profiler, start) {
+            profiler.record(component, start);
+        }
+    }
+
+    /// Records a whole CPU step (see Profiler::record_step) that began at start.
+    pub fn profile_step(&mut self, start: Option<u64>) {
+        if let (Some(profiler), Some(start)) = (&mut self.profiler, start) {
+            profiler.record_step(start);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_open_bus(&mut self, open_bus: OpenBus) {
+        self.open_bus = open_bus;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_rumble_strength(&mut self, strength: f32) {
+        self.rumble.set_strength(strength);
+    }
+
+    pub fn rng_seed(&self) -> u64 {
+        self.rng.seed()
+    }
+
+    pub fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.cartridge.set_image_source(source);
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        self.cartridge.has_clock()
+    }
+
+    pub fn adjust_rtc(&mut self, seconds: i64) {
+        self.cartridge.adjust_clock(seconds);
+    }
+
+    pub fn connect_serial(&mut self, link: Box<dyn SerialLink>) {
+        self.serial.connect(link);
+    }
+
+    /// Lets the link cable know the game finished a frame, and queues what happened on it.
+    fn end_serial_frame(&mut self) {
+        self.serial.end_frame();
+        while let Some(event) = self.serial.poll_event() {
+            self.events.push(event);
+        }
+    }
+
+    /// Points the cartridge's IR port at ir if it has one, otherwise the CGB's RP register.
+    pub fn connect_infrared(&mut self, ir: Box<dyn IrTransceiver>) {
+        if self.cartridge.has_infrared() {
+            self.cartridge.connect_infrared(ir);
+        } else {
+            self.ir = Some(ir);
+        }
+    }
+
+    /// RP: bit 0 is the LED, bit 1 reads 0 while light is received (only with bits 6-7 set
+    /// to enable reading), and bits 2-5 are unused.
+    fn read_rp(&self) -> u8 {
+        let receiving = self.rp & 0xC0 == 0xC0 && self.ir.as_ref().is_some_and(|ir| ir.light_received());
+        0x3C | self.rp | if receiving { 0x00 } else { 0x02 }
+    }
+
+    fn write_rp(&mut self, byte: u8) {
+        self.rp = byte & 0xC1;
+        if let Some(ir) = &mut self.ir {
+            ir.set_led(byte & 0x01 != 0);
+        }
+    }
+
+    /// If specified address is writable, writes byte to it; MAY trigger an OAM DMA.
+    pub fn write_byte(&mut self, addr: u16, byte: u8) {
+        let addr = addr as usize;
+
+        match addr {
+            ROM_START..=ROM_END     => self.write_rom(addr, byte),
+            VRAM_START..=VRAM_END   => if self.ppu.vram_accessible() { self.ppu.write_vram(addr, byte) },
+            RAM_START..=RAM_END     => {
+                if let Some(warning) = self.cartridge.check_ram_write() {
+                    self.warn(warning);
+                }
+                self.cartridge.write_ram(addr, byte)
+            },
+            WRAM_START..=WRAM_END   => self.write_wram(addr, byte),
+            WRAM2_START..=WRAM2_END => self.write_wram(addr - 2*WRAM_SIZE, byte),
+            OAM_START..=OAM_END     => if self.ppu.oam_accessible() { self.ppu.write_oam(addr, byte) },
             EMPTY_START..=EMPTY_END => {},
 
             // IO Registers
-            0xFF00          => self.joypad.write_joypad(byte),
-            0xFF01          => self.serial_output.push(char::from(byte)),
+            0xFF00          => {
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.write_p1(byte);
+                    let (player, multiplayer) = sgb.player();
+                    self.joypad.select_player(player, multiplayer);
+                }
+                self.joypad.write_joypad(byte);
+            },
+            0xFF01          => {
+                self.serial_output.push(char::from(byte));
+                self.serial.write_io(addr, byte);
+            },
+            0xFF02          => self.serial.write_io(addr, byte),
             0xFF04..=0xFF07 => self.timer.write_io(addr, byte),
             0xFF0F          => self.interrupt_flag = 0xE0 | byte,
-            0xFF10..=0xFF26 => self.apu.write_io(addr, byte),
-            0xFF30..=0xFF3F => self.apu.write_io(addr, byte),
+            0xFF10..=0xFF26 => self.write_apu(addr, byte),
+            0xFF30..=0xFF3F => self.write_apu(addr, byte),
             0xFF46          => self.write_dma(byte),
             0xFF40..=0xFF4B => self.ppu.write_io(addr, byte),
-            0xFF50          => self.cartridge.write_bank(byte),
+            0xFF50          => {
+                // unmapping the boot ROM locks in the mode it selected
+                if self.key0_writable() && byte != 0 {
+                    self.lock_key0(self.key0);
+                }
+                self.cartridge.write_bank(byte);
+            },
 
             // CGB Registers
-            0xFF4D if self.is_cgb() => self.key1 = byte & 0x7F,
-            0xFF4F if self.is_cgb() => self.ppu.write_io(addr, byte),
-            0xFF51 if self.is_cgb() => self.hdma1 = byte as usize,
-            0xFF52 if self.is_cgb() => self.hdma2 = byte as usize,
-            0xFF53 if self.is_cgb() => self.hdma3 = byte as usize,
-            0xFF54 if self.is_cgb() => self.hdma4 = byte as usize,
-            0xFF55 if self.is_cgb() => self.write_hdma5(byte),
-            0xFF56 if self.is_cgb() => self.rp = byte & 0xFD,
-            0xFF68..=0xFF6C if self.is_cgb() => self.ppu.write_io(addr, byte),
-            0xFF70 if self.is_cgb() => self.svbk = byte,
+            0xFF4C if self.key0_writable() => self.key0 = byte & 0x0F,
+            0xFF4D if self.cgb_mode() => self.key1 = byte & 0x7F,
+            0xFF4F if self.cgb_mode() => self.ppu.write_io(addr, byte),
+            0xFF51 if self.cgb_mode() => self.hdma1 = byte as usize,
+            0xFF52 if self.cgb_mode() => self.hdma2 = byte as usize,
+            0xFF53 if self.cgb_mode() => self.hdma3 = byte as usize,
+            0xFF54 if self.cgb_mode() => self.hdma4 = byte as usize,
+            0xFF55 if self.cgb_mode() => self.write_hdma5(byte),
+            0xFF56 if self.cgb_mode() => self.write_rp(byte),
+            0xFF68..=0xFF6B if self.cgb_mode() => self.ppu.write_io(addr, byte),
+            0xFF6C if self.is_cgb() => self.ppu.write_io(addr, byte),
+            0xFF70 if self.cgb_mode() => self.svbk = byte,
 
             HRAM_START..=HRAM_END => self.hram[addr - HRAM_START] = byte,
             0xFFFF          => self.interrupt_enable = byte,
-            _               => {},
+            _               => self.warn(&format!("Wrote {:02X} to unmapped register {:04X}", byte, addr)),
         }
     }
 
+    /// Records a non-fatal emulation problem (see Warnings).
+    fn warn(&mut self, message: &str) {
+        self.warnings.warn(message, self.elapsed);
+    }
+
+    pub fn warnings(&self) -> &Warnings {
+        &self.warnings
+    }
+
+    /// Writes to cartridge MBC registers, reporting any change to the rumble motor.
+    fn write_rom(&mut self, addr: usize, byte: u8) {
+        if let Some(warning) = self.cartridge.check_rom_write(addr, byte) {
+            self.warn(&warning);
+        }
+        let rumble_on = self.cartridge.rumble_on();
+        self.cartridge.write_rom(addr, byte);
+
+        if self.cartridge.rumble_on() != rumble_on {
+            self.events.push(CoreEvent::RumbleChanged(!rumble_on));
+        }
+    }
+
+    // the PPU locks VRAM in mode 3 and OAM in modes 2 and 3, where CPU reads return 0xFF
+    fn read_vram(&self, addr: usize) -> u8 {
+        if self.ppu.vram_accessible() { self.ppu.read_vram(addr) } else { 0xFF }
+    }
+
+    fn read_oam(&self, addr: usize) -> u8 {
+        if self.ppu.oam_accessible() { self.ppu.read_oam(addr) } else { 0xFF }
+    }
+
     fn read_wram(&self, addr: usize) -> u8 {
         if addr < WRAM_START + WRAM_SIZE {
             return self.wram[0][addr - WRAM_START];
         }
 
-        if self.is_cgb() {
+        if self.cgb_mode() {
             let wram_bank = ((self.svbk as usize) & 0x7) + (self.svbk == 0) as usize;
             self.wram[wram_bank][addr - WRAM_START - WRAM_SIZE]
         } else {
@@ -247,7 +673,7 @@ impl Bus {
             return;
         }
 
-        if self.is_cgb() {
+        if self.cgb_mode() {
             let wram_bank = ((self.svbk as usize) & 0x7) + (self.svbk == 0) as usize;
             self.wram[wram_bank][addr - WRAM_START - WRAM_SIZE] = byte;
         } else {
@@ -298,7 +724,7 @@ impl Bus {
 
     /// (CGB Only) Steps through HDMA, returning the number of T-Cycles taken.
     fn step_vram_dma(&mut self) -> u32 {
-        if !self.is_cgb() {
+        if !self.cgb_mode() {
             return 0;
         }
 
@@ -388,7 +814,7 @@ impl Bus {
     /// If speed switch has been armed, unarms it, switches speed and returns true;
     /// otherwise nothing happens and returns false.
     pub fn speed_switch(&mut self) -> bool {
-        if self.is_cgb() && self.key1 & 1 != 0 {
+        if self.cgb_mode() && self.key1 & 1 != 0 {
             self.key1 &= 0xFE;
             self.key1 = !(self.key1 & 0x80) | (self.key1 & 0x7F);
             self.double_speed = !self.double_speed;
@@ -399,23 +825,270 @@ impl Bus {
     }
 
     fn is_cgb(&self) -> bool {
-        matches!(self.model, GBModel::CGB)
+        self.model.is_cgb()
+    }
+
+    /// Returns true if CGB features are available: on a CGB outside of DMG compatibility mode.
+    pub fn cgb_mode(&self) -> bool {
+        self.is_cgb() && !self.dmg_compat
+    }
+
+    /// (CGB ONLY) Returns true if the CGB is running a DMG game in DMG compatibility mode.
+    pub fn dmg_compat(&self) -> bool {
+        self.dmg_compat
+    }
+
+    // KEY0 can only be written by the boot ROM
+    fn key0_writable(&self) -> bool {
+        self.is_cgb() && self.cartridge.read_bank() == 0
+    }
+
+    /// (CGB ONLY) Locks in the mode selected by key0, like unmapping the boot ROM does: with
+    /// bit 2 set the CGB runs in DMG compatibility mode, where its own registers (VRAM/WRAM
+    /// banks, palettes, HDMA, speed switch and RP) are disabled and frames are colorized
+    /// through the first BG and two OBJ palettes.
+    pub fn lock_key0(&mut self, key0: u8) {
+        self.key0 = key0;
+        self.dmg_compat = self.is_cgb() && key0 & 0x04 != 0;
+        self.ppu.set_dmg_compat(self.dmg_compat);
     }
 
     pub fn get_audio_output(&mut self) -> Option<[[f32; 2]; AUDIO_SAMPLES]> {
-        self.apu.get_audio_output()
+        // the newest buffered sample was just produced, the chunk's first one this long ago
+        let buffered = self.apu.buffered_samples() as u64 * T_CYCLE_HZ as u64 / self.apu.sample_rate() as u64;
+        let output = self.apu.get_audio_output()?;
+        self.audio_timestamp = Timestamp(self.elapsed.0.saturating_sub(buffered));
+        Some(output)
+    }
+
+    /// Returns how much time has been emulated since power on.
+    pub fn timestamp(&self) -> Timestamp {
+        self.elapsed
+    }
+
+    /// Returns when the frame last returned by get_display_output() finished (at VBlank, or
+    /// while the LCD is off; see Ppu::set_lcd_off_policy).
+    pub fn frame_timestamp(&self) -> Timestamp {
+        self.frame_timestamp
+    }
+
+    /// Returns when the first sample of the chunk last returned by get_audio_output() was produced.
+    pub fn audio_timestamp(&self) -> Timestamp {
+        self.audio_timestamp
     }
 
-    pub fn get_display_output(&mut self) -> Option<&[u8; LCD_BYTE_WIDTH * LCD_HEIGHT]> {
-        self.ppu.get_display_output()
+    /// Returns the finished frame (if any), display_size() pixels big.
+    pub fn get_display_output(&mut self) -> Option<&[u8]> {
+        match &mut self.sgb {
+            Some(sgb) => {
+                self.ppu.get_display_output()?;
+                Some(sgb.update_frame(self.ppu.shades()))
+            },
+            None => self.ppu.get_display_output().map(|frame| frame.as_slice()),
+        }
+    }
+
+    pub fn title(&self) -> String {
+        self.cartridge.get_title()
+    }
+
+    /// Returns the width and height of frames: the LCD's, or the SNES picture's for SGB.
+    pub fn display_size(&self) -> (usize, usize) {
+        match self.model {
+            GBModel::SGB => (SGB_WIDTH, SGB_HEIGHT),
+            _ => (LCD_WIDTH, LCD_HEIGHT),
+        }
     }
 
-    pub fn entered_hblank(&self) -> bool {
-        self.ppu.entered_hblank()
+    /// Returns the palette index (see Ppu::palette_indices) of the pixel at (x, y) in the last
+    /// frame, or None outside of the LCD's picture (e.g. on the SGB border).
+    pub fn palette_index_at(&self, x: usize, y: usize) -> Option<u8> {
+        let (x, y) = match self.model {
+            GBModel::SGB => (x.checked_sub(sgb::LCD_X)?, y.checked_sub(sgb::LCD_Y)?),
+            _ => (x, y),
+        };
+        (x < LCD_WIDTH && y < LCD_HEIGHT).then(|| self.ppu.palette_indices()[y * LCD_WIDTH + x])
     }
 
+    /// Sets the inputs the game sees from the next VBlank on (see Joypad).
     pub fn update_joypad(&mut self, status: u8) {
-        self.joypad.update(status)
+        self.joypad.set_pending(status)
+    }
+
+    /// (SGB ONLY) Sets the inputs of player (0-3), which the game reads after enabling
+    /// multiplayer; they're latched along with the first player's.
+    pub fn update_joypad_player(&mut self, player: usize, status: u8) {
+        self.joypad.set_pending_player(player, status)
+    }
+
+    /// Returns the inputs the game currently sees.
+    pub fn current_inputs(&self) -> u8 {
+        self.joypad.status()
+    }
+
+    /// Latches pending inputs; in DMG compatibility mode, held button combos right after
+    /// power on also select a palette like the CGB boot ROM does.
+    fn latch_joypad(&mut self) {
+        let status = self.joypad.pending_status();
+        if self.dmg_compat() && self.frames_since_boot < PALETTE_COMBO_FRAMES {
+            if let Some(index) = palette::combo_palette_index(status) {
+                if self.dmg_palette_index != Some(index) {
+                    self.set_dmg_palette_index(index);
+                }
+            }
+        }
+
+        self.joypad.latch()
+    }
+
+    /// (DMG ONLY) Colorizes the game like the CGB boot ROM would, if it knows the game;
+    /// in DMG compatibility mode, games it doesn't know get its default palette.
+    pub fn apply_title_palette(&mut self) {
+        let default = self.dmg_compat.then_some(palette::CGB_DEFAULT_PALETTE);
+        if let Some(palette) = self.cartridge.title_palette().or(default) {
+            self.ppu.set_dmg_palette(palette);
+        }
+    }
+
+    /// (DMG ONLY) Switches to the next palette in COMBO_PALETTES.
+    pub fn cycle_dmg_palette(&mut self) {
+        let index = match self.dmg_palette_index {
+            Some(index) => (index + 1) % COMBO_PALETTES.len(),
+            None => 0,
+        };
+        self.set_dmg_palette_index(index);
+    }
+
+    /// (DMG/SGB ONLY) Lets the PPU corrupt OAM for a 16-bit INC/DEC of value (see Ppu::corrupt_oam).
+    pub fn trigger_oam_bug(&mut self, value: u16) {
+        self.ppu.corrupt_oam(value);
+    }
+
+    /// Produces audio at hz from now on (see Apu::set_sample_rate).
+    pub fn set_audio_rate(&mut self, hz: u32) {
+        self.apu.set_sample_rate(hz);
+    }
+
+    pub fn audio_rate(&self) -> u32 {
+        self.apu.sample_rate()
+    }
+
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.apu.set_resample_quality(quality);
+    }
+
+    pub fn set_audio_output(&mut self, enabled: bool) {
+        self.apu.set_output_enabled(enabled);
+    }
+
+    fn write_apu(&mut self, addr: usize, byte: u8) {
+        self.apu_registers[addr - 0xFF10] = byte;
+        if let Some(writes) = &mut self.apu_writes {
+            writes.push(ApuWrite { time: self.elapsed, addr: addr as u16, value: byte });
+        }
+        self.apu.write_io(addr, byte);
+    }
+
+    /// Starts (or stops) logging writes to the APU for take_apu_writes().
+    pub fn set_apu_write_log(&mut self, on: bool) {
+        self.apu_writes = on.then(Vec::new);
+    }
+
+    pub fn take_apu_writes(&mut self) -> Vec<ApuWrite> {
+        self.apu_writes.as_mut().map(core::mem::take).unwrap_or_default()
+    }
+
+    /// Returns writes that set up a powered-off APU like the last writes to it did: NR52,
+    /// NR50 and NR51, wave RAM, then every channel register, without triggering channels.
+    pub fn apu_setup_writes(&self) -> Vec<ApuWrite> {
+        let addrs = [0xFF26, 0xFF24, 0xFF25].into_iter()
+            .chain(0xFF30..=0xFF3F)
+            .chain((0xFF10..=0xFF23).filter(|addr| ![0xFF15, 0xFF1F].contains(addr)));
+        addrs.map(|addr: u16| {
+            let mut value = self.apu_registers[addr as usize - 0xFF10];
+            if [0xFF14, 0xFF19, 0xFF1E, 0xFF23].contains(&addr) {
+                value &= 0x7F;
+            }
+            ApuWrite { time: self.elapsed, addr, value }
+        }).collect()
+    }
+
+    pub fn set_high_pass(&mut self, on: bool) {
+        self.apu.set_high_pass(on);
+    }
+
+    pub fn set_channel_enabled(&mut self, ch: usize, enabled: bool) {
+        self.apu.set_channel_enabled(ch, enabled);
+    }
+
+    pub fn channel_enabled(&self, ch: usize) -> bool {
+        self.apu.channel_enabled(ch)
+    }
+
+    /// Adds a GameShark or Game Genie code (see Cheats::add); returns its index.
+    pub fn add_cheat(&mut self, code: &str) -> Result<usize, String> {
+        self.cheats.add(code)
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_cheat(&mut self, index: usize) -> Option<Cheat> {
+        self.cheats.remove(index)
+    }
+
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) {
+        self.cheats.set_enabled(index, enabled)
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        self.cheats.list()
+    }
+
+    /// Makes the writes of the GameShark codes that are on, through the bus like the GameShark
+    /// does (so e.g. disabled cartridge RAM ignores them), or straight to their WRAM bank.
+    fn apply_cheats(&mut self) {
+        for (bank, addr, value) in self.cheats.ram_writes() {
+            match (bank, addr as usize) {
+                // D000-DFFF, where the banks are switched
+                (Some(bank), addr) if self.cgb_mode() && (WRAM_START + WRAM_SIZE..=WRAM_END).contains(&addr) => {
+                    self.wram[bank][addr - WRAM_START - WRAM_SIZE] = value;
+                },
+                _ => self.write_byte(addr, value),
+            }
+        }
+    }
+
+    pub fn set_lcd_off_policy(&mut self, policy: LcdOffPolicy) {
+        self.ppu.set_lcd_off_policy(policy);
+    }
+
+    /// Mixes ghosting/255 of the last frame into every frame (see Ppu::set_ghosting).
+    pub fn set_lcd_ghosting(&mut self, ghosting: u8) {
+        self.ppu.set_ghosting(ghosting);
+    }
+
+    /// Tints pixels by the layer they come from (see Ppu::set_layer_tints).
+    pub fn set_layer_tints(&mut self, on: bool) {
+        self.ppu.set_layer_tints(on);
+    }
+
+    pub fn layer_tints(&self) -> bool {
+        self.ppu.layer_tints()
+    }
+
+    /// (CGB ONLY) Converts CGB colours with correction from now on.
+    pub fn set_colour_correction(&mut self, correction: ColourCorrection) {
+        self.ppu.set_colour_correction(correction);
+    }
+
+    /// (DMG ONLY) Shows the game in palette from now on.
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette_index = None;
+        self.ppu.set_dmg_palette(palette);
+    }
+
+    fn set_dmg_palette_index(&mut self, index: usize) {
+        self.dmg_palette_index = Some(index);
+        self.ppu.set_dmg_palette(COMBO_PALETTES[index]);
     }
 
     #[allow(dead_code)]
@@ -424,16 +1097,172 @@ impl Bus {
     }
 
     pub fn save_mbc_state(&mut self) {
-        self.cartridge.save_mbc_state()
+        match self.cartridge.save_mbc_state() {
+            Ok(true) => self.events.push(CoreEvent::BatterySaved),
+            Ok(false) => {},
+            Err(e) => {
+                self.warn(&e);
+                self.events.push(CoreEvent::Error(e));
+            },
+        }
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cartridge.set_tilt(x, y)
     }
 
-    #[cfg(target_arch = "wasm32")]
+    /// Returns true (once) if battery-backed state was written since the last call.
+    pub fn take_ram_dirty(&mut self) -> bool {
+        self.cartridge.take_ram_dirty()
+    }
+
+    pub fn push_event(&mut self, event: CoreEvent) {
+        self.events.push(event);
+    }
+
+    /// Returns true if the PPU entered VBlank during the last step.
+    pub fn entered_vblank(&self) -> bool {
+        self.ppu.entered_vblank()
+    }
+
+    /// Returns true if the LCD is on (LCDC bit 7), without a read of LCDC through the bus.
+    pub fn lcd_on(&self) -> bool {
+        self.ppu.lcd_on()
+    }
+
+    /// CRC-32 of the cartridge's ROM (see Cartridge::rom_hash).
+    pub fn rom_hash(&self) -> u32 {
+        self.cartridge.rom_hash()
+    }
+
+    pub fn poll_event(&mut self) -> Option<CoreEvent> {
+        self.events.poll()
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     pub fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
         self.cartridge.load_save(data, save_type);
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     pub fn save_id(&self) -> Option<String> {
         self.cartridge.save_id()
     }
-}
\ No newline at end of file
+}
+
+// settings, debugging aids and whatever's plugged in (rumble, IR, link cables) stay as they are
+impl Snapshot for Bus {
+    fn save(&self, state: &mut StateWriter) {
+        self.double_speed.save(state);
+        self.frames_since_boot.save(state);
+        self.elapsed.0.save(state);
+        self.frame_timestamp.0.save(state);
+        self.audio_timestamp.0.save(state);
+        self.last_bus_value.save(state);
+        self.rng.save(state);
+        self.cartridge.save(state);
+        self.joypad.save(state);
+        self.apu.save(state);
+        self.apu_registers.save(state);
+        self.ppu.save(state);
+        // only SGB cores have one, so it's there in both or neither
+        if let Some(sgb) = &self.sgb {
+            sgb.save(state);
+        }
+        self.wram.save(state);
+        self.timer.save(state);
+        self.serial.save(state);
+        self.hram.save(state);
+        self.interrupt_enable.save(state);
+        self.interrupt_flag.save(state);
+        self.dma_start.save(state);
+        self.dma_ticks.save(state);
+        self.key0.save(state);
+        self.dmg_compat.save(state);
+        self.key1.save(state);
+        self.hdma1.save(state);
+        self.hdma2.save(state);
+        self.hdma3.save(state);
+        self.hdma4.save(state);
+        self.hdma5.save(state);
+        self.rp.save(state);
+        self.svbk.save(state);
+        self.hdma_bytes.save(state);
+        self.hdma_mode.save(state);
+        self.hdma_length.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.double_speed.load(state)?;
+        self.frames_since_boot.load(state)?;
+        self.elapsed.0.load(state)?;
+        self.frame_timestamp.0.load(state)?;
+        self.audio_timestamp.0.load(state)?;
+        self.last_bus_value.load(state)?;
+        self.rng.load(state)?;
+        self.cartridge.load(state)?;
+        self.joypad.load(state)?;
+        self.apu.load(state)?;
+        self.apu_registers.load(state)?;
+        self.ppu.load(state)?;
+        if let Some(sgb) = &mut self.sgb {
+            sgb.load(state)?;
+        }
+        self.wram.load(state)?;
+        self.timer.load(state)?;
+        self.serial.load(state)?;
+        self.hram.load(state)?;
+        self.interrupt_enable.load(state)?;
+        self.interrupt_flag.load(state)?;
+        self.dma_start.load(state)?;
+        self.dma_ticks.load(state)?;
+        self.key0.load(state)?;
+        self.dmg_compat.load(state)?;
+        self.key1.load(state)?;
+        self.hdma1.load(state)?;
+        self.hdma2.load(state)?;
+        self.hdma3.load(state)?;
+        self.hdma4.load(state)?;
+        self.hdma5.load(state)?;
+        self.rp.load(state)?;
+        self.svbk.load(state)?;
+        self.hdma_bytes.load(state)?;
+        self.hdma_mode.load(state)?;
+        self.hdma_length.load(state)
+    }
+}
+
+impl Snapshot for HDMAMode {
+    fn save(&self, state: &mut StateWriter) {
+        let hdma_mode: u8 = match self {
+            HDMAMode::GDMA => 0,
+            HDMAMode::HDMA => 1,
+            HDMAMode::None => 2,
+        };
+        hdma_mode.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut hdma_mode = 0u8;
+        hdma_mode.load(state)?;
+        *self = match hdma_mode {
+            0 => HDMAMode::GDMA,
+            1 => HDMAMode::HDMA,
+            2 => HDMAMode::None,
+            _ => return Err(StateError::Invalid("HDMA mode")),
+        };
+        Ok(())
+    }
+}
+
+/// (TURBO ONLY) Turns t_cycles of CPU time into the T-cycles everything else runs for: one
+/// M-cycle for every turbo M-cycles of the CPU's, with the rest carried over to the next call.
+fn turbo_scale(t_cycles: u32, turbo: u32, carry: &mut u32) -> u32 {
+    if turbo == 1 {
+        return t_cycles;
+    }
+
+    let m_cycles = t_cycles / 4 + *carry;
+    *carry = m_cycles % turbo;
+    m_cycles / turbo * 4
+}
@@ -0,0 +1,107 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+// the address space is counted in 256-byte pages, shown as a 16x16 heatmap
+const PAGE_COUNT: usize = 0x100;
+const HEATMAP_SHADES: &[u8] = b" .:-=+*#%@";
+
+// (name, first address, last address)
+const REGIONS: [(&str, u16, u16); 10] = [
+    ("ROM0", 0x0000, 0x3FFF),
+    ("ROMX", 0x4000, 0x7FFF),
+    ("VRAM", 0x8000, 0x9FFF),
+    ("SRAM", 0xA000, 0xBFFF),
+    ("WRAM", 0xC000, 0xDFFF),
+    ("ECHO", 0xE000, 0xFDFF),
+    ("OAM", 0xFE00, 0xFE9F),
+    ("UNUSED", 0xFEA0, 0xFEFF),
+    ("IO", 0xFF00, 0xFF7F),
+    ("HRAM/IE", 0xFF80, 0xFFFF),
+];
+
+/// Counts CPU reads and writes (DMA transfers aren't included) per region and per page,
+/// for finding unexpectedly hot memory traffic.
+pub struct BusStats {
+    region_reads: [u64; REGIONS.len()],
+    region_writes: [u64; REGIONS.len()],
+    page_accesses: [u64; PAGE_COUNT],
+}
+
+impl BusStats {
+    pub fn new() -> Self {
+        BusStats {
+            region_reads: [0; REGIONS.len()],
+            region_writes: [0; REGIONS.len()],
+            page_accesses: [0; PAGE_COUNT],
+        }
+    }
+
+    pub fn record(&mut self, addr: u16, write: bool) {
+        let region = REGIONS.iter().position(|&(_, start, end)| (start..=end).contains(&addr)).unwrap();
+        if write {
+            self.region_writes[region] += 1;
+        } else {
+            self.region_reads[region] += 1;
+        }
+        self.page_accesses[addr as usize >> 8] += 1;
+    }
+
+    /// Returns (region name, reads, writes) for every memory region.
+    #[allow(dead_code)]
+    pub fn region_totals(&self) -> Vec<(&'static str, u64, u64)> {
+        REGIONS.iter().enumerate()
+            .map(|(i, &(name, _, _))| (name, self.region_reads[i], self.region_writes[i]))
+            .collect()
+    }
+
+    /// Formats per-region totals followed by a heatmap of accesses per page,
+    /// one row per 4 KiB (shaded on a log scale relative to the busiest page).
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+
+        writeln!(report, "{:<8} {:>12} {:>12}", "REGION", "READS", "WRITES").unwrap();
+        for (name, reads, writes) in self.region_totals() {
+            writeln!(report, "{:<8} {:>12} {:>12}", name, reads, writes).unwrap();
+        }
+        writeln!(report).unwrap();
+
+        let max = self.page_accesses.iter().copied().max().unwrap_or(0);
+        let max_log = libm::log((max + 1) as f64);
+        writeln!(report, "      0123456789ABCDEF").unwrap();
+        for (row, pages) in self.page_accesses.chunks(0x10).enumerate() {
+            let shades: String = pages.iter().map(|&count| {
+                let level = if max == 0 { 0.0 } else { libm::log((count + 1) as f64) / max_log };
+                let shade = libm::round(level * (HEATMAP_SHADES.len() - 1) as f64) as usize;
+                HEATMAP_SHADES[shade] as char
+            }).collect();
+            writeln!(report, "{:04X}: {}", row << 12, shades).unwrap();
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BusStats;
+
+    #[test]
+    fn bus_stats_test() {
+        let mut stats = BusStats::new();
+        for _ in 0..100 {
+            stats.record(0xFF44, false);
+        }
+        stats.record(0xC000, true);
+        stats.record(0x4000, false);
+
+        let totals = stats.region_totals();
+        assert!(totals.contains(&("IO", 100, 0)));
+        assert!(totals.contains(&("WRAM", 0, 1)));
+        assert!(totals.contains(&("ROMX", 1, 0)));
+
+        // the busiest page gets the darkest shade, untouched pages stay blank
+        let report = stats.report();
+        assert!(report.contains("F000:                @"));
+        assert!(report.contains("0000:                 "));
+    }
+}
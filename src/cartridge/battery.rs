@@ -1,31 +1,66 @@
-#[cfg(not(target_arch = "wasm32"))]
-use std::fs::{create_dir_all, read, write};
+use alloc::{string::String, vec::Vec};
+#[cfg(not(feature = "std"))]
+use alloc::string::ToString;
 
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::fs::{copy, create_dir_all, read, read_dir, remove_file, rename, File, OpenOptions};
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::io::{self, Seek, SeekFrom, Write};
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::path::{Path, PathBuf};
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use crate::config::MMAP_SRAM;
+
+
+#[cfg(all(feature = "std", target_arch = "wasm32"))]
 use {
     serde_wasm_bindgen::{from_value, to_value},
     crate::{save_to_db, load_from_db, log},
 };
 
-use super::{mbc::RAM_BANK_SIZE, rtc::{Rtc, RTC_REGISTERS_SIZE}};
+use super::{mbc::RAM_BANK_SIZE, rtc::{Rtc, RTC_FOOTER_SIZE, RTC_REGISTERS_SIZE}, sram::Sram};
 pub const SAVE_PATH: &str = "saves";
 
-/// Saves and loads RAM and/or RTC state to a file; identified by cartridge header title.
-#[cfg(not(target_arch = "wasm32"))]
+// appended to a save's path for the copy kept of a corrupted save
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+const CORRUPTED_EXTENSION: &str = "corrupted";
+// appended to a save's path for the copy kept of a save that doesn't fit the game's RAM
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+const MISMATCHED_EXTENSION: &str = "mismatched";
+// appended to a save's path for the file keeping its checksum
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+const CHECKSUM_EXTENSION: &str = "crc";
+// ends the checksum trailer (the save's CRC-32, then this) older versions put at the end of saves
+const CHECKSUM_MAGIC: &[u8; 4] = b"MGCK";
+const CHECKSUM_TRAILER_SIZE: usize = 8;
+
+/// Saves and loads RAM and/or RTC state to a file; identified by cartridge header title and hash.
+/// MBC3 clocks are kept in the RAM save as the RTC footer used by VBA/BGB .sav files.
+/// Every save is written to a temporary file that then replaces the old one, with its CRC-32
+/// kept next to it in a .crc file (so the save itself stays a plain .sav other emulators
+/// read); saves that don't match their checksum aren't loaded. RAM mapped onto its save file
+/// (MMAP_SRAM) is written in place, so it has no checksum.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
 pub struct Battery {
     save_folder: String,
     ram_file_location: String,
     rtc_file_location: String,
 }
 
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
 impl Battery {
-    pub fn new(id_name: String) -> Self {
-        let save_folder: String = format!("{}/{}", SAVE_PATH, id_name);
-        let ram_file_location = format!("{}/ram", save_folder);
+    /// Creates a battery saving to save_dir/id_name, where id_name is the title followed by
+    /// the header hash; existing saves of the same title are migrated there (see migrate_save).
+    pub fn new(save_dir: &str, title: &str, id_name: String) -> Self {
+        let save_folder: String = format!("{}/{}", save_dir, id_name);
+        let ram_file_location = Battery::save_location(save_dir, &id_name);
         let rtc_file_location = format!("{}/rtc", save_folder);
 
+        if let Err(e) = Battery::migrate_save(save_dir, title, &save_folder) {
+            println!("Unable to migrate existing save: {}", e);
+        }
+
         Battery { 
             save_folder, 
             ram_file_location, 
@@ -33,54 +68,238 @@ impl Battery {
         }
     }
 
-    /// Saves current RAM state.
-    pub fn save_ram(&self, ram: &Vec<[u8; RAM_BANK_SIZE]>) {
-        if let Err(e) = create_dir_all(&self.save_folder) {
-            println!("Failed to create directory: {}", e);
+    /// Returns the path of the RAM save for id_name in save_dir.
+    pub fn save_location(save_dir: &str, id_name: &str) -> String {
+        format!("{}/{}/ram", save_dir, id_name)
+    }
+
+    /// If save_folder doesn't exist yet, copies in the save of the same title from either the
+    /// default save directory or a folder with a different hash (e.g. from a build that hashed
+    /// headers differently). Nothing is copied if more than one such save is found.
+    fn migrate_save(save_dir: &str, title: &str, save_folder: &str) -> Result<(), String> {
+        if Path::new(save_folder).exists() {
+            return Ok(());
         }
 
-        let ram_flat: Vec<u8> = ram.iter().flatten().copied().collect();
+        let mut candidates: Vec<PathBuf> = Vec::new();
+        for dir in [save_dir, SAVE_PATH] {
+            let entries = match read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let same_title = match name.strip_prefix(title) {
+                    Some(hash) => !hash.is_empty() && hash.chars().all(|c| c.is_ascii_digit()),
+                    None => false,
+                };
+                if same_title && entry.path().is_dir() && !candidates.contains(&entry.path()) {
+                    candidates.push(entry.path());
+                }
+            }
+        }
+
+        let old_folder = match candidates.as_slice() {
+            [old_folder] => old_folder,
+            [] => return Ok(()),
+            _ => return Err(format!("found {} saves for {}, not sure which to use", candidates.len(), title)),
+        };
+
+        create_dir_all(save_folder).map_err(|e| format!("Failed to create directory: {}", e))?;
+        for file in ["ram", "ram.crc", "rtc", "rtc.crc"] {
+            let old_file = old_folder.join(file);
+            if old_file.exists() {
+                copy(&old_file, Path::new(save_folder).join(file)).map_err(|e| e.to_string())?;
+            }
+        }
+        println!("Migrated save from {} to {}", old_folder.display(), save_folder);
+
+        Ok(())
+    }
 
-        match write(&self.ram_file_location, ram_flat) {
-            Ok(_) => println!("Saved RAM to: {}", self.ram_file_location),
-            Err(e) => println!("Unable to save RAM to {}: {}", self.ram_file_location, e)
+    /// Saves current RAM state.
+    pub fn save_ram(&self, ram: &[[u8; RAM_BANK_SIZE]]) -> Result<(), String> {
+        self.write_ram_file(ram.iter().flatten().copied().collect())
+    }
+
+    /// Saves cartridge RAM; RAM mapped onto the save file only has to be flushed.
+    pub fn save_sram(&self, sram: &Sram) -> Result<(), String> {
+        if !sram.is_mapped() {
+            return self.save_ram(sram);
+        }
+
+        match sram.flush() {
+            Ok(_) => {
+                println!("Flushed RAM to: {}", self.ram_file_location);
+                Ok(())
+            },
+            Err(e) => Err(format!("Unable to flush RAM to {}: {}", self.ram_file_location, e))
         }
     }
 
-    /// Loads RAM from last save and returns it or returns None is no valid save found.
-    pub fn load_ram(&self) -> Option<Vec<[u8; RAM_BANK_SIZE]>> {
-        match read(&self.ram_file_location) {
-            Ok(data) => {
-                println!("loaded RAM from {}", self.ram_file_location);
-                Some(Battery::parse_ram(data))
+    /// Loads cartridge RAM of ram_banks banks from last save, or maps it onto the save file
+    /// (creating it if needed) when MMAP_SRAM is on; returns None if there's nothing to load,
+    /// or if the save doesn't hold ram_banks banks (see keep_mismatched_save).
+    pub fn load_sram(&self, ram_banks: usize) -> Option<Sram> {
+        if MMAP_SRAM && ram_banks > 0 {
+            // the mapped file changes with every write, so its checksum is checked one last time
+            let saved = match Path::new(&self.ram_file_location).exists() {
+                true => Some(self.read_save(&self.ram_file_location)?),
+                false => None,
+            };
+            // mapping would resize a save that doesn't fit, so it's started again from fresh RAM
+            if let Some(data) = saved.filter(|data| data.len() / RAM_BANK_SIZE != ram_banks) {
+                self.keep_mismatched_save(data.len() / RAM_BANK_SIZE, ram_banks);
+                if let Err(e) = remove_file(&self.ram_file_location) {
+                    eprintln!("Unable to remove {}: {}", self.ram_file_location, e);
+                    return None;
+                }
             }
-            Err(_) => {
-                println!("No RAM save detected...");
-                None
+
+            let mapped = create_dir_all(&self.save_folder)
+                .and_then(|_| strip_checksum(&self.ram_file_location))
+                .and_then(|_| Sram::map_file(&self.ram_file_location, ram_banks));
+            match mapped {
+                Ok(sram) => {
+                    println!("Mapped RAM onto {}", self.ram_file_location);
+                    return Some(sram);
+                }
+                Err(e) => println!("Unable to map RAM onto {} ({}), keeping it in memory", self.ram_file_location, e),
             }
         }
+
+        self.load_ram(&[ram_banks]).map(Sram::from_banks)
+    }
+
+    /// Saves current RAM state followed by the RTC footer.
+    pub fn save_ram_and_rtc(&self, ram: Option<&Sram>, rtc: &Rtc) -> Result<(), String> {
+        let ram = match ram {
+            Some(ram) if ram.is_mapped() => ram,
+            _ => return self.write_ram_file(Battery::flatten_ram_and_rtc(ram.map_or(&[], |ram| ram), rtc)),
+        };
+
+        // the footer goes right after the mapped RAM
+        self.save_sram(ram)?;
+        let footer_start = (ram.len() * RAM_BANK_SIZE) as u64;
+        let result = OpenOptions::new().write(true).open(&self.ram_file_location).and_then(|mut file| {
+            file.set_len(footer_start + RTC_FOOTER_SIZE as u64)?;
+            file.seek(SeekFrom::Start(footer_start))?;
+            file.write_all(&rtc.to_footer())
+        });
+        result.map_err(|e| format!("Unable to save RTC to {}: {}", self.ram_file_location, e))
+    }
+
+    /// Loads RAM (see load_sram) and the RTC footer after it from last save; the RTC falls
+    /// back to the separate RTC save older versions wrote (None if neither is found). A save
+    /// that doesn't hold ram_banks banks isn't loaded at all (see keep_mismatched_save).
+    pub fn load_ram_and_rtc(&self, ram_banks: usize) -> (Option<Sram>, Option<Rtc>) {
+        if MMAP_SRAM && ram_banks > 0 {
+            let sram = self.load_sram(ram_banks);
+            let rtc = sram.as_ref()
+                .and_then(|_| read(&self.ram_file_location).ok())
+                .and_then(|data| Battery::parse_ram_and_rtc(data).1);
+            return (sram, rtc.or_else(|| self.load_rtc()));
+        }
+
+        match self.read_save(&self.ram_file_location) {
+            Some(data) => {
+                let (ram, rtc) = Battery::parse_ram_and_rtc(data);
+                if ram.len() != ram_banks {
+                    self.keep_mismatched_save(ram.len(), ram_banks);
+                    return (None, self.load_rtc());
+                }
+                (Some(Sram::from_banks(ram)), rtc.or_else(|| self.load_rtc()))
+            },
+            None => (None, self.load_rtc()),
+        }
     }
 
-    /// Saves current RTC state.
-    pub fn save_rtc(&self, rtc: &Rtc) {
+    fn write_ram_file(&self, data: Vec<u8>) -> Result<(), String> {
         if let Err(e) = create_dir_all(&self.save_folder) {
-            println!("Failed to create directory: {}", e);
+            return Err(format!("Failed to create directory: {}", e));
+        }
+
+        match write_save(&self.ram_file_location, &data) {
+            Ok(_) => {
+                println!("Saved RAM to: {}", self.ram_file_location);
+                Ok(())
+            },
+            Err(e) => Err(format!("Unable to save RAM to {}: {}", self.ram_file_location, e))
+        }
+    }
+
+    /// Reads the save at path (without the checksum trailer older versions wrote), checking it
+    /// against its checksums (if it has any); returns None if there's no save, or if it's
+    /// corrupted, in which case a copy is kept for recovery.
+    fn read_save(&self, path: &str) -> Option<Vec<u8>> {
+        let mut data = read(path).ok()?;
+        let mut expected: Vec<u32> = split_checksum(&mut data).into_iter().collect();
+        expected.extend(read_checksums(path));
+
+        // saves from other emulators (or copied in without their .crc file) have no checksum
+        if expected.is_empty() || expected.contains(&crc32(&data)) {
+            return Some(data);
         }
 
-        match write(&self.rtc_file_location, rtc.to_save()) {
-            Ok(_) => println!("Saved RTC state to: {}", self.ram_file_location),
-            Err(e) => println!("Unable to save RTC state to {}: {}", self.ram_file_location, e)
+        keep_copy(path, CORRUPTED_EXTENSION, "is corrupted (checksum mismatch)");
+        None
+    }
+
+    /// Keeps a copy of the RAM save, which holds banks banks of RAM instead of the expected
+    /// ones, as the game starts with fresh RAM instead that'd overwrite it with the next save
+    /// (Bus::new warns about the mismatch, see Cartridge::check_save_size).
+    fn keep_mismatched_save(&self, banks: usize, expected: usize) {
+        let problem = format!("holds {} RAM banks but the game has {}", banks, expected);
+        keep_copy(&self.ram_file_location, MISMATCHED_EXTENSION, &problem);
+    }
+
+    /// Loads RAM from last save and returns it, or returns None if no valid save is found or it
+    /// doesn't hold as many banks as one of sizes (see keep_mismatched_save).
+    pub fn load_ram(&self, sizes: &[usize]) -> Option<Vec<[u8; RAM_BANK_SIZE]>> {
+        let ram = match self.read_save(&self.ram_file_location) {
+            Some(data) => Battery::parse_ram(data),
+            None => {
+                println!("No RAM save detected...");
+                return None;
+            }
+        };
+        if !sizes.contains(&ram.len()) {
+            self.keep_mismatched_save(ram.len(), sizes[0]);
+            return None;
         }
+
+        println!("loaded RAM from {}", self.ram_file_location);
+        Some(ram)
     }
 
     /// Loads RTC from last save and returns it or returns None is no valid save found.
     pub fn load_rtc(&self) -> Option<Rtc> {
-        match read(&self.rtc_file_location) {
-            Ok(data) => {
-                println!("loaded RTC state from {}", self.ram_file_location);
-                Some(Battery::parse_rtc(data))
+        self.load_clock().map(Battery::parse_rtc)
+    }
+
+    /// Saves raw clock state to the RTC save (for mappers whose clock isn't an Rtc).
+    pub fn save_clock(&self, data: &[u8]) -> Result<(), String> {
+        if let Err(e) = create_dir_all(&self.save_folder) {
+            return Err(format!("Failed to create directory: {}", e));
+        }
+
+        match write_save(&self.rtc_file_location, data) {
+            Ok(_) => {
+                println!("Saved RTC state to: {}", self.rtc_file_location);
+                Ok(())
+            },
+            Err(e) => Err(format!("Unable to save RTC state to {}: {}", self.rtc_file_location, e))
+        }
+    }
+
+    /// Loads raw clock state from the RTC save or returns None if no save found.
+    pub fn load_clock(&self) -> Option<Vec<u8>> {
+        match self.read_save(&self.rtc_file_location) {
+            Some(data) => {
+                println!("loaded RTC state from {}", self.rtc_file_location);
+                Some(data)
             }
-            Err(_) => {
+            None => {
                 println!("No RTC save detected...");
                 None
             }
@@ -88,6 +307,96 @@ impl Battery {
     }
 }
 
+/// Replaces the save at path with data, keeping its checksum in path.crc. The checksum file is
+/// replaced first and holds the checksums of both the new save and the one it replaces, so
+/// whichever of them is in place if the save can't be replaced (e.g. after a crash) matches.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn write_save(path: &str, data: &[u8]) -> io::Result<()> {
+    let mut checksums = crc32(data).to_le_bytes().to_vec();
+    if let Ok(mut old) = read(path) {
+        split_checksum(&mut old);
+        checksums.extend(crc32(&old).to_le_bytes());
+    }
+
+    write_atomic(&checksum_path(path), &checksums)?;
+    write_atomic(path, data)
+}
+
+/// Replaces the file at path with data without ever leaving it half-written: everything goes
+/// to a temporary file first, which is then renamed over it.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn write_atomic(path: &str, data: &[u8]) -> io::Result<()> {
+    let temp_path = format!("{}.tmp", path);
+    let mut file = File::create(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    rename(&temp_path, path)
+}
+
+/// Returns the path of the file keeping the checksums of the save at path.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn checksum_path(path: &str) -> String {
+    format!("{}.{}", path, CHECKSUM_EXTENSION)
+}
+
+/// Reads the checksums kept for the save at path (none if it has no checksum file).
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn read_checksums(path: &str) -> Vec<u32> {
+    let data = read(checksum_path(path)).unwrap_or_default();
+    data.chunks_exact(4).map(|crc| u32::from_le_bytes(crc.try_into().unwrap())).collect()
+}
+
+/// Copies the save at path to path.extension, as it has a problem and won't be loaded.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn keep_copy(path: &str, extension: &str, problem: &str) {
+    let backup = format!("{}.{}", path, extension);
+    match copy(path, &backup) {
+        Ok(_) => eprintln!("Save {} {}, not loading it; a copy was kept at {}", path, problem, backup),
+        Err(e) => eprintln!("Save {} {}, not loading it; unable to keep a copy: {}", path, problem, e),
+    }
+}
+
+/// Removes the checksum trailer older versions wrote from the end of data, returning the
+/// checksum it held (None if data has no trailer).
+pub fn split_checksum(data: &mut Vec<u8>) -> Option<u32> {
+    let trailer_start = data.len().checked_sub(CHECKSUM_TRAILER_SIZE)?;
+    if !data.ends_with(CHECKSUM_MAGIC) {
+        return None;
+    }
+
+    let expected = u32::from_le_bytes(data[trailer_start..trailer_start + 4].try_into().unwrap());
+    data.truncate(trailer_start);
+    Some(expected)
+}
+
+/// Removes the checksums (if any) of the save at path, as they'd go stale once the save's
+/// mapped and written in place.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn strip_checksum(path: &str) -> io::Result<()> {
+    match remove_file(checksum_path(path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+
+    let mut data = match read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if split_checksum(&mut data).is_some() {
+        OpenOptions::new().write(true).open(path)?.set_len(data.len() as u64)?;
+    }
+    Ok(())
+}
+
+/// CRC-32 (as used by zip and PNG) of data.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg()))
+    })
+}
+
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 impl Battery {
     pub fn parse_ram(data: Vec<u8>) -> Vec<[u8; RAM_BANK_SIZE]> {
         data.chunks_exact(RAM_BANK_SIZE).map(|chunk| {
@@ -97,6 +406,20 @@ impl Battery {
         }).collect()
     }
 
+    /// Splits a RAM save into RAM banks and the RTC footer after them (if there is one).
+    pub fn parse_ram_and_rtc(data: Vec<u8>) -> (Vec<[u8; RAM_BANK_SIZE]>, Option<Rtc>) {
+        let ram_size = data.len() - data.len() % RAM_BANK_SIZE;
+        let rtc = Rtc::from_footer(&data[ram_size..]);
+        (Battery::parse_ram(data), rtc)
+    }
+
+    fn flatten_ram_and_rtc(ram: &[[u8; RAM_BANK_SIZE]], rtc: &Rtc) -> Vec<u8> {
+        let mut data = Vec::with_capacity(ram.len() * RAM_BANK_SIZE + RTC_FOOTER_SIZE);
+        data.extend(ram.iter().flatten());
+        data.extend(rtc.to_footer());
+        data
+    }
+
     pub fn parse_rtc(data: Vec<u8>) -> Rtc {
         let mut registers = [0; RTC_REGISTERS_SIZE + 8];
         registers.copy_from_slice(&data[0..RTC_REGISTERS_SIZE + 8]);
@@ -104,34 +427,57 @@ impl Battery {
     }
 }
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(feature = "std", target_arch = "wasm32"))]
 pub struct Battery {
     save_id: String,
 }
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(feature = "std", target_arch = "wasm32"))]
 impl Battery {
-    pub fn new(save_id: String) -> Self {
+    /// Saves are kept in the browser under save_id, so there's no save directory.
+    pub fn new(_save_dir: &str, _title: &str, save_id: String) -> Self {
         Battery { 
             save_id,
         }
     }
 
-    pub fn save_ram(&self, ram: &Vec<[u8; RAM_BANK_SIZE]>) {
+    pub fn save_ram(&self, ram: &[[u8; RAM_BANK_SIZE]]) -> Result<(), String> {
         let ram_flat: Vec<u8> = ram.iter().flatten().copied().collect();
-        save_to_db(&self.save_id, "ram", to_value(&ram_flat).unwrap());
+        save_to_db(&self.save_id, "ram", to_value(&ram_flat).map_err(|e| e.to_string())?);
+        Ok(())
     }
 
-    pub fn load_ram(&self) -> Option<Vec<[u8; RAM_BANK_SIZE]>> {
+    pub fn load_ram(&self, _sizes: &[usize]) -> Option<Vec<[u8; RAM_BANK_SIZE]>> {
         load_from_db(&self.save_id, "ram");
         None
     }
 
-    pub fn save_rtc(&self, rtc: &Rtc) {
-        save_to_db(&self.save_id, "rtc", to_value(&rtc.to_save()).unwrap())
+    pub fn save_sram(&self, sram: &Sram) -> Result<(), String> {
+        self.save_ram(sram)
     }
 
-    pub fn load_rtc(&self) -> Option<Rtc> {
+    pub fn load_sram(&self, _ram_banks: usize) -> Option<Sram> {
+        load_from_db(&self.save_id, "ram");
+        None
+    }
+
+    pub fn save_ram_and_rtc(&self, ram: Option<&Sram>, rtc: &Rtc) -> Result<(), String> {
+        let data = Battery::flatten_ram_and_rtc(ram.map_or(&[], |ram| ram), rtc);
+        save_to_db(&self.save_id, "ram", to_value(&data).map_err(|e| e.to_string())?);
+        Ok(())
+    }
+
+    pub fn load_ram_and_rtc(&self, _ram_banks: usize) -> (Option<Sram>, Option<Rtc>) {
+        load_from_db(&self.save_id, "ram");
+        (None, None)
+    }
+
+    pub fn save_clock(&self, data: &[u8]) -> Result<(), String> {
+        save_to_db(&self.save_id, "rtc", to_value(data).map_err(|e| e.to_string())?);
+        Ok(())
+    }
+
+    pub fn load_clock(&self) -> Option<Vec<u8>> {
         load_from_db(&self.save_id, "rtc");
         None
     }
@@ -139,4 +485,134 @@ impl Battery {
     pub fn save_id(&self) -> String {
         self.save_id.clone()
     }
-}
\ No newline at end of file
+
+    /// Returns the store name RAM saves for id_name are kept under.
+    pub fn save_location(_save_dir: &str, id_name: &str) -> String {
+        id_name.to_string()
+    }
+}
+
+/// Without std there's nowhere to keep saves, so battery-backed RAM and clocks only last
+/// as long as the cartridge does.
+#[cfg(not(feature = "std"))]
+pub struct Battery;
+
+#[cfg(not(feature = "std"))]
+impl Battery {
+    pub fn new(_save_dir: &str, _title: &str, _id_name: String) -> Self {
+        Battery
+    }
+
+    pub fn save_ram(&self, _ram: &[[u8; RAM_BANK_SIZE]]) -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn load_ram(&self, _sizes: &[usize]) -> Option<Vec<[u8; RAM_BANK_SIZE]>> {
+        None
+    }
+
+    pub fn save_sram(&self, _sram: &Sram) -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn load_sram(&self, _ram_banks: usize) -> Option<Sram> {
+        None
+    }
+
+    pub fn save_ram_and_rtc(&self, _ram: Option<&Sram>, _rtc: &Rtc) -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn load_ram_and_rtc(&self, _ram_banks: usize) -> (Option<Sram>, Option<Rtc>) {
+        (None, None)
+    }
+
+    pub fn save_clock(&self, _data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn load_clock(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    pub fn save_location(_save_dir: &str, id_name: &str) -> String {
+        id_name.to_string()
+    }
+}
+
+#[cfg(all(test, feature = "std", not(target_arch = "wasm32")))]
+mod tests {
+    use std::fs::{create_dir_all, read, remove_dir_all, remove_file, write};
+
+    use super::{crc32, Battery};
+    use crate::cartridge::mbc::RAM_BANK_SIZE;
+
+    #[test]
+    fn battery_migrate_save_test() {
+        let save_dir = std::env::temp_dir().join("melon-gb-battery-test");
+        let _ = remove_dir_all(&save_dir);
+        create_dir_all(save_dir.join("GAME123")).unwrap();
+        write(save_dir.join("GAME123/ram"), [0x42; RAM_BANK_SIZE]).unwrap();
+        let save_dir = save_dir.to_str().unwrap();
+
+        // a save under an older hash of the same title is picked up
+        let battery = Battery::new(save_dir, "GAME", String::from("GAME789"));
+        assert_eq!(battery.load_ram(&[1]).unwrap()[0][0], 0x42);
+        assert_eq!(Battery::save_location(save_dir, "GAME789"), format!("{}/GAME789/ram", save_dir));
+
+        // saves of other titles aren't picked up
+        let battery = Battery::new(save_dir, "GAM", String::from("GAM000"));
+        assert!(battery.load_ram(&[1]).is_none());
+
+        remove_dir_all(save_dir).unwrap();
+    }
+
+    #[test]
+    fn battery_checksum_test() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+
+        let save_dir = std::env::temp_dir().join("melon-gb-checksum-test");
+        let _ = remove_dir_all(&save_dir);
+        let save_dir = save_dir.to_str().unwrap();
+        let battery = Battery::new(save_dir, "GAME", String::from("GAME123"));
+        let ram_path = format!("{}/GAME123/ram", save_dir);
+
+        let crc_path = format!("{}.crc", ram_path);
+
+        // the save stays plain, with its checksum kept next to it
+        battery.save_ram(&[[0x42; RAM_BANK_SIZE]]).unwrap();
+        assert_eq!(battery.load_ram(&[1]).unwrap()[0][0], 0x42);
+        assert_eq!(read(&ram_path).unwrap(), [0x42; RAM_BANK_SIZE]);
+        assert_eq!(read(&crc_path).unwrap(), crc32(&[0x42; RAM_BANK_SIZE]).to_le_bytes());
+
+        // the checksum file also holds the replaced save's checksum, so the old save still
+        // loads if it's never replaced (e.g. after a crash)
+        battery.save_ram(&[[0x24; RAM_BANK_SIZE]]).unwrap();
+        assert_eq!(read(&crc_path).unwrap().len(), 8);
+        write(&ram_path, [0x42; RAM_BANK_SIZE]).unwrap();
+        assert_eq!(battery.load_ram(&[1]).unwrap()[0][0], 0x42);
+
+        // a corrupted save isn't loaded, but is kept
+        let mut data = read(&ram_path).unwrap();
+        data[0x100] ^= 0x01;
+        write(&ram_path, &data).unwrap();
+        assert!(battery.load_ram(&[1]).is_none());
+        assert_eq!(read(format!("{}.corrupted", ram_path)).unwrap(), data);
+
+        // saves without a checksum (e.g. from other emulators) are loaded as they are
+        remove_file(&crc_path).unwrap();
+        assert_eq!(battery.load_ram(&[1]).unwrap()[0][0x100], 0x43);
+
+        // as are saves with the checksum trailer older versions wrote, without it
+        let mut trailed = [0x24; RAM_BANK_SIZE].to_vec();
+        trailed.extend(crc32(&trailed).to_le_bytes());
+        trailed.extend(b"MGCK");
+        write(&ram_path, &trailed).unwrap();
+        assert_eq!(battery.load_ram(&[1]).unwrap()[0][0], 0x24);
+        trailed[0] ^= 0x01;
+        write(&ram_path, &trailed).unwrap();
+        assert!(battery.load_ram(&[1]).is_none());
+
+        remove_dir_all(save_dir).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+/// Size of the Pocket Camera's captured image in pixels.
+use alloc::{format, string::String, vec::Vec};
+
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+pub const SENSOR_PIXELS: usize = SENSOR_WIDTH * SENSOR_HEIGHT;
+
+/// Supplies what the Pocket Camera's sensor sees; frontends implement this to feed it
+/// a static image or webcam frames.
+pub trait ImageSource {
+    /// Fills pixels (SENSOR_WIDTH x SENSOR_HEIGHT, row-major) with brightness from 0 (black) to 255 (white).
+    fn capture(&mut self, pixels: &mut [u8; SENSOR_PIXELS]);
+}
+
+/// Shows the same image on every capture.
+pub struct StaticImage {
+    pixels: [u8; SENSOR_PIXELS],
+}
+
+impl StaticImage {
+    /// Creates an image from grayscale pixels of any size, scaled (nearest neighbour) to the sensor.
+    pub fn new(width: usize, height: usize, pixels: &[u8]) -> Result<Self, String> {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            return Err(format!("Expected {}x{} grayscale pixels, got {} bytes", width, height, pixels.len()));
+        }
+
+        let mut scaled = [0; SENSOR_PIXELS];
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let src_x = x * width / SENSOR_WIDTH;
+                let src_y = y * height / SENSOR_HEIGHT;
+                scaled[y * SENSOR_WIDTH + x] = pixels[src_y * width + src_x];
+            }
+        }
+
+        Ok(StaticImage { pixels: scaled })
+    }
+
+    /// Creates an image from RGBA pixels (e.g. a webcam frame drawn to a canvas).
+    #[allow(dead_code)]
+    pub fn from_rgba(width: usize, height: usize, rgba: &[u8]) -> Result<Self, String> {
+        let gray: Vec<u8> = rgba.chunks_exact(4).map(|pixel| {
+            ((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000) as u8
+        }).collect();
+        StaticImage::new(width, height, &gray)
+    }
+
+    /// Creates an image from a binary PGM (P5) file with 8-bit samples.
+    #[allow(dead_code)]
+    pub fn from_pgm(bytes: &[u8]) -> Result<Self, String> {
+        // magic number, width, height and max value, separated by whitespace or comments
+        let mut fields = Vec::new();
+        let mut i = 0;
+        while fields.len() < 4 {
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if i < bytes.len() && bytes[i] == b'#' {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+
+            let start = i;
+            while i < bytes.len() && !bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if start == i {
+                return Err(String::from("Truncated PGM header"));
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start..i]).into_owned());
+        }
+
+        if fields[0] != "P5" {
+            return Err(String::from("Not a binary PGM (P5) image"));
+        }
+        let parse = |field: &str| field.parse::<usize>().map_err(|e| format!("Invalid PGM header: {}", e));
+        let (width, height, max_value) = (parse(&fields[1])?, parse(&fields[2])?, parse(&fields[3])?);
+        if max_value == 0 || max_value > 255 {
+            return Err(format!("Unsupported PGM max value {}", max_value));
+        }
+
+        // a single whitespace byte separates the header from the samples
+        let data = bytes.get(i + 1..i + 1 + width * height).ok_or("Truncated PGM data")?;
+        let gray: Vec<u8> = data.iter().map(|&sample| (sample as usize * 255 / max_value) as u8).collect();
+        StaticImage::new(width, height, &gray)
+    }
+}
+
+impl ImageSource for StaticImage {
+    fn capture(&mut self, pixels: &mut [u8; SENSOR_PIXELS]) {
+        pixels.copy_from_slice(&self.pixels);
+    }
+}
+
+/// Fills every capture with random static, like a sensor with nothing to look at.
+pub struct NoiseImage {
+    state: u32,
+}
+
+impl NoiseImage {
+    pub fn new() -> Self {
+        NoiseImage { state: 0x2545_F491 }
+    }
+}
+
+impl ImageSource for NoiseImage {
+    fn capture(&mut self, pixels: &mut [u8; SENSOR_PIXELS]) {
+        for pixel in pixels.iter_mut() {
+            // xorshift32
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 17;
+            self.state ^= self.state << 5;
+            *pixel = (self.state >> 24) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImageSource, StaticImage, SENSOR_PIXELS, SENSOR_WIDTH};
+
+    #[test]
+    fn static_image_pgm_test() {
+        let mut pgm = b"P5\n# 2x2 checker\n2 2\n15\n".to_vec();
+        pgm.extend_from_slice(&[0, 15, 15, 0]);
+
+        let mut image = StaticImage::from_pgm(&pgm).unwrap();
+        let mut pixels = [0; SENSOR_PIXELS];
+        image.capture(&mut pixels);
+
+        assert_eq!(pixels[0], 0);
+        assert_eq!(pixels[SENSOR_WIDTH - 1], 255);
+        assert_eq!(pixels[SENSOR_PIXELS - 1], 0);
+        assert!(StaticImage::from_pgm(b"P6\n2 2\n255\n").is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use core::cell::Cell;
+#[cfg(not(feature = "std"))]
+use core::sync::atomic::{AtomicU32, Ordering};
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::time::SystemTime;
+
+#[cfg(all(feature = "std", target_arch = "wasm32"))]
+use js_sys::Date;
+
+use crate::constants::T_CYCLE_HZ;
+
+// without std there's no wall clock to read, so the frontend keeps this up to date (see set_unix_time)
+#[cfg(not(feature = "std"))]
+static UNIX_TIME: AtomicU32 = AtomicU32::new(0);
+
+/// Where cartridge clocks (MBC3's RTC, HuC3 and TAMA5) read the time from. This is the only
+/// host state the core's output depends on, so a core given a FixedClock or an EmulatedClock
+/// runs the same way every time (see Cartridge::with_clock).
+pub trait ClockSource {
+    /// Returns the current time as seconds elapsed since UNIX_EPOCH.
+    fn now(&self) -> u64;
+
+    /// Called as the core runs for t_cycles T-cycles (at single speed), for clocks that count
+    /// emulated time.
+    fn step(&self, _t_cycles: u32) {}
+}
+
+/// The host's clock, so cartridge clocks keep real time (and keep counting while the game's closed).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WallClock;
+
+impl ClockSource for WallClock {
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    fn now(&self) -> u64 {
+        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs(),
+            Err(_) => {
+                println!("Unable to get currrent system time");
+                0
+            }
+        }
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn now(&self) -> u64 {
+        let date = Date::new_0();
+        (date.get_time() / 1000.0) as u64
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn now(&self) -> u64 {
+        UNIX_TIME.load(Ordering::Relaxed) as u64
+    }
+}
+
+/// A clock stopped at the given time (seconds elapsed since UNIX_EPOCH).
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FixedClock(pub u64);
+
+impl ClockSource for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Counts emulated time from start (seconds elapsed since UNIX_EPOCH): the clock moves with
+/// the game, so it runs fast while fast-forwarding, stops while paused, and reads the same
+/// at the same point of a replay.
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+pub struct EmulatedClock {
+    start: u64,
+    t_cycles: Cell<u64>,
+}
+
+#[allow(dead_code)]
+impl EmulatedClock {
+    pub fn new(start: u64) -> Self {
+        EmulatedClock { start, t_cycles: Cell::new(0) }
+    }
+}
+
+impl ClockSource for EmulatedClock {
+    fn now(&self) -> u64 {
+        self.start + self.t_cycles.get() / T_CYCLE_HZ as u64
+    }
+
+    fn step(&self, t_cycles: u32) {
+        self.t_cycles.set(self.t_cycles.get() + t_cycles as u64);
+    }
+}
+
+/// Sets the current time (seconds elapsed since UNIX_EPOCH) that WallClock reads where there's
+/// no std to read it from; the frontend should keep it up to date from the host's RTC.
+#[cfg(not(feature = "std"))]
+pub fn set_unix_time(seconds: u64) {
+    UNIX_TIME.store(seconds as u32, Ordering::Relaxed);
+}
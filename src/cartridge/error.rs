@@ -0,0 +1,39 @@
+use alloc::string::String;
+use core::fmt;
+
+/// Why a ROM (or boot ROM) couldn't be loaded as a cartridge.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CartridgeError {
+    /// Reading a file failed: (path, reason).
+    Io(String, String),
+    /// The ROM is too small to hold a header (its size in bytes).
+    TooSmall(usize),
+    /// The header checksum at 0x014D doesn't match the header bytes.
+    BadHeaderChecksum { expected: u8, actual: u8 },
+    InvalidTitle,
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    /// The ROM holds more data than its header's ROM size allows (its size in bytes).
+    RomSizeMismatch(usize),
+    UnsupportedType(u8),
+    /// A boot ROM file has the wrong size: (path, size in bytes).
+    InvalidBootrom(String, usize),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(path, reason) => write!(f, "Error reading {}: {}", path, reason),
+            CartridgeError::TooSmall(size) => write!(f, "ROM is too small to hold a header ({} bytes)", size),
+            CartridgeError::BadHeaderChecksum { expected, actual } =>
+                write!(f, "Header checksum is {:02X} but header bytes sum to {:02X}", actual, expected),
+            CartridgeError::InvalidTitle => write!(f, "Unable to parse header title"),
+            CartridgeError::InvalidRomSize(size) => write!(f, "Invalid ROM size {:02X} in header", size),
+            CartridgeError::InvalidRamSize(size) => write!(f, "Invalid RAM size {:02X} in header", size),
+            CartridgeError::RomSizeMismatch(size) => write!(f, "ROM is larger ({} bytes) than its header says", size),
+            CartridgeError::UnsupportedType(cartridge_type) =>
+                write!(f, "Unsupported cartridge type {:02X}", cartridge_type),
+            CartridgeError::InvalidBootrom(path, size) => write!(f, "Boot ROM {} has an invalid size ({} bytes)", path, size),
+        }
+    }
+}
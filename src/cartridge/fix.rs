@@ -0,0 +1,68 @@
+use alloc::{format, string::String, vec::Vec};
+use super::error::CartridgeError;
+
+const HEADER_CHECKSUM: usize = 0x14D;
+const GLOBAL_CHECKSUM: usize = 0x14E;
+const HEADER_END: usize = 0x150;
+
+/// Recomputes the header checksum (0x014D) and global checksum (0x014E-0x014F) of rom_bytes
+/// and patches them in, e.g. for homebrew built without a header fixing tool. Returns what
+/// changed, one checksum per line. The global checksum isn't checked by real hardware,
+/// but some tools and flash carts refuse ROMs with a wrong one.
+pub fn fix_header(rom_bytes: &mut [u8]) -> Result<Vec<String>, CartridgeError> {
+    if rom_bytes.len() < HEADER_END {
+        return Err(CartridgeError::TooSmall(rom_bytes.len()));
+    }
+    let mut report = Vec::new();
+
+    let header_checksum = rom_bytes[0x134..HEADER_CHECKSUM].iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+    report.push(describe("Header checksum", rom_bytes[HEADER_CHECKSUM] as u16, header_checksum as u16, 2));
+    rom_bytes[HEADER_CHECKSUM] = header_checksum;
+
+    // every byte but the global checksum's own
+    let global_checksum = rom_bytes.iter().enumerate()
+        .filter(|(addr, _)| !(GLOBAL_CHECKSUM..HEADER_END).contains(addr))
+        .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+    let old = u16::from_be_bytes([rom_bytes[GLOBAL_CHECKSUM], rom_bytes[GLOBAL_CHECKSUM + 1]]);
+    report.push(describe("Global checksum", old, global_checksum, 4));
+    rom_bytes[GLOBAL_CHECKSUM..HEADER_END].copy_from_slice(&global_checksum.to_be_bytes());
+
+    Ok(report)
+}
+
+fn describe(name: &str, old: u16, new: u16, digits: usize) -> String {
+    if old == new {
+        format!("{}: {:02$X} (already correct)", name, new, digits)
+    } else {
+        format!("{}: {:03$X} -> {:03$X}", name, old, new, digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Cartridge;
+    use crate::cpu::test_helpers::make_test_rom;
+    use super::fix_header;
+
+    #[test]
+    fn fix_header_test() {
+        let mut rom = make_test_rom(&[]);
+        rom[0x134] = b'Z';
+        assert!(Cartridge::from_bytes(&rom).is_err());
+
+        let report = fix_header(&mut rom).unwrap();
+        assert!(report[0].contains("->"));
+        assert!(Cartridge::from_bytes(&rom).is_ok());
+
+        let sum = rom.iter().enumerate()
+            .filter(|(addr, _)| *addr != 0x14E && *addr != 0x14F)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+        assert_eq!(rom[0x14E..0x150], sum.to_be_bytes());
+
+        // fixing again changes nothing
+        let report = fix_header(&mut rom).unwrap();
+        assert!(report.iter().all(|line| line.ends_with("(already correct)")));
+        assert!(fix_header(&mut [0; 0x100]).is_err());
+    }
+}
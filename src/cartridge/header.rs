@@ -1,16 +1,30 @@
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::string::ToString;
+#[cfg(feature = "std")]
 use std::collections::hash_map::DefaultHasher;
-use std::fs::File;
-use std::hash::{Hash, Hasher};
-use std::io::{self, Read, Seek, SeekFrom};
+#[cfg(feature = "std")]
+use core::hash::{Hash, Hasher};
+
+use super::error::CartridgeError;
 
 const HEADER_SIZE: usize = 0x50;
 const HEADER_START: usize = 0x100;
 
 const LOGO_BYTES: usize = 0x30;
 
+// MMM01 multicarts boot from (and keep their header in) the last 32 KiB of ROM
+const MMM01_MENU_SIZE: usize = 0x8000;
+const MMM01_TYPES: [u8; 3] = [0x0B, 0x0C, 0x0D];
+
 const CGB_ENHANCED: u8 = 0x80;
 const CGB_ONLY: u8 = 0xC0;
 
+const SGB_SUPPORTED: u8 = 0x03;
+
+const NINTENDO_OLD_LICENSEE: u16 = 0x01;
+const NINTENDO_NEW_LICENSEE: u16 = 0x3031;
+
 #[derive(Hash)]
 pub struct Header {
     nintendo_logo: [u8; LOGO_BYTES],
@@ -30,27 +44,50 @@ pub struct Header {
 }
 
 impl Header {
-    pub fn from_file(rom_path: &str) -> io::Result<Self> {
-        let mut file = File::open(rom_path)?;
-        file.seek(SeekFrom::Start(HEADER_START as u64))?;
-        let mut header_bytes = [0; HEADER_SIZE];
-        file.read_exact(&mut header_bytes)?;
+    /// Reads the header of a whole ROM image; for MMM01 multicarts this is the menu's header.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        if bytes.len() < HEADER_START + HEADER_SIZE {
+            return Err(CartridgeError::TooSmall(bytes.len()));
+        }
+
+        if let Some(header_bytes) = Header::mmm01_header_bytes(bytes) {
+            return Header::new(header_bytes);
+        }
 
-        Ok(Header::new(header_bytes))
+        Header::new(Header::header_bytes_at(bytes, 0))
     }
 
-    #[allow(dead_code)]
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        let mut header_bytes = [0; HEADER_SIZE];
-        for i in 0..HEADER_SIZE {
-            header_bytes[i] = bytes[HEADER_START + i];
+    /// Returns the header bytes at the start of the last 32 KiB if they form a valid MMM01 header.
+    fn mmm01_header_bytes(bytes: &[u8]) -> Option<[u8; HEADER_SIZE]> {
+        if bytes.len() <= MMM01_MENU_SIZE {
+            return None;
         }
 
-        Header::new(header_bytes)
+        let header_bytes = Header::header_bytes_at(bytes, bytes.len() - MMM01_MENU_SIZE);
+        let is_mmm01 = MMM01_TYPES.contains(&header_bytes[0x47]);
+        if is_mmm01 && Header::checksum(&header_bytes) == header_bytes[0x4D] {
+            Some(header_bytes)
+        } else {
+            None
+        }
+    }
+
+    fn header_bytes_at(bytes: &[u8], offset: usize) -> [u8; HEADER_SIZE] {
+        let mut header_bytes = [0; HEADER_SIZE];
+        header_bytes.copy_from_slice(&bytes[offset + HEADER_START..offset + HEADER_START + HEADER_SIZE]);
+        header_bytes
+    }
+
+    fn checksum(header_bytes: &[u8; HEADER_SIZE]) -> u8 {
+        let mut checksum: u8 = 0;
+        for i in 0x34..=0x4C {
+            checksum = checksum.wrapping_sub(header_bytes[i]).wrapping_sub(1);
+        }
+        checksum
     }
 
     /// Constructs a header using header_bytes (from addresses 0x0100 to 0x014F)
-    pub fn new(header_bytes: [u8; HEADER_SIZE]) -> Self {
+    pub fn new(header_bytes: [u8; HEADER_SIZE]) -> Result<Self, CartridgeError> {
         let nintendo_logo = header_bytes[0x04..=0x33].try_into().unwrap();
 
         let cgb_flag = header_bytes[0x43];
@@ -62,7 +99,7 @@ impl Header {
         let title_bytes = header_bytes[0x34..=title_end].to_vec();
         let title = match String::from_utf8(title_bytes) {
             Ok(s) => s.replace("\0", ""),
-            Err(e) => panic!("Unable to parse header title: {}", e),
+            Err(_) => return Err(CartridgeError::InvalidTitle),
         };
 
         let manufacturer_code = match String::from_utf8(header_bytes[0x3F..=0x42].to_vec()) {
@@ -94,13 +131,12 @@ impl Header {
         // unused for most games
         let global_checksum = ((header_bytes[0x4E] as u16) << 8) | header_bytes[0x4F] as u16;
 
-        let mut checksum: u8 = 0;
-        for i in 0x34..=0x4C {
-            checksum = checksum.wrapping_sub(header_bytes[i]).wrapping_sub(1);
+        let checksum = Header::checksum(&header_bytes);
+        if checksum != header_checksum {
+            return Err(CartridgeError::BadHeaderChecksum { expected: checksum, actual: header_checksum });
         }
-        assert!(checksum == header_checksum, "Header bytes do not match header checksum.");
 
-        Header {
+        let header = Header {
             nintendo_logo,
             title,
             manufacturer_code,
@@ -114,11 +150,15 @@ impl Header {
             version_number,
             header_checksum, 
             global_checksum,
-        }
+        };
+
+        header.num_rom_banks()?;
+        header.num_ram_banks()?;
+        Ok(header)
     }
 
-    pub fn num_rom_banks(&self) -> usize {
-        match self.rom_size {
+    pub fn num_rom_banks(&self) -> Result<usize, CartridgeError> {
+        Ok(match self.rom_size {
             0x00 => 2,
             0x01 => 4, // Unused
             0x02 => 8,
@@ -128,20 +168,20 @@ impl Header {
             0x06 => 128,
             0x07 => 256,
             0x08 => 512,
-            _ => panic!("Invalid ROM size in header")
-        }
+            _ => return Err(CartridgeError::InvalidRomSize(self.rom_size))
+        })
     }
 
-    pub fn num_ram_banks(&self) -> usize {
-        match self.ram_size {
+    pub fn num_ram_banks(&self) -> Result<usize, CartridgeError> {
+        Ok(match self.ram_size {
             0x00 => 0,
             0x01 => 0, // Unused
             0x02 => 1,
             0x03 => 4,
             0x04 => 16,
             0x05 => 8,
-            _ =>panic!("Invalid RAM size in header")
-        }
+            _ => return Err(CartridgeError::InvalidRamSize(self.ram_size))
+        })
     }
 
     pub fn title(&self) -> String {
@@ -156,9 +196,35 @@ impl Header {
         self.cgb_flag & 0x80 !=  0
     }
 
+    /// Nintendo's licensee code, as either the old style code or the new one ("01").
+    pub fn nintendo_licensed(&self) -> bool {
+        self.licensee_code == NINTENDO_OLD_LICENSEE || self.licensee_code == NINTENDO_NEW_LICENSEE
+    }
+
+    /// The SGB only listens to games with the SGB flag set that use the new licensee code
+    /// (old licensee code 0x33), which is the only way licensee_code gets above 0xFF.
+    pub fn sgb_compatible(&self) -> bool {
+        self.sgb_flag == SGB_SUPPORTED && self.licensee_code > 0xFF
+    }
+
+    #[cfg(feature = "std")]
     pub fn get_hash_string(&self) -> String {
         let mut hasher = DefaultHasher::new();
         self.hash(&mut hasher);
         hasher.finish().to_string()
     }
+
+    /// Name battery saves are kept under: the title followed by the header hash.
+    #[cfg(feature = "std")]
+    pub fn save_name(&self) -> String {
+        let mut save_name = self.title();
+        save_name.push_str(&self.get_hash_string());
+        save_name
+    }
+
+    /// Without std nothing is saved, so the title alone will do.
+    #[cfg(not(feature = "std"))]
+    pub fn save_name(&self) -> String {
+        self.title()
+    }
 }
\ No newline at end of file
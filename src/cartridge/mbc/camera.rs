@@ -0,0 +1,346 @@
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+use crate::bus::RAM_START;
+use crate::cartridge::battery::Battery;
+use crate::cartridge::camera::{ImageSource, NoiseImage, SENSOR_HEIGHT, SENSOR_PIXELS, SENSOR_WIDTH};
+use crate::cartridge::sram::Sram;
+
+use super::{Mbc, ROM_BANK_SIZE};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+// A000-A035 (mirrored every 0x80 bytes): capture control, gain/edge flags, exposure (2),
+// edge ratio/invert/voltage, zero point and the 4x4 dither matrix of 3 thresholds per pixel
+const CAMERA_REGISTERS: usize = 0x36;
+const REG_CONTROL: usize = 0x00;
+const REG_GAIN_EDGE: usize = 0x01;
+const REG_EXPOSURE_HIGH: usize = 0x02;
+const REG_EXPOSURE_LOW: usize = 0x03;
+const REG_EDGE_RATIO: usize = 0x04;
+const REG_DITHER_START: usize = 0x06;
+
+// the captured image is written as 16x14 tiles (2bpp) into RAM bank 0 from this offset
+const IMAGE_OFFSET: usize = 0x100;
+
+const EDGE_RATIOS: [f64; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+/// Nintendo's Pocket Camera mapper: ROM/RAM banking like an MBC3 (without RTC), plus the M64282FP
+/// sensor's registers mapped over RAM when bit 4 of the RAM bank is set. Sensor gain is
+/// approximated linearly (0.88x at 0 up to ~1.67x at 31).
+pub struct PocketCamera {
+    rom: Vec<[u8; ROM_BANK_SIZE]>,
+    rom_banks: usize,
+    ram: Sram,
+    ram_banks: usize,
+    battery: Option<Battery>,
+    // battery-backed state was written since the last take_ram_dirty()
+    ram_dirty: bool,
+    ram_enabled: bool,
+    current_rom_bank: usize,
+    current_ram_bank: usize,
+    registers_mapped: bool,
+
+    registers: [u8; CAMERA_REGISTERS],
+    // T-cycles until the capture in progress finishes
+    capture_cycles: u32,
+    source: Box<dyn ImageSource>,
+}
+
+impl PocketCamera {
+    pub fn new(rom: Vec<[u8; ROM_BANK_SIZE]>, rom_banks: usize) -> Self {
+        PocketCamera {
+            rom,
+            rom_banks,
+            ram: Sram::new(0),
+            ram_banks: 0,
+            battery: None,
+            ram_dirty: false,
+            ram_enabled: false,
+            current_rom_bank: 1,
+            current_ram_bank: 0,
+            registers_mapped: false,
+            registers: [0; CAMERA_REGISTERS],
+            capture_cycles: 0,
+            source: Box::new(NoiseImage::new()),
+        }
+    }
+
+    pub fn with_ram(mut self, ram_banks: usize) -> Self {
+        self.ram = Sram::new(ram_banks);
+        self.ram_banks = ram_banks;
+        self
+    }
+
+    /// Specifies battery and loads last RAM save (if any exists).
+    pub fn with_battery(mut self, battery: Battery) -> Self {
+        if let Some(ram) = battery.load_sram(self.ram_banks) {
+            self.ram = ram;
+        }
+        self.battery = Some(battery);
+        self
+    }
+
+    fn capturing(&self) -> bool {
+        self.capture_cycles > 0
+    }
+
+    fn exposure(&self) -> u32 {
+        (self.registers[REG_EXPOSURE_HIGH] as u32) << 8 | self.registers[REG_EXPOSURE_LOW] as u32
+    }
+
+    fn write_register(&mut self, reg: usize, byte: u8) {
+        if reg >= CAMERA_REGISTERS {
+            return;
+        }
+
+        if reg != REG_CONTROL {
+            self.registers[reg] = byte;
+            return;
+        }
+
+        self.registers[REG_CONTROL] = byte & 0x07;
+        if byte & 0x01 == 0 {
+            self.capture_cycles = 0;
+        } else if !self.capturing() {
+            // M-cycles taken by the sensor; skipping the N (negative) filter saves 512 of them
+            let n_cycles = if self.registers[REG_GAIN_EDGE] & 0x80 != 0 { 0 } else { 512 };
+            self.capture_cycles = 4 * (32446 + n_cycles + 16 * self.exposure());
+        }
+    }
+
+    /// Takes a picture from the image source and writes it to RAM as dithered 2bpp tiles.
+    fn finish_capture(&mut self) {
+        self.registers[REG_CONTROL] &= !0x01;
+        if self.ram_banks == 0 {
+            return;
+        }
+
+        let mut pixels = [0; SENSOR_PIXELS];
+        self.source.capture(&mut pixels);
+
+        let gain = 0.88 + (self.registers[REG_GAIN_EDGE] & 0x1F) as f64 * 0.0255;
+        let exposure = self.exposure() as f64 / 0x1000 as f64;
+        let sensed = |x: isize, y: isize| {
+            let x = x.clamp(0, SENSOR_WIDTH as isize - 1) as usize;
+            let y = y.clamp(0, SENSOR_HEIGHT as isize - 1) as usize;
+            pixels[y * SENSOR_WIDTH + x] as f64 * gain * exposure
+        };
+
+        let edge_enhance = self.registers[REG_GAIN_EDGE] & 0xE0 == 0xE0;
+        let edge_ratio = EDGE_RATIOS[(self.registers[REG_EDGE_RATIO] as usize >> 4) & 0x7];
+
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let (xi, yi) = (x as isize, y as isize);
+                let mut value = sensed(xi, yi);
+                if edge_enhance {
+                    let neighbours = sensed(xi - 1, yi) + sensed(xi + 1, yi) + sensed(xi, yi - 1) + sensed(xi, yi + 1);
+                    value += (value * 4.0 - neighbours) * edge_ratio;
+                }
+
+                let matrix = REG_DITHER_START + ((x & 3) + (y & 3) * 4) * 3;
+                let thresholds = &self.registers[matrix..matrix + 3];
+                let color = thresholds.iter().take_while(|&&threshold| value >= threshold as f64).count();
+                let color = 3 - color as u8;
+
+                let tile = (y / 8) * (SENSOR_WIDTH / 8) + x / 8;
+                let row = IMAGE_OFFSET + tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                let bytes = &mut self.ram[0][row..row + 2];
+                bytes[0] = (bytes[0] & !(1 << bit)) | (color & 1) << bit;
+                bytes[1] = (bytes[1] & !(1 << bit)) | (color >> 1) << bit;
+            }
+        }
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn read_rom(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[0][addr],
+            0x4000..=0x7FFF => self.rom[self.current_rom_bank & (self.rom_banks - 1)][addr - 0x4000],
+            _ => unreachable!()
+        }
+    }
+
+    fn write_rom(&mut self, addr: usize, byte: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = (byte & 0xF) == 0xA,
+            0x2000..=0x3FFF => self.current_rom_bank = byte as usize & 0x3F,
+            0x4000..=0x5FFF => {
+                self.registers_mapped = byte & 0x10 != 0;
+                if !self.registers_mapped {
+                    self.current_ram_bank = byte as usize & 0x0F;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: usize) -> u8 {
+        if self.registers_mapped {
+            // only the control register can be read back
+            return match (addr - RAM_START) & 0x7F {
+                REG_CONTROL => self.registers[REG_CONTROL],
+                _ => 0x00,
+            };
+        }
+
+        // RAM is busy while the sensor writes to it
+        if self.capturing() {
+            return 0x00;
+        }
+        match self.current_ram_bank < self.ram_banks {
+            true => self.ram[self.current_ram_bank][addr - RAM_START],
+            false => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, byte: u8) {
+        if self.registers_mapped {
+            self.write_register((addr - RAM_START) & 0x7F, byte);
+            return;
+        }
+
+        if self.ram_enabled && !self.capturing() && self.current_ram_bank < self.ram_banks {
+            self.ram[self.current_ram_bank][addr - RAM_START] = byte;
+            self.ram_dirty = true;
+        }
+    }
+
+    fn step(&mut self, t_cycles: u32) {
+        if !self.capturing() {
+            return;
+        }
+
+        self.capture_cycles = self.capture_cycles.saturating_sub(t_cycles);
+        if !self.capturing() {
+            self.finish_capture();
+        }
+    }
+
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.source = source;
+    }
+
+    fn take_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.ram_dirty)
+    }
+
+    fn display(&self) -> String {
+        let mut ret = format!("Pocket Camera w/ {} ROM banks + {} RAM banks", self.rom_banks, self.ram_banks);
+        if self.battery.is_some() {
+            ret.push_str(" + Battery");
+        }
+        ret
+    }
+
+    fn save_state(&self) -> Result<bool, String> {
+        let battery = match &self.battery {
+            Some(battery) => battery,
+            None => return Ok(false)
+        };
+
+        battery.save_sram(&self.ram)?;
+        Ok(true)
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
+        assert!(save_type == "ram");
+        self.ram = Sram::from_banks(Battery::parse_ram(data));
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn save_id(&self) -> Option<String> {
+        match &self.battery {
+            Some(battery) => Some(battery.save_id()),
+            None => None,
+        }
+    }
+}
+
+impl Snapshot for PocketCamera {
+    fn save(&self, state: &mut StateWriter) {
+        self.ram.save(state);
+        self.ram_enabled.save(state);
+        self.current_rom_bank.save(state);
+        self.current_ram_bank.save(state);
+        self.registers_mapped.save(state);
+        self.registers.save(state);
+        self.capture_cycles.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ram.load(state)?;
+        self.ram_enabled.load(state)?;
+        self.current_rom_bank.load(state)?;
+        self.current_ram_bank.load(state)?;
+        self.registers_mapped.load(state)?;
+        self.registers.load(state)?;
+        self.capture_cycles.load(state)?;
+        self.ram_dirty = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mbc, PocketCamera, ROM_BANK_SIZE};
+    use crate::cartridge::camera::{StaticImage, SENSOR_PIXELS, SENSOR_WIDTH};
+
+    fn setup_camera(pixels: &[u8]) -> PocketCamera {
+        let mut mbc = PocketCamera::new(vec![[0; ROM_BANK_SIZE]; 64], 64).with_ram(16);
+        mbc.set_image_source(Box::new(StaticImage::new(SENSOR_WIDTH, SENSOR_PIXELS / SENSOR_WIDTH, pixels).unwrap()));
+
+        // exposure of 0x1000 (1x) and every dither threshold at 0x80
+        mbc.write_rom(0x4000, 0x10);
+        mbc.write_ram(0xA002, 0x10);
+        mbc.write_ram(0xA003, 0x00);
+        for addr in 0xA006..0xA036 {
+            mbc.write_ram(addr, 0x80);
+        }
+        mbc
+    }
+
+    fn capture(mbc: &mut PocketCamera) {
+        mbc.write_ram(0xA000, 0x01);
+        while mbc.read_ram(0xA000) & 0x01 != 0 {
+            mbc.step(4);
+        }
+        mbc.write_rom(0x4000, 0x00);
+    }
+
+    #[test]
+    fn camera_capture_test() {
+        let mut mbc = setup_camera(&[0; SENSOR_PIXELS]);
+
+        mbc.write_ram(0xA000, 0x01);
+        mbc.step(4);
+        assert_eq!(mbc.read_ram(0xA000), 0x01);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA100), 0x00);
+        mbc.write_rom(0x4000, 0x10);
+
+        capture(&mut mbc);
+
+        // a black image comes out as colour 3 everywhere
+        assert_eq!(mbc.read_ram(0xA100), 0xFF);
+        assert_eq!(mbc.read_ram(0xAEFF), 0xFF);
+        assert_eq!(mbc.read_ram(0xAF00), 0x00);
+    }
+
+    #[test]
+    fn camera_dither_test() {
+        // left half white, right half black
+        let pixels: Vec<u8> = (0..SENSOR_PIXELS)
+            .map(|i| if i % SENSOR_WIDTH < SENSOR_WIDTH / 2 { 0xFF } else { 0x00 })
+            .collect();
+        let mut mbc = setup_camera(&pixels);
+        capture(&mut mbc);
+
+        // first tile of a row is white, last one is black
+        assert_eq!(mbc.read_ram(0xA100), 0x00);
+        assert_eq!(mbc.read_ram(0xA101), 0x00);
+        assert_eq!(mbc.read_ram(0xA100 + 15 * 16), 0xFF);
+        assert_eq!(mbc.read_ram(0xA101 + 15 * 16), 0xFF);
+    }
+}
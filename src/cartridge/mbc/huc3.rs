@@ -0,0 +1,423 @@
+use alloc::{boxed::Box, format, rc::Rc, string::String, vec::Vec};
+use crate::bus::RAM_START;
+use crate::cartridge::battery::Battery;
+use crate::cartridge::clock::{ClockSource, WallClock};
+use crate::cartridge::sram::Sram;
+use crate::infrared::IrTransceiver;
+
+use super::{Mbc, ROM_BANK_SIZE};
+use crate::savestate::{snapshot_fields, Snapshot, StateError, StateReader, StateWriter};
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+const DAYS_MASK: u64 = 0xFFF;
+
+// bytes of minutes (2), days (2) and the time they were taken at (8)
+const CLOCK_SAVE_SIZE: usize = 12;
+
+// the RTC's internal memory is addressed in nibbles
+const RTC_MEMORY_SIZE: usize = 0x100;
+
+/// HuC3 clock, which counts minutes of the day and days (12 bits) instead of seconds.
+struct Huc3Clock {
+    minutes: u64,
+    days: u64,
+    clock_time: u64,
+}
+
+impl Huc3Clock {
+    fn new(now: u64) -> Self {
+        Huc3Clock {
+            minutes: 0,
+            days: 0,
+            clock_time: now,
+        }
+    }
+
+    fn from_save(data: &[u8]) -> Option<Self> {
+        if data.len() < CLOCK_SAVE_SIZE {
+            return None;
+        }
+
+        Some(Huc3Clock {
+            minutes: u16::from_be_bytes([data[0], data[1]]) as u64,
+            days: u16::from_be_bytes([data[2], data[3]]) as u64,
+            clock_time: u64::from_be_bytes(data[4..12].try_into().unwrap()),
+        })
+    }
+
+    fn to_save(&self, now: u64) -> [u8; CLOCK_SAVE_SIZE] {
+        let (minutes, days) = self.at(now);
+        let mut save = [0; CLOCK_SAVE_SIZE];
+        save[0..2].copy_from_slice(&(minutes as u16).to_be_bytes());
+        save[2..4].copy_from_slice(&(days as u16).to_be_bytes());
+        save[4..12].copy_from_slice(&now.to_be_bytes());
+        save
+    }
+
+    /// Returns (minute of the day, days) as of now.
+    fn at(&self, now: u64) -> (u64, u64) {
+        let elapsed_minutes = now.saturating_sub(self.clock_time) / 60;
+        let total_minutes = self.days * MINUTES_PER_DAY + self.minutes + elapsed_minutes;
+
+        (total_minutes % MINUTES_PER_DAY, (total_minutes / MINUTES_PER_DAY) & DAYS_MASK)
+    }
+
+    /// Moves the clock seconds forward (or back, down to day 0 00:00), to the minute.
+    fn adjust(&mut self, seconds: i64, now: u64) {
+        let (minutes, days) = self.at(now);
+        let total_minutes = (days * MINUTES_PER_DAY + minutes).saturating_add_signed(seconds / 60);
+        self.set(total_minutes % MINUTES_PER_DAY, total_minutes / MINUTES_PER_DAY, now);
+    }
+
+    fn set(&mut self, minutes: u64, days: u64, now: u64) {
+        self.minutes = minutes % MINUTES_PER_DAY;
+        self.days = days & DAYS_MASK;
+        self.clock_time = now;
+    }
+}
+
+/// Hudson's mapper used by Robopon and Pokémon Card GB2. The RAM window doubles as an
+/// interface to the RTC (driven by 4-bit commands) and IR port, depending on the selected mode.
+/// The melody IC's tone generator is accepted but produces no sound.
+pub struct Huc3 {
+    rom: Vec<[u8; ROM_BANK_SIZE]>,
+    rom_banks: usize,
+    ram: Sram,
+    ram_banks: usize,
+    battery: Option<Battery>,
+    // battery-backed state was written since the last take_ram_dirty()
+    ram_dirty: bool,
+    clock: Huc3Clock,
+    clock_source: Rc<dyn ClockSource>,
+    current_rom_bank: usize,
+    current_ram_bank: usize,
+    mode: u8,
+
+    rtc_memory: [u8; RTC_MEMORY_SIZE],
+    rtc_address: usize,
+    rtc_command: u8,
+    rtc_response: u8,
+
+    // what the IR port faces (nothing if None)
+    ir: Option<Box<dyn IrTransceiver>>,
+}
+
+impl Huc3 {
+    pub fn new(rom: Vec<[u8; ROM_BANK_SIZE]>, rom_banks: usize) -> Self {
+        Huc3 {
+            rom,
+            rom_banks,
+            ram: Sram::new(0),
+            ram_banks: 0,
+            battery: None,
+            ram_dirty: false,
+            clock: Huc3Clock::new(WallClock.now()),
+            clock_source: Rc::new(WallClock),
+            current_rom_bank: 1,
+            current_ram_bank: 0,
+            mode: 0,
+            rtc_memory: [0; RTC_MEMORY_SIZE],
+            rtc_address: 0,
+            rtc_command: 0,
+            rtc_response: 0,
+            ir: None,
+        }
+    }
+
+    pub fn with_ram(mut self, ram_banks: usize) -> Self {
+        self.ram = Sram::new(ram_banks);
+        self.ram_banks = ram_banks;
+        self
+    }
+
+    /// Has the RTC count with clock_source instead of the host's clock (restarting it from zero).
+    pub fn with_clock(mut self, clock_source: Rc<dyn ClockSource>) -> Self {
+        self.clock = Huc3Clock::new(clock_source.now());
+        self.clock_source = clock_source;
+        self
+    }
+
+    /// Specifies battery and loads last RAM and clock saves (if any exist).
+    pub fn with_battery(mut self, battery: Battery) -> Self {
+        if let Some(ram) = battery.load_sram(self.ram_banks) {
+            self.ram = ram;
+        }
+        if let Some(clock) = battery.load_clock().and_then(|data| Huc3Clock::from_save(&data)) {
+            self.clock = clock;
+        }
+        self.battery = Some(battery);
+        self
+    }
+
+    /// Runs an RTC command written in mode 0xB: upper nibble is the command, lower is its argument.
+    fn rtc_command(&mut self, byte: u8) {
+        let command = (byte >> 4) & 0x7;
+        let arg = byte & 0xF;
+        self.rtc_command = command;
+
+        match command {
+            // read nibble and increment address
+            0x1 => {
+                self.rtc_response = self.rtc_memory[self.rtc_address];
+                self.rtc_address = (self.rtc_address + 1) % RTC_MEMORY_SIZE;
+            },
+            // write nibble and increment address
+            0x3 => {
+                self.rtc_memory[self.rtc_address] = arg;
+                self.rtc_address = (self.rtc_address + 1) % RTC_MEMORY_SIZE;
+            },
+            0x4 => self.rtc_address = (self.rtc_address & 0xF0) | arg as usize,
+            0x5 => self.rtc_address = (self.rtc_address & 0x0F) | (arg as usize) << 4,
+            0x6 => self.rtc_extended_command(arg),
+            _ => {}
+        }
+    }
+
+    fn rtc_extended_command(&mut self, arg: u8) {
+        match arg {
+            // copy current time to memory 0x00-0x05
+            0x0 => {
+                let (minutes, days) = self.clock.at(self.clock_source.now());
+                for i in 0..3 {
+                    self.rtc_memory[i] = ((minutes >> (4 * i)) & 0xF) as u8;
+                    self.rtc_memory[i + 3] = ((days >> (4 * i)) & 0xF) as u8;
+                }
+            },
+            // set current time from memory 0x00-0x05
+            0x1 => {
+                self.ram_dirty = true;
+                let mut minutes = 0;
+                let mut days = 0;
+                for i in 0..3 {
+                    minutes |= (self.rtc_memory[i] as u64) << (4 * i);
+                    days |= (self.rtc_memory[i + 3] as u64) << (4 * i);
+                }
+                self.clock.set(minutes, days, self.clock_source.now());
+            },
+            // status check, always reports the clock as running
+            0x2 => self.rtc_response = 0x1,
+            // 0xE starts the tone generator (not emulated)
+            _ => {}
+        }
+    }
+}
+
+impl Mbc for Huc3 {
+    fn read_rom(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[0][addr],
+            0x4000..=0x7FFF => self.rom[self.current_rom_bank & (self.rom_banks - 1)][addr - 0x4000],
+            _ => unreachable!()
+        }
+    }
+
+    fn write_rom(&mut self, addr: usize, byte: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.mode = byte & 0xF,
+            0x2000..=0x3FFF => self.current_rom_bank = byte as usize & 0x7F,
+            0x4000..=0x5FFF => {
+                let ram_bank = byte as usize & 0x03;
+                if ram_bank < self.ram_banks {
+                    self.current_ram_bank = ram_bank;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: usize) -> u8 {
+        match self.mode {
+            0x0 | 0xA if self.ram_banks > 0 => self.ram[self.current_ram_bank][addr - RAM_START],
+            0xC => 0x80 | (self.rtc_command << 4) | self.rtc_response,
+            // semaphore: commands finish immediately so the RTC is always ready
+            0xD => 0x01,
+            // IR: bit 0 is set while light is received
+            0xE => 0xC0 | self.ir.as_ref().is_some_and(|ir| ir.light_received()) as u8,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, byte: u8) {
+        match self.mode {
+            0xA if self.ram_banks > 0 => {
+                self.ram[self.current_ram_bank][addr - RAM_START] = byte;
+                self.ram_dirty = true;
+            },
+            0xB => self.rtc_command(byte),
+            0xE => if let Some(ir) = &mut self.ir {
+                ir.set_led(byte & 0x01 != 0);
+            },
+            _ => {}
+        }
+    }
+
+    fn has_infrared(&self) -> bool {
+        true
+    }
+
+    fn connect_infrared(&mut self, ir: Box<dyn IrTransceiver>) {
+        self.ir = Some(ir);
+    }
+
+    fn has_clock(&self) -> bool {
+        true
+    }
+
+    fn adjust_clock(&mut self, seconds: i64) {
+        self.clock.adjust(seconds, self.clock_source.now());
+        self.ram_dirty = true;
+    }
+
+    fn take_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.ram_dirty)
+    }
+
+    fn display(&self) -> String {
+        let mut ret = format!("HuC3 w/ {} ROM banks + {} RAM banks + RTC", self.rom_banks, self.ram_banks);
+        if self.battery.is_some() {
+            ret.push_str(" + Battery");
+        }
+        ret
+    }
+
+    fn save_state(&self) -> Result<bool, String> {
+        let battery = match &self.battery {
+            Some(battery) => battery,
+            None => return Ok(false)
+        };
+
+        battery.save_sram(&self.ram)?;
+        battery.save_clock(&self.clock.to_save(self.clock_source.now()))?;
+        Ok(true)
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
+        match save_type {
+            "ram" => self.ram = Sram::from_banks(Battery::parse_ram(data)),
+            "rtc" => if let Some(clock) = Huc3Clock::from_save(&data) {
+                self.clock = clock;
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn save_id(&self) -> Option<String> {
+        match &self.battery {
+            Some(battery) => Some(battery.save_id()),
+            None => None,
+        }
+    }
+}
+
+impl Snapshot for Huc3 {
+    fn save(&self, state: &mut StateWriter) {
+        self.ram.save(state);
+        self.clock.save(state);
+        self.current_rom_bank.save(state);
+        self.current_ram_bank.save(state);
+        self.mode.save(state);
+        self.rtc_memory.save(state);
+        self.rtc_address.save(state);
+        self.rtc_command.save(state);
+        self.rtc_response.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ram.load(state)?;
+        self.clock.load(state)?;
+        self.current_rom_bank.load(state)?;
+        self.current_ram_bank.load(state)?;
+        self.mode.load(state)?;
+        self.rtc_memory.load(state)?;
+        self.rtc_address.load(state)?;
+        self.rtc_command.load(state)?;
+        self.rtc_response.load(state)?;
+        self.ram_dirty = true;
+        Ok(())
+    }
+}
+
+snapshot_fields!(Huc3Clock, minutes, days, clock_time);
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+
+    use crate::cartridge::clock::{ClockSource, EmulatedClock, FixedClock};
+    use crate::constants::T_CYCLE_HZ;
+    use super::{Huc3, Mbc, ROM_BANK_SIZE};
+
+    fn rtc_command(mbc: &mut Huc3, byte: u8) {
+        mbc.write_rom(0x0000, 0xB);
+        mbc.write_ram(0xA000, byte);
+    }
+
+    /// Writes the time (minutes then days, as 6 nibbles) into memory 0x00-0x05, then sets the
+    /// clock from it.
+    fn set_time(mbc: &mut Huc3, nibbles: [u8; 6]) {
+        rtc_command(mbc, 0x40);
+        rtc_command(mbc, 0x50);
+        for nibble in nibbles {
+            rtc_command(mbc, 0x30 | nibble);
+        }
+        rtc_command(mbc, 0x61);
+    }
+
+    /// Clears memory 0x00-0x05, copies the time back into it and reads it out.
+    fn read_time(mbc: &mut Huc3) -> Vec<u8> {
+        rtc_command(mbc, 0x40);
+        for _ in 0..6 {
+            rtc_command(mbc, 0x30);
+        }
+        rtc_command(mbc, 0x60);
+        rtc_command(mbc, 0x40);
+
+        let mut nibbles = Vec::new();
+        for _ in 0..6 {
+            rtc_command(mbc, 0x10);
+            mbc.write_rom(0x0000, 0xC);
+            let response = mbc.read_ram(0xA000);
+            assert_eq!(response & 0xF0, 0x90);
+            nibbles.push(response & 0xF);
+        }
+        nibbles
+    }
+
+    #[test]
+    fn huc3_rtc_commands_test() {
+        let mut mbc = Huc3::new(vec![[0; ROM_BANK_SIZE]; 2], 2).with_clock(Rc::new(FixedClock(0))).with_ram(1);
+
+        // 1 day, 0x123 minutes
+        set_time(&mut mbc, [0x3, 0x2, 0x1, 0x1, 0x0, 0x0]);
+        assert_eq!(read_time(&mut mbc), [0x3, 0x2, 0x1, 0x1, 0x0, 0x0]);
+    }
+
+    #[test]
+    fn huc3_rtc_advance_test() {
+        let clock = Rc::new(EmulatedClock::new(0));
+        let mut mbc = Huc3::new(vec![[0; ROM_BANK_SIZE]; 2], 2).with_clock(clock.clone()).with_ram(1);
+
+        // 23:59 on day 1 rolls over to 00:00 on day 2 a minute of emulated time later
+        set_time(&mut mbc, [0xF, 0x9, 0x5, 0x1, 0x0, 0x0]);
+        clock.step(T_CYCLE_HZ * 59);
+        assert_eq!(read_time(&mut mbc), [0xF, 0x9, 0x5, 0x1, 0x0, 0x0]);
+        clock.step(T_CYCLE_HZ);
+        assert_eq!(read_time(&mut mbc), [0x0, 0x0, 0x0, 0x2, 0x0, 0x0]);
+    }
+
+    #[test]
+    fn huc3_ram_modes_test() {
+        let mut mbc = Huc3::new(vec![[0; ROM_BANK_SIZE]; 2], 2).with_clock(Rc::new(FixedClock(0))).with_ram(1);
+
+        mbc.write_rom(0x0000, 0xA);
+        mbc.write_ram(0xA000, 0x42);
+        mbc.write_rom(0x0000, 0x0);
+        mbc.write_ram(0xA000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x42);
+
+        mbc.write_rom(0x0000, 0xD);
+        assert_eq!(mbc.read_ram(0xA000), 0x01);
+    }
+}
@@ -1,20 +1,33 @@
-use std::cmp::min;
+use alloc::{format, string::String, vec::Vec};
+use core::cmp::min;
 
 use crate::bus::RAM_START;
 use crate::cartridge::battery::Battery;
+use crate::cartridge::sram::Sram;
 
-use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use super::{Mbc, ROM_BANK_SIZE};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+// multicarts are 8 Mbit, with each game's header (and Nintendo logo) at the start of a 16-bank block
+const MULTICART_ROM_BANKS: usize = 64;
+const MULTICART_GAME_BANKS: usize = 0x10;
+const LOGO_START: usize = 0x104;
+const LOGO_END: usize = 0x134;
 
 pub struct Mbc1 {
     rom: Vec<[u8; ROM_BANK_SIZE]>,
     rom_banks: usize,
-    ram: Option<Vec<[u8; RAM_BANK_SIZE]>>,
+    ram: Option<Sram>,
     ram_banks: usize,
     battery: Option<Battery>,
+    // battery-backed state was written since the last take_ram_dirty()
+    ram_dirty: bool,
     current_rom_bank: usize,
     current_ram_bank: usize,
     ram_enabled: bool,
     banking_mode: bool,
+    // MBC1M wiring: BANK2 drives ROM bank bits 4-5 and BANK1's bit 4 isn't connected
+    multicart: bool,
 }
 
 impl Mbc1 {
@@ -25,16 +38,44 @@ impl Mbc1 {
             ram: None,
             ram_banks: 0,
             battery: None,
+            ram_dirty: false,
             current_rom_bank: 1,
             current_ram_bank: 0,
             ram_enabled: false,
             banking_mode: false,
+            multicart: false,
+        }
+    }
+
+    /// Specifies MBC1M multicart wiring (see detect_multicart()).
+    pub fn with_multicart(mut self, multicart: bool) -> Self {
+        self.multicart = multicart;
+        self
+    }
+
+    /// Returns true if rom looks like an MBC1M multicart: the header says MBC1, but
+    /// a second copy of the Nintendo logo shows up at bank 0x10 (the second game's menu entry).
+    pub fn detect_multicart(rom_bytes: &[u8]) -> bool {
+        if rom_bytes.len() != MULTICART_ROM_BANKS * ROM_BANK_SIZE {
+            return false;
+        }
+
+        let game_start = MULTICART_GAME_BANKS * ROM_BANK_SIZE;
+        rom_bytes[LOGO_START..LOGO_END] == rom_bytes[game_start + LOGO_START..game_start + LOGO_END]
+    }
+
+    /// Returns which bits of current_rom_bank come from the BANK2 register.
+    fn bank2_mask(&self) -> usize {
+        if self.multicart {
+            0b0110000
+        } else {
+            0b1100000
         }
     }
 
     /// Specifies RAM (sets ram to not None).
     pub fn with_ram(mut self, ram_banks: usize) -> Self {
-        self.ram = Some(vec![[0; RAM_BANK_SIZE]; ram_banks]);
+        self.ram = Some(Sram::new(ram_banks));
         self.ram_banks = ram_banks;
         self
     }
@@ -42,13 +83,7 @@ impl Mbc1 {
     /// ASSUMES: RAM has already be set to not None
     /// Specifies battery and loads last RAM save (if any exists), otherwise creates a new one.
     pub fn with_battery(mut self, battery: Battery) -> Self { 
-        self.ram = Some(match battery.load_ram() {
-            Some(ram) => {
-                assert!(ram.len() == self.ram_banks, "Invalid RAM Save Size!");
-                ram
-            },
-            None => vec![[0; RAM_BANK_SIZE]; self.ram_banks],
-        });
+        self.ram = Some(battery.load_sram(self.ram_banks).unwrap_or_else(|| Sram::new(self.ram_banks)));
         self.battery = Some(battery);
         self
     }
@@ -59,7 +94,7 @@ impl Mbc for Mbc1 {
         match addr {
             0x0000..=0x3FFF => {
                 let rom_bank = if self.banking_mode {
-                    self.current_rom_bank & 0b1100000
+                    self.current_rom_bank & self.bank2_mask()
                 } else {
                     0
                 };
@@ -75,6 +110,10 @@ impl Mbc for Mbc1 {
     fn write_rom(&mut self, addr: usize, byte: u8) {
         match addr {
             0x0000..=0x1FFF => self.ram_enabled = (byte & 0xF) == 0xA,
+            0x2000..=0x3FFF if self.multicart => {
+                let byte = byte + (byte & 0b11111 == 0) as u8;
+                self.current_rom_bank = (self.current_rom_bank & self.bank2_mask()) | (byte & 0b1111) as usize;
+            },
             0x2000..=0x3FFF => {
                 let byte = byte + (byte & 0b11111 == 0) as u8;
                 let mask = min(self.rom_banks as u8 - 1, 0b11111);
@@ -85,7 +124,9 @@ impl Mbc for Mbc1 {
                     self.current_ram_bank = (byte & (self.ram_banks as u8 - 1)) as usize;
                 }
 
-                if self.rom_banks > 0b11111 {
+                if self.multicart {
+                    self.current_rom_bank = ((byte & 0b11) << 4) as usize | (self.current_rom_bank & 0b1111);
+                } else if self.rom_banks > 0b11111 {
                     let mask = ((self.rom_banks as u8 - 1) - 0b11111) >> 5;
                     self.current_rom_bank = ((byte & mask) << 5) as usize | (self.current_rom_bank & 0b11111);
                 } 
@@ -117,14 +158,21 @@ impl Mbc for Mbc1 {
 
         let ram_bank = if self.banking_mode { self.current_ram_bank } else { 0 };
         
-        match &mut self.ram {
-            Some(ram) => ram[ram_bank][addr - RAM_START] = byte,
-            None => {}
-        };
+        if let Some(ram) = &mut self.ram {
+            ram[ram_bank][addr - RAM_START] = byte;
+            self.ram_dirty = true;
+        }
+    }
+
+    fn take_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.ram_dirty)
     }
 
     fn display(&self) -> String {
         let mut ret = format!("Mbc1 w/ {} ROM banks", self.rom_banks);
+        if self.multicart {
+            ret.push_str(" (Multicart)");
+        }
         if self.ram.is_some() {
             ret.push_str(&format!(" + {} RAM banks", self.ram_banks));
         }
@@ -134,27 +182,28 @@ impl Mbc for Mbc1 {
         ret
     }
 
-    fn save_state(&self) {
+    fn save_state(&self) -> Result<bool, String> {
         let battery = match &self.battery {
             Some(battery) => battery,
-            None => return
+            None => return Ok(false)
         };
 
-        let ram: &Vec<[u8; 8192]> = match &self.ram {
+        let ram: &Sram = match &self.ram {
             Some(ram) => ram,
-            None => return
+            None => return Ok(false)
         };
 
-        battery.save_ram(ram);
+        battery.save_sram(ram)?;
+        Ok(true)
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
         assert!(save_type == "ram");
-        self.ram = Some(Battery::parse_ram(data));
+        self.ram = Some(Sram::from_banks(Battery::parse_ram(data)));
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn save_id(&self) -> Option<String> {
         match &self.battery {
             Some(battery) => Some(battery.save_id()),
@@ -163,9 +212,74 @@ impl Mbc for Mbc1 {
     }
 }
 
+impl Snapshot for Mbc1 {
+    fn save(&self, state: &mut StateWriter) {
+        self.ram.save(state);
+        self.current_rom_bank.save(state);
+        self.current_ram_bank.save(state);
+        self.ram_enabled.save(state);
+        self.banking_mode.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ram.load(state)?;
+        self.current_rom_bank.load(state)?;
+        self.current_ram_bank.load(state)?;
+        self.ram_enabled.load(state)?;
+        self.banking_mode.load(state)?;
+        self.ram_dirty = true;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::cpu::{test_helpers::test_mooneye_rom, GBModel::DMG};
+    use super::{Mbc, Mbc1, ROM_BANK_SIZE};
+
+    #[test]
+    fn mbc1_multicart_test() {
+        let mut rom_bytes = vec![0; 64 * ROM_BANK_SIZE];
+        for bank in 0..64 {
+            rom_bytes[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        // (made up) logo in the menu's header only, then in the game's header at bank 0x10 too
+        for (i, byte) in rom_bytes[0x104..0x134].iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+        assert!(!Mbc1::detect_multicart(&rom_bytes));
+        rom_bytes.copy_within(0x104..0x134, 0x10 * ROM_BANK_SIZE + 0x104);
+        assert!(Mbc1::detect_multicart(&rom_bytes));
+
+        let banked_rom = rom_bytes.chunks_exact(ROM_BANK_SIZE).map(|bank| bank.try_into().unwrap()).collect();
+        let mut mbc = Mbc1::new(banked_rom, 64).with_multicart(true);
+
+        // BANK2 = 1 selects the second game; BANK1's bit 4 only counts towards the bank 0 check
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x13);
+        assert_eq!(mbc.read_rom(0x4000), 0x13);
+        mbc.write_rom(0x2000, 0x10);
+        assert_eq!(mbc.read_rom(0x4000), 0x10);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x11);
+
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x10);
+    }
+
+    #[test]
+    fn mbc1_ram_dirty_test() {
+        let mut mbc = Mbc1::new(vec![[0; ROM_BANK_SIZE]; 2], 2).with_ram(1);
+
+        // writes while RAM is disabled don't count
+        mbc.write_ram(0xA000, 0x42);
+        assert!(!mbc.take_ram_dirty());
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x42);
+        assert!(mbc.take_ram_dirty());
+        assert!(!mbc.take_ram_dirty());
+    }
     
     #[test]
     fn mbc1_bits_test() {
@@ -1,7 +1,9 @@
+use alloc::{format, string::String, vec, vec::Vec};
 use crate::bus::RAM_START;
 use crate::cartridge::battery::Battery;
 
 use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
 
 const MBC2_RAM_SIZE: usize = 512;
 
@@ -10,6 +12,8 @@ pub struct Mbc2 {
     rom_banks: usize,
     ram: [u8; MBC2_RAM_SIZE],
     battery: Option<Battery>,
+    // battery-backed state was written since the last take_ram_dirty()
+    ram_dirty: bool,
     current_rom_bank: usize,
     ram_enabled: bool,
 }
@@ -21,6 +25,7 @@ impl Mbc2 {
             rom_banks,
             ram: [0; MBC2_RAM_SIZE],
             battery: None,
+            ram_dirty: false,
             current_rom_bank: 1,
             ram_enabled: false,
         }
@@ -28,7 +33,7 @@ impl Mbc2 {
 
     /// Specifies battery and loads last RAM save (if any exists), otherwise creates a new one.
     pub fn with_battery(mut self, battery: Battery) -> Self {
-        match battery.load_ram() {
+        match battery.load_ram(&[1]) {
             Some(ram) => {
                 for i in 0..MBC2_RAM_SIZE {
                     self.ram[i] = ram[0][i] & 0xF;
@@ -79,6 +84,11 @@ impl Mbc for Mbc2 {
             return;
         }
         self.ram[(addr - RAM_START) & 0b111111111] = byte & 0xF;
+        self.ram_dirty = true;
+    }
+
+    fn take_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.ram_dirty)
     }
 
     fn display(&self) -> String {
@@ -89,20 +99,21 @@ impl Mbc for Mbc2 {
         ret
     }
 
-    fn save_state(&self) {
+    fn save_state(&self) -> Result<bool, String> {
         let battery = match &self.battery {
             Some(battery) => battery,
-            None => return
+            None => return Ok(false)
         };
 
         let mut ram = vec![[0; RAM_BANK_SIZE]; 1];
         for i in 0..MBC2_RAM_SIZE {
             ram[0][i] = self.ram[i] & 0xF
         }
-        battery.save_ram(&ram);
+        battery.save_ram(&ram)?;
+        Ok(true)
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
         assert!(save_type == "ram");
 
@@ -112,7 +123,7 @@ impl Mbc for Mbc2 {
         }
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn save_id(&self) -> Option<String> {
         match &self.battery {
             Some(battery) => Some(battery.save_id()),
@@ -121,6 +132,22 @@ impl Mbc for Mbc2 {
     }
 }
 
+impl Snapshot for Mbc2 {
+    fn save(&self, state: &mut StateWriter) {
+        self.ram.save(state);
+        self.current_rom_bank.save(state);
+        self.ram_enabled.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ram.load(state)?;
+        self.current_rom_bank.load(state)?;
+        self.ram_enabled.load(state)?;
+        self.ram_dirty = true;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::cpu::test_helpers::test_mooneye_rom;
@@ -1,17 +1,24 @@
-use std::cmp::max;
+use alloc::{format, rc::Rc, string::String, vec::Vec};
+use core::cmp::max;
 use crate::bus::RAM_START;
 use crate::cartridge::battery::Battery;
+use crate::cartridge::clock::{ClockSource, WallClock};
 use crate::cartridge::rtc::Rtc;
+use crate::cartridge::sram::Sram;
 
-use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use super::{Mbc, ROM_BANK_SIZE};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
 
 pub struct Mbc3 {
     rom: Vec<[u8; ROM_BANK_SIZE]>,
     rom_banks: usize,
-    ram: Option<Vec<[u8; RAM_BANK_SIZE]>>,
+    ram: Option<Sram>,
     ram_banks: usize,
     rtc: Option<Rtc>,
+    clock: Rc<dyn ClockSource>,
     battery: Option<Battery>,
+    // battery-backed state was written since the last take_ram_dirty()
+    ram_dirty: bool,
     current_rom_bank: usize,
     current_ram_bank: usize,
     ram_rtc_enabled: bool,
@@ -27,7 +34,9 @@ impl Mbc3 {
             ram: None,
             ram_banks: 0,
             battery: None,
+            ram_dirty: false,
             rtc: None,
+            clock: Rc::new(WallClock),
             current_rom_bank: 1,
             current_ram_bank: 0,
             ram_rtc_enabled: false,
@@ -36,40 +45,42 @@ impl Mbc3 {
         }
     }
 
-    /// Specifies RTC registers (sets timer to not None).
-    pub fn with_rtctimer(mut self) -> Self {
-        self.rtc = Some(Rtc::new());
+    /// Specifies RTC registers (sets timer to not None) counting with clock.
+    pub fn with_rtctimer(mut self, clock: Rc<dyn ClockSource>) -> Self {
+        self.rtc = Some(Rtc::new(clock.now()));
+        self.clock = clock;
         self
     }
 
     /// Specifies RAM (sets ram to not None).
     pub fn with_ram(mut self, ram_banks: usize) -> Self {
-        self.ram = Some(vec![[0; RAM_BANK_SIZE]; ram_banks]);
+        self.ram = Some(Sram::new(ram_banks));
         self.ram_banks = ram_banks;
         self
     }
 
     /// Specifies Battery and loads in existing RAM (if ram is not None), 
-    /// and RTC registers (if timer is not None).
+    /// and RTC registers (if timer is not None) from the RAM save's RTC footer.
     pub fn with_battery(mut self, battery: Battery) -> Self {   
-        if self.ram.is_some() {
-            match battery.load_ram() {
-                Some(ram) => {
-                    assert!(ram.len() == self.ram_banks, "Invalid RAM Save Size!");
-                    self.ram = Some(ram)
-                },
-                None => {}
-            };
-        }
-        if self.rtc.is_some() {
-            match battery.load_rtc() {
-                Some(rtc) => self.rtc = Some(rtc),
-                None => {}
-            }
-        }
+        let (ram, rtc) = match self.rtc {
+            Some(_) => battery.load_ram_and_rtc(self.ram_banks),
+            None if self.ram.is_some() => (battery.load_sram(self.ram_banks), None),
+            None => (None, None),
+        };
+        self.load_ram_and_rtc(ram, rtc);
         self.battery = Some(battery);
         self
     }
+
+    fn load_ram_and_rtc(&mut self, ram: Option<Sram>, rtc: Option<Rtc>) {
+        // (a save of the wrong size keeps the fresh RAM)
+        if let (Some(_), Some(ram)) = (&self.ram, ram.filter(|ram| ram.len() == self.ram_banks)) {
+            self.ram = Some(ram)
+        }
+        if let (Some(_), Some(rtc)) = (&self.rtc, rtc) {
+            self.rtc = Some(rtc)
+        }
+    }
 }
 
 impl Mbc for Mbc3 {
@@ -107,10 +118,9 @@ impl Mbc for Mbc3 {
             },
             0x6000..=0x7FFF => {
                 if self.prev_latch_write == 0 && byte == 1 {
-                    match &mut self.rtc {
-                        Some(rtc) => rtc.latch_clock_data(),
-                        None => {}
-                    };
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.latch_clock_data(self.clock.now());
+                    }
                 }
                 self.prev_latch_write = byte;
             },
@@ -142,18 +152,31 @@ impl Mbc for Mbc3 {
         }
 
         if self.using_ram {
-            match &mut self.ram {
-                Some(ram) => ram[self.current_ram_bank][addr - RAM_START] = byte,
-                None => {}
-            };
-        } else {
-            match &mut self.rtc {
-                Some(rtc) => rtc.write(byte),
-                None => {}
+            if let Some(ram) = &mut self.ram {
+                ram[self.current_ram_bank][addr - RAM_START] = byte;
+                self.ram_dirty = true;
             }
+        } else if let Some(rtc) = &mut self.rtc {
+            rtc.write(byte, self.clock.now());
+            self.ram_dirty = true;
+        }
+    }
+
+    fn has_clock(&self) -> bool {
+        self.rtc.is_some()
+    }
+
+    fn adjust_clock(&mut self, seconds: i64) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.adjust(seconds, self.clock.now());
+            self.ram_dirty = true;
         }
     }
 
+    fn take_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.ram_dirty)
+    }
+
     fn display(&self) -> String {
         let mut ret = format!("Mbc3 w/ {} ROM banks", self.rom_banks);
         if self.rtc.is_some() {
@@ -168,37 +191,60 @@ impl Mbc for Mbc3 {
         ret
     }
 
-    fn save_state(&self) {
+    fn save_state(&self) -> Result<bool, String> {
         let battery = match &self.battery {
             Some(battery) => battery,
-            None => return
-        };
-
-        match &self.ram {
-            Some(ram) => battery.save_ram(ram),
-            None => {}
+            None => return Ok(false)
         };
 
-        match &self.rtc {
-            Some(rtc) => battery.save_rtc(rtc),
-            None => {}
+        match (&self.ram, &self.rtc) {
+            (ram, Some(rtc)) => battery.save_ram_and_rtc(ram.as_ref(), &rtc.at(self.clock.now()))?,
+            (Some(ram), None) => battery.save_sram(ram)?,
+            (None, None) => {}
         }
+
+        Ok(true)
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
         if save_type == "ram" {
-            self.ram = Some(Battery::parse_ram(data));
+            let (ram, rtc) = Battery::parse_ram_and_rtc(data);
+            self.load_ram_and_rtc(Some(Sram::from_banks(ram)), rtc);
         } else {
-            self.rtc = Some(Battery::parse_rtc(data));
+            self.load_ram_and_rtc(None, Some(Battery::parse_rtc(data)));
         }
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn save_id(&self) -> Option<String> {
         match &self.battery {
             Some(battery) => Some(battery.save_id()),
             None => None,
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Mbc3 {
+    fn save(&self, state: &mut StateWriter) {
+        self.ram.save(state);
+        self.rtc.save(state);
+        self.current_rom_bank.save(state);
+        self.current_ram_bank.save(state);
+        self.ram_rtc_enabled.save(state);
+        self.prev_latch_write.save(state);
+        self.using_ram.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ram.load(state)?;
+        self.rtc.load(state)?;
+        self.current_rom_bank.load(state)?;
+        self.current_ram_bank.load(state)?;
+        self.ram_rtc_enabled.load(state)?;
+        self.prev_latch_write.load(state)?;
+        self.using_ram.load(state)?;
+        self.ram_dirty = true;
+        Ok(())
+    }
+}
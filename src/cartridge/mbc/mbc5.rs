@@ -1,18 +1,24 @@
+use alloc::{format, string::String, vec::Vec};
 use crate::bus::RAM_START;
 use crate::cartridge::battery::Battery;
+use crate::cartridge::sram::Sram;
 
-use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use super::{Mbc, ROM_BANK_SIZE};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
 
 pub struct Mbc5 {
     rom: Vec<[u8; ROM_BANK_SIZE]>,
     rom_banks: usize,
-    ram: Option<Vec<[u8; RAM_BANK_SIZE]>>,
+    ram: Option<Sram>,
     ram_banks: usize,
     battery: Option<Battery>,
+    // battery-backed state was written since the last take_ram_dirty()
+    ram_dirty: bool,
     current_rom_bank: usize,
     current_ram_bank: usize,
     ram_enabled: bool,
     rumble: bool,
+    rumble_on: bool,
 }
 
 impl Mbc5 {
@@ -23,22 +29,24 @@ impl Mbc5 {
             ram: None,
             ram_banks: 0,
             battery: None,
+            ram_dirty: false,
             current_rom_bank: 1,
             current_ram_bank: 0,
             ram_enabled: false,
             rumble: false,
+            rumble_on: false,
         }
     }
 
+    /// Specifies rumble motor (bit 3 of the RAM bank register drives the motor instead).
     pub fn with_rumble(mut self) -> Self {
-        // Can't think of a way to implement this???
         self.rumble = true;
         self
     }
 
     /// Specifies RAM (sets ram to not None).
     pub fn with_ram(mut self, ram_banks: usize) -> Self {
-        self.ram = Some(vec![[0; RAM_BANK_SIZE]; ram_banks]);
+        self.ram = Some(Sram::new(ram_banks));
         self.ram_banks = ram_banks;
         self
     }
@@ -46,13 +54,7 @@ impl Mbc5 {
     /// ASSUMES: RAM has already be set to not None
     /// Specifies battery and loads last RAM save (if any exists), otherwise creates a new one.
     pub fn with_battery(mut self, battery: Battery) -> Self {
-        self.ram = Some(match battery.load_ram() {
-            Some(ram) => {
-                assert!(ram.len() == self.ram_banks, "Invalid RAM Save Size!");
-                ram
-            },
-            None => vec![[0; RAM_BANK_SIZE]; self.ram_banks],
-        });
+        self.ram = Some(battery.load_sram(self.ram_banks).unwrap_or_else(|| Sram::new(self.ram_banks)));
         self.battery = Some(battery);
         self
     }
@@ -72,8 +74,16 @@ impl Mbc for Mbc5 {
             0x0000..=0x1FFF => self.ram_enabled = (byte & 0xF) == 0xA,
             0x2000..=0x2FFF => self.current_rom_bank = byte as usize,
             0x3000..=0x3FFF => self.current_rom_bank |= (byte as usize & 1) << 8,
-            0x4000..=0x5FFF => if (byte as usize) < self.ram_banks {
-                self.current_ram_bank = byte as usize;
+            0x4000..=0x5FFF => {
+                let mut ram_bank = byte as usize;
+                if self.rumble {
+                    self.rumble_on = byte & 0x08 != 0;
+                    ram_bank &= 0x07;
+                }
+
+                if ram_bank < self.ram_banks {
+                    self.current_ram_bank = ram_bank;
+                }
             },
             _ => {}
         }
@@ -93,16 +103,20 @@ impl Mbc for Mbc5 {
         if !self.ram_enabled {
             return;
         }
-        match &mut self.ram {
-            Some(ram) => ram[self.current_ram_bank][addr - RAM_START] = byte,
-            None => {}
-        };
+        if let Some(ram) = &mut self.ram {
+            ram[self.current_ram_bank][addr - RAM_START] = byte;
+            self.ram_dirty = true;
+        }
+    }
+
+    fn take_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.ram_dirty)
     }
 
     fn display(&self) -> String {
         let mut ret = format!("Mbc5 w/ {} ROM banks", self.rom_banks);
         if self.rumble {
-            ret.push_str(&format!(" + Rumble (not supported)"));
+            ret.push_str(" + Rumble");
         }
         if self.ram.is_some() {
             ret.push_str(&format!(" + {} RAM banks", self.ram_banks));
@@ -113,27 +127,32 @@ impl Mbc for Mbc5 {
         ret
     }
 
-    fn save_state(&self) {
+    fn save_state(&self) -> Result<bool, String> {
         let battery = match &self.battery {
             Some(battery) => battery,
-            None => return
+            None => return Ok(false)
         };
 
-        let ram: &Vec<[u8; 8192]> = match &self.ram {
+        let ram: &Sram = match &self.ram {
             Some(ram) => ram,
-            None => return
+            None => return Ok(false)
         };
 
-        battery.save_ram(ram);
+        battery.save_sram(ram)?;
+        Ok(true)
+    }
+
+    fn rumble_on(&self) -> bool {
+        self.rumble_on
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
         assert!(save_type == "ram");
-        self.ram = Some(Battery::parse_ram(data));
+        self.ram = Some(Sram::from_banks(Battery::parse_ram(data)));
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn save_id(&self) -> Option<String> {
         match &self.battery {
             Some(battery) => Some(battery.save_id()),
@@ -142,6 +161,26 @@ impl Mbc for Mbc5 {
     }
 }
 
+impl Snapshot for Mbc5 {
+    fn save(&self, state: &mut StateWriter) {
+        self.ram.save(state);
+        self.current_rom_bank.save(state);
+        self.current_ram_bank.save(state);
+        self.ram_enabled.save(state);
+        self.rumble_on.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ram.load(state)?;
+        self.current_rom_bank.load(state)?;
+        self.current_ram_bank.load(state)?;
+        self.ram_enabled.load(state)?;
+        self.rumble_on.load(state)?;
+        self.ram_dirty = true;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::cpu::{test_helpers::test_mooneye_rom, GBModel::DMG};
@@ -0,0 +1,486 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use crate::bus::RAM_START;
+use crate::cartridge::battery::Battery;
+use crate::cartridge::sram::Sram;
+
+use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::savestate::{snapshot_fields, Snapshot, StateError, StateReader, StateWriter};
+
+// MBC6 switches ROM/Flash in 8 KiB halves and RAM in 4 KiB halves
+const HALF_ROM_BANK_SIZE: usize = ROM_BANK_SIZE / 2;
+const HALF_RAM_BANK_SIZE: usize = RAM_BANK_SIZE / 2;
+
+const FLASH_BANKS: usize = 64;
+const FLASH_SELECT: u8 = 0x08;
+/// The flash is saved after the RAM, as this many more RAM banks.
+pub const FLASH_SAVE_BANKS: usize = FLASH_BANKS * ROM_BANK_SIZE / RAM_BANK_SIZE;
+
+// the flash chip (a Macronix MX29F008) takes commands written to these addresses, each
+// after unlock writes of UNLOCK_1 to COMMAND_ADDR_1 and UNLOCK_2 to COMMAND_ADDR_2
+const COMMAND_ADDR_1: usize = 0x5555;
+const COMMAND_ADDR_2: usize = 0x2AAA;
+const UNLOCK_1: u8 = 0xAA;
+const UNLOCK_2: u8 = 0x55;
+const COMMAND_ID: u8 = 0x90;
+const COMMAND_PROGRAM: u8 = 0xA0;
+const COMMAND_ERASE: u8 = 0x80;
+const COMMAND_RESET: u8 = 0xF0;
+const ERASE_SECTOR: u8 = 0x30;
+const ERASE_CHIP: u8 = 0x10;
+// what the chip reads back at addresses 0 and 1 in ID mode
+const FLASH_ID: [u8; 2] = [0xC2, 0x81];
+// erases clear whole 128 KiB sectors
+const SECTOR_BANKS: usize = 8;
+
+/// One of the two switchable windows (0x4000-0x5FFF and 0x6000-0x7FFF for ROM/Flash,
+/// 0xA000-0xAFFF and 0xB000-0xBFFF for RAM).
+#[derive(Clone, Copy)]
+struct Window {
+    rom_bank: usize,
+    ram_bank: usize,
+    flash_mapped: bool,
+}
+
+/// Where the flash chip is in a command sequence.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FlashState {
+    Read,
+    /// Got the first unlock write.
+    Unlocked,
+    /// Got both unlock writes, the command comes next.
+    Command,
+    /// Reads return FLASH_ID until a reset.
+    Id,
+    /// The next write programs a byte.
+    Program,
+    /// Got an erase command, which needs unlocking again before saying what to erase.
+    EraseSetup,
+    EraseUnlocked,
+    Erase,
+}
+
+/// Mapper used by Net de Get: Minigame @ 100, with 1 MiB of flash that the game writes
+/// through the flash chip's commands and that's kept in its battery save after its RAM.
+pub struct Mbc6 {
+    rom: Vec<[u8; ROM_BANK_SIZE]>,
+    rom_banks: usize,
+    ram: Option<Sram>,
+    ram_banks: usize,
+    flash: Vec<[u8; ROM_BANK_SIZE]>,
+    flash_state: FlashState,
+    battery: Option<Battery>,
+    windows: [Window; 2],
+    ram_enabled: bool,
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    // battery-backed state was written since the last take_ram_dirty()
+    ram_dirty: bool,
+}
+
+impl Mbc6 {
+    pub fn new(rom: Vec<[u8; ROM_BANK_SIZE]>, rom_banks: usize) -> Self {
+        Mbc6 {
+            rom,
+            rom_banks,
+            ram: None,
+            ram_banks: 0,
+            flash: vec![[0xFF; ROM_BANK_SIZE]; FLASH_BANKS],
+            flash_state: FlashState::Read,
+            battery: None,
+            windows: [Window { rom_bank: 0, ram_bank: 0, flash_mapped: false }; 2],
+            ram_enabled: false,
+            flash_enabled: false,
+            flash_write_enabled: false,
+            ram_dirty: false,
+        }
+    }
+
+    /// Specifies RAM (sets ram to not None).
+    pub fn with_ram(mut self, ram_banks: usize) -> Self {
+        self.ram = Some(Sram::new(ram_banks));
+        self.ram_banks = ram_banks;
+        self
+    }
+
+    /// ASSUMES: RAM has already be set to not None
+    /// Specifies battery and loads the RAM and flash of the last save (if any exists); saves
+    /// from before flash was kept only hold the RAM.
+    pub fn with_battery(mut self, battery: Battery) -> Self {
+        if let Some(banks) = battery.load_ram(&[self.ram_banks, self.ram_banks + FLASH_SAVE_BANKS]) {
+            self.load_banks(banks);
+        }
+        self.battery = Some(battery);
+        self
+    }
+
+    /// Loads RAM banks, then the flash (if it follows them), from a battery save; a save of any
+    /// other size is ignored.
+    fn load_banks(&mut self, mut banks: Vec<[u8; RAM_BANK_SIZE]>) {
+        if banks.len() != self.ram_banks && banks.len() != self.ram_banks + FLASH_SAVE_BANKS {
+            return;
+        }
+
+        let flash = banks.split_off(self.ram_banks);
+        for (i, half) in flash.iter().enumerate() {
+            let offset = (i % 2) * RAM_BANK_SIZE;
+            self.flash[i / 2][offset..offset + RAM_BANK_SIZE].copy_from_slice(half);
+        }
+        self.ram = Some(Sram::from_banks(banks));
+    }
+
+    /// Returns RAM banks followed by the flash, as written to the battery save.
+    fn save_banks(&self) -> Vec<[u8; RAM_BANK_SIZE]> {
+        let mut banks: Vec<[u8; RAM_BANK_SIZE]> = self.ram.as_ref().map_or(Vec::new(), |ram| ram.to_vec());
+        for bank in &self.flash {
+            for half in bank.chunks_exact(RAM_BANK_SIZE) {
+                let mut ram_bank = [0; RAM_BANK_SIZE];
+                ram_bank.copy_from_slice(half);
+                banks.push(ram_bank);
+            }
+        }
+        banks
+    }
+
+    /// Returns (8 KiB bank, offset into that bank) of the RAM byte at addr.
+    fn ram_location(&self, addr: usize) -> (usize, usize) {
+        let offset = addr - RAM_START;
+        let window = self.windows[offset / HALF_RAM_BANK_SIZE];
+        let half_bank = window.ram_bank & (self.ram_banks * 2 - 1);
+
+        (half_bank / 2, (half_bank % 2) * HALF_RAM_BANK_SIZE + offset % HALF_RAM_BANK_SIZE)
+    }
+
+    /// Returns (16 KiB bank, offset into that bank) of the ROM/Flash byte at addr.
+    fn rom_location(window: &Window, banks: usize, addr: usize) -> (usize, usize) {
+        let half_bank = window.rom_bank & (banks * 2 - 1);
+
+        (half_bank / 2, (half_bank % 2) * HALF_ROM_BANK_SIZE + addr % HALF_ROM_BANK_SIZE)
+    }
+
+    /// Takes a write of byte to the flash at (bank, offset) as part of a command.
+    fn write_flash(&mut self, bank: usize, offset: usize, byte: u8) {
+        // the chip sees its own addresses, of which the command ones are in its first 32 KiB
+        let chip_addr = bank * ROM_BANK_SIZE + offset;
+        let command_addr = chip_addr & 0x7FFF;
+
+        self.flash_state = match (self.flash_state, byte) {
+            (FlashState::Program, _) => {
+                if self.flash_write_enabled {
+                    // programming can only clear bits, erasing sets them again
+                    self.flash[bank][offset] &= byte;
+                    self.ram_dirty = true;
+                }
+                FlashState::Read
+            },
+            (FlashState::Erase, ERASE_SECTOR) => {
+                let first = bank - bank % SECTOR_BANKS;
+                self.erase_flash(first..first + SECTOR_BANKS);
+                FlashState::Read
+            },
+            (FlashState::Erase, ERASE_CHIP) if command_addr == COMMAND_ADDR_1 => {
+                self.erase_flash(0..FLASH_BANKS);
+                FlashState::Read
+            },
+            (_, COMMAND_RESET) => FlashState::Read,
+            (FlashState::Read | FlashState::Id, UNLOCK_1) if command_addr == COMMAND_ADDR_1 => FlashState::Unlocked,
+            (FlashState::Unlocked, UNLOCK_2) if command_addr == COMMAND_ADDR_2 => FlashState::Command,
+            (FlashState::Command, COMMAND_ID) if command_addr == COMMAND_ADDR_1 => FlashState::Id,
+            (FlashState::Command, COMMAND_PROGRAM) if command_addr == COMMAND_ADDR_1 => FlashState::Program,
+            (FlashState::Command, COMMAND_ERASE) if command_addr == COMMAND_ADDR_1 => FlashState::EraseSetup,
+            (FlashState::EraseSetup, UNLOCK_1) if command_addr == COMMAND_ADDR_1 => FlashState::EraseUnlocked,
+            (FlashState::EraseUnlocked, UNLOCK_2) if command_addr == COMMAND_ADDR_2 => FlashState::Erase,
+            // anything else ends the command
+            _ => FlashState::Read,
+        };
+    }
+
+    fn erase_flash(&mut self, banks: core::ops::Range<usize>) {
+        if !self.flash_write_enabled {
+            return;
+        }
+        for bank in &mut self.flash[banks] {
+            bank.fill(0xFF);
+        }
+        self.ram_dirty = true;
+    }
+}
+
+impl Mbc for Mbc6 {
+    fn read_rom(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[0][addr],
+            0x4000..=0x7FFF => {
+                let window = &self.windows[(addr - 0x4000) / HALF_ROM_BANK_SIZE];
+                if window.flash_mapped {
+                    if !self.flash_enabled {
+                        return 0xFF;
+                    }
+                    let (bank, offset) = Mbc6::rom_location(window, FLASH_BANKS, addr);
+                    match self.flash_state {
+                        FlashState::Id => FLASH_ID.get(bank * ROM_BANK_SIZE + offset).copied().unwrap_or(0xFF),
+                        _ => self.flash[bank][offset],
+                    }
+                } else {
+                    let (bank, offset) = Mbc6::rom_location(window, self.rom_banks, addr);
+                    self.rom[bank][offset]
+                }
+            },
+            _ => unreachable!()
+        }
+    }
+
+    fn write_rom(&mut self, addr: usize, byte: u8) {
+        match addr {
+            0x0000..=0x03FF => self.ram_enabled = (byte & 0xF) == 0xA,
+            0x0400..=0x07FF => self.windows[0].ram_bank = byte as usize,
+            0x0800..=0x0BFF => self.windows[1].ram_bank = byte as usize,
+            0x0C00..=0x0FFF => self.flash_enabled = byte & 1 != 0,
+            0x1000 => self.flash_write_enabled = byte & 1 != 0,
+            0x2000..=0x27FF => self.windows[0].rom_bank = byte as usize,
+            0x2800..=0x2FFF => self.windows[0].flash_mapped = byte == FLASH_SELECT,
+            0x3000..=0x37FF => self.windows[1].rom_bank = byte as usize,
+            0x3800..=0x3FFF => self.windows[1].flash_mapped = byte == FLASH_SELECT,
+            0x4000..=0x7FFF => {
+                let window = self.windows[(addr - 0x4000) / HALF_ROM_BANK_SIZE];
+                if window.flash_mapped && self.flash_enabled {
+                    let (bank, offset) = Mbc6::rom_location(&window, FLASH_BANKS, addr);
+                    self.write_flash(bank, offset, byte);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: usize) -> u8 {
+        if !self.ram_enabled || self.ram_banks == 0 {
+            return 0xFF;
+        }
+        let (bank, offset) = self.ram_location(addr);
+        match &self.ram {
+            Some(ram) => ram[bank][offset],
+            None => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, byte: u8) {
+        if !self.ram_enabled || self.ram_banks == 0 {
+            return;
+        }
+        let (bank, offset) = self.ram_location(addr);
+        if let Some(ram) = &mut self.ram {
+            ram[bank][offset] = byte;
+            self.ram_dirty = true;
+        }
+    }
+
+    fn take_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.ram_dirty)
+    }
+
+    fn display(&self) -> String {
+        let mut ret = format!("Mbc6 w/ {} ROM banks + Flash", self.rom_banks);
+        if self.ram.is_some() {
+            ret.push_str(&format!(" + {} RAM banks", self.ram_banks));
+        }
+        if self.battery.is_some() {
+            ret.push_str(" + Battery");
+        }
+        ret
+    }
+
+    fn save_state(&self) -> Result<bool, String> {
+        let battery = match &self.battery {
+            Some(battery) => battery,
+            None => return Ok(false)
+        };
+
+        battery.save_ram(&self.save_banks())?;
+        Ok(true)
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
+        assert!(save_type == "ram");
+        self.load_banks(Battery::parse_ram(data));
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn save_id(&self) -> Option<String> {
+        match &self.battery {
+            Some(battery) => Some(battery.save_id()),
+            None => None,
+        }
+    }
+}
+
+impl Snapshot for Mbc6 {
+    fn save(&self, state: &mut StateWriter) {
+        self.ram.save(state);
+        self.flash[..].save(state);
+        self.flash_state.save(state);
+        self.windows.save(state);
+        self.ram_enabled.save(state);
+        self.flash_enabled.save(state);
+        self.flash_write_enabled.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ram.load(state)?;
+        self.flash[..].load(state)?;
+        self.flash_state.load(state)?;
+        self.windows.load(state)?;
+        self.ram_enabled.load(state)?;
+        self.flash_enabled.load(state)?;
+        self.flash_write_enabled.load(state)?;
+        self.ram_dirty = true;
+        Ok(())
+    }
+}
+
+snapshot_fields!(Window, rom_bank, ram_bank, flash_mapped);
+
+impl Snapshot for FlashState {
+    fn save(&self, state: &mut StateWriter) {
+        let flash_state: u8 = match self {
+            FlashState::Read => 0,
+            FlashState::Unlocked => 1,
+            FlashState::Command => 2,
+            FlashState::Id => 3,
+            FlashState::Program => 4,
+            FlashState::EraseSetup => 5,
+            FlashState::EraseUnlocked => 6,
+            FlashState::Erase => 7,
+        };
+        flash_state.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut flash_state = 0u8;
+        flash_state.load(state)?;
+        *self = match flash_state {
+            0 => FlashState::Read,
+            1 => FlashState::Unlocked,
+            2 => FlashState::Command,
+            3 => FlashState::Id,
+            4 => FlashState::Program,
+            5 => FlashState::EraseSetup,
+            6 => FlashState::EraseUnlocked,
+            7 => FlashState::Erase,
+            _ => return Err(StateError::Invalid("flash state")),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mbc, Mbc6, FLASH_SAVE_BANKS, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    #[test]
+    fn mbc6_banking_test() {
+        let mut rom = vec![[0; ROM_BANK_SIZE]; 4];
+        for (i, bank) in rom.iter_mut().enumerate() {
+            bank[0x0000] = (i * 2) as u8;
+            bank[0x2000] = (i * 2 + 1) as u8;
+        }
+        let mut mbc = Mbc6::new(rom, 4).with_ram(1);
+
+        mbc.write_rom(0x2000, 5);
+        mbc.write_rom(0x3000, 2);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+        assert_eq!(mbc.read_rom(0x6000), 2);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x0400, 1);
+        mbc.write_rom(0x0800, 0);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xB000), 0x00);
+        mbc.write_rom(0x0800, 1);
+        assert_eq!(mbc.read_ram(0xB000), 0x12);
+
+        mbc.write_rom(0x2800, 0x08);
+        assert_eq!(mbc.read_rom(0x4000), 0xFF);
+        mbc.write_rom(0x0C00, 1);
+        assert_eq!(mbc.read_rom(0x4000), 0xFF);
+        assert_eq!(mbc.read_rom(0x6000), 2);
+    }
+
+    /// Maps the flash into both windows with the flash half banks holding chip addresses
+    /// 0x4000 (at 0x4000-0x5FFF) and 0x2000 (at 0x6000-0x7FFF), as games do to send commands.
+    fn command_mbc() -> Mbc6 {
+        let mut mbc = Mbc6::new(vec![[0; ROM_BANK_SIZE]; 4], 4).with_ram(1);
+        mbc.write_rom(0x2800, 0x08);
+        mbc.write_rom(0x3800, 0x08);
+        mbc.write_rom(0x2000, 2);
+        mbc.write_rom(0x3000, 1);
+        mbc.write_rom(0x0C00, 1);
+        mbc
+    }
+
+    fn unlock(mbc: &mut Mbc6) {
+        mbc.write_rom(0x5555, 0xAA);
+        mbc.write_rom(0x6AAA, 0x55);
+    }
+
+    #[test]
+    fn mbc6_flash_test() {
+        let mut mbc = command_mbc();
+
+        // plain writes don't change the flash
+        mbc.write_rom(0x1000, 1);
+        mbc.write_rom(0x4000, 0x34);
+        assert_eq!(mbc.read_rom(0x4000), 0xFF);
+
+        unlock(&mut mbc);
+        mbc.write_rom(0x5555, 0x90);
+        assert_eq!(mbc.read_rom(0x4000), 0xFF);
+        mbc.write_rom(0x2000, 0);
+        assert_eq!((mbc.read_rom(0x4000), mbc.read_rom(0x4001)), (0xC2, 0x81));
+        mbc.write_rom(0x4000, 0xF0);
+        assert_eq!(mbc.read_rom(0x4000), 0xFF);
+
+        // programming needs the write enable register, and only clears bits
+        mbc.write_rom(0x2000, 2);
+        for (byte, expected) in [(0x34, 0x34), (0xF1, 0x30)] {
+            unlock(&mut mbc);
+            mbc.write_rom(0x5555, 0xA0);
+            mbc.write_rom(0x4123, byte);
+            assert_eq!(mbc.read_rom(0x4123), expected);
+        }
+        mbc.write_rom(0x1000, 0);
+        unlock(&mut mbc);
+        mbc.write_rom(0x5555, 0xA0);
+        mbc.write_rom(0x4124, 0x00);
+        assert_eq!(mbc.read_rom(0x4124), 0xFF);
+        mbc.write_rom(0x1000, 1);
+        assert!(mbc.take_ram_dirty());
+
+        // sector erase sets the 128 KiB holding the address back to 0xFF
+        unlock(&mut mbc);
+        mbc.write_rom(0x5555, 0x80);
+        unlock(&mut mbc);
+        mbc.write_rom(0x4000, 0x30);
+        assert_eq!(mbc.read_rom(0x4123), 0xFF);
+    }
+
+    #[test]
+    fn mbc6_flash_save_test() {
+        let mut mbc = command_mbc();
+        mbc.write_rom(0x1000, 1);
+        unlock(&mut mbc);
+        mbc.write_rom(0x5555, 0xA0);
+        mbc.write_rom(0x6001, 0x12);
+
+        let banks = mbc.save_banks();
+        assert_eq!(banks.len(), 1 + FLASH_SAVE_BANKS);
+        // chip address 0x2001, in the flash's second RAM-bank-sized half
+        assert_eq!(banks[1 + 0x2001 / RAM_BANK_SIZE][0x2001 % RAM_BANK_SIZE], 0x12);
+
+        let mut loaded = command_mbc();
+        loaded.load_banks(banks);
+        assert_eq!(loaded.read_rom(0x6001), 0x12);
+        // saves from before the flash was kept only have the RAM
+        loaded.load_banks(vec![[0; RAM_BANK_SIZE]]);
+        assert_eq!(loaded.read_rom(0x6001), 0x12);
+    }
+}
@@ -0,0 +1,492 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use crate::bus::RAM_START;
+use crate::cartridge::battery::Battery;
+
+use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+const EEPROM_WORDS: usize = 128;
+
+// accelerometer reading when the cartridge is held flat, and how much it moves per g of tilt
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+const ACCEL_PER_G: f32 = 0x70 as f32;
+const ACCEL_ERASED: (u16, u16) = (0x8000, 0x8000);
+
+/// MBC7 (used by Kirby Tilt 'n' Tumble and Command Master): ROM banking, a 2-axis
+/// accelerometer and a 93LC56 serial EEPROM, both mapped to registers at 0xA000-0xAFFF.
+pub struct Mbc7 {
+    rom: Vec<[u8; ROM_BANK_SIZE]>,
+    rom_banks: usize,
+    battery: Option<Battery>,
+    current_rom_bank: usize,
+    ram_enabled_1: bool,
+    ram_enabled_2: bool,
+    // current tilt (in g, positive is right / down) and the last accelerometer reading latched
+    tilt: (f32, f32),
+    accel: (u16, u16),
+    accel_erased: bool,
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new(rom: Vec<[u8; ROM_BANK_SIZE]>, rom_banks: usize) -> Self {
+        Mbc7 {
+            rom,
+            rom_banks,
+            battery: None,
+            current_rom_bank: 1,
+            ram_enabled_1: false,
+            ram_enabled_2: false,
+            tilt: (0.0, 0.0),
+            accel: ACCEL_ERASED,
+            accel_erased: false,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    /// Specifies battery and loads the last EEPROM save (if any exists).
+    pub fn with_battery(mut self, battery: Battery) -> Self {
+        if let Some(ram) = battery.load_ram(&[1]) {
+            self.eeprom.load(&ram[0]);
+        }
+        self.battery = Some(battery);
+        self
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled_1 && self.ram_enabled_2
+    }
+
+    fn latch_accel(&mut self) {
+        let axis = |g: f32| (ACCEL_CENTER - g.clamp(-1.0, 1.0) * ACCEL_PER_G) as u16;
+        self.accel = (axis(self.tilt.0), axis(self.tilt.1));
+        self.accel_erased = false;
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn read_rom(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[0][addr],
+            0x4000..=0x7FFF => self.rom[self.current_rom_bank][addr - 0x4000],
+            _ => unreachable!()
+        }
+    }
+
+    fn write_rom(&mut self, addr: usize, byte: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled_1 = byte & 0xF == 0xA,
+            0x2000..=0x3FFF => self.current_rom_bank = byte as usize & (self.rom_banks - 1),
+            0x4000..=0x5FFF => self.ram_enabled_2 = byte == 0x40,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: usize) -> u8 {
+        if !self.ram_enabled() || addr - RAM_START >= 0x1000 {
+            return 0xFF;
+        }
+        match (addr >> 4) & 0xF {
+            0x2 => self.accel.0 as u8,
+            0x3 => (self.accel.0 >> 8) as u8,
+            0x4 => self.accel.1 as u8,
+            0x5 => (self.accel.1 >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, byte: u8) {
+        if !self.ram_enabled() || addr - RAM_START >= 0x1000 {
+            return;
+        }
+        match (addr >> 4) & 0xF {
+            0x0 if byte == 0x55 => {
+                self.accel = ACCEL_ERASED;
+                self.accel_erased = true;
+            },
+            0x1 if byte == 0xAA && self.accel_erased => self.latch_accel(),
+            0x8 => self.eeprom.write(byte),
+            _ => {}
+        }
+    }
+
+    fn take_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.eeprom.dirty)
+    }
+
+    fn display(&self) -> String {
+        let mut ret = format!("Mbc7 w/ {} ROM banks + Accelerometer + EEPROM", self.rom_banks);
+        if self.battery.is_some() {
+            ret.push_str(" + Battery");
+        }
+        ret
+    }
+
+    fn save_state(&self) -> Result<bool, String> {
+        let battery = match &self.battery {
+            Some(battery) => battery,
+            None => return Ok(false)
+        };
+
+        let mut ram = vec![[0; RAM_BANK_SIZE]; 1];
+        self.eeprom.store(&mut ram[0]);
+        battery.save_ram(&ram)?;
+        Ok(true)
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
+        assert!(save_type == "ram");
+
+        let ram = Battery::parse_ram(data);
+        self.eeprom.load(&ram[0]);
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn save_id(&self) -> Option<String> {
+        match &self.battery {
+            Some(battery) => Some(battery.save_id()),
+            None => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum EepromState {
+    // waiting for a start bit
+    Idle,
+    // shifting in the opcode and address after the start bit
+    Command { bits: u16, count: u8 },
+    // shifting out words starting at addr, one bit per clock
+    Read { addr: usize, bit: u8 },
+    // shifting in a word to write to addr (or to every word if None)
+    Write { addr: Option<usize>, data: u16, count: u8 },
+}
+
+/// 93LC56 EEPROM (128 16-bit words) driven one bit at a time through bit 7 (CS),
+/// bit 6 (CLK), bit 1 (DI) and bit 0 (DO) of its register; bits are latched on rising CLK.
+struct Eeprom {
+    words: [u16; EEPROM_WORDS],
+    state: EepromState,
+    write_enabled: bool,
+    // words were written since the last take_ram_dirty()
+    dirty: bool,
+    cs: bool,
+    clk: bool,
+    di: bool,
+    do_: bool,
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Eeprom {
+            words: [0xFFFF; EEPROM_WORDS],
+            state: EepromState::Idle,
+            write_enabled: false,
+            dirty: false,
+            cs: false,
+            clk: false,
+            di: false,
+            do_: true,
+        }
+    }
+
+    /// Loads words (stored big-endian) from the start of bank.
+    fn load(&mut self, bank: &[u8; RAM_BANK_SIZE]) {
+        for (i, word) in self.words.iter_mut().enumerate() {
+            *word = u16::from_be_bytes([bank[i * 2], bank[i * 2 + 1]]);
+        }
+    }
+
+    /// Stores words (big-endian) at the start of bank.
+    fn store(&self, bank: &mut [u8; RAM_BANK_SIZE]) {
+        for (i, word) in self.words.iter().enumerate() {
+            bank[i * 2..i * 2 + 2].copy_from_slice(&word.to_be_bytes());
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.do_ as u8
+    }
+
+    fn write(&mut self, byte: u8) {
+        let (cs, clk, di) = (byte & 0x80 != 0, byte & 0x40 != 0, byte & 0x02 != 0);
+        let rising = clk && !self.clk;
+        (self.cs, self.clk, self.di) = (cs, clk, di);
+
+        if !cs {
+            self.state = EepromState::Idle;
+        } else if rising {
+            self.clock(di);
+        }
+    }
+
+    fn clock(&mut self, di: bool) {
+        self.state = match self.state {
+            EepromState::Idle if di => EepromState::Command { bits: 0, count: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, count } => {
+                let bits = bits << 1 | di as u16;
+                if count + 1 < 10 {
+                    EepromState::Command { bits, count: count + 1 }
+                } else {
+                    self.command(bits)
+                }
+            },
+            EepromState::Read { addr, bit } => {
+                self.do_ = self.words[addr] >> (15 - bit) & 1 != 0;
+                match bit {
+                    15 => EepromState::Read { addr: (addr + 1) % EEPROM_WORDS, bit: 0 },
+                    _ => EepromState::Read { addr, bit: bit + 1 },
+                }
+            },
+            EepromState::Write { addr, data, count } => {
+                let data = data << 1 | di as u16;
+                if count + 1 < 16 {
+                    EepromState::Write { addr, data, count: count + 1 }
+                } else {
+                    match addr {
+                        Some(addr) => self.program(addr, data),
+                        None => (0..EEPROM_WORDS).for_each(|addr| self.program(addr, data)),
+                    }
+                    EepromState::Idle
+                }
+            },
+        };
+    }
+
+    /// Runs the command in bits (2 opcode bits then 8 address bits) and returns the next state.
+    fn command(&mut self, bits: u16) -> EepromState {
+        let addr = bits as usize & (EEPROM_WORDS - 1);
+        match (bits >> 8, bits >> 6 & 0b11) {
+            // READ (preceded by a dummy 0 bit)
+            (0b10, _) => {
+                self.do_ = false;
+                EepromState::Read { addr, bit: 0 }
+            },
+            // WRITE
+            (0b01, _) => EepromState::Write { addr: Some(addr), data: 0, count: 0 },
+            // ERASE
+            (0b11, _) => {
+                self.program(addr, 0xFFFF);
+                EepromState::Idle
+            },
+            // EWEN / EWDS
+            (_, 0b11) => {
+                self.write_enabled = true;
+                EepromState::Idle
+            },
+            (_, 0b00) => {
+                self.write_enabled = false;
+                EepromState::Idle
+            },
+            // ERAL
+            (_, 0b10) => {
+                (0..EEPROM_WORDS).for_each(|addr| self.program(addr, 0xFFFF));
+                EepromState::Idle
+            },
+            // WRAL
+            _ => EepromState::Write { addr: None, data: 0, count: 0 },
+        }
+    }
+
+    /// Writes data to addr if writes are enabled; DO then reports the EEPROM is ready.
+    fn program(&mut self, addr: usize, data: u16) {
+        if self.write_enabled {
+            self.words[addr] = data;
+            self.dirty = true;
+        }
+        self.do_ = true;
+    }
+}
+
+impl Snapshot for Mbc7 {
+    fn save(&self, state: &mut StateWriter) {
+        self.current_rom_bank.save(state);
+        self.ram_enabled_1.save(state);
+        self.ram_enabled_2.save(state);
+        self.accel.save(state);
+        self.accel_erased.save(state);
+        Snapshot::save(&self.eeprom, state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.current_rom_bank.load(state)?;
+        self.ram_enabled_1.load(state)?;
+        self.ram_enabled_2.load(state)?;
+        self.accel.load(state)?;
+        self.accel_erased.load(state)?;
+        Snapshot::load(&mut self.eeprom, state)?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Eeprom {
+    fn save(&self, state: &mut StateWriter) {
+        self.words.save(state);
+        self.state.save(state);
+        self.write_enabled.save(state);
+        self.cs.save(state);
+        self.clk.save(state);
+        self.di.save(state);
+        self.do_.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.words.load(state)?;
+        self.state.load(state)?;
+        self.write_enabled.load(state)?;
+        self.cs.load(state)?;
+        self.clk.load(state)?;
+        self.di.load(state)?;
+        self.do_.load(state)?;
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+impl Snapshot for EepromState {
+    fn save(&self, state: &mut StateWriter) {
+        match *self {
+            EepromState::Idle => 0u8.save(state),
+            EepromState::Command { bits, count } => {
+                1u8.save(state);
+                bits.save(state);
+                count.save(state);
+            },
+            EepromState::Read { addr, bit } => {
+                2u8.save(state);
+                addr.save(state);
+                bit.save(state);
+            },
+            EepromState::Write { addr, data, count } => {
+                3u8.save(state);
+                addr.save(state);
+                data.save(state);
+                count.save(state);
+            },
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut eeprom_state = 0u8;
+        eeprom_state.load(state)?;
+        *self = match eeprom_state {
+            0 => EepromState::Idle,
+            1 => {
+                let (mut bits, mut count) = (0, 0);
+                bits.load(state)?;
+                count.load(state)?;
+                EepromState::Command { bits, count }
+            },
+            2 => {
+                let (mut addr, mut bit) = (0, 0);
+                addr.load(state)?;
+                bit.load(state)?;
+                if addr >= EEPROM_WORDS {
+                    return Err(StateError::Invalid("EEPROM address"));
+                }
+                EepromState::Read { addr, bit }
+            },
+            3 => {
+                let (mut addr, mut data, mut count) = (None, 0, 0);
+                addr.load(state)?;
+                data.load(state)?;
+                count.load(state)?;
+                if addr.is_some_and(|addr| addr >= EEPROM_WORDS) {
+                    return Err(StateError::Invalid("EEPROM address"));
+                }
+                EepromState::Write { addr, data, count }
+            },
+            _ => return Err(StateError::Invalid("EEPROM state")),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mbc, Mbc7, ROM_BANK_SIZE};
+
+    const EEPROM: usize = 0xA080;
+
+    /// Clocks count bits (MSB first) into the EEPROM and returns what DO read after each one.
+    fn send(mbc: &mut Mbc7, bits: u64, count: u32) -> u64 {
+        let mut out = 0;
+        for i in (0..count).rev() {
+            let di = ((bits >> i) & 1) as u8;
+            mbc.write_ram(EEPROM, 0x80 | di << 1);
+            mbc.write_ram(EEPROM, 0xC0 | di << 1);
+            out = out << 1 | (mbc.read_ram(EEPROM) & 1) as u64;
+        }
+        out
+    }
+
+    fn deselect(mbc: &mut Mbc7) {
+        mbc.write_ram(EEPROM, 0x00);
+    }
+
+    fn enabled_mbc() -> Mbc7 {
+        let mut mbc = Mbc7::new(vec![[0; ROM_BANK_SIZE]; 2], 2);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x40);
+        mbc
+    }
+
+    /// Returns a command's 11 bits: the start bit, opcode and address.
+    fn command(opcode: u64, addr: u64) -> u64 {
+        1 << 10 | opcode << 8 | addr
+    }
+
+    #[test]
+    fn mbc7_eeprom_test() {
+        let mut mbc = enabled_mbc();
+        let (read, write, erase, ewen) = (0b10, 0b01, 0b11, 0b00);
+
+        // writes are ignored until EWEN
+        send(&mut mbc, command(write, 5) << 16 | 0x1234, 27);
+        deselect(&mut mbc);
+        assert_eq!(send(&mut mbc, command(read, 5) << 16, 11 + 16) & 0xFFFF, 0xFFFF);
+        deselect(&mut mbc);
+
+        send(&mut mbc, command(ewen, 0xC0), 11);
+        deselect(&mut mbc);
+        send(&mut mbc, command(write, 5) << 16 | 0x1234, 27);
+        deselect(&mut mbc);
+        send(&mut mbc, command(write, 6) << 16 | 0xABCD, 27);
+        deselect(&mut mbc);
+
+        // the dummy 0 bit comes first, then both words in sequence
+        assert_eq!(send(&mut mbc, command(read, 5) << 32, 11 + 32) & 0xFFFF_FFFF, 0x1234ABCD);
+        deselect(&mut mbc);
+
+        send(&mut mbc, command(erase, 5), 11);
+        deselect(&mut mbc);
+        assert_eq!(send(&mut mbc, command(read, 5) << 16, 11 + 16) & 0xFFFF, 0xFFFF);
+    }
+
+    #[test]
+    fn mbc7_accelerometer_test() {
+        let mut mbc = enabled_mbc();
+        mbc.set_tilt(1.0, -0.5);
+
+        // only latches after being erased
+        mbc.write_ram(0xA010, 0xAA);
+        assert_eq!(mbc.read_ram(0xA020), 0x00);
+        assert_eq!(mbc.read_ram(0xA030), 0x80);
+
+        mbc.write_ram(0xA000, 0x55);
+        mbc.write_ram(0xA010, 0xAA);
+        assert_eq!(u16::from_le_bytes([mbc.read_ram(0xA020), mbc.read_ram(0xA030)]), 0x81D0 - 0x70);
+        assert_eq!(u16::from_le_bytes([mbc.read_ram(0xA040), mbc.read_ram(0xA050)]), 0x81D0 + 0x38);
+
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA020), 0xFF);
+    }
+}
@@ -0,0 +1,276 @@
+use alloc::{format, string::String, vec::Vec};
+use crate::bus::RAM_START;
+use crate::cartridge::battery::Battery;
+use crate::cartridge::sram::Sram;
+
+use super::{Mbc, ROM_BANK_SIZE};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+/// Multicart mapper: boots "unmapped" into the menu in the last 32 KiB of ROM, which picks a game
+/// by setting the outer bank bits and then locking them in. After that it behaves like an MBC1
+/// confined to the selected game's banks (MBC1 mode select is ignored).
+pub struct Mmm01 {
+    rom: Vec<[u8; ROM_BANK_SIZE]>,
+    rom_banks: usize,
+    ram: Option<Sram>,
+    ram_banks: usize,
+    battery: Option<Battery>,
+    // battery-backed state was written since the last take_ram_dirty()
+    ram_dirty: bool,
+    ram_enabled: bool,
+    mapped: bool,
+
+    rom_bank_low: usize,
+    rom_bank_mid: usize,
+    rom_bank_high: usize,
+    // bits 1-4 of rom_bank_low that can no longer be written once mapped
+    rom_bank_mask: usize,
+    ram_bank_low: usize,
+    ram_bank_high: usize,
+}
+
+impl Mmm01 {
+    pub fn new(rom: Vec<[u8; ROM_BANK_SIZE]>, rom_banks: usize) -> Self {
+        Mmm01 {
+            rom,
+            rom_banks,
+            ram: None,
+            ram_banks: 0,
+            battery: None,
+            ram_dirty: false,
+            ram_enabled: false,
+            mapped: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+        }
+    }
+
+    /// Specifies RAM (sets ram to not None).
+    pub fn with_ram(mut self, ram_banks: usize) -> Self {
+        self.ram = Some(Sram::new(ram_banks));
+        self.ram_banks = ram_banks;
+        self
+    }
+
+    /// ASSUMES: RAM has already be set to not None
+    /// Specifies battery and loads last RAM save (if any exists), otherwise creates a new one.
+    pub fn with_battery(mut self, battery: Battery) -> Self {
+        self.ram = Some(battery.load_sram(self.ram_banks).unwrap_or_else(|| Sram::new(self.ram_banks)));
+        self.battery = Some(battery);
+        self
+    }
+
+    fn fixed_low_bits(&self) -> usize {
+        self.rom_bank_mask << 1
+    }
+
+    /// Returns the ROM bank mapped to 0x0000-0x3FFF (free bank bits are all 0).
+    fn rom_bank_0(&self) -> usize {
+        if !self.mapped {
+            return self.rom_banks - 2;
+        }
+
+        let bank = (self.rom_bank_low & self.fixed_low_bits()) | self.outer_rom_bank();
+        bank & (self.rom_banks - 1)
+    }
+
+    /// Returns the ROM bank mapped to 0x4000-0x7FFF.
+    fn rom_bank_1(&self) -> usize {
+        if !self.mapped {
+            return self.rom_banks - 1;
+        }
+
+        let mut low = self.rom_bank_low;
+        if low & 0x1F & !self.fixed_low_bits() == 0 {
+            low |= 1;
+        }
+        (low | self.outer_rom_bank()) & (self.rom_banks - 1)
+    }
+
+    fn outer_rom_bank(&self) -> usize {
+        (self.rom_bank_mid << 5) | (self.rom_bank_high << 7)
+    }
+
+    fn ram_bank(&self) -> usize {
+        ((self.ram_bank_high << 2) | self.ram_bank_low) % self.ram_banks.max(1)
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn read_rom(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[self.rom_bank_0()][addr],
+            0x4000..=0x7FFF => self.rom[self.rom_bank_1()][addr - 0x4000],
+            _ => unreachable!()
+        }
+    }
+
+    fn write_rom(&mut self, addr: usize, byte: u8) {
+        let byte = byte as usize;
+
+        match addr {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = (byte & 0xF) == 0xA;
+                if !self.mapped {
+                    self.mapped = byte & 0x40 != 0;
+                }
+            },
+            0x2000..=0x3FFF => {
+                if self.mapped {
+                    let fixed = self.fixed_low_bits();
+                    self.rom_bank_low = (self.rom_bank_low & fixed) | (byte & 0x1F & !fixed);
+                } else {
+                    self.rom_bank_low = byte & 0x1F;
+                    self.rom_bank_mid = (byte >> 5) & 0x3;
+                }
+            },
+            0x4000..=0x5FFF => {
+                self.ram_bank_low = byte & 0x3;
+                if !self.mapped {
+                    self.ram_bank_high = (byte >> 2) & 0x3;
+                    self.rom_bank_high = (byte >> 4) & 0x3;
+                }
+            },
+            0x6000..=0x7FFF if !self.mapped => self.rom_bank_mask = (byte >> 2) & 0xF,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: usize) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match &self.ram {
+            Some(ram) if !ram.is_empty() => ram[self.ram_bank()][addr - RAM_START],
+            _ => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, byte: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        let ram_bank = self.ram_bank();
+        match &mut self.ram {
+            Some(ram) if !ram.is_empty() => {
+                ram[ram_bank][addr - RAM_START] = byte;
+                self.ram_dirty = true;
+            },
+            _ => {}
+        }
+    }
+
+    fn take_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.ram_dirty)
+    }
+
+    fn display(&self) -> String {
+        let mut ret = format!("MMM01 w/ {} ROM banks", self.rom_banks);
+        if self.ram.is_some() {
+            ret.push_str(&format!(" + {} RAM banks", self.ram_banks));
+        }
+        if self.battery.is_some() {
+            ret.push_str(" + Battery");
+        }
+        ret
+    }
+
+    fn save_state(&self) -> Result<bool, String> {
+        let battery = match &self.battery {
+            Some(battery) => battery,
+            None => return Ok(false)
+        };
+
+        let ram = match &self.ram {
+            Some(ram) => ram,
+            None => return Ok(false)
+        };
+
+        battery.save_sram(ram)?;
+        Ok(true)
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
+        assert!(save_type == "ram");
+        self.ram = Some(Sram::from_banks(Battery::parse_ram(data)));
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn save_id(&self) -> Option<String> {
+        match &self.battery {
+            Some(battery) => Some(battery.save_id()),
+            None => None,
+        }
+    }
+}
+
+impl Snapshot for Mmm01 {
+    fn save(&self, state: &mut StateWriter) {
+        self.ram.save(state);
+        self.ram_enabled.save(state);
+        self.mapped.save(state);
+        self.rom_bank_low.save(state);
+        self.rom_bank_mid.save(state);
+        self.rom_bank_high.save(state);
+        self.rom_bank_mask.save(state);
+        self.ram_bank_low.save(state);
+        self.ram_bank_high.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ram.load(state)?;
+        self.ram_enabled.load(state)?;
+        self.mapped.load(state)?;
+        self.rom_bank_low.load(state)?;
+        self.rom_bank_mid.load(state)?;
+        self.rom_bank_high.load(state)?;
+        self.rom_bank_mask.load(state)?;
+        self.ram_bank_low.load(state)?;
+        self.ram_bank_high.load(state)?;
+        self.ram_dirty = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mbc, Mmm01, ROM_BANK_SIZE};
+
+    fn numbered_rom(banks: usize) -> Vec<[u8; ROM_BANK_SIZE]> {
+        (0..banks).map(|i| [i as u8; ROM_BANK_SIZE]).collect()
+    }
+
+    #[test]
+    fn mmm01_unmapped_boot_test() {
+        let mbc = Mmm01::new(numbered_rom(64), 64);
+        assert_eq!(mbc.read_rom(0x0000), 62);
+        assert_eq!(mbc.read_rom(0x4000), 63);
+    }
+
+    #[test]
+    fn mmm01_mapped_game_test() {
+        let mut mbc = Mmm01::new(numbered_rom(64), 64);
+
+        // select the game at bank 0x20 with 8 banks (bank bits 3-4 fixed) and map it in
+        mbc.write_rom(0x2000, 0x20);
+        mbc.write_rom(0x6000, 0x0C << 2);
+        mbc.write_rom(0x0000, 0x40);
+
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mbc.read_rom(0x4000), 0x27);
+
+        // outer bank can no longer change
+        mbc.write_rom(0x2000, 0x60);
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+    }
+}
@@ -3,16 +3,32 @@ mod mbc1;
 mod mbc3;
 mod mbc2;
 mod mbc5;
-
-use core::panic;
-
+mod mbc6;
+mod mbc7;
+mod huc3;
+mod mmm01;
+mod camera;
+mod tama5;
+
+use alloc::{boxed::Box, rc::Rc, string::String, vec};
 use self::mbc1::Mbc1;
 use self::mbc2::Mbc2;
 use self::mbc3::Mbc3;
 use self::mbc5::Mbc5;
+use self::mbc6::{Mbc6, FLASH_SAVE_BANKS};
+use self::mbc7::Mbc7;
+use self::huc3::Huc3;
+use self::mmm01::Mmm01;
+use self::camera::PocketCamera;
+use self::tama5::Tama5;
 use self::no_mbc::NoMbc;
 
 use super::battery::Battery;
+use super::camera::ImageSource;
+use super::clock::ClockSource;
+use crate::infrared::IrTransceiver;
+use crate::savestate::Snapshot;
+use super::error::CartridgeError;
 use super::header::Header;
 
 pub const ROM_MEMORY_SPACE: usize = 0x8000; 
@@ -21,7 +37,9 @@ pub const RAM_MEMORY_SPACE: usize = 0x2000;
 pub const ROM_BANK_SIZE: usize = 0x4000;
 pub const RAM_BANK_SIZE: usize = 0x2000;
 
-pub trait Mbc {
+/// Save states (see Snapshot) cover the MBC's registers, RAM and clock but not its ROM; loading
+/// one counts as a RAM write, so the battery save catches up with it.
+pub trait Mbc: Snapshot {
     /// Handles bus reads from 0x0000 to 0x7FFF
     fn read_rom(&self, addr: usize) -> u8;
 
@@ -35,46 +53,113 @@ pub trait Mbc {
     fn write_ram(&mut self, addr: usize, byte: u8);
 
     /// Displays Mbc specifications.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
     fn display(&self) -> String;
 
-    /// Handles saving of MBC state (if it includes battery).
-    fn save_state(&self);
+    /// Handles saving of MBC state (if it includes battery); returns true if anything was saved.
+    fn save_state(&self) -> Result<bool, String>;
+
+    /// Returns true if the cartridge's rumble motor is currently on.
+    fn rumble_on(&self) -> bool {
+        false
+    }
+
+    /// Tilts the cartridge's accelerometer (if it has one) by x and y g (positive is right / down).
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    /// Advances hardware on the cartridge (e.g. a camera capture) by the given T-cycles.
+    fn step(&mut self, _t_cycles: u32) {}
+
+    /// Sets what the cartridge's camera sensor sees (if it has one).
+    fn set_image_source(&mut self, _source: Box<dyn ImageSource>) {}
+
+    /// Returns true if the cartridge has a clock (MBC3's RTC, HuC3 or TAMA5).
+    fn has_clock(&self) -> bool {
+        false
+    }
+
+    /// Moves the cartridge's clock (if it has one) seconds forward, or back for negative seconds.
+    fn adjust_clock(&mut self, _seconds: i64) {}
+
+    /// Returns true if the cartridge has its own IR port (in which case games use it instead of RP).
+    fn has_infrared(&self) -> bool {
+        false
+    }
+
+    /// Points the cartridge's IR port (if it has one) at ir.
+    fn connect_infrared(&mut self, _ir: Box<dyn IrTransceiver>) {}
+
+    /// Returns true (once) if battery-backed RAM (or clock) was written since the last call.
+    fn take_ram_dirty(&mut self) -> bool {
+        false
+    }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn load_save(&mut self, data: Vec<u8>, save_type: &str);
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn save_id(&self) -> Option<String>;
 }
 
-pub fn make_mbc(rom_bytes: &[u8], header: &Header) -> Box<dyn Mbc> {
-    let rom_banks = header.num_rom_banks();
-    let ram_banks = header.num_ram_banks();
+/// What a cartridge type keeps in its battery save.
+pub struct SaveLayout {
+    pub ram_banks: usize,
+    /// MBC3 clocks follow the RAM as a .sav RTC footer.
+    pub rtc_footer: bool,
+    /// HuC3 and TAMA5 clocks are saved in a separate file.
+    pub separate_clock: bool,
+}
+
+/// Returns the battery save layout of header's cartridge type (None if it has no battery).
+pub fn save_layout(header: &Header) -> Result<Option<SaveLayout>, CartridgeError> {
+    let ram_banks = header.num_ram_banks()?;
+    let (ram_banks, rtc_footer, separate_clock) = match header.cartridge_type() {
+        0x03 | 0x0D | 0x13 | 0x1B | 0x1E | 0xFC => (ram_banks, false, false),
+        // MBC6's flash is saved after its RAM
+        0x20 => (ram_banks + FLASH_SAVE_BANKS, false, false),
+        // MBC2's 512 bytes of RAM are saved padded to a whole bank
+        0x06 => (1, false, false),
+        // MBC7's 256 byte EEPROM is saved padded to a whole bank
+        0x22 => (1, false, false),
+        0x0F => (0, true, false),
+        0x10 => (ram_banks, true, false),
+        0xFD => (1, false, true),
+        0xFE => (ram_banks, false, true),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(SaveLayout { ram_banks, rtc_footer, separate_clock }))
+}
+
+/// Builds the MBC for header's cartridge type; battery saves are kept in save_dir and
+/// cartridge clocks count with clock.
+pub fn make_mbc(rom_bytes: &[u8], header: &Header, save_dir: &str, clock: Rc<dyn ClockSource>) -> Result<Box<dyn Mbc>, CartridgeError> {
+    let rom_banks = header.num_rom_banks()?;
+    let ram_banks = header.num_ram_banks()?;
+    if rom_bytes.len() > rom_banks * ROM_BANK_SIZE {
+        return Err(CartridgeError::RomSizeMismatch(rom_bytes.len()));
+    }
 
     let mut banked_rom = vec![[0; ROM_BANK_SIZE]; rom_banks];
     for i in 0..rom_bytes.len() {
         banked_rom[i / ROM_BANK_SIZE][i % ROM_BANK_SIZE] = rom_bytes[i];
     }
 
-    let mut id_name = header.title();
-    id_name.push_str(&header.get_hash_string());
-    
-    let battery = Battery::new(id_name);
+    let battery = Battery::new(save_dir, &header.title(), header.save_name());
+    let multicart = Mbc1::detect_multicart(rom_bytes);
 
-    match header.cartridge_type() {
+    Ok(match header.cartridge_type() {
         0x00 => Box::new(NoMbc::new(rom_bytes)),
-        0x01 => Box::new(Mbc1::new(banked_rom, rom_banks)),
-        0x02 => Box::new(Mbc1::new(banked_rom, rom_banks).with_ram(ram_banks)),
-        0x03 => Box::new(Mbc1::new(banked_rom, rom_banks).with_ram(ram_banks).with_battery(battery)),
+        0x01 => Box::new(Mbc1::new(banked_rom, rom_banks).with_multicart(multicart)),
+        0x02 => Box::new(Mbc1::new(banked_rom, rom_banks).with_multicart(multicart).with_ram(ram_banks)),
+        0x03 => Box::new(Mbc1::new(banked_rom, rom_banks).with_multicart(multicart).with_ram(ram_banks).with_battery(battery)),
         0x05 => Box::new(Mbc2::new_with_ram(banked_rom, rom_banks)),
         0x06 => Box::new(Mbc2::new_with_ram(banked_rom, rom_banks).with_battery(battery)),
-        0x08 => unimplemented!(),
-        0x09 => unimplemented!(),
-        0x0B => unimplemented!(),
-        0x0C => unimplemented!(),
-        0x0D => unimplemented!(),
-        0x0F => Box::new(Mbc3::new(banked_rom, rom_banks).with_rtctimer().with_battery(battery)),
-        0x10 => Box::new(Mbc3::new(banked_rom, rom_banks).with_rtctimer().with_ram(ram_banks).with_battery(battery)),
+        0x0B => Box::new(Mmm01::new(banked_rom, rom_banks)),
+        0x0C => Box::new(Mmm01::new(banked_rom, rom_banks).with_ram(ram_banks)),
+        0x0D => Box::new(Mmm01::new(banked_rom, rom_banks).with_ram(ram_banks).with_battery(battery)),
+        0x0F => Box::new(Mbc3::new(banked_rom, rom_banks).with_rtctimer(clock).with_battery(battery)),
+        0x10 => Box::new(Mbc3::new(banked_rom, rom_banks).with_rtctimer(clock).with_ram(ram_banks).with_battery(battery)),
         0x11 => Box::new(Mbc3::new(banked_rom, rom_banks)),
         0x12 => Box::new(Mbc3::new(banked_rom, rom_banks).with_ram(ram_banks)),
         0x13 => Box::new(Mbc3::new(banked_rom, rom_banks).with_ram(ram_banks).with_battery(battery)),
@@ -84,12 +169,11 @@ pub fn make_mbc(rom_bytes: &[u8], header: &Header) -> Box<dyn Mbc> {
         0x1C => Box::new(Mbc5::new(banked_rom, rom_banks).with_rumble()),
         0x1D => Box::new(Mbc5::new(banked_rom, rom_banks).with_rumble().with_ram(ram_banks)),
         0x1E => Box::new(Mbc5::new(banked_rom, rom_banks).with_rumble().with_ram(ram_banks).with_battery(battery)),
-        0x20 => unimplemented!(),
-        0x22 => unimplemented!(),
-        0xFC => unimplemented!(),
-        0xFD => unimplemented!(),
-        0xFE => unimplemented!(),
-        0xFF => unimplemented!(),
-        _ => panic!("Unknown cartridge type!")
-    }
+        0x20 => Box::new(Mbc6::new(banked_rom, rom_banks).with_ram(ram_banks).with_battery(battery)),
+        0x22 => Box::new(Mbc7::new(banked_rom, rom_banks).with_battery(battery)),
+        0xFC => Box::new(PocketCamera::new(banked_rom, rom_banks).with_ram(ram_banks).with_battery(battery)),
+        0xFD => Box::new(Tama5::new(banked_rom, rom_banks).with_clock(clock).with_battery(battery)),
+        0xFE => Box::new(Huc3::new(banked_rom, rom_banks).with_clock(clock).with_ram(ram_banks).with_battery(battery)),
+        cartridge_type => return Err(CartridgeError::UnsupportedType(cartridge_type)),
+    })
 }
@@ -1,5 +1,7 @@
+use alloc::string::String;
 use crate::bus::{RAM_START, ROM_START};
 use super::{Mbc, RAM_MEMORY_SPACE, ROM_MEMORY_SPACE};
+use crate::savestate::snapshot_fields;
 
 
 pub struct NoMbc {
@@ -28,16 +30,16 @@ impl Mbc for NoMbc {
         String::from("No Mbc")
     }
 
-    fn save_state(&self) {
-        // do nothing
+    fn save_state(&self) -> Result<bool, String> {
+        Ok(false)
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn load_save(&mut self, _data: Vec<u8>, _save_type: &str) {
         // do nothing
     }
     
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     fn save_id(&self) -> Option<String> {
         None
     }
@@ -54,4 +56,6 @@ impl NoMbc {
             ram: [0; RAM_MEMORY_SPACE],
         }
     }
-}
\ No newline at end of file
+}
+
+snapshot_fields!(NoMbc, ram);
@@ -0,0 +1,523 @@
+use alloc::{format, rc::Rc, string::String, vec, vec::Vec};
+use crate::cartridge::battery::Battery;
+use crate::cartridge::clock::{ClockSource, WallClock};
+
+use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::savestate::{snapshot_fields, Snapshot, StateError, StateReader, StateWriter};
+
+// the mapper's internal memory; kept in the first bytes of a single RAM bank
+const MEMORY_SIZE: usize = 0x20;
+
+// bytes of the calendar time (7), running flag (1) and the time they were taken at (8)
+const CLOCK_SAVE_SIZE: usize = 16;
+
+const REG_ROM_BANK_LOW: usize = 0x0;
+const REG_ROM_BANK_HIGH: usize = 0x1;
+const REG_DATA_LOW: usize = 0x4;
+const REG_DATA_HIGH: usize = 0x5;
+const REG_COMMAND: usize = 0x6;
+const REG_ADDRESS: usize = 0x7;
+const REG_READY: usize = 0xA;
+const REG_RESULT_LOW: usize = 0xC;
+const REG_RESULT_HIGH: usize = 0xD;
+
+/// Calendar time as kept by the TAMA5's RTC chip (TC8521).
+#[derive(Clone, Copy)]
+struct Tama5Time {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    weekday: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+}
+
+impl Tama5Time {
+    /// Returns the time seconds later (or earlier, for negative seconds).
+    fn shifted(mut self, seconds: i64) -> Self {
+        let total = self.second as i64 + self.minute as i64 * 60 + self.hour as i64 * 3600 + seconds;
+        let time_of_day = total.rem_euclid(3600 * 24);
+        self.second = (time_of_day % 60) as u8;
+        self.minute = (time_of_day / 60 % 60) as u8;
+        self.hour = (time_of_day / 3600) as u8;
+
+        let days = total.div_euclid(3600 * 24);
+        for _ in 0..days {
+            self.next_day();
+        }
+        for _ in days..0 {
+            self.previous_day();
+        }
+        self
+    }
+
+    fn next_day(&mut self) {
+        self.weekday = (self.weekday + 1) % 7;
+        self.day += 1;
+        if self.day > self.days_in_month() {
+            self.day = 1;
+            self.month += 1;
+            if self.month > 12 {
+                self.month = 1;
+                self.year = (self.year + 1) % 100;
+            }
+        }
+    }
+
+    fn previous_day(&mut self) {
+        self.weekday = (self.weekday + 6) % 7;
+        // the game can write out of range dates, so they saturate instead of underflowing
+        self.day = self.day.saturating_sub(1);
+        if self.day == 0 {
+            self.month = self.month.saturating_sub(1);
+            if self.month == 0 {
+                self.month = 12;
+                self.year = (self.year + 99) % 100;
+            }
+            self.day = self.days_in_month();
+        }
+    }
+
+    fn days_in_month(&self) -> u8 {
+        match self.month {
+            2 if self.year.is_multiple_of(4) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    /// Reads a digit of the chip's time page (0x0-0xC: seconds through years, ones digit first).
+    fn read_register(&self, reg: u8) -> u8 {
+        match reg {
+            0x0 => self.second % 10,
+            0x1 => self.second / 10,
+            0x2 => self.minute % 10,
+            0x3 => self.minute / 10,
+            0x4 => self.hour % 10,
+            0x5 => self.hour / 10,
+            0x6 => self.weekday,
+            0x7 => self.day % 10,
+            0x8 => self.day / 10,
+            0x9 => self.month % 10,
+            0xA => self.month / 10,
+            0xB => self.year % 10,
+            0xC => self.year / 10,
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, reg: u8, value: u8) {
+        let (field, tens) = match reg {
+            0x0 | 0x1 => (&mut self.second, reg == 0x1),
+            0x2 | 0x3 => (&mut self.minute, reg == 0x3),
+            0x4 | 0x5 => (&mut self.hour, reg == 0x5),
+            0x6 => {
+                self.weekday = value & 0x7;
+                return;
+            },
+            0x7 | 0x8 => (&mut self.day, reg == 0x8),
+            0x9 | 0xA => (&mut self.month, reg == 0xA),
+            0xB | 0xC => (&mut self.year, reg == 0xC),
+            _ => return,
+        };
+
+        *field = if tens {
+            value * 10 + *field % 10
+        } else {
+            *field / 10 * 10 + value
+        };
+    }
+}
+
+struct Tama5Clock {
+    time: Tama5Time,
+    running: bool,
+    clock_time: u64,
+}
+
+impl Tama5Clock {
+    fn new(now: u64) -> Self {
+        Tama5Clock {
+            time: Tama5Time { second: 0, minute: 0, hour: 0, weekday: 0, day: 1, month: 1, year: 0 },
+            running: true,
+            clock_time: now,
+        }
+    }
+
+    fn from_save(data: &[u8]) -> Option<Self> {
+        if data.len() < CLOCK_SAVE_SIZE {
+            return None;
+        }
+
+        Some(Tama5Clock {
+            time: Tama5Time {
+                second: data[0],
+                minute: data[1],
+                hour: data[2],
+                weekday: data[3],
+                day: data[4],
+                month: data[5],
+                year: data[6],
+            },
+            running: data[7] != 0,
+            clock_time: u64::from_be_bytes(data[8..16].try_into().unwrap()),
+        })
+    }
+
+    fn to_save(&self, now: u64) -> [u8; CLOCK_SAVE_SIZE] {
+        let time = self.at(now);
+        let mut save = [0; CLOCK_SAVE_SIZE];
+        save[0..8].copy_from_slice(&[
+            time.second, time.minute, time.hour, time.weekday,
+            time.day, time.month, time.year, self.running as u8,
+        ]);
+        save[8..16].copy_from_slice(&now.to_be_bytes());
+        save
+    }
+
+    /// Returns the calendar time as of now.
+    fn at(&self, now: u64) -> Tama5Time {
+        if !self.running {
+            return self.time;
+        }
+        self.time.shifted(now.saturating_sub(self.clock_time) as i64)
+    }
+
+    fn set(&mut self, time: Tama5Time, now: u64) {
+        self.time = time;
+        self.clock_time = now;
+    }
+
+    fn adjust(&mut self, seconds: i64, now: u64) {
+        self.set(self.at(now).shifted(seconds), now);
+    }
+
+    fn set_running(&mut self, running: bool, now: u64) {
+        self.set(self.at(now), now);
+        self.running = running;
+    }
+}
+
+/// Bandai's mapper used by Game de Hakken!! Tamagotchi - Osutchi to Mesutchi. Everything goes through
+/// 0xA000 (register value) and 0xA001 (register select): ROM banking, the 32 bytes of internal
+/// memory and the RTC are driven by 4-bit registers, with register 0x7 running the selected command.
+/// The RTC's alarm is accepted but never goes off.
+pub struct Tama5 {
+    rom: Vec<[u8; ROM_BANK_SIZE]>,
+    rom_banks: usize,
+    ram: Vec<[u8; RAM_BANK_SIZE]>,
+    battery: Option<Battery>,
+    // battery-backed state was written since the last take_ram_dirty()
+    ram_dirty: bool,
+    clock: Tama5Clock,
+    clock_source: Rc<dyn ClockSource>,
+    registers: [u8; 0x10],
+    selected_register: usize,
+    result: u8,
+}
+
+impl Tama5 {
+    pub fn new(rom: Vec<[u8; ROM_BANK_SIZE]>, rom_banks: usize) -> Self {
+        Tama5 {
+            rom,
+            rom_banks,
+            ram: vec![[0; RAM_BANK_SIZE]],
+            battery: None,
+            ram_dirty: false,
+            clock: Tama5Clock::new(WallClock.now()),
+            clock_source: Rc::new(WallClock),
+            registers: [0; 0x10],
+            selected_register: 0,
+            result: 0,
+        }
+    }
+
+    /// Has the RTC count with clock_source instead of the host's clock (restarting it from zero).
+    pub fn with_clock(mut self, clock_source: Rc<dyn ClockSource>) -> Self {
+        self.clock = Tama5Clock::new(clock_source.now());
+        self.clock_source = clock_source;
+        self
+    }
+
+    /// Specifies battery and loads last memory and clock saves (if any exist).
+    pub fn with_battery(mut self, battery: Battery) -> Self {
+        if let Some(ram) = battery.load_ram(&[1]) {
+            self.ram = ram;
+        }
+        if let Some(clock) = battery.load_clock().and_then(|data| Tama5Clock::from_save(&data)) {
+            self.clock = clock;
+        }
+        self.battery = Some(battery);
+        self
+    }
+
+    fn rom_bank(&self) -> usize {
+        let bank = self.registers[REG_ROM_BANK_LOW] as usize
+            | (self.registers[REG_ROM_BANK_HIGH] as usize & 0x1) << 4;
+        bank & (self.rom_banks - 1)
+    }
+
+    /// Runs the command in register 0x6 (bits 1-3) on the address in registers 0x6 (bit 0) and 0x7.
+    fn run_command(&mut self) {
+        let address = ((self.registers[REG_COMMAND] as usize & 0x1) << 4) | self.registers[REG_ADDRESS] as usize;
+        let data = self.registers[REG_DATA_LOW] | (self.registers[REG_DATA_HIGH] << 4);
+        let command = self.registers[REG_COMMAND] >> 1;
+
+        // memory writes and anything that sets the clock need saving
+        self.ram_dirty |= match command {
+            0x0 => true,
+            0x2 => address <= 0x05,
+            0x4 => self.registers[REG_ADDRESS] == 0x0,
+            _ => false,
+        };
+
+        match command {
+            0x0 => self.ram[0][address % MEMORY_SIZE] = data,
+            0x1 => self.result = self.ram[0][address % MEMORY_SIZE],
+            0x2 => self.rtc_command(address, data),
+            // direct access to the time page: register 0x4 selects the digit, 0x5 holds its value
+            0x4 => {
+                let reg = self.registers[REG_DATA_LOW];
+                let now = self.clock_source.now();
+                match self.registers[REG_ADDRESS] {
+                    0x0 => {
+                        let mut time = self.clock.at(now);
+                        time.write_register(reg, self.registers[REG_DATA_HIGH]);
+                        self.clock.set(time, now);
+                    },
+                    0x1 => self.result = self.clock.at(now).read_register(reg),
+                    _ => {}
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn rtc_command(&mut self, address: usize, data: u8) {
+        let now = self.clock_source.now();
+        let mut time = self.clock.at(now);
+
+        match address {
+            0x00 => self.clock.set_running(false, now),
+            0x01 => {
+                time.second = 0;
+                self.clock.set(time, now);
+                self.clock.set_running(true, now);
+            },
+            0x04 => {
+                time.minute = from_bcd(data) % 60;
+                self.clock.set(time, now);
+            },
+            0x05 => {
+                time.hour = from_bcd(data) % 24;
+                self.clock.set(time, now);
+            },
+            0x06 => self.result = to_bcd(time.minute),
+            0x07 => self.result = to_bcd(time.hour),
+            // 0x10 and 0x11 disable and enable the alarm (not emulated)
+            _ => {}
+        }
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xF)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+impl Mbc for Tama5 {
+    fn read_rom(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[0][addr],
+            0x4000..=0x7FFF => self.rom[self.rom_bank()][addr - 0x4000],
+            _ => unreachable!()
+        }
+    }
+
+    fn write_rom(&mut self, _addr: usize, _byte: u8) {}
+
+    fn read_ram(&self, addr: usize) -> u8 {
+        if addr & 1 != 0 {
+            return 0xFF;
+        }
+
+        match self.selected_register {
+            // commands finish immediately so the mapper is always ready
+            REG_READY => 0xF1,
+            REG_RESULT_LOW => 0xF0 | (self.result & 0xF),
+            REG_RESULT_HIGH => 0xF0 | (self.result >> 4),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, byte: u8) {
+        if addr & 1 != 0 {
+            self.selected_register = byte as usize & 0xF;
+            return;
+        }
+
+        self.registers[self.selected_register] = byte & 0xF;
+        if self.selected_register == REG_ADDRESS {
+            self.run_command();
+        }
+    }
+
+    fn has_clock(&self) -> bool {
+        true
+    }
+
+    fn adjust_clock(&mut self, seconds: i64) {
+        self.clock.adjust(seconds, self.clock_source.now());
+        self.ram_dirty = true;
+    }
+
+    fn take_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.ram_dirty)
+    }
+
+    fn display(&self) -> String {
+        let mut ret = format!("TAMA5 w/ {} ROM banks + RTC", self.rom_banks);
+        if self.battery.is_some() {
+            ret.push_str(" + Battery");
+        }
+        ret
+    }
+
+    fn save_state(&self) -> Result<bool, String> {
+        let battery = match &self.battery {
+            Some(battery) => battery,
+            None => return Ok(false)
+        };
+
+        battery.save_ram(&self.ram)?;
+        battery.save_clock(&self.clock.to_save(self.clock_source.now()))?;
+        Ok(true)
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
+        match save_type {
+            "ram" => self.ram = Battery::parse_ram(data),
+            "rtc" => if let Some(clock) = Tama5Clock::from_save(&data) {
+                self.clock = clock;
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    fn save_id(&self) -> Option<String> {
+        match &self.battery {
+            Some(battery) => Some(battery.save_id()),
+            None => None,
+        }
+    }
+}
+
+impl Snapshot for Tama5 {
+    fn save(&self, state: &mut StateWriter) {
+        self.ram[..].save(state);
+        self.clock.save(state);
+        self.registers.save(state);
+        self.selected_register.save(state);
+        self.result.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ram[..].load(state)?;
+        self.clock.load(state)?;
+        self.registers.load(state)?;
+        self.selected_register.load(state)?;
+        self.result.load(state)?;
+        self.ram_dirty = true;
+        Ok(())
+    }
+}
+
+snapshot_fields!(Tama5Clock, time, running, clock_time);
+snapshot_fields!(Tama5Time, second, minute, hour, weekday, day, month, year);
+
+#[cfg(test)]
+mod tests {
+    use super::{Mbc, Tama5, Tama5Time, ROM_BANK_SIZE};
+
+    fn write_register(mbc: &mut Tama5, reg: u8, value: u8) {
+        mbc.write_ram(0xA001, reg);
+        mbc.write_ram(0xA000, value);
+    }
+
+    fn read_result(mbc: &mut Tama5) -> u8 {
+        mbc.write_ram(0xA001, 0xC);
+        let low = mbc.read_ram(0xA000) & 0xF;
+        mbc.write_ram(0xA001, 0xD);
+        let high = mbc.read_ram(0xA000) & 0xF;
+        (high << 4) | low
+    }
+
+    #[test]
+    fn tama5_memory_test() {
+        let mut mbc = Tama5::new(vec![[0; ROM_BANK_SIZE]; 32], 32);
+
+        mbc.write_ram(0xA001, 0xA);
+        assert_eq!(mbc.read_ram(0xA000), 0xF1);
+
+        // write 0x5A to address 0x13, then read it back
+        write_register(&mut mbc, 0x4, 0xA);
+        write_register(&mut mbc, 0x5, 0x5);
+        write_register(&mut mbc, 0x6, 0x1);
+        write_register(&mut mbc, 0x7, 0x3);
+
+        write_register(&mut mbc, 0x6, 0x3);
+        write_register(&mut mbc, 0x7, 0x3);
+        assert_eq!(read_result(&mut mbc), 0x5A);
+
+        write_register(&mut mbc, 0x0, 0x3);
+        write_register(&mut mbc, 0x1, 0x1);
+        assert_eq!(mbc.rom_bank(), 0x13);
+    }
+
+    #[test]
+    fn tama5_rtc_test() {
+        let mut mbc = Tama5::new(vec![[0; ROM_BANK_SIZE]; 32], 32);
+
+        // stop the clock so it can't tick mid-test, then set 23:45
+        write_register(&mut mbc, 0x6, 0x4);
+        write_register(&mut mbc, 0x7, 0x0);
+        write_register(&mut mbc, 0x4, 0x5);
+        write_register(&mut mbc, 0x5, 0x4);
+        write_register(&mut mbc, 0x7, 0x4);
+        write_register(&mut mbc, 0x4, 0x3);
+        write_register(&mut mbc, 0x5, 0x2);
+        write_register(&mut mbc, 0x7, 0x5);
+
+        write_register(&mut mbc, 0x7, 0x6);
+        assert_eq!(read_result(&mut mbc), 0x45);
+        write_register(&mut mbc, 0x7, 0x7);
+        assert_eq!(read_result(&mut mbc), 0x23);
+
+        // read the hour's tens digit straight from the time page
+        write_register(&mut mbc, 0x6, 0x8);
+        write_register(&mut mbc, 0x4, 0x5);
+        write_register(&mut mbc, 0x7, 0x1);
+        assert_eq!(read_result(&mut mbc), 0x2);
+    }
+
+    #[test]
+    fn tama5_time_shift_test() {
+        let new_year = Tama5Time { second: 10, minute: 0, hour: 0, weekday: 0, day: 1, month: 1, year: 0 };
+
+        let before = new_year.shifted(-20);
+        assert_eq!((before.year, before.month, before.day, before.weekday), (99, 12, 31, 6));
+        assert_eq!((before.hour, before.minute, before.second), (23, 59, 50));
+
+        // 2000 (year 0) is a leap year
+        let after = before.shifted(60 * 24 * 3600 + 20);
+        assert_eq!((after.year, after.month, after.day, after.weekday), (0, 3, 1, 4));
+        assert_eq!((after.hour, after.minute, after.second), (0, 0, 10));
+    }
+}
@@ -2,14 +2,41 @@ mod mbc;
 mod header;
 mod battery;
 mod rtc;
+mod clock;
+mod sram;
+mod error;
+mod verify;
+mod fix;
+pub mod rumble;
+pub mod camera;
 
+use alloc::{boxed::Box, format, rc::Rc, string::{String, ToString}, vec::Vec};
+#[cfg(feature = "std")]
 use std::fs::File;
+#[cfg(feature = "std")]
 use std::io::{self, Read};
 
+#[cfg(feature = "std")]
 use crate::config::{CGB_BOOTROM_PATH, DMG_BOOTROM_PATH};
+use crate::infrared::IrTransceiver;
+use crate::palette::{self, DmgPalette};
 
+#[allow(unused_imports)]
+pub use self::battery::SAVE_PATH;
+
+use self::camera::ImageSource;
+pub use self::error::CartridgeError;
+#[allow(unused_imports)]
+pub use self::verify::{verify_save, SaveCheck};
+use self::verify::save_size_warning;
+pub use self::fix::fix_header;
+#[allow(unused_imports)]
+pub use self::clock::{ClockSource, EmulatedClock, FixedClock, WallClock};
+#[cfg(not(feature = "std"))]
+pub use self::clock::set_unix_time;
 use self::header::Header;
 use self::mbc::Mbc;
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
 
 const BOOTROM_SIZE: usize = 0x100;
 
@@ -17,6 +44,9 @@ const BOOTROM_SIZE: usize = 0x100;
 const BOOTROM_2_START: usize = 0x200;
 const BOOTROM_2_END: usize = 0x900;
 
+const TITLE_START: usize = 0x134;
+const TITLE_BYTES: usize = 16;
+
 pub struct Cartridge {
     bootrom: [u8; BOOTROM_SIZE],
     bootrom2: [u8; BOOTROM_2_END - BOOTROM_2_START],
@@ -24,39 +54,97 @@ pub struct Cartridge {
     bank: u8,
     header: Header,
     with_bootrom: bool,
+    // kept to rebuild the MBC when the save directory changes
+    rom: Vec<u8>,
+    // CRC-32 of rom (see rom_hash), worked out once as save states are stamped with it
+    rom_hash: u32,
+    save_dir: String,
+    clock: Rc<dyn ClockSource>,
     mbc: Box<dyn Mbc>,
 }
 
+// cartridge types whose RAM area is only backed by RAM (when the header declares some)
+const PLAIN_RAM_TYPES: [u8; 4] = [0x00, 0x01, 0x19, 0x1C];
+
 impl Cartridge {
-    /// Loads cartridge from array slice of bytes (TODO: currently does NOT support bootrom)
+    /// Loads cartridge from array slice of bytes (see with_bootrom to run a boot ROM first)
     #[allow(dead_code)]
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        let header = Header::from_bytes(bytes);
-        Cartridge { 
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        let header = Header::from_bytes(bytes)?;
+        Ok(Cartridge { 
             bootrom: [0; BOOTROM_SIZE],
             bootrom2: [0; BOOTROM_2_END - BOOTROM_2_START],
-            mbc: mbc::make_mbc(bytes, &header),
+            mbc: mbc::make_mbc(bytes, &header, SAVE_PATH, Rc::new(WallClock))?,
             cgb_bootrom: false,
             bank: 1,
             header,
             with_bootrom: false,
+            rom: bytes.to_vec(),
+            rom_hash: battery::crc32(bytes),
+            save_dir: SAVE_PATH.to_string(),
+            clock: Rc::new(WallClock),
+        })
+    }
+
+    /// Runs bootrom before the game: a DMG boot ROM (0x100 bytes) or a CGB one (0x900 bytes,
+    /// its second part right after the header), like from_file does with the configured paths.
+    #[allow(dead_code)]
+    pub fn with_bootrom(mut self, bootrom: &[u8]) -> Result<Self, CartridgeError> {
+        match bootrom.len() {
+            BOOTROM_SIZE => self.cgb_bootrom = false,
+            BOOTROM_2_END => {
+                self.bootrom2.copy_from_slice(&bootrom[BOOTROM_2_START..BOOTROM_2_END]);
+                self.cgb_bootrom = true;
+            }
+            size => return Err(CartridgeError::InvalidBootrom("image".to_string(), size)),
         }
+        self.bootrom.copy_from_slice(&bootrom[..BOOTROM_SIZE]);
+        self.bank = 0;
+        self.with_bootrom = true;
+        Ok(self)
+    }
+
+    /// Keeps battery saves in save_dir (instead of saves/) and reloads them from there. 
+    /// Saves left in saves/ or under an older header hash are migrated on first use.
+    #[allow(dead_code)]
+    pub fn with_save_dir(mut self, save_dir: &str) -> Self {
+        self.mbc = mbc::make_mbc(&self.rom, &self.header, save_dir, Rc::clone(&self.clock))
+            .expect("ROM already loaded with the default save directory");
+        self.save_dir = save_dir.to_string();
+        self
+    }
+
+    /// Has the cartridge's clock (if it has one) count with clock instead of the host's clock,
+    /// e.g. a FixedClock so runs can be repeated exactly. Battery saves are reloaded.
+    #[allow(dead_code)]
+    pub fn with_clock(mut self, clock: Rc<dyn ClockSource>) -> Self {
+        self.mbc = mbc::make_mbc(&self.rom, &self.header, &self.save_dir, Rc::clone(&clock))
+            .expect("ROM already loaded with the host's clock");
+        self.clock = clock;
+        self
     }
 
     /// Loads cartridge from the given file path (and optionally runs it with boot ROM).
-    pub fn from_file(rom_path: &str, with_bootrom: bool) -> Self {
-        let header = match Header::from_file(rom_path) {
-            Ok(header) => header,
-            Err(err) => {
-                panic!("Error reading header from {}: {}", rom_path, err);
-            }
-        };
+    #[cfg(feature = "std")]
+    #[allow(dead_code)]
+    pub fn from_file(rom_path: &str, with_bootrom: bool) -> Result<Self, CartridgeError> {
+        Cartridge::from_file_in(rom_path, with_bootrom, SAVE_PATH)
+    }
+
+    /// Like from_file, but with battery saves kept in save_dir from the start, so nothing is
+    /// read from or written to saves/ except for migrating an existing save (see with_save_dir).
+    #[cfg(feature = "std")]
+    pub fn from_file_in(rom_path: &str, with_bootrom: bool, save_dir: &str) -> Result<Self, CartridgeError> {
+        let rom_bytes = Cartridge::read_from_file(rom_path)
+            .map_err(|err| CartridgeError::Io(rom_path.to_string(), err.to_string()))?;
+        let header = Header::from_bytes(&rom_bytes)?;
 
         let mut bootrom = [0; BOOTROM_SIZE];
         let mut bootrom2 = [0; BOOTROM_2_END - BOOTROM_2_START];
         let mut bank = 1;
         let mut cgb_bootrom = false;
-        
+        let mut with_bootrom = with_bootrom;
+
         if with_bootrom {
             bank = 0;
 
@@ -64,7 +152,9 @@ impl Cartridge {
                 cgb_bootrom = true;
                 match Cartridge::read_from_file(CGB_BOOTROM_PATH) {
                     Ok(rom_data) => {
-                        assert!(rom_data.len() == BOOTROM_SIZE + 0x100 + BOOTROM_2_END - BOOTROM_2_START);
+                        if rom_data.len() != BOOTROM_SIZE + 0x100 + BOOTROM_2_END - BOOTROM_2_START {
+                            return Err(CartridgeError::InvalidBootrom(CGB_BOOTROM_PATH.to_string(), rom_data.len()));
+                        }
                         for i in 0..BOOTROM_SIZE {
                             bootrom[i] = rom_data[i];
                         }
@@ -74,54 +164,87 @@ impl Cartridge {
                         }
                     }
                     Err(err) => {
-                        eprintln!("Error reading bootrom file from {}: {}", CGB_BOOTROM_PATH, err);
+                        eprintln!("Error reading bootrom file from {}: {} (booting without it)", CGB_BOOTROM_PATH, err);
+                        with_bootrom = false;
                     }
                 }
             } else {
                 match Cartridge::read_from_file(DMG_BOOTROM_PATH) {
                     Ok(rom_data) => {
-                        assert!(rom_data.len() == BOOTROM_SIZE);
+                        if rom_data.len() != BOOTROM_SIZE {
+                            return Err(CartridgeError::InvalidBootrom(DMG_BOOTROM_PATH.to_string(), rom_data.len()));
+                        }
                         for i in 0..BOOTROM_SIZE {
                             bootrom[i] = rom_data[i];
                         }
                     }
                     Err(err) => {
-                        eprintln!("Error reading bootrom file from {}: {}", DMG_BOOTROM_PATH, err);
+                        eprintln!("Error reading bootrom file from {}: {} (booting without it)", DMG_BOOTROM_PATH, err);
+                        with_bootrom = false;
                     }
                 }
             }
         }
 
-        let rom_bytes =  match Cartridge::read_from_file(rom_path) {
-            Ok(rom) => rom,
-            Err(e) => panic!("{}", e),
-        };
-        let mbc = mbc::make_mbc(&rom_bytes, &header);
+        // without a boot ROM to run, the cartridge starts mapped in and the boot is emulated
+        if !with_bootrom {
+            bank = 1;
+            cgb_bootrom = false;
+        }
+
+        let mbc = mbc::make_mbc(&rom_bytes, &header, save_dir, Rc::new(WallClock))?;
         println!("Detected MBC: {}", mbc.display());
 
-        Cartridge { 
+        Ok(Cartridge { 
             bootrom,
             bootrom2,
             cgb_bootrom,
             bank,
             header,
             with_bootrom,
+            rom_hash: battery::crc32(&rom_bytes),
+            rom: rom_bytes,
+            save_dir: save_dir.to_string(),
+            clock: Rc::new(WallClock),
             mbc,
+        })
+    }
+
+    /// (DMG ONLY) Returns the palette the CGB boot ROM would colorize this game with,
+    /// which it only does for Nintendo's games (see palette::title_palette).
+    pub fn title_palette(&self) -> Option<DmgPalette> {
+        if !self.header.nintendo_licensed() {
+            return None;
         }
+        palette::title_palette(&self.rom[TITLE_START..TITLE_START + TITLE_BYTES])
     }
 
     pub fn has_bootrom(&self) -> bool {
         self.with_bootrom
     }
 
+    #[allow(dead_code)]
+    pub fn has_cgb_bootrom(&self) -> bool {
+        self.with_bootrom && self.cgb_bootrom
+    }
+
     pub fn cgb_compatible(&self) -> bool {
         self.header.cgb_compatible()
     }
 
+    pub fn sgb_compatible(&self) -> bool {
+        self.header.sgb_compatible()
+    }
+
     pub fn get_title(&self) -> String {
         self.header.title()
     }
 
+    /// CRC-32 of the whole ROM, which tells apart games (or revisions) sharing a title.
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
     /// Writes to BANK register, which unmaps the boot ROM.
     pub fn write_bank(&mut self, byte: u8) {
         self.bank = byte;
@@ -131,6 +254,7 @@ impl Cartridge {
         self.bank
     }
 
+    #[cfg(feature = "std")]
     fn read_from_file(file_path: &str) -> io::Result<Vec<u8>> {
         let mut file = File::open(file_path)?;
 
@@ -154,8 +278,54 @@ impl Cartridge {
         }
     }
 
-    pub fn save_mbc_state(&self) {
-        self.mbc.save_state();
+    /// Saves battery-backed MBC state; returns true if anything was saved.
+    pub fn save_mbc_state(&self) -> Result<bool, String> {
+        self.mbc.save_state()
+    }
+
+    /// Returns true (once) if battery-backed state was written since the last call.
+    pub fn take_ram_dirty(&mut self) -> bool {
+        self.mbc.take_ram_dirty()
+    }
+
+    pub fn rumble_on(&self) -> bool {
+        self.mbc.rumble_on()
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    pub fn step(&mut self, t_cycles: u32) {
+        self.mbc.step(t_cycles);
+    }
+
+    /// Advances the cartridge's clock source by t_cycles T-cycles at single speed (see
+    /// ClockSource::step), so an EmulatedClock counts the time the game has run for.
+    pub fn step_clock(&self, t_cycles: u32) {
+        self.clock.step(t_cycles);
+    }
+
+    pub fn has_clock(&self) -> bool {
+        self.mbc.has_clock()
+    }
+
+    /// Moves the cartridge's clock (if it has one) seconds forward, or back for negative
+    /// seconds; the change is battery-saved like the game setting the clock would be.
+    pub fn adjust_clock(&mut self, seconds: i64) {
+        self.mbc.adjust_clock(seconds);
+    }
+
+    pub fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mbc.set_image_source(source);
+    }
+
+    pub fn has_infrared(&self) -> bool {
+        self.mbc.has_infrared()
+    }
+
+    pub fn connect_infrared(&mut self, ir: Box<dyn IrTransceiver>) {
+        self.mbc.connect_infrared(ir);
     }
 
     pub fn write_rom(&mut self, addr: usize, byte: u8) {
@@ -170,13 +340,157 @@ impl Cartridge {
         self.mbc.write_ram(addr, byte);
     }
 
-    #[cfg(target_arch = "wasm32")]
+    /// Returns a warning if the game writes to RAM (0xA000-0xBFFF) without having any.
+    pub fn check_ram_write(&self) -> Option<&'static str> {
+        let ram_less = self.header.num_ram_banks() == Ok(0) && PLAIN_RAM_TYPES.contains(&self.header.cartridge_type());
+        ram_less.then_some("Wrote to cartridge RAM, but the cartridge has none")
+    }
+
+    /// Returns a warning if writing byte to addr selects a ROM bank past the end of the ROM.
+    pub fn check_rom_write(&self, addr: usize, byte: u8) -> Option<String> {
+        let bank = match (self.header.cartridge_type(), addr) {
+            (0x01..=0x03, 0x2000..=0x3FFF) => byte & 0x1F,
+            (0x0F..=0x13, 0x2000..=0x3FFF) => byte & 0x7F,
+            (0x19..=0x1E, 0x2000..=0x2FFF) => byte,
+            _ => return None,
+        } as usize;
+        let rom_banks = self.header.num_rom_banks().ok()?;
+        (bank >= rom_banks).then(|| format!("Selected ROM bank {:02X}, but the ROM only has {} banks", bank, rom_banks))
+    }
+
+    /// Returns a warning if this game's battery save doesn't match the size of its RAM.
+    pub fn check_save_size(&self) -> Option<String> {
+        save_size_warning(&self.header, &self.save_dir)
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     pub fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
         self.mbc.load_save(data, save_type)
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     pub fn save_id(&self) -> Option<String> {
         self.mbc.save_id()
     }
-}
\ No newline at end of file
+}
+
+// the boot ROMs and ROM can't change, so only the bank register and the MBC are saved
+impl Snapshot for Cartridge {
+    fn save(&self, state: &mut StateWriter) {
+        self.bank.save(state);
+        self.mbc.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.bank.load(state)?;
+        self.mbc.load(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::test_helpers::make_test_rom;
+    use crate::cpu::{Cpu, GBModel};
+    use super::battery::Battery;
+    use super::{Cartridge, CartridgeError};
+
+    fn fix_header_checksum(rom: &mut [u8]) {
+        rom[0x14D] = rom[0x134..=0x14C].iter().fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+    }
+
+    #[test]
+    fn cartridge_error_test() {
+        assert_eq!(Cartridge::from_bytes(&[0; 0x100]).err(), Some(CartridgeError::TooSmall(0x100)));
+
+        let mut rom = make_test_rom(&[]);
+        rom[0x14D] ^= 0xFF;
+        assert!(matches!(Cartridge::from_bytes(&rom), Err(CartridgeError::BadHeaderChecksum { .. })));
+
+        let mut rom = make_test_rom(&[]);
+        rom[0x147] = 0xFF;
+        fix_header_checksum(&mut rom);
+        assert_eq!(Cartridge::from_bytes(&rom).err(), Some(CartridgeError::UnsupportedType(0xFF)));
+
+        let mut rom = make_test_rom(&[]);
+        rom[0x148] = 0x42;
+        fix_header_checksum(&mut rom);
+        assert_eq!(Cartridge::from_bytes(&rom).err(), Some(CartridgeError::InvalidRomSize(0x42)));
+
+        let mut rom = make_test_rom(&[]);
+        rom.extend_from_slice(&[0; 0x4000]);
+        assert_eq!(Cartridge::from_bytes(&rom).err(), Some(CartridgeError::RomSizeMismatch(0xC000)));
+
+        assert!(matches!(Cartridge::from_file("roms/missing.gb", false), Err(CartridgeError::Io(..))));
+    }
+
+    #[test]
+    fn from_file_in_test() {
+        let dir = std::env::temp_dir().join("melon-gb-save-dir-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // MBC1 with 8 KiB of battery-backed RAM
+        let mut rom = make_test_rom(&[]);
+        rom[0x134..0x13C].copy_from_slice(b"SAVEDIR\0");
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        fix_header_checksum(&mut rom);
+        let rom_path = dir.join("game.gb");
+        std::fs::write(&rom_path, &rom).unwrap();
+        let save_dir = dir.join("saves");
+        let save_dir = save_dir.to_str().unwrap();
+
+        let mut cartridge = Cartridge::from_file_in(rom_path.to_str().unwrap(), false, save_dir).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x42);
+        assert!(cartridge.save_mbc_state().unwrap());
+
+        // the save only lands in save_dir, and is loaded from there
+        let saves: Vec<_> = std::fs::read_dir(save_dir).unwrap().flatten().collect();
+        assert_eq!(saves.len(), 1);
+        let mut cartridge = Cartridge::from_file_in(rom_path.to_str().unwrap(), false, save_dir).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        assert_eq!(cartridge.read_ram(0xA000), 0x42);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_save_test() {
+        let dir = std::env::temp_dir().join("melon-gb-truncated-save-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // MBC1 with 8 KiB of battery-backed RAM
+        let mut rom = make_test_rom(&[]);
+        rom[0x134..0x13C].copy_from_slice(b"TRUNCATE");
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        fix_header_checksum(&mut rom);
+        let rom_path = dir.join("game.gb");
+        std::fs::write(&rom_path, &rom).unwrap();
+        let rom_path = rom_path.to_str().unwrap();
+        let save_dir = dir.join("saves");
+        let save_dir = save_dir.to_str().unwrap();
+
+        let mut cartridge = Cartridge::from_file_in(rom_path, false, save_dir).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x42);
+        assert!(cartridge.save_mbc_state().unwrap());
+        let save_path = Battery::save_location(save_dir, &cartridge.header.save_name());
+        let save = std::fs::read(&save_path).unwrap();
+        std::fs::write(&save_path, &save[..0x1000]).unwrap();
+        // as if copied in from elsewhere, without its checksum
+        std::fs::remove_file(format!("{}.crc", save_path)).unwrap();
+
+        // the game starts with fresh RAM, and the save is kept and warned about
+        let mut cartridge = Cartridge::from_file_in(rom_path, false, save_dir).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        assert_eq!(cartridge.read_ram(0xA000), 0x00);
+        assert_eq!(std::fs::read(format!("{}.mismatched", save_path)).unwrap(), &save[..0x1000]);
+        let cpu = Cpu::new(cartridge, GBModel::DMG);
+        assert!(cpu.warnings().iter().any(|warning| warning.message.contains("holds 0 bytes of SRAM but the ROM expects 8192")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,12 +1,19 @@
-#[cfg(not(target_arch = "wasm32"))]
-use std::time::SystemTime;
-
-#[cfg(target_arch = "wasm32")]
-use js_sys::Date;
+use alloc::{format, string::String};
+use crate::savestate::snapshot_fields;
 
 pub const RTC_REGISTERS_SIZE: usize = 5;
 
-# [derive(Clone)]
+/// Size of the RTC footer VBA/BGB append to .sav files: current and latched registers as
+/// u32s, then the time of saving as a u64 (all little-endian).
+pub const RTC_FOOTER_SIZE: usize = 48;
+// older VBA saves store the time of saving as a u32 instead
+const RTC_FOOTER_SIZE_32: usize = 44;
+
+const DAYS_HI: usize = 4;
+const HALT_FLAG: u8 = 0x40;
+const DAY_CARRY_FLAG: u8 = 0x80;
+
+# [derive(Clone, Default)]
 pub struct Rtc {
     out_registers: [u8; RTC_REGISTERS_SIZE],
     rtc_registers: [u8; RTC_REGISTERS_SIZE],
@@ -15,18 +22,19 @@ pub struct Rtc {
 }
 
 impl Rtc {
-    /// Creates a brand new RTC register that counts starting from current time.
-    pub fn new() -> Self {
+    /// Creates a brand new RTC register that counts starting from now (see ClockSource::now).
+    pub fn new(now: u64) -> Self {
         Rtc { 
             out_registers: [0; RTC_REGISTERS_SIZE],
             rtc_registers: [0; RTC_REGISTERS_SIZE],
-            register_time: Rtc::get_current_time(),
+            register_time: now,
             active_register: 0,
         }
     }
 
     /// Unparses current state of registers, along with register_time from save. 
-    /// Updates rtc_registers with the time elapsed since the last save.
+    /// The time elapsed since the last save is counted on the next update.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
     pub fn from_save(save: [u8; RTC_REGISTERS_SIZE + 8]) -> Self {
         let mut registers = [0; RTC_REGISTERS_SIZE];
         let mut time_bytes = [0; 8];
@@ -40,41 +48,83 @@ impl Rtc {
         }
         let register_time = u64::from_be_bytes(time_bytes);
 
-        let mut rtc = Rtc {
+        Rtc {
             out_registers: [0; RTC_REGISTERS_SIZE],
             rtc_registers: registers,
             register_time,
             active_register: 0,
+        }
+    }
+
+    /// Parses a .sav RTC footer (48 or 44 bytes), counting the time elapsed since it was saved
+    /// on the next update; returns None for any other size.
+    pub fn from_footer(footer: &[u8]) -> Option<Self> {
+        if footer.len() != RTC_FOOTER_SIZE && footer.len() != RTC_FOOTER_SIZE_32 {
+            return None;
+        }
+
+        let mut words = footer.chunks_exact(4).map(|word| u32::from_le_bytes(word.try_into().unwrap()));
+        let mut rtc_registers = [0; RTC_REGISTERS_SIZE];
+        let mut out_registers = [0; RTC_REGISTERS_SIZE];
+        for register in rtc_registers.iter_mut().chain(out_registers.iter_mut()) {
+            *register = words.next().unwrap() as u8;
+        }
+        let register_time = match footer.len() {
+            RTC_FOOTER_SIZE => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            _ => words.next().unwrap() as u64,
         };
-        rtc.update_rtc_registers();
-        rtc
+
+        Some(Rtc {
+            out_registers,
+            rtc_registers,
+            register_time,
+            active_register: 0,
+        })
     }
 
-    /// Parses current state of registers, along with register_time to be saved.
-    pub fn to_save(&self) -> [u8; RTC_REGISTERS_SIZE + 8] {
+    /// Returns the RTC as it is at now, e.g. to save or describe it.
+    pub fn at(&self, now: u64) -> Rtc {
         let mut rtc = self.clone();
-        rtc.update_rtc_registers();
-
-        let mut save = [0; RTC_REGISTERS_SIZE + 8];
-        let time_bytes = rtc.register_time.to_be_bytes();
+        rtc.update_rtc_registers(now);
+        rtc
+    }
 
-        for i in 0..(RTC_REGISTERS_SIZE + 8) {
-            save[i] = if i < RTC_REGISTERS_SIZE {
-                rtc.rtc_registers[i]
-            } else {
-                time_bytes[i - RTC_REGISTERS_SIZE]
-            };
+    /// Formats current and latched registers along with the time they were last updated
+    /// at as a .sav RTC footer (see at).
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn to_footer(&self) -> [u8; RTC_FOOTER_SIZE] {
+        let mut footer = [0; RTC_FOOTER_SIZE];
+        let registers = self.rtc_registers.iter().chain(self.out_registers.iter());
+        for (word, &register) in footer.chunks_exact_mut(4).zip(registers) {
+            word.copy_from_slice(&(register as u32).to_le_bytes());
         }
+        footer[40..48].copy_from_slice(&self.register_time.to_le_bytes());
 
-        save
+        footer
+    }
+
+    /// Describes the counting registers, e.g. "day 12 03:04:05 (halted)".
+    pub fn describe(&self) -> String {
+        let mut description = format!("day {} {:02}:{:02}:{:02}", 
+            self.get_days(), self.rtc_registers[2], self.rtc_registers[1], self.rtc_registers[0]);
+        if self.rtc_registers[DAYS_HI] & HALT_FLAG != 0 {
+            description.push_str(" (halted)");
+        }
+        if self.rtc_registers[DAYS_HI] & DAY_CARRY_FLAG != 0 {
+            description.push_str(" (day counter overflowed)");
+        }
+        description
     }
 
     pub fn set_active_reg(&mut self, byte: u8) {
         self.active_register = byte as usize - 8;
     }
 
-    pub fn write(&mut self, byte: u8) {
+    /// Writes to both the latched and the counting register (e.g. games setting the clock).
+    pub fn write(&mut self, byte: u8, now: u64) {
+        self.update_rtc_registers(now);
         self.out_registers[self.active_register] = byte;
+        self.rtc_registers[self.active_register] = byte;
     }
 
     pub fn read(&self) -> u8 {
@@ -82,55 +132,121 @@ impl Rtc {
     }
 
     /// Updates rtc clock data and latches it onto registers.
-    pub fn latch_clock_data(&mut self) {
-        self.update_rtc_registers();
+    pub fn latch_clock_data(&mut self, now: u64) {
+        self.update_rtc_registers(now);
         self.out_registers = self.rtc_registers
     }
 
-    /// Updates current rtc_registers and sets register_time to the current time.
-    fn update_rtc_registers(&mut self) {
-        let current_time = Rtc::get_current_time();
-        let elapsed =  current_time - self.register_time;
-
-        self.rtc_registers[0] = ((self.rtc_registers[0] as u64 +  elapsed      )   % 60) as u8;
-        self.rtc_registers[1] = ((self.rtc_registers[1] as u64 + (elapsed / 60))   % 60) as u8;
-        self.rtc_registers[2] = ((self.rtc_registers[2] as u64 + (elapsed / 3600)) % 24) as u8;
+    /// Moves the counting registers seconds forward (or back, down to day 0 00:00:00),
+    /// halted or not, like a game setting its clock would.
+    pub fn adjust(&mut self, seconds: i64, now: u64) {
+        self.update_rtc_registers(now);
+        self.set_seconds(self.get_seconds().saturating_add_signed(seconds));
+    }
 
-        let extra_days = elapsed / (3600 * 24);
-        let new_days = self.get_days() + extra_days;
+    /// Updates current rtc_registers (unless halted) and sets register_time to now.
+    fn update_rtc_registers(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.register_time);
+        self.register_time = now;
 
-        self.rtc_registers[3] = (new_days & 0xFF) as u8;
-        self.rtc_registers[4] = self.rtc_registers[4] & 0xFE;
-        if new_days >= 256 && new_days <= 511 {
-            self.rtc_registers[4] |= 0x01;
-        } else if new_days > 511 {
-            self.rtc_registers[4] |= 0x80;
+        if self.rtc_registers[DAYS_HI] & HALT_FLAG != 0 {
+            return;
         }
+        self.set_seconds(self.get_seconds() + elapsed);
+    }
 
-        self.register_time = current_time;
+    fn get_seconds(&self) -> u64 {
+        self.rtc_registers[0] as u64 
+            + self.rtc_registers[1] as u64 * 60 
+            + self.rtc_registers[2] as u64 * 3600 
+            + self.get_days() * 3600 * 24
+    }
+
+    /// Sets the counting registers to seconds, flagging a day counter overflow past 511 days.
+    fn set_seconds(&mut self, seconds: u64) {
+        let days = seconds / (3600 * 24);
+
+        self.rtc_registers[0] = (seconds % 60) as u8;
+        self.rtc_registers[1] = ((seconds / 60) % 60) as u8;
+        self.rtc_registers[2] = ((seconds / 3600) % 24) as u8;
+        self.rtc_registers[3] = (days & 0xFF) as u8;
+        self.rtc_registers[DAYS_HI] = (self.rtc_registers[DAYS_HI] & 0xFE) | ((days >> 8) & 0x01) as u8;
+        if days > 511 {
+            self.rtc_registers[DAYS_HI] |= DAY_CARRY_FLAG;
+        }
     }
 
     fn get_days(&self) -> u64 {
         let days_lo = self.rtc_registers[3] as u64;
-        let days_hi = self.rtc_registers[4] as u64 & 0x01;
+        let days_hi = self.rtc_registers[DAYS_HI] as u64 & 0x01;
         (days_hi << 8) | days_lo
     }
+}
+
+snapshot_fields!(Rtc, out_registers, rtc_registers, register_time, active_register);
+
+#[cfg(test)]
+mod tests {
+    use super::{Rtc, RTC_FOOTER_SIZE};
+
+    const NOW: u64 = 1_700_000_000;
 
-    /// Gets the current time represented as seconds elapsed since UNIX_EPOCH.
-    #[cfg(not(target_arch = "wasm32"))]
-    pub fn get_current_time() -> u64 {
-        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(duration) => duration.as_secs(),
-            Err(_) => {
-                println!("Unable to get currrent system time");
-                0
-            }
+    fn make_footer(registers: [u8; 5], saved_at: u64) -> [u8; RTC_FOOTER_SIZE] {
+        let mut footer = [0; RTC_FOOTER_SIZE];
+        for (i, &register) in registers.iter().chain(registers.iter()).enumerate() {
+            footer[i * 4] = register;
         }
+        footer[40..48].copy_from_slice(&saved_at.to_le_bytes());
+        footer
     }
 
-    #[cfg(target_arch = "wasm32")]
-    pub fn get_current_time() -> u64 {
-        let date = Date::new_0();
-        (date.get_time() / 1000.0) as u64
+    #[test]
+    fn rtc_footer_test() {
+        // saved at 23:59:00 on day 511 one day, one hour, one minute and one second ago
+        let saved_at = NOW - (24 * 3600 + 3661);
+        let mut rtc = Rtc::from_footer(&make_footer([0, 59, 23, 0xFF, 0x01], saved_at)).unwrap();
+
+        // latched registers stay as saved until the next latch
+        rtc.set_active_reg(0x09);
+        assert_eq!(rtc.read(), 59);
+        rtc.latch_clock_data(NOW);
+
+        let footer = rtc.at(NOW).to_footer();
+        assert_eq!((footer[0], footer[4], footer[8], footer[12], footer[16]), (1, 0, 1, 1, 0x80));
+        assert_eq!(&footer[20..40], &footer[0..20]);
+
+        // a halted clock doesn't advance while closed
+        let halted = Rtc::from_footer(&make_footer([5, 4, 3, 2, 0x40], saved_at)).unwrap().at(NOW).to_footer();
+        assert_eq!((halted[0], halted[4], halted[8], halted[12], halted[16]), (5, 4, 3, 2, 0x40));
+
+        // older saves use a 32 bit timestamp
+        assert!(Rtc::from_footer(&make_footer([0; 5], saved_at)[..44]).is_some());
+        assert!(Rtc::from_footer(&[0; 13]).is_none());
+    }
+
+    fn read_latched(rtc: &mut Rtc) -> [u8; 5] {
+        let mut registers = [0; 5];
+        for (i, register) in registers.iter_mut().enumerate() {
+            rtc.set_active_reg(0x08 + i as u8);
+            *register = rtc.read();
+        }
+        registers
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rtc_adjust_test() {
+        // 30 seconds in, wind the clock forward two days and an hour
+        let mut rtc = Rtc::new(NOW);
+        rtc.adjust(2 * 24 * 3600 + 3600, NOW + 30);
+        rtc.latch_clock_data(NOW + 30);
+        assert_eq!(read_latched(&mut rtc), [30, 0, 1, 2, 0]);
+
+        // it keeps counting from there, and can't go back past day 0
+        rtc.adjust(-3600, NOW + 90);
+        rtc.latch_clock_data(NOW + 90);
+        assert_eq!(read_latched(&mut rtc), [30, 1, 0, 2, 0]);
+        rtc.adjust(-3 * 24 * 3600, NOW + 90);
+        rtc.latch_clock_data(NOW + 90);
+        assert_eq!(read_latched(&mut rtc), [0; 5]);
+    }
+}
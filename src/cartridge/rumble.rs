@@ -0,0 +1,97 @@
+use crate::config::{DEFAULT_RUMBLE_STRENGTH, RUMBLE_SMOOTHING, RUMBLE_STRENGTHS, RUMBLE_WINDOW_MS};
+use crate::constants::T_CYCLE_HZ;
+
+/// Turns the rumble motor's raw on/off toggling into a smooth intensity: the motor's duty cycle
+/// is averaged over fixed windows, eased between windows and scaled by the game's rumble strength.
+pub struct RumbleShaper {
+    strength: f32,
+    window_t_cycles: u32,
+    elapsed_t_cycles: u32,
+    on_t_cycles: u32,
+    level: f32,
+    intensity: u8,
+}
+
+impl RumbleShaper {
+    /// Creates a shaper using the configured strength for the game with the given title.
+    pub fn new(title: &str) -> Self {
+        let strength = RUMBLE_STRENGTHS.iter()
+            .find(|(game, _)| *game == title)
+            .map_or(DEFAULT_RUMBLE_STRENGTH, |(_, strength)| *strength);
+
+        RumbleShaper {
+            strength,
+            window_t_cycles: (RUMBLE_WINDOW_MS * T_CYCLE_HZ as u64 / 1000) as u32,
+            elapsed_t_cycles: 0,
+            on_t_cycles: 0,
+            level: 0.0,
+            intensity: 0,
+        }
+    }
+
+    /// Sets how strongly the motor's duty cycle maps to intensity (clamped to 0.0-1.0).
+    pub fn set_strength(&mut self, strength: f32) {
+        self.strength = strength.clamp(0.0, 1.0);
+    }
+
+    /// Records t_cycles (at normal speed) with the motor in the given state;
+    /// returns the new intensity (0 to 255) when it changes at the end of a window.
+    pub fn step(&mut self, t_cycles: u32, motor_on: bool) -> Option<u8> {
+        self.elapsed_t_cycles += t_cycles;
+        if motor_on {
+            self.on_t_cycles += t_cycles;
+        }
+
+        if self.elapsed_t_cycles < self.window_t_cycles {
+            return None;
+        }
+
+        let duty = self.on_t_cycles as f32 / self.elapsed_t_cycles as f32;
+        self.level += (duty - self.level) * RUMBLE_SMOOTHING;
+        self.elapsed_t_cycles = 0;
+        self.on_t_cycles = 0;
+
+        let intensity = libm::roundf(self.level * self.strength * 255.0) as u8;
+        if intensity == self.intensity {
+            return None;
+        }
+        self.intensity = intensity;
+        Some(intensity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RumbleShaper;
+
+    fn run_windows(rumble: &mut RumbleShaper, windows: u32, duty_eighths: u32) -> Option<u8> {
+        let step = rumble.window_t_cycles.div_ceil(8);
+        let mut last = None;
+        for _ in 0..windows {
+            for i in 0..8 {
+                if let Some(intensity) = rumble.step(step, i < duty_eighths) {
+                    last = Some(intensity);
+                }
+            }
+        }
+        last
+    }
+
+    #[test]
+    fn rumble_shaping_test() {
+        let mut rumble = RumbleShaper::new("");
+        rumble.set_strength(1.0);
+
+        // a motor held on eases towards full intensity instead of jumping
+        let first = run_windows(&mut rumble, 1, 8).unwrap();
+        assert!(first > 0 && first < 255);
+        assert_eq!(run_windows(&mut rumble, 30, 8), Some(255));
+
+        // constant intensity reports nothing new
+        assert_eq!(run_windows(&mut rumble, 5, 8), None);
+
+        rumble.set_strength(0.5);
+        assert_eq!(run_windows(&mut rumble, 1, 8), Some(128));
+        assert_eq!(run_windows(&mut rumble, 30, 0), Some(0));
+    }
+}
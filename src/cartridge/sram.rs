@@ -0,0 +1,133 @@
+use alloc::{vec, vec::Vec};
+use core::ops::{Deref, DerefMut};
+
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use {
+    memmap2::{MmapMut, MmapOptions},
+    std::fs::OpenOptions,
+    std::io,
+};
+
+use super::mbc::RAM_BANK_SIZE;
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+/// Cartridge RAM banks; kept in memory, or (on native builds with config::MMAP_SRAM)
+/// mapped onto the save file so every write reaches it without saving explicitly.
+pub struct Sram {
+    storage: Storage,
+}
+
+enum Storage {
+    Memory(Vec<[u8; RAM_BANK_SIZE]>),
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    Mapped(MmapMut),
+}
+
+impl Sram {
+    /// Creates ram_banks zeroed banks in memory.
+    pub fn new(ram_banks: usize) -> Self {
+        Sram::from_banks(vec![[0; RAM_BANK_SIZE]; ram_banks])
+    }
+
+    pub fn from_banks(banks: Vec<[u8; RAM_BANK_SIZE]>) -> Self {
+        Sram { storage: Storage::Memory(banks) }
+    }
+
+    /// Maps ram_banks banks onto the start of the file at path, creating or growing it as needed
+    /// (anything after them, like an RTC footer, is left alone).
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    pub fn map_file(path: &str, ram_banks: usize) -> io::Result<Self> {
+        let size = ram_banks * RAM_BANK_SIZE;
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        if file.metadata()?.len() < size as u64 {
+            file.set_len(size as u64)?;
+        }
+
+        // SAFETY: the save file is only meant to be changed through this mapping while it's open
+        let mmap = unsafe { MmapOptions::new().len(size).map_mut(&file)? };
+        Ok(Sram { storage: Storage::Mapped(mmap) })
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn is_mapped(&self) -> bool {
+        !matches!(self.storage, Storage::Memory(_))
+    }
+
+    /// Makes sure writes to a mapped file have reached the disk (does nothing for in-memory RAM).
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    pub fn flush(&self) -> io::Result<()> {
+        match &self.storage {
+            Storage::Memory(_) => Ok(()),
+            Storage::Mapped(mmap) => mmap.flush(),
+        }
+    }
+}
+
+impl Deref for Sram {
+    type Target = [[u8; RAM_BANK_SIZE]];
+
+    fn deref(&self) -> &Self::Target {
+        match &self.storage {
+            Storage::Memory(banks) => banks,
+            #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+            Storage::Mapped(mmap) => mmap.as_chunks().0,
+        }
+    }
+}
+
+impl DerefMut for Sram {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut self.storage {
+            Storage::Memory(banks) => banks,
+            #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+            Storage::Mapped(mmap) => mmap.as_chunks_mut().0,
+        }
+    }
+}
+
+// an empty Sram, for save states to load into (which fails unless the state has no RAM either)
+impl Default for Sram {
+    fn default() -> Self {
+        Sram::new(0)
+    }
+}
+
+// banks are saved whether they're in memory or mapped onto the save file, and have to number the same
+impl Snapshot for Sram {
+    fn save(&self, state: &mut StateWriter) {
+        (**self).save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        (**self).load(state)
+    }
+}
+
+#[cfg(all(test, feature = "std", not(target_arch = "wasm32")))]
+mod tests {
+    use std::fs::{read, remove_file};
+
+    use super::{Sram, RAM_BANK_SIZE};
+
+    #[test]
+    fn sram_mapped_test() {
+        let path = std::env::temp_dir().join("melon-gb-sram-test");
+        let path = path.to_str().unwrap();
+        let _ = remove_file(path);
+
+        let mut sram = Sram::map_file(path, 2).unwrap();
+        assert!(sram.is_mapped());
+        assert_eq!(sram.len(), 2);
+        sram[1][0x10] = 0x42;
+        sram.flush().unwrap();
+
+        // writes reach the file without saving
+        let data = read(path).unwrap();
+        assert_eq!(data.len(), 2 * RAM_BANK_SIZE);
+        assert_eq!(data[RAM_BANK_SIZE + 0x10], 0x42);
+
+        drop(sram);
+        assert_eq!(Sram::map_file(path, 2).unwrap()[1][0x10], 0x42);
+        remove_file(path).unwrap();
+    }
+}
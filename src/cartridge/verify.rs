@@ -0,0 +1,136 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use super::battery::{crc32, split_checksum, Battery, SAVE_PATH};
+use super::error::CartridgeError;
+use super::header::Header;
+use super::mbc::{save_layout, RAM_BANK_SIZE};
+use super::rtc::Rtc;
+use super::clock::{ClockSource, WallClock};
+
+/// Result of checking a battery save against the ROM it's meant for.
+pub struct SaveCheck {
+    /// What the ROM expects and what the save holds, one fact per line.
+    pub report: Vec<String>,
+    /// Why the save won't load (empty if it will).
+    pub problems: Vec<String>,
+    /// Where the emulator looks for this ROM's battery save.
+    pub expected_location: String,
+}
+
+/// Checks whether save (a battery save, e.g. a .sav from another emulator) would load for the ROM
+/// in rom_bytes from the default save directory, and describes what it contains. Save states
+/// aren't supported yet, so every file is treated as a battery save.
+pub fn verify_save(rom_bytes: &[u8], save: &[u8]) -> Result<SaveCheck, CartridgeError> {
+    let header = Header::from_bytes(rom_bytes)?;
+    let mut report = vec![format!("ROM: {} (cartridge type {:02X}, {} ROM banks, {} RAM banks)",
+        header.title(), header.cartridge_type(), header.num_rom_banks()?, header.num_ram_banks()?)];
+    let mut problems = Vec::new();
+    let expected_location = Battery::save_location(SAVE_PATH, &header.save_name());
+
+    let layout = match save_layout(&header)? {
+        Some(layout) => layout,
+        None => {
+            problems.push(String::from("This cartridge type has no battery, so it never loads saves"));
+            return Ok(SaveCheck { report, problems, expected_location });
+        }
+    };
+
+    let ram_size = layout.ram_banks * RAM_BANK_SIZE;
+    let mut expected = format!("Expected save: {} bytes of SRAM", ram_size);
+    if layout.rtc_footer {
+        expected.push_str(" + RTC footer");
+    }
+    report.push(expected);
+    report.push(format!("Save file: {} bytes", save.len()));
+
+    let mut save = save.to_vec();
+    match split_checksum(&mut save) {
+        Some(expected) if expected == crc32(&save) => report.push(String::from("Checksum trailer (from an older version): matches")),
+        Some(_) => problems.push(String::from("Save doesn't match its checksum trailer (it's corrupted), so it won't load")),
+        None => {}
+    }
+
+    // RAM is loaded in whole banks, anything after them can only be an RTC footer
+    let save_ram_size = save.len() - save.len() % RAM_BANK_SIZE;
+    if save_ram_size != ram_size {
+        problems.push(format!("Save holds {} bytes of SRAM but this ROM expects {}", save_ram_size, ram_size));
+    }
+
+    let trailing = &save[save_ram_size..];
+    match Rtc::from_footer(trailing) {
+        Some(rtc) if layout.rtc_footer =>
+            report.push(format!("RTC footer: {} bytes, clock now reads {}", trailing.len(), rtc.at(WallClock.now()).describe())),
+        Some(_) => report.push(String::from("RTC footer: present but ignored, this cartridge has no MBC3 clock")),
+        None if !trailing.is_empty() => report.push(format!("{} unrecognized bytes after SRAM are ignored", trailing.len())),
+        None if layout.rtc_footer => report.push(String::from("RTC footer: missing, the clock starts from zero")),
+        None => {}
+    }
+
+    if layout.separate_clock {
+        report.push(String::from("This cartridge's clock is kept in a separate rtc file next to the save"));
+    }
+
+    Ok(SaveCheck { report, problems, expected_location })
+}
+
+/// Returns a warning if header's game has a battery save in save_dir that won't fit its SRAM.
+#[cfg(feature = "std")]
+pub fn save_size_warning(header: &Header, save_dir: &str) -> Option<String> {
+    let layout = save_layout(header).ok()??;
+    let location = Battery::save_location(save_dir, &header.save_name());
+    let save_size = std::fs::metadata(&location).ok()?.len() as usize;
+
+    let ram_size = layout.ram_banks * RAM_BANK_SIZE;
+    let save_ram_size = save_size - save_size % RAM_BANK_SIZE;
+    (save_ram_size != ram_size).then(|| format!(
+        "Save {} holds {} bytes of SRAM but the ROM expects {} (see `verify`)", location, save_ram_size, ram_size))
+}
+
+/// Without std there are no save files to check.
+#[cfg(not(feature = "std"))]
+pub fn save_size_warning(_header: &Header, _save_dir: &str) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::test_helpers::make_test_rom;
+    use super::verify_save;
+    use crate::cartridge::battery::crc32;
+
+    fn make_rom(cartridge_type: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = make_test_rom(&[]);
+        rom[0x147] = cartridge_type;
+        rom[0x149] = ram_size;
+        rom[0x14D] = rom[0x134..=0x14C].iter().fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+        rom
+    }
+
+    #[test]
+    fn verify_save_test() {
+        // MBC3+TIMER+RAM+BATTERY with 4 RAM banks
+        let rom = make_rom(0x10, 0x03);
+
+        let check = verify_save(&rom, &vec![0; 0x8000 + 48]).unwrap();
+        assert!(check.problems.is_empty());
+        assert!(check.report.iter().any(|line| line.starts_with("RTC footer: 48 bytes")));
+        assert!(check.expected_location.ends_with("/ram"));
+
+        let check = verify_save(&rom, &vec![0; 0x2000]).unwrap();
+        assert_eq!(check.problems.len(), 1);
+        assert!(check.report.iter().any(|line| line.starts_with("RTC footer: missing")));
+
+        // saves written by older versions of this emulator end with a checksum trailer
+        let mut save = vec![0; 0x8000 + 48];
+        save.extend(crc32(&save).to_le_bytes());
+        save.extend(b"MGCK");
+        let check = verify_save(&rom, &save).unwrap();
+        assert!(check.problems.is_empty());
+        assert!(check.report.iter().any(|line| line.starts_with("RTC footer: 48 bytes")));
+        save[0] ^= 0x01;
+        assert_eq!(verify_save(&rom, &save).unwrap().problems.len(), 1);
+
+        // ROM only
+        let check = verify_save(&make_rom(0x00, 0x00), &[0; 0x2000]).unwrap();
+        assert_eq!(check.problems.len(), 1);
+    }
+}
@@ -0,0 +1,182 @@
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
+use core::str::FromStr;
+
+// Game Genie codes hide the address's top digit (XORed with this) and the byte they replace
+const GAME_GENIE_ADDR_XOR: u16 = 0xF000;
+const GAME_GENIE_COMPARE_XOR: u8 = 0xBA;
+
+// GameShark code types: a write to wherever the address is mapped, or (CGB) to a WRAM
+// bank's D000-DFFF regardless of which one is mapped
+const GAMESHARK_MAPPED: u8 = 0x01;
+const GAMESHARK_WRAM_BANK: u8 = 0x90;
+
+/// What a cheat code does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatCode {
+    /// GameShark (`01VVAAAA`, or `9BVVAAAA` for WRAM bank B): writes value to addr every
+    /// frame, in WRAM bank `bank` if it's set.
+    GameShark { bank: Option<usize>, addr: u16, value: u8 },
+    /// Game Genie (`VVA-AAA` or `VVA-AAA-CxC`): makes the cartridge's ROM read value at addr,
+    /// only while the ROM holds compare there if it's set (for games that switch banks).
+    GameGenie { addr: u16, value: u8, compare: Option<u8> },
+}
+
+/// Parses a GameShark code (8 hex digits) or a Game Genie code (6 or 9 hex digits,
+/// optionally grouped in threes by dashes).
+impl FromStr for CheatCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim();
+        let hex: String = code.chars().filter(|&c| c != '-').collect();
+        let digits: Vec<u8> = hex.chars()
+            .map(|c| c.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("Cheat code '{}' should only have hex digits (and dashes)", code))?;
+        let byte = |i: usize| digits[i] << 4 | digits[i + 1];
+
+        match digits.len() {
+            8 if !code.contains('-') => {
+                let bank = match byte(0) {
+                    GAMESHARK_MAPPED => None,
+                    kind @ GAMESHARK_WRAM_BANK..=0x97 => Some(((kind & 0x7) as usize).max(1)),
+                    kind => return Err(format!("Unsupported GameShark code type {:02X} in '{}'", kind, code)),
+                };
+                let addr = u16::from_le_bytes([byte(4), byte(6)]);
+                Ok(CheatCode::GameShark { bank, addr, value: byte(2) })
+            },
+            6 | 9 => {
+                let addr = ((digits[5] as u16) << 12 | (digits[2] as u16) << 8 | (digits[3] as u16) << 4 | digits[4] as u16)
+                    ^ GAME_GENIE_ADDR_XOR;
+                if addr >= 0x8000 {
+                    return Err(format!("Game Genie code '{}' patches {:04X}, outside of the ROM", code, addr));
+                }
+                // the middle digit of the last group isn't used
+                let compare = (digits.len() == 9)
+                    .then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ GAME_GENIE_COMPARE_XOR);
+                Ok(CheatCode::GameGenie { addr, value: byte(0), compare })
+            },
+            _ => Err(format!("Cheat code '{}' isn't a GameShark (8 digits) or Game Genie (6 or 9 digits) code", code)),
+        }
+    }
+}
+
+/// A cheat code added to a game, which can be turned off without removing it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    /// The code as it was entered (trimmed and upper case).
+    pub code: String,
+    pub kind: CheatCode,
+    pub enabled: bool,
+}
+
+/// The cheat codes applied to a game: GameShark codes poke RAM at every VBlank, and Game Genie
+/// codes patch what's read from the cartridge's ROM.
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats::default()
+    }
+
+    /// Adds code (turned on) and returns its index, or an error if it can't be parsed.
+    pub fn add(&mut self, code: &str) -> Result<usize, String> {
+        let kind = code.parse()?;
+        self.cheats.push(Cheat { code: code.trim().to_ascii_uppercase(), kind, enabled: true });
+        Ok(self.cheats.len() - 1)
+    }
+
+    /// Removes the cheat at index (the ones after it move down one).
+    #[allow(dead_code)]
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        (index < self.cheats.len()).then(|| self.cheats.remove(index))
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(cheat) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+    }
+
+    /// Returns every cheat, in the order they were added.
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    /// Returns what reading byte from the ROM at addr gives with the Game Genie codes on.
+    pub fn patch_rom(&self, addr: u16, byte: u8) -> u8 {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            if let CheatCode::GameGenie { addr: patched, value, compare } = cheat.kind {
+                if patched == addr && compare.is_none_or(|compare| compare == byte) {
+                    return value;
+                }
+            }
+        }
+        byte
+    }
+
+    /// Returns the writes the GameShark codes that are on make every VBlank:
+    /// (WRAM bank if any, address, value).
+    pub fn ram_writes(&self) -> Vec<(Option<usize>, u16, u8)> {
+        self.cheats.iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match cheat.kind {
+                CheatCode::GameShark { bank, addr, value } => Some((bank, addr, value)),
+                CheatCode::GameGenie { .. } => None,
+            })
+            .collect()
+    }
+}
+
+/// Describes what the code does, e.g. "ROM 4A2B = 3C where it was 12".
+impl fmt::Display for CheatCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatCode::GameShark { bank: Some(bank), addr, value } => write!(f, "WRAM bank {} {:04X} = {:02X} every frame", bank, addr, value),
+            CheatCode::GameShark { bank: None, addr, value } => write!(f, "{:04X} = {:02X} every frame", addr, value),
+            CheatCode::GameGenie { addr, value, compare: Some(compare) } => write!(f, "ROM {:04X} = {:02X} where it was {:02X}", addr, value, compare),
+            CheatCode::GameGenie { addr, value, compare: None } => write!(f, "ROM {:04X} = {:02X}", addr, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CheatCode, Cheats};
+
+    #[test]
+    fn parse_test() {
+        assert_eq!("010238CD".parse(), Ok(CheatCode::GameShark { bank: None, addr: 0xCD38, value: 0x02 }));
+        assert_eq!("93FF21D1".parse(), Ok(CheatCode::GameShark { bank: Some(3), addr: 0xD121, value: 0xFF }));
+        assert_eq!("3ca-2bb-a02".parse(), Ok(CheatCode::GameGenie { addr: 0x4A2B, value: 0x3C, compare: Some(0x12) }));
+        assert_eq!("3CA2BB".parse(), Ok(CheatCode::GameGenie { addr: 0x4A2B, value: 0x3C, compare: None }));
+
+        assert!("3CA-2B7".parse::<CheatCode>().unwrap_err().contains("outside of the ROM"));
+        assert!("020238CD".parse::<CheatCode>().unwrap_err().contains("type 02"));
+        assert!("01-0238CD".parse::<CheatCode>().is_err());
+        assert!("XYZ-123".parse::<CheatCode>().is_err());
+    }
+
+    #[test]
+    fn apply_test() {
+        let mut cheats = Cheats::new();
+        cheats.add("3CA-2BB-A02").unwrap();
+        let shark = cheats.add(" 010238cd ").unwrap();
+        assert_eq!(cheats.list()[shark].code, "010238CD");
+
+        // Game Genie codes only patch their address while it holds what they expect
+        assert_eq!(cheats.patch_rom(0x4A2B, 0x12), 0x3C);
+        assert_eq!(cheats.patch_rom(0x4A2B, 0x13), 0x13);
+        assert_eq!(cheats.patch_rom(0x4A2C, 0x12), 0x12);
+        assert_eq!(cheats.ram_writes(), vec![(None, 0xCD38, 0x02)]);
+
+        cheats.set_enabled(0, false);
+        assert_eq!(cheats.patch_rom(0x4A2B, 0x12), 0x12);
+        cheats.remove(shark);
+        assert!(cheats.ram_writes().is_empty());
+    }
+}
@@ -1,21 +1,108 @@
-use crate::constants::BYTES_PER_PIXEL;
-
-// (CGB ONLY) set to true for display colours to be more 
-// realistic to actual hardware
-pub const WITH_COLOUR_CORRECTION: bool = true;
-
-///(DMG ONLY)
-pub const COLOURS: [[u8; BYTES_PER_PIXEL]; 4] = [
-    [0xE8, 0xFF, 0xFF, 0xFF], // => white
-    [0x74, 0xD4, 0x9B, 0xFF], // => light grey
-    [0x80, 0x9A, 0x30, 0xFF], // => dark grey
-    [0x4F, 0x3D, 0x1A, 0xFF], // => black
-];
+use crate::apu::ResampleQuality;
+use crate::bus::OpenBus;
+use crate::palette::ColourCorrection;
+use crate::ppu::LcdOffPolicy;
+
+// how much of the previous frame (out of 255) lingers in the next, like the slow LCDs of
+// the DMG and CGB; games such as Chikyuu Kaihou Gun ZAS flicker sprites to make them
+// look transparent through it (0 turns it off; doesn't apply to SGB)
+pub const LCD_GHOSTING: u8 = 0;
+
+// what the screen shows while a game turns the LCD off: Blank (like hardware), Hold (the
+// last frame stays up) or Fade (to blank; see LcdOffPolicy)
+pub const LCD_OFF_POLICY: LcdOffPolicy = LcdOffPolicy::Blank;
+
+// (DMG/SGB ONLY) emulates the OAM corruption bug: 16-bit INC/DEC of a value in FE00-FEFF
+// while the PPU scans OAM garbles the objects it's reading, like on hardware (some test ROMs
+// check for it, and a few games show glitched sprites because of it)
+pub const OAM_BUG: bool = true;
+
+// (CGB ONLY) how CGB colours are converted for display: Simple or Balanced look
+// closer to the real LCD than Off (see ColourCorrection)
+pub const COLOUR_CORRECTION: ColourCorrection = ColourCorrection::Simple;
+
+// (DMG ONLY, native only) runs games with Super Game Boy features on an SGB, showing
+// their border and palettes (frames are then 256x224)
+#[allow(dead_code)]
+pub const SGB_ENHANCED: bool = true;
 
+// (CGB ONLY) runs CGB games on a Game Boy Advance instead, which they can tell apart by
+// the B register after boot (some unlock bonus content, e.g. Shantae)
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub const AGB_MODE: bool = false;
+
+// (DMG ONLY) palette for DMG games that the CGB boot ROM wouldn't colorize: a preset's name
+// (see palette::PRESETS) or colours (see DmgPalette's FromStr)
+pub const DMG_PALETTE: &str = "melon";
+
+// (DMG/SGB ONLY) when starting without a boot ROM, scrolls the logo in and plays the
+// boot sound like the DMG boot ROM does (about 4s) before the game starts
+pub const BOOT_ANIMATION: bool = false;
+
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 pub const DMG_BOOTROM_PATH: &str = "bootroms/bootrom.gb";
 
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 pub const CGB_BOOTROM_PATH: &str = "bootroms/bootrom.gbc";
 
+// the sample rate audio is produced at unless the frontend asks for another (see Cpu::set_audio_rate)
 pub const SAMPLING_RATE_HZ: u32 = 48000;
 
+// how audio is brought down to that rate: BandLimited filters out the aliasing that
+// Linear lets through, for a little more work (see ResampleQuality)
+pub const RESAMPLE_QUALITY: ResampleQuality = ResampleQuality::BandLimited;
+
+// runs audio through the high-pass filter formed by the capacitors on the hardware's outputs,
+// so it sits around silence like recordings of a real Game Boy instead of jumping when
+// channels turn on and off (off gives the mixer's raw output, e.g. for analysing it)
+pub const HIGH_PASS: bool = true;
+
 pub const AUDIO_SAMPLES: usize = 2048;
+
+// (native only) unless frames are synced to audio (see --sync), the audio queue is kept near
+// AUDIO_LATENCY_MS by nudging the sample rate (by at most RATE_CONTROL_DELTA, too little
+// to hear), so long sessions neither run out of audio nor build up latency
+#[allow(dead_code)]
+pub const AUDIO_LATENCY_MS: u32 = 100;
+#[allow(dead_code)]
+pub const RATE_CONTROL_DELTA: f64 = 0.005;
+
+// what reads from unmapped addresses return; OpenBus::LastValue is closest
+// to hardware, the others help test homebrew against different assumptions
+pub const OPEN_BUS: OpenBus = OpenBus::High;
+
+// seed for everything random in the core (open bus noise, initial RAM), so runs
+// with the same seed are reproducible; Cpu::with_seed() overrides it
+pub const RNG_SEED: u64 = 0;
+
+// fills WRAM and HRAM with random bytes at power on (like real hardware) instead of zeroes
+pub const RANDOM_INITIAL_RAM: bool = false;
+
+// (native only) maps cartridge RAM onto its save file so writes persist right away, 
+// instead of only when the game is saved
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub const MMAP_SRAM: bool = false;
+
+// models flashcarts whose SRAM can't keep up with the CPU: every CPU access to cartridge RAM
+// (A000-BFFF) stalls it for SRAM_WAIT_CYCLES extra M-cycles; cartridges have no wait states,
+// so this starts off unless SRAM_WAIT is set (toggled with SRAM_WAIT_HOTKEY while running)
+pub const SRAM_WAIT: bool = false;
+pub const SRAM_WAIT_CYCLES: u32 = 1;
+
+// counts CPU reads/writes per memory region (see bus_stats.rs); costs a little speed
+pub const BUS_STATS: bool = false;
+
+// emulated milliseconds without a VBlank (while the LCD is on) before a game
+// is reported as not responding (see cpu/watchdog.rs); None disables the watchdog
+pub const WATCHDOG_MS: Option<u64> = Some(2000);
+
+// the rumble motor's on/off duty cycle is averaged over windows of this many
+// ms, then eased between windows (1.0 jumps straight to the new level)
+pub const RUMBLE_WINDOW_MS: u64 = 16;
+pub const RUMBLE_SMOOTHING: f32 = 0.5;
+
+// rumble strength (0.0 to 1.0) by game title; other games use the default
+pub const DEFAULT_RUMBLE_STRENGTH: f32 = 1.0;
+pub const RUMBLE_STRENGTHS: [(&str, f32); 1] = [
+    ("POKEMON PINBALL", 0.5),
+];
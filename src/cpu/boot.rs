@@ -0,0 +1,293 @@
+use crate::bus::Bus;
+use crate::savestate::snapshot_fields;
+
+use super::GBModel;
+
+const LOGO_START: u16 = 0x0104;
+const LOGO_BYTES: u16 = 0x30;
+// the boot ROM draws the logo from tile 1 on, followed by the ® tile
+const LOGO_TILES_START: u16 = 0x8010;
+const REGISTERED_TILE: u8 = 0x19;
+const REGISTERED_TILE_ROWS: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+const REGISTERED_TILE_MAP_ADDR: u16 = 0x9910;
+// the logo takes up 12 tiles on each of two rows ending at these tile map addresses
+const LOGO_TILE_MAP_ROWS_END: [u16; 2] = [0x992F, 0x990F];
+const LOGO_TILES_PER_ROW: u8 = 12;
+
+const LCDC: u16 = 0xFF40;
+const SCY: u16 = 0xFF42;
+const BGP: u16 = 0xFF47;
+const NR12: u16 = 0xFF12;
+const NR13: u16 = 0xFF13;
+const NR14: u16 = 0xFF14;
+const WAVE_RAM_START: u16 = 0xFF30;
+const WAVE_RAM_BYTES: u16 = 0x10;
+
+// DIV's internal counter as the DMG boot ROM leaves it
+const DMG_DIV_COUNTER: u16 = 0xABCC;
+
+// the logo scrolls in one line every FRAMES_PER_STEP frames, then stays put for PAUSE_STEPS more
+const SCROLL_STEPS: u8 = 0x64;
+const PAUSE_STEPS: u8 = 0x20;
+const FRAMES_PER_STEP: u8 = 2;
+const VBLANK_LY: u8 = 0x90;
+// (step, channel 1 frequency low byte) of the two notes in the boot sound
+const BOOT_SOUNDS: [(u8, u8); 2] = [(0x62, 0x83), (0x64, 0xC1)];
+
+// what the CGB boot ROM writes to KEY0 for DMG games
+pub const DMG_COMPAT_KEY0: u8 = 0x04;
+const OPRI: u16 = 0xFF6C;
+
+const BCPS: u16 = 0xFF68;
+const BCPD: u16 = 0xFF69;
+const CGB_PALETTE_BYTES: usize = 64;
+const WHITE: u16 = 0x7FFF;
+
+/// Leaves bus in the state the boot ROM would have (apart from CPU registers), for starting
+/// without one: VRAM/OAM are cleared (as Bus starts them) except for the logo the DMG boot ROM
+/// draws, the APU is on with channel 1 silent after the boot sound, BGP is set, CGB
+/// background palettes are white and the other IO registers hold their post-boot values.
+/// DMG games on the CGB model (which should already be in DMG compatibility mode, see
+/// Bus::lock_key0) get the compatibility palettes loaded into CRAM, which on the DMG model
+/// are applied as DMG palettes instead (see palette::title_palette).
+pub fn apply_post_boot_state(bus: &mut Bus, model: GBModel) {
+    if let GBModel::DMG | GBModel::SGB = model {
+        draw_logo(bus);
+    }
+
+    // the boot sound ends with channel 1 still on at volume 0
+    power_on_apu(bus);
+    bus.write_byte(NR12, 0x08);
+    bus.write_byte(NR13, 0xC1);
+    bus.write_byte(NR14, 0x87);
+    bus.write_byte(NR12, 0xF3);
+
+    if model.is_cgb() {
+        bus.write_byte(BCPS, 0x80);
+        for i in 0..CGB_PALETTE_BYTES {
+            bus.write_byte(BCPD, (WHITE >> (8 * (i % 2))) as u8);
+        }
+        if bus.dmg_compat() {
+            // objects are prioritized by X coordinate, like on DMG
+            bus.write_byte(OPRI, 0x01);
+        }
+    }
+
+    bus.write_byte(SCY, 0x00);
+    bus.write_byte(BGP, 0xFC);
+    bus.write_byte(LCDC, 0x91);
+    finish_boot(bus, model);
+}
+
+/// Sets up the APU like the boot ROM does before playing its sound on channel 1.
+fn power_on_apu(bus: &mut Bus) {
+    bus.write_byte(0xFF26, 0x80);
+    bus.write_byte(0xFF11, 0x80);
+    bus.write_byte(NR12, 0xF3);
+    bus.write_byte(0xFF24, 0x77);
+    bus.write_byte(0xFF25, 0xF3);
+}
+
+/// Sets the IO registers the boot ROM leaves behind on its way out.
+fn finish_boot(bus: &mut Bus, model: GBModel) {
+    bus.write_byte(0xFF41, 0x81);
+    // VBlank is still pending from the boot ROM's last frame
+    bus.write_byte(0xFF0F, 0x01);
+
+    if model.is_cgb() {
+        for i in 0..WAVE_RAM_BYTES {
+            bus.write_byte(WAVE_RAM_START + i, if i % 2 == 0 { 0x00 } else { 0xFF });
+        }
+    }
+
+    // how far DIV got only is known for the DMG's boot ROM, whose timing doesn't depend on the game
+    let div_counter = match model {
+        GBModel::DMG => Some(DMG_DIV_COUNTER),
+        GBModel::CGB | GBModel::AGB | GBModel::SGB => None,
+    };
+    let dma = match model {
+        GBModel::CGB | GBModel::AGB => 0x00,
+        GBModel::DMG | GBModel::SGB => 0xFF,
+    };
+    bus.set_post_boot_registers(div_counter, dma);
+
+    if matches!(model, GBModel::DMG) || bus.dmg_compat() {
+        bus.apply_title_palette();
+    }
+}
+
+/// (DMG/SGB ONLY) Plays the DMG boot ROM's logo scroll and sound, for starting without one
+/// (see config::BOOT_ANIMATION); the CPU waits until it's done, like it would in the boot ROM.
+#[derive(Default)]
+pub struct BootAnimation {
+    // steps of FRAMES_PER_STEP frames taken so far
+    steps: u8,
+    frames: u8,
+    last_ly: u8,
+}
+
+impl BootAnimation {
+    /// Draws the logo just below the screen, ready to scroll in.
+    pub fn start(bus: &mut Bus) -> Self {
+        draw_logo(bus);
+        power_on_apu(bus);
+        bus.write_byte(SCY, SCROLL_STEPS);
+        bus.write_byte(BGP, 0xFC);
+        bus.write_byte(LCDC, 0x91);
+
+        BootAnimation { steps: 0, frames: 0, last_ly: 0 }
+    }
+
+    /// Moves the animation along (meant to be called every M-cycle); once it's done,
+    /// leaves bus in its post-boot state and returns true.
+    pub fn step(&mut self, bus: &mut Bus, model: GBModel) -> bool {
+        // like the boot ROM, counts frames by waiting for LY to reach VBlank
+        let ly = bus.read_byte(0xFF44);
+        let new_frame = ly == VBLANK_LY && self.last_ly != VBLANK_LY;
+        self.last_ly = ly;
+        if !new_frame {
+            return false;
+        }
+
+        self.frames += 1;
+        if self.frames < FRAMES_PER_STEP {
+            return false;
+        }
+        self.frames = 0;
+        self.steps += 1;
+
+        if let Some(&(_, frequency)) = BOOT_SOUNDS.iter().find(|(step, _)| *step == self.steps) {
+            bus.write_byte(NR13, frequency);
+            bus.write_byte(NR14, 0x87);
+        }
+        if self.steps <= SCROLL_STEPS {
+            bus.write_byte(SCY, SCROLL_STEPS - self.steps);
+        }
+
+        let done = self.steps == SCROLL_STEPS + PAUSE_STEPS;
+        if done {
+            finish_boot(bus, model);
+        }
+        done
+    }
+}
+
+/// Copies the header's logo into VRAM like the DMG boot ROM does: every bit is doubled in
+/// width and height (on bitplane 0 only), then the tiles are placed in the middle of the screen.
+fn draw_logo(bus: &mut Bus) {
+    let mut addr = LOGO_TILES_START;
+    for i in 0..LOGO_BYTES {
+        let byte = bus.read_byte(LOGO_START + i);
+        for nibble in [byte >> 4, byte & 0xF] {
+            let row = double_bits(nibble);
+            bus.write_byte(addr, row);
+            bus.write_byte(addr + 2, row);
+            addr += 4;
+        }
+    }
+    for row in REGISTERED_TILE_ROWS {
+        bus.write_byte(addr, row);
+        addr += 2;
+    }
+
+    bus.write_byte(REGISTERED_TILE_MAP_ADDR, REGISTERED_TILE);
+    let mut tile = REGISTERED_TILE - 1;
+    for row_end in LOGO_TILE_MAP_ROWS_END {
+        for i in 0..LOGO_TILES_PER_ROW {
+            bus.write_byte(row_end - i as u16, tile);
+            tile -= 1;
+        }
+    }
+}
+
+/// Turns the 4 bits of nibble into 8 by doubling each of them.
+fn double_bits(nibble: u8) -> u8 {
+    (0..4).fold(0, |row, bit| row | ((((nibble >> bit) & 1) * 0b11) << (2 * bit)))
+}
+
+snapshot_fields!(BootAnimation, steps, frames, last_ly);
+
+#[cfg(test)]
+mod tests {
+    use super::BootAnimation;
+    use crate::cpu::test_helpers::make_test_rom;
+    use crate::cpu::{Cpu, GBModel};
+    use crate::cartridge::Cartridge;
+
+    #[test]
+    fn post_boot_state_test() {
+        let mut rom = make_test_rom(&[]);
+        rom[0x104] = 0xCE;
+        let cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+
+        assert_eq!(cpu.read_byte(0xFF26), 0xF1);
+        assert_eq!(cpu.read_byte(0xFF24), 0x77);
+        assert_eq!(cpu.read_byte(0xFF47), 0xFC);
+        assert_eq!(cpu.read_byte(0xFF00), 0xCF);
+        assert_eq!(cpu.read_byte(0xFF04), 0xAB);
+        assert_eq!(cpu.read_byte(0xFF07), 0xF8);
+        assert_eq!(cpu.read_byte(0xFF0F), 0xE1);
+        assert_eq!(cpu.read_byte(0xFF46), 0xFF);
+
+        // 0xC -> 0xF0, 0xE -> 0xFC on rows 0-1 and 2-3 of tile 1
+        let tile: Vec<u8> = (0x8010..0x8018).map(|addr| cpu.read_byte(addr)).collect();
+        assert_eq!(tile, [0xF0, 0x00, 0xF0, 0x00, 0xFC, 0x00, 0xFC, 0x00]);
+        assert_eq!(cpu.read_byte(0x8190), 0x3C);
+
+        assert_eq!(cpu.read_byte(0x9910), 0x19);
+        assert_eq!(cpu.read_byte(0x992F), 0x18);
+        assert_eq!(cpu.read_byte(0x9924), 0x0D);
+        assert_eq!(cpu.read_byte(0x990F), 0x0C);
+        assert_eq!(cpu.read_byte(0x9904), 0x01);
+    }
+
+    #[test]
+    fn dmg_compat_test() {
+        let rom = make_test_rom(&[]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::CGB);
+        assert_eq!((cpu.de.full(), cpu.hl.full()), (0x0008, 0x007C));
+        assert_eq!(cpu.read_byte(0xFF6C) & 0x01, 0x01);
+
+        // VRAM bank and KEY0 writes are ignored after boot
+        cpu.bus.write_byte(0xFF4C, 0x80);
+        cpu.bus.write_byte(0xFF4F, 0x01);
+        cpu.bus.write_byte(0x8000, 0x12);
+        cpu.bus.write_byte(0xFF4F, 0x00);
+        assert_eq!(cpu.read_byte(0x8000), 0x12);
+        assert!(cpu.bus.dmg_compat());
+
+        // BGP maps colour 0 to shade 1 of BG palette 0, loaded with the default (dark green) palette
+        cpu.bus.write_byte(0xFF47, 0x01);
+        let [b, g, r, _] = loop {
+            cpu.step();
+            if let Some(frame) = cpu.get_display_output() {
+                break [frame[0], frame[1], frame[2], frame[3]];
+            }
+        };
+        assert!(g > r && g > b, "expected green, got {:?}", (r, g, b));
+    }
+
+    #[test]
+    fn boot_animation_test() {
+        let rom = make_test_rom(&[]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.boot_animation = Some(BootAnimation::start(&mut cpu.bus));
+        assert_eq!(cpu.read_byte(0xFF42), 0x64);
+
+        // the logo scrolls in over 200 frames, then stays for 64 more before the game starts
+        let mut frames: u32 = 0;
+        while cpu.boot_animation.is_some() {
+            cpu.step();
+            if cpu.get_display_output().is_some() {
+                frames += 1;
+                if frames == 100 {
+                    assert_eq!(cpu.read_byte(0xFF42), 0x64 - 50);
+                }
+            }
+        }
+        assert!(frames.abs_diff(264) <= 1);
+        assert_eq!(cpu.read_byte(0xFF42), 0x00);
+        assert_eq!(cpu.pc.full(), 0x0100);
+        assert_eq!(cpu.read_byte(0xFF04), 0xAB);
+    }
+}
@@ -0,0 +1,200 @@
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use core::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum RegisterName {
+    A, F, B, C, D, E, H, L,
+    AF, BC, DE, HL, SP, PC,
+}
+
+impl RegisterName {
+    fn parse(name: &str) -> Option<Self> {
+        let register = match name.to_ascii_lowercase().as_str() {
+            "a" => RegisterName::A,
+            "f" => RegisterName::F,
+            "b" => RegisterName::B,
+            "c" => RegisterName::C,
+            "d" => RegisterName::D,
+            "e" => RegisterName::E,
+            "h" => RegisterName::H,
+            "l" => RegisterName::L,
+            "af" => RegisterName::AF,
+            "bc" => RegisterName::BC,
+            "de" => RegisterName::DE,
+            "hl" => RegisterName::HL,
+            "sp" => RegisterName::SP,
+            "pc" => RegisterName::PC,
+            _ => return None,
+        };
+        Some(register)
+    }
+
+    fn is_16_bit(self) -> bool {
+        matches!(self, RegisterName::AF | RegisterName::BC | RegisterName::DE | RegisterName::HL | RegisterName::SP | RegisterName::PC)
+    }
+}
+
+/// Registers and memory to start a core from (see Cpu::load_initial_state), so a reported
+/// bug can be reproduced from the few values that matter instead of a whole save state.
+/// Written in a small subset of TOML, where registers and memory left out keep their values:
+///
+/// ```toml
+/// [registers]              # a-l, af/bc/de/hl, sp, pc and ime
+/// a = 0x11
+/// pc = 0x0150
+/// ime = true
+///
+/// [memory]                 # bytes from each address on, written like the CPU would
+/// 0xC000 = [0x01, 0x02, 0x03]
+/// 0xFF80 = 0x42
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InitialState {
+    pub(super) registers: Vec<(RegisterName, u16)>,
+    pub(super) ime: Option<bool>,
+    pub(super) memory: Vec<(u16, Vec<u8>)>,
+}
+
+enum Section {
+    None,
+    Registers,
+    Memory,
+}
+
+impl FromStr for InitialState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut state = InitialState::default();
+        let mut section = Section::None;
+        let mut lines = s.lines().enumerate();
+
+        while let Some((i, line)) = lines.next() {
+            let line = strip_comment(line);
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                section = match name.trim() {
+                    "registers" => Section::Registers,
+                    "memory" => Section::Memory,
+                    name => return Err(error(format!("unknown section [{}] (expected [registers] or [memory])", name))),
+                };
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| error(String::from("expected key = value")))?;
+            let key = key.trim().trim_matches('"');
+            let mut value = value.trim().to_string();
+            // arrays can go on over several lines
+            while value.starts_with('[') && !value.ends_with(']') {
+                match lines.next() {
+                    Some((_, line)) => value.push_str(strip_comment(line)),
+                    None => return Err(error(String::from("array is never closed"))),
+                }
+            }
+
+            match section {
+                Section::Registers if key == "ime" => match value.as_str() {
+                    "true" => state.ime = Some(true),
+                    "false" => state.ime = Some(false),
+                    _ => return Err(error(format!("ime must be true or false, not {}", value))),
+                },
+                Section::Registers => {
+                    let register = RegisterName::parse(key).ok_or_else(|| error(format!("unknown register {}", key)))?;
+                    let max = if register.is_16_bit() { 0xFFFF } else { 0xFF };
+                    let value = parse_int(&value, max).map_err(error)?;
+                    state.registers.push((register, value as u16));
+                }
+                Section::Memory => {
+                    let addr = parse_int(key, 0xFFFF).map_err(error)?;
+                    let bytes = match value.strip_prefix('[').and_then(|value| value.strip_suffix(']')) {
+                        Some(list) => list.split(',')
+                            .map(str::trim)
+                            .filter(|byte| !byte.is_empty())
+                            .map(|byte| parse_int(byte, 0xFF).map(|byte| byte as u8))
+                            .collect::<Result<Vec<u8>, String>>()
+                            .map_err(error)?,
+                        None => vec![parse_int(&value, 0xFF).map_err(error)? as u8],
+                    };
+                    if addr as usize + bytes.len() > 0x10000 {
+                        return Err(error(format!("{} bytes from ${:04X} go past $FFFF", bytes.len(), addr)));
+                    }
+                    state.memory.push((addr as u16, bytes));
+                }
+                Section::None => return Err(error(format!("{} isn't in a [registers] or [memory] section", key))),
+            }
+        }
+
+        Ok(state)
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.split_once('#') {
+        Some((line, _)) => line.trim(),
+        None => line.trim(),
+    }
+}
+
+/// Parses a decimal or hex ($ or 0x) integer of at most max.
+fn parse_int(s: &str, max: u32) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix('$')) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.replace('_', "").parse(),
+    };
+    match parsed {
+        Ok(value) if value <= max => Ok(value),
+        Ok(value) => Err(format!("{} is too big (at most {})", value, max)),
+        Err(_) => Err(format!("{} isn't a number", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InitialState, RegisterName};
+    use crate::cartridge::Cartridge;
+    use crate::cpu::{test_helpers::make_test_rom, Cpu, GBModel};
+
+    const STATE: &str = "
+        # crashes after the third enemy spawns
+        [registers]
+        a = 0x11
+        HL = $C0_00
+        pc = 0x0150
+        ime = true
+
+        [memory]
+        0xC000 = [0x01, 0x02,   # enemies
+                  0x03]
+        \"0xFF80\" = 66
+    ";
+
+    #[test]
+    fn initial_state_parse_test() {
+        let state: InitialState = STATE.parse().unwrap();
+        assert_eq!(state.registers, [(RegisterName::A, 0x11), (RegisterName::HL, 0xC000), (RegisterName::PC, 0x0150)]);
+        assert_eq!(state.ime, Some(true));
+        assert_eq!(state.memory, [(0xC000, vec![1, 2, 3]), (0xFF80, vec![66])]);
+
+        assert!("[registers]\na = 0x100".parse::<InitialState>().unwrap_err().contains("line 2"));
+        assert!("[registers]\nix = 1".parse::<InitialState>().is_err());
+        assert!("[memory]\n0xFFFF = [1, 2]".parse::<InitialState>().is_err());
+        assert!("[memory]\n0xC000 = [1, 2".parse::<InitialState>().is_err());
+        assert!("a = 1".parse::<InitialState>().is_err());
+    }
+
+    #[test]
+    fn load_initial_state_test() {
+        // LD A, (HL); JR -2
+        let rom = make_test_rom(&[0x7E, 0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.load_initial_state(&"[registers]\nhl = 0xC001\npc = 0x0100\n[memory]\n0xC000 = [1, 2, 3]".parse().unwrap());
+
+        cpu.step();
+        assert_eq!(cpu.af.hi(), 2);
+        assert_eq!(cpu.read_byte(0xC002), 3);
+    }
+}
@@ -1,5 +1,5 @@
 #![allow(non_snake_case)]
-use super::{Cpu, GBModel, Interrupt::{self, *}};
+use super::{Cpu, Interrupt::{self, *}};
 
 impl Cpu {
     /// Execute the next instruction and steps through SOME parts bus (see partial_step in bus);
@@ -325,7 +325,7 @@ impl Cpu {
 
     fn stop(&mut self) -> u8 {
         let _ = self.n8();
-        if matches!(self.model, GBModel::CGB) {
+        if self.model.is_cgb() {
             if self.bus.speed_switch() {
                 self.do_speed_switch = true;
             } else {
@@ -693,6 +693,7 @@ impl Cpu {
 
     fn dec_r16(&mut self, r16_name: &str) -> u8 {
         let r16 = self.r16(r16_name) as u16;
+        self.bus.trigger_oam_bug(r16);
         self.set_r16(r16_name, r16.wrapping_sub(1));
         2
     }
@@ -715,6 +716,7 @@ impl Cpu {
 
     fn inc_r16(&mut self, r16_name: &str) -> u8 {
         let r16 = self.r16(r16_name) as u16;
+        self.bus.trigger_oam_bug(r16);
         self.set_r16(r16_name, r16.wrapping_add(1));
         2
     }
@@ -1386,18 +1388,34 @@ impl Cpu {
     }
 
     fn bus_read_byte(&mut self, addr: u16) -> u8 {
+        self.wait_for(addr);
         let byte = self.bus.read_byte(addr);
+        self.bus.latch_bus_value(byte);
+        self.bus.record_access(addr, byte, false);
         self.bus.partial_step(4);
         self.t_cycles_so_far += 4;
         byte
     }
 
     fn bus_write_byte(&mut self, addr: u16, byte: u8) {
+        self.wait_for(addr);
         self.bus.write_byte(addr, byte);
+        self.bus.latch_bus_value(byte);
+        self.bus.record_access(addr, byte, true);
         self.bus.partial_step(4);
         self.t_cycles_so_far += 4;
     }
 
+    /// Stalls for as long as an access to addr has to wait (see Bus::access_wait).
+    fn wait_for(&mut self, addr: u16) {
+        let wait = self.bus.access_wait(addr);
+        if wait > 0 {
+            self.bus.partial_step(wait);
+            self.t_cycles_so_far += wait;
+            self.wait_t_cycles += wait;
+        }
+    }
+
     fn n16(&mut self) -> u16 {
         let lo = self.n8() as u16;
         let hi = self.n8() as u16;
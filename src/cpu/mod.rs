@@ -1,18 +1,59 @@
+mod boot;
+mod initial_state;
 mod instr;
 mod register;
+#[cfg(feature = "std")]
+mod trace;
+mod watchdog;
 
+use alloc::{boxed::Box, string::String, vec::Vec};
+#[cfg(feature = "std")]
+use std::io::Write;
+
+use self::boot::BootAnimation;
+use self::initial_state::RegisterName;
 use self::register::Register;
+use self::watchdog::Watchdog;
 use self::Interrupt::*;
 
-use crate::bus::Bus;
+pub use self::initial_state::InitialState;
+#[allow(unused_imports)]
+pub use self::watchdog::Diagnostics;
+
+use crate::apu::{ApuWrite, ResampleQuality};
+use crate::bus::{Bus, OpenBus};
 use crate::cartridge::Cartridge;
-use crate::config::AUDIO_SAMPLES;
-use crate::constants::{LCD_BYTE_WIDTH, LCD_HEIGHT};
+use crate::cartridge::camera::ImageSource;
+use crate::cheats::Cheat;
+use crate::config::{AUDIO_SAMPLES, BOOT_ANIMATION, RNG_SEED, WATCHDOG_MS};
+use crate::disasm::{self, DisasmLine};
+use crate::profiler::{FrameProfile, HostClock};
+use crate::palette::{ColourCorrection, DmgPalette};
+use crate::rng::Rng;
+use crate::savestate::{snapshot_fields, Snapshot, StateError, StateReader, StateWriter};
+use crate::serial::{HostLink, HostSender, SerialLink};
+use crate::infrared::IrTransceiver;
+use crate::events::{CoreEvent, MemoryAccess, Timestamp};
+use crate::frame::Frame;
+use crate::ppu::{LcdOffPolicy, FRAME_DOTS};
+use crate::warnings::Warning;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum GBModel {
     DMG,
-    CGB
+    CGB,
+    /// Super Game Boy: a DMG whose frames are colorized and framed by the SNES (see sgb.rs).
+    SGB,
+    /// Game Boy Advance running its CGB core, which games can only tell apart from a CGB
+    /// by the B register after boot. NOTE: the AGB's subtly different timing isn't emulated.
+    AGB,
+}
+
+impl GBModel {
+    /// Returns true for models with the CGB's hardware (CGB and AGB).
+    pub fn is_cgb(self) -> bool {
+        matches!(self, GBModel::CGB | GBModel::AGB)
+    }
 }
 
 pub struct Cpu {
@@ -25,6 +66,8 @@ pub struct Cpu {
     pub(self) halt_bug: bool,
     pub(self) halt_triggered: bool,
     pub(self) t_cycles_so_far: u32,
+    // T-cycles the CPU stalled for this step on top of its instruction (see Bus::access_wait)
+    pub(self) wait_t_cycles: u32,
 
     pub(self) af: Register,
     pub(self) bc: Register,
@@ -35,6 +78,25 @@ pub struct Cpu {
 
     // CGB ONLY
     do_speed_switch: bool,
+
+    // if set, every executed instruction gets logged here (see trace.rs)
+    #[cfg(feature = "std")]
+    trace_sink: Option<Box<dyn Write>>,
+
+    watchdog: Watchdog,
+
+    // queues bytes for the game while the frontend is its link partner (see connect_serial_host)
+    serial_host: Option<HostSender>,
+
+    // while set, the CPU waits for the boot animation to finish (see boot.rs)
+    boot_animation: Option<BootAnimation>,
+
+    // the frame last finished by step_frame(), run_until_vblank() or run_cycles()
+    frame: Frame,
+    // interleaved stereo samples produced by those since drain_audio(), and the ones
+    // drain_audio() returned last (kept so the buffers get reused)
+    audio: Vec<f32>,
+    drained_audio: Vec<f32>,
 }
 
 pub enum Interrupt {
@@ -45,31 +107,58 @@ pub enum Interrupt {
     Joypad,
 }
 
+/// What the registers hold when the CPU starts (all 0 when a boot ROM runs first).
+#[derive(Clone, Copy, Default)]
+struct StartRegisters {
+    af: u16,
+    bc: u16,
+    de: u16,
+    hl: u16,
+    pc: u16,
+    sp: u16,
+}
+
 impl Cpu {
+    #[allow(dead_code)]
     pub fn new(cartridge: Cartridge, model: GBModel) -> Self {
-        assert!(!(matches!(model, GBModel::CGB) && !cartridge.cgb_compatible()), 
-            "This cartridge is not compatible with CGB functions!");
+        Cpu::with_seed(cartridge, model, RNG_SEED)
+    }
 
-        if cartridge.has_bootrom() {
-            let bus = Bus::new(cartridge, model);
-            Cpu::make_cpu(0, 0, 00, 0, 0, 0, model, bus)
-        } else {
-            let mut bus = Bus::new(cartridge, model);
-            bus.write_byte(0xFF40, 0x91);
-            bus.write_byte(0xFF41, 0x81);
+    /// Creates a CPU whose randomness (see config::RNG_SEED) all comes from seed.
+    pub fn with_seed(cartridge: Cartridge, model: GBModel, seed: u64) -> Self {
+        let has_bootrom = cartridge.has_bootrom();
+        let dmg_game = !cartridge.cgb_compatible();
+        let mut bus = Bus::new(cartridge, model, Rng::new(seed));
+        if has_bootrom {
+            return Cpu::make_cpu(StartRegisters::default(), model, bus, None);
+        }
 
-            match model {
-                GBModel::DMG => {
-                    Cpu::make_cpu(0x01B0, 0x0013, 0x00D8, 0x014D, 0x0100, 0xFFFE, model, bus)
-                }
-                GBModel::CGB => {
-                    Cpu::make_cpu(0x1180, 0x0000, 0xFF56, 0x000D, 0x0100, 0xFFFE, model, bus)
-                }
-            }
+        // the CGB boot ROM runs DMG games in DMG compatibility mode
+        if model.is_cgb() && dmg_game {
+            bus.lock_key0(boot::DMG_COMPAT_KEY0);
         }
+
+        let boot_animation = (BOOT_ANIMATION && !model.is_cgb())
+            .then(|| BootAnimation::start(&mut bus));
+        if boot_animation.is_none() {
+            boot::apply_post_boot_state(&mut bus, model);
+        }
+
+        let (af, bc, de, hl) = match model {
+            GBModel::DMG => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            // NOTE: for Nintendo's games, B holds the title checksum, which isn't emulated
+            GBModel::CGB if bus.dmg_compat() => (0x1180, 0x0000, 0x0008, 0x007C),
+            GBModel::CGB => (0x1180, 0x0000, 0xFF56, 0x000D),
+            // B bit 0 is the AGB flag
+            GBModel::AGB if bus.dmg_compat() => (0x1100, 0x0100, 0x0008, 0x007C),
+            GBModel::AGB => (0x1100, 0x0100, 0xFF56, 0x000D),
+            GBModel::SGB => (0x0100, 0x0014, 0x0000, 0xC060),
+        };
+        let registers = StartRegisters { af, bc, de, hl, pc: 0x0100, sp: 0xFFFE };
+        Cpu::make_cpu(registers, model, bus, boot_animation)
     }
 
-    fn make_cpu(af: u16, bc: u16, de: u16, hl: u16, pc: u16, sp: u16, model: GBModel, bus: Bus) -> Self {
+    fn make_cpu(registers: StartRegisters, model: GBModel, bus: Bus, boot_animation: Option<BootAnimation>) -> Self {
         Cpu { 
             bus,
             model,
@@ -79,32 +168,61 @@ impl Cpu {
             halt_bug: false,
             halt_triggered: false,
             t_cycles_so_far: 0,
-            af: Register(af),
-            bc: Register(bc),
-            de: Register(de),
-            hl: Register(hl),
-            pc: Register(pc),
-            sp: Register(sp),
+            wait_t_cycles: 0,
+            af: Register(registers.af),
+            bc: Register(registers.bc),
+            de: Register(registers.de),
+            hl: Register(registers.hl),
+            pc: Register(registers.pc),
+            sp: Register(registers.sp),
             do_speed_switch: false,
+            #[cfg(feature = "std")]
+            trace_sink: None,
+            watchdog: Watchdog::new(WATCHDOG_MS),
+            serial_host: None,
+            boot_animation,
+            frame: Frame::default(),
+            audio: Vec::new(),
+            drained_audio: Vec::new(),
         }
     }
 
     /// Steps through all parts of the emulator over the period
-    /// that the next CPU instruction will take; returns that period's length in T-cycles.
+    /// that the next CPU instruction will take; returns that period's length in T-cycles
+    /// (of real time, so shorter than the instruction in turbo mode).
     pub fn step(&mut self) -> u32 {
+        if self.boot_animation.is_some() {
+            return self.step_boot_animation();
+        }
+
+        let start = self.bus.profile_start();
         let t_cycles = self.cycle();
 
-        if matches!(self.model, GBModel::CGB) && self.do_speed_switch {
+        if self.model.is_cgb() && self.do_speed_switch {
             self.do_speed_switch = false;
             // TODO: implement pausing after STOP instruction triggers speed switch
             return 2560 
         }
 
-        self.bus.step(t_cycles);
+        let t_cycles = self.bus.step(t_cycles);
+        self.step_watchdog(t_cycles);
+        self.bus.profile_step(start);
        
         t_cycles
     }
 
+    /// Steps everything but the CPU (still "in the boot ROM") for an M-cycle of the boot animation.
+    fn step_boot_animation(&mut self) -> u32 {
+        self.bus.partial_step(4);
+        let t_cycles = self.bus.step(4);
+        if let Some(animation) = &mut self.boot_animation {
+            if animation.step(&mut self.bus, self.model) {
+                self.boot_animation = None;
+            }
+        }
+        t_cycles
+    }
+
     /// Do a CPU fetch-execute cycle and return the number of T-cycles taken.
     fn cycle(&mut self) -> u32 {
         self.halt_triggered = false;
@@ -115,6 +233,9 @@ impl Cpu {
         }
         
         let mut t_cycles = if !self.halted {
+            #[cfg(feature = "std")]
+            self.trace_instruction();
+            self.record_history();
             self.execute_next_instruction() * 4
         } else {
             4
@@ -133,6 +254,7 @@ impl Cpu {
             },
             None => {}
         }
+        t_cycles += core::mem::take(&mut self.wait_t_cycles);
 
         if t_cycles > self.t_cycles_so_far {
             self.bus.partial_step(t_cycles - self.t_cycles_so_far);
@@ -169,18 +291,286 @@ impl Cpu {
         self.bus.get_audio_output()
     }
 
-    pub fn get_display_output(&mut self) -> Option<&[u8; LCD_BYTE_WIDTH * LCD_HEIGHT]> {
+    /// Returns how much time has been emulated since power on (see Timestamp).
+    #[allow(dead_code)]
+    pub fn timestamp(&self) -> Timestamp {
+        self.bus.timestamp()
+    }
+
+    /// Returns when (in emulated time) the frame last returned by get_display_output() finished.
+    pub fn frame_timestamp(&self) -> Timestamp {
+        self.bus.frame_timestamp()
+    }
+
+    /// Returns when (in emulated time) the first sample of the audio chunk last returned by
+    /// get_audio_output() was produced.
+    #[allow(dead_code)]
+    pub fn audio_timestamp(&self) -> Timestamp {
+        self.bus.audio_timestamp()
+    }
+
+    /// Returns the finished frame (if any) as BGRA bytes, display_size() pixels big.
+    pub fn get_display_output(&mut self) -> Option<&[u8]> {
         self.bus.get_display_output()
     }
 
-    pub fn entered_hblank(&self) -> bool {
-        self.bus.entered_hblank()
+    /// Runs until the next frame is done and returns it; audio produced meanwhile is kept for
+    /// drain_audio(). For frontends that don't need anything finer than a frame at a time
+    /// (get_display_output() and get_audio_output() shouldn't be called alongside it).
+    #[allow(dead_code)]
+    pub fn step_frame(&mut self) -> &Frame {
+        while !self.step_collecting().1 {}
+        &self.frame
+    }
+
+    /// Runs until the next VBlank begins, or for a frame's worth of cycles while the LCD is off
+    /// (where there's none), so callers counting frames never wait on the game; returns true if
+    /// a frame was drawn (see frame(); the LCD-off policy can draw them with the LCD off). Like step_frame(), it only depends on emulated time.
+    #[allow(dead_code)]
+    pub fn run_until_vblank(&mut self) -> bool {
+        let mut t_cycles = 0;
+        while t_cycles < FRAME_DOTS {
+            let (step_t_cycles, frame_done) = self.step_collecting();
+            if frame_done {
+                return true;
+            }
+            t_cycles += step_t_cycles;
+        }
+        false
+    }
+
+    /// Runs for at least t_cycles T-cycles (it can only stop between instructions) and returns
+    /// how many were run; finished frames and audio are kept like step_frame() does.
+    #[allow(dead_code)]
+    pub fn run_cycles(&mut self, t_cycles: u64) -> u64 {
+        let mut run = 0;
+        while run < t_cycles {
+            run += self.step_collecting().0 as u64;
+        }
+        run
+    }
+
+    /// Steps once, keeping the audio and frame produced for drain_audio() and frame();
+    /// returns the T-cycles taken and whether a frame was finished.
+    fn step_collecting(&mut self) -> (u32, bool) {
+        let t_cycles = self.step();
+
+        if let Some(audio_output) = self.bus.get_audio_output() {
+            self.audio.extend(audio_output.iter().flatten());
+        }
+
+        let (width, height) = self.display_size();
+        if let Some(pixels) = self.bus.get_display_output() {
+            self.frame.pixels.clear();
+            self.frame.pixels.extend_from_slice(pixels);
+            (self.frame.width, self.frame.height) = (width, height);
+            return (t_cycles, true);
+        }
+        (t_cycles, false)
+    }
+
+    pub fn model(&self) -> GBModel {
+        self.model
     }
 
+    /// Returns true if the game can use CGB features (colours included): on a CGB, outside
+    /// of DMG compatibility mode.
+    pub fn cgb_mode(&self) -> bool {
+        self.bus.cgb_mode()
+    }
+
+    /// Returns the game's title, from its cartridge header.
+    pub fn title(&self) -> String {
+        self.bus.title()
+    }
+
+    /// Returns the last frame finished by step_frame() and the like (empty before the first one).
+    #[allow(dead_code)]
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Returns the interleaved stereo samples (at audio_rate()) produced by
+    /// step_frame(), run_until_vblank() or run_cycles() since the last call.
+    #[allow(dead_code)]
+    pub fn drain_audio(&mut self) -> &[f32] {
+        core::mem::swap(&mut self.audio, &mut self.drained_audio);
+        self.audio.clear();
+        &self.drained_audio
+    }
+
+    /// Returns the width and height of frames: 160x144, or 256x224 for SGB (border included).
+    pub fn display_size(&self) -> (usize, usize) {
+        self.bus.display_size()
+    }
+
+    /// Returns the palette index of the pixel at (x, y) in the last frame: its colour id (0-3)
+    /// in bits 0-1, palette in bits 2-4 and ppu::OBJ_PIXEL set for objects (None on the SGB border).
+    pub fn palette_index_at(&self, x: usize, y: usize) -> Option<u8> {
+        self.bus.palette_index_at(x, y)
+    }
+
+    /// Returns the oldest core event that hasn't been polled yet (if any).
+    pub fn poll_event(&mut self) -> Option<CoreEvent> {
+        self.bus.poll_event()
+    }
+
+    /// Sets the joypad inputs, which the game sees from the start of the next VBlank
+    /// (or right away while the LCD is off) until the inputs of the VBlank after it.
     pub fn update_joypad(&mut self, status: u8) {
         self.bus.update_joypad(status)
     }
 
+    /// Tilts the cartridge by x and y g (positive is right / down), for MBC7 games.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.bus.set_tilt(x, y)
+    }
+
+    /// Returns the non-fatal emulation problems seen so far (e.g. writes to unmapped registers
+    /// or a save that doesn't fit the cartridge), each once with how often it happened.
+    #[allow(dead_code)]
+    pub fn warnings(&self) -> &[Warning] {
+        self.bus.warnings().list()
+    }
+
+    /// Returns warnings() as text, one warning per line.
+    pub fn warnings_report(&self) -> String {
+        self.bus.warnings().report()
+    }
+
+    /// (SGB ONLY) Sets the joypad inputs of player (0-3, 0 being the one update_joypad sets),
+    /// for games that read more than one controller through the SGB's MLT_REQ command.
+    pub fn update_joypad_player(&mut self, player: usize, status: u8) {
+        self.bus.update_joypad_player(player, status)
+    }
+
+    /// Returns the joypad inputs the game currently sees (latched at the last VBlank).
+    #[allow(dead_code)]
+    pub fn current_inputs(&self) -> u8 {
+        self.bus.current_inputs()
+    }
+
+    /// (DMG ONLY) Switches to the next boot ROM combo palette.
+    pub fn cycle_dmg_palette(&mut self) {
+        self.bus.cycle_dmg_palette()
+    }
+
+    /// (DMG ONLY) Shows the game in palette (e.g. one of palette::PRESETS) from now on;
+    /// BG, OBJ0 and OBJ1 can each have their own colours.
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.bus.set_dmg_palette(palette)
+    }
+
+    /// Produces audio at hz (e.g. the rate the host's audio device runs at) from now on,
+    /// instead of config::SAMPLING_RATE_HZ. It can be nudged while running to keep the
+    /// host's audio queue from running dry or filling up (see pacing::FramePacer).
+    pub fn set_audio_rate(&mut self, hz: u32) {
+        self.bus.set_audio_rate(hz)
+    }
+
+    /// Returns the sample rate of get_audio_output() and drain_audio().
+    #[allow(dead_code)]
+    pub fn audio_rate(&self) -> u32 {
+        self.bus.audio_rate()
+    }
+
+    /// Brings audio down to its sample rate with quality (see config::RESAMPLE_QUALITY).
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.bus.set_resample_quality(quality)
+    }
+
+    /// Stops producing audio (get_audio_output() and drain_audio() stay empty) or starts again,
+    /// e.g. to save power while the frontend is muted or in the background; games still see
+    /// the APU exactly as they would otherwise.
+    #[allow(dead_code)]
+    pub fn set_audio_output(&mut self, enabled: bool) {
+        self.bus.set_audio_output(enabled)
+    }
+
+    /// Starts (or stops) logging every write to the APU's registers and wave RAM, with when
+    /// it happened, for take_apu_writes(): music as the game plays it, e.g. to save as VGM.
+    pub fn set_apu_write_log(&mut self, on: bool) {
+        self.bus.set_apu_write_log(on)
+    }
+
+    /// Returns the APU writes logged since the last call (none while the log is off).
+    pub fn take_apu_writes(&mut self) -> Vec<ApuWrite> {
+        self.bus.take_apu_writes()
+    }
+
+    /// Returns the writes that bring a freshly powered APU to the settings last written to
+    /// this one (see Bus::apu_setup_writes), to start a write log from mid-game. Channels
+    /// that are already playing are only heard once the game triggers them again.
+    pub fn apu_setup_writes(&self) -> Vec<ApuWrite> {
+        self.bus.apu_setup_writes()
+    }
+
+    /// Filters audio like the hardware's output capacitors do (see config::HIGH_PASS);
+    /// off gives the raw mix, which is offset whenever any channel's DAC is on.
+    pub fn set_high_pass(&mut self, on: bool) {
+        self.bus.set_high_pass(on)
+    }
+
+    /// Mutes (or unmutes) sound channel ch: 0 and 1 are the pulse channels, 2 the wave
+    /// channel and 3 noise (see apu::CHANNEL_NAMES). Only the output changes, not the game.
+    pub fn set_channel_enabled(&mut self, ch: usize, enabled: bool) {
+        self.bus.set_channel_enabled(ch, enabled)
+    }
+
+    pub fn channel_enabled(&self, ch: usize) -> bool {
+        self.bus.channel_enabled(ch)
+    }
+
+    /// Adds a GameShark code (RAM written at every VBlank) or Game Genie code (ROM patched)
+    /// and turns it on; returns its index for remove_cheat() and set_cheat_enabled().
+    pub fn add_cheat(&mut self, code: &str) -> Result<usize, String> {
+        self.bus.add_cheat(code)
+    }
+
+    /// Removes the cheat at index; the ones added after it move down one.
+    #[allow(dead_code)]
+    pub fn remove_cheat(&mut self, index: usize) -> Option<Cheat> {
+        self.bus.remove_cheat(index)
+    }
+
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) {
+        self.bus.set_cheat_enabled(index, enabled)
+    }
+
+    /// Returns the cheats added, in order.
+    pub fn cheats(&self) -> &[Cheat] {
+        self.bus.cheats()
+    }
+
+    /// Sets what frames show while the game has the LCD off (see config::LCD_OFF_POLICY).
+    /// Frames from an SGB aren't affected.
+    pub fn set_lcd_off_policy(&mut self, policy: LcdOffPolicy) {
+        self.bus.set_lcd_off_policy(policy)
+    }
+
+    /// Emulates the LCD's ghosting by mixing ghosting/255 of the last frame into every frame
+    /// (0 turns it off; see config::LCD_GHOSTING). Frames from an SGB aren't affected.
+    pub fn set_lcd_ghosting(&mut self, ghosting: u8) {
+        self.bus.set_lcd_ghosting(ghosting)
+    }
+
+    /// Tints every pixel by the layer that drew it (BG, window, OBP0/OBP1 objects, or BG
+    /// over an object through priority; see ppu::Layer), for debugging graphics. Frames
+    /// from an SGB aren't affected.
+    pub fn set_layer_tints(&mut self, on: bool) {
+        self.bus.set_layer_tints(on)
+    }
+
+    pub fn layer_tints(&self) -> bool {
+        self.bus.layer_tints()
+    }
+
+    /// (CGB ONLY) Converts CGB colours with correction (see palette::ColourCorrection),
+    /// starting with the frame being drawn.
+    pub fn set_colour_correction(&mut self, correction: ColourCorrection) {
+        self.bus.set_colour_correction(correction)
+    }
+
     #[allow(dead_code)]
     pub fn get_serial_output(&self) -> &str {
         self.bus.get_serial_output()
@@ -190,25 +580,256 @@ impl Cpu {
         self.bus.save_mbc_state()
     }
 
+    /// Returns true (once) if battery-backed state was written since the last call,
+    /// for frontends to schedule autosaves.
+    pub fn take_ram_dirty(&mut self) -> bool {
+        self.bus.take_ram_dirty()
+    }
+
+    /// Sets what reads from unmapped addresses return.
+    #[allow(dead_code)]
+    pub fn set_open_bus(&mut self, open_bus: OpenBus) {
+        self.bus.set_open_bus(open_bus)
+    }
+
+    /// Turns per-region bus access counting on (from zero) or off; see config::BUS_STATS.
+    #[allow(dead_code)]
+    pub fn set_bus_stats(&mut self, enabled: bool) {
+        self.bus.set_bus_stats(enabled)
+    }
+
+    /// Starts or stops recording the CPU's reads and writes of addr (instruction fetches
+    /// included) for take_memory_accesses(), e.g. for scripts that react to a variable changing.
+    #[allow(dead_code)]
+    pub fn watch_memory(&mut self, addr: u16, on: bool) {
+        self.bus.watch_memory(addr, on)
+    }
+
+    /// Returns the CPU's accesses to watched addresses since this was last called, oldest first.
+    #[allow(dead_code)]
+    pub fn take_memory_accesses(&mut self) -> Vec<MemoryAccess> {
+        self.bus.take_memory_accesses()
+    }
+
+    /// Returns the bus access statistics and heatmap as text, if counting is on.
+    pub fn bus_stats_report(&self) -> Option<String> {
+        self.bus.bus_stats().map(|stats| stats.report())
+    }
+
+    /// (ENHANCEMENT) Runs the CPU multiplier (2 or 4) times faster while the PPU, APU, timers
+    /// and cartridge keep real time, so games drop fewer frames; 1 goes back to normal speed.
+    /// Not authentic, so keep it off for test ROMs and recorded inputs.
+    pub fn set_turbo(&mut self, multiplier: u32) {
+        self.bus.set_turbo(multiplier)
+    }
+
+    /// (ACCURACY) Makes every CPU access to cartridge RAM (A000-BFFF) take m_cycles extra
+    /// M-cycles, like flashcarts whose SRAM can't keep up with the CPU; 0 is how cartridges
+    /// behave. Can be changed at any time, e.g. to check homebrew against both.
+    pub fn set_sram_wait(&mut self, m_cycles: u32) {
+        self.bus.set_sram_wait(m_cycles)
+    }
+
+    /// Returns the extra M-cycles cartridge RAM accesses take (see set_sram_wait).
+    #[allow(dead_code)]
+    pub fn sram_wait(&self) -> u32 {
+        self.bus.sram_wait()
+    }
+
+    /// Starts (or stops) measuring host time per subsystem each frame (native builds only).
+    #[cfg(feature = "std")]
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.bus.set_profiling(enabled.then(HostClock::instant))
+    }
+
+    /// Starts measuring host time per subsystem each frame with clock (e.g. a cycle counter
+    /// where there's no Instant), or stops with None.
+    #[allow(dead_code)]
+    pub fn set_profiling_clock(&mut self, clock: Option<HostClock>) {
+        self.bus.set_profiling(clock)
+    }
+
+    /// Returns the per-subsystem times of frames finished since the last call (empty if profiling is off).
+    pub fn take_frame_profiles(&mut self) -> Vec<FrameProfile> {
+        self.bus.take_frame_profiles()
+    }
+
+    /// Returns the seed the core's Rng started from; record it to reproduce this run.
+    pub fn rng_seed(&self) -> u64 {
+        self.bus.rng_seed()
+    }
+
+    /// Saves what the game can observe (see Snapshot) as a state for load_state(), which only
+    /// takes it back on the same game and model.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new(self.bus.rom_hash(), self.model);
+        self.save(&mut state);
+        state.finish()
+    }
+
+    /// Restores a state from save_state(); if it doesn't load, the core carries on as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data, self.bus.rom_hash(), self.model)?;
+        let backup = self.save_state();
+        let loaded = self.load(&mut state).and_then(|_| state.finish());
+        if loaded.is_err() {
+            let mut backup = StateReader::new(&backup, self.bus.rom_hash(), self.model).unwrap();
+            self.load(&mut backup).expect("core's own state loads");
+        }
+        loaded
+    }
+
+    /// Overrides the game's rumble strength (0.0 to 1.0) from config::RUMBLE_STRENGTHS.
+    #[allow(dead_code)]
+    pub fn set_rumble_strength(&mut self, strength: f32) {
+        self.bus.set_rumble_strength(strength)
+    }
+
+    /// Returns true if the cartridge has a clock the game can read (see adjust_rtc).
+    pub fn has_rtc(&self) -> bool {
+        self.bus.has_rtc()
+    }
+
+    /// Moves the cartridge's clock seconds forward (or back, for negative seconds), e.g. for
+    /// players changing the in-game time; ignored by cartridges without one.
+    pub fn adjust_rtc(&mut self, seconds: i64) {
+        self.bus.adjust_rtc(seconds)
+    }
+
+    /// Sets what a Pocket Camera cartridge's sensor sees; ignored by other cartridges.
+    pub fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.bus.set_image_source(source)
+    }
+
+    /// Plugs a link cable into the serial port (e.g. one end of serial::LinkPort::pair(),
+    /// with the other end in a second Cpu).
+    pub fn connect_serial(&mut self, link: Box<dyn SerialLink>) {
+        self.serial_host = None;
+        self.bus.connect_serial(link)
+    }
+
+    /// Makes the caller the link partner (see serial::HostLink): every byte the game sends
+    /// out is passed to on_receive, and bytes given to serial_send() are sent back to it.
+    #[allow(dead_code)]
+    pub fn connect_serial_host(&mut self, on_receive: Box<dyn FnMut(u8)>) {
+        let (link, sender) = HostLink::new(on_receive);
+        self.serial_host = Some(sender);
+        self.connect_serial(Box::new(link));
+    }
+
+    /// Queues byte to be sent to the game with its next serial transfer; plugs in a link
+    /// partner that ignores the game's bytes if connect_serial_host() wasn't called before.
+    #[allow(dead_code)]
+    pub fn serial_send(&mut self, byte: u8) {
+        if self.serial_host.is_none() {
+            self.connect_serial_host(Box::new(|_| {}));
+        }
+        if let Some(sender) = &self.serial_host {
+            sender.send(byte);
+        }
+    }
+
+    /// Points the IR port (the cartridge's, or the CGB's RP register) at ir, e.g. one end of
+    /// infrared::IrPort::pair() with the other end in a second Cpu.
+    pub fn connect_infrared(&mut self, ir: Box<dyn IrTransceiver>) {
+        self.bus.connect_infrared(ir)
+    }
+
+    /// Reads addr as the CPU sees it, without drawing open bus noise (see Bus::inspect_byte).
     #[allow(dead_code)]
     pub fn read_byte(&self, addr: u16) -> u8 {
-        self.bus.read_byte(addr)
+        self.bus.inspect_byte(addr)
+    }
+
+    /// Reads addr without the PPU's VRAM and OAM locking (see Bus::peek).
+    #[allow(dead_code)]
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+
+    /// Writes byte to the RAM at addr without side effects; false if it isn't RAM (see Bus::poke).
+    #[allow(dead_code)]
+    pub fn poke(&mut self, addr: u16, byte: u8) -> bool {
+        self.bus.poke(addr, byte)
+    }
+
+    /// Sets the registers and writes the memory in state (written like the CPU would, so
+    /// a write to ROM selects a bank), e.g. to start a test from a reported game state.
+    #[allow(dead_code)]
+    pub fn load_initial_state(&mut self, state: &InitialState) {
+        for (addr, bytes) in &state.memory {
+            for (i, &byte) in bytes.iter().enumerate() {
+                self.bus.write_byte(addr + i as u16, byte);
+            }
+        }
+
+        for &(register, value) in &state.registers {
+            match register {
+                RegisterName::A => self.af.set_hi(value as u8),
+                // the low nibble of F always reads 0
+                RegisterName::F => self.af.set_lo(value as u8 & 0xF0),
+                RegisterName::B => self.bc.set_hi(value as u8),
+                RegisterName::C => self.bc.set_lo(value as u8),
+                RegisterName::D => self.de.set_hi(value as u8),
+                RegisterName::E => self.de.set_lo(value as u8),
+                RegisterName::H => self.hl.set_hi(value as u8),
+                RegisterName::L => self.hl.set_lo(value as u8),
+                RegisterName::AF => self.af.set(value & 0xFFF0),
+                RegisterName::BC => self.bc.set(value),
+                RegisterName::DE => self.de.set(value),
+                RegisterName::HL => self.hl.set(value),
+                RegisterName::SP => self.sp.set(value),
+                RegisterName::PC => self.pc.set(value),
+            }
+        }
+
+        if let Some(ime) = state.ime {
+            self.ime = ime;
+            self.scheduled_ei = false;
+        }
+    }
+
+    /// Disassembles count instructions starting at addr (see disasm::disassemble).
+    #[allow(dead_code)]
+    pub fn disassemble(&self, addr: u16, count: usize) -> Vec<DisasmLine> {
+        disasm::disassemble(&self.bus, addr, count)
     }
 
-    #[cfg(target_arch = "wasm32")]
+    /// Returns the memory bus, for tools reading through it (e.g. disasm::disassemble).
+    #[allow(dead_code)]
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     pub fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
         self.bus.load_save(data, save_type);
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     pub fn save_id(&self) -> Option<String> {
         self.bus.save_id()
     }
 }
 
+// t_cycles_so_far isn't saved as states are only taken between steps, where it's 0
+snapshot_fields!(Cpu,
+    bus, scheduled_ei, ime, halted, halt_bug, halt_triggered, wait_t_cycles,
+    af, bc, de, hl, pc, sp, do_speed_switch, boot_animation,
+);
+
 #[cfg(test)]
 mod tests {
-    use super::test_helpers::test_blargg_rom;
+    use super::test_helpers::{make_test_rom, mooneye_models, run_blargg_memory, run_mooneye, test_blargg_rom, test_mooneye_acceptance};
+    use super::test_helpers::{compare_screenshot, write_screenshot};
+    use super::{Cpu, GBModel};
+    use crate::apu::ApuWrite;
+    use crate::bus::OpenBus;
+    use crate::config::AUDIO_SAMPLES;
+    use crate::cartridge::Cartridge;
+    use crate::events::{CoreEvent, MemoryAccess};
+    use crate::palette::DmgPalette;
+    use crate::ppu::{LcdOffPolicy, FRAME_DOTS};
 
     const CPU_INSTR: &str = "roms/tests/cpu_instrs.gb";
     const MEM_TIMING: &str = "roms/tests/mem_timing.gb";
@@ -228,51 +849,557 @@ mod tests {
     fn cpu_instr_timing_test() {
         test_blargg_rom(INSTR_TIMING, super::GBModel::DMG);
     }
+
+    #[test]
+    fn open_bus_test() {
+        // LD C, $03; LDH A, [C] (0xFF03 is unmapped)
+        let rom = make_test_rom(&[0x0E, 0x03, 0xF2]);
+
+        for (open_bus, expected) in [(OpenBus::High, 0xFF), (OpenBus::Low, 0x00), (OpenBus::LastValue, 0xF2)] {
+            let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+            cpu.set_open_bus(open_bus);
+            cpu.step();
+            cpu.step();
+            assert_eq!(cpu.af.hi(), expected);
+        }
+
+        // noise is reproducible from the seed
+        let noise: Vec<Vec<u8>> = [7, 7, 8].iter().map(|&seed| {
+            let mut cpu = Cpu::with_seed(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG, seed);
+            cpu.set_open_bus(OpenBus::Noise);
+            (0..8).map(|_| cpu.bus.read_byte(0xFF03)).collect()
+        }).collect();
+        assert_eq!(noise[0], noise[1]);
+        assert_ne!(noise[0], noise[2]);
+
+        // looking at memory doesn't draw noise, so the game still reads what it would have
+        let mut cpu = Cpu::with_seed(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG, 7);
+        cpu.set_open_bus(OpenBus::Noise);
+        let peeked = cpu.peek(0xFF03);
+        assert_eq!((cpu.peek(0xFF03), cpu.read_byte(0xFF03)), (peeked, peeked));
+        cpu.disassemble(0xFF00, 8);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.af.hi(), peeked);
+    }
+
+    #[test]
+    fn joypad_latch_test() {
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+
+        // START pressed mid-frame isn't seen until VBlank starts
+        cpu.update_joypad(0x7F);
+        for _ in 0..100 {
+            cpu.step();
+        }
+        assert_eq!(cpu.current_inputs(), 0xFF);
+
+        for _ in 0..2 {
+            loop {
+                cpu.step();
+                if std::iter::from_fn(|| cpu.poll_event()).any(|event| event == CoreEvent::VBlank) {
+                    break;
+                }
+            }
+            assert_eq!(cpu.current_inputs(), 0x7F);
+        }
+    }
+
+    #[test]
+    fn combo_palette_test() {
+        let dmg_rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cgb_rom = dmg_rom.clone();
+        cgb_rom[0x143] = 0x80;
+        cgb_rom[0x14D] = cgb_rom[0x134..=0x14C].iter().fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+
+        // colour of the top left pixel two frames after power on, with status held
+        let first_pixel = |rom: &[u8], model: GBModel, status: u8| {
+            let mut cpu = Cpu::new(Cartridge::from_bytes(rom).unwrap(), model);
+            cpu.bus.write_byte(0xFF47, 0x01);
+            cpu.update_joypad(status);
+            let mut frames = 0;
+            loop {
+                cpu.step();
+                if let Some(frame) = cpu.get_display_output() {
+                    frames += 1;
+                    if frames == 2 {
+                        break [frame[0], frame[1], frame[2]];
+                    }
+                }
+            }
+        };
+
+        // only a CGB running a DMG game (in DMG compatibility mode) takes UP as the brown palette
+        assert_ne!(first_pixel(&dmg_rom, GBModel::CGB, !0x04), first_pixel(&dmg_rom, GBModel::CGB, 0xFF));
+        assert_eq!(first_pixel(&cgb_rom, GBModel::CGB, !0x04), first_pixel(&cgb_rom, GBModel::CGB, 0xFF));
+        assert_eq!(first_pixel(&dmg_rom, GBModel::DMG, !0x04), first_pixel(&dmg_rom, GBModel::DMG, 0xFF));
+    }
+
+    #[test]
+    fn turbo_test() {
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+
+        // the CPU gets through twice the instructions before each VBlank at 2x
+        let steps_per_frame: Vec<(u32, u32)> = [1, 2].iter().map(|&multiplier| {
+            let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+            cpu.set_turbo(multiplier);
+            let (mut steps, mut t_cycles) = (0, 0);
+            loop {
+                t_cycles += cpu.step();
+                steps += 1;
+                if std::iter::from_fn(|| cpu.poll_event()).any(|event| event == CoreEvent::VBlank) {
+                    break (steps, t_cycles);
+                }
+            }
+        }).collect();
+
+        let ((steps, t_cycles), (turbo_steps, turbo_t_cycles)) = (steps_per_frame[0], steps_per_frame[1]);
+        assert!(turbo_steps.abs_diff(2 * steps) <= 1);
+        assert!(turbo_t_cycles.abs_diff(t_cycles) <= 12);
+    }
+
+    #[test]
+    fn agb_test() {
+        let mut rom = make_test_rom(&[]);
+        rom[0x143] = 0x80;
+        rom[0x14D] = rom[0x134..=0x14C].iter().fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+
+        let cgb = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::CGB);
+        let agb = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::AGB);
+        assert_eq!((cgb.af.full(), cgb.bc.full()), (0x1180, 0x0000));
+        assert_eq!((agb.af.full(), agb.bc.full()), (0x1100, 0x0100));
+        // everything else is a CGB
+        assert_eq!(agb.read_byte(0xFF4D), cgb.read_byte(0xFF4D));
+        assert_eq!(agb.display_size(), cgb.display_size());
+    }
+
+    #[test]
+    fn timestamp_test() {
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.set_turbo(2);
+
+        let mut frame_timestamps = Vec::new();
+        let mut audio_timestamp = None;
+        while frame_timestamps.len() < 4 || audio_timestamp.is_none() {
+            cpu.step();
+            if cpu.get_display_output().is_some() {
+                frame_timestamps.push(cpu.frame_timestamp().t_cycles());
+            }
+            if cpu.get_audio_output().is_some() {
+                audio_timestamp.get_or_insert(cpu.audio_timestamp().t_cycles());
+            }
+        }
+
+        // frames are a frame's worth of emulated time apart, even with a turbo CPU
+        assert!(frame_timestamps.windows(2).all(|pair| (pair[1] - pair[0]).abs_diff(70224) <= 12));
+        // the APU is on from power on, so the first chunk starts right away
+        assert!(audio_timestamp.unwrap() <= 2 * 84);
+    }
+    #[test]
+    fn step_frame_test() {
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+
+        for _ in 0..4 {
+            let frame = cpu.step_frame();
+            assert_eq!((frame.width, frame.height), (160, 144));
+            assert_eq!(frame.pixels.len(), 160 * 144 * 4);
+        }
+
+        // audio comes in chunks of AUDIO_SAMPLES stereo samples, a bit more than two frames' worth
+        let samples = cpu.drain_audio().len();
+        assert!(samples > 0 && samples.is_multiple_of(2 * AUDIO_SAMPLES));
+        assert!(cpu.drain_audio().is_empty());
+    }
+
+    #[test]
+    fn run_until_vblank_test() {
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        assert!(cpu.run_until_vblank());
+        assert_eq!(cpu.frame().pixels.len(), 160 * 144 * 4);
+
+        // XOR A; LDH ($40), A; JR -2 (turns the LCD off for good)
+        let rom = make_test_rom(&[0xAF, 0xE0, 0x40, 0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.set_lcd_off_policy(LcdOffPolicy::Hold);
+        for _ in 0..2 {
+            let start = cpu.timestamp().t_cycles();
+            assert!(!cpu.run_until_vblank());
+            assert!((cpu.timestamp().t_cycles() - start).abs_diff(FRAME_DOTS as u64) <= 12);
+        }
+        assert!(cpu.frame().pixels.is_empty());
+
+        // blanking draws a frame every FRAME_DOTS instead
+        cpu.set_lcd_off_policy(LcdOffPolicy::Blank);
+        let start = cpu.timestamp().t_cycles();
+        assert!(cpu.run_until_vblank());
+        assert!((cpu.timestamp().t_cycles() - start).abs_diff(FRAME_DOTS as u64) <= 12);
+        let white = DmgPalette::default().colour(0);
+        assert!(cpu.frame().pixels.chunks_exact(4).all(|pixel| pixel == white));
+    }
+
+    #[test]
+    fn run_cycles_test() {
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+
+        // JR takes 12 T-cycles, so it stops at the first multiple of 12 from 1000 on
+        assert_eq!(cpu.run_cycles(1000), 1008);
+        assert_eq!(cpu.timestamp().t_cycles(), 1008);
+
+        // frames and audio finished on the way are kept
+        cpu.run_cycles(3 * FRAME_DOTS as u64);
+        assert_eq!(cpu.frame().pixels.len(), 160 * 144 * 4);
+        assert!(!cpu.drain_audio().is_empty());
+    }
+
+    #[test]
+    fn sram_wait_test() {
+        // LD A, ($A000); LD ($B000), A; LD A, ($C000); LD A, ($A000)
+        let rom = make_test_rom(&[0xFA, 0x00, 0xA0, 0xEA, 0x00, 0xB0, 0xFA, 0x00, 0xC0, 0xFA, 0x00, 0xA0]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.set_sram_wait(2);
+
+        assert_eq!(cpu.step(), 4 * (4 + 2));
+        assert_eq!(cpu.step(), 4 * (4 + 2));
+        // only cartridge RAM waits
+        assert_eq!(cpu.step(), 4 * 4);
+
+        cpu.set_sram_wait(0);
+        assert_eq!(cpu.step(), 4 * 4);
+    }
+
+    #[test]
+    fn apu_write_log_test() {
+        // LD A, $87; LDH ($14), A; LD A, $55; LDH ($11), A; LDH ($40), A
+        let rom = make_test_rom(&[0x3E, 0x87, 0xE0, 0x14, 0x3E, 0x55, 0xE0, 0x11, 0xE0, 0x40]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.set_apu_write_log(true);
+        for _ in 0..5 {
+            cpu.step();
+        }
+
+        // only APU registers are logged, when they were written
+        let writes = cpu.take_apu_writes();
+        assert_eq!(writes.iter().map(|write| (write.addr, write.value)).collect::<Vec<_>>(), [(0xFF14, 0x87), (0xFF11, 0x55)]);
+        assert_eq!(writes[1].time.t_cycles() - writes[0].time.t_cycles(), (2 + 3) * 4);
+        assert!(cpu.take_apu_writes().is_empty());
+
+        // setting up another APU powers it on first and doesn't trigger channels
+        let setup = cpu.apu_setup_writes();
+        assert_eq!(setup[0].addr, 0xFF26);
+        assert!(setup.contains(&ApuWrite { time: cpu.timestamp(), addr: 0xFF14, value: 0x07 }));
+        assert!(setup.contains(&ApuWrite { time: cpu.timestamp(), addr: 0xFF11, value: 0x55 }));
+
+        cpu.set_apu_write_log(false);
+        assert!(cpu.take_apu_writes().is_empty());
+    }
+
+    #[test]
+    fn blargg_memory_test() {
+        // enables cartridge RAM, signs it, starts, prints "ok" and reports code
+        let program = |code: u8| {
+            let mut program = vec![0x3E, 0x0A, 0xEA, 0x00, 0x00];
+            for (addr, byte) in [(0xA001u16, 0xDE), (0xA002, 0xB0), (0xA003, 0x61), (0xA000, 0x80),
+                (0xA004, b'o'), (0xA005, b'k'), (0xA006, 0x00), (0xA000, code)] {
+                // LD A, byte; LD (addr), A
+                program.extend_from_slice(&[0x3E, byte, 0xEA, addr as u8, (addr >> 8) as u8]);
+            }
+            // JR -2
+            program.extend_from_slice(&[0x18, 0xFE]);
+            program
+        };
+        let cpu_for = |code: u8| {
+            // MBC1 with 8 KiB of RAM
+            let mut rom = make_test_rom(&program(code));
+            rom[0x147] = 0x02;
+            rom[0x149] = 0x02;
+            rom[0x14D] = rom[0x134..=0x14C].iter().fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+            Box::new(Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG))
+        };
+
+        assert_eq!(run_blargg_memory(&mut cpu_for(0)), Ok(()));
+        assert_eq!(run_blargg_memory(&mut cpu_for(3)), Err(String::from("failed with code 3:\nok")));
+    }
+
+    #[test]
+    fn screenshot_compare_test() {
+        let dir = std::env::temp_dir().join(format!("melon-gb-screenshot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("reference.png").to_string_lossy().to_string();
+
+        // a white and a red pixel over two black ones (BGRA)
+        let mut frame = [0x00, 0x00, 0x00, 0xFF].repeat(4);
+        frame[..8].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF]);
+        write_screenshot(&path, &frame, (2, 2)).unwrap();
+        assert_eq!(compare_screenshot(&frame, (2, 2), &path), Ok(()));
+
+        // small differences in colour are let through, but not a different colour
+        frame[4] = 0x03;
+        assert_eq!(compare_screenshot(&frame, (2, 2), &path), Ok(()));
+        frame[12..14].copy_from_slice(&[0xFF, 0xFF]);
+        let error = compare_screenshot(&frame, (2, 2), &path).unwrap_err();
+        assert!(error.starts_with("1 pixels differ") && error.contains("at (1, 1)"), "{}", error);
+        assert!(compare_screenshot(&frame, (4, 1), &path).unwrap_err().contains("is 2x2"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cheats_test() {
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cpu = Box::new(Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG));
+        // 0x42 at C123 every frame, and 0x3C read from 0150 (where the ROM holds 0x00)
+        cpu.add_cheat("0142 23C1").unwrap_err();
+        cpu.add_cheat("014223C1").unwrap();
+        let genie = cpu.add_cheat("3C1-50F-E0A").unwrap();
+
+        assert_eq!(cpu.read_byte(0x0150), 0x3C);
+        cpu.run_until_vblank();
+        assert_eq!(cpu.read_byte(0xC123), 0x42);
+
+        cpu.set_cheat_enabled(genie, false);
+        assert_eq!(cpu.read_byte(0x0150), 0x00);
+    }
+
+    #[test]
+    fn watch_memory_test() {
+        // LD A, $42; LD ($C000), A; LD A, ($C000); LD ($C001), A; JR -2
+        let rom = make_test_rom(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0xFA, 0x00, 0xC0, 0xEA, 0x01, 0xC0, 0x18, 0xFE]);
+        let mut cpu = Box::new(Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG));
+        cpu.watch_memory(0xC000, true);
+        cpu.watch_memory(0xC001, true);
+        cpu.watch_memory(0xC001, false);
+
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.take_memory_accesses(), vec![
+            MemoryAccess { addr: 0xC000, value: 0x42, write: true },
+            MemoryAccess { addr: 0xC000, value: 0x42, write: false },
+        ]);
+        assert!(cpu.take_memory_accesses().is_empty());
+    }
+
+    #[test]
+    fn mooneye_breakpoint_test() {
+        // loads registers with values, then LD B,B; JR -2
+        let cpu_for = |values: [u8; 6]| {
+            let mut program = Vec::new();
+            for (opcode, value) in [0x06, 0x0E, 0x16, 0x1E, 0x26, 0x2E].into_iter().zip(values) {
+                program.extend_from_slice(&[opcode, value]);
+            }
+            program.extend_from_slice(&[0x40, 0x18, 0xFE]);
+            Box::new(Cpu::new(Cartridge::from_bytes(&make_test_rom(&program)).unwrap(), GBModel::DMG))
+        };
+
+        assert_eq!(run_mooneye(&mut cpu_for([3, 5, 8, 13, 21, 34])), Ok(()));
+        assert_eq!(run_mooneye(&mut cpu_for([0x42; 6])), Err(String::from("failed")));
+        assert!(run_mooneye(&mut cpu_for([1; 6])).unwrap_err().contains("unexpected registers"));
+    }
+
+    #[test]
+    fn mooneye_models_test() {
+        use GBModel::*;
+        assert_eq!(mooneye_models("acceptance/add_sp_e_timing.gb"), vec![DMG]);
+        assert_eq!(mooneye_models("acceptance/di_timing-GS.gb"), vec![DMG, SGB]);
+        assert_eq!(mooneye_models("acceptance/boot_regs-dmgABC.gb"), vec![DMG]);
+        assert_eq!(mooneye_models("acceptance/boot_sclk_align-dmgABCmgb.gb"), vec![DMG]);
+        assert_eq!(mooneye_models("acceptance/boot_regs-sgb2.gb"), vec![SGB]);
+        assert_eq!(mooneye_models("acceptance/boot_div-cgbABCDE.gb"), vec![CGB]);
+        assert_eq!(mooneye_models("acceptance/boot_div-dmg0.gb"), vec![]);
+        assert_eq!(mooneye_models("acceptance/boot_hwio-C.gb"), vec![CGB]);
+    }
+
+    #[test]
+    #[ignore = "needs the mooneye acceptance ROMs; run with cargo test -- --ignored"]
+    fn mooneye_acceptance_test() {
+        test_mooneye_acceptance();
+    }
 }
 
 #[cfg(test)]
 pub mod test_helpers {
     use crate::Cartridge;
+    use crate::constants::BYTES_PER_PIXEL;
+    use crate::palette::ColourCorrection;
     use super::{Cpu, GBModel};
 
     const TEST_TIMEOUT: u64 = 1 << 32;
 
-    fn mooneye_pass_check(cpu: &Cpu) -> bool {
-        cpu.bc.hi() == 3 && 
-        cpu.bc.lo() == 5 && 
-        cpu.de.hi() == 8 && 
-        cpu.de.lo() == 13 &&
-        cpu.hl.hi() == 21 && 
-        cpu.hl.lo() == 34
-    }  
-    
-    fn mooneye_fail_check(cpu: &Cpu) -> bool {
-        cpu.bc.hi() == 0x42 && 
-        cpu.bc.lo() == 0x42 && 
-        cpu.de.hi() == 0x42 && 
-        cpu.de.lo() == 0x42 &&
-        cpu.hl.hi() == 0x42 && 
-        cpu.hl.lo() == 0x42
+    // screenshots can be this far off their reference in every channel, as emulators widen
+    // 5-bit CGB colours to 8 bits slightly differently
+    const SCREENSHOT_TOLERANCE: u8 = 4;
+    // when set, screenshot tests save what they see as their new reference images
+    const UPDATE_SCREENSHOTS_VAR: &str = "UPDATE_SCREENSHOTS";
+
+    // blargg's tests that can't print to serial (e.g. dmg_sound, oam_bug) report in cartridge RAM:
+    // a status at 0xA000 (0x80 while running, 0x81 to be reset, or the result code: 0 passes),
+    // this signature at 0xA001 and their text output from 0xA004 (null-terminated)
+    const BLARGG_STATUS: u16 = 0xA000;
+    const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+    const BLARGG_RUNNING: u8 = 0x80;
+    const BLARGG_RESET: u8 = 0x81;
+
+    /// Builds a 32 KiB ROM-only image (with a valid header checksum) that runs program from 0x0100.
+    pub fn make_test_rom(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+
+        let mut checksum: u8 = 0;
+        for byte in &rom[0x134..=0x14C] {
+            checksum = checksum.wrapping_sub(*byte).wrapping_sub(1);
+        }
+        rom[0x14D] = checksum;
+
+        rom
     }
-    
+
+    // mooneye's tests finish with LD B,B (a breakpoint in debuggers) once they've loaded these
+    // registers: the Fibonacci numbers in B-L when they pass, and 0x42 in all of them when not
+    const MOONEYE_BREAKPOINT: u8 = 0x40;
+    const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+    const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+    // about 32 seconds, where mooneye's tests take well under one
+    const MOONEYE_TIMEOUT: u64 = 1 << 27;
+    // where mooneye_acceptance_test looks for the acceptance tests (and their subdirectories)
+    const MOONEYE_ACCEPTANCE: &str = "roms/tests/mooneye/acceptance";
+
     pub fn test_mooneye_rom(test_rom_path: &str, model: GBModel) {
-        let cartridge = Cartridge::from_file(test_rom_path, false);
+        let cartridge = Cartridge::from_file(test_rom_path, false).unwrap();
         let mut cpu = Cpu::new(cartridge, model);
-    
+        if let Err(e) = run_mooneye(&mut cpu) {
+            panic!("Mooneye Test Failed: {}: {}", test_rom_path, e);
+        }
+    }
+
+    /// Runs cpu until its mooneye test reaches its LD B,B breakpoint; returns why it didn't
+    /// pass otherwise.
+    pub fn run_mooneye(cpu: &mut Cpu) -> Result<(), String> {
         let mut cycles: u64 = 0;
-        while cycles < TEST_TIMEOUT {
+        while cycles < MOONEYE_TIMEOUT {
+            let pc = cpu.pc.full();
+            let opcode = cpu.read_byte(pc);
             cycles += cpu.step() as u64;
-    
-            if mooneye_fail_check(&cpu) {
-                panic!("Mooneye Test Failed: {}", test_rom_path)
-            } else if mooneye_pass_check(&cpu) {
-                return;
+            // (not an interrupt being serviced instead)
+            if opcode != MOONEYE_BREAKPOINT || cpu.pc.full() != pc.wrapping_add(1) {
+                continue;
             }
-        } 
+
+            let registers = [cpu.bc.hi(), cpu.bc.lo(), cpu.de.hi(), cpu.de.lo(), cpu.hl.hi(), cpu.hl.lo()];
+            return match registers {
+                MOONEYE_PASS => Ok(()),
+                MOONEYE_FAIL => Err(String::from("failed")),
+                _ => Err(format!("stopped at {:#06X} with unexpected registers {:02X?}", pc, registers)),
+            };
+        }
+        Err(String::from("timed out"))
+    }
+
+    /// Returns the models a mooneye test runs on (that are emulated), from the suffix of its
+    /// file name: e.g. `-GS` for DMG/MGB and SGB/SGB2, or `-dmgABCmgb` and `-cgbABCDE`
+    /// for specific revisions. Tests without one run on every model, so they're run on DMG.
+    pub fn mooneye_models(test_rom_path: &str) -> Vec<GBModel> {
+        let stem = std::path::Path::new(test_rom_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let suffix = match stem.rsplit_once('-') {
+            Some((_, suffix)) => suffix,
+            None => return vec![GBModel::DMG],
+        };
+
+        let mut models = Vec::new();
+        let mut add = |model| if !models.contains(&model) { models.push(model) };
+        if suffix.chars().all(|c| c.is_ascii_uppercase()) {
+            for c in suffix.chars() {
+                match c {
+                    'G' => add(GBModel::DMG),
+                    'S' => add(GBModel::SGB),
+                    'C' => add(GBModel::CGB),
+                    'A' => add(GBModel::AGB),
+                    _ => {},
+                }
+            }
+            return models;
+        }
+
+        // revisions follow the model they're of, e.g. "dmgABC" and "mgb" in "dmgABCmgb"
+        let mut start = 0;
+        let bytes = suffix.as_bytes();
+        for end in 1..=bytes.len() {
+            let next_model = end == bytes.len() || (bytes[end].is_ascii_lowercase() && !bytes[end - 1].is_ascii_lowercase());
+            if !next_model {
+                continue;
+            }
+            let (name, revisions) = suffix[start..end].split_at(3.min(end - start));
+            start = end;
+            // DMG-0 and CGB-0 boot differently from the later revisions that are emulated
+            match (name, revisions) {
+                ("dmg", "0") | ("cgb", "0") => {},
+                ("dmg" | "mgb", _) => add(GBModel::DMG),
+                ("sgb", _) => add(GBModel::SGB),
+                ("cgb", _) => add(GBModel::CGB),
+                ("agb" | "ags", _) => add(GBModel::AGB),
+                _ => {},
+            }
+        }
+        models
+    }
+
+    /// Adds every ROM in dir and its subdirectories to roms.
+    fn find_roms(dir: &std::path::Path, roms: &mut Vec<String>) {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.is_dir() {
+                find_roms(&path, roms);
+            } else if path.extension().is_some_and(|extension| extension == "gb" || extension == "gbc") {
+                roms.push(path.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    /// Runs every mooneye acceptance test in MOONEYE_ACCEPTANCE on each model it's for,
+    /// panicking with the ones that didn't pass (or if there aren't any).
+    pub fn test_mooneye_acceptance() {
+        let mut roms = Vec::new();
+        find_roms(std::path::Path::new(MOONEYE_ACCEPTANCE), &mut roms);
+        roms.sort();
+        assert!(!roms.is_empty(), "No mooneye tests found in {}", MOONEYE_ACCEPTANCE);
+
+        let mut runs = 0;
+        let mut failures = Vec::new();
+        for rom in &roms {
+            for model in mooneye_models(rom) {
+                runs += 1;
+                let result = Cartridge::from_file(rom, false)
+                    .map_err(|e| e.to_string())
+                    .and_then(|cartridge| run_mooneye(&mut Cpu::new(cartridge, model)));
+                if let Err(e) = result {
+                    failures.push(format!("{} ({:?}): {}", rom, model, e));
+                }
+            }
+        }
+
+        println!("{}/{} mooneye acceptance runs passed", runs - failures.len(), runs);
+        assert!(failures.is_empty(), "{} failed:\n{}", failures.len(), failures.join("\n"));
     }
     
     pub fn test_blargg_rom(test_rom_path: &str, model: GBModel) {
-        let cartridge = Cartridge::from_file(test_rom_path, false);
+        let cartridge = Cartridge::from_file(test_rom_path, false).unwrap();
         let mut cpu = Cpu::new(cartridge, model);
     
         let mut cycles: u64 = 0;
@@ -286,4 +1413,122 @@ pub mod test_helpers {
             }
         } 
     }
+
+    /// Runs the ROM at test_rom_path on model for frames frames and compares the last one
+    /// against the PNG at reference_path, panicking if they differ (see compare_screenshot).
+    /// DMG games are shown in grayscale and CGB colours without correction, like the reference
+    /// images of test ROMs (e.g. dmg-acid2's and cgb-acid2's). With UPDATE_SCREENSHOTS_VAR set,
+    /// the frame is saved as the new reference instead.
+    pub fn test_screenshot(test_rom_path: &str, model: GBModel, frames: usize, reference_path: &str) {
+        let cartridge = Cartridge::from_file(test_rom_path, false).unwrap();
+        let mut cpu = Cpu::new(cartridge, model);
+        cpu.set_dmg_palette("grayscale".parse().unwrap());
+        cpu.set_colour_correction(ColourCorrection::Off);
+        for _ in 0..frames {
+            cpu.step_frame();
+        }
+
+        let frame = cpu.step_frame();
+        if std::env::var_os(UPDATE_SCREENSHOTS_VAR).is_some() {
+            write_screenshot(reference_path, &frame.pixels, (frame.width, frame.height)).unwrap();
+            println!("Updated {}", reference_path);
+            return;
+        }
+        if let Err(e) = compare_screenshot(&frame.pixels, (frame.width, frame.height), reference_path) {
+            panic!("{}: {}", test_rom_path, e);
+        }
+    }
+
+    /// Compares frame (BGRA, of the given width and height) against the PNG at reference_path;
+    /// returns how many pixels are off by more than SCREENSHOT_TOLERANCE, and where the first is.
+    pub fn compare_screenshot(frame: &[u8], (width, height): (usize, usize), reference_path: &str) -> Result<(), String> {
+        let file = std::fs::File::open(reference_path).map_err(|e| format!("Unable to open {}: {}", reference_path, e))?;
+        let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| format!("Unable to read {}: {}", reference_path, e))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|e| format!("Unable to read {}: {}", reference_path, e))?;
+
+        if (info.width as usize, info.height as usize) != (width, height) {
+            return Err(format!("frame is {}x{}, but {} is {}x{}", width, height, reference_path, info.width, info.height));
+        }
+        let channels = info.color_type.samples();
+        let reference = buffer[..info.buffer_size()].chunks_exact(channels).map(|pixel| match channels {
+            1 | 2 => [pixel[0]; 3],
+            _ => [pixel[0], pixel[1], pixel[2]],
+        });
+
+        let mut mismatches = 0;
+        let mut first = None;
+        for (i, (pixel, expected)) in frame.chunks_exact(BYTES_PER_PIXEL).zip(reference).enumerate() {
+            let actual = [pixel[2], pixel[1], pixel[0]];
+            if actual.iter().zip(expected).all(|(&a, e)| a.abs_diff(e) <= SCREENSHOT_TOLERANCE) {
+                continue;
+            }
+            mismatches += 1;
+            first.get_or_insert((i % width, i / width, actual, expected));
+        }
+
+        match first {
+            None => Ok(()),
+            Some((x, y, actual, expected)) => Err(format!(
+                "{} pixels differ from {}, the first at ({}, {}): {:02X?} instead of {:02X?}",
+                mismatches, reference_path, x, y, actual, expected
+            )),
+        }
+    }
+
+    /// Saves frame (BGRA, of the given width and height) to path as an RGB PNG.
+    pub fn write_screenshot(path: &str, frame: &[u8], (width, height): (usize, usize)) -> Result<(), String> {
+        let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+        let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let rgb: Vec<u8> = frame.chunks_exact(BYTES_PER_PIXEL)
+            .flat_map(|pixel| [pixel[2], pixel[1], pixel[0]])
+            .collect();
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&rgb))
+            .map_err(|e| e.to_string())
+    }
+
+    /// Runs a blargg test ROM that reports its result in cartridge RAM rather than over
+    /// serial, panicking with the ROM's output unless it passes.
+    pub fn test_blargg_rom_memory(test_rom_path: &str, model: GBModel) {
+        let cartridge = Cartridge::from_file(test_rom_path, false).unwrap();
+        let mut cpu = Cpu::new(cartridge, model);
+        if let Err(e) = run_blargg_memory(&mut cpu) {
+            panic!("{}: {}", test_rom_path, e);
+        }
+    }
+
+    /// Runs cpu until its blargg test reports in cartridge RAM; returns its output on failure.
+    pub fn run_blargg_memory(cpu: &mut Cpu) -> Result<(), String> {
+        // RAM could hold a result from before, so it only counts after the test has started
+        let mut started = false;
+        let mut cycles: u64 = 0;
+        while cycles < TEST_TIMEOUT {
+            cycles += cpu.step() as u64;
+
+            let signed = (0..3).all(|i| cpu.read_byte(BLARGG_STATUS + 1 + i) == BLARGG_SIGNATURE[i as usize]);
+            match cpu.read_byte(BLARGG_STATUS) {
+                _ if !signed => {},
+                BLARGG_RUNNING => started = true,
+                BLARGG_RESET => return Err(String::from("asks to be reset, which isn't supported")),
+                0 if started => return Ok(()),
+                code if started => return Err(format!("failed with code {}:\n{}", code, blargg_output(cpu))),
+                _ => {},
+            }
+        }
+        Err(format!("timed out:\n{}", blargg_output(cpu)))
+    }
+
+    fn blargg_output(cpu: &Cpu) -> String {
+        (BLARGG_STATUS + 4..=0xBFFF)
+            .map(|addr| cpu.read_byte(addr))
+            .take_while(|&byte| byte != 0)
+            .map(char::from)
+            .collect()
+    }
 }
\ No newline at end of file
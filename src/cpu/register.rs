@@ -1,3 +1,5 @@
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
 pub struct Register(pub u16);
 
 impl Register {
@@ -36,4 +38,14 @@ impl Register {
             self.0 &= !(1 << k);
         }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Register {
+    fn save(&self, state: &mut StateWriter) {
+        self.0.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.0.load(state)
+    }
+}
@@ -0,0 +1,81 @@
+use std::io::Write;
+
+use super::Cpu;
+
+impl Cpu {
+    /// Starts logging every executed instruction to sink in the Gameboy Doctor format;
+    /// passing None stops logging.
+    #[allow(dead_code)]
+    pub fn set_trace_sink(&mut self, sink: Option<Box<dyn Write>>) {
+        self.trace_sink = sink;
+    }
+
+    /// Logs CPU registers and the 4 bytes at PC (before the instruction at PC executes).
+    pub(super) fn trace_instruction(&mut self) {
+        if self.trace_sink.is_none() {
+            return;
+        }
+
+        let pc = self.pc.full();
+        let line = format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            self.af.hi(), self.af.lo(), self.bc.hi(), self.bc.lo(),
+            self.de.hi(), self.de.lo(), self.hl.hi(), self.hl.lo(),
+            self.sp.full(), pc,
+            self.bus.inspect_byte(pc),
+            self.bus.inspect_byte(pc.wrapping_add(1)),
+            self.bus.inspect_byte(pc.wrapping_add(2)),
+            self.bus.inspect_byte(pc.wrapping_add(3)),
+        );
+
+        if let Some(sink) = &mut self.trace_sink {
+            if let Err(e) = writeln!(sink, "{}", line) {
+                eprintln!("Stopped instruction trace: {}", e);
+                self.trace_sink = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    use crate::cartridge::Cartridge;
+    use crate::cpu::test_helpers::make_test_rom;
+    use crate::cpu::{Cpu, GBModel};
+
+    #[derive(Clone)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_gameboy_doctor_format_test() {
+        // NOP; JP $0150
+        let rom = make_test_rom(&[0x00, 0xC3, 0x50, 0x01]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+
+        let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+        cpu.set_trace_sink(Some(Box::new(buffer.clone())));
+        cpu.step();
+        cpu.step();
+
+        let log = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines, vec![
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01",
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,00",
+        ]);
+    }
+}
@@ -0,0 +1,200 @@
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Write;
+
+use crate::constants::T_CYCLE_HZ;
+use crate::events::CoreEvent;
+
+use super::Cpu;
+
+// number of most recently executed instructions kept for the report
+const HISTORY_LENGTH: usize = 64;
+const REPORT_DISASM_LINES: usize = 8;
+const REPORT_PCS_PER_LINE: usize = 8;
+
+/// What the watchdog captured when the game stopped responding.
+pub struct Diagnostics {
+    /// Registers, the next instructions, IO registers and recently run PCs, as text.
+    pub report: String,
+    /// The whole address space as the CPU saw it (see Cpu::peek), with the banks mapped then.
+    pub memory: Vec<u8>,
+}
+
+/// Watches for games that stop producing frames with the LCD on (e.g. stuck in a loop
+/// waiting on hardware that isn't emulated) and captures diagnostics when they do.
+pub struct Watchdog {
+    limit_ms: Option<u64>,
+    limit_t_cycles: Option<u64>,
+    t_cycles_since_vblank: u64,
+    fired: bool,
+    // PC of each recent instruction, a ring buffer where next is written over next
+    pcs: [u16; HISTORY_LENGTH],
+    next: usize,
+    recorded: usize,
+    diagnostics: Option<Diagnostics>,
+}
+
+impl Watchdog {
+    pub fn new(limit_ms: Option<u64>) -> Self {
+        Watchdog {
+            limit_ms,
+            limit_t_cycles: limit_ms.map(|ms| ms * T_CYCLE_HZ as u64 / 1000),
+            t_cycles_since_vblank: 0,
+            fired: false,
+            pcs: [0; HISTORY_LENGTH],
+            next: 0,
+            recorded: 0,
+            diagnostics: None,
+        }
+    }
+
+    /// Returns the recent PCs, oldest first.
+    fn history(&self) -> impl Iterator<Item = &u16> {
+        let (newer, older) = self.pcs[..self.recorded].split_at(self.next);
+        older.iter().chain(newer)
+    }
+}
+
+impl Cpu {
+    /// Sets how many emulated milliseconds may pass without a VBlank (while the LCD is on)
+    /// before the game is reported as not responding; None disables the watchdog.
+    #[allow(dead_code)]
+    pub fn set_watchdog_ms(&mut self, limit_ms: Option<u64>) {
+        self.watchdog = Watchdog::new(limit_ms);
+    }
+
+    /// Returns the diagnostics captured the last time the watchdog fired (if not taken yet).
+    pub fn take_diagnostics(&mut self) -> Option<Diagnostics> {
+        self.watchdog.diagnostics.take()
+    }
+
+    /// Remembers the PC of the instruction about to execute.
+    pub(super) fn record_history(&mut self) {
+        if self.watchdog.limit_t_cycles.is_none() {
+            return;
+        }
+
+        let watchdog = &mut self.watchdog;
+        watchdog.pcs[watchdog.next] = self.pc.full();
+        watchdog.next = (watchdog.next + 1) % HISTORY_LENGTH;
+        watchdog.recorded = (watchdog.recorded + 1).min(HISTORY_LENGTH);
+    }
+
+    /// Advances the watchdog after a step of t_cycles; fires at most once per missed VBlank.
+    pub(super) fn step_watchdog(&mut self, t_cycles: u32) {
+        let limit = match self.watchdog.limit_t_cycles {
+            Some(limit) => limit,
+            None => return,
+        };
+
+        if self.bus.entered_vblank() || !self.bus.lcd_on() {
+            self.watchdog.t_cycles_since_vblank = 0;
+            self.watchdog.fired = false;
+            return;
+        }
+
+        self.watchdog.t_cycles_since_vblank += t_cycles as u64;
+        if self.watchdog.t_cycles_since_vblank >= limit && !self.watchdog.fired {
+            self.watchdog.fired = true;
+            let memory = (0..=0xFFFF).map(|addr| self.bus.peek(addr)).collect();
+            self.watchdog.diagnostics = Some(Diagnostics { report: self.diagnostic_report(), memory });
+            self.bus.push_event(CoreEvent::NotResponding);
+        }
+    }
+
+    fn diagnostic_report(&self) -> String {
+        let mut report = String::new();
+        let limit_ms = self.watchdog.limit_ms.unwrap_or_default();
+
+        writeln!(report, "Game not responding: no VBlank for {} ms with the LCD on", limit_ms).unwrap();
+        writeln!(report, "AF:{:04X} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X} PC:{:04X}",
+            self.af.full(), self.bc.full(), self.de.full(), self.hl.full(), self.sp.full(), self.pc.full()).unwrap();
+        writeln!(report, "IME: {} HALTED: {}", self.ime, self.halted).unwrap();
+        writeln!(report).unwrap();
+
+        writeln!(report, "Next instructions:").unwrap();
+        for line in self.disassemble(self.pc.full(), REPORT_DISASM_LINES) {
+            writeln!(report, "  {}", line).unwrap();
+        }
+        writeln!(report).unwrap();
+
+        writeln!(report, "IO registers:").unwrap();
+        for row in (0xFF00..=0xFF7F).step_by(0x10) {
+            let bytes: Vec<String> = (row..row + 0x10)
+                .map(|addr| format!("{:02X}", self.bus.inspect_byte(addr)))
+                .collect();
+            writeln!(report, "  {:04X}: {}", row, bytes.join(" ")).unwrap();
+        }
+        writeln!(report, "  FFFF: {:02X}", self.bus.inspect_byte(0xFFFF)).unwrap();
+        writeln!(report).unwrap();
+
+        writeln!(report, "PCs of the last {} instructions (oldest first):", self.watchdog.recorded).unwrap();
+        let pcs: Vec<String> = self.watchdog.history().map(|pc| format!("{:04X}", pc)).collect();
+        for line in pcs.chunks(REPORT_PCS_PER_LINE) {
+            writeln!(report, "  {}", line.join(" ")).unwrap();
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Cartridge;
+    use crate::cpu::test_helpers::make_test_rom;
+    use crate::cpu::{Cpu, GBModel};
+    use crate::events::CoreEvent;
+
+    #[test]
+    fn watchdog_quiet_with_vblank_test() {
+        // DI; LD A, $91; LDH [$FF40], A; JR -2
+        let rom = make_test_rom(&[0xF3, 0x3E, 0x91, 0xE0, 0x40, 0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.set_watchdog_ms(Some(100));
+
+        // VBlanks keep happening, so the watchdog stays quiet
+        for _ in 0..100_000 {
+            cpu.step();
+        }
+        while let Some(event) = cpu.poll_event() {
+            assert_ne!(event, CoreEvent::NotResponding);
+        }
+        assert!(cpu.take_diagnostics().is_none());
+    }
+
+    #[test]
+    fn watchdog_report_test() {
+        // LD A, $91; LDH [$FF40], A; JR -2
+        let rom = make_test_rom(&[0x3E, 0x91, 0xE0, 0x40, 0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.set_watchdog_ms(Some(0));
+
+        cpu.step();
+
+        let diagnostics = cpu.take_diagnostics().unwrap();
+        assert!(diagnostics.report.contains("0104: 18 FE     JR $0104"));
+        assert!(diagnostics.report.contains("FF40: 91"));
+        assert!(diagnostics.report.contains("PC:0102"));
+        assert!(diagnostics.report.contains("(oldest first):\n  0100\n"));
+        assert_eq!(diagnostics.memory.len(), 0x10000);
+        assert_eq!((diagnostics.memory[0x0100], diagnostics.memory[0xFF40]), (0x3E, 0x91));
+    }
+
+    #[test]
+    fn watchdog_history_test() {
+        // NOP; ...; JR -2
+        let mut program = [0x00; 70];
+        program[68..].copy_from_slice(&[0x18, 0xFE]);
+        let rom = make_test_rom(&program);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.set_watchdog_ms(Some(1000));
+
+        for _ in 0..70 {
+            cpu.step();
+        }
+
+        // only the last 64 are kept, and in order
+        let history: Vec<u16> = cpu.watchdog.history().copied().collect();
+        let expected: Vec<u16> = (0x0106..0x0144).chain([0x0144, 0x0144]).collect();
+        assert_eq!(history, expected);
+    }
+}
@@ -0,0 +1,67 @@
+use std::env;
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::cartridge::SAVE_PATH;
+
+// sets the data directory when --data-dir and --sandbox aren't given
+pub const DATA_DIR_VAR: &str = "MELON_GB_DATA_DIR";
+
+/// Where the frontend keeps everything it writes: battery saves, the play log and the
+/// frames, recordings, prints, profiles and reports it produces. By default that's relative
+/// to the working directory like always; with a data directory, all of it goes in there
+/// instead, so a run (e.g. an automated test, or trying out a ROM) leaves the user's own
+/// files alone. ROMs and boot ROMs are still read from where they're configured.
+#[derive(Clone, Debug, Default)]
+pub struct DataDir {
+    root: Option<PathBuf>,
+}
+
+impl DataDir {
+    /// Keeps everything in root, creating it if needed.
+    pub fn new(root: &str) -> Result<Self, String> {
+        create_dir_all(root).map_err(|e| format!("Unable to create data directory {}: {}", root, e))?;
+        Ok(DataDir { root: Some(PathBuf::from(root)) })
+    }
+
+    /// Keeps everything in a new directory under the system's temporary directory, for a
+    /// single run that starts from nothing.
+    pub fn sandbox() -> Result<Self, String> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        let root = env::temp_dir().join(format!("melon-gb-{}-{}", std::process::id(), timestamp));
+        DataDir::new(&root.to_string_lossy())
+    }
+
+    /// Uses the directory in DATA_DIR_VAR if it's set, otherwise the working directory.
+    pub fn from_env() -> Result<Self, String> {
+        match env::var(DATA_DIR_VAR) {
+            Ok(root) if !root.is_empty() => DataDir::new(&root),
+            _ => Ok(DataDir::default()),
+        }
+    }
+
+    /// Returns the directory, if one was set.
+    pub fn root(&self) -> Option<String> {
+        self.root.as_ref().map(|root| root.to_string_lossy().to_string())
+    }
+
+    /// Returns where path (relative, e.g. "frames") goes.
+    pub fn path(&self, path: &str) -> String {
+        match &self.root {
+            Some(root) => root.join(path).to_string_lossy().to_string(),
+            None => path.to_string(),
+        }
+    }
+
+    /// Returns where battery saves go (or save_dir instead, outside of a data directory).
+    pub fn save_dir(&self, save_dir: Option<&str>) -> String {
+        match (&self.root, save_dir) {
+            (None, Some(save_dir)) => save_dir.to_string(),
+            _ => self.path(SAVE_PATH),
+        }
+    }
+}
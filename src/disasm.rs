@@ -0,0 +1,368 @@
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::{self, Write};
+use core::ops::Range;
+
+use crate::bus::Bus;
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "[HL]", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const R16_MEM: [&str; 4] = ["[BC]", "[DE]", "[HL+]", "[HL-]"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB A,", "SBC A,", "AND A,", "XOR A,", "OR A,", "CP A,"];
+const ROTATES: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+const BANK_SIZE: usize = 0x4000;
+// the cartridge header (logo, title, checksums...) is never code
+const HEADER: Range<usize> = 0x104..0x150;
+const DATA_PER_LINE: usize = 8;
+
+/// Set in a code/data log's byte for every ROM byte that was executed.
+pub const CDL_CODE: u8 = 0x01;
+// header Mesen starts its code/data logs with (followed by a CRC-32 of the ROM)
+const CDL_HEADER: &[u8] = b"CDLv2";
+
+/// A single decoded SM83 instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisasmLine {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{:04X}: {:<9} {}", self.addr, bytes.join(" "), self.mnemonic)
+    }
+}
+
+/// Disassembles count instructions starting at addr, reading through the bus without side
+/// effects (see Bus::peek: VRAM and OAM are read even while the PPU has them locked, and open
+/// bus noise isn't drawn). Get a Cpu's bus from Cpu::bus().
+pub fn disassemble(bus: &Bus, addr: u16, count: usize) -> Vec<DisasmLine> {
+    disassemble_with(|addr| bus.peek(addr), addr, count)
+}
+
+/// Disassembles count instructions starting at addr, using read to fetch each byte.
+pub fn disassemble_with<F: Fn(u16) -> u8>(read: F, addr: u16, count: usize) -> Vec<DisasmLine> {
+    let mut lines = Vec::with_capacity(count);
+    let mut addr = addr;
+
+    for _ in 0..count {
+        let line = decode(&read, addr);
+        addr = addr.wrapping_add(line.bytes.len() as u16);
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Disassembles a whole ROM into RGBDS source (a SECTION per bank) that assembles back into the
+/// same bytes. Given cdl, a code/data log (one byte of flags per ROM byte, see CDL_CODE, optionally
+/// after Mesen's header), only bytes it logged as code are disassembled and the rest is written
+/// as `db` data; without it, everything but the cartridge header is disassembled.
+pub fn export_rgbds(rom: &[u8], cdl: Option<&[u8]>) -> String {
+    let cdl = cdl.map(|cdl| match cdl.strip_prefix(CDL_HEADER) {
+        Some(rest) => rest.get(4..).unwrap_or_default(),
+        None => cdl,
+    });
+    let is_code = |i: usize| !HEADER.contains(&i) && cdl.is_none_or(|cdl| cdl.get(i).is_some_and(|flags| flags & CDL_CODE != 0));
+
+    let mut out = String::new();
+    let _ = writeln!(out, "; {} bytes in {} banks, disassembled by melon-gb", rom.len(), rom.len().div_ceil(BANK_SIZE));
+
+    for (bank, bytes) in rom.chunks(BANK_SIZE).enumerate() {
+        let origin = if bank == 0 { 0 } else { BANK_SIZE };
+        let _ = match bank {
+            0 => writeln!(out, "\nSECTION \"ROM Bank $000\", ROM0[$0000]"),
+            _ => writeln!(out, "\nSECTION \"ROM Bank ${0:03X}\", ROMX[$4000], BANK[${0:X}]", bank),
+        };
+
+        let read = |addr: u16| bytes.get((addr as usize).wrapping_sub(origin)).copied().unwrap_or(0);
+        let mut data = Vec::new();
+        let mut data_start = origin;
+        let mut i = 0;
+        while i < bytes.len() {
+            let line = is_code(bank * BANK_SIZE + i).then(|| decode(&read, (origin + i) as u16));
+            let instruction = line.filter(|line| {
+                let len = line.bytes.len();
+                i + len <= bytes.len() && (1..len).all(|k| is_code(bank * BANK_SIZE + i + k))
+            });
+
+            match instruction {
+                Some(line) => {
+                    write_data(&mut out, &mut data, data_start);
+                    let _ = if reassembles(&line) {
+                        writeln!(out, "    {:<40}; ${:04X}", line.mnemonic, line.addr)
+                    } else {
+                        writeln!(out, "    {:<40}; ${:04X} {}", db(&line.bytes), line.addr, line.mnemonic)
+                    };
+                    i += line.bytes.len();
+                },
+                None => {
+                    if data.is_empty() {
+                        data_start = origin + i;
+                    }
+                    data.push(bytes[i]);
+                    i += 1;
+                    if data.len() == DATA_PER_LINE {
+                        write_data(&mut out, &mut data, data_start);
+                    }
+                },
+            }
+        }
+        write_data(&mut out, &mut data, data_start);
+    }
+
+    out
+}
+
+// writes (and clears) data as a `db` line starting at addr start
+fn write_data(out: &mut String, data: &mut Vec<u8>, start: usize) {
+    if data.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "    {:<40}; ${:04X}", db(data), start);
+    data.clear();
+}
+
+fn db(bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|byte| format!("${:02X}", byte)).collect();
+    format!("db {}", values.join(", "))
+}
+
+/// Whether RGBDS assembles line's mnemonic back into the same bytes.
+fn reassembles(line: &DisasmLine) -> bool {
+    match line.bytes[..] {
+        // invalid opcodes (already written as DB)
+        _ if line.mnemonic.starts_with("DB") => false,
+        // RGBDS always pads STOP with 0x00
+        [0x10, padding] => padding == 0x00,
+        // RGBDS may turn these into LDH
+        [0xEA | 0xFA, _, 0xFF] => false,
+        _ => true,
+    }
+}
+
+/// Decodes the instruction at addr.
+pub fn decode<F: Fn(u16) -> u8>(read: &F, addr: u16) -> DisasmLine {
+    let opcode = read(addr);
+    let n8 = read(addr.wrapping_add(1));
+    let n16 = ((read(addr.wrapping_add(2)) as u16) << 8) | n8 as u16;
+
+    let (mnemonic, len) = if opcode == 0xCB {
+        (decode_cb(n8), 2)
+    } else {
+        decode_base(opcode, n8, n16, addr)
+    };
+
+    let bytes = (0..len).map(|i| read(addr.wrapping_add(i))).collect();
+
+    DisasmLine { addr, bytes, mnemonic }
+}
+
+/// Returns the mnemonic of a non-prefixed opcode along with its length in bytes.
+fn decode_base(opcode: u8, n8: u8, n16: u16, addr: u16) -> (String, u16) {
+    let x = (opcode >> 6) as usize;
+    let y = ((opcode >> 3) & 7) as usize;
+    let z = (opcode & 7) as usize;
+    let p = y >> 1;
+    let jr_target = addr.wrapping_add(2).wrapping_add(n8 as i8 as u16);
+
+    match (x, z) {
+        (0, 0) => match y {
+            0 => (String::from("NOP"), 1),
+            1 => (format!("LD [${:04X}], SP", n16), 3),
+            2 => (String::from("STOP"), 2),
+            3 => (format!("JR ${:04X}", jr_target), 2),
+            _ => (format!("JR {}, ${:04X}", CONDITIONS[y - 4], jr_target), 2),
+        },
+        (0, 1) => if y & 1 == 0 {
+            (format!("LD {}, ${:04X}", R16[p], n16), 3)
+        } else {
+            (format!("ADD HL, {}", R16[p]), 1)
+        },
+        (0, 2) => if y & 1 == 0 {
+            (format!("LD {}, A", R16_MEM[p]), 1)
+        } else {
+            (format!("LD A, {}", R16_MEM[p]), 1)
+        },
+        (0, 3) => if y & 1 == 0 {
+            (format!("INC {}", R16[p]), 1)
+        } else {
+            (format!("DEC {}", R16[p]), 1)
+        },
+        (0, 4) => (format!("INC {}", R8[y]), 1),
+        (0, 5) => (format!("DEC {}", R8[y]), 1),
+        (0, 6) => (format!("LD {}, ${:02X}", R8[y], n8), 2),
+        (0, 7) => {
+            let names = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+            (String::from(names[y]), 1)
+        },
+        (1, _) => if opcode == 0x76 {
+            (String::from("HALT"), 1)
+        } else {
+            (format!("LD {}, {}", R8[y], R8[z]), 1)
+        },
+        (2, _) => (format!("{} {}", ALU[y], R8[z]), 1),
+        (3, _) => decode_x3(opcode, y, z, n8, n16),
+        _ => unreachable!(),
+    }
+}
+
+/// Decodes opcodes 0xC0 to 0xFF (excluding the 0xCB prefix).
+fn decode_x3(opcode: u8, y: usize, z: usize, n8: u8, n16: u16) -> (String, u16) {
+    let p = y >> 1;
+
+    match opcode {
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => (format!("RET {}", CONDITIONS[y]), 1),
+        0xE0 => (format!("LDH [${:04X}], A", 0xFF00 | n8 as u16), 2),
+        0xE8 => (format!("ADD SP, {}", signed_hex(n8)), 2),
+        0xF0 => (format!("LDH A, [${:04X}]", 0xFF00 | n8 as u16), 2),
+        0xF8 => (format!("LD HL, SP{}", signed_offset(n8)), 2),
+        0xC9 => (String::from("RET"), 1),
+        0xD9 => (String::from("RETI"), 1),
+        0xE9 => (String::from("JP HL"), 1),
+        0xF9 => (String::from("LD SP, HL"), 1),
+        0xC2 | 0xCA | 0xD2 | 0xDA => (format!("JP {}, ${:04X}", CONDITIONS[y], n16), 3),
+        0xE2 => (String::from("LDH [C], A"), 1),
+        0xEA => (format!("LD [${:04X}], A", n16), 3),
+        0xF2 => (String::from("LDH A, [C]"), 1),
+        0xFA => (format!("LD A, [${:04X}]", n16), 3),
+        0xC3 => (format!("JP ${:04X}", n16), 3),
+        0xF3 => (String::from("DI"), 1),
+        0xFB => (String::from("EI"), 1),
+        0xC4 | 0xCC | 0xD4 | 0xDC => (format!("CALL {}, ${:04X}", CONDITIONS[y], n16), 3),
+        0xCD => (format!("CALL ${:04X}", n16), 3),
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => (format!("POP {}", R16_STACK[p]), 1),
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => (format!("PUSH {}", R16_STACK[p]), 1),
+        _ if z == 6 => (format!("{} ${:02X}", ALU[y], n8), 2),
+        _ if z == 7 => (format!("RST ${:02X}", y * 8), 1),
+
+        // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD
+        _ => (format!("DB ${:02X}", opcode), 1),
+    }
+}
+
+/// Returns the mnemonic of a 0xCB-prefixed opcode.
+fn decode_cb(opcode: u8) -> String {
+    let y = ((opcode >> 3) & 7) as usize;
+    let z = (opcode & 7) as usize;
+
+    match opcode >> 6 {
+        0 => format!("{} {}", ROTATES[y], R8[z]),
+        1 => format!("BIT {}, {}", y, R8[z]),
+        2 => format!("RES {}, {}", y, R8[z]),
+        3 => format!("SET {}, {}", y, R8[z]),
+        _ => unreachable!(),
+    }
+}
+
+fn signed_hex(byte: u8) -> String {
+    let e8 = byte as i8;
+    if e8 < 0 {
+        format!("-${:02X}", e8.unsigned_abs())
+    } else {
+        format!("${:02X}", e8)
+    }
+}
+
+fn signed_offset(byte: u8) -> String {
+    let e8 = byte as i8;
+    if e8 < 0 {
+        format!("-${:02X}", e8.unsigned_abs())
+    } else {
+        format!("+${:02X}", e8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, disassemble_with, export_rgbds, CDL_CODE};
+    use crate::cpu::test_helpers::make_test_rom;
+    use crate::cartridge::Cartridge;
+    use crate::cpu::{Cpu, GBModel};
+
+    fn disassemble_bytes(bytes: &[u8], count: usize) -> Vec<String> {
+        let read = |addr: u16| *bytes.get(addr as usize - 0x100).unwrap_or(&0);
+        disassemble_with(read, 0x100, count).into_iter().map(|line| line.mnemonic).collect()
+    }
+
+    #[test]
+    fn disasm_base_opcodes_test() {
+        let bytes = [
+            0x00,             // NOP
+            0xC3, 0x50, 0x01, // JP $0150
+            0x3E, 0x12,       // LD A, $12
+            0x22,             // LD [HL+], A
+            0x20, 0xFE,       // JR NZ, $0107
+            0xE0, 0x44,       // LDH [$FF44], A
+            0xF8, 0xFF,       // LD HL, SP-$01
+            0x76,             // HALT
+            0xD3,             // DB $D3
+        ];
+
+        assert_eq!(disassemble_bytes(&bytes, 9), vec![
+            "NOP",
+            "JP $0150",
+            "LD A, $12",
+            "LD [HL+], A",
+            "JR NZ, $0107",
+            "LDH [$FF44], A",
+            "LD HL, SP-$01",
+            "HALT",
+            "DB $D3",
+        ]);
+    }
+
+    #[test]
+    fn disasm_cb_opcodes_test() {
+        let bytes = [0xCB, 0x37, 0xCB, 0x7E, 0xCB, 0xC1];
+
+        assert_eq!(disassemble_bytes(&bytes, 3), vec!["SWAP A", "BIT 7, [HL]", "SET 0, C"]);
+    }
+
+    #[test]
+    fn disasm_bus_test() {
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        assert_eq!(disassemble(cpu.bus(), 0x0100, 1)[0].mnemonic, "JR $0100");
+
+        // code in VRAM disassembles whatever mode the PPU is in
+        cpu.poke(0x8000, 0x3E);
+        cpu.poke(0x8001, 0x12);
+        for _ in 0..200 {
+            cpu.run_cycles(40);
+            assert_eq!(disassemble(cpu.bus(), 0x8000, 1)[0].mnemonic, "LD A, $12");
+        }
+    }
+
+    #[test]
+    fn export_rgbds_test() {
+        // NOP, JP $0150
+        let mut rom = make_test_rom(&[0x00, 0xC3, 0x50, 0x01]);
+        // NOP, LD [$FF40], A, STOP $01 (neither assembles back as is), JR -2
+        rom[0x150..0x158].copy_from_slice(&[0x00, 0xEA, 0x40, 0xFF, 0x10, 0x01, 0x18, 0xFE]);
+        let line = |text: &str, addr: u16| format!("    {:<40}; ${:04X}", text, addr);
+
+        let asm = export_rgbds(&rom, None);
+        let lines: Vec<&str> = asm.lines().collect();
+        assert!(lines.contains(&"SECTION \"ROM Bank $000\", ROM0[$0000]"));
+        assert!(lines.contains(&"SECTION \"ROM Bank $001\", ROMX[$4000], BANK[$1]"));
+        assert!(lines.contains(&line("JP $0150", 0x101).as_str()));
+        assert!(lines.contains(&line("db $00, $00, $00, $00, $00, $00, $00, $00", 0x104).as_str()));
+        assert!(lines.contains(&format!("{} LD [$FF40], A", line("db $EA, $40, $FF", 0x151)).as_str()));
+        assert!(lines.contains(&format!("{} STOP", line("db $10, $01", 0x154)).as_str()));
+        assert!(lines.contains(&line("JR $0156", 0x156).as_str()));
+
+        // with a code/data log, only what it logged as code is disassembled
+        let mut cdl = b"CDLv2\0\0\0\0".to_vec();
+        cdl.extend(rom.iter().enumerate().map(|(i, _)| if (0x100..0x104).contains(&i) { CDL_CODE } else { 0 }));
+        let asm = export_rgbds(&rom, Some(&cdl));
+        assert!(asm.contains(&line("JP $0150", 0x101)));
+        assert!(asm.contains(&line("db $10, $01, $18, $FE, $00, $00, $00, $00", 0x154)));
+        assert_eq!(asm.matches("NOP").count(), 1);
+    }
+}
@@ -1,20 +1,51 @@
+use std::collections::VecDeque;
+use std::fs::{create_dir_all, read, read_to_string, write, File};
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, SyncSender};
-use std::time::Duration;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use gbemulib::constants::{LCD_BYTE_WIDTH, LCD_HEIGHT, LCD_WIDTH, T_CYCLE_DURATION_NS};
+use gbemulib::filters::FilterChain;
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use sdl2::{AudioSubsystem, Sdl};
-use sdl2::video::Window;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::render::{Canvas, Texture};
-use sdl2::rect::Rect;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::rect::Rect;
 use sdl2::EventPump;
 
+use crate::apu::{ResampleQuality, CHANNEL_NAMES};
+use crate::bindings::{Bindings, Hotkey};
 use crate::cartridge::Cartridge;
+use crate::cartridge::camera::StaticImage;
 use crate::cpu::{Cpu, GBModel};
-use crate::config::{AUDIO_SAMPLES, SAMPLING_RATE_HZ};
+use crate::events::CoreEvent;
+use crate::config::{AGB_MODE, AUDIO_SAMPLES, RNG_SEED, SAMPLING_RATE_HZ, SGB_ENHANCED, SRAM_WAIT_CYCLES};
+use crate::data_dir::DataDir;
+use crate::frame_dump::FrameDump;
+use crate::gamepad::{Gamepads, PadMapping};
+use crate::recorder::{RecordFormat, Recorder};
+use crate::screenshot;
+use crate::video::{VideoFormat, VideoRecorder};
+use crate::infrared::IrPort;
+use crate::serial::LinkPort;
+use crate::joypad::{block_opposing, Turbo};
+use crate::link::tcp::{LinkMode, Speculation, TcpLink, SPECULATION_FRAMES};
+use crate::magnifier::Magnifier;
+use crate::movie::Movie;
+use crate::music::MusicControls;
+use crate::pacing::{self, FramePacer, SyncMode};
+use crate::palette::{ColourCorrection, DmgPalette};
+use crate::pause_menu::{MenuItem, PauseMenu};
+use crate::play_log::PlaySession;
+use crate::ppu::LcdOffPolicy;
+use crate::profiler::FrameProfile;
+use crate::peripherals::printer::Printer;
+use crate::renderer::{self, NoRenderer, RenderOptions, Renderer};
+use crate::rewind::{Granularity, Rewind};
+#[cfg(feature = "lua")]
+use crate::script::Script;
 
 // in order of: START, SELECT, B, A, DOWN, UP, LEFT, RIGHT.
 pub const KEYMAPPINGS: [Keycode; 8] = [
@@ -28,29 +59,311 @@ pub const KEYMAPPINGS: [Keycode; 8] = [
     Keycode::D,
 ];
 
+// keys for the second core in split-screen mode (or the second SGB controller otherwise),
+// in the same order as KEYMAPPINGS
+pub const SECOND_KEYMAPPINGS: [Keycode; 8] = [
+    Keycode::Return,
+    Keycode::Backspace,
+    Keycode::Comma,
+    Keycode::Period,
+    Keycode::Down,
+    Keycode::Up,
+    Keycode::Left,
+    Keycode::Right,
+];
+
+// tilt the cartridge (for MBC7 games), in order of: DOWN, UP, LEFT, RIGHT.
+pub const TILT_KEYMAPPINGS: [Keycode; 4] = [
+    Keycode::Kp2,
+    Keycode::Kp8,
+    Keycode::Kp4,
+    Keycode::Kp6,
+];
+
+// turbo B and turbo A (auto-fire, see joypad::Turbo) for each player
+pub const TURBO_KEYMAPPINGS: [[Keycode; 2]; 2] = [
+    [Keycode::U, Keycode::O],
+    [Keycode::M, Keycode::Slash],
+];
+
+// switches to the next DMG palette
+pub const PALETTE_HOTKEY: Keycode = Keycode::P;
+// mute or unmute the main core's pulse 1, pulse 2, wave and noise channels; with shift held,
+// they solo the channel instead (or bring all of them back if it was already soloed)
+pub const CHANNEL_HOTKEYS: [Keycode; 4] = [Keycode::F1, Keycode::F2, Keycode::F3, Keycode::F4];
+// tints the main core's pixels by the layer that drew them (see Cpu::set_layer_tints)
+pub const LAYER_TINTS_HOTKEY: Keycode = Keycode::F5;
+// turns cartridge RAM wait states on or off (see Cpu::set_sram_wait)
+pub const SRAM_WAIT_HOTKEY: Keycode = Keycode::F6;
+// freezes the main core on its next frame to inspect it: the mouse wheel zooms, dragging
+// pans and the pixel under the cursor is described next to the title (press again to resume)
+pub const MAGNIFIER_HOTKEY: Keycode = Keycode::F7;
+// how often input is checked while the magnifier or the pause menu is open
+pub const MAGNIFIER_POLL: Duration = Duration::from_millis(16);
+// prints the main core's emulation warnings (see Cpu::warnings)
+pub const WARNINGS_HOTKEY: Keycode = Keycode::F8;
+// prints bus access statistics (when config::BUS_STATS is on)
+pub const BUS_STATS_HOTKEY: Keycode = Keycode::F9;
+// dumps the next FRAME_DUMP_COUNT frames of the main core to FRAME_DUMP_PATH
+pub const FRAME_DUMP_HOTKEY: Keycode = Keycode::F10;
+pub const FRAME_DUMP_COUNT: usize = 60;
+pub const FRAME_DUMP_PATH: &str = "frames";
+// shows the average time per frame spent in each subsystem (over PROFILE_OVERLAY_FRAMES frames)
+// next to the title; see also --profile-frames, which writes every frame's times to PROFILES_PATH
+pub const PROFILE_HOTKEY: Keycode = Keycode::F11;
+pub const PROFILE_OVERLAY_FRAMES: usize = 30;
+pub const PROFILES_PATH: &str = "profiles";
+// starts or stops recording the main core's audio to RECORDINGS_PATH (as WAV, or VGM with --record vgm)
+pub const RECORD_HOTKEY: Keycode = Keycode::F12;
+pub const RECORDINGS_PATH: &str = "recordings";
+// starts or stops recording the main core's frames to VIDEOS_PATH (as a GIF, or as --video says)
+pub const VIDEO_HOTKEY: Keycode = Keycode::G;
+pub const VIDEOS_PATH: &str = "videos";
+// pauses the main core on its next frame and opens a menu over it (press again to resume),
+// moved through with the joypad's keys: up and down pick, A or START choose and B resumes
+pub const MENU_HOTKEY: Keycode = Keycode::Tab;
+// how far the pause menu's CLOCK AHEAD and CLOCK BACK move the cartridge's clock
+pub const CLOCK_STEP_SECONDS: i64 = 3600;
+// starts typing a GameShark or Game Genie code for the main core (shown next to the title):
+// Enter adds it, or turns it off or back on if it was added already, and Escape cancels
+pub const CHEAT_HOTKEY: Keycode = Keycode::C;
+// while recording a movie, goes back RERECORD_FRAMES frames (powering the game on again and
+// replaying the movie up to there) to record them again; while playing one back, starts
+// recording over it from the current frame
+pub const RERECORD_HOTKEY: Keycode = Keycode::R;
+pub const RERECORD_FRAMES: usize = 60;
+// switches the window between full screen and windowed
+pub const FULLSCREEN_HOTKEY: Keycode = Keycode::F;
+// switches between stretching frames over the window and keeping their aspect ratio
+pub const STRETCH_HOTKEY: Keycode = Keycode::V;
+// runs the main core as fast as it goes while held (its audio is dropped meanwhile)
+pub const FAST_FORWARD_HOTKEY: Keycode = Keycode::Space;
+// saves the main core's next frame to SCREENSHOTS_PATH as a PNG named after the game
+pub const SCREENSHOT_HOTKEY: Keycode = Keycode::T;
+pub const SCREENSHOTS_PATH: &str = "screenshots";
+// with --rewind, plays the main core back while held, through snapshots of it taken every
+// REWIND_INTERVAL frames, compressed at REWIND_LEVEL and kept within REWIND_MEMORY bytes; while
+// fast-forwarding they're taken further apart the faster it goes, and compressed at
+// REWIND_FAST_LEVEL (see rewind::Rewind). Fast-forwarding while it's held rewinds faster too
+pub const REWIND_HOTKEY: Keycode = Keycode::Backquote;
+pub const REWIND_INTERVAL: u32 = 2;
+pub const REWIND_LEVEL: u8 = 1;
+pub const REWIND_FAST_LEVEL: u8 = 6;
+pub const REWIND_MEMORY: usize = 64 << 20;
+
+// where the Game Boy Printer's prints get saved
+pub const PRINTS_PATH: &str = "prints";
+
+// how much the window's scaled up when it opens (it can be resized after)
 pub const SCREEN_SCALE: i32 = 5;
 
 pub const MASTER_VOLUME: f32 = 0.2;
 
-const PIXEL_FORMAT: PixelFormatEnum = PixelFormatEnum::ARGB8888;
+// where diagnostics (a report and a memory dump) get written when a game stops responding
+pub const DIAGNOSTICS_PATH: &str = "diagnostics";
+
+// battery saves are written once the game stops writing to them for AUTOSAVE_DELAY,
+// or AUTOSAVE_MAX_DELAY after the first unsaved write for games that write constantly
+pub const AUTOSAVE_DELAY: Duration = Duration::from_secs(2);
+pub const AUTOSAVE_MAX_DELAY: Duration = Duration::from_secs(30);
+
+// per-game play time, frame and save counts, written every PLAY_LOG_INTERVAL and on exit
+pub const PLAY_LOG_PATH: &str = "config/play-log.csv";
+pub const PLAY_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+// which keys press which buttons and trigger which hotkeys (see bindings::Bindings); the
+// hotkeys above and KEYMAPPINGS are the defaults, written here when it doesn't exist
+pub const BINDINGS_PATH: &str = "config/bindings.toml";
+
+// how often terminal controls are checked while music player mode is paused
+pub const MUSIC_PAUSE_POLL: Duration = Duration::from_millis(50);
 
 pub struct Emulator {
     event_pump: EventPump,
-    canvas: Canvas<Window>,
+    renderer: Box<dyn Renderer>,
     key_status: u8,
+    // bits set for the TILT_KEYMAPPINGS held down
+    tilt_status: u8,
+    // (SGB ONLY) the second controller, for games that read more than one
+    player2_key_status: u8,
+    gamepads: Gamepads,
+    // which keys press the joypads' buttons and trigger hotkeys
+    bindings: Bindings,
+    // each player's turbo buttons held down (in joypad status order, 0 = held)
+    turbo_status: [u8; 2],
+    turbo: Turbo,
+    // whether opposing directions held together are let go of (see joypad::block_opposing)
+    block_opposing: bool,
+    // set while FAST_FORWARD_HOTKEY is held
+    fast_forward: bool,
+    // snapshots of the main core to rewind through (see enable_rewind), and set while
+    // REWIND_HOTKEY is held
+    rewind: Option<Rewind>,
+    rewinding: bool,
+    // with a LinkMode::Speculative link cable, the main core's save state and joypad status
+    // at the start of each of its last few frames, to roll back to (see roll_back_speculation)
+    speculation: Option<Speculation>,
+    speculation_states: VecDeque<(u64, Vec<u8>, u8)>,
     cpu: Cpu,
+    autosave: Autosave,
+    play_session: PlaySession,
+    play_log_written: Instant,
+    frame_dump: Option<FrameDump>,
+    recorder: Option<Recorder>,
+    // what RECORD_HOTKEY records as
+    record_format: RecordFormat,
+    video: Option<VideoRecorder>,
+    // what VIDEO_HOTKEY records as, and whether with sound
+    video_format: VideoFormat,
+    video_audio: bool,
+    // post-processing for the main core's frames
+    filters: FilterChain,
+    // frames profiled since the overlay was last updated (None if the overlay is off)
+    profile_overlay: Option<Vec<FrameProfile>>,
+    profile_dump: Option<ProfileDump>,
+    // set while the main core is frozen to inspect its frame
+    magnifier: Option<Magnifier>,
+    // freezes the main core's next frame into the magnifier
+    magnify_next_frame: bool,
+    // saves the main core's next frame (see SCREENSHOT_HOTKEY)
+    screenshot_next_frame: bool,
+    // set while the main core is paused in the menu, and to open it on the next frame
+    pause_menu: Option<PauseMenu>,
+    pause_next_frame: bool,
+    // the cheat code being typed, while it is (see CHEAT_HOTKEY)
+    cheat_entry: Option<String>,
+    // the Lua script running alongside the main core (see load_script)
+    #[cfg(feature = "lua")]
+    script: Option<Script>,
+    // the input movie being recorded or played back on the main core (see record_movie)
+    movie: Option<MovieSession>,
+    // set by RERECORD_HOTKEY until it's done between frames
+    rerecord_pending: bool,
+    // settings to give cores made after they were set (see reset)
+    settings: CoreSettings,
+    // loads the main core's cartridge again for reset()
+    reload: Option<Box<dyn Fn() -> Result<Cartridge, String>>>,
+    // emulated time the main core ran for before it was last reset
+    time_before_reset: Duration,
+    // M-cycles SRAM_WAIT_HOTKEY makes cartridge RAM accesses wait for
+    sram_wait_cycles: u32,
+    // set in music player mode
+    music: Option<MusicControls>,
+    second: Option<SecondCore>,
+    // where everything the emulator keeps is written
+    data_dir: DataDir,
     _audio_subsystem: AudioSubsystem,
     _audio_device: AudioDevice<Callback>,
-    audio_tx: SyncSender<[[f32; 2]; AUDIO_SAMPLES]>
+    audio_tx: SyncSender<[[f32; 2]; AUDIO_SAMPLES]>,
+    pacer: FramePacer,
+}
+
+/// An input movie being recorded or played back on the main core.
+struct MovieSession {
+    movie: Movie,
+    // where it's written when recorded
+    path: String,
+    recording: bool,
+    // VBlanks since power on, which is the frame whose input comes next
+    frame: usize,
+    // the input the core holds until then
+    status: u8,
+}
+
+/// Core drawn to the right of the main one in split-screen mode (its audio is muted).
+struct SecondCore {
+    cpu: Cpu,
+    autosave: Autosave,
+    key_status: u8,
+    // T-cycles this core still has to run to catch up with the main core
+    cycles_behind: i64,
 }
 
 impl Emulator {
-    /// Loads in given cartridge and initializes Gameboy emulator.
-    pub fn load_cartridge(cartridge: Cartridge) -> Result<Self, String> {
+    /// Loads in given cartridge and initializes Gameboy emulator, pacing frames with sync.
+    pub fn load_cartridge(cartridge: Cartridge, renderer: RenderOptions, sync: SyncMode, data_dir: DataDir) -> Result<Self, String> {
+        Emulator::build(cartridge, None, Some(renderer), sync, data_dir)
+    }
+
+    /// Plays cartridge without a window (music player mode): the game is heard but not seen,
+    /// and is controlled from the terminal (see MusicControls).
+    pub fn load_music_player(cartridge: Cartridge, sync: SyncMode, data_dir: DataDir) -> Result<Self, String> {
+        let mut emulator = Emulator::build(cartridge, None, None, sync, data_dir)?;
+        emulator.music = Some(MusicControls::new());
+        Ok(emulator)
+    }
+
+    /// Runs two cores side-by-side in one window; the second is controlled with player 2's keys (SECOND_KEYMAPPINGS by default).
+    /// They're connected by a link cable, and their IR ports face each other (e.g. for Mystery Gift).
+    pub fn load_split_screen(
+        cartridge: Cartridge,
+        second_cartridge: Cartridge,
+        renderer: RenderOptions,
+        sync: SyncMode,
+        data_dir: DataDir
+    ) -> Result<Self, String> {
+        Emulator::build(cartridge, Some(second_cartridge), Some(renderer), sync, data_dir)
+    }
+
+    /// Creates the emulator, with a window drawn by renderer (no window if it's None) and
+    /// frames paced with sync, writing everything it keeps to data_dir.
+    fn build(
+        cartridge: Cartridge,
+        second_cartridge: Option<Cartridge>,
+        renderer: Option<RenderOptions>,
+        sync: SyncMode,
+        data_dir: DataDir
+    ) -> Result<Self, String> {
         let sdl_context: Sdl = sdl2::init()?;
 
-        let window_title = &cartridge.get_title();
-        let canvas = Emulator::build_canvas(&sdl_context, SCREEN_SCALE as u32, window_title)?;
+        let play_session = PlaySession::start(&data_dir.path(PLAY_LOG_PATH), cartridge.rom_hash(), &cartridge.get_title());
+        let play_stats = play_session.totals(Duration::ZERO);
+        let bindings = Bindings::load(&data_dir.path(BINDINGS_PATH))?;
+        println!("{}: {}", play_stats.title, play_stats.summary());
+
+        let mut window_title = format!("{} ({})", cartridge.get_title(), play_stats.summary());
+        if let Some(second_cartridge) = &second_cartridge {
+            window_title = format!("{} + {}", window_title, second_cartridge.get_title());
+        }
+
+        // both screens have to be the same size, so SGB is left out of split screen
+        let mut cpu = Emulator::make_cpu(cartridge, second_cartridge.is_none(), RNG_SEED);
+        let mut second = second_cartridge.map(|cartridge| {
+            let mut second_cpu = Emulator::make_cpu(cartridge, false, RNG_SEED);
+            connect_cores(&mut cpu, &mut second_cpu);
+
+            SecondCore {
+                cpu: second_cpu,
+                autosave: Autosave::new(),
+                key_status: 0xFF,
+                cycles_behind: 0,
+            }
+        });
+
+        let sync = match sync {
+            SyncMode::Vsync if renderer.is_none() => {
+                println!("There's no window to sync to, so frames are timed instead");
+                SyncMode::Timer
+            },
+            SyncMode::Vsync => {
+                let refresh_hz = sdl_context.video()?.current_display_mode(0).map_or(0, |mode| mode.refresh_rate);
+                if pacing::can_vsync(refresh_hz) {
+                    SyncMode::Vsync
+                } else {
+                    println!("The display refreshes at {} Hz, too far from the Game Boy's to sync to, so frames are timed instead", refresh_hz);
+                    SyncMode::Timer
+                }
+            },
+            sync => sync,
+        };
+
+        let screens = 1 + second.is_some() as u32;
+        let vsync = sync == SyncMode::Vsync;
+        let renderer: Box<dyn Renderer> = match renderer {
+            Some(options) => renderer::build_renderer(&sdl_context, options, vsync, SCREEN_SCALE as u32, screens, cpu.display_size(), &window_title)?,
+            None => Box::new(NoRenderer),
+        };
+        let gamepads = Gamepads::new(&sdl_context)?;
         let event_pump = sdl_context.event_pump()?;
 
         let (audio_tx, audio_rx) = std::sync::mpsc::sync_channel(4);
@@ -60,136 +373,1581 @@ impl Emulator {
             samples: Some(AUDIO_SAMPLES as u16),
         };
         let _audio_subsystem = sdl_context.audio()?;
+        let queued_audio = Arc::new(AtomicUsize::new(0));
+        let callback_queued_audio = queued_audio.clone();
         let _audio_device = _audio_subsystem.open_playback(None, &desired_spec, |_spec| {
-            Callback { audio_rx, prev_sample: [0.0; 2] }
+            Callback { audio_rx, queued: callback_queued_audio, prev_sample: [0.0; 2] }
         }).unwrap();
         _audio_device.resume();
 
-        let model = if cartridge.cgb_compatible() {
-            GBModel::CGB
-        } else {
-            GBModel::DMG
-        };
-        println!("detected model: {:?}", model);
+        // the device can run at another rate than the one asked for, which the cores then produce
+        let audio_rate = _audio_device.spec().freq as u32;
+        cpu.set_audio_rate(audio_rate);
+        if let Some(second) = &mut second {
+            second.cpu.set_audio_rate(audio_rate);
+        }
 
         Ok(Emulator {
             event_pump,
-            canvas,
+            renderer,
             key_status: 0xFF,
-            cpu: Cpu::new(cartridge, model),
+            tilt_status: 0,
+            player2_key_status: 0xFF,
+            gamepads,
+            bindings,
+            turbo_status: [0xFF; 2],
+            turbo: Turbo::default(),
+            block_opposing: false,
+            fast_forward: false,
+            rewind: None,
+            rewinding: false,
+            speculation: None,
+            speculation_states: VecDeque::new(),
+            cpu,
+            autosave: Autosave::new(),
+            play_session,
+            play_log_written: Instant::now(),
+            frame_dump: None,
+            recorder: None,
+            record_format: RecordFormat::Wav,
+            video: None,
+            video_format: VideoFormat::Gif,
+            video_audio: false,
+            filters: FilterChain::new(),
+            profile_overlay: None,
+            profile_dump: None,
+            magnifier: None,
+            magnify_next_frame: false,
+            screenshot_next_frame: false,
+            pause_menu: None,
+            pause_next_frame: false,
+            cheat_entry: None,
+            #[cfg(feature = "lua")]
+            script: None,
+            movie: None,
+            rerecord_pending: false,
+            settings: CoreSettings::default(),
+            reload: None,
+            time_before_reset: Duration::ZERO,
+            sram_wait_cycles: SRAM_WAIT_CYCLES,
+            music: None,
+            second,
+            data_dir,
             _audio_device,
             _audio_subsystem,
             audio_tx,
+            pacer: FramePacer::new(sync, audio_rate, queued_audio),
         })
     }
 
-    fn build_canvas(sdl_context: &Sdl, scale: u32, title: &str) -> Result<Canvas<Window>, String> {
-        let video_subsystem = sdl_context.video()?;
-        let window_width = LCD_WIDTH as u32 * scale;
-        let window_height = LCD_HEIGHT as u32 * scale;
+    /// Logs every executed instruction to the file at path (in the Gameboy Doctor format).
+    pub fn trace_to_file(&mut self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("Unable to create trace file {}: {}", path, e))?;
+        self.cpu.set_trace_sink(Some(Box::new(BufWriter::new(file))));
+        Ok(())
+    }
 
-        let window = video_subsystem
-            .window("Gameboy Emulator", window_width, window_height)
-            .position_centered()
-            .opengl()
-            .build()
-            .map_err(|e| e.to_string())?;
+    /// Shows the binary PGM image at path to a Pocket Camera's sensor.
+    pub fn set_camera_image(&mut self, path: &str) -> Result<(), String> {
+        let bytes = read(path).map_err(|e| format!("Unable to read camera image {}: {}", path, e))?;
+        self.cpu.set_image_source(Box::new(StaticImage::from_pgm(&bytes)?));
+        Ok(())
+    }
+
+    /// Dumps the next frame_count frames as PNGs (with a register manifest) to FRAME_DUMP_PATH.
+    pub fn dump_frames(&mut self, frame_count: usize) -> Result<(), String> {
+        self.frame_dump = Some(FrameDump::start(&self.data_dir.path(FRAME_DUMP_PATH), frame_count)?);
+        Ok(())
+    }
 
-        println!("Created window of width {} and height {}", window_width, window_height);
+    /// Starts recording the main core's audio as format to RECORDINGS_PATH (until stop_recording()
+    /// or exit), which RECORD_HOTKEY then also records as.
+    pub fn start_recording(&mut self, format: RecordFormat) -> Result<(), String> {
+        self.stop_recording();
+        self.record_format = format;
+        self.recorder = Some(Recorder::start(&self.data_dir.path(RECORDINGS_PATH), format, &mut self.cpu)?);
+        self.renderer.show_status("Recording audio");
+        Ok(())
+    }
 
-        let mut canvas: Canvas<Window> = window
-            .into_canvas()
-            .build()
-            .map_err(|e| e.to_string())?;
+    /// Finishes the running recording, if any.
+    pub fn stop_recording(&mut self) {
+        let recorder = match self.recorder.take() {
+            Some(recorder) => recorder,
+            None => return,
+        };
 
-        let title = &format!("MelonBoy | Playing: {}", title);
-        canvas.window_mut().set_title(title).unwrap();
-        Ok(canvas)
+        let status = match recorder.finish(&mut self.cpu) {
+            Ok(path) => format!("Recording saved to {}", path),
+            Err(e) => {
+                eprintln!("{}", e);
+                format!("Recording failed: {}", e)
+            },
+        };
+        self.renderer.show_status(&status);
     }
 
-    /// Runs the emulator for the specified number of nanoseconds.
-    pub fn run_for_duration(&mut self, total_dur_ns: u64) {
-        let mut dur_ns = 0;
+    /// Starts recording the main core's frames (and audio, with audio, if format has sound) as
+    /// format to VIDEOS_PATH (until stop_video() or exit), which VIDEO_HOTKEY then also records as.
+    pub fn start_video(&mut self, format: VideoFormat, audio: bool) -> Result<(), String> {
+        self.stop_video();
+        (self.video_format, self.video_audio) = (format, audio);
+        let audio_rate = audio.then(|| self.pacer.device_hz());
+        self.video = Some(VideoRecorder::start(&self.data_dir.path(VIDEOS_PATH), format, self.cpu.display_size(), audio_rate)?);
+        self.renderer.show_status("Recording video");
+        Ok(())
+    }
 
-        let creator = self.canvas.texture_creator();
-        let mut texture = creator
-            .create_texture_streaming(PIXEL_FORMAT, LCD_WIDTH as u32, LCD_HEIGHT as u32)
-            .map_err(|e| e.to_string())
-            .unwrap();
+    /// Finishes the running video, if any.
+    pub fn stop_video(&mut self) {
+        let video = match self.video.take() {
+            Some(video) => video,
+            None => return,
+        };
 
-        let screen_width = LCD_WIDTH as u32 * SCREEN_SCALE as u32;
-        let screen_height = LCD_HEIGHT as u32 * SCREEN_SCALE as u32;
-        let rect = Rect::new(0, 0, screen_width, screen_height);
+        let status = match video.finish() {
+            Ok(path) => format!("Video saved to {}", path),
+            Err(e) => {
+                eprintln!("{}", e);
+                format!("Video failed: {}", e)
+            },
+        };
+        self.renderer.show_status(&status);
+    }
+
+    /// Writes the time each subsystem takes in the next frame_count frames to a CSV in PROFILES_PATH.
+    pub fn profile_frames(&mut self, frame_count: usize) -> Result<(), String> {
+        self.profile_dump = Some(ProfileDump::start(&self.data_dir.path(PROFILES_PATH), frame_count)?);
+        self.cpu.set_profiling(true);
+        Ok(())
+    }
+
+    /// Overclocks the CPU of every core by multiplier (see Cpu::set_turbo), which is shown
+    /// as a warning since games then no longer run like on hardware; does nothing while a
+    /// movie's recorded or played back, as it'd play back differently without turbo.
+    pub fn set_turbo(&mut self, multiplier: u32) {
+        if self.movie.is_some() {
+            println!("Turbo CPU isn't available while a movie runs");
+            return;
+        }
+
+        self.settings.turbo = Some(multiplier);
+        self.cpu.set_turbo(multiplier);
+        if let Some(second) = &mut self.second {
+            second.cpu.set_turbo(multiplier);
+        }
+
+        let status = format!("Turbo CPU {}x (enhancement, not accurate)", multiplier);
+        println!("{}", status);
+        self.renderer.show_status(&status);
+    }
+
+    /// Runs every core at the hardware's speed again after set_turbo().
+    fn stop_turbo(&mut self) {
+        self.settings.turbo = None;
+        self.cpu.set_turbo(1);
+        if let Some(second) = &mut self.second {
+            second.cpu.set_turbo(1);
+        }
+    }
+
+    /// Keeps snapshots of the main core for REWIND_HOTKEY to play it back through; not in
+    /// split-screen mode, as the second core can't go back with it.
+    pub fn enable_rewind(&mut self) -> Result<(), String> {
+        if self.second.is_some() {
+            return Err(String::from("Rewinding isn't available in split-screen mode"));
+        }
+        let normal = Granularity { interval: REWIND_INTERVAL, level: REWIND_LEVEL };
+        self.rewind = Some(Rewind::new(REWIND_MEMORY, normal, REWIND_FAST_LEVEL));
+        println!("Rewinding enabled (hold {} to rewind)", self.bindings.key(Hotkey::Rewind));
+        Ok(())
+    }
+
+    /// Makes turbo buttons press for on frames then release for off frames while held.
+    pub fn set_turbo_rate(&mut self, on: u32, off: u32) {
+        self.turbo = Turbo::new(on, off);
+    }
+
+    /// Lets go of LEFT+RIGHT and UP+DOWN when they're held together, instead of letting the game
+    /// see them as a DMG would.
+    pub fn set_block_opposing(&mut self, block: bool) {
+        self.block_opposing = block;
+    }
+
+    /// Sets which controller buttons press which Game Boy buttons (see gamepad::PadMapping).
+    pub fn set_pad_mapping(&mut self, mapping: PadMapping) {
+        self.gamepads.set_mapping(mapping);
+    }
+
+    /// Shows DMG games on every core in palette from now on.
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        self.settings.palette = Some(palette);
+        self.cpu.set_dmg_palette(palette);
+        if let Some(second) = &mut self.second {
+            second.cpu.set_dmg_palette(palette);
+        }
+    }
+
+    /// Emulates LCD ghosting on every core (see Cpu::set_lcd_ghosting).
+    pub fn set_lcd_ghosting(&mut self, ghosting: u8) {
+        self.settings.ghosting = Some(ghosting);
+        self.cpu.set_lcd_ghosting(ghosting);
+        if let Some(second) = &mut self.second {
+            second.cpu.set_lcd_ghosting(ghosting);
+        }
+    }
+
+    /// Resamples audio on every core with quality (see Cpu::set_resample_quality).
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.settings.resample_quality = Some(quality);
+        self.cpu.set_resample_quality(quality);
+        if let Some(second) = &mut self.second {
+            second.cpu.set_resample_quality(quality);
+        }
+    }
+
+    /// Filters audio on every core like the hardware does, or not (see Cpu::set_high_pass).
+    pub fn set_high_pass(&mut self, on: bool) {
+        self.settings.high_pass = Some(on);
+        self.cpu.set_high_pass(on);
+        if let Some(second) = &mut self.second {
+            second.cpu.set_high_pass(on);
+        }
+    }
+
+    /// Makes cartridge RAM accesses on every core wait for m_cycles extra M-cycles
+    /// (see Cpu::set_sram_wait); SRAM_WAIT_HOTKEY then toggles between that and none.
+    pub fn set_sram_wait(&mut self, m_cycles: u32) {
+        if m_cycles > 0 {
+            self.sram_wait_cycles = m_cycles;
+        }
+        self.cpu.set_sram_wait(m_cycles);
+        if let Some(second) = &mut self.second {
+            second.cpu.set_sram_wait(m_cycles);
+        }
+
+        let status = match m_cycles {
+            0 => String::from("SRAM wait states off"),
+            _ => format!("SRAM wait states: {} M-cycles per access", m_cycles),
+        };
+        println!("{}", status);
+        self.renderer.show_status(&status);
+    }
+
+    /// Mutes or unmutes the main core's sound channel ch (see CHANNEL_HOTKEYS); solo plays
+    /// only ch instead, or every channel again if ch was the only one playing.
+    fn toggle_channel(&mut self, ch: usize, solo: bool) {
+        let soloed = (0..4).all(|other| self.cpu.channel_enabled(other) == (other == ch));
+        for other in 0..4 {
+            let enabled = match solo {
+                true => soloed || other == ch,
+                false if other == ch => !self.cpu.channel_enabled(ch),
+                false => self.cpu.channel_enabled(other),
+            };
+            self.cpu.set_channel_enabled(other, enabled);
+        }
+
+        let playing: Vec<&str> = (0..4).filter(|&ch| self.cpu.channel_enabled(ch)).map(|ch| CHANNEL_NAMES[ch]).collect();
+        let status = match playing.len() {
+            4 => String::from("All channels playing"),
+            0 => String::from("All channels muted"),
+            _ => format!("Playing: {}", playing.join(", ")),
+        };
+        println!("{}", status);
+        self.renderer.show_status(&status);
+    }
+
+    /// Converts CGB colours on every core with correction from now on.
+    /// Sets what every core shows while its LCD is off (see Cpu::set_lcd_off_policy).
+    pub fn set_lcd_off_policy(&mut self, policy: LcdOffPolicy) {
+        self.settings.lcd_off_policy = Some(policy);
+        self.cpu.set_lcd_off_policy(policy);
+        if let Some(second) = &mut self.second {
+            second.cpu.set_lcd_off_policy(policy);
+        }
+    }
+
+    pub fn set_colour_correction(&mut self, correction: ColourCorrection) {
+        self.settings.colour_correction = Some(correction);
+        self.cpu.set_colour_correction(correction);
+        if let Some(second) = &mut self.second {
+            second.cpu.set_colour_correction(correction);
+        }
+    }
+
+    /// Post-processes the main core's frames with filters before they are drawn.
+    pub fn set_filters(&mut self, filters: FilterChain) {
+        self.filters = filters;
+    }
 
-        // NOTE: cycle timings seem to be controlled by APU audio callback 
-        while dur_ns < total_dur_ns {
-            self.cpu.update_joypad(self.key_status);
+    /// Waits for another melon-gb to connect to addr and cables it to the main core,
+    /// with transfers waiting on each other as mode says.
+    pub fn host_link(&mut self, addr: &str, mode: LinkMode) -> Result<(), String> {
+        let link = TcpLink::host(addr, mode).map_err(|e| format!("Unable to host link cable on {}: {}", addr, e))?;
+        self.speculation = link.speculation();
+        self.cpu.connect_serial(Box::new(link));
+        Ok(())
+    }
+
+    /// Cables the main core to another melon-gb hosting at addr (which picks the LinkMode).
+    pub fn connect_link(&mut self, addr: &str) -> Result<(), String> {
+        let link = TcpLink::connect(addr).map_err(|e| format!("Unable to connect link cable to {}: {}", addr, e))?;
+        self.speculation = link.speculation();
+        self.cpu.connect_serial(Box::new(link));
+        Ok(())
+    }
+
+    /// Plugs a Game Boy Printer into the main core, saving prints to PRINTS_PATH.
+    pub fn connect_printer(&mut self) {
+        self.cpu.connect_serial(Box::new(Printer::new(&self.data_dir.path(PRINTS_PATH))));
+    }
+
+    /// Adds a GameShark or Game Genie code to the main core (see Cpu::add_cheat).
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), String> {
+        let index = self.cpu.add_cheat(code)?;
+        println!("Cheat {} on: {}", code, self.cpu.cheats()[index].kind);
+        Ok(())
+    }
+
+    /// Runs the Lua script at path alongside the main core (see script::Script).
+    #[cfg(feature = "lua")]
+    pub fn load_script(&mut self, path: &str) -> Result<(), String> {
+        self.script = Some(Script::load(path, &mut self.cpu)?);
+        Ok(())
+    }
+
+    /// Records the main core's input into a movie written to path on exit (see movie::Movie);
+    /// call before it runs, as movies start from power on. Battery saves aren't written from
+    /// then on, so powering on again for RERECORD_HOTKEY starts the game the same way.
+    pub fn record_movie(&mut self, path: &str) -> Result<(), String> {
+        self.stop_turbo();
+        let movie = Movie::new(self.play_session.title(), self.play_session.rom_hash(), self.cpu.model(), self.cpu.rng_seed());
+        self.movie = Some(MovieSession { movie, path: path.to_string(), recording: true, frame: 0, status: 0xFF });
+        println!("Recording movie to {} (press {} to re-record)", path, self.bindings.key(Hotkey::Rerecord));
+        Ok(())
+    }
+
+    /// Plays back the movie at path on the main core instead of taking keys until it ends;
+    /// call before the core runs, like record_movie(), and before plugging anything into it,
+    /// as the core is powered on again with the movie's seed (see set_reload).
+    pub fn play_movie(&mut self, path: &str) -> Result<(), String> {
+        let text = read_to_string(path).map_err(|e| format!("Unable to read movie {}: {}", path, e))?;
+        let movie: Movie = text.parse().map_err(|e| format!("Unable to read movie {}: {}", path, e))?;
+        movie.check(self.play_session.rom_hash(), self.cpu.model())?;
+        println!("Playing movie {} ({} frames, {} re-records)", path, movie.len(), movie.rerecords);
+
+        self.stop_turbo();
+        let cartridge = self.reload_cartridge()?;
+        let (cpu, _) = movie.replay(cartridge, 0, |cpu| self.configure_core(cpu));
+        self.power_on(cpu);
+        self.movie = Some(MovieSession { movie, path: path.to_string(), recording: false, frame: 0, status: 0xFF });
+        Ok(())
+    }
+
+    /// Lets reset() load the main core's cartridge again with reload (e.g. from its file, so
+    /// the battery save written before resetting is read back).
+    pub fn set_reload(&mut self, reload: impl Fn() -> Result<Cartridge, String> + 'static) {
+        self.reload = Some(Box::new(reload));
+    }
+
+    /// Powers the main core off and on again, with its battery save written and its cartridge
+    /// loaded again (see set_reload). It keeps the settings given to the emulator, but what
+    /// was plugged into it (link cable, printer, camera image, trace) is left unplugged.
+    pub fn reset(&mut self) -> Result<(), String> {
+        let cartridge = self.reload_cartridge()?;
+        let mut cpu = Emulator::make_cpu(cartridge, self.second.is_none(), self.cpu.rng_seed());
+        self.configure_core(&mut cpu);
+        self.power_on(cpu);
+        Ok(())
+    }
+
+    /// Writes the main core's battery save and loads its cartridge again, to power it on again.
+    fn reload_cartridge(&mut self) -> Result<Cartridge, String> {
+        if self.movie.is_none() && self.autosave.step(&mut self.cpu, true) {
+            self.play_session.saves += 1;
+        }
+        let cartridge = match &self.reload {
+            Some(reload) => reload()?,
+            None => return Err(String::from("Unable to reset: there's no cartridge to load again")),
+        };
+        self.stop_recording();
+        Ok(cartridge)
+    }
+
+    /// Gives cpu, about to replace the main core, the main core's settings and cheats.
+    fn configure_core(&self, cpu: &mut Cpu) {
+        cpu.set_audio_rate(self.pacer.device_hz());
+        cpu.set_sram_wait(self.cpu.sram_wait());
+        self.settings.apply(cpu);
+        for cheat in self.cpu.cheats() {
+            if let Ok(index) = cpu.add_cheat(&cheat.code) {
+                cpu.set_cheat_enabled(index, cheat.enabled);
+            }
+        }
+    }
+
+    /// Replaces the main core with cpu (see configure_core), as if powered off and on again.
+    fn power_on(&mut self, mut cpu: Cpu) {
+        if let Some(second) = &mut self.second {
+            connect_cores(&mut cpu, &mut second.cpu);
+        }
+
+        self.time_before_reset += self.cpu.timestamp().as_duration();
+        self.cpu = cpu;
+        self.pacer.resync();
+        // the snapshots were of the core that was just replaced
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        // and the link cable was left unplugged
+        self.speculation = None;
+        self.speculation_states.clear();
+    }
+
+    /// Picks the model to run cartridge on; games with SGB features get an SGB
+    /// (if allow_sgb and SGB_ENHANCED) unless they can run on a CGB (or AGB, if AGB_MODE).
+    /// Its randomness all comes from seed (see Cpu::with_seed).
+    fn make_cpu(cartridge: Cartridge, allow_sgb: bool, seed: u64) -> Cpu {
+        let model = if cartridge.cgb_compatible() {
+            if AGB_MODE { GBModel::AGB } else { GBModel::CGB }
+        } else if allow_sgb && SGB_ENHANCED && cartridge.sgb_compatible() {
+            GBModel::SGB
+        } else {
+            GBModel::DMG
+        };
+        println!("detected model: {:?}", model);
+
+        Cpu::with_seed(cartridge, model, seed)
+    }
+
+    /// Runs the emulator until it's closed, a frame at a time: each frame is emulated whole,
+    /// then presented, then waited out as the sync mode says (see pacing::SyncMode).
+    pub fn run(&mut self) {
+        loop {
+            if std::mem::take(&mut self.rerecord_pending) {
+                self.rerecord();
+            }
+            if self.music.as_ref().is_some_and(MusicControls::paused) {
+                thread::sleep(MUSIC_PAUSE_POLL);
+                if let Err(e) = self.get_events() {
+                    panic!("{}", e)
+                }
+                continue;
+            }
+            if self.magnifier.is_some() || self.pause_menu.is_some() {
+                thread::sleep(MAGNIFIER_POLL);
+                if let Err(e) = self.get_events() {
+                    panic!("{}", e)
+                }
+                self.renderer.present();
+                continue;
+            }
+
+            self.run_frame();
+        }
+    }
+
+    /// Runs the main core (and the second one alongside it) until it finishes a frame, presents
+    /// it, waits it out, then takes the next frame's input.
+    fn run_frame(&mut self) {
+        if self.rewinding {
+            self.rewind_frame();
+            return;
+        }
+
+        if let Some(speculation) = &self.speculation {
+            let frame = speculation.frame();
+            if self.speculation_states.back().map(|(last, _, _)| *last) != Some(frame) {
+                self.speculation_states.push_back((frame, self.cpu.save_state(), self.joypad_status()));
+            }
+            while self.speculation_states.len() as u64 > SPECULATION_FRAMES {
+                self.speculation_states.pop_front();
+            }
+        }
+
+        let frame_timestamp = self.cpu.frame_timestamp();
+        let start = self.cpu.timestamp();
+        // the core can go longer without finishing a frame (e.g. while stopped), but the
+        // window's still presented and input still taken every frame or so
+        while self.cpu.frame_timestamp() == frame_timestamp
+            && self.cpu.timestamp().t_cycles() - start.t_cycles() < pacing::FRAME_T_CYCLES as u64
+        {
+            self.cpu.update_joypad(self.joypad_status());
             let t_cycles = self.cpu.step() as u64;
-            self.step_emulator(&mut texture, rect);
-            let cpu_duration_ns = t_cycles * T_CYCLE_DURATION_NS;
-            dur_ns += cpu_duration_ns;
-        } 
+            self.step_emulator();
+            self.step_second_core(t_cycles);
+        }
+        self.roll_back_speculation();
+        // fast-forwarding takes snapshots further apart (see rewind::Rewind)
+        if let Some(rewind) = &mut self.rewind {
+            rewind.end_frame(&self.cpu, self.fast_forward);
+        }
+
+        self.renderer.present();
+        if !self.fast_forward {
+            self.pacer.end_frame(self.cpu.timestamp().as_duration());
+        }
+        self.turbo.step();
+        match self.get_events() {
+            Ok(_) => {
+                self.step_movie();
+                self.cpu.update_joypad(self.joypad_status());
+                self.cpu.update_joypad_player(1, self.player2_status(self.player2_key_status));
+            },
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    /// With a LinkMode::Speculative link cable, takes the main core back to the start of the
+    /// frame a guessed reply turned out wrong in, and runs it back up to where it was with the
+    /// joypad as it was, the link playing back the real replies. Those frames were already shown
+    /// and heard (if wrongly), so they run unseen, with their events and audio dropped.
+    fn roll_back_speculation(&mut self) {
+        let speculation = match &self.speculation {
+            Some(speculation) => speculation.clone(),
+            None => return,
+        };
+        let frame = match speculation.take_misprediction() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let index = match self.speculation_states.iter().position(|(start, _, _)| *start == frame) {
+            Some(index) => index,
+            None => {
+                eprintln!("Link cable reply was guessed wrong too long ago to roll back");
+                return;
+            },
+        };
+        if let Err(e) = self.cpu.load_state(&self.speculation_states[index].1) {
+            eprintln!("Unable to roll back the link cable's guess: {}", e);
+            return;
+        }
+        speculation.rewind(frame);
+
+        // the states from then on are taken again as the frames run again
+        let joypads: Vec<u8> = self.speculation_states.drain(index..).map(|(_, _, joypad)| joypad).collect();
+        for joypad in joypads {
+            self.speculation_states.push_back((speculation.frame(), self.cpu.save_state(), joypad));
+            let frame_timestamp = self.cpu.frame_timestamp();
+            let start = self.cpu.timestamp();
+            while self.cpu.frame_timestamp() == frame_timestamp
+                && self.cpu.timestamp().t_cycles() - start.t_cycles() < pacing::FRAME_T_CYCLES as u64
+            {
+                self.cpu.update_joypad(joypad);
+                self.cpu.step();
+                while self.cpu.poll_event().is_some() {}
+                self.cpu.get_audio_output();
+                self.cpu.get_display_output();
+            }
+        }
+    }
+
+    /// Takes the place of run_frame() while REWIND_HOTKEY is held: loads the main core's
+    /// newest snapshot and shows the frame it draws next, a frame's time apart (or as fast as
+    /// it goes while fast-forwarding). Nothing else sees that frame and its audio is dropped,
+    /// as the game's only going back over where it's been.
+    fn rewind_frame(&mut self) {
+        let start = Instant::now();
+        let rewind = match &mut self.rewind {
+            Some(rewind) => rewind,
+            None => return,
+        };
+        match rewind.step_back(&mut self.cpu) {
+            Ok(true) => {
+                let frame_timestamp = self.cpu.frame_timestamp();
+                let start = self.cpu.timestamp();
+                while self.cpu.frame_timestamp() == frame_timestamp
+                    && self.cpu.timestamp().t_cycles() - start.t_cycles() < pacing::FRAME_T_CYCLES as u64
+                {
+                    self.cpu.update_joypad(self.joypad_status());
+                    self.cpu.step();
+                    while self.cpu.poll_event().is_some() {}
+                    self.cpu.get_audio_output();
+                    let size = self.cpu.display_size();
+                    if let Some(frame_buffer) = self.cpu.get_display_output() {
+                        let frame = self.filters.apply(frame_buffer, size);
+                        self.renderer.draw_frame(0, &frame.pixels, (frame.width, frame.height)).expect("drawing frame failed");
+                    }
+                }
+            },
+            Ok(false) => {
+                self.renderer.show_status("Can't rewind any further");
+                self.rewinding = false;
+            },
+            Err(e) => {
+                self.renderer.show_status(&e);
+                self.rewinding = false;
+            },
+        }
+
+        self.renderer.present();
+        if !self.rewinding {
+            self.pacer.resync();
+        } else if !self.fast_forward {
+            thread::sleep(pacing::FRAME_DURATION.saturating_sub(start.elapsed()));
+        }
+        match self.get_events() {
+            Ok(_) => self.cpu.update_joypad(self.joypad_status()),
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    /// Queues a chunk of the main core's audio for the device. With SyncMode::Audio, waiting on
+    /// it paces emulation; otherwise a full queue (the device stalled) drops the chunk rather
+    /// than holding up emulation, and the sample rate is nudged towards AUDIO_LATENCY_MS.
+    fn send_audio(&mut self, audio_output: [[f32; 2]; AUDIO_SAMPLES]) {
+        let queued = &self.pacer.queued;
+        queued.fetch_add(AUDIO_SAMPLES, Ordering::Relaxed);
+        if self.pacer.sync == SyncMode::Audio && !self.fast_forward {
+            self.audio_tx.send(audio_output).unwrap();
+            return;
+        }
+
+        if self.audio_tx.try_send(audio_output).is_err() {
+            queued.fetch_sub(AUDIO_SAMPLES, Ordering::Relaxed);
+        }
+        self.cpu.set_audio_rate(self.pacer.rate());
+    }
+
+    /// Runs the second core (if any) until it has caught up to the main core's t_cycles.
+    fn step_second_core(&mut self, t_cycles: u64) {
+        let player2_status = self.second.as_ref().map(|second| self.player2_status(second.key_status));
+        let second = match &mut self.second {
+            Some(second) => second,
+            None => return,
+        };
+
+        second.cycles_behind += t_cycles as i64;
+        while second.cycles_behind > 0 {
+            second.cpu.update_joypad(player2_status.unwrap_or(0xFF));
+            second.cycles_behind -= second.cpu.step() as i64;
+
+            while let Some(event) = second.cpu.poll_event() {
+                match event {
+                    CoreEvent::VBlank => { second.autosave.step(&mut second.cpu, false); },
+                    CoreEvent::RumbleIntensity(intensity) => self.gamepads.set_rumble(1, intensity),
+                    // the controller follows the smoothed RumbleIntensity rather than the motor itself
+                    CoreEvent::RumbleChanged(_) => {},
+                    event => self.renderer.show_status(&format!("(second core) {}", event)),
+                }
+            }
+
+            // only the main core's audio is played
+            second.cpu.get_audio_output();
+
+            let size = second.cpu.display_size();
+            if let Some(frame_buffer) = second.cpu.get_display_output() {
+                self.renderer.draw_frame(1, frame_buffer, size).expect("drawing frame failed");
+            }
+        }
+    }
+
+    /// Returns the main core's joypad status: the movie's input while there's one, otherwise
+    /// the keys held down plus the buttons the script holds (if any).
+    fn joypad_status(&self) -> u8 {
+        match &self.movie {
+            Some(session) => session.status,
+            None => self.input_status(),
+        }
+    }
+
+    /// Returns the second player's joypad status, given the keys they hold down (key_status).
+    fn player2_status(&self, key_status: u8) -> u8 {
+        let status = self.turbo.apply(key_status & self.gamepads.status(1), self.turbo_status[1]);
+        if self.block_opposing { block_opposing(status) } else { status }
+    }
+
+    fn input_status(&self) -> u8 {
+        let mut status = self.turbo.apply(self.key_status & self.gamepads.status(0), self.turbo_status[0]);
+        if self.block_opposing {
+            status = block_opposing(status);
+        }
+        #[cfg(feature = "lua")]
+        if let Some(script) = &self.script {
+            return status & !script.held_buttons();
+        }
+        status
     }
 
-    /// Steps SDL2 joypad input, texture display and audio callback
-    fn step_emulator(&mut self, texture: &mut Texture, rect: Rect) {
-        if self.cpu.entered_hblank() {
-            match self.get_events() {
-                Ok(_) => self.cpu.update_joypad(self.key_status),
-                Err(e) => panic!("{}", e)
+    /// Runs the script's memory hooks, or also its frame callbacks if frame (stopping it if
+    /// it fails).
+    #[cfg(feature = "lua")]
+    fn step_script(&mut self, frame: bool) {
+        let script = match &mut self.script {
+            Some(script) => script,
+            None => return,
+        };
+
+        let mut result = script.on_memory_accesses(&mut self.cpu);
+        if frame && result.is_ok() {
+            result = script.on_frame(&mut self.cpu);
+        }
+        if let Some(status) = script.take_status() {
+            self.renderer.show_status(&status);
+        }
+        if let Err(e) = result {
+            println!("Script stopped: {}", e);
+            self.renderer.show_status("Script stopped (see the terminal)");
+            self.script = None;
+        }
+    }
+
+    /// Handles what the main core's last step produced: its events, audio and frame (drawn,
+    /// to be shown when the frame's presented).
+    fn step_emulator(&mut self) {
+        #[cfg(feature = "lua")]
+        self.step_script(false);
+
+        while let Some(event) = self.cpu.poll_event() {
+            match event {
+                CoreEvent::VBlank => {
+                    #[cfg(feature = "lua")]
+                    self.step_script(true);
+                    self.play_session.frames += 1;
+                    // movies start from no battery save, so none is written while they run
+                    if self.movie.is_none() && self.autosave.step(&mut self.cpu, false) {
+                        self.play_session.saves += 1;
+                    }
+                    if self.play_log_written.elapsed() >= PLAY_LOG_INTERVAL {
+                        self.save_play_log();
+                    }
+                    self.step_profiling();
+                },
+                CoreEvent::NotResponding => self.save_diagnostics(),
+                CoreEvent::RumbleIntensity(intensity) => self.gamepads.set_rumble(0, intensity),
+                // the controller follows the smoothed RumbleIntensity rather than the motor itself
+                CoreEvent::RumbleChanged(_) => {},
+                event => self.renderer.show_status(&event.to_string()),
+            }
+        }
+
+        if let Some(audio_output) = self.cpu.get_audio_output() {
+            self.record_audio(&audio_output);
+            self.send_audio(audio_output);
+        }
+
+        let size = self.cpu.display_size();
+        // movies don't record clock changes, so they'd play back differently
+        let has_clock = self.cpu.has_rtc() && self.movie.is_none();
+        if let Some(frame_buffer) = self.cpu.get_display_output() {
+            #[cfg(feature = "lua")]
+            let overlaid = self.script.as_ref().and_then(|script| script.draw_overlay(frame_buffer, size));
+            #[cfg(not(feature = "lua"))]
+            let overlaid: Option<Vec<u8>> = None;
+            let frame = self.filters.apply(overlaid.as_deref().unwrap_or(frame_buffer), size);
+            self.renderer.draw_frame(0, &frame.pixels, (frame.width, frame.height)).expect("drawing frame failed");
+            // frames are dumped as the core drew them, before filtering
+            if std::mem::take(&mut self.magnify_next_frame) {
+                self.magnifier = Some(Magnifier::freeze(frame_buffer, size));
+            }
+            if std::mem::take(&mut self.pause_next_frame) {
+                self.pause_menu = Some(PauseMenu::open(frame_buffer, size, has_clock));
+                // the game shouldn't see keys held down for the menu once it resumes
+                self.key_status = 0xFF;
+                self.turbo_status = [0xFF; 2];
+                self.renderer.show_status("Paused");
+            }
+            let video_failed = self.video.as_mut().and_then(|video| video.add_frame(frame_buffer).err());
+            let screenshot = std::mem::take(&mut self.screenshot_next_frame).then(|| frame_buffer.to_vec());
+            if let Some(frame) = self.frame_dump.is_some().then(|| frame_buffer.to_vec()) {
+                self.dump_frame(&frame);
+            }
+            if let Some(frame) = screenshot {
+                self.save_screenshot(&frame, size);
+            }
+            if let Some(e) = video_failed {
+                eprintln!("Video stopped: {}", e);
+                self.video = None;
             }
+            self.show_magnifier();
+            self.show_pause_menu();
         }
+    }
+
+    /// Takes the next frame's input from the keys into the movie being recorded, or from the
+    /// movie being played back (ending it, and handing control back to the keys, at its end).
+    fn step_movie(&mut self) {
+        let status = self.input_status();
+        let session = match &mut self.movie {
+            Some(session) => session,
+            None => return,
+        };
 
-        match self.cpu.get_audio_output() {
-            Some(audio_output) => self.audio_tx.send(audio_output).unwrap(),
-            None => {}
+        if session.recording {
+            session.movie.record(status);
+            session.status = status;
+        } else if let Some(input) = session.movie.input(session.frame) {
+            session.status = input;
+        } else {
+            self.renderer.show_status("Movie finished");
+            self.movie = None;
+            return;
         }
+        session.frame += 1;
+    }
 
-        match self.cpu.get_display_output() {
-            Some(frame_buffer) => {
-                texture
-                    .update(None, frame_buffer, LCD_BYTE_WIDTH)
-                    .expect("texture update failed");
+    /// Goes back RERECORD_FRAMES frames in the movie being recorded, or starts recording over
+    /// the one being played back from the current frame (see RERECORD_HOTKEY).
+    fn rerecord(&mut self) {
+        let session = match &mut self.movie {
+            Some(session) => session,
+            None => return,
+        };
+        if !session.recording {
+            session.movie.rerecord(session.frame);
+            session.recording = true;
+            let status = format!("Recording over the movie from frame {}", session.frame);
+            self.renderer.show_status(&status);
+            return;
+        }
 
-                self.canvas.copy(&texture, None, rect).unwrap();
-                self.canvas.present();
+        let frame = session.frame.saturating_sub(RERECORD_FRAMES);
+        session.movie.rerecord(frame);
+        let cartridge = match self.reload_cartridge() {
+            Ok(cartridge) => cartridge,
+            Err(e) => {
+                self.renderer.show_status(&e);
+                return;
             }
-            None => {}
         };
+        if let Some(mut session) = self.movie.take() {
+            let (cpu, status) = session.movie.replay(cartridge, frame, |cpu| self.configure_core(cpu));
+            self.power_on(cpu);
+            session.status = status;
+            session.frame = frame;
+            let status = format!("Re-recording from frame {} (re-record {})", frame, session.movie.rerecords);
+            self.renderer.show_status(&status);
+            self.movie = Some(session);
+        }
+    }
+
+    /// Writes the movie being recorded (if any) to its file.
+    fn save_movie(&mut self) {
+        let session = match &self.movie {
+            Some(session) if session.recording => session,
+            _ => return,
+        };
+        match write(&session.path, session.movie.to_string()) {
+            Ok(_) => println!("Movie written to {} ({} frames, {} re-records)", session.path, session.movie.len(), session.movie.rerecords),
+            Err(e) => eprintln!("Unable to write movie {}: {}", session.path, e),
+        }
+    }
+
+    /// Adds a chunk of the main core's audio (or for VGM, the APU writes so far) to the running
+    /// recording and video, ending them if that fails.
+    fn record_audio(&mut self, audio_output: &[[f32; 2]]) {
+        if let Some(Err(e)) = self.video.as_mut().map(|video| video.add_audio(audio_output)) {
+            eprintln!("Video stopped: {}", e);
+            self.video = None;
+        }
+
+        let recorder = match &mut self.recorder {
+            Some(recorder) => recorder,
+            None => return,
+        };
+
+        recorder.add_apu_writes(&mut self.cpu);
+        if let Err(e) = recorder.add_audio(audio_output) {
+            eprintln!("Recording stopped: {}", e);
+            self.recorder = None;
+            self.cpu.set_apu_write_log(false);
+        }
+    }
+
+    /// Draws the magnified part of the frozen frame (if the magnifier is open) over the main
+    /// core's screen, describing the pixel under the cursor next to the title.
+    fn show_magnifier(&mut self) {
+        let magnifier = match &self.magnifier {
+            Some(magnifier) => magnifier,
+            None => return,
+        };
+
+        let (view, size) = magnifier.view();
+        self.renderer.draw_frame(0, &view, size).expect("drawing frame failed");
+        let palette_index = magnifier.pixel_under_cursor().and_then(|(x, y)| self.cpu.palette_index_at(x, y));
+        self.renderer.show_status(&magnifier.describe(palette_index));
+    }
+
+
+    /// Draws the pause menu (if it's open) over the main core's screen.
+    fn show_pause_menu(&mut self) {
+        if let Some(menu) = &self.pause_menu {
+            let (view, size) = menu.view();
+            self.renderer.draw_frame(0, &view, size).expect("drawing frame failed");
+        }
+    }
+
+    /// Moves through the pause menu with one of player 1's keys (see MENU_HOTKEY); returns an
+    /// error when Quit is chosen.
+    fn pause_menu_input(&mut self, key: Keycode) -> Result<(), &'static str> {
+        // positions in player 1's keys (in the same order as KEYMAPPINGS)
+        const START: usize = 0;
+        const B: usize = 2;
+        const A: usize = 3;
+        const DOWN: usize = 4;
+        const UP: usize = 5;
+
+        let menu = match &mut self.pause_menu {
+            Some(menu) => menu,
+            None => return Ok(()),
+        };
+        match self.bindings.buttons(0).iter().position(|&mapping| mapping == key) {
+            Some(UP) => menu.move_selection(-1),
+            Some(DOWN) => menu.move_selection(1),
+            Some(B) => self.choose(MenuItem::Resume)?,
+            Some(A | START) => {
+                let item = menu.selected();
+                self.choose(item)?;
+            },
+            _ => {},
+        }
+        self.show_pause_menu();
+        Ok(())
+    }
+
+    /// Does what item in the pause menu does; returns an error for Quit.
+    fn choose(&mut self, item: MenuItem) -> Result<(), &'static str> {
+        match item {
+            MenuItem::Resume => {
+                self.pause_menu = None;
+                self.renderer.show_status("Resumed");
+            },
+            MenuItem::Reset => {
+                self.pause_menu = None;
+                match self.reset() {
+                    Ok(_) => self.renderer.show_status("Reset"),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        self.renderer.show_status(&e);
+                    },
+                }
+            },
+            MenuItem::Palette => {
+                self.cpu.cycle_dmg_palette();
+                self.renderer.show_status("Switched to the next palette (DMG games only), seen once resumed");
+            },
+            MenuItem::ClockAhead => {
+                self.cpu.adjust_rtc(CLOCK_STEP_SECONDS);
+                self.renderer.show_status("Moved the game's clock an hour ahead");
+            },
+            MenuItem::ClockBack => {
+                self.cpu.adjust_rtc(-CLOCK_STEP_SECONDS);
+                self.renderer.show_status("Moved the game's clock an hour back");
+            },
+            MenuItem::Quit => {
+                self.flush_saves();
+                return Err("User Exited");
+            },
+        }
+        Ok(())
+    }
+
+    /// Types key into the cheat code being entered (see CHEAT_HOTKEY).
+    fn cheat_entry_input(&mut self, key: Keycode) {
+        let entry = match &mut self.cheat_entry {
+            Some(entry) => entry,
+            None => return,
+        };
+        match key {
+            Keycode::Escape => {
+                self.cheat_entry = None;
+                self.renderer.show_status("Cheat entry cancelled");
+                return;
+            },
+            Keycode::Return | Keycode::KpEnter => {
+                let code = self.cheat_entry.take().unwrap_or_default();
+                self.enter_cheat(&code);
+                return;
+            },
+            Keycode::Backspace => { entry.pop(); },
+            key => {
+                let name = key.name();
+                if name.len() == 1 && name.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+                    entry.push_str(&name);
+                }
+            },
+        }
+        let status = format!("Cheat code: {}_ (Enter to add, Escape to cancel)", entry);
+        self.renderer.show_status(&status);
+    }
+
+    /// Adds code to the main core, or turns it off (or back on) if it's there already.
+    fn enter_cheat(&mut self, code: &str) {
+        let code = code.trim().to_ascii_uppercase();
+        let existing = self.cpu.cheats().iter().position(|cheat| cheat.code == code);
+        let status = match existing {
+            Some(index) => {
+                let enabled = !self.cpu.cheats()[index].enabled;
+                self.cpu.set_cheat_enabled(index, enabled);
+                format!("Cheat {} {}", code, if enabled { "on" } else { "off" })
+            },
+            None => match self.cpu.add_cheat(&code) {
+                Ok(index) => format!("Cheat {} on: {}", code, self.cpu.cheats()[index].kind),
+                Err(e) => e,
+            },
+        };
+        println!("{}", status);
+        self.renderer.show_status(&status);
+    }
+
+    /// Passes frames profiled since the last call on to the overlay and profile dump.
+    fn step_profiling(&mut self) {
+        let frames = self.cpu.take_frame_profiles();
+        if frames.is_empty() {
+            return;
+        }
+
+        if let Some(dump) = &mut self.profile_dump {
+            match dump.add_frames(&frames) {
+                Ok(false) => {},
+                Ok(true) => self.profile_dump = None,
+                Err(e) => {
+                    eprintln!("Profile dump stopped: {}", e);
+                    self.profile_dump = None;
+                },
+            }
+        }
+
+        if let Some(overlay) = &mut self.profile_overlay {
+            overlay.extend(frames);
+            if overlay.len() >= PROFILE_OVERLAY_FRAMES {
+                self.renderer.show_status(&FrameProfile::average(overlay).summary());
+                overlay.clear();
+            }
+        }
+
+        if self.profile_dump.is_none() && self.profile_overlay.is_none() {
+            self.cpu.set_profiling(false);
+        }
+    }
+
+    /// Adds frame to the running frame dump, ending it when it's done or fails.
+    fn dump_frame(&mut self, frame: &[u8]) {
+        let dump = match &mut self.frame_dump {
+            Some(dump) => dump,
+            None => return,
+        };
+
+        match dump.add_frame(&self.cpu, frame) {
+            Ok(false) => {},
+            Ok(true) => self.frame_dump = None,
+            Err(e) => {
+                eprintln!("Frame dump stopped: {}", e);
+                self.frame_dump = None;
+            },
+        }
+    }
+
+    /// Writes frame (as the main core drew it, display_size() pixels big) to SCREENSHOTS_PATH
+    /// as a PNG, paletted for DMG games, and says where in the window title.
+    fn save_screenshot(&mut self, frame: &[u8], size: (usize, usize)) {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        let dir = self.data_dir.path(SCREENSHOTS_PATH);
+        let path = format!("{}/{}", dir, screenshot::file_name(&self.cpu.title(), timestamp));
+
+        let result = screenshot::encode_png(frame, size, !self.cpu.cgb_mode())
+            .and_then(|png| create_dir_all(&dir).and_then(|_| write(&path, png)).map_err(|e| e.to_string()));
+        match result {
+            Ok(_) => self.renderer.show_status(&format!("Screenshot saved to {}", path)),
+            Err(e) => eprintln!("Unable to save screenshot {}: {}", path, e),
+        }
+    }
+
+    /// Writes what the watchdog captured to a folder in DIAGNOSTICS_PATH (the report as
+    /// report.txt, the address space as memory.bin) and warns in the window title.
+    fn save_diagnostics(&mut self) {
+        let diagnostics = match self.cpu.take_diagnostics() {
+            Some(diagnostics) => diagnostics,
+            None => return,
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let dir = format!("{}/report-{}", self.data_dir.path(DIAGNOSTICS_PATH), timestamp);
+
+        let result = create_dir_all(&dir)
+            .and_then(|_| write(format!("{}/report.txt", dir), diagnostics.report))
+            .and_then(|_| write(format!("{}/memory.bin", dir), diagnostics.memory));
+        let status = match result {
+            Ok(_) => format!("Game not responding! Diagnostics saved to {}", dir),
+            Err(e) => format!("Game not responding! Unable to save diagnostics: {}", e),
+        };
+
+        eprintln!("{}", status);
+        self.renderer.show_status(&status);
+    }
+
+    /// Writes battery saves of both cores if they have unsaved changes, the play log and
+    /// the running recording and video.
+    fn flush_saves(&mut self) {
+        self.stop_recording();
+        self.stop_video();
+        self.save_movie();
+        if self.autosave.step(&mut self.cpu, true) {
+            self.play_session.saves += 1;
+        }
+        if let Some(second) = &mut self.second {
+            second.autosave.step(&mut second.cpu, true);
+        }
+        self.save_play_log();
+    }
+
+    /// Writes the main core's game stats, counting emulated time as time played
+    /// (so pauses and slowdown don't count).
+    fn save_play_log(&mut self) {
+        self.play_log_written = Instant::now();
+        let play_time = self.time_before_reset + self.cpu.timestamp().as_duration();
+        if let Err(e) = self.play_session.save(play_time) {
+            eprintln!("{}", e);
+        }
     }
 
     fn get_events(&mut self) -> Result<(), &str> { 
+        let mut magnifier_moved = false;
+        let mut toggle_sram_wait = false;
+        let mut toggle_recording = false;
+        let mut toggle_video = false;
+        let mut toggle_pause_menu = false;
+        // player 1's keys pressed while the pause menu is open, which go to it instead
+        let mut menu_keys = Vec::new();
+        // keys pressed while a cheat code is being typed, which go to it instead
+        let mut cheat_keys = Vec::new();
+        // (channel, solo) when a channel hotkey was pressed
+        let mut channel_toggled = None;
+        // converts a position in the window (or a movement, without the offset) to fractions of
+        // where the main core's frames are drawn
+        let area = self.renderer.frame_area(0).unwrap_or_else(|| {
+            let (width, height) = self.cpu.display_size();
+            Rect::new(0, 0, width as u32 * SCREEN_SCALE as u32, height as u32 * SCREEN_SCALE as u32)
+        });
+        let screen_fraction = |x: i32, y: i32, offset: bool| {
+            let (x, y) = if offset { (x - area.x(), y - area.y()) } else { (x, y) };
+            (x as f32 / area.width() as f32, y as f32 / area.height() as f32)
+        };
         for event in self.event_pump.poll_iter() {
+            let hotkey = match event {
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } => self.bindings.hotkey(key),
+                // quitting works on repeats too, in case the first press was missed
+                Event::KeyDown { keycode: Some(key), .. } => self.bindings.hotkey(key).filter(|&hotkey| hotkey == Hotkey::Quit),
+                _ => None,
+            };
             match event {
+                Event::KeyDown { keycode: Some(key), .. } if self.cheat_entry.is_some() => {
+                    cheat_keys.push(key);
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Cheat) => {
+                    self.cheat_entry = Some(String::new());
+                    self.renderer.show_status("Cheat code: _ (Enter to add, Escape to cancel)");
+                },
                 Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    self.cpu.save_mbc_state();
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Quit) => {
+                    self.flush_saves();
                     return Err("User Exited");
                 },
-                Event::KeyDown { keycode: Some(key), ..} => {   
-                    for i in 0..8 {
-                        if KEYMAPPINGS[i] == key {
-                            self.key_status &= !(1 << (7 - i));
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Palette) => {
+                    self.cpu.cycle_dmg_palette();
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::SramWait) => {
+                    toggle_sram_wait = true;
+                },
+                Event::KeyDown { keymod, .. } if matches!(hotkey, Some(Hotkey::Channel(_))) => {
+                    if let Some(Hotkey::Channel(ch)) = hotkey {
+                        channel_toggled = Some((ch, keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD)));
+                    }
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::LayerTints) => {
+                    let on = !self.cpu.layer_tints();
+                    self.cpu.set_layer_tints(on);
+                    self.renderer.show_status(if on {
+                        "Layer tints: BG blue, window green, OBJ0 red, OBJ1 yellow, BG priority magenta"
+                    } else {
+                        "Layer tints off"
+                    });
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Warnings) => {
+                    println!("{}", self.cpu.warnings_report());
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::BusStats) => {
+                    match self.cpu.bus_stats_report() {
+                        Some(report) => println!("{}", report),
+                        None => println!("Bus statistics are off (set BUS_STATS in src/config.rs)"),
+                    }
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Profile) => {
+                    if self.profile_overlay.take().is_some() {
+                        self.renderer.show_status("Profiling off");
+                    } else {
+                        self.profile_overlay = Some(Vec::new());
+                        self.cpu.set_profiling(true);
+                    }
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Magnifier) => {
+                    if self.magnifier.take().is_some() {
+                        self.renderer.show_status("Magnifier off");
+                    } else {
+                        self.magnify_next_frame = true;
+                    }
+                },
+                Event::MouseMotion { x, y, xrel, yrel, mousestate, .. } if self.magnifier.is_some() => {
+                    let (cursor, drag) = (screen_fraction(x, y, true), screen_fraction(xrel, yrel, false));
+                    if let Some(magnifier) = &mut self.magnifier {
+                        if mousestate.left() {
+                            magnifier.drag(drag);
                         }
+                        magnifier.move_cursor(cursor);
+                    }
+                    magnifier_moved = true;
+                },
+                Event::MouseWheel { y, .. } => {
+                    if let Some(magnifier) = &mut self.magnifier {
+                        magnifier.zoom(y);
+                        magnifier_moved = true;
+                    }
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Menu) => {
+                    toggle_pause_menu = true;
+                },
+                Event::KeyDown { keycode: Some(key), .. } if self.pause_menu.is_some() && self.bindings.buttons(0).contains(&key) => {
+                    menu_keys.push(key);
+                },
+                Event::ControllerDeviceAdded { which, .. } => self.gamepads.connect(which),
+                Event::ControllerDeviceRemoved { which, .. } => self.gamepads.disconnect(which),
+                Event::ControllerButtonDown { which, button, .. } => {
+                    let pressed = self.gamepads.button(which, button, true);
+                    // the pause menu's moved through with controllers too
+                    if let Some(i) = pressed.filter(|_| self.pause_menu.is_some()) {
+                        menu_keys.push(self.bindings.buttons(0)[i]);
+                    }
+                },
+                Event::ControllerButtonUp { which, button, .. } => {
+                    self.gamepads.button(which, button, false);
+                },
+                Event::ControllerAxisMotion { which, axis, value, .. } => self.gamepads.axis(which, axis, value),
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Rerecord) && self.movie.is_some() => {
+                    self.rerecord_pending = true;
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Fullscreen) => {
+                    self.renderer.toggle_fullscreen();
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Stretch) => {
+                    let stretched = self.renderer.toggle_stretch();
+                    self.renderer.show_status(if stretched { "Stretching frames" } else { "Keeping the aspect ratio" });
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Record) => {
+                    toggle_recording = true;
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Video) => {
+                    toggle_video = true;
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::FrameDump) => {
+                    match FrameDump::start(&self.data_dir.path(FRAME_DUMP_PATH), FRAME_DUMP_COUNT) {
+                        Ok(dump) => self.frame_dump = Some(dump),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Screenshot) => {
+                    self.screenshot_next_frame = true;
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::Rewind) => {
+                    match &self.rewind {
+                        Some(_) => self.rewinding = true,
+                        None => self.renderer.show_status("Rewinding is off (start with --rewind)"),
+                    }
+                },
+                Event::KeyUp { keycode: Some(key), .. } if self.rewinding && key == self.bindings.key(Hotkey::Rewind) => {
+                    self.rewinding = false;
+                    self.pacer.resync();
+                },
+                Event::KeyDown { .. } if hotkey == Some(Hotkey::FastForward) => {
+                    self.fast_forward = true;
+                },
+                Event::KeyUp { keycode: Some(key), .. } if self.fast_forward && key == self.bindings.key(Hotkey::FastForward) => {
+                    self.fast_forward = false;
+                    // pacing starts over from here rather than waiting for the wall clock to catch up
+                    self.pacer.resync();
+                },
+                Event::KeyDown { keycode: Some(key), ..} => {   
+                    update_key_status(&mut self.key_status, self.bindings.buttons(0), key, true);
+                    if update_tilt_status(&mut self.tilt_status, key, true) {
+                        let (x, y) = tilt(self.tilt_status);
+                        self.cpu.set_tilt(x, y);
+                    }
+                    for player in 0..2 {
+                        update_turbo_status(&mut self.turbo_status[player], self.bindings.turbo_buttons(player), key, true);
+                    }
+                    match &mut self.second {
+                        Some(second) => update_key_status(&mut second.key_status, self.bindings.buttons(1), key, true),
+                        None => update_key_status(&mut self.player2_key_status, self.bindings.buttons(1), key, true),
                     }
                 }
                 Event::KeyUp { keycode: Some(key), .. } => {
-                    for i in 0..8 {
-                        if KEYMAPPINGS[i] == key {
-                            self.key_status |= 1 << (7 - i);
-                        }
+                    update_key_status(&mut self.key_status, self.bindings.buttons(0), key, false);
+                    if update_tilt_status(&mut self.tilt_status, key, false) {
+                        let (x, y) = tilt(self.tilt_status);
+                        self.cpu.set_tilt(x, y);
+                    }
+                    for player in 0..2 {
+                        update_turbo_status(&mut self.turbo_status[player], self.bindings.turbo_buttons(player), key, false);
+                    }
+                    match &mut self.second {
+                        Some(second) => update_key_status(&mut second.key_status, self.bindings.buttons(1), key, false),
+                        None => update_key_status(&mut self.player2_key_status, self.bindings.buttons(1), key, false),
                     }
                 }
                 _ => {}
             }
         }
 
+        if toggle_sram_wait {
+            let m_cycles = if self.cpu.sram_wait() == 0 { self.sram_wait_cycles } else { 0 };
+            self.set_sram_wait(m_cycles);
+        }
+        if toggle_recording {
+            if self.recorder.is_some() {
+                self.stop_recording();
+            } else if let Err(e) = self.start_recording(self.record_format) {
+                eprintln!("{}", e);
+            }
+        }
+        if toggle_video {
+            if self.video.is_some() {
+                self.stop_video();
+            } else if let Err(e) = self.start_video(self.video_format, self.video_audio) {
+                eprintln!("{}", e);
+                self.renderer.show_status(&e);
+            }
+        }
+        if toggle_pause_menu {
+            if self.pause_menu.is_some() {
+                self.choose(MenuItem::Resume)?;
+            } else {
+                self.pause_next_frame = true;
+            }
+        }
+        for key in cheat_keys {
+            self.cheat_entry_input(key);
+        }
+        for key in menu_keys {
+            self.pause_menu_input(key)?;
+        }
+        if let Some((ch, solo)) = channel_toggled {
+            self.toggle_channel(ch, solo);
+        }
+        if magnifier_moved {
+            self.show_magnifier();
+        }
+
+        if let Some(music) = &mut self.music {
+            if !music.step(&mut self.key_status) {
+                self.flush_saves();
+                return Err("User Exited");
+            }
+        }
+
         Ok(())
     }
 }
 
+/// Sets (pressed) or clears (released) the bit of key in tilt_status if it is in 
+/// TILT_KEYMAPPINGS; returns true if tilt_status changed.
+fn update_tilt_status(tilt_status: &mut u8, key: Keycode, pressed: bool) -> bool {
+    let old_status = *tilt_status;
+    for (i, mapping) in TILT_KEYMAPPINGS.iter().enumerate() {
+        if *mapping == key {
+            if pressed {
+                *tilt_status |= 1 << i;
+            } else {
+                *tilt_status &= !(1 << i);
+            }
+        }
+    }
+    *tilt_status != old_status
+}
+
+/// Returns the tilt (in g, positive is right / down) of the cartridge with tilt_status held.
+fn tilt(tilt_status: u8) -> (f32, f32) {
+    let axis = |negative: u8, positive: u8| {
+        (tilt_status >> positive & 1) as f32 - (tilt_status >> negative & 1) as f32
+    };
+    (axis(2, 3), axis(1, 0))
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.flush_saves();
+    }
+}
+
+/// Settings given to the emulator for every core, kept to set up cores made later on.
+#[derive(Default)]
+struct CoreSettings {
+    turbo: Option<u32>,
+    palette: Option<DmgPalette>,
+    ghosting: Option<u8>,
+    resample_quality: Option<ResampleQuality>,
+    high_pass: Option<bool>,
+    lcd_off_policy: Option<LcdOffPolicy>,
+    colour_correction: Option<ColourCorrection>,
+}
+
+impl CoreSettings {
+    fn apply(&self, cpu: &mut Cpu) {
+        if let Some(multiplier) = self.turbo {
+            cpu.set_turbo(multiplier);
+        }
+        if let Some(palette) = self.palette {
+            cpu.set_dmg_palette(palette);
+        }
+        if let Some(ghosting) = self.ghosting {
+            cpu.set_lcd_ghosting(ghosting);
+        }
+        if let Some(quality) = self.resample_quality {
+            cpu.set_resample_quality(quality);
+        }
+        if let Some(on) = self.high_pass {
+            cpu.set_high_pass(on);
+        }
+        if let Some(policy) = self.lcd_off_policy {
+            cpu.set_lcd_off_policy(policy);
+        }
+        if let Some(correction) = self.colour_correction {
+            cpu.set_colour_correction(correction);
+        }
+    }
+}
+
+/// Decides when a core's battery save gets written, based on when the game writes to it.
+struct Autosave {
+    last_write: Option<Instant>,
+    unsaved_since: Option<Instant>,
+}
+
+impl Autosave {
+    fn new() -> Self {
+        Autosave {
+            last_write: None,
+            unsaved_since: None,
+        }
+    }
+
+    /// Saves cpu's battery-backed state if it's due (see AUTOSAVE_DELAY), or if force is set
+    /// and there are unsaved changes; meant to be called once per frame. Returns true if it saved.
+    fn step(&mut self, cpu: &mut Cpu, force: bool) -> bool {
+        let now = Instant::now();
+        if cpu.take_ram_dirty() {
+            self.last_write = Some(now);
+            self.unsaved_since.get_or_insert(now);
+        }
+
+        let (last_write, unsaved_since) = match (self.last_write, self.unsaved_since) {
+            (Some(last_write), Some(unsaved_since)) => (last_write, unsaved_since),
+            _ => return false,
+        };
+
+        if force || now - last_write >= AUTOSAVE_DELAY || now - unsaved_since >= AUTOSAVE_MAX_DELAY {
+            cpu.save_mbc_state();
+            self.last_write = None;
+            self.unsaved_since = None;
+            return true;
+        }
+        false
+    }
+}
+
+/// Writes per-subsystem frame times to a CSV file, one row per frame (in microseconds).
+struct ProfileDump {
+    path: String,
+    writer: BufWriter<File>,
+    frames_left: usize,
+    frames_written: usize,
+}
+
+impl ProfileDump {
+    fn start(root: &str, frame_count: usize) -> Result<Self, String> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let path = format!("{}/profile-{}.csv", root, timestamp);
+
+        let file = create_dir_all(root).and_then(|_| File::create(&path))
+            .map_err(|e| format!("Unable to create {}: {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "frame,cpu_us,ppu_us,apu_us,dma_us,total_us").map_err(|e| e.to_string())?;
+
+        println!("Profiling {} frames to {}", frame_count, path);
+        Ok(ProfileDump { path, writer, frames_left: frame_count, frames_written: 0 })
+    }
+
+    /// Writes frames (up to the requested count); returns true once all frames have been written.
+    fn add_frames(&mut self, frames: &[FrameProfile]) -> Result<bool, String> {
+        for frame in frames.iter().take(self.frames_left) {
+            let us = |duration: Duration| duration.as_micros();
+            writeln!(self.writer, "{},{},{},{},{},{}", self.frames_written,
+                us(frame.cpu), us(frame.ppu), us(frame.apu), us(frame.dma), us(frame.total()))
+                .map_err(|e| format!("Unable to write {}: {}", self.path, e))?;
+            self.frames_written += 1;
+            self.frames_left -= 1;
+        }
+
+        if self.frames_left > 0 {
+            return Ok(false);
+        }
+        self.writer.flush().map_err(|e| format!("Unable to write {}: {}", self.path, e))?;
+        println!("Profiled {} frames to {}", self.frames_written, self.path);
+        Ok(true)
+    }
+}
+
+/// Connects the split-screen cores cpu and second_cpu with a link cable, and faces their IR ports.
+fn connect_cores(cpu: &mut Cpu, second_cpu: &mut Cpu) {
+    let (port, second_port) = LinkPort::pair();
+    cpu.connect_serial(Box::new(port));
+    second_cpu.connect_serial(Box::new(second_port));
+
+    let (ir, second_ir) = IrPort::pair();
+    cpu.connect_infrared(Box::new(ir));
+    second_cpu.connect_infrared(Box::new(second_ir));
+}
+
+/// Clears (pressed) or sets (released) the status bit of key if it is in keymappings.
+/// Holds down or lets go of turbo B or turbo A in turbo_status if key is one of turbo_keys
+/// (see TURBO_KEYMAPPINGS).
+fn update_turbo_status(turbo_status: &mut u8, turbo_keys: &[Keycode; 2], key: Keycode, pressed: bool) {
+    // B and A's bits in joypad status
+    const BITS: [u8; 2] = [1 << 5, 1 << 4];
+    for (&turbo_key, bit) in turbo_keys.iter().zip(BITS) {
+        if turbo_key == key {
+            if pressed {
+                *turbo_status &= !bit;
+            } else {
+                *turbo_status |= bit;
+            }
+        }
+    }
+}
+
+fn update_key_status(key_status: &mut u8, keymappings: &[Keycode; 8], key: Keycode, pressed: bool) {
+    for (i, mapping) in keymappings.iter().enumerate() {
+        if *mapping == key {
+            if pressed {
+                *key_status &= !(1 << (7 - i));
+            } else {
+                *key_status |= 1 << (7 - i);
+            }
+        }
+    }
+}
+
 struct Callback {
     audio_rx: Receiver<[[f32; 2]; AUDIO_SAMPLES]>,
+    // samples queued for the device (see pacing::FramePacer)
+    queued: Arc<AtomicUsize>,
     prev_sample: [f32; 2],
 }
 
@@ -199,6 +1957,7 @@ impl AudioCallback for Callback {
     fn callback(&mut self, stream: &mut [f32]) {
         match self.audio_rx.recv_timeout(Duration::from_millis(30)) {
             Ok(buffer) => {
+                self.queued.fetch_sub(buffer.len(), Ordering::Relaxed);
                 for i in 0..buffer.len() {
                     stream[i * 2] = buffer[i][0];
                     stream[i * 2 + 1] = buffer[i][1];
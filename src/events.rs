@@ -0,0 +1,117 @@
+use alloc::{collections::VecDeque, string::String};
+use core::fmt;
+use core::time::Duration;
+
+use crate::constants::T_CYCLE_HZ;
+
+// Oldest events get dropped once this many are waiting, so frontends
+// that never poll don't grow the queue forever
+const MAX_QUEUED_EVENTS: usize = 64;
+
+/// Notifications from the emulator core that frontends can poll each frame.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CoreEvent {
+    VBlank,
+    BatterySaved,
+    RumbleChanged(bool),
+    RumbleIntensity(u8),
+    PrinterPageReady,
+    SerialConnected,
+    SerialDisconnected,
+    NotResponding,
+    Error(String),
+}
+
+impl fmt::Display for CoreEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoreEvent::VBlank => write!(f, "Entered VBlank"),
+            CoreEvent::BatterySaved => write!(f, "Battery save flushed"),
+            CoreEvent::RumbleChanged(true) => write!(f, "Rumble on"),
+            CoreEvent::RumbleChanged(false) => write!(f, "Rumble off"),
+            CoreEvent::RumbleIntensity(intensity) => write!(f, "Rumble intensity {}/255", intensity),
+            CoreEvent::PrinterPageReady => write!(f, "Printer page ready"),
+            CoreEvent::SerialConnected => write!(f, "Serial link connected"),
+            CoreEvent::SerialDisconnected => write!(f, "Serial link dropped"),
+            CoreEvent::NotResponding => write!(f, "Game not responding"),
+            CoreEvent::Error(message) => write!(f, "Error: {}", message),
+        }
+    }
+}
+
+/// A CPU read or write of a watched address (see Cpu::watch_memory).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: u16,
+    /// The byte read, or written.
+    pub value: u8,
+    pub write: bool,
+}
+
+/// A point in emulated time: T-cycles (at normal speed) since power on. It only advances as the
+/// core runs, so frames and audio chunks tagged with it stay in sync however fast or unevenly
+/// the frontend runs the core (e.g. in turbo mode or while fast-forwarding).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    #[allow(dead_code)]
+    pub fn t_cycles(self) -> u64 {
+        self.0
+    }
+
+    pub fn as_duration(self) -> Duration {
+        Duration::from_nanos((self.0 as u128 * 1_000_000_000 / T_CYCLE_HZ as u128) as u64)
+    }
+}
+
+pub struct EventQueue {
+    events: VecDeque<CoreEvent>,
+}
+
+impl EventQueue {
+    pub fn new() -> Self {
+        EventQueue {
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: CoreEvent) {
+        if self.events.len() >= MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Returns the oldest event that hasn't been polled yet (if any).
+    pub fn poll(&mut self) -> Option<CoreEvent> {
+        self.events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{CoreEvent, EventQueue, Timestamp, MAX_QUEUED_EVENTS};
+
+    #[test]
+    fn event_queue_drops_oldest_test() {
+        let mut queue = EventQueue::new();
+        queue.push(CoreEvent::BatterySaved);
+        for _ in 0..MAX_QUEUED_EVENTS {
+            queue.push(CoreEvent::VBlank);
+        }
+
+        for _ in 0..MAX_QUEUED_EVENTS {
+            assert_eq!(queue.poll(), Some(CoreEvent::VBlank));
+        }
+        assert_eq!(queue.poll(), None);
+    }
+
+    #[test]
+    fn timestamp_duration_test() {
+        assert_eq!(Timestamp(1 << 22).as_duration(), Duration::from_secs(1));
+        assert_eq!(Timestamp(70224).as_duration(), Duration::from_nanos(16_742_706));
+    }
+}
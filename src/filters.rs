@@ -0,0 +1,493 @@
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
+use core::cmp::min;
+use core::str::FromStr;
+
+use crate::constants::BYTES_PER_PIXEL;
+pub use crate::frame::Frame;
+
+pub type Colour = [u8; BYTES_PER_PIXEL];
+
+impl Frame {
+    fn pixel(&self, x: usize, y: usize) -> Colour {
+        let start = (y * self.width + x) * BYTES_PER_PIXEL;
+        self.pixels[start..start + BYTES_PER_PIXEL].try_into().unwrap()
+    }
+}
+
+/// One step of post-processing (see FilterChain).
+pub trait Filter {
+    /// Changes frame in place; filters that scale it also update its size.
+    fn apply(&mut self, frame: &mut Frame);
+
+    /// Returns the size of frames coming out of this filter, given frames of size going in.
+    fn output_size(&self, size: (usize, usize)) -> (usize, usize) {
+        size
+    }
+}
+
+/// Post-processing for finished frames: filters run one after the other, in the chain's order,
+/// and can be added, removed or reordered at any time. Besides the built-in filters below,
+/// anything implementing Filter can be plugged in.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter>>,
+    frame: Frame,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        FilterChain::default()
+    }
+
+    /// Adds filter to the end of the chain.
+    pub fn push(&mut self, filter: Box<dyn Filter>) {
+        self.filters.push(filter);
+    }
+
+    pub fn insert(&mut self, index: usize, filter: Box<dyn Filter>) {
+        self.filters.insert(index, filter);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Box<dyn Filter> {
+        self.filters.remove(index)
+    }
+
+    /// Moves the filter at index from to index to, shifting the ones in between.
+    pub fn move_filter(&mut self, from: usize, to: usize) {
+        let filter = self.filters.remove(from);
+        self.filters.insert(to, filter);
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Returns the size of frames coming out of the chain, given frames of size going in.
+    pub fn output_size(&self, size: (usize, usize)) -> (usize, usize) {
+        self.filters.iter().fold(size, |size, filter| filter.output_size(size))
+    }
+
+    /// Runs frame (BGRA bytes of the given width and height) through every filter.
+    pub fn apply(&mut self, frame: &[u8], (width, height): (usize, usize)) -> &Frame {
+        self.frame.width = width;
+        self.frame.height = height;
+        self.frame.pixels.clear();
+        self.frame.pixels.extend_from_slice(frame);
+
+        for filter in &mut self.filters {
+            filter.apply(&mut self.frame);
+        }
+        &self.frame
+    }
+}
+
+/// Parses a comma-separated list of built-in filters, in order: `correct` (ColourCorrection),
+/// `blend` (FrameBlend), `scale2x` (Scale2x), `2x`/`3x`/`4x` (NearestScale), `scanlines`,
+/// `lcd` (LcdGrid) and `dotmatrix` (DotMatrix).
+impl FromStr for FilterChain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chain = FilterChain::new();
+        for name in s.split(',').map(str::trim) {
+            let filter: Box<dyn Filter> = match name {
+                "correct" => Box::new(ColourCorrection),
+                "blend" => Box::new(FrameBlend::new(0x80)),
+                "scale2x" => Box::new(Scale2x),
+                "2x" => Box::new(NearestScale(2)),
+                "3x" => Box::new(NearestScale(3)),
+                "4x" => Box::new(NearestScale(4)),
+                "scanlines" => Box::new(Scanlines { darkness: 0x40 }),
+                "lcd" => Box::new(LcdGrid),
+                "dotmatrix" => Box::new(DotMatrix),
+                _ => return Err(format!(
+                    "Unknown filter '{}' (expected correct, blend, scale2x, 2x, 3x, 4x, scanlines, lcd or dotmatrix)", name)),
+            };
+            chain.push(filter);
+        }
+        Ok(chain)
+    }
+}
+
+/// Mimics the colours of the CGB's LCD (the same correction as ColourCorrection::Simple,
+/// for frames drawn with it off).
+pub struct ColourCorrection;
+
+impl Filter for ColourCorrection {
+    fn apply(&mut self, frame: &mut Frame) {
+        for pixel in frame.pixels.chunks_exact_mut(BYTES_PER_PIXEL) {
+            let (b5, g5, r5) = ((pixel[0] >> 3) as u32, (pixel[1] >> 3) as u32, (pixel[2] >> 3) as u32);
+            pixel[0] = (min(960, r5 * 6 + g5 * 4 + b5 * 22) >> 2) as u8;
+            pixel[1] = (min(960, g5 * 24 + b5 * 8) >> 2) as u8;
+            pixel[2] = (min(960, r5 * 26 + g5 * 4 + b5 * 2) >> 2) as u8;
+        }
+    }
+}
+
+/// Swaps every pixel of colour from[i] for to[i], e.g. to give DMG games different shades.
+pub struct PaletteSwap {
+    pub from: [Colour; 4],
+    pub to: [Colour; 4],
+}
+
+impl Filter for PaletteSwap {
+    fn apply(&mut self, frame: &mut Frame) {
+        for pixel in frame.pixels.chunks_exact_mut(BYTES_PER_PIXEL) {
+            if let Some(i) = self.from.iter().position(|colour| colour == pixel) {
+                pixel.copy_from_slice(&self.to[i]);
+            }
+        }
+    }
+}
+
+/// Mixes in the previous frame (mix/255 of it), like the ghosting of the DMG's slow LCD;
+/// this smooths out games that flicker sprites on and off every other frame.
+pub struct FrameBlend {
+    mix: u8,
+    previous: Vec<u8>,
+}
+
+impl FrameBlend {
+    pub fn new(mix: u8) -> Self {
+        FrameBlend { mix, previous: Vec::new() }
+    }
+}
+
+impl Filter for FrameBlend {
+    fn apply(&mut self, frame: &mut Frame) {
+        if self.previous.len() == frame.pixels.len() {
+            let mix = self.mix as u32;
+            for (byte, &previous) in frame.pixels.iter_mut().zip(&self.previous) {
+                *byte = ((*byte as u32 * (255 - mix) + previous as u32 * mix) / 255) as u8;
+            }
+        }
+        self.previous.clear();
+        self.previous.extend_from_slice(&frame.pixels);
+    }
+}
+
+/// Scales frames up by factor, copying every pixel into a factor x factor square.
+pub struct NearestScale(pub usize);
+
+impl Filter for NearestScale {
+    fn apply(&mut self, frame: &mut Frame) {
+        let factor = self.0;
+        let (width, height) = self.output_size((frame.width, frame.height));
+        let mut pixels = Vec::with_capacity(width * height * BYTES_PER_PIXEL);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.extend_from_slice(&frame.pixel(x / factor, y / factor));
+            }
+        }
+        *frame = Frame { width, height, pixels };
+    }
+
+    fn output_size(&self, (width, height): (usize, usize)) -> (usize, usize) {
+        (width * self.0, height * self.0)
+    }
+}
+
+/// Doubles frames in size with the Scale2x (EPX) algorithm, which rounds off diagonal edges.
+pub struct Scale2x;
+
+impl Filter for Scale2x {
+    fn apply(&mut self, frame: &mut Frame) {
+        let (width, height) = (frame.width * 2, frame.height * 2);
+        let mut pixels = vec![0; width * height * BYTES_PER_PIXEL];
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let p = frame.pixel(x, y);
+                let a = if y > 0 { frame.pixel(x, y - 1) } else { p };
+                let b = if x + 1 < frame.width { frame.pixel(x + 1, y) } else { p };
+                let c = if x > 0 { frame.pixel(x - 1, y) } else { p };
+                let d = if y + 1 < frame.height { frame.pixel(x, y + 1) } else { p };
+
+                let corners = [
+                    if c == a && c != d && a != b { a } else { p },
+                    if a == b && a != c && b != d { b } else { p },
+                    if d == c && d != b && c != a { c } else { p },
+                    if b == d && b != a && d != c { d } else { p },
+                ];
+
+                for (i, colour) in corners.iter().enumerate() {
+                    let (out_x, out_y) = (x * 2 + i % 2, y * 2 + i / 2);
+                    let start = (out_y * width + out_x) * BYTES_PER_PIXEL;
+                    pixels[start..start + BYTES_PER_PIXEL].copy_from_slice(colour);
+                }
+            }
+        }
+        *frame = Frame { width, height, pixels };
+    }
+
+    fn output_size(&self, (width, height): (usize, usize)) -> (usize, usize) {
+        (width * 2, height * 2)
+    }
+}
+
+/// Darkens every other row by darkness/255, like a CRT's scanlines (best after scaling up).
+pub struct Scanlines {
+    pub darkness: u8,
+}
+
+impl Filter for Scanlines {
+    fn apply(&mut self, frame: &mut Frame) {
+        let row_bytes = frame.width * BYTES_PER_PIXEL;
+        let keep = 255 - self.darkness as u32;
+        for row in frame.pixels.chunks_exact_mut(row_bytes).skip(1).step_by(2) {
+            for pixel in row.chunks_exact_mut(BYTES_PER_PIXEL) {
+                for channel in &mut pixel[..3] {
+                    *channel = (*channel as u32 * keep / 255) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Scales colour by keep/255 (leaving alpha alone).
+fn darken(colour: Colour, keep: u32) -> Colour {
+    let [b, g, r, a] = colour;
+    let scale = |channel: u8| (channel as u32 * keep / 255) as u8;
+    [scale(b), scale(g), scale(r), a]
+}
+
+/// Triples frames in size, drawing every pixel as the red, green and blue subpixel columns of
+/// a colour LCD (as on the CGB) with a dark gap below, so the screen's grid shows at high scales.
+pub struct LcdGrid;
+
+impl Filter for LcdGrid {
+    fn apply(&mut self, frame: &mut Frame) {
+        let (width, height) = self.output_size((frame.width, frame.height));
+        let mut pixels = Vec::with_capacity(width * height * BYTES_PER_PIXEL);
+        for y in 0..height {
+            for x in 0..width {
+                let mut colour = frame.pixel(x / 3, y / 3);
+                // columns show red, green then blue (bytes 2, 1 and 0), letting a third of the others through
+                let lit = 2 - x % 3;
+                for (channel, byte) in colour[..3].iter_mut().enumerate() {
+                    if channel != lit {
+                        *byte /= 3;
+                    }
+                }
+                if y % 3 == 2 {
+                    colour = darken(colour, 0x80);
+                }
+                pixels.extend_from_slice(&colour);
+            }
+        }
+        *frame = Frame { width, height, pixels };
+    }
+
+    fn output_size(&self, (width, height): (usize, usize)) -> (usize, usize) {
+        (width * 3, height * 3)
+    }
+}
+
+/// Triples frames in size, drawing every pixel as a 2x2 dot on the DMG's dot-matrix LCD: the
+/// gaps between dots show the screen's backing, lightened, with each dot's shadow falling
+/// onto the gaps below and to the right of it.
+pub struct DotMatrix;
+
+impl Filter for DotMatrix {
+    fn apply(&mut self, frame: &mut Frame) {
+        let (width, height) = self.output_size((frame.width, frame.height));
+        let mut pixels = Vec::with_capacity(width * height * BYTES_PER_PIXEL);
+        for y in 0..height {
+            for x in 0..width {
+                let dot = x % 3 < 2 && y % 3 < 2;
+                let colour = if dot {
+                    frame.pixel(x / 3, y / 3)
+                } else {
+                    // the backing is the lightest shade, darkened by the dot up and to the left
+                    let [b, g, r, a] = frame.pixel(x.saturating_sub(1) / 3, y.saturating_sub(1) / 3);
+                    let lighten = |channel: u8| (channel as u32 + (255 - channel as u32) * 3 / 4) as u8;
+                    [lighten(b), lighten(g), lighten(r), a]
+                };
+                pixels.extend_from_slice(&colour);
+            }
+        }
+        *frame = Frame { width, height, pixels };
+    }
+
+    fn output_size(&self, (width, height): (usize, usize)) -> (usize, usize) {
+        (width * 3, height * 3)
+    }
+}
+
+/// Maps every colour through a 3D lookup table (e.g. colour grading made in an image editor,
+/// or a profile of a real screen's colours), interpolating between its entries.
+#[derive(Clone, Debug)]
+pub struct ColourLut {
+    // entries along each axis
+    size: usize,
+    // size^3 RGB entries (0.0-1.0), red changing fastest, then green, then blue
+    table: Vec<[f32; 3]>,
+}
+
+impl ColourLut {
+    /// Parses a LUT in the .cube format (a `LUT_3D_SIZE` line, then its entries as `r g b` lines).
+    pub fn from_cube(text: &str) -> Result<Self, String> {
+        let mut size = None;
+        let mut table = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("LUT_3D_SIZE") {
+                let value = value.trim().parse::<usize>().map_err(|_| format!("Invalid LUT size '{}'", value.trim()))?;
+                if !(2..=256).contains(&value) {
+                    return Err(format!("LUT size {} is out of range (2-256)", value));
+                }
+                size = Some(value);
+            } else if line.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+                let mut entry = [0.0; 3];
+                let mut values = line.split_whitespace().map(str::parse::<f32>);
+                for channel in &mut entry {
+                    match values.next() {
+                        Some(Ok(value)) => *channel = value.clamp(0.0, 1.0),
+                        _ => return Err(format!("Invalid LUT entry '{}'", line)),
+                    }
+                }
+                table.push(entry);
+            }
+            // other keywords (TITLE, DOMAIN_MIN...) are left at their defaults
+        }
+
+        let size = size.ok_or_else(|| String::from("LUT has no LUT_3D_SIZE (only 3D LUTs are supported)"))?;
+        if table.len() != size * size * size {
+            return Err(format!("LUT has {} entries, but a size of {} needs {}", table.len(), size, size * size * size));
+        }
+        Ok(ColourLut { size, table })
+    }
+
+    /// Returns how many entries the table has along each axis.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the table's size^3 RGB entries (0.0-1.0), red changing fastest, then green,
+    /// then blue (the layout of a 3D texture indexed by red, green and blue).
+    pub fn table(&self) -> &[[f32; 3]] {
+        &self.table
+    }
+
+    fn entry(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.table[(b * self.size + g) * self.size + r]
+    }
+
+    /// Looks up an RGB colour (0.0-1.0), blending the 8 entries around it.
+    fn lookup(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let max = (self.size - 1) as f32;
+        let mut low = [0; 3];
+        let mut fraction = [0.0; 3];
+        for i in 0..3 {
+            let position = rgb[i] * max;
+            low[i] = (position as usize).min(self.size - 2);
+            fraction[i] = position - low[i] as f32;
+        }
+
+        let mut out = [0.0; 3];
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let weight: f32 = (0..3)
+                .map(|i| if offset[i] == 1 { fraction[i] } else { 1.0 - fraction[i] })
+                .product();
+            let entry = self.entry(low[0] + offset[0], low[1] + offset[1], low[2] + offset[2]);
+            for i in 0..3 {
+                out[i] += entry[i] * weight;
+            }
+        }
+        out
+    }
+}
+
+impl Filter for ColourLut {
+    fn apply(&mut self, frame: &mut Frame) {
+        for pixel in frame.pixels.chunks_exact_mut(BYTES_PER_PIXEL) {
+            let rgb = [pixel[2], pixel[1], pixel[0]].map(|channel| channel as f32 / 255.0);
+            let [r, g, b] = self.lookup(rgb).map(|channel| (channel * 255.0 + 0.5) as u8);
+            pixel[..3].copy_from_slice(&[b, g, r]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColourLut, DotMatrix, Filter, FilterChain, Frame, FrameBlend, LcdGrid, PaletteSwap};
+
+    const WHITE: [u8; 4] = [0xFF; 4];
+    const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+    #[test]
+    fn filter_chain_test() {
+        let mut chain: FilterChain = "2x,scanlines".parse().unwrap();
+        assert_eq!(chain.output_size((160, 144)), (320, 288));
+        assert!("2x,sharpen".parse::<FilterChain>().is_err());
+
+        // a 2x1 frame: white, black
+        let input = [WHITE, BLACK].concat();
+        let frame = chain.apply(&input, (2, 1));
+        assert_eq!((frame.width, frame.height), (4, 2));
+        assert_eq!(frame.pixels[..16], [WHITE, WHITE, BLACK, BLACK].concat());
+        // second row is darkened
+        assert_eq!(frame.pixels[16..20], [0xBF, 0xBF, 0xBF, 0xFF]);
+
+        // swapping colours before scaling instead
+        chain.insert(0, Box::new(PaletteSwap { from: [WHITE, BLACK, BLACK, BLACK], to: [BLACK, WHITE, WHITE, WHITE] }));
+        chain.move_filter(2, 0);
+        assert_eq!(chain.len(), 3);
+        let frame = chain.apply(&input, (2, 1));
+        assert_eq!(frame.pixels[..16], [BLACK, BLACK, WHITE, WHITE].concat());
+    }
+
+    #[test]
+    fn frame_blend_test() {
+        let mut blend = FrameBlend::new(0x80);
+        // the first frame has nothing to blend with
+        let mut frame = Frame { width: 1, height: 1, pixels: WHITE.to_vec() };
+        blend.apply(&mut frame);
+        assert_eq!(frame.pixels, WHITE);
+
+        let mut frame = Frame { width: 1, height: 1, pixels: BLACK.to_vec() };
+        blend.apply(&mut frame);
+        assert_eq!(frame.pixels, [0x80, 0x80, 0x80, 0xFF]);
+    }
+
+    #[test]
+    fn lcd_grid_test() {
+        let mut frame = Frame { width: 1, height: 1, pixels: WHITE.to_vec() };
+        LcdGrid.apply(&mut frame);
+        assert_eq!((frame.width, frame.height), (3, 3));
+        // red, green and blue columns (in BGRA)
+        assert_eq!(frame.pixels[..12], [[0x55, 0x55, 0xFF, 0xFF], [0x55, 0xFF, 0x55, 0xFF], [0xFF, 0x55, 0x55, 0xFF]].concat());
+        // the gap below is darker
+        assert_eq!(frame.pixels[24..28], [0x2A, 0x2A, 0x80, 0xFF]);
+
+        let mut frame = Frame { width: 2, height: 1, pixels: [BLACK, WHITE].concat() };
+        DotMatrix.apply(&mut frame);
+        assert_eq!((frame.width, frame.height), (6, 3));
+        assert_eq!(frame.pixels[..4], BLACK);
+        // the gap right of the black dot is in its shadow, the white dot's isn't
+        assert_eq!(frame.pixels[8..12], [0xBF, 0xBF, 0xBF, 0xFF]);
+        assert_eq!(frame.pixels[20..24], WHITE);
+    }
+
+    #[test]
+    fn colour_lut_test() {
+        assert!(ColourLut::from_cube("0 0 0\n1 1 1").is_err());
+        assert!(ColourLut::from_cube("LUT_3D_SIZE 2\n0 0 0").is_err());
+
+        // inverts colours
+        let mut cube = String::from("TITLE \"invert\"\nLUT_3D_SIZE 2\n");
+        for i in 0..8 {
+            cube += &format!("{} {} {}\n", 1 - (i & 1), 1 - ((i >> 1) & 1), 1 - ((i >> 2) & 1));
+        }
+        let mut lut = ColourLut::from_cube(&cube).unwrap();
+        let mut frame = Frame { width: 2, height: 1, pixels: [WHITE, [0x40, 0x80, 0xFF, 0xFF]].concat() };
+        lut.apply(&mut frame);
+        assert_eq!(frame.pixels, [BLACK, [0xBF, 0x7F, 0x00, 0xFF]].concat());
+    }
+}
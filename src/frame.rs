@@ -0,0 +1,9 @@
+use alloc::vec::Vec;
+
+/// A finished frame: BGRA bytes (as the PPU draws them), width x height pixels.
+#[derive(Clone, Default)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
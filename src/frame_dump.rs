@@ -0,0 +1,92 @@
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::time::SystemTime;
+
+use crate::accuracy::ACCURACY_REV;
+use gbemulib::constants::BYTES_PER_PIXEL;
+
+use crate::cpu::Cpu;
+
+// PPU registers recorded for every dumped frame, in manifest column order
+const REGISTERS: [(&str, u16); 11] = [
+    ("LCDC", 0xFF40),
+    ("STAT", 0xFF41),
+    ("SCY", 0xFF42),
+    ("SCX", 0xFF43),
+    ("LY", 0xFF44),
+    ("LYC", 0xFF45),
+    ("BGP", 0xFF47),
+    ("OBP0", 0xFF48),
+    ("OBP1", 0xFF49),
+    ("WY", 0xFF4A),
+    ("WX", 0xFF4B),
+];
+
+/// Writes a run of frames as numbered PNGs into a new directory, along with manifest.csv
+/// listing when each frame finished in emulated time (see Timestamp) and its PPU registers
+/// (read at that point), tagged with the core's ACCURACY_REV for comparing dumps later.
+pub struct FrameDump {
+    dir: String,
+    manifest: BufWriter<File>,
+    frames_written: usize,
+    frame_count: usize,
+}
+
+impl FrameDump {
+    /// Creates a timestamped directory in root for the next frame_count frames.
+    pub fn start(root: &str, frame_count: usize) -> Result<Self, String> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        let dir = format!("{}/frames-{}", root, timestamp);
+        create_dir_all(&dir).map_err(|e| format!("Unable to create {}: {}", dir, e))?;
+
+        let manifest_path = format!("{}/manifest.csv", dir);
+        let file = File::create(&manifest_path).map_err(|e| format!("Unable to create {}: {}", manifest_path, e))?;
+        let mut manifest = BufWriter::new(file);
+        let columns: Vec<&str> = REGISTERS.iter().map(|(name, _)| *name).collect();
+        writeln!(manifest, "frame,file,time_ns,accuracy_rev,{}", columns.join(","))
+            .map_err(|e| format!("Unable to write {}: {}", manifest_path, e))?;
+
+        println!("Dumping {} frames to {}", frame_count, dir);
+        Ok(FrameDump { dir, manifest, frames_written: 0, frame_count })
+    }
+
+    /// Writes frame (in the PPU's frame buffer order) with cpu's current registers;
+    /// returns true once all frames have been written.
+    pub fn add_frame(&mut self, cpu: &Cpu, frame: &[u8]) -> Result<bool, String> {
+        let file_name = format!("frame-{:04}.png", self.frames_written);
+        let path = format!("{}/{}", self.dir, file_name);
+        write_png(&path, frame, cpu.display_size()).map_err(|e| format!("Unable to write {}: {}", path, e))?;
+
+        let registers: Vec<String> = REGISTERS.iter()
+            .map(|(_, addr)| format!("{:02X}", cpu.read_byte(*addr)))
+            .collect();
+        let time_ns = cpu.frame_timestamp().as_duration().as_nanos();
+        writeln!(self.manifest, "{},{},{},{},{}", self.frames_written, file_name, time_ns, ACCURACY_REV, registers.join(","))
+            .and_then(|_| self.manifest.flush())
+            .map_err(|e| format!("Unable to write manifest in {}: {}", self.dir, e))?;
+
+        self.frames_written += 1;
+        if self.frames_written == self.frame_count {
+            println!("Dumped {} frames to {}", self.frame_count, self.dir);
+        }
+        Ok(self.frames_written == self.frame_count)
+    }
+}
+
+/// Saves frame (BGRA bytes, as the PPU draws them) of the given width and height to path as an RGB PNG.
+pub fn write_png(path: &str, frame: &[u8], (width, height): (usize, usize)) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let rgb: Vec<u8> = frame.chunks_exact(BYTES_PER_PIXEL)
+        .flat_map(|pixel| [pixel[2], pixel[1], pixel[0]])
+        .collect();
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgb))
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,569 @@
+use alloc::{boxed::Box, format, rc::Rc, string::String, vec::Vec};
+use core::ops::BitOr;
+
+use crate::cartridge::{Cartridge, CartridgeError, ClockSource};
+use crate::config::RNG_SEED;
+use crate::cpu::{Cpu, GBModel, InitialState};
+use crate::frame::Frame;
+use crate::savestate::StateError;
+
+/// A joypad button, for GameBoy::press() and GameBoy::release().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// The button's bit in the joypad status given to Cpu::update_joypad().
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl BitOr for Button {
+    type Output = Buttons;
+
+    fn bitor(self, other: Button) -> Buttons {
+        Buttons::from(self) | other
+    }
+}
+
+/// A set of held buttons, e.g. `Button::A | Button::Right` (see GameBoy::frames()).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Buttons(u8);
+
+impl Buttons {
+    pub const NONE: Buttons = Buttons(0);
+
+    pub fn contains(self, button: Button) -> bool {
+        self.0 & button.mask() != 0
+    }
+
+    pub fn with(self, button: Button) -> Buttons {
+        Buttons(self.0 | button.mask())
+    }
+
+    pub fn without(self, button: Button) -> Buttons {
+        Buttons(self.0 & !button.mask())
+    }
+
+    /// Returns the joypad status for Cpu::update_joypad(), where a cleared bit is a held button.
+    pub fn joypad_status(self) -> u8 {
+        !self.0
+    }
+}
+
+impl From<Button> for Buttons {
+    fn from(button: Button) -> Buttons {
+        Buttons::NONE.with(button)
+    }
+}
+
+impl BitOr<Button> for Buttons {
+    type Output = Buttons;
+
+    fn bitor(self, button: Button) -> Buttons {
+        self.with(button)
+    }
+}
+
+/// Builds a GameBoy; only the ROM is required.
+pub struct GameBoyBuilder<'a> {
+    rom: &'a [u8],
+    model: Option<GBModel>,
+    bootrom: Option<&'a [u8]>,
+    sample_rate: Option<u32>,
+    initial_state: Option<&'a InitialState>,
+    clock: Option<Rc<dyn ClockSource>>,
+    seed: Option<u64>,
+}
+
+impl<'a> GameBoyBuilder<'a> {
+    pub fn rom(mut self, rom: &'a [u8]) -> Self {
+        self.rom = rom;
+        self
+    }
+
+    /// Runs the game on model; by default it's picked from the boot ROM if there is one,
+    /// or else from the cartridge header (CGB for games that support it, DMG otherwise).
+    pub fn model(mut self, model: GBModel) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Runs bootrom (see Cartridge::with_bootrom) before the game instead of emulating the boot.
+    pub fn bootrom(mut self, bootrom: &'a [u8]) -> Self {
+        self.bootrom = Some(bootrom);
+        self
+    }
+
+    /// Produces audio at hz (see Cpu::set_audio_rate) instead of config::SAMPLING_RATE_HZ.
+    pub fn sample_rate(mut self, hz: u32) -> Self {
+        self.sample_rate = Some(hz);
+        self
+    }
+
+    /// Starts from state's registers and memory (see Cpu::load_initial_state) instead of
+    /// where the boot leaves them.
+    pub fn initial_state(mut self, state: &'a InitialState) -> Self {
+        self.initial_state = Some(state);
+        self
+    }
+
+    /// Has the cartridge's clock count with clock (see Cartridge::with_clock) instead of the
+    /// host's, e.g. a FixedClock for runs that play out the same every time.
+    pub fn clock(mut self, clock: Rc<dyn ClockSource>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Draws all the core's randomness (initial RAM, open bus noise) from seed (see
+    /// Cpu::with_seed) instead of config::RNG_SEED; runs with the same seed play out the same.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn build(self) -> Result<GameBoy, CartridgeError> {
+        let mut cartridge = Cartridge::from_bytes(self.rom)?;
+        if let Some(clock) = self.clock {
+            cartridge = cartridge.with_clock(clock);
+        }
+        if let Some(bootrom) = self.bootrom {
+            cartridge = cartridge.with_bootrom(bootrom)?;
+        }
+
+        let cgb = if cartridge.has_bootrom() { cartridge.has_cgb_bootrom() } else { cartridge.cgb_compatible() };
+        let model = self.model.unwrap_or(if cgb { GBModel::CGB } else { GBModel::DMG });
+
+        let mut cpu = Box::new(Cpu::with_seed(cartridge, model, self.seed.unwrap_or(RNG_SEED)));
+        if let Some(hz) = self.sample_rate {
+            cpu.set_audio_rate(hz);
+        }
+        if let Some(state) = self.initial_state {
+            cpu.load_initial_state(state);
+        }
+
+        Ok(GameBoy {
+            cpu,
+            buttons: Buttons::NONE,
+            audio: Vec::new(),
+        })
+    }
+}
+
+/// A Game Boy running a game a frame at a time: the simplest way to embed the core,
+/// with Cpu's finer-grained API (link cables, palettes, events...) behind cpu_mut().
+pub struct GameBoy {
+    // boxed, as the Cpu is a few hundred KiB
+    cpu: Box<Cpu>,
+    buttons: Buttons,
+    // samples produced by the last run_frame()
+    audio: Vec<f32>,
+}
+
+impl GameBoy {
+    pub fn builder<'a>() -> GameBoyBuilder<'a> {
+        GameBoyBuilder {
+            rom: &[],
+            model: None,
+            bootrom: None,
+            sample_rate: None,
+            initial_state: None,
+            clock: None,
+            seed: None,
+        }
+    }
+
+    /// Runs until the next frame is done (see screen() and audio_samples()), or for a frame's
+    /// worth of cycles while the LCD is off; returns false if no frame was drawn.
+    pub fn run_frame(&mut self) -> bool {
+        let frame_done = self.cpu.run_until_vblank();
+        self.take_audio();
+        frame_done
+    }
+
+    /// Runs for at least t_cycles T-cycles (see Cpu::run_cycles()) and returns how many were run.
+    pub fn run_cycles(&mut self, t_cycles: u64) -> u64 {
+        let run = self.cpu.run_cycles(t_cycles);
+        self.take_audio();
+        run
+    }
+
+    fn take_audio(&mut self) {
+        self.audio.clear();
+        self.audio.extend_from_slice(self.cpu.drain_audio());
+    }
+
+    /// Runs a frame for every set of held buttons in inputs and yields what was on screen after
+    /// it (the last frame again while the LCD is off), so it's ROM and inputs in, frames out.
+    /// Like press(), the buttons held for a frame are seen by the game from the VBlank ending it.
+    pub fn frames<'a>(&'a mut self, inputs: impl IntoIterator<Item = Buttons> + 'a) -> impl Iterator<Item = Frame> + 'a {
+        inputs.into_iter().map(move |buttons| {
+            self.set_buttons(buttons);
+            self.run_frame();
+            self.screen().clone()
+        })
+    }
+
+    /// Holds down button; the game sees it from the next VBlank on.
+    pub fn press(&mut self, button: Button) {
+        self.set_buttons(self.buttons.with(button));
+    }
+
+    /// Lets go of button; the game sees it from the next VBlank on.
+    pub fn release(&mut self, button: Button) {
+        self.set_buttons(self.buttons.without(button));
+    }
+
+    /// Holds down exactly the buttons in buttons; the game sees them from the next VBlank on.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        self.cpu.update_joypad(buttons.joypad_status());
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    /// Returns the last frame drawn (empty before the first one).
+    pub fn screen(&self) -> &Frame {
+        self.cpu.frame()
+    }
+
+    /// Returns the interleaved stereo samples (at Cpu::audio_rate()) produced by the
+    /// last run_frame() or run_cycles().
+    pub fn audio_samples(&self) -> &[f32] {
+        &self.audio
+    }
+
+    /// Returns the byte at addr as the game would read it, except that VRAM and OAM can
+    /// always be read; for auto-splitters, trainers and agents watching game state.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.peek(addr)
+    }
+
+    /// Returns the len bytes from start on (wrapping past FFFF), read like peek().
+    pub fn peek_range(&self, start: u16, len: usize) -> Vec<u8> {
+        (0..len).map(|i| self.peek(start.wrapping_add(i as u16))).collect()
+    }
+
+    /// Writes byte to the RAM (VRAM, cartridge RAM, WRAM, OAM or HRAM) at addr without
+    /// switching banks or triggering anything else; returns false, writing nothing, for
+    /// ROM and IO registers.
+    pub fn poke(&mut self, addr: u16, byte: u8) -> bool {
+        self.cpu.poke(addr, byte)
+    }
+
+    /// Saves the game's state for load_state() (see Cpu::save_state).
+    pub fn save_state(&self) -> Vec<u8> {
+        self.cpu.save_state()
+    }
+
+    /// Goes back to a state from save_state(), keeping the buttons held now held; if it
+    /// doesn't load, the game carries on as it was.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        self.cpu.load_state(state)?;
+        self.set_buttons(self.buttons);
+        Ok(())
+    }
+
+    /// Returns the last frame drawn as a PNG: indexed with its few shades for DMG games, or
+    /// RGB for CGB games (see screenshot::file_name for naming it after cpu().title()).
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    pub fn screenshot(&self) -> Result<Vec<u8>, String> {
+        let screen = self.screen();
+        crate::screenshot::encode_png(&screen.pixels, (screen.width, screen.height), !self.cpu.cgb_mode())
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// Gives access to everything the facade doesn't cover; frames and audio should still
+    /// be run through run_frame() or run_cycles().
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+}
+
+/// Runs a GameBoy from build twice, a frame for every set of held buttons in inputs, hashing
+/// each frame and the audio produced with it; returns where the second run first differs from
+/// the first. Runs can only differ through host state leaking into the core, so this fails if
+/// build doesn't stop the cartridge's clock (see GameBoyBuilder::clock and FixedClock).
+pub fn verify_determinism(build: impl Fn() -> GameBoy, inputs: &[Buttons]) -> Result<(), String> {
+    let runs: Vec<Vec<(u64, u64)>> = (0..2).map(|_| {
+        let mut gameboy = build();
+        inputs.iter().map(|&buttons| {
+            gameboy.set_buttons(buttons);
+            gameboy.run_frame();
+            let audio: Vec<u8> = gameboy.audio_samples().iter().flat_map(|sample| sample.to_le_bytes()).collect();
+            (fnv1a(&gameboy.screen().pixels), fnv1a(&audio))
+        }).collect()
+    }).collect();
+
+    match runs[0].iter().zip(&runs[1]).position(|(first, second)| first != second) {
+        Some(frame) => {
+            let ((frame_1, audio_1), (frame_2, audio_2)) = (runs[0][frame], runs[1][frame]);
+            Err(format!("runs diverge at frame {}: frame hashes {:016X} and {:016X}, audio hashes {:016X} and {:016X}",
+                frame, frame_1, frame_2, audio_1, audio_2))
+        }
+        None => Ok(()),
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use crate::bus::OpenBus;
+    use crate::cartridge::{fix_header, EmulatedClock, FixedClock};
+    use crate::cpu::test_helpers::make_test_rom;
+    use crate::config::{AUDIO_SAMPLES, SAMPLING_RATE_HZ};
+    use crate::constants::{LCD_HEIGHT, LCD_WIDTH};
+    use super::*;
+
+    // loop: JR loop
+    const IDLE_PROGRAM: [u8; 2] = [0x18, 0xFE];
+
+    #[test]
+    fn run_frame_test() {
+        let rom = make_test_rom(&IDLE_PROGRAM);
+        let mut gameboy = GameBoy::builder().rom(&rom).model(GBModel::DMG).build().unwrap();
+        assert!(gameboy.screen().pixels.is_empty());
+
+        gameboy.run_frame();
+        let screen = gameboy.screen();
+        assert_eq!((screen.width, screen.height), (LCD_WIDTH, LCD_HEIGHT));
+
+        // audio comes in chunks of AUDIO_SAMPLES, so only a second's worth adds up
+        let mut samples = 0;
+        for _ in 0..60 {
+            gameboy.run_frame();
+            samples += gameboy.audio_samples().len();
+        }
+        assert!(samples.abs_diff(2 * SAMPLING_RATE_HZ as usize) <= 2 * AUDIO_SAMPLES, "{} samples", samples);
+
+        let mut gameboy = GameBoy::builder().rom(&rom).sample_rate(96000).build().unwrap();
+        let samples: usize = (0..60).map(|_| { gameboy.run_frame(); gameboy.audio_samples().len() }).sum();
+        assert!(samples.abs_diff(2 * 96000) <= 2 * AUDIO_SAMPLES, "{} samples", samples);
+    }
+
+    #[test]
+    fn screenshot_test() {
+        let mut cgb_rom = make_test_rom(&IDLE_PROGRAM);
+        // CGB flag, for CGB mode rather than DMG compatibility mode
+        cgb_rom[0x143] = 0x80;
+        fix_header(&mut cgb_rom).unwrap();
+        let dmg_rom = make_test_rom(&IDLE_PROGRAM);
+        for (rom, model, colour_type) in [(&dmg_rom, GBModel::DMG, png::ColorType::Indexed), (&cgb_rom, GBModel::CGB, png::ColorType::Rgb)] {
+            let mut gameboy = GameBoy::builder().rom(rom).model(model).build().unwrap();
+            assert!(gameboy.screenshot().is_err());
+
+            gameboy.run_frame();
+            let png = gameboy.screenshot().unwrap();
+            let info = png::Decoder::new(png.as_slice()).read_info().unwrap().info().clone();
+            assert_eq!((info.width as usize, info.height as usize), (LCD_WIDTH, LCD_HEIGHT));
+            assert_eq!(info.color_type, colour_type, "{:?}", model);
+        }
+    }
+
+    #[test]
+    fn press_release_test() {
+        let rom = make_test_rom(&IDLE_PROGRAM);
+        let mut gameboy = GameBoy::builder().rom(&rom).build().unwrap();
+
+        gameboy.press(Button::Start);
+        gameboy.press(Button::A);
+        gameboy.release(Button::A);
+        gameboy.run_frame();
+        gameboy.run_frame();
+        assert_eq!(gameboy.cpu().current_inputs(), 0x7F);
+    }
+
+    #[test]
+    fn bootrom_test() {
+        let mut rom = make_test_rom(&IDLE_PROGRAM);
+        rom[0] = 0x42;
+
+        // LD A, $91; LDH ($40), A (turns on the LCD)
+        // LD A, $01; LDH ($50), A (unmaps the boot ROM, then runs the cartridge from $0008)
+        let mut bootrom = [0; 0x100];
+        bootrom[..8].copy_from_slice(&[0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x01, 0xE0, 0x50]);
+
+        let mut gameboy = GameBoy::builder().rom(&rom).bootrom(&bootrom).build().unwrap();
+        assert_eq!(gameboy.cpu().read_byte(0x0000), 0x3E);
+        gameboy.run_frame();
+        assert_eq!(gameboy.cpu().read_byte(0x0000), 0x42);
+
+        let result = GameBoy::builder().rom(&rom).bootrom(&bootrom[..0x80]).build();
+        assert!(matches!(result, Err(CartridgeError::InvalidBootrom(_, 0x80))));
+    }
+
+    #[test]
+    fn frames_test() {
+        let rom = make_test_rom(&IDLE_PROGRAM);
+        let mut gameboy = GameBoy::builder().rom(&rom).build().unwrap();
+
+        let inputs = [Buttons::NONE, Button::Start | Button::Down, Buttons::from(Button::A)];
+        let frames: Vec<Frame> = gameboy.frames(inputs).collect();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|frame| frame.pixels.len() == LCD_WIDTH * LCD_HEIGHT * 4));
+        assert_eq!(gameboy.cpu().current_inputs(), 0xEF);
+        assert_eq!(gameboy.buttons(), Buttons::from(Button::A));
+
+        // XOR A; LDH ($40), A; JR -2 (turns the LCD off for good)
+        let rom = make_test_rom(&[0xAF, 0xE0, 0x40, 0x18, 0xFE]);
+        let mut gameboy = GameBoy::builder().rom(&rom).build().unwrap();
+        assert_eq!(gameboy.frames([Buttons::NONE; 5]).count(), 5);
+    }
+
+    #[test]
+    fn peek_poke_test() {
+        let rom = make_test_rom(&IDLE_PROGRAM);
+        let mut gameboy = GameBoy::builder().rom(&rom).build().unwrap();
+        gameboy.run_frame();
+
+        assert!(gameboy.poke(0xC100, 0x12));
+        assert!(gameboy.poke(0xFF90, 0x34));
+        assert_eq!(gameboy.peek(0xE100), 0x12);
+        assert_eq!(gameboy.peek_range(0xFF90, 2), vec![0x34, gameboy.peek(0xFF91)]);
+        assert_eq!(gameboy.peek_range(0xFFFF, 2), vec![gameboy.peek(0xFFFF), gameboy.peek(0x0000)]);
+
+        // ROM and IO registers can't be poked
+        assert!(!gameboy.poke(0x2000, 0x05));
+        assert!(!gameboy.poke(0xFF40, 0x00));
+        assert_eq!(gameboy.peek(0x0100), 0x18);
+        assert_eq!(gameboy.peek(0xFF40) & 0x80, 0x80);
+
+        // VRAM and OAM can be poked and peeked whatever mode the PPU is in
+        for i in 0..200 {
+            gameboy.run_cycles(40);
+            assert!(gameboy.poke(0x8000 + i, i as u8));
+            assert!(gameboy.poke(0xFE00 + i % 0xA0, !i as u8));
+            assert_eq!((gameboy.peek(0x8000 + i), gameboy.peek(0xFE00 + i % 0xA0)), (i as u8, !i as u8));
+        }
+    }
+
+    #[test]
+    fn seed_test() {
+        let rom = make_test_rom(&IDLE_PROGRAM);
+        let noise = |seed| {
+            let mut gameboy = GameBoy::builder().rom(&rom).seed(seed).build().unwrap();
+            assert_eq!(gameboy.cpu().rng_seed(), seed);
+            gameboy.cpu_mut().set_open_bus(OpenBus::Noise);
+            gameboy.run_frame();
+            gameboy.peek(0xFF03)
+        };
+        assert_eq!(noise(7), noise(7));
+        assert_ne!(noise(7), noise(8));
+    }
+
+    /// Counts step seconds every time it's read.
+    struct TickingClock {
+        time: Cell<u64>,
+        step: u64,
+    }
+
+    impl ClockSource for TickingClock {
+        fn now(&self) -> u64 {
+            self.time.set(self.time.get() + self.step);
+            self.time.get()
+        }
+    }
+
+    // MBC3 with a clock: latch it and show the seconds register through BGP, forever
+    fn make_rtc_rom() -> Vec<u8> {
+        let mut rom = make_test_rom(&[
+            0x3E, 0x0A, 0xEA, 0x00, 0x00, // LD A, $0A; LD ($0000), A (enable the RTC)
+            0x3E, 0x08, 0xEA, 0x00, 0x40, // LD A, $08; LD ($4000), A (select seconds)
+            0xAF, 0xEA, 0x00, 0x60,       // loop: XOR A; LD ($6000), A
+            0x3C, 0xEA, 0x00, 0x60,       // INC A; LD ($6000), A (latch)
+            0xFA, 0x00, 0xA0,             // LD A, ($A000)
+            0xE0, 0x47,                   // LDH ($47), A
+            0x18, 0xF1,                   // JR loop
+        ]);
+        rom[0x147] = 0x0F;
+        fix_header(&mut rom).unwrap();
+        rom
+    }
+
+    #[test]
+    fn determinism_test() {
+        let rom = make_rtc_rom();
+        let inputs = [Buttons::NONE, Buttons::from(Button::Start), Buttons::NONE, Buttons::from(Button::A)];
+
+        let build = |clock: Rc<dyn ClockSource>| GameBoy::builder().rom(&rom).model(GBModel::DMG).clock(clock).build().unwrap();
+        verify_determinism(|| build(Rc::new(FixedClock(1_700_000_000))), &inputs).unwrap();
+        verify_determinism(|| build(Rc::new(TickingClock { time: Cell::new(0), step: 1 })), &inputs).unwrap();
+
+        // the clock shows on screen, so a clock that runs differently the second time diverges
+        let runs = Cell::new(0);
+        let divergence = verify_determinism(|| {
+            runs.set(runs.get() + 1);
+            build(Rc::new(TickingClock { time: Cell::new(0), step: runs.get() }))
+        }, &inputs).unwrap_err();
+        assert!(divergence.starts_with("runs diverge at frame 0"), "{}", divergence);
+    }
+
+    #[test]
+    fn emulated_clock_test() {
+        let rom = make_rtc_rom();
+        let clock = Rc::new(EmulatedClock::new(1_700_000_000));
+        let mut gameboy = GameBoy::builder().rom(&rom).model(GBModel::DMG).clock(clock.clone()).build().unwrap();
+        assert!(gameboy.cpu().has_rtc());
+
+        // a second of emulated time (about 60 frames) moves the RTC a second
+        for _ in 0..65 {
+            gameboy.run_frame();
+        }
+        assert_eq!(clock.now(), 1_700_000_001);
+        assert_eq!(gameboy.peek(0xFF47), 1);
+
+        gameboy.cpu_mut().adjust_rtc(20);
+        gameboy.run_frame();
+        assert_eq!(gameboy.peek(0xFF47), 21);
+    }
+
+    #[test]
+    fn save_state_test() {
+        // LD A,$0A; LD ($0000),A; LD HL,$A000; loop: INC (HL); LDH A,($00); LD ($A001),A; JR loop
+        let mut rom = make_test_rom(&[0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x21, 0x00, 0xA0, 0x34, 0xF0, 0x00, 0xEA, 0x01, 0xA0, 0x18, 0xF8]);
+        // MBC5 with a bank of RAM, so the cartridge's state is saved too
+        (rom[0x147], rom[0x149]) = (0x1A, 0x02);
+        fix_header(&mut rom).unwrap();
+        let inputs = [Buttons::NONE, Button::A.into(), Button::A | Button::Down, Buttons::NONE];
+        for model in [GBModel::DMG, GBModel::CGB, GBModel::SGB] {
+            let mut gameboy = GameBoy::builder().rom(&rom).model(model).build().unwrap();
+            gameboy.frames([Button::Start.into(); 30]).for_each(drop);
+            let state = gameboy.save_state();
+            let frames: Vec<Vec<u8>> = gameboy.frames(inputs).map(|frame| frame.pixels).collect();
+            let ram = gameboy.peek_range(0xA000, 2);
+
+            // the same inputs from the state play out the same
+            gameboy.load_state(&state).unwrap();
+            assert!(gameboy.frames(inputs).map(|frame| frame.pixels).eq(frames.iter().cloned()), "{:?}", model);
+            assert_eq!(gameboy.peek_range(0xA000, 2), ram);
+
+            // states that don't load leave the game as it was
+            gameboy.load_state(&state).unwrap();
+            assert_eq!(gameboy.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+            let mut other_rom = rom.clone();
+            other_rom[0x7FFF] = 1;
+            let other = GameBoy::builder().rom(&other_rom).model(model).build().unwrap();
+            assert!(matches!(gameboy.load_state(&other.save_state()), Err(StateError::WrongGame(_))));
+            assert!(gameboy.frames(inputs).map(|frame| frame.pixels).eq(frames.iter().cloned()));
+        }
+    }
+}
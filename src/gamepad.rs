@@ -0,0 +1,170 @@
+use std::str::FromStr;
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::GameControllerSubsystem;
+use sdl2::Sdl;
+
+// in the same order as KEYMAPPINGS: START, SELECT, B, A, DOWN, UP, LEFT, RIGHT
+const JOYPAD_BUTTONS: [&str; 8] = ["start", "select", "b", "a", "down", "up", "left", "right"];
+
+// how far the left stick has to be pushed (out of i16::MAX) to hold its direction down
+const STICK_DEADZONE: i16 = 16000;
+// how long a rumble lasts unless the game changes it first (the longest SDL allows)
+const RUMBLE_DURATION_MS: u32 = 0xFFFF;
+
+/// Which controller button presses each Game Boy button, in the same order as KEYMAPPINGS.
+/// The default is positional, as on Nintendo's pads: A is the right face button and B the
+/// bottom one (B and A on an Xbox pad, circle and cross on a PlayStation one).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PadMapping(pub [Button; 8]);
+
+impl Default for PadMapping {
+    fn default() -> Self {
+        PadMapping([
+            Button::Start,
+            Button::Back,
+            Button::A,
+            Button::B,
+            Button::DPadDown,
+            Button::DPadUp,
+            Button::DPadLeft,
+            Button::DPadRight,
+        ])
+    }
+}
+
+/// Parses a comma-separated list of `<game boy button>=<controller button>` overrides of the
+/// default mapping (e.g. `a=a,b=x`), with controller buttons named as in SDL's mappings.
+impl FromStr for PadMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mapping = PadMapping::default();
+        for pair in s.split(',').map(str::trim) {
+            let (joypad, pad) = pair.split_once('=')
+                .ok_or_else(|| format!("Expected <game boy button>=<controller button>, got '{}'", pair))?;
+            let i = JOYPAD_BUTTONS.iter().position(|&name| name == joypad.trim())
+                .ok_or_else(|| format!("Unknown Game Boy button '{}' (expected one of {})", joypad, JOYPAD_BUTTONS.join(", ")))?;
+            mapping.0[i] = Button::from_string(pad.trim())
+                .ok_or_else(|| format!("Unknown controller button '{}' (e.g. a, b, x, y, back, start, leftshoulder)", pad))?;
+        }
+        Ok(mapping)
+    }
+}
+
+/// A connected controller and what it holds down.
+struct Pad {
+    controller: GameController,
+    // bits set for held buttons, in joypad status order (bit 7 START ... bit 0 RIGHT)
+    buttons: u8,
+    // the same for directions held on the left stick
+    stick: u8,
+}
+
+/// Game controllers, opened as they're plugged in (SDL also reports the ones already plugged in
+/// at startup) and closed as they're unplugged. SDL's database maps their buttons to an Xbox
+/// layout, so any pad it knows works without setting up; the first pad connected is player 1
+/// and the second is player 2 (the second core, or the second SGB controller).
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    pads: Vec<Pad>,
+    mapping: PadMapping,
+    // each player's rumble intensity (0 to 255)
+    rumble: [u8; 2],
+}
+
+impl Gamepads {
+    pub fn new(sdl_context: &Sdl) -> Result<Self, String> {
+        Ok(Gamepads {
+            subsystem: sdl_context.game_controller()?,
+            pads: Vec::new(),
+            mapping: PadMapping::default(),
+            rumble: [0; 2],
+        })
+    }
+
+    pub fn set_mapping(&mut self, mapping: PadMapping) {
+        self.mapping = mapping;
+        // buttons held under the old mapping would otherwise never be let go
+        for pad in &mut self.pads {
+            pad.buttons = 0;
+        }
+    }
+
+    /// Opens the controller at joystick_index (from a ControllerDeviceAdded event).
+    pub fn connect(&mut self, joystick_index: u32) {
+        match self.subsystem.open(joystick_index) {
+            Ok(controller) => {
+                println!("Controller connected as player {}: {}", self.pads.len() + 1, controller.name());
+                self.pads.push(Pad { controller, buttons: 0, stick: 0 });
+                self.apply_rumble();
+            },
+            Err(e) => eprintln!("Unable to open controller {}: {}", joystick_index, e),
+        }
+    }
+
+    /// Closes the controller with instance_id (from a ControllerDeviceRemoved event), letting
+    /// go of everything it held; the players after it move up.
+    pub fn disconnect(&mut self, instance_id: u32) {
+        if let Some(i) = self.pads.iter().position(|pad| pad.controller.instance_id() == instance_id) {
+            let pad = self.pads.remove(i);
+            println!("Controller disconnected: {}", pad.controller.name());
+            self.apply_rumble();
+        }
+    }
+
+    /// Rumbles player's controller at intensity (0 to 255, from CoreEvent::RumbleIntensity)
+    /// until it's changed again; a controller connected later picks it up.
+    pub fn set_rumble(&mut self, player: usize, intensity: u8) {
+        if self.rumble[player] != intensity {
+            self.rumble[player] = intensity;
+            self.apply_rumble();
+        }
+    }
+
+    /// Sets every player's controller rumbling at their intensity.
+    fn apply_rumble(&mut self) {
+        for (pad, &intensity) in self.pads.iter_mut().zip(&self.rumble) {
+            let strength = intensity as u16 * 0x101;
+            // controllers without motors refuse, which is fine
+            let _ = pad.controller.set_rumble(strength, strength, RUMBLE_DURATION_MS);
+        }
+    }
+
+    /// Presses or releases button on the controller with instance_id; returns the position of
+    /// the Game Boy button it's mapped to in KEYMAPPINGS (if any).
+    pub fn button(&mut self, instance_id: u32, button: Button, pressed: bool) -> Option<usize> {
+        let i = self.mapping.0.iter().position(|&mapped| mapped == button)?;
+        let pad = self.pads.iter_mut().find(|pad| pad.controller.instance_id() == instance_id)?;
+        if pressed {
+            pad.buttons |= 1 << (7 - i);
+        } else {
+            pad.buttons &= !(1 << (7 - i));
+        }
+        Some(i)
+    }
+
+    /// Moves an axis of the controller with instance_id; the left stick holds down directions.
+    pub fn axis(&mut self, instance_id: u32, axis: Axis, value: i16) {
+        // (bit when pushed negative, bit when pushed positive) in joypad status order
+        let (negative, positive) = match axis {
+            Axis::LeftX => (1 << 1, 1 << 0),
+            Axis::LeftY => (1 << 2, 1 << 3),
+            _ => return,
+        };
+        if let Some(pad) = self.pads.iter_mut().find(|pad| pad.controller.instance_id() == instance_id) {
+            pad.stick &= !(negative | positive);
+            if value <= -STICK_DEADZONE {
+                pad.stick |= negative;
+            } else if value >= STICK_DEADZONE {
+                pad.stick |= positive;
+            }
+        }
+    }
+
+    /// Returns what player's controller holds down as a joypad status (active-low, like
+    /// key_status), which is 0xFF if they have none.
+    pub fn status(&self, player: usize) -> u8 {
+        self.pads.get(player).map_or(0xFF, |pad| !(pad.buttons | pad.stick))
+    }
+}
@@ -0,0 +1,88 @@
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+/// The other side of the infrared port (the CGB's RP register or a cartridge's IR LED):
+/// decides what light this side's sensor sees.
+pub trait IrTransceiver {
+    /// Turns this side's LED on or off.
+    fn set_led(&mut self, on: bool);
+
+    /// Returns true if the sensor is currently receiving light.
+    fn light_received(&self) -> bool;
+}
+
+/// Reflects this side's own LED back into its sensor, like holding a mirror up to the port.
+#[allow(dead_code)]
+#[derive(Default)]
+pub struct IrLoopback {
+    led: bool,
+}
+
+impl IrTransceiver for IrLoopback {
+    fn set_led(&mut self, on: bool) {
+        self.led = on;
+    }
+
+    fn light_received(&self) -> bool {
+        self.led
+    }
+}
+
+/// One of two IR ports facing each other, for emulators in the same process (see IrPort::pair()).
+/// Pulses are timed in CPU cycles, so both emulators should be stepped in small, even slices.
+pub struct IrPort {
+    leds: Rc<RefCell<[bool; 2]>>,
+    side: usize,
+}
+
+impl IrPort {
+    /// Returns two ports facing each other, to be given to two Cpus with connect_infrared().
+    pub fn pair() -> (IrPort, IrPort) {
+        let leds = Rc::new(RefCell::new([false; 2]));
+        (IrPort { leds: leds.clone(), side: 0 }, IrPort { leds, side: 1 })
+    }
+}
+
+impl IrTransceiver for IrPort {
+    fn set_led(&mut self, on: bool) {
+        self.leds.borrow_mut()[self.side] = on;
+    }
+
+    fn light_received(&self) -> bool {
+        self.leds.borrow()[1 - self.side]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IrPort;
+    use crate::cartridge::Cartridge;
+    use crate::cpu::{test_helpers::make_test_rom, Cpu, GBModel};
+
+    #[test]
+    fn infrared_rp_test() {
+        // LD A,rp; LDH (RP),A; JR -2
+        let make_cpu = |rp: u8| {
+            let mut rom = make_test_rom(&[0x3E, rp, 0xE0, 0x56, 0x18, 0xFE]);
+            rom[0x143] = 0x80;
+            rom[0x14D] = rom[0x134..=0x14C].iter().fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+            Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::CGB)
+        };
+        // one side has its LED on, the other has reading enabled
+        let mut sender = make_cpu(0x01);
+        let mut receiver = make_cpu(0xC0);
+
+        let (port_a, port_b) = IrPort::pair();
+        sender.connect_infrared(Box::new(port_a));
+        receiver.connect_infrared(Box::new(port_b));
+
+        assert_eq!(receiver.read_byte(0xFF56), 0x3E);
+        for _ in 0..10 {
+            sender.step();
+            receiver.step();
+        }
+
+        assert_eq!(sender.read_byte(0xFF56), 0x3F);
+        assert_eq!(receiver.read_byte(0xFF56), 0xFC);
+    }
+}
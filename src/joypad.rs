@@ -1,7 +1,34 @@
+use crate::savestate::snapshot_fields;
 
+// (SGB ONLY) controllers that can be plugged in
+pub const PLAYERS: usize = 4;
 
+/// Inputs are given in order of: START (msb), SELECT, B, A, DOWN, UP, LEFT, RIGHT (lsb),
+/// with FALSE/0 = pressed and TRUE/1 = released.
+///
+/// Inputs set by frontends only reach the game when latched, which the bus does at the start
+/// of every VBlank (or right away while the LCD is off), so each frame's inputs stay fixed
+/// from one VBlank to the next no matter when in the frame they were set.
+///
+/// The game reads them through P1 as a matrix: writing 0 to bit 5 (P15) selects the buttons
+/// and writing 0 to bit 4 (P14) the d-pad, and the bottom four bits read low for whatever's
+/// held in the selected groups (in both, if both are selected). Any of those bits going from
+/// high to low, whether from a press or from selecting a group with something held, requests
+/// the Joypad interrupt. Nothing stops opposing directions from being held together, as on a
+/// DMG with a worn d-pad; some games misbehave with them, so frontends can filter them out.
+///
+/// An SGB can have up to PLAYERS controllers plugged in (see Sgb's MLT_REQ); the game then
+/// reads the one the SGB selects, and with neither button group selected, its ID instead.
 pub struct Joypad {
     joypad: u8,
+    // inputs the game currently sees, for every player
+    status: [u8; PLAYERS],
+    // inputs to latch at the next VBlank
+    pending_status: [u8; PLAYERS],
+    // (SGB ONLY) the controller the game reads
+    player: usize,
+    // (SGB ONLY) whether more than one controller is enabled
+    multiplayer: bool,
     interrupt: bool,
 }
 
@@ -9,25 +36,60 @@ impl Joypad {
     pub fn new() -> Self {
         Joypad {
             joypad: 0xFF,
+            status: [0xFF; PLAYERS],
+            pending_status: [0xFF; PLAYERS],
+            player: 0,
+            multiplayer: false,
             interrupt: false,
         }
     }
 
+    /// Sets the first player's inputs to be latched at the next VBlank.
+    pub fn set_pending(&mut self, status: u8) {
+        self.pending_status[0] = status;
+    }
+
+    /// (SGB ONLY) Sets player's (0-3) inputs to be latched at the next VBlank.
+    pub fn set_pending_player(&mut self, player: usize, status: u8) {
+        self.pending_status[player] = status;
+    }
+
+    pub fn pending_status(&self) -> u8 {
+        self.pending_status[0]
+    }
+
+    /// Makes the pending inputs visible to the game.
+    pub fn latch(&mut self) {
+        self.status = self.pending_status;
+        self.update();
+    }
+
+    /// Returns the inputs the game currently sees (for the first player).
+    pub fn status(&self) -> u8 {
+        self.status[0]
+    }
+
+    /// (SGB ONLY) Reads player's controller from now on; with multiplayer on,
+    /// selecting neither button group reads its ID.
+    pub fn select_player(&mut self, player: usize, multiplayer: bool) {
+        self.player = player;
+        self.multiplayer = multiplayer;
+    }
+
     pub fn read_joypad(&self) -> u8 {
         self.joypad
     }
 
-    /// Bottom four bits are read-only.
+    /// Bottom four bits are read-only; they follow the newly selected buttons/d-pad.
     pub fn write_joypad(&mut self, byte: u8) {
         let joypad = self.joypad & 0xCF;
         self.joypad = (byte & 0x30) | joypad;
+        self.update();
     }
 
     /// Update joypad register based on status, checking for any interrupts.
-    /// status is in order of: START (msb), SELECT, B, A, DOWN, UP, LEFT, RIGHT (lsb).
-    /// FALSE/0 = pressed and TRUE/1 = released.
-    pub fn update(&mut self, status: u8) {
-        self.interrupt = false; 
+    fn update(&mut self) {
+        let status = self.status[self.player];
 
         let upper_nibble = self.joypad & 0xF0;
         let lower_nibble = if self.select_buttons() && self.select_dpad() {
@@ -37,6 +99,9 @@ impl Joypad {
                 status >> 4
             } else if self.select_dpad() {
                 status & 0xF
+            } else if self.multiplayer {
+                // 0xF for the first controller, 0xE for the second...
+                0xF - self.player as u8
             } else {
                 0xF
             }
@@ -57,7 +122,133 @@ impl Joypad {
         self.joypad & 0x10 == 0
     }
 
-    pub fn interrupt_triggered(&self) -> bool {
-        self.interrupt
+    /// Returns true (once) if a button line went low since the last call.
+    pub fn interrupt_triggered(&mut self) -> bool {
+        core::mem::take(&mut self.interrupt)
+    }
+}
+
+/// Returns status (0 = pressed) with LEFT+RIGHT and UP+DOWN released if they're held together,
+/// for games that glitch on inputs no intact d-pad can make.
+pub fn block_opposing(status: u8) -> u8 {
+    let mut status = status;
+    for pair in [0x03, 0x0C] {
+        if status & pair == 0 {
+            status |= pair;
+        }
+    }
+    status
+}
+
+/// Auto-fire: buttons held through it alternate between pressed for on frames and released for
+/// off frames, for games that want a button mashed (shmups, or skipping through RPG text).
+/// Frontends hold turbo buttons alongside the usual ones and step it once a frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Turbo {
+    on: u32,
+    off: u32,
+    // frames into the current on-off cycle
+    frame: u32,
+}
+
+impl Turbo {
+    /// Presses for on frames and releases for off frames (both at least 1).
+    pub fn new(on: u32, off: u32) -> Self {
+        Turbo { on: on.max(1), off: off.max(1), frame: 0 }
+    }
+
+    /// Moves on to the next frame.
+    pub fn step(&mut self) {
+        self.frame = (self.frame + 1) % (self.on + self.off);
+    }
+
+    /// Returns status with the buttons held in turbo_status (both with 0 = pressed) pressed
+    /// too, if this is one of the frames they're pressed on.
+    pub fn apply(&self, status: u8, turbo_status: u8) -> u8 {
+        if self.frame < self.on {
+            status & turbo_status
+        } else {
+            status
+        }
+    }
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Turbo::new(2, 2)
+    }
+}
+
+snapshot_fields!(Joypad, joypad, status, pending_status, player, multiplayer, interrupt);
+
+#[cfg(test)]
+mod tests {
+    use super::{block_opposing, Joypad, Turbo};
+
+    #[test]
+    fn joypad_matrix_test() {
+        let mut joypad = Joypad::new();
+        // A and DOWN held
+        joypad.set_pending(0xE7);
+        joypad.latch();
+
+        joypad.write_joypad(0x10);
+        assert_eq!(joypad.read_joypad(), 0xDE);
+        joypad.write_joypad(0x20);
+        assert_eq!(joypad.read_joypad(), 0xE7);
+        joypad.write_joypad(0x00);
+        assert_eq!(joypad.read_joypad(), 0xC6);
+        joypad.write_joypad(0x30);
+        assert_eq!(joypad.read_joypad(), 0xFF);
+    }
+
+    #[test]
+    fn joypad_interrupt_test() {
+        let mut joypad = Joypad::new();
+        joypad.write_joypad(0x20);
+        assert!(!joypad.interrupt_triggered());
+
+        // pressing RIGHT pulls P10 low
+        joypad.set_pending(0xFE);
+        joypad.latch();
+        assert!(joypad.interrupt_triggered());
+        assert!(!joypad.interrupt_triggered());
+
+        // releasing it doesn't, nor does pressing a button while only the d-pad is selected
+        joypad.set_pending(0xEF);
+        joypad.latch();
+        assert!(!joypad.interrupt_triggered());
+
+        // selecting the buttons with A held does
+        joypad.write_joypad(0x10);
+        assert!(joypad.interrupt_triggered());
+    }
+
+    #[test]
+    fn joypad_opposing_directions_test() {
+        let mut joypad = Joypad::new();
+        // LEFT and RIGHT held
+        joypad.set_pending(0xFC);
+        joypad.latch();
+        joypad.write_joypad(0x20);
+        assert_eq!(joypad.read_joypad() & 0x0F, 0x0C);
+
+        // LEFT+RIGHT are let go of, UP and A aren't
+        assert_eq!(block_opposing(0xE8), 0xEB);
+        assert_eq!(block_opposing(0x00), 0x0F);
+        assert_eq!(block_opposing(0xFB), 0xFB);
+    }
+
+    #[test]
+    fn turbo_test() {
+        // A held through turbo, START held normally
+        let (status, turbo_status) = (0x7F, 0xEF);
+        let mut turbo = Turbo::new(1, 2);
+        let mut seen = [0; 6];
+        for status_seen in &mut seen {
+            *status_seen = turbo.apply(status, turbo_status);
+            turbo.step();
+        }
+        assert_eq!(seen, [0x6F, 0x7F, 0x7F, 0x6F, 0x7F, 0x7F]);
     }
 }
@@ -1,22 +1,80 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+extern crate alloc;
+#[cfg(feature = "std")]
 extern crate wasm_bindgen;
 
 mod cpu;
 mod config;
 mod bus;
+mod bus_stats;
+mod profiler;
 mod ppu;
 mod apu;
 mod joypad;
 mod timer;
+mod serial;
+mod infrared;
+mod link;
+mod peripherals;
 mod cartridge;
+mod cheats;
+mod disasm;
+mod events;
+mod palette;
+mod rng;
+mod savestate;
+mod sgb;
+mod warnings;
+mod frame;
+mod gameboy;
+mod movie;
+pub mod accuracy;
+pub mod filters;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod screenshot;
+#[cfg(feature = "rl")]
+pub mod rl;
 
-pub use cartridge::Cartridge;
-use config::{AUDIO_SAMPLES, SAMPLING_RATE_HZ};
+pub use cartridge::{fix_header, verify_save, Cartridge, CartridgeError, ClockSource, EmulatedClock, FixedClock, SaveCheck, WallClock};
+#[cfg(not(feature = "std"))]
+pub use cartridge::set_unix_time;
+#[cfg(feature = "std")]
+use cartridge::camera::StaticImage;
+#[cfg(feature = "std")]
+use config::{AGB_MODE, AUDIO_SAMPLES, SAMPLING_RATE_HZ};
+#[cfg(feature = "std")]
 use constants::{BYTES_PER_PIXEL, LCD_HEIGHT, LCD_WIDTH};
-pub use cpu::Cpu;
+pub use bus::Bus;
+pub use cpu::{Cpu, Diagnostics, GBModel, InitialState};
+pub use disasm::{disassemble, disassemble_with, export_rgbds, DisasmLine, CDL_CODE};
+pub use events::{CoreEvent, Timestamp};
+pub use frame::Frame;
+pub use gameboy::{verify_determinism, Button, Buttons, GameBoy, GameBoyBuilder};
+pub use joypad::{block_opposing, Turbo};
+pub use apu::{ApuWrite, ResampleQuality, CHANNEL_NAMES};
+pub use profiler::{FrameProfile, HostClock};
+pub use serial::{HostLink, HostSender, LinkPort, SerialLink};
+pub use infrared::{IrLoopback, IrPort, IrTransceiver};
+pub use palette::{ColourCorrection, DmgPalette, PRESETS};
+pub use ppu::LcdOffPolicy;
+pub use savestate::StateError;
+pub use cheats::{Cheat, CheatCode};
+pub use movie::Movie;
+pub use warnings::Warning;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub use link::tcp::{LinkMode, Speculation, TcpLink};
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub use peripherals::printer::Printer;
 
-use cpu::GBModel;
+#[cfg(feature = "std")]
+use std::cell::RefCell;
+#[cfg(feature = "std")]
+use std::rc::Rc;
+#[cfg(feature = "std")]
 use wasm_bindgen::prelude::*;
 
+#[cfg(feature = "std")]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
@@ -45,31 +103,40 @@ pub mod constants {
     pub const T_CYCLE_DURATION_NS: u64 = (1e9 as u32 / T_CYCLE_HZ) as u64;
 }
 
+#[cfg(feature = "std")]
 #[wasm_bindgen]
 pub struct Emulator {
     title: String,
     cpu: Cpu,
     audio_output_flat: [f32; 2 * AUDIO_SAMPLES],
+    // audio produced by run_frame() that hasn't been taken yet
+    pending_audio: Vec<f32>,
+    // bytes the game sent over the link port since take_serial_received() (see connect_serial_host)
+    serial_received: Rc<RefCell<Vec<u8>>>,
 }
 
+#[cfg(feature = "std")]
 #[wasm_bindgen]
 impl Emulator {
-    pub fn new(cartridge_bytes: &[u8]) -> Self {
-        let cartridge = Cartridge::from_bytes(cartridge_bytes);
+    /// Loads a ROM image; fails with a readable message for corrupt or unsupported ROMs.
+    pub fn new(cartridge_bytes: &[u8]) -> Result<Emulator, String> {
+        let cartridge = Cartridge::from_bytes(cartridge_bytes).map_err(|e| e.to_string())?;
         let title = cartridge.get_title();
 
         let model = if cartridge.cgb_compatible() {
-            GBModel::CGB
+            if AGB_MODE { GBModel::AGB } else { GBModel::CGB }
         } else {
             GBModel::DMG
         };
         log(&format!("detected model: {:?}", model));
 
-        Emulator { 
+        Ok(Emulator { 
             title,
             cpu: Cpu::new(cartridge, model),
             audio_output_flat: [0.0; 2 * AUDIO_SAMPLES],
-        }
+            pending_audio: Vec::new(),
+            serial_received: Rc::new(RefCell::new(Vec::new())),
+        })
     }
 
     pub fn step(&mut self) {
@@ -93,14 +160,48 @@ impl Emulator {
         }
     }
 
+    /// Returns the core's behaviour revision (see accuracy::ACCURACY_REV), to store with
+    /// recordings of its exact output.
+    pub fn accuracy_rev() -> u32 {
+        accuracy::ACCURACY_REV
+    }
+
     pub fn audio_output_length() -> usize {
         2 * AUDIO_SAMPLES
     }
 
+    /// Returns the sample rate audio is produced at unless set_audio_rate() is called.
     pub fn audio_rate() -> u32 {
         SAMPLING_RATE_HZ
     }
 
+    /// Produces audio at hz from now on, e.g. the AudioContext's sampleRate (often 44100).
+    pub fn set_audio_rate(&mut self, hz: u32) {
+        self.cpu.set_audio_rate(hz);
+    }
+
+    /// Brings audio down to its sample rate with `linear` or `band-limited` resampling.
+    pub fn set_resampler(&mut self, quality: &str) -> Result<(), String> {
+        self.cpu.set_resample_quality(quality.parse()?);
+        Ok(())
+    }
+
+    /// Stops producing audio (or starts again) while the page is muted or hidden, which saves
+    /// a good part of the emulator's work on phones; games keep running the same.
+    pub fn set_audio_output(&mut self, enabled: bool) {
+        self.cpu.set_audio_output(enabled);
+    }
+
+    /// Turns the hardware's high-pass filter on the audio output on (the default) or off.
+    pub fn set_high_pass(&mut self, on: bool) {
+        self.cpu.set_high_pass(on);
+    }
+
+    /// Mutes (or unmutes) sound channel ch: 0-1 pulse, 2 wave, 3 noise.
+    pub fn set_channel_enabled(&mut self, ch: usize, enabled: bool) {
+        self.cpu.set_channel_enabled(ch, enabled);
+    }
+
     pub fn get_display_output(&mut self) -> Option<*const u8> {
         match self.cpu.get_display_output() {
             Some(display_output) => Some(display_output.as_ptr()),
@@ -108,6 +209,46 @@ impl Emulator {
         }
     }
 
+    /// Runs until the next frame is ready and returns it as RGBA bytes (for ImageData).
+    /// Meant for running inside a Web Worker, where the returned buffer can be transferred
+    /// to the main thread instead of being read out of wasm memory; audio produced
+    /// meanwhile is kept for take_audio(). joypad_status is latched at the VBlank ending
+    /// this frame, so it applies to the frame returned by the next call.
+    pub fn run_frame(&mut self, joypad_status: u8) -> Vec<u8> {
+        self.cpu.update_joypad(joypad_status);
+
+        let rgba = bgra_to_rgba(&self.cpu.step_frame().pixels);
+        self.pending_audio.extend_from_slice(self.cpu.drain_audio());
+        rgba
+    }
+
+    /// Runs until the next VBlank (or a frame's worth of cycles while the LCD is off), for
+    /// pages that count frames themselves; returns true if a frame was drawn, which
+    /// frame_rgba() then returns. Audio is kept for take_audio(), like run_frame() does.
+    pub fn run_until_vblank(&mut self) -> bool {
+        let frame_done = self.cpu.run_until_vblank();
+        self.pending_audio.extend_from_slice(self.cpu.drain_audio());
+        frame_done
+    }
+
+    /// Runs for at least t_cycles T-cycles (2^22 a second) and returns how many were run.
+    pub fn run_cycles(&mut self, t_cycles: u32) -> u32 {
+        let run = self.cpu.run_cycles(t_cycles as u64);
+        self.pending_audio.extend_from_slice(self.cpu.drain_audio());
+        run as u32
+    }
+
+    /// Returns the last frame drawn as RGBA bytes (see run_frame()).
+    pub fn frame_rgba(&self) -> Vec<u8> {
+        bgra_to_rgba(&self.cpu.frame().pixels)
+    }
+
+    /// Returns interleaved stereo samples produced by run_frame(), run_until_vblank() or
+    /// run_cycles() since the last call.
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.pending_audio)
+    }
+
     pub fn display_height() -> usize {
         LCD_HEIGHT
     }
@@ -120,25 +261,167 @@ impl Emulator {
         BYTES_PER_PIXEL
     }
 
-    pub fn entered_hblank(&self) -> bool {
-        self.cpu.entered_hblank()
+    /// Returns a description of the oldest core event that hasn't been polled yet (if any).
+    pub fn poll_event(&mut self) -> Option<String> {
+        self.cpu.poll_event().map(|event| event.to_string())
+    }
+
+    /// Returns the report built when the game last stopped responding (see CoreEvent::NotResponding).
+    pub fn take_diagnostic_report(&mut self) -> Option<String> {
+        self.cpu.take_diagnostics().map(|diagnostics| diagnostics.report)
+    }
+
+    /// Shows an RGBA frame (e.g. from a webcam or an uploaded image) to the Pocket Camera's sensor.
+    pub fn set_camera_frame(&mut self, width: usize, height: usize, rgba: Vec<u8>) {
+        match StaticImage::from_rgba(width, height, &rgba) {
+            Ok(image) => self.cpu.set_image_source(Box::new(image)),
+            Err(e) => log(&format!("unable to set camera frame: {}", e)),
+        }
+    }
+
+    /// Returns true if the cartridge has a clock the game can read (see adjust_rtc).
+    pub fn has_rtc(&self) -> bool {
+        self.cpu.has_rtc()
+    }
+
+    /// Moves the cartridge's clock seconds forward (or back, for negative seconds).
+    pub fn adjust_rtc(&mut self, seconds: i32) {
+        self.cpu.adjust_rtc(seconds as i64);
+    }
+
+    /// Overrides how strongly this game rumbles (0.0 to 1.0); see CoreEvent::RumbleIntensity.
+    pub fn set_rumble_strength(&mut self, strength: f32) {
+        self.cpu.set_rumble_strength(strength);
+    }
+
+    /// Makes the page the game's link partner: bytes the game sends are collected for
+    /// take_serial_received(), and bytes given to serial_send() are sent back to it.
+    pub fn connect_serial_host(&mut self) {
+        let received = self.serial_received.clone();
+        self.cpu.connect_serial_host(Box::new(move |byte| received.borrow_mut().push(byte)));
     }
 
+    /// Queues byte to be sent to the game with its next serial transfer.
+    pub fn serial_send(&mut self, byte: u8) {
+        self.cpu.serial_send(byte);
+    }
+
+    /// Returns (and clears) the bytes the game sent since the last call.
+    pub fn take_serial_received(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.serial_received.borrow_mut())
+    }
+
+    /// Returns the emulation warnings seen so far (e.g. writes to unmapped registers) as text,
+    /// one per line with when it first happened and how often.
+    pub fn warnings_report(&self) -> String {
+        self.cpu.warnings_report()
+    }
+
+    /// Turns bus access counting on (from zero) or off.
+    pub fn set_bus_stats(&mut self, enabled: bool) {
+        self.cpu.set_bus_stats(enabled);
+    }
+
+    /// Returns bus access statistics and a heatmap of the address space as text (if counting is on).
+    pub fn bus_stats_report(&self) -> Option<String> {
+        self.cpu.bus_stats_report()
+    }
+
+    /// Sets the joypad inputs, which the game sees from the start of the next VBlank on.
     pub fn update_joypad(&mut self, status: u8) {
         self.cpu.update_joypad(status)
     }
 
+    /// (SGB ONLY) Sets the inputs of another controller (player 1-3), for games that
+    /// read more than one; latched along with update_joypad's.
+    pub fn update_joypad_player(&mut self, player: usize, status: u8) {
+        self.cpu.update_joypad_player(player, status)
+    }
+
+    /// Returns the joypad inputs the game currently sees (latched at the last VBlank).
+    pub fn current_inputs(&self) -> u8 {
+        self.cpu.current_inputs()
+    }
+
+    pub fn cycle_palette(&mut self) {
+        self.cpu.cycle_dmg_palette()
+    }
+
+    /// Shows DMG games in a palette preset or custom colours (see DmgPalette's FromStr).
+    pub fn set_palette(&mut self, palette: &str) -> Result<(), String> {
+        self.cpu.set_dmg_palette(palette.parse()?);
+        Ok(())
+    }
+
+    /// Mixes ghosting/255 of the last frame into every frame, like the LCD's ghosting (0 turns it off).
+    pub fn set_lcd_ghosting(&mut self, ghosting: u8) {
+        self.cpu.set_lcd_ghosting(ghosting);
+    }
+
+    /// Adds a GameShark or Game Genie code (turned on); returns its index.
+    pub fn add_cheat(&mut self, code: &str) -> Result<usize, String> {
+        self.cpu.add_cheat(code)
+    }
+
+    /// Removes the cheat at index; the ones added after it move down one.
+    pub fn remove_cheat(&mut self, index: usize) {
+        self.cpu.remove_cheat(index);
+    }
+
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) {
+        self.cpu.set_cheat_enabled(index, enabled);
+    }
+
+    /// Sets what's shown while the game has the LCD off: `hold` (the last frame), `blank`
+    /// or `fade` (see LcdOffPolicy).
+    pub fn set_lcd_off_policy(&mut self, policy: &str) -> Result<(), String> {
+        self.cpu.set_lcd_off_policy(policy.parse()?);
+        Ok(())
+    }
+
+    /// Tints every pixel by the layer that drew it: BG blue, window green, objects red (OBP0)
+    /// or yellow (OBP1), and BG/window over an object through priority magenta.
+    pub fn set_layer_tints(&mut self, on: bool) {
+        self.cpu.set_layer_tints(on);
+    }
+
+    /// Makes every access to cartridge RAM take m_cycles extra M-cycles, like flashcarts
+    /// whose SRAM can't keep up (0, the default, is how cartridges behave).
+    pub fn set_sram_wait(&mut self, m_cycles: u32) {
+        self.cpu.set_sram_wait(m_cycles);
+    }
+
+    /// Converts CGB colours with `off`, `simple` or `balanced` colour correction
+    /// (see ColourCorrection), starting with the frame being drawn.
+    pub fn set_colour_correction(&mut self, correction: &str) -> Result<(), String> {
+        self.cpu.set_colour_correction(correction.parse()?);
+        Ok(())
+    }
+
     pub fn save_game(&mut self) {
         self.cpu.save_mbc_state()
     }
 
-    #[cfg(target_arch = "wasm32")]
+    /// Returns true (once) if the game wrote to battery-backed RAM since the last call, 
+    /// so the page can autosave with save_game().
+    pub fn take_ram_dirty(&mut self) -> bool {
+        self.cpu.take_ram_dirty()
+    }
+
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     pub fn fetch_game_id(&self) -> Option<String> {
         self.cpu.save_id()
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
     pub fn load_save(&mut self, data: Vec<u8>, save_type: &str) {
         self.cpu.load_save(data, save_type);
     }
 }
+
+#[cfg(feature = "std")]
+fn bgra_to_rgba(bgra: &[u8]) -> Vec<u8> {
+    bgra.chunks_exact(BYTES_PER_PIXEL)
+        .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+        .collect()
+}
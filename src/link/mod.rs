@@ -0,0 +1,4 @@
+// link cable transports for the serial port (see serial::SerialLink);
+// two emulators in the same process can use serial::LinkPort instead
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod tcp;
@@ -0,0 +1,726 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::events::CoreEvent;
+use crate::serial::SerialLink;
+
+// how long a transfer waits for the other side to get ready for it in LinkMode::Direct (about
+// a frame), which covers the two emulators drifting apart a little; after that the line reads
+// as disconnected
+const SYNC_TIMEOUT: Duration = Duration::from_millis(17);
+// how long a transfer waits for the other side's reply before giving up on the connection
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+// in LinkMode::Lockstep, how many frames one side may run ahead of the other, which hides
+// round trips up to this many frames long (about 100 ms)
+const LOCKSTEP_BUFFER_FRAMES: u64 = 6;
+// how long LinkMode::Lockstep waits on the other side (e.g. while it's paused) before
+// letting this one carry on alone for a frame or a transfer
+const LOCKSTEP_TIMEOUT: Duration = Duration::from_secs(5);
+// in LinkMode::Speculative, how many frames back a guessed reply can be rolled back, which
+// hides round trips up to this many frames long (about 130 ms); a transfer whose reply is
+// later than that holds up the frame until it arrives
+pub const SPECULATION_FRAMES: u64 = 8;
+
+// every message is [kind, byte]
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+const NO_REPLY: u8 = 0x03;
+// sent by the host when the other side connects, with the session's LinkMode
+const HELLO: u8 = 0x04;
+// LinkMode::Lockstep's messages: the sender's game is (or stopped) waiting on a transfer
+// with byte in SB, finished a frame, is holding a transfer of its own until it hears back
+// (and is done with it), or clocked byte into the other side's waiting transfer
+const WAITING: u8 = 0x05;
+const NOT_WAITING: u8 = 0x06;
+const FRAME: u8 = 0x07;
+const CLOCKING: u8 = 0x08;
+const CLOCKED: u8 = 0x09;
+const SENT: u8 = 0x0A;
+
+/// How transfers wait on the other side, picked by the host for the whole session.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinkMode {
+    /// Both sides tell each other when their games wait on a transfer and when they finish a
+    /// frame, and neither runs more than LOCKSTEP_BUFFER_FRAMES ahead of the other: a transfer
+    /// goes to the other side's waiting byte as soon as it's heard of, or otherwise is held
+    /// until the other side finishes the frame it's in without getting ready (when it reads as
+    /// disconnected). Latency slows the games down instead of dropping bytes, so it's meant
+    /// for trades and battles over the internet.
+    Lockstep,
+    /// A transfer the other side isn't ready for within about a frame reads as disconnected,
+    /// which keeps games responsive on a LAN but desyncs on slow connections.
+    Direct,
+    /// Transfers are exchanged as in Direct, but a transfer this side clocks doesn't wait for
+    /// the reply: the game goes on with a guess (the byte the other side last said it's
+    /// waiting with, or else its last reply), and if the real reply turns out different, the
+    /// frontend rolls the game back through save states and runs it again with it (see
+    /// Speculation). Games stay as responsive as the other side's, at the cost of running
+    /// frames twice on a wrong guess; the bytes sent can't be taken back, so a game that sends
+    /// something else once rolled back still desyncs.
+    Speculative,
+}
+
+/// Parses `lockstep`, `direct` or `speculative`.
+impl FromStr for LinkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lockstep" => Ok(LinkMode::Lockstep),
+            "direct" => Ok(LinkMode::Direct),
+            "speculative" => Ok(LinkMode::Speculative),
+            _ => Err(format!("Unknown link mode '{}' (expected lockstep, direct or speculative)", s)),
+        }
+    }
+}
+
+/// This side's state, shared with the thread answering the other side's transfers.
+struct Port {
+    waiting: Option<u8>,
+    received: Option<u8>,
+    // what the other side last told us in LinkMode::Lockstep
+    peer_waiting: Option<u8>,
+    peer_frames: u64,
+    peer_clocking: bool,
+    // CLOCKING messages heard, so a wait can tell one came (and went) in the meantime
+    peer_clockings: u64,
+    connected: bool,
+}
+
+impl Default for Port {
+    fn default() -> Self {
+        Port {
+            waiting: None,
+            received: None,
+            peer_waiting: None,
+            peer_frames: 0,
+            peer_clocking: false,
+            peer_clockings: 0,
+            connected: true,
+        }
+    }
+}
+
+/// A transfer in LinkMode::Speculative, kept until it's too old to roll back.
+enum Exchange {
+    /// This side clocked a transfer in frame and went on with guess as the reply; reply is
+    /// the real one, once the other side's answer arrives.
+    Clocked { frame: u64, guess: Option<u8>, reply: Option<Option<u8>> },
+    /// The other side clocked byte into a transfer this side was waiting on in frame.
+    Received { frame: u64, byte: u8 },
+}
+
+impl Exchange {
+    fn frame(&self) -> u64 {
+        match self {
+            Exchange::Clocked { frame, .. } | Exchange::Received { frame, .. } => *frame,
+        }
+    }
+}
+
+/// LinkMode::Speculative's transfers, shared with the frontend through Speculation.
+#[derive(Default)]
+struct Log {
+    exchanges: VecDeque<Exchange>,
+    // how many of exchanges the game's been through; after a rollback, the rest are played
+    // back to it instead of being exchanged again
+    played: usize,
+    // frames this side finished
+    frames: u64,
+    // the earliest frame a guess played to the game turned out wrong in, until it's taken
+    mispredicted: Option<u64>,
+    // the other side's last reply, guessed when it isn't known to be waiting
+    last_reply: Option<u8>,
+}
+
+impl Log {
+    /// Fills in the oldest transfer still waiting on reply.
+    fn confirm(&mut self, reply: Option<u8>) {
+        self.last_reply = reply;
+        let pending = self.exchanges.iter().position(|exchange| matches!(exchange, Exchange::Clocked { reply: None, .. }));
+        if let Some(index) = pending {
+            if let Exchange::Clocked { frame, guess, reply: slot } = &mut self.exchanges[index] {
+                *slot = Some(reply);
+                // one the game hasn't got to again yet is played back right anyway
+                if *guess != reply && index < self.played {
+                    self.mispredicted = Some(self.mispredicted.map_or(*frame, |earliest| earliest.min(*frame)));
+                }
+            }
+        }
+    }
+
+    fn confirm_all(&mut self, replies: &Receiver<Option<u8>>) {
+        while let Ok(reply) = replies.try_recv() {
+            self.confirm(reply);
+        }
+    }
+
+    /// Returns the frame of the oldest transfer still waiting on its reply.
+    fn oldest_pending(&self) -> Option<u64> {
+        self.exchanges.iter().find_map(|exchange| match exchange {
+            Exchange::Clocked { frame, reply: None, .. } => Some(*frame),
+            _ => None,
+        })
+    }
+}
+
+/// The frontend's handle on a LinkMode::Speculative link, to roll the game back when a guessed
+/// reply turns out wrong: it keeps a save state from the start of each of the last
+/// SPECULATION_FRAMES frames, and once take_misprediction() returns one of them, loads it,
+/// calls rewind() and runs the game back up to where it was, the link playing back the
+/// transfers made since (with the real replies) instead of making them again.
+#[derive(Clone)]
+pub struct Speculation(Arc<Mutex<Log>>);
+
+impl Speculation {
+    /// Returns the number of frames this side's game finished, i.e. the frame it's in.
+    pub fn frame(&self) -> u64 {
+        self.0.lock().unwrap().frames
+    }
+
+    /// Returns the earliest frame a guessed reply turned out wrong in since the last call.
+    pub fn take_misprediction(&self) -> Option<u64> {
+        self.0.lock().unwrap().mispredicted.take()
+    }
+
+    /// Lets the link know the game was taken back to the start of frame.
+    pub fn rewind(&self, frame: u64) {
+        let mut log = self.0.lock().unwrap();
+        log.frames = frame;
+        log.played = log.exchanges.iter().position(|exchange| exchange.frame() >= frame).unwrap_or(log.exchanges.len());
+    }
+}
+
+/// Link cable to another melon-gb over TCP. Each transfer clocked by one side blocks until
+/// the other side has answered with the byte in its SB (see LinkMode), so both see the
+/// same exchange.
+pub struct TcpLink {
+    writer: Arc<Mutex<TcpStream>>,
+    port: Arc<(Mutex<Port>, Condvar)>,
+    replies: Receiver<Option<u8>>,
+    mode: LinkMode,
+    // frames this side finished, in LinkMode::Lockstep
+    frames: u64,
+    // transfers that can still be rolled back, in LinkMode::Speculative
+    log: Arc<Mutex<Log>>,
+    // whether poll_event() reported the connection, and its drop
+    reported_connected: bool,
+    reported_disconnected: bool,
+}
+
+impl TcpLink {
+    /// Waits for the other side to connect to addr (e.g. "0.0.0.0:5738"), then tells it
+    /// the session's mode.
+    pub fn host(addr: &str, mode: LinkMode) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        println!("Waiting for link cable connection on {}...", listener.local_addr()?);
+        let (stream, peer) = listener.accept()?;
+        println!("Link cable connected to {} ({:?} mode)", peer, mode);
+        TcpLink::accept(stream, mode)
+    }
+
+    /// Connects to the other side hosting at addr, in the mode it picked.
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let link = TcpLink::join(stream)?;
+        println!("Link cable connected to {} ({:?} mode)", addr, link.mode);
+        Ok(link)
+    }
+
+    /// Starts a session with mode over stream, as the host.
+    pub fn accept(mut stream: TcpStream, mode: LinkMode) -> io::Result<Self> {
+        let byte = match mode {
+            LinkMode::Lockstep => 0,
+            LinkMode::Direct => 1,
+            LinkMode::Speculative => 2,
+        };
+        stream.write_all(&[HELLO, byte])?;
+        TcpLink::from_stream(stream, mode)
+    }
+
+    /// Joins the host's session over stream, in the mode the host sends.
+    pub fn join(mut stream: TcpStream) -> io::Result<Self> {
+        let mut message = [0; 2];
+        stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+        stream.read_exact(&mut message)?;
+        stream.set_read_timeout(None)?;
+        let mode = match message {
+            [HELLO, 0] => LinkMode::Lockstep,
+            [HELLO, 1] => LinkMode::Direct,
+            [HELLO, 2] => LinkMode::Speculative,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown greeting {:02X?}", message))),
+        };
+        TcpLink::from_stream(stream, mode)
+    }
+
+    fn from_stream(stream: TcpStream, mode: LinkMode) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let reader = stream.try_clone()?;
+        let writer = Arc::new(Mutex::new(stream));
+        let port = Arc::new((Mutex::new(Port::default()), Condvar::new()));
+        let (reply_tx, replies) = mpsc::channel();
+
+        let (thread_writer, thread_port) = (writer.clone(), port.clone());
+        thread::spawn(move || {
+            if let Err(e) = serve(reader, thread_writer, &thread_port, reply_tx) {
+                eprintln!("Link cable disconnected: {}", e);
+            }
+            let (lock, ready) = &*thread_port;
+            lock.lock().unwrap().connected = false;
+            ready.notify_all();
+        });
+
+        Ok(TcpLink {
+            writer,
+            port,
+            replies,
+            mode,
+            frames: 0,
+            log: Arc::new(Mutex::new(Log::default())),
+            reported_connected: false,
+            reported_disconnected: false,
+        })
+    }
+
+    /// Returns the handle to roll the game back with, in LinkMode::Speculative.
+    pub fn speculation(&self) -> Option<Speculation> {
+        (self.mode == LinkMode::Speculative).then(|| Speculation(self.log.clone()))
+    }
+
+    fn send(&self, kind: u8, byte: u8) -> io::Result<()> {
+        self.writer.lock().unwrap().write_all(&[kind, byte])
+    }
+
+    /// Clocks byte into the other side's waiting transfer in LinkMode::Lockstep, holding it
+    /// until the other side is heard to be waiting, clocking a transfer of its own (so neither
+    /// will ever be waiting) or done with the frame this side is in.
+    fn clock_lockstep(&mut self, byte: u8) -> io::Result<Option<u8>> {
+        let (lock, ready) = &*self.port;
+        let frames = self.frames;
+        let unanswered = |port: &Port| port.peer_waiting.is_none() && !port.peer_clocking && port.peer_frames <= frames;
+
+        let mut port = lock.lock().unwrap();
+        // the other side hears of it even if this side won't wait, in case it's holding a transfer too
+        let held = port.connected && port.peer_waiting.is_none();
+        if held {
+            drop(port);
+            self.send(CLOCKING, 0)?;
+            port = lock.lock().unwrap();
+            let clockings = port.peer_clockings;
+            port = ready.wait_timeout_while(port, LOCKSTEP_TIMEOUT, |port| {
+                port.connected && unanswered(port) && port.peer_clockings == clockings
+            }).unwrap().0;
+        }
+        let reply = port.peer_waiting.take();
+        drop(port);
+
+        if reply.is_some() {
+            self.send(SENT, byte)?;
+        }
+        if held {
+            self.send(CLOCKED, 0)?;
+        }
+        Ok(reply)
+    }
+
+    /// Tells the other side this one finished a frame in LinkMode::Lockstep, then waits for
+    /// it to get within LOCKSTEP_BUFFER_FRAMES.
+    fn end_lockstep_frame(&mut self) -> io::Result<()> {
+        self.frames += 1;
+        self.send(FRAME, 0)?;
+
+        let (lock, ready) = &*self.port;
+        let frames = self.frames;
+        let port = lock.lock().unwrap();
+        let (port, timeout) = ready.wait_timeout_while(port, LOCKSTEP_TIMEOUT, |port| {
+            port.connected && port.peer_frames + LOCKSTEP_BUFFER_FRAMES < frames
+        }).unwrap();
+        if timeout.timed_out() && port.connected {
+            eprintln!("Link cable partner is {} frames behind, carrying on without it", frames - port.peer_frames);
+        }
+        Ok(())
+    }
+
+    /// Sends byte to the other side in LinkMode::Speculative and returns the reply guessed for
+    /// it, or after a rollback, plays back the transfer made there the first time.
+    fn clock_speculative(&mut self, byte: u8) -> io::Result<Option<u8>> {
+        let (peer_waiting, connected) = {
+            let mut port = self.port.0.lock().unwrap();
+            (port.peer_waiting.take(), port.connected)
+        };
+        let mut log = self.log.lock().unwrap();
+        log.confirm_all(&self.replies);
+        let played = log.played;
+        if let Some(Exchange::Clocked { guess, reply, .. }) = log.exchanges.get(played) {
+            let reply = reply.unwrap_or(*guess);
+            log.played += 1;
+            return Ok(reply);
+        }
+        if !connected {
+            return Ok(None);
+        }
+
+        let frame = log.frames;
+        let guess = peer_waiting.or(log.last_reply);
+        log.exchanges.push_back(Exchange::Clocked { frame, guess, reply: None });
+        // if the game's gone another way since a rollback, the rest isn't played back
+        log.played = log.exchanges.len();
+        drop(log);
+        self.send(TRANSFER, byte)?;
+        Ok(guess)
+    }
+
+    /// Returns the byte the other side clocked into this side's waiting transfer in
+    /// LinkMode::Speculative, or after a rollback, the one it clocked there the first time.
+    fn receive_speculative(&mut self) -> Option<u8> {
+        let mut log = self.log.lock().unwrap();
+        let (played, frames) = (log.played, log.frames);
+        if let Some(&Exchange::Received { frame, byte }) = log.exchanges.get(played) {
+            if frame <= frames {
+                log.played += 1;
+                return Some(byte);
+            }
+        }
+
+        let byte = self.port.0.lock().unwrap().received.take()?;
+        log.exchanges.push_back(Exchange::Received { frame: frames, byte });
+        log.played = log.exchanges.len();
+        Some(byte)
+    }
+
+    /// Counts a frame in LinkMode::Speculative, waiting on replies too old to roll back, and
+    /// forgets transfers that are.
+    fn end_speculative_frame(&mut self) {
+        let mut log = self.log.lock().unwrap();
+        log.frames += 1;
+        log.confirm_all(&self.replies);
+        while let Some(frame) = log.oldest_pending() {
+            if frame + SPECULATION_FRAMES > log.frames {
+                break;
+            }
+            match self.replies.recv_timeout(REPLY_TIMEOUT) {
+                Ok(reply) => log.confirm(reply),
+                Err(_) => {
+                    eprintln!("Link cable partner stopped answering, carrying on without it");
+                    self.port.0.lock().unwrap().connected = false;
+                    while log.oldest_pending().is_some() {
+                        log.confirm(None);
+                    }
+                },
+            }
+        }
+
+        let frames = log.frames;
+        while log.exchanges.front().is_some_and(|exchange| exchange.frame() + SPECULATION_FRAMES <= frames) {
+            log.exchanges.pop_front();
+            log.played = log.played.saturating_sub(1);
+        }
+    }
+}
+
+/// Answers the other side's transfers and passes its replies and (in LinkMode::Lockstep)
+/// what it says it's doing on, until the connection drops.
+fn serve(
+    mut reader: TcpStream,
+    writer: Arc<Mutex<TcpStream>>,
+    port: &(Mutex<Port>, Condvar),
+    reply_tx: Sender<Option<u8>>,
+) -> io::Result<()> {
+    let (lock, ready) = port;
+    let mut message = [0; 2];
+    loop {
+        reader.read_exact(&mut message)?;
+        match message {
+            [TRANSFER, byte] => {
+                let guard = lock.lock().unwrap();
+                let (mut guard, _) = ready.wait_timeout_while(guard, SYNC_TIMEOUT, |port| port.waiting.is_none()).unwrap();
+                let reply = match guard.waiting.take() {
+                    Some(reply) => {
+                        guard.received = Some(byte);
+                        [REPLY, reply]
+                    },
+                    None => [NO_REPLY, 0],
+                };
+                drop(guard);
+                writer.lock().unwrap().write_all(&reply)?;
+            },
+            [REPLY, byte] => { let _ = reply_tx.send(Some(byte)); },
+            [NO_REPLY, _] => { let _ = reply_tx.send(None); },
+            [kind @ (WAITING | NOT_WAITING | FRAME | CLOCKING | CLOCKED | SENT), byte] => {
+                let mut port = lock.lock().unwrap();
+                match kind {
+                    WAITING => port.peer_waiting = Some(byte),
+                    NOT_WAITING => port.peer_waiting = None,
+                    FRAME => port.peer_frames += 1,
+                    CLOCKING => {
+                        port.peer_clocking = true;
+                        port.peer_clockings += 1;
+                    },
+                    CLOCKED => port.peer_clocking = false,
+                    // a transfer this side stopped waiting on in the meantime is missed
+                    _ => if port.waiting.take().is_some() {
+                        port.received = Some(byte);
+                    },
+                }
+                ready.notify_all();
+            },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown message {:02X?}", message))),
+        }
+    }
+}
+
+impl SerialLink for TcpLink {
+    fn clock_transfer(&mut self, byte: u8) -> Option<u8> {
+        let result = match self.mode {
+            LinkMode::Lockstep => self.clock_lockstep(byte),
+            LinkMode::Direct => self.send(TRANSFER, byte).map(|_| self.replies.recv_timeout(REPLY_TIMEOUT).ok().flatten()),
+            LinkMode::Speculative => self.clock_speculative(byte),
+        };
+        result.unwrap_or_else(|e| {
+            eprintln!("Link cable transfer failed: {}", e);
+            None
+        })
+    }
+
+    fn set_waiting(&mut self, byte: Option<u8>) {
+        let (lock, ready) = &*self.port;
+        let mut port = lock.lock().unwrap();
+        let changed = port.waiting != byte;
+        port.waiting = byte;
+        ready.notify_all();
+        drop(port);
+
+        // the other side guesses replies from it in LinkMode::Speculative
+        if self.mode != LinkMode::Direct && changed {
+            let result = match byte {
+                Some(byte) => self.send(WAITING, byte),
+                None => self.send(NOT_WAITING, 0),
+            };
+            if let Err(e) = result {
+                eprintln!("Link cable transfer failed: {}", e);
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        if self.mode == LinkMode::Speculative {
+            return self.receive_speculative();
+        }
+        self.port.0.lock().unwrap().received.take()
+    }
+
+    fn poll_event(&mut self) -> Option<CoreEvent> {
+        if !self.reported_connected {
+            self.reported_connected = true;
+            return Some(CoreEvent::SerialConnected);
+        }
+        if !self.reported_disconnected && !self.port.0.lock().unwrap().connected {
+            self.reported_disconnected = true;
+            return Some(CoreEvent::SerialDisconnected);
+        }
+        None
+    }
+
+    fn end_frame(&mut self) {
+        match self.mode {
+            LinkMode::Lockstep => if let Err(e) = self.end_lockstep_frame() {
+                eprintln!("Link cable transfer failed: {}", e);
+            },
+            LinkMode::Speculative => self.end_speculative_frame(),
+            LinkMode::Direct => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread::{sleep, spawn};
+    use std::time::{Duration, Instant};
+
+    use super::{LinkMode, TcpLink, LOCKSTEP_BUFFER_FRAMES, SYNC_TIMEOUT};
+    use crate::cartridge::Cartridge;
+    use crate::cpu::{test_helpers::make_test_rom, Cpu, GBModel};
+    use crate::events::CoreEvent;
+    use crate::serial::SerialLink;
+
+    fn link_pair(mode: LinkMode) -> (TcpLink, TcpLink) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let host = TcpLink::accept(listener.accept().unwrap().0, mode).unwrap();
+        (host, TcpLink::join(client).unwrap())
+    }
+
+    #[test]
+    fn tcp_link_test() {
+        let (mut host, mut client) = link_pair(LinkMode::Direct);
+        assert_eq!(client.mode, LinkMode::Direct);
+
+        // nobody is waiting, so the line reads as disconnected
+        assert_eq!(host.clock_transfer(0x11), None);
+
+        client.set_waiting(Some(0x22));
+        assert_eq!(host.clock_transfer(0x11), Some(0x22));
+        assert_eq!(client.receive(), Some(0x11));
+        assert_eq!(client.receive(), None);
+
+        // the transfer goes through once the other side gets ready (in time)
+        let waiter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            host.set_waiting(Some(0x33));
+            host
+        });
+        assert_eq!(client.clock_transfer(0x44), Some(0x33));
+        assert_eq!(waiter.join().unwrap().receive(), Some(0x44));
+    }
+
+    #[test]
+    fn link_events_test() {
+        let (host, client) = link_pair(LinkMode::Direct);
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.connect_serial(Box::new(host));
+
+        // the link's events are queued as frames finish
+        let drain_frame = |cpu: &mut Cpu| -> Vec<CoreEvent> {
+            let mut events = Vec::new();
+            while !events.contains(&CoreEvent::VBlank) {
+                cpu.step();
+                events.extend(std::iter::from_fn(|| cpu.poll_event()));
+            }
+            events.extend(std::iter::from_fn(|| cpu.poll_event()));
+            events
+        };
+        assert!(drain_frame(&mut cpu).contains(&CoreEvent::SerialConnected));
+        assert!(!drain_frame(&mut cpu).contains(&CoreEvent::SerialConnected));
+
+        // as if the other side quit
+        client.writer.lock().unwrap().shutdown(Shutdown::Both).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !drain_frame(&mut cpu).contains(&CoreEvent::SerialDisconnected) {
+            assert!(Instant::now() < deadline, "the dropped link was never reported");
+            sleep(Duration::from_millis(1));
+        }
+        assert!(!drain_frame(&mut cpu).contains(&CoreEvent::SerialDisconnected));
+    }
+
+    /// Waits (a little) for link to pick up a byte the other side clocked into it.
+    fn receive_soon(link: &mut TcpLink) -> Option<u8> {
+        for _ in 0..100 {
+            if let Some(byte) = link.receive() {
+                return Some(byte);
+            }
+            sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    #[test]
+    fn lockstep_link_test() {
+        let (host, mut client) = link_pair(LinkMode::Lockstep);
+        assert_eq!(client.mode, LinkMode::Lockstep);
+
+        // the other side gets ready far later than Direct mode would wait
+        let waiter = spawn(move || {
+            let mut host = host;
+            sleep(Duration::from_millis(100));
+            host.set_waiting(Some(0x55));
+            host
+        });
+        assert_eq!(client.clock_transfer(0x66), Some(0x55));
+        let mut host = waiter.join().unwrap();
+        assert_eq!(receive_soon(&mut host), Some(0x66));
+
+        // a waiting byte the other side already sent over is taken right away
+        client.set_waiting(Some(0x77));
+        sleep(Duration::from_millis(20));
+        let start = Instant::now();
+        assert_eq!(host.clock_transfer(0x88), Some(0x77));
+        assert!(start.elapsed() < Duration::from_millis(20));
+        assert_eq!(receive_soon(&mut client), Some(0x88));
+
+        // once the other side finishes the frame without getting ready, the line reads as disconnected
+        host.end_frame();
+        assert_eq!(client.clock_transfer(0x99), None);
+
+        // both sides clocking at once hear nothing back
+        client.end_frame();
+        let clocker = spawn(move || {
+            let reply = host.clock_transfer(0xAA);
+            (host, reply)
+        });
+        assert_eq!(client.clock_transfer(0xBB), None);
+        assert_eq!(clocker.join().unwrap().1, None);
+
+        assert_eq!("lockstep".parse(), Ok(LinkMode::Lockstep));
+        assert!("rollback".parse::<LinkMode>().is_err());
+    }
+
+    #[test]
+    fn lockstep_frames_test() {
+        let (host, mut client) = link_pair(LinkMode::Lockstep);
+
+        // a side can run up to the buffer ahead, then waits for the other to catch up
+        let start = Instant::now();
+        for _ in 0..LOCKSTEP_BUFFER_FRAMES {
+            client.end_frame();
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        let behind = spawn(move || {
+            let mut host = host;
+            sleep(Duration::from_millis(100));
+            host.end_frame();
+            host
+        });
+        client.end_frame();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        behind.join().unwrap();
+    }
+
+    #[test]
+    fn speculative_link_test() {
+        let (mut host, mut client) = link_pair(LinkMode::Speculative);
+        assert_eq!(client.mode, LinkMode::Speculative);
+        let speculation = client.speculation().unwrap();
+        assert!(host.speculation().is_some());
+
+        // the byte the other side said it's waiting with is guessed right away
+        host.set_waiting(Some(0x12));
+        sleep(Duration::from_millis(20));
+        assert_eq!(client.clock_transfer(0x34), Some(0x12));
+        assert_eq!(receive_soon(&mut host), Some(0x34));
+        sleep(SYNC_TIMEOUT);
+        client.end_frame();
+        assert_eq!(speculation.take_misprediction(), None);
+
+        // the other side isn't waiting this time, so guessing its last reply was wrong
+        let start = Instant::now();
+        assert_eq!(client.clock_transfer(0x56), Some(0x12));
+        assert!(start.elapsed() < SYNC_TIMEOUT);
+        sleep(SYNC_TIMEOUT * 3);
+        client.end_frame();
+        assert_eq!(speculation.frame(), 2);
+        assert_eq!(speculation.take_misprediction(), Some(1));
+        assert_eq!(speculation.take_misprediction(), None);
+
+        // rolled back, the transfer's played back with the real reply instead of being sent again
+        speculation.rewind(1);
+        assert_eq!(client.clock_transfer(0x56), None);
+        client.end_frame();
+        assert_eq!(speculation.take_misprediction(), None);
+        host.set_waiting(Some(0x78));
+        sleep(Duration::from_millis(20));
+        assert_eq!(client.clock_transfer(0x9A), Some(0x78));
+        assert_eq!(receive_soon(&mut host), Some(0x9A));
+
+        assert_eq!("speculative".parse(), Ok(LinkMode::Speculative));
+    }
+}
@@ -0,0 +1,116 @@
+use gbemulib::constants::BYTES_PER_PIXEL;
+
+use crate::ppu::OBJ_PIXEL;
+
+const MAX_ZOOM: usize = 16;
+
+/// A frozen frame to inspect up close: zoomed in around the cursor (in powers of two),
+/// panned by dragging, telling the position and colour of the pixel under the cursor.
+pub struct Magnifier {
+    frame: Vec<u8>,
+    size: (usize, usize),
+    zoom: usize,
+    // top left corner of the view in frame pixels (fractional, so slow drags add up)
+    origin: (f32, f32),
+    // where the cursor is over the screen, as fractions (0.0-1.0) of its width and height
+    cursor: (f32, f32),
+}
+
+impl Magnifier {
+    /// Freezes frame (in the PPU's frame buffer order, of the given width and height).
+    pub fn freeze(frame: &[u8], size: (usize, usize)) -> Self {
+        Magnifier {
+            frame: frame.to_vec(),
+            size,
+            zoom: 1,
+            origin: (0.0, 0.0),
+            cursor: (0.5, 0.5),
+        }
+    }
+
+    pub fn move_cursor(&mut self, cursor: (f32, f32)) {
+        self.cursor = cursor;
+    }
+
+    /// Returns the frame pixel under the cursor, if it's over the screen.
+    pub fn pixel_under_cursor(&self) -> Option<(usize, usize)> {
+        let (x, y) = self.cursor;
+        if !(0.0..1.0).contains(&x) || !(0.0..1.0).contains(&y) {
+            return None;
+        }
+        let (view_width, view_height) = self.view_size();
+        Some((
+            self.origin.0 as usize + (x * view_width as f32) as usize,
+            self.origin.1 as usize + (y * view_height as f32) as usize,
+        ))
+    }
+
+    /// Doubles the zoom per step in (or halves it per step out), keeping the pixel
+    /// under the cursor where it is.
+    pub fn zoom(&mut self, steps: i32) {
+        let (x, y) = self.cursor;
+        let (view_width, view_height) = self.view_size();
+        let anchor = (self.origin.0 + x * view_width as f32, self.origin.1 + y * view_height as f32);
+
+        self.zoom = match steps {
+            0.. => self.zoom << steps.min(4),
+            _ => self.zoom >> (-steps).min(4),
+        }.clamp(1, MAX_ZOOM);
+
+        let (view_width, view_height) = self.view_size();
+        self.origin = (anchor.0 - x * view_width as f32, anchor.1 - y * view_height as f32);
+        self.clamp_origin();
+    }
+
+    /// Moves the picture along with the cursor by (dx, dy), as fractions of the screen.
+    pub fn drag(&mut self, (dx, dy): (f32, f32)) {
+        let (view_width, view_height) = self.view_size();
+        self.origin.0 -= dx * view_width as f32;
+        self.origin.1 -= dy * view_height as f32;
+        self.clamp_origin();
+    }
+
+    /// Returns the part of the frame in view and its size, to be stretched over the screen.
+    pub fn view(&self) -> (Vec<u8>, (usize, usize)) {
+        let (width, _) = self.size;
+        let (view_width, view_height) = self.view_size();
+        let (left, top) = (self.origin.0 as usize, self.origin.1 as usize);
+
+        let mut view = Vec::with_capacity(view_width * view_height * BYTES_PER_PIXEL);
+        for y in top..top + view_height {
+            let start = (y * width + left) * BYTES_PER_PIXEL;
+            view.extend_from_slice(&self.frame[start..start + view_width * BYTES_PER_PIXEL]);
+        }
+        (view, (view_width, view_height))
+    }
+
+    /// Describes the zoom and the pixel under the cursor: its position, colour and, given its
+    /// palette_index (see Cpu::palette_index_at), which palette entry it was drawn with.
+    pub fn describe(&self, palette_index: Option<u8>) -> String {
+        let (x, y) = match self.pixel_under_cursor() {
+            Some(pixel) => pixel,
+            None => return format!("Magnifier {}x", self.zoom),
+        };
+        let i = (y * self.size.0 + x) * BYTES_PER_PIXEL;
+        let (red, green, blue) = (self.frame[i + 2], self.frame[i + 1], self.frame[i]);
+
+        let mut description = format!("Magnifier {}x | ({}, {}) #{:02X}{:02X}{:02X}", self.zoom, x, y, red, green, blue);
+        if let Some(index) = palette_index {
+            let layer = if index & OBJ_PIXEL != 0 { "OBJ" } else { "BG" };
+            description += &format!(" {} palette {} colour {}", layer, (index >> 2) & 0x07, index & 0x03);
+        }
+        description
+    }
+
+    fn view_size(&self) -> (usize, usize) {
+        let (width, height) = self.size;
+        ((width / self.zoom).max(1), (height / self.zoom).max(1))
+    }
+
+    fn clamp_origin(&mut self) {
+        let (width, height) = self.size;
+        let (view_width, view_height) = self.view_size();
+        self.origin.0 = self.origin.0.clamp(0.0, (width - view_width) as f32);
+        self.origin.1 = self.origin.1.clamp(0.0, (height - view_height) as f32);
+    }
+}
@@ -1,33 +1,607 @@
 #[cfg(not(target_arch = "wasm32"))]
 extern crate sdl2;
 
+extern crate alloc;
 extern crate gbemulib;
 
 mod cpu;
 mod config;
 mod bus;
+mod bus_stats;
+mod profiler;
 mod ppu;
 mod apu;
 mod joypad;
 mod timer;
+mod serial;
+mod infrared;
+mod link;
+mod peripherals;
 mod cartridge;
+mod cheats;
+mod disasm;
+mod events;
+mod palette;
+mod rng;
+mod savestate;
+mod sgb;
+mod warnings;
+mod frame;
+mod accuracy;
+mod movie;
+mod screenshot;
+mod bindings;
+mod data_dir;
 mod emulator;
+mod frame_dump;
+mod gamepad;
+mod magnifier;
+mod music;
+mod pacing;
+mod pause_menu;
+mod play_log;
+mod recorder;
+mod video;
+mod renderer;
+mod rewind;
+mod shaders;
+#[cfg(feature = "lua")]
+mod script;
 
 use cartridge::Cartridge;
+#[cfg(not(target_arch = "wasm32"))]
+use cartridge::{ClockSource, EmulatedClock, FixedClock, WallClock};
 use emulator::Emulator;
+#[cfg(not(target_arch = "wasm32"))]
+use renderer::RenderOptions;
+#[cfg(not(target_arch = "wasm32"))]
+use pacing::SyncMode;
+#[cfg(not(target_arch = "wasm32"))]
+use gamepad::PadMapping;
+#[cfg(not(target_arch = "wasm32"))]
+use gbemulib::filters::{ColourLut, FilterChain};
+#[cfg(not(target_arch = "wasm32"))]
+use palette::{ColourCorrection, DmgPalette};
+#[cfg(not(target_arch = "wasm32"))]
+use ppu::LcdOffPolicy;
+#[cfg(not(target_arch = "wasm32"))]
+use apu::ResampleQuality;
+#[cfg(not(target_arch = "wasm32"))]
+use recorder::RecordFormat;
+use video::VideoFormat;
+#[cfg(not(target_arch = "wasm32"))]
+use data_dir::DataDir;
+#[cfg(not(target_arch = "wasm32"))]
+use link::tcp::LinkMode;
 use gbemulib::constants;
+#[cfg(not(target_arch = "wasm32"))]
+use std::rc::Rc;
 
 #[cfg(target_arch = "wasm32")]
 use gbemulib::{load_from_db, save_to_db, log};
 
 const ROM_PATH: &str = "roms/pokemoncrystal.gbc";
 const WITH_BOOTROM: bool = false;
+// when set, runs this ROM as a second core next to ROM_PATH in the same window, the two
+// connected by a link cable
+const SPLIT_SCREEN_ROM_PATH: Option<&str> = None;
+// logs every instruction in the Gameboy Doctor format when set
+const TRACE_PATH: Option<&str> = None;
+// keeps battery saves in this directory instead of saves/ when set (unless there's a data
+// directory, see --data-dir)
+const SAVE_DIR: Option<&str> = None;
+// binary PGM image shown to the Pocket Camera's sensor when set (otherwise it sees static)
+const CAMERA_IMAGE_PATH: Option<&str> = None;
 
 #[cfg(not(target_arch = "wasm32"))]
 fn main() -> Result<(), String> {
-    let cartridge = Cartridge::from_file(ROM_PATH, WITH_BOOTROM);
-    let mut emulator = Emulator::load_cartridge(cartridge)?;
-    emulator.run_for_duration(40e12 as u64);
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("verify") {
+        args.next();
+        return verify(args);
+    }
+    if args.peek().map(String::as_str) == Some("fix-header") {
+        args.next();
+        return fix_header(args);
+    }
+    if args.peek().map(String::as_str) == Some("disasm") {
+        args.next();
+        return export_disasm(args);
+    }
+
+    let options = parse_options(args)?;
+
+    let data_dir = match &options.data_dir {
+        // movies start from power on with no battery save
+        _ if options.movie.is_some() => DataDir::sandbox()?,
+        Some(DataDirOption::Sandbox) => DataDir::sandbox()?,
+        Some(DataDirOption::Path(root)) => DataDir::new(root)?,
+        None => DataDir::from_env()?,
+    };
+    if let Some(root) = data_dir.root() {
+        println!("Keeping saves and everything else written in {}", root);
+    }
+
+    if options.turbo.is_some() && options.movie.is_some() {
+        return Err(String::from("Movies run at the hardware's speed, so --turbo can't be given with them"));
+    }
+    if options.rewind && options.movie.is_some() {
+        return Err(String::from("Movies go back with re-recording, so --rewind can't be given with them"));
+    }
+    if options.rewind && matches!(options.link, Some(LinkOption::Host(_) | LinkOption::Connect(_))) {
+        return Err(String::from("The other Game Boy can't rewind along, so --rewind can't be given with a link cable"));
+    }
+
+    let rtc = match options.rtc {
+        Some(_) if options.movie.is_some() => return Err(String::from("Movies keep the cartridge's clock emulated, so --rtc can't be given with them")),
+        // the clock starts from zero without a battery save, so movies see the same times
+        None if options.movie.is_some() => RtcOption::Emulated(0),
+        Some(rtc) => rtc,
+        None => RtcOption::Wall,
+    };
+
+    let save_dir = data_dir.save_dir(SAVE_DIR);
+    let cartridge = load_cartridge(ROM_PATH, &save_dir, rtc)?;
+    let mut emulator = match SPLIT_SCREEN_ROM_PATH {
+        _ if options.music => Emulator::load_music_player(cartridge, options.sync, data_dir)?,
+        Some(path) => {
+            let second_cartridge = load_cartridge(path, &save_dir, rtc)?;
+            Emulator::load_split_screen(cartridge, second_cartridge, options.renderer, options.sync, data_dir)?
+        },
+        None => Emulator::load_cartridge(cartridge, options.renderer, options.sync, data_dir)?,
+    };
+    emulator.set_reload(move || load_cartridge(ROM_PATH, &save_dir, rtc));
+    // playing a movie back powers the core on again, so it comes before plugging things in
+    match &options.movie {
+        Some(MovieOption::Record(path)) => emulator.record_movie(path)?,
+        Some(MovieOption::Play(path)) => emulator.play_movie(path)?,
+        None => {},
+    }
+    if let Some(path) = TRACE_PATH {
+        emulator.trace_to_file(path)?;
+    }
+    if let Some(path) = CAMERA_IMAGE_PATH {
+        emulator.set_camera_image(path)?;
+    }
+    match &options.link {
+        Some(LinkOption::Host(addr)) => emulator.host_link(addr, options.link_mode)?,
+        Some(LinkOption::Connect(addr)) => emulator.connect_link(addr)?,
+        Some(LinkOption::Printer) => emulator.connect_printer(),
+        None => {},
+    }
+    if let Some(frame_count) = options.dump_frames {
+        emulator.dump_frames(frame_count)?;
+    }
+    if let Some(frame_count) = options.profile_frames {
+        emulator.profile_frames(frame_count)?;
+    }
+    if let Some((on, off)) = options.autofire {
+        emulator.set_turbo_rate(on, off);
+    }
+    if options.block_opposing {
+        emulator.set_block_opposing(true);
+    }
+    if let Some(mapping) = options.pad_mapping {
+        emulator.set_pad_mapping(mapping);
+    }
+    if let Some(multiplier) = options.turbo {
+        emulator.set_turbo(multiplier);
+    }
+    if options.rewind {
+        emulator.enable_rewind()?;
+    }
+    let mut filters = options.filters;
+    if let Some(lut) = options.lut {
+        // colours are graded before any filter scales frames up
+        filters.get_or_insert_with(FilterChain::new).insert(0, Box::new(lut));
+    }
+    if let Some(filters) = filters {
+        emulator.set_filters(filters);
+    }
+    if let Some(palette) = options.palette {
+        emulator.set_palette(palette);
+    }
+    if let Some(correction) = options.colour_correction {
+        emulator.set_colour_correction(correction);
+    }
+    if let Some(ghosting) = options.ghosting {
+        emulator.set_lcd_ghosting(ghosting);
+    }
+    for code in &options.cheats {
+        emulator.add_cheat(code)?;
+    }
+    #[cfg(feature = "lua")]
+    if let Some(path) = &options.script {
+        emulator.load_script(path)?;
+    }
+    if let Some(policy) = options.lcd_off {
+        emulator.set_lcd_off_policy(policy);
+    }
+    if let Some(m_cycles) = options.sram_wait {
+        emulator.set_sram_wait(m_cycles);
+    }
+    if let Some(quality) = options.resampler {
+        emulator.set_resample_quality(quality);
+    }
+    if options.raw_audio {
+        emulator.set_high_pass(false);
+    }
+    if let Some(format) = options.record {
+        emulator.start_recording(format)?;
+    }
+    if let Some(format) = options.video {
+        emulator.start_video(format, options.video_audio)?;
+    }
+    emulator.run();
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Loads the ROM at path with its battery saves kept in save_dir and its clock (if it has one)
+/// counting with a new clock source for rtc.
+#[cfg(not(target_arch = "wasm32"))]
+fn load_cartridge(path: &str, save_dir: &str, rtc: RtcOption) -> Result<Cartridge, String> {
+    let cartridge = Cartridge::from_file_in(path, WITH_BOOTROM, save_dir).map_err(|e| e.to_string())?;
+    Ok(match rtc {
+        RtcOption::Wall => cartridge,
+        RtcOption::Emulated(start) => cartridge.with_clock(Rc::new(EmulatedClock::new(start))),
+        RtcOption::Fixed(time) => cartridge.with_clock(Rc::new(FixedClock(time))),
+    })
+}
+
+/// `melon-gb verify <rom> <save>`: prints what the save holds and whether it loads for the ROM.
+#[cfg(not(target_arch = "wasm32"))]
+fn verify(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let (rom_path, save_path) = match (args.next(), args.next(), args.next()) {
+        (Some(rom_path), Some(save_path), None) => (rom_path, save_path),
+        _ => return Err(String::from("Usage: melon-gb verify <rom> <save>")),
+    };
+
+    let rom = std::fs::read(&rom_path).map_err(|e| format!("Error reading {}: {}", rom_path, e))?;
+    let save = std::fs::read(&save_path).map_err(|e| format!("Error reading {}: {}", save_path, e))?;
+    let check = cartridge::verify_save(&rom, &save).map_err(|e| e.to_string())?;
+
+    for line in &check.report {
+        println!("{}", line);
+    }
+    if !std::path::Path::new(&save_path).ends_with(&check.expected_location) {
+        println!("Note: this ROM's save is loaded from {}", check.expected_location);
+    }
+
+    if check.problems.is_empty() {
+        println!("OK: the save is compatible with this ROM");
+        Ok(())
+    } else {
+        for problem in &check.problems {
+            println!("Problem: {}", problem);
+        }
+        Err(String::from("The save won't load for this ROM"))
+    }
+}
+
+/// `melon-gb fix-header <rom> [output]`: writes a copy of the ROM with its header and global
+/// checksums fixed, to output or next to the ROM (e.g. game.gb -> game-fixed.gb).
+#[cfg(not(target_arch = "wasm32"))]
+fn fix_header(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let (rom_path, output_path) = match (args.next(), args.next(), args.next()) {
+        (Some(rom_path), output_path, None) => {
+            let output_path = output_path.unwrap_or_else(|| {
+                let path = std::path::Path::new(&rom_path);
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let file_name = match path.extension() {
+                    Some(extension) => format!("{}-fixed.{}", stem, extension.to_string_lossy()),
+                    None => format!("{}-fixed", stem),
+                };
+                path.with_file_name(file_name).to_string_lossy().into_owned()
+            });
+            (rom_path, output_path)
+        },
+        _ => return Err(String::from("Usage: melon-gb fix-header <rom> [output]")),
+    };
+
+    let mut rom = std::fs::read(&rom_path).map_err(|e| format!("Error reading {}: {}", rom_path, e))?;
+    for line in cartridge::fix_header(&mut rom).map_err(|e| e.to_string())? {
+        println!("{}", line);
+    }
+    std::fs::write(&output_path, &rom).map_err(|e| format!("Error writing {}: {}", output_path, e))?;
+    println!("Wrote {}", output_path);
+    Ok(())
+}
+
+/// `melon-gb disasm <rom> [--out file.asm] [--cdl file.cdl]`: writes an RGBDS disassembly of the
+/// whole ROM to file.asm (or prints it), telling code from data with the code/data log given
+/// or the one next to the ROM (e.g. game.gb -> game.cdl), if there's one.
+#[cfg(not(target_arch = "wasm32"))]
+fn export_disasm(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let usage = || String::from("Usage: melon-gb disasm <rom> [--out file.asm] [--cdl file.cdl]");
+    let (mut rom_path, mut out_path, mut cdl_path) = (None, None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out_path = Some(args.next().ok_or_else(usage)?),
+            "--cdl" => cdl_path = Some(args.next().ok_or_else(usage)?),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(usage()),
+        }
+    }
+    let rom_path = rom_path.ok_or_else(usage)?;
+    let cdl_path = cdl_path.or_else(|| {
+        let path = std::path::Path::new(&rom_path).with_extension("cdl");
+        path.exists().then(|| path.to_string_lossy().into_owned())
+    });
+
+    let rom = std::fs::read(&rom_path).map_err(|e| format!("Error reading {}: {}", rom_path, e))?;
+    let cdl = match &cdl_path {
+        Some(path) => Some(std::fs::read(path).map_err(|e| format!("Error reading {}: {}", path, e))?),
+        None => None,
+    };
+    let asm = disasm::export_rgbds(&rom, cdl.as_deref());
+
+    match out_path {
+        Some(path) => {
+            std::fs::write(&path, asm).map_err(|e| format!("Error writing {}: {}", path, e))?;
+            match cdl_path {
+                Some(cdl_path) => println!("Wrote {} (code told apart from data by {})", path, cdl_path),
+                None => println!("Wrote {} (no code/data log, so everything but the header is code)", path),
+            }
+        },
+        None => print!("{}", asm),
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct Options {
+    // how the window's drawn (see renderer::RenderOptions)
+    renderer: RenderOptions,
+    // what frames wait on once they're presented (see pacing::SyncMode)
+    sync: SyncMode,
+    // which controller buttons press which Game Boy buttons (see gamepad::PadMapping)
+    pad_mapping: Option<PadMapping>,
+    // frames turbo buttons stay pressed, then released (see joypad::Turbo)
+    autofire: Option<(u32, u32)>,
+    // lets go of opposing directions held together (see joypad::block_opposing)
+    block_opposing: bool,
+    // dumps this many frames from the start (see Emulator::dump_frames)
+    dump_frames: Option<usize>,
+    // profiles this many frames from the start (see Emulator::profile_frames)
+    profile_frames: Option<usize>,
+    link: Option<LinkOption>,
+    // how transfers wait on each other when hosting a link cable (see link::tcp::LinkMode)
+    link_mode: LinkMode,
+    // plays the game without a window (see Emulator::load_music_player)
+    music: bool,
+    // overclocks the CPU by this much (see Emulator::set_turbo)
+    turbo: Option<u32>,
+    // keeps snapshots to rewind through (see Emulator::enable_rewind)
+    rewind: bool,
+    // post-processing for drawn frames (see gbemulib::filters)
+    filters: Option<FilterChain>,
+    // a colour lookup table run before the filters (see gbemulib::filters::ColourLut)
+    lut: Option<ColourLut>,
+    // colours for DMG games (see palette::PRESETS)
+    palette: Option<DmgPalette>,
+    // how CGB colours are converted for display (see palette::ColourCorrection)
+    colour_correction: Option<ColourCorrection>,
+    // how much of the last frame lingers in the next (see Cpu::set_lcd_ghosting)
+    ghosting: Option<u8>,
+    // what's shown while the LCD is off (see ppu::LcdOffPolicy)
+    lcd_off: Option<LcdOffPolicy>,
+    // GameShark and Game Genie codes to start with (see cheats::CheatCode)
+    cheats: Vec<String>,
+    // a Lua script to run alongside the game (see script::Script)
+    #[cfg(feature = "lua")]
+    script: Option<String>,
+    // M-cycles cartridge RAM accesses wait for (see Emulator::set_sram_wait)
+    sram_wait: Option<u32>,
+    // how audio is resampled to the device's rate (see apu::ResampleQuality)
+    resampler: Option<ResampleQuality>,
+    // audio skips the hardware's high-pass filter (see Cpu::set_high_pass)
+    raw_audio: bool,
+    // records audio from the start (see Emulator::start_recording)
+    record: Option<RecordFormat>,
+    // records video from the start (see Emulator::start_video)
+    video: Option<VideoFormat>,
+    // records sound along with WebM and MP4 videos
+    video_audio: bool,
+    // where saves and everything else written go (see data_dir::DataDir)
+    data_dir: Option<DataDirOption>,
+    // an input movie to record or play back (see movie::Movie)
+    movie: Option<MovieOption>,
+    // where the cartridge's clock reads the time from (see cartridge::ClockSource)
+    rtc: Option<RtcOption>,
+}
+
+/// What the cartridge's clock counts: the host's time, emulated time from the given UNIX
+/// time on (so it runs with the game), or the given UNIX time, stopped.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy)]
+enum RtcOption {
+    Wall,
+    Emulated(u64),
+    Fixed(u64),
+}
+
+/// Whether a run records an input movie to a file or plays one back from it.
+#[cfg(not(target_arch = "wasm32"))]
+enum MovieOption {
+    Record(String),
+    Play(String),
+}
+
+/// Where a run keeps what it writes: a given directory or a new temporary one.
+#[cfg(not(target_arch = "wasm32"))]
+enum DataDirOption {
+    Path(String),
+    Sandbox,
+}
+
+/// What's on the other end of the link cable: another melon-gb (see link::tcp) or a printer.
+#[cfg(not(target_arch = "wasm32"))]
+enum LinkOption {
+    Host(String),
+    Connect(String),
+    Printer,
+}
+
+/// Reads `--renderer <software|gpu>` (defaulting to the GPU renderer), `--scale-filter <nearest|linear>`,
+/// `--integer-scale`, `--stretch`, `--fullscreen`, `--shader <none|lcd|dotmatrix|scanlines>`, `--pad-buttons <list>`, `--autofire <on>:<off>`, `--block-opposing`, `--sync <audio|vsync|timer>`
+/// (defaulting to timer), `--dump-frames <count>`,
+/// `--profile-frames <count>`, `--link-host <addr>` (with `--link-mode <lockstep|direct|speculative>`),
+/// `--link-connect <addr>` or `--printer`,
+/// `--music`, `--turbo <2|4>`, `--rewind`, `--filters <list>`, `--lut <file.cube>`, `--palette <preset|colours>`
+/// `--colour-correction <off|simple|balanced>`, `--ghosting <0-255>`, `--lcd-off <hold|blank|fade>`,
+/// `--cheat <code>` (any number of times), `--script <path>` (with the `lua` feature), `--sram-wait <m-cycles>`
+/// `--resampler <linear|band-limited>`, `--raw-audio`, `--record <wav|vgm>`, `--video <gif|webm|mp4>`
+/// (with `--video-audio`) and
+/// `--data-dir <dir>` or `--sandbox`, `--record-movie <path>` or `--play-movie <path>`, and
+/// `--rtc <wall|emulated|unix time>` from args.
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options { renderer: RenderOptions::default(), sync: SyncMode::Timer, pad_mapping: None, autofire: None, block_opposing: false, dump_frames: None, profile_frames: None, link: None, link_mode: LinkMode::Lockstep, music: false, turbo: None, rewind: false, filters: None, lut: None, palette: None, colour_correction: None, ghosting: None, lcd_off: None, cheats: Vec::new(), #[cfg(feature = "lua")] script: None, sram_wait: None, resampler: None, raw_audio: false, record: None, video: None, video_audio: false, data_dir: None, movie: None, rtc: None };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--renderer" => match args.next() {
+                Some(kind) => options.renderer.kind = kind.parse()?,
+                None => return Err(String::from("--renderer expects 'software' or 'gpu'")),
+            },
+            "--scale-filter" => match args.next() {
+                Some(filter) => options.renderer.filter = filter.parse()?,
+                None => return Err(String::from("--scale-filter expects 'nearest' or 'linear'")),
+            },
+            "--integer-scale" => options.renderer.integer_scale = true,
+            "--stretch" => options.renderer.stretch = true,
+            "--fullscreen" => options.renderer.fullscreen = true,
+            "--shader" => match args.next() {
+                Some(shader) => options.renderer.shader = Some(shader.parse()?),
+                None => return Err(String::from("--shader expects none, lcd, dotmatrix or scanlines")),
+            },
+            "--pad-buttons" => match args.next() {
+                Some(mapping) => options.pad_mapping = Some(mapping.parse()?),
+                None => return Err(String::from("--pad-buttons expects a list of <game boy button>=<controller button> (e.g. a=a,b=x)")),
+            },
+            "--autofire" => match args.next().as_deref().and_then(|rate| rate.split_once(':')) {
+                Some((on, off)) => match (on.parse(), off.parse()) {
+                    (Ok(on @ 1..), Ok(off @ 1..)) => options.autofire = Some((on, off)),
+                    _ => return Err(String::from("--autofire expects frames pressed and frames released, both at least 1 (e.g. 2:2)")),
+                },
+                None => return Err(String::from("--autofire expects frames pressed and frames released (e.g. 2:2)")),
+            },
+            "--block-opposing" => options.block_opposing = true,
+            "--sync" => match args.next() {
+                Some(sync) => options.sync = sync.parse()?,
+                None => return Err(String::from("--sync expects 'audio', 'vsync' or 'timer'")),
+            },
+            "--dump-frames" => match args.next().and_then(|count| count.parse().ok()) {
+                Some(count) if count > 0 => options.dump_frames = Some(count),
+                _ => return Err(String::from("--dump-frames expects a number of frames")),
+            },
+            "--profile-frames" => match args.next().and_then(|count| count.parse().ok()) {
+                Some(count) if count > 0 => options.profile_frames = Some(count),
+                _ => return Err(String::from("--profile-frames expects a number of frames")),
+            },
+            "--link-host" => match args.next() {
+                Some(addr) => options.link = Some(LinkOption::Host(addr)),
+                None => return Err(String::from("--link-host expects an address to listen on (e.g. 0.0.0.0:5738)")),
+            },
+            "--link-mode" => match args.next() {
+                Some(mode) => options.link_mode = mode.parse()?,
+                None => return Err(String::from("--link-mode expects lockstep, direct or speculative")),
+            },
+            "--link-connect" => match args.next() {
+                Some(addr) => options.link = Some(LinkOption::Connect(addr)),
+                None => return Err(String::from("--link-connect expects the host's address")),
+            },
+            "--printer" => options.link = Some(LinkOption::Printer),
+            "--music" => options.music = true,
+            "--turbo" => match args.next().and_then(|multiplier| multiplier.parse().ok()) {
+                Some(multiplier @ (2 | 4)) => options.turbo = Some(multiplier),
+                _ => return Err(String::from("--turbo expects 2 or 4")),
+            },
+            "--rewind" => options.rewind = true,
+            "--filters" => match args.next() {
+                Some(list) => options.filters = Some(list.parse()?),
+                None => return Err(String::from("--filters expects a comma-separated list of filters (e.g. scale2x,scanlines)")),
+            },
+            "--lut" => match args.next() {
+                Some(path) => {
+                    let text = std::fs::read_to_string(&path).map_err(|e| format!("Unable to read LUT {}: {}", path, e))?;
+                    options.lut = Some(ColourLut::from_cube(&text).map_err(|e| format!("{}: {}", path, e))?);
+                },
+                None => return Err(String::from("--lut expects the path of a .cube file")),
+            },
+            "--palette" => match args.next() {
+                Some(palette) => options.palette = Some(palette.parse()?),
+                None => return Err(String::from("--palette expects a preset (melon, pocket, light, green or grayscale) or RRGGBB colours")),
+            },
+            "--ghosting" => match args.next().and_then(|ghosting| ghosting.parse().ok()) {
+                Some(ghosting) => options.ghosting = Some(ghosting),
+                None => return Err(String::from("--ghosting expects how much of the last frame to mix in (0-255)")),
+            },
+            "--cheat" => match args.next() {
+                Some(code) => options.cheats.push(code),
+                None => return Err(String::from("--cheat expects a GameShark (e.g. 010238CD) or Game Genie (e.g. 00A-17B-C49) code")),
+            },
+            #[cfg(feature = "lua")]
+            "--script" => match args.next() {
+                Some(path) => options.script = Some(path),
+                None => return Err(String::from("--script expects the path of a Lua script")),
+            },
+            "--lcd-off" => match args.next() {
+                Some(policy) => options.lcd_off = Some(policy.parse()?),
+                None => return Err(String::from("--lcd-off expects hold, blank or fade")),
+            },
+            "--colour-correction" => match args.next() {
+                Some(correction) => options.colour_correction = Some(correction.parse()?),
+                None => return Err(String::from("--colour-correction expects off, simple or balanced")),
+            },
+            "--sram-wait" => match args.next().and_then(|m_cycles| m_cycles.parse().ok()) {
+                Some(m_cycles) => options.sram_wait = Some(m_cycles),
+                None => return Err(String::from("--sram-wait expects how many M-cycles cartridge RAM accesses wait for (0 for none)")),
+            },
+            "--resampler" => match args.next() {
+                Some(quality) => options.resampler = Some(quality.parse()?),
+                None => return Err(String::from("--resampler expects linear or band-limited")),
+            },
+            "--raw-audio" => options.raw_audio = true,
+            "--record" => match args.next() {
+                Some(format) => options.record = Some(format.parse()?),
+                None => return Err(String::from("--record expects wav or vgm")),
+            },
+            "--data-dir" => match args.next() {
+                Some(dir) => options.data_dir = Some(DataDirOption::Path(dir)),
+                None => return Err(String::from("--data-dir expects a directory to keep saves and everything else written in")),
+            },
+            "--sandbox" => options.data_dir = Some(DataDirOption::Sandbox),
+            "--video" => match args.next() {
+                Some(format) => options.video = Some(format.parse()?),
+                None => return Err(String::from("--video expects gif, webm or mp4")),
+            },
+            "--video-audio" => options.video_audio = true,
+            "--record-movie" => match args.next() {
+                Some(path) => options.movie = Some(MovieOption::Record(path)),
+                None => return Err(String::from("--record-movie expects the path to write the movie to")),
+            },
+            "--play-movie" => match args.next() {
+                Some(path) => options.movie = Some(MovieOption::Play(path)),
+                None => return Err(String::from("--play-movie expects the path of a movie")),
+            },
+            "--rtc" => options.rtc = Some(match args.next().as_deref() {
+                Some("wall") => RtcOption::Wall,
+                Some("emulated") => RtcOption::Emulated(WallClock.now()),
+                Some(time) => match time.parse() {
+                    Ok(time) => RtcOption::Fixed(time),
+                    Err(_) => return Err(format!("--rtc expects wall, emulated or a UNIX time to stop the clock at, not {}", time)),
+                },
+                None => return Err(String::from("--rtc expects wall, emulated or a UNIX time to stop the clock at")),
+            }),
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+
+    if options.renderer.shader.is_some() {
+        // the shader grades colours on the GPU as it draws frames, after any filters
+        options.renderer.lut = options.lut.take();
+    }
+    Ok(options)
+}
@@ -0,0 +1,248 @@
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
+use core::str::FromStr;
+
+use crate::accuracy::{check_accuracy_rev, ACCURACY_REV};
+use crate::cartridge::Cartridge;
+use crate::config::RNG_SEED;
+use crate::cpu::{Cpu, GBModel};
+use crate::events::CoreEvent;
+
+const MOVIE_VERSION: &str = "melon-gb 1";
+
+// the buttons in an input log line, e.g. "|U...S..A|", and their bits in the joypad status
+const LOG_KEY: &str = "#Up|Down|Left|Right|Start|Select|B|A|";
+const MNEMONICS: [(char, u8); 8] = [
+    ('U', 2), ('D', 3), ('L', 1), ('R', 0), ('S', 7), ('s', 6), ('B', 5), ('A', 4),
+];
+
+/// A tool-assisted input movie: the joypad status held for every frame from power on,
+/// written in the spirit of BizHawk's BK2 (a header of `Key Value` lines, then an input log).
+/// Frame n's input goes in at the n-th VBlank (counting from 0) and the game sees it from
+/// the VBlank after, so a movie plays back the same on any frontend that does the same
+/// (see replay()). It only covers the first controller, and starts from a cartridge with
+/// no battery save: there are no save states to anchor a movie on yet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub game_name: String,
+    /// CRC-32 of the ROM (see Cartridge::rom_hash).
+    pub game_hash: u32,
+    pub model: GBModel,
+    /// The seed the core's Rng started from (see Cpu::rng_seed); movies without one were
+    /// recorded with config::RNG_SEED.
+    pub seed: u64,
+    /// The core's ACCURACY_REV when it was recorded.
+    pub accuracy_rev: u32,
+    /// How many times recording went back to an earlier frame.
+    pub rerecords: u32,
+    // joypad status per frame, where a cleared bit is a held button
+    inputs: Vec<u8>,
+}
+
+impl Movie {
+    pub fn new(game_name: &str, game_hash: u32, model: GBModel, seed: u64) -> Self {
+        Movie {
+            game_name: String::from(game_name),
+            game_hash,
+            model,
+            seed,
+            accuracy_rev: ACCURACY_REV,
+            rerecords: 0,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Adds status as the input of the frame after the last one recorded.
+    pub fn record(&mut self, status: u8) {
+        self.inputs.push(status);
+    }
+
+    /// Throws away the input from frame on, to record it again.
+    pub fn rerecord(&mut self, frame: usize) {
+        self.inputs.truncate(frame);
+        self.rerecords += 1;
+    }
+
+    pub fn input(&self, frame: usize) -> Option<u8> {
+        self.inputs.get(frame).copied()
+    }
+
+    #[allow(dead_code)]
+    pub fn inputs(&self) -> &[u8] {
+        &self.inputs
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Returns why the movie wouldn't play back right on a core running the ROM with
+    /// game_hash on model, if it wouldn't.
+    pub fn check(&self, game_hash: u32, model: GBModel) -> Result<(), String> {
+        if game_hash != self.game_hash {
+            return Err(format!("Movie was recorded on another ROM ({}, CRC-32 {:08X})", self.game_name, self.game_hash));
+        }
+        if model != self.model {
+            return Err(format!("Movie was recorded on a {:?}, not a {:?}", self.model, model));
+        }
+        check_accuracy_rev(self.accuracy_rev).map_err(|mismatch| format!("{}", mismatch))
+    }
+
+    /// Powers on a core with cartridge on the movie's model and seed, lets setup configure it
+    /// (e.g. with the frontend's settings), then runs it until it's started frames VBlanks,
+    /// handing it the movie's inputs; returns it with the input it's left holding (for
+    /// frames - 1, if any).
+    pub fn replay(&self, cartridge: Cartridge, frames: usize, setup: impl FnOnce(&mut Cpu)) -> (Cpu, u8) {
+        let mut cpu = Cpu::with_seed(cartridge, self.model, self.seed);
+        setup(&mut cpu);
+
+        let mut status = 0xFF;
+        let mut frame = 0;
+        cpu.update_joypad(status);
+        while frame < frames {
+            cpu.step();
+            while let Some(event) = cpu.poll_event() {
+                if event == CoreEvent::VBlank && frame < frames {
+                    status = self.input(frame).unwrap_or(0xFF);
+                    frame += 1;
+                }
+            }
+            cpu.update_joypad(status);
+            // the frames and audio produced aren't shown
+            cpu.get_audio_output();
+            cpu.get_display_output();
+        }
+        (cpu, status)
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "MovieVersion {}", MOVIE_VERSION)?;
+        writeln!(f, "GameName {}", self.game_name)?;
+        writeln!(f, "GameHash {:08X}", self.game_hash)?;
+        writeln!(f, "Model {:?}", self.model)?;
+        writeln!(f, "Seed {:016X}", self.seed)?;
+        writeln!(f, "AccuracyRev {}", self.accuracy_rev)?;
+        writeln!(f, "RerecordCount {}", self.rerecords)?;
+        writeln!(f, "StartsFrom PowerOn")?;
+        writeln!(f, "[Input]")?;
+        writeln!(f, "LogKey:{}", LOG_KEY)?;
+        for &status in &self.inputs {
+            let buttons: String = MNEMONICS.iter()
+                .map(|&(mnemonic, bit)| if status & (1 << bit) == 0 { mnemonic } else { '.' })
+                .collect();
+            writeln!(f, "|{}|", buttons)?;
+        }
+        writeln!(f, "[/Input]")
+    }
+}
+
+impl FromStr for Movie {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut movie = Movie::new("", 0, GBModel::DMG, RNG_SEED);
+        let mut lines = s.lines().map(str::trim).enumerate().filter(|(_, line)| !line.is_empty());
+
+        for (i, line) in lines.by_ref() {
+            if line == "[Input]" {
+                break;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            let (key, value) = line.split_once(' ').map_or((line, ""), |(key, value)| (key, value.trim()));
+            match key {
+                "MovieVersion" if value == MOVIE_VERSION => {},
+                "MovieVersion" => return Err(error(format!("unsupported movie version {}", value))),
+                "GameName" => movie.game_name = String::from(value),
+                "GameHash" => movie.game_hash = u32::from_str_radix(value, 16).map_err(|_| error(format!("invalid CRC-32 {}", value)))?,
+                "Model" => movie.model = match value {
+                    "DMG" => GBModel::DMG,
+                    "CGB" => GBModel::CGB,
+                    "SGB" => GBModel::SGB,
+                    "AGB" => GBModel::AGB,
+                    _ => return Err(error(format!("unknown model {}", value))),
+                },
+                "Seed" => movie.seed = u64::from_str_radix(value, 16).map_err(|_| error(format!("invalid seed {}", value)))?,
+                "AccuracyRev" => movie.accuracy_rev = value.parse().map_err(|_| error(format!("invalid revision {}", value)))?,
+                "RerecordCount" => movie.rerecords = value.parse().map_err(|_| error(format!("invalid count {}", value)))?,
+                "StartsFrom" if value == "PowerOn" => {},
+                "StartsFrom" => return Err(error(format!("movies can only start from PowerOn, not {}", value))),
+                // other tools' keys don't matter here
+                _ => {},
+            }
+        }
+
+        for (i, line) in lines {
+            if line == "[/Input]" {
+                return Ok(movie);
+            }
+            if line.starts_with("LogKey:") {
+                continue;
+            }
+            let buttons: Vec<char> = match line.strip_prefix('|').and_then(|line| line.strip_suffix('|')) {
+                Some(buttons) if buttons.chars().count() == MNEMONICS.len() => buttons.chars().collect(),
+                _ => return Err(format!("line {}: expected an input like |U...S..A|, not {}", i + 1, line)),
+            };
+            let status = MNEMONICS.iter().zip(buttons)
+                .filter(|&(_, button)| button != '.')
+                .fold(0xFF, |status, (&(_, bit), _)| status & !(1 << bit));
+            movie.record(status);
+        }
+        Err(String::from("Movie has no [Input] ... [/Input] log"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use crate::cartridge::Cartridge;
+    use crate::cpu::test_helpers::make_test_rom;
+    use super::*;
+
+    #[test]
+    fn movie_format_test() {
+        let mut movie = Movie::new("TETRIS", 0x46DBCE03, GBModel::CGB, 0x1234);
+        movie.record(0xFF);
+        movie.record(!(1 << 2 | 1 << 7 | 1 << 4));
+        let text = movie.to_string();
+        assert!(text.contains("|U...S..A|"));
+        assert_eq!(text.parse::<Movie>(), Ok(movie.clone()));
+        assert!(text.contains("Seed 0000000000001234"));
+        let unseeded: String = text.lines().filter(|line| !line.starts_with("Seed")).map(|line| line.to_string() + "\n").collect();
+        assert_eq!(unseeded.parse::<Movie>().unwrap().seed, RNG_SEED);
+
+        movie.rerecord(1);
+        assert_eq!((movie.len(), movie.rerecords), (1, 1));
+
+        assert!(text.replace("PowerOn", "SaveState").parse::<Movie>().unwrap_err().contains("PowerOn"));
+        assert!(text.replace("|U...S..A|", "|U..S..A|").parse::<Movie>().is_err());
+        assert!(text.replace("[/Input]", "").parse::<Movie>().is_err());
+        assert!(movie.check(0x46DBCE03, GBModel::CGB).is_ok());
+        assert!(movie.check(0x46DBCE03, GBModel::DMG).is_err());
+        assert!(movie.check(0, GBModel::CGB).is_err());
+    }
+
+    #[test]
+    fn movie_replay_test() {
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut movie = Movie::new("", 0, GBModel::DMG, 0xBEEF);
+        for status in [0xFE, 0xFD, 0xFB, 0xF7] {
+            movie.record(status);
+        }
+
+        let (cpu, status) = movie.replay(Cartridge::from_bytes(&rom).unwrap(), 3, |_| {});
+        assert_eq!(status, 0xFB);
+        // the game sees each frame's input from the VBlank after it
+        assert_eq!(cpu.current_inputs(), 0xFD);
+        // on the core the movie was recorded on
+        assert_eq!((cpu.model(), cpu.rng_seed()), (GBModel::DMG, 0xBEEF));
+    }
+}
@@ -0,0 +1,104 @@
+use std::io::stdin;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+// how many frames an injected button press is held down for
+const PRESS_FRAMES: u32 = 6;
+
+// key_status bits (see emulator::KEYMAPPINGS)
+const START: u8 = 1 << 7;
+const B: u8 = 1 << 5;
+const A: u8 = 1 << 4;
+const LEFT: u8 = 1 << 1;
+const RIGHT: u8 = 1 << 0;
+
+const HELP: &str = "Music mode: type a command and press Enter\n  \
+    p: pause/resume, n: next track (RIGHT), b: previous track (LEFT),\n  \
+    a: press A, x: press B, s: press START, q: quit";
+
+enum Command {
+    TogglePause,
+    Press(u8),
+    Quit,
+}
+
+/// Terminal controls for music player mode (--music); commands are read from stdin a line at a time
+/// and turned into pausing or button presses, since there's no window to take keyboard input.
+pub struct MusicControls {
+    commands: Receiver<Command>,
+    paused: bool,
+    // the injected button's key_status bit, and how many more frames it stays pressed
+    pressed: Option<(u8, u32)>,
+}
+
+impl MusicControls {
+    pub fn new() -> Self {
+        let (tx, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdin().lines() {
+                let command = match line.as_deref().map(str::trim) {
+                    Ok("p") => Command::TogglePause,
+                    Ok("n") => Command::Press(RIGHT),
+                    Ok("b") => Command::Press(LEFT),
+                    Ok("a") => Command::Press(A),
+                    Ok("x") => Command::Press(B),
+                    Ok("s") => Command::Press(START),
+                    Ok("q") => Command::Quit,
+                    Ok(_) => {
+                        println!("{}", HELP);
+                        continue;
+                    },
+                    Err(_) => return,
+                };
+                if tx.send(command).is_err() {
+                    return;
+                }
+            }
+        });
+
+        println!("{}", HELP);
+        MusicControls { commands, paused: false, pressed: None }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Applies commands typed since the last call, and presses or releases the injected button
+    /// in key_status; meant to be called once per frame (and regularly while paused).
+    /// Returns false once the user quits.
+    pub fn step(&mut self, key_status: &mut u8) -> bool {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                Command::TogglePause => {
+                    self.paused = !self.paused;
+                    println!("{}", if self.paused { "Paused" } else { "Resumed" });
+                },
+                Command::Press(button) => {
+                    if let Some((held, _)) = self.pressed {
+                        *key_status |= held;
+                    }
+                    self.pressed = Some((button, PRESS_FRAMES));
+                },
+                Command::Quit => return false,
+            }
+        }
+
+        if self.paused {
+            return true;
+        }
+
+        self.pressed = match self.pressed {
+            Some((button, 0)) => {
+                *key_status |= button;
+                None
+            },
+            Some((button, frames)) => {
+                *key_status &= !button;
+                Some((button, frames - 1))
+            },
+            None => None,
+        };
+        true
+    }
+}
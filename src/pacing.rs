@@ -0,0 +1,122 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gbemulib::constants::T_CYCLE_HZ;
+
+use crate::config::{AUDIO_LATENCY_MS, RATE_CONTROL_DELTA};
+
+// T-cycles from one VBlank to the next
+pub const FRAME_T_CYCLES: u32 = 70224;
+// wall time a frame's shown for at the hardware's speed
+pub const FRAME_DURATION: Duration = Duration::from_nanos(FRAME_T_CYCLES as u64 * 1_000_000_000 / T_CYCLE_HZ as u64);
+
+// how far emulation can fall behind the wall clock (e.g. after a pause) before pacing starts
+// over from where it is instead of rushing to catch up
+pub const MAX_PACING_LAG: Duration = Duration::from_millis(100);
+
+// with SyncMode::Vsync, how far emulation can get ahead of the wall clock before it's held
+// back (in case the driver doesn't actually wait for vblank)
+pub const MAX_VSYNC_LEAD: Duration = Duration::from_millis(50);
+
+/// What a frame waits on once it's been emulated and presented, selected via --sync.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncMode {
+    /// Waits on the audio device to take the frame's audio, keeping its queue full; frames
+    /// are shown whenever the audio's done with them, so they can jitter.
+    Audio,
+    /// Waits for the display's vblank on present, so each frame is shown for exactly one
+    /// refresh; needs a display running close to the Game Boy's ~59.73 Hz.
+    Vsync,
+    /// Sleeps until the wall clock catches up with the frame.
+    Timer,
+}
+
+impl FromStr for SyncMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "audio" => Ok(SyncMode::Audio),
+            "vsync" => Ok(SyncMode::Vsync),
+            "timer" => Ok(SyncMode::Timer),
+            _ => Err(format!("Unknown sync mode '{}' (expected 'audio', 'vsync' or 'timer')", s)),
+        }
+    }
+}
+
+/// Returns whether a display refreshing refresh_hz times a second can pace emulation with
+/// SyncMode::Vsync: running at its speed has to be within what FramePacer::rate can make up for.
+pub fn can_vsync(refresh_hz: i32) -> bool {
+    let frame_hz = T_CYCLE_HZ as f64 / FRAME_T_CYCLES as f64;
+    (refresh_hz as f64 / frame_hz - 1.0).abs() <= RATE_CONTROL_DELTA
+}
+
+/// Paces the main core a frame at a time as its sync mode says and, unless it's SyncMode::Audio,
+/// nudges its sample rate so the audio queue stays near AUDIO_LATENCY_MS, as the clock pacing
+/// it and the audio device's clock never run at quite the same speed.
+pub struct FramePacer {
+    pub sync: SyncMode,
+    // the rate the audio device plays at
+    device_hz: u32,
+    // samples queued for the device (taken off by its callback as it plays them)
+    pub queued: Arc<AtomicUsize>,
+    // when emulated time was last lined up with the wall clock, and the emulated time then
+    synced_at: Option<(Instant, Duration)>,
+}
+
+impl FramePacer {
+    pub fn new(sync: SyncMode, device_hz: u32, queued: Arc<AtomicUsize>) -> Self {
+        FramePacer {
+            sync,
+            device_hz,
+            queued,
+            synced_at: None,
+        }
+    }
+
+    /// Returns the rate the audio device plays at.
+    pub fn device_hz(&self) -> u32 {
+        self.device_hz
+    }
+
+    /// Starts pacing over from the next frame, for when emulated time starts over (see Emulator::reset).
+    pub fn resync(&mut self) {
+        self.synced_at = None;
+    }
+
+    /// Returns the rate to produce audio at: up to RATE_CONTROL_DELTA above the device's while
+    /// the queue is shorter than AUDIO_LATENCY_MS, and as much below while it's longer.
+    pub fn rate(&self) -> u32 {
+        let target = self.device_hz as f64 * AUDIO_LATENCY_MS as f64 / 1000.0;
+        let fill = (self.queued.load(Ordering::Relaxed) as f64 / target).min(2.0);
+        (self.device_hz as f64 * (1.0 + RATE_CONTROL_DELTA * (1.0 - fill))).round() as u32
+    }
+
+    /// Waits out the rest of the frame that ended at emulated (time since power on), once it's
+    /// been presented.
+    pub fn end_frame(&mut self, emulated: Duration) {
+        match self.sync {
+            // sending the frame's audio already waited on the device
+            SyncMode::Audio => {},
+            // presenting already waited for vblank
+            SyncMode::Vsync => self.pace(emulated, MAX_VSYNC_LEAD),
+            SyncMode::Timer => self.pace(emulated, Duration::ZERO),
+        }
+    }
+
+    /// Sleeps until the wall clock is no more than lead behind emulated.
+    fn pace(&mut self, emulated: Duration, lead: Duration) {
+        let now = Instant::now();
+        let (synced_at, synced_emulated) = *self.synced_at.get_or_insert((now, emulated));
+        let due = synced_at + emulated.saturating_sub(synced_emulated);
+
+        if due > now + lead {
+            thread::sleep(due - lead - now);
+        } else if now.saturating_duration_since(due) > MAX_PACING_LAG {
+            self.synced_at = Some((now, emulated));
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use alloc::{format, string::String, vec::Vec};
+use core::cmp::min;
+use core::str::FromStr;
+
+use crate::config::DMG_PALETTE;
+use crate::constants::BYTES_PER_PIXEL;
+
+pub type Colour = [u8; BYTES_PER_PIXEL];
+
+/// (DMG ONLY) Colours used for the BG/Window and both object palettes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DmgPalette {
+    pub bg: [Colour; 4],
+    pub obj0: [Colour; 4],
+    pub obj1: [Colour; 4],
+}
+
+impl DmgPalette {
+    /// Uses the same four colours for every layer.
+    pub const fn uniform(colours: [Colour; 4]) -> Self {
+        DmgPalette {
+            bg: colours,
+            obj0: colours,
+            obj1: colours,
+        }
+    }
+
+    /// Returns the display colour of a DMG pixel produced by the PPU,
+    /// where bits 0-1 are the colour index and bits 2-3 are the layer (see PaletteLayer).
+    pub fn colour(&self, pixel: u16) -> Colour {
+        let index = (pixel & 0x03) as usize;
+        match pixel >> 2 {
+            0 => self.bg[index],
+            1 => self.obj0[index],
+            _ => self.obj1[index],
+        }
+    }
+}
+
+/// The palette in config::DMG_PALETTE (or the first preset, if it doesn't parse).
+impl Default for DmgPalette {
+    fn default() -> Self {
+        DMG_PALETTE.parse().unwrap_or(PRESETS[0].1)
+    }
+}
+
+/// Parses a preset's name (see PRESETS) or colours as RRGGBB hex: four comma-separated colours
+/// (lightest first) for every layer, or BG, OBJ0 and OBJ1 colours separated by slashes
+/// (e.g. `FFFFFF,A5A5A5,525252,000000/FFFFFF,FF8484,943A3A,000000/...`).
+impl FromStr for DmgPalette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, preset)) = PRESETS.iter().find(|(name, _)| *name == s) {
+            return Ok(*preset);
+        }
+
+        let layers = s.split('/').map(parse_colours).collect::<Result<Vec<_>, _>>()?;
+        match layers[..] {
+            [colours] => Ok(DmgPalette::uniform(colours)),
+            [bg, obj0, obj1] => Ok(DmgPalette { bg, obj0, obj1 }),
+            _ => Err(format!("Palette '{}' should have 1 or 3 layers of colours, separated by '/'", s)),
+        }
+    }
+}
+
+fn parse_colours(s: &str) -> Result<[Colour; 4], String> {
+    let colours = s.split(',')
+        .map(|hex| match u32::from_str_radix(hex.trim(), 16) {
+            Ok(colour) if hex.trim().len() == 6 => Ok(rgb(colour)),
+            _ => Err(format!("'{}' isn't a palette preset or an RRGGBB colour", hex)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    colours.try_into().map_err(|_| format!("'{}' should have 4 colours (lightest first)", s))
+}
+
+/// Which DMG palette register a pixel's colour came from.
+#[derive(Clone, Copy)]
+pub enum PaletteLayer {
+    Bg = 0,
+    Obj0 = 1,
+    Obj1 = 2,
+}
+
+/// Converts 0xRRGGBB into the frame buffer's byte order.
+const fn rgb(hex: u32) -> Colour {
+    [hex as u8, (hex >> 8) as u8, (hex >> 16) as u8, 0xFF]
+}
+
+const fn palette(colours: [u32; 4]) -> [Colour; 4] {
+    [rgb(colours[0]), rgb(colours[1]), rgb(colours[2]), rgb(colours[3])]
+}
+
+const BROWN: [Colour; 4] = palette([0xFFFFFF, 0xFFAD63, 0x843100, 0x000000]);
+const RED: [Colour; 4] = palette([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]);
+const GREEN: [Colour; 4] = palette([0xFFFFFF, 0x7BFF31, 0x008400, 0x000000]);
+const BLUE: [Colour; 4] = palette([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000]);
+
+/// Named palettes for DMG games (see config::DMG_PALETTE and Cpu::set_dmg_palette).
+pub const PRESETS: [(&str, DmgPalette); 5] = [
+    ("melon", DmgPalette::uniform(palette([0xFFFFE8, 0x9BD474, 0x309A80, 0x1A3D4F]))),
+    // Game Boy Pocket
+    ("pocket", DmgPalette::uniform(palette([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]))),
+    // Game Boy Light's backlight
+    ("light", DmgPalette::uniform(palette([0x00B581, 0x009A71, 0x00694A, 0x004F3B]))),
+    // the original DMG's screen
+    ("green", DmgPalette::uniform(palette([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]))),
+    ("grayscale", DmgPalette::uniform(palette([0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]))),
+];
+
+/// Palettes selectable by holding a direction (+ optionally A or B) while the CGB boot ROM runs.
+/// Entries are in the order of: UP, UP+A, UP+B, LEFT, LEFT+A, LEFT+B,
+/// DOWN, DOWN+A, DOWN+B, RIGHT, RIGHT+A, RIGHT+B.
+pub const COMBO_PALETTES: [DmgPalette; 12] = [
+    // Brown
+    DmgPalette::uniform(BROWN),
+    // Red
+    DmgPalette { bg: RED, obj0: GREEN, obj1: BLUE },
+    // Dark Brown
+    DmgPalette { bg: palette([0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108]), obj0: BROWN, obj1: BROWN },
+    // Blue
+    DmgPalette { bg: BLUE, obj0: RED, obj1: GREEN },
+    // Dark Blue
+    DmgPalette { bg: palette([0xFFFFFF, 0x8C8CDE, 0x52528C, 0x000000]), obj0: RED, obj1: BROWN },
+    // Grayscale
+    DmgPalette::uniform(palette([0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000])),
+    // Pastel Mix
+    DmgPalette::uniform(palette([0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000])),
+    // Orange
+    DmgPalette::uniform(palette([0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000])),
+    // Yellow
+    DmgPalette { bg: palette([0xFFFFFF, 0xFFFF00, 0x7B4A00, 0x000000]), obj0: BLUE, obj1: GREEN },
+    // Green
+    DmgPalette::uniform(palette([0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000])),
+    // Dark Green
+    DmgPalette { bg: palette([0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000]), obj0: RED, obj1: RED },
+    // Inverted
+    DmgPalette::uniform(palette([0x000000, 0x008484, 0xFFDE00, 0xFFFFFF])),
+];
+
+/// Palette the CGB boot ROM gives DMG games it has no title palette for (Dark Green).
+pub const CGB_DEFAULT_PALETTE: DmgPalette = COMBO_PALETTES[9];
+
+/// Compatibility palettes the CGB boot ROM gives Nintendo's DMG games, by title checksum
+/// (the sum of the title's 16 bytes) and, for checksums shared by several titles, the title's
+/// fourth letter. NOTE: only a few well-known games are in here so far (the boot ROM knows
+/// about 90), and their colours are approximated with the combo palettes' colours.
+const TITLE_PALETTES: [(u8, Option<u8>, DmgPalette); 4] = [
+    // POKEMON RED
+    (0x14, None, DmgPalette { bg: RED, obj0: RED, obj1: GREEN }),
+    // POKEMON GREEN
+    (0xAA, None, DmgPalette { bg: GREEN, obj0: RED, obj1: GREEN }),
+    // POKEMON BLUE
+    (0x61, Some(b'E'), DmgPalette { bg: BLUE, obj0: RED, obj1: BLUE }),
+    // SUPER MARIOLAND
+    (0x46, Some(b'E'), DmgPalette { bg: RED, obj0: RED, obj1: BLUE }),
+];
+
+/// Returns the palette the CGB boot ROM picks for a DMG game with the given 16 title bytes.
+pub fn title_palette(title: &[u8]) -> Option<DmgPalette> {
+    let checksum = title.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    TITLE_PALETTES.iter()
+        .find(|(entry, letter, _)| *entry == checksum && letter.is_none_or(|letter| title.get(3) == Some(&letter)))
+        .map(|(_, _, palette)| *palette)
+}
+
+/// Returns the index into COMBO_PALETTES for the buttons held in status (if they form a combo).
+/// status is in order of: START (msb), SELECT, B, A, DOWN, UP, LEFT, RIGHT (lsb) where 0 = pressed.
+pub fn combo_palette_index(status: u8) -> Option<usize> {
+    let pressed = !status;
+
+    let direction = match pressed & 0x0F {
+        0x04 => 0,
+        0x02 => 1,
+        0x08 => 2,
+        0x01 => 3,
+        _ => return None,
+    };
+
+    let button = if pressed & 0x10 != 0 {
+        1
+    } else if pressed & 0x20 != 0 {
+        2
+    } else {
+        0
+    };
+
+    Some(direction * 3 + button)
+}
+
+/// (CGB ONLY) How RGB555 colours from CRAM are turned into display colours (see
+/// config::COLOUR_CORRECTION and Cpu::set_colour_correction).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColourCorrection {
+    /// Raw colours, scaled up to 8 bits per channel (vivid, but far from the real LCD).
+    Off,
+    /// Cheap channel mixing (https://saveweb.github.io/near.sh/articles/video/color-emulation.html).
+    Simple,
+    /// Gamma curve plus green/blue mixing that keeps brightness and contrast,
+    /// in the spirit of SameBoy's "Modern - Balanced".
+    Balanced,
+}
+
+// 5-bit channel to 8 bits along the LCD's gamma curve (from SameBoy)
+const CHANNEL_CURVE: [u8; 32] = [
+    0, 6, 12, 20, 28, 36, 45, 56, 66, 76, 88, 100, 113, 125, 137, 149,
+    161, 172, 182, 192, 202, 210, 218, 225, 232, 238, 243, 247, 250, 252, 254, 255,
+];
+
+impl ColourCorrection {
+    /// Returns the display colour of an RGB555 colour.
+    pub fn colour(self, rgb555: u16) -> Colour {
+        let r5 = (rgb555 & 0x1F) as u32;
+        let g5 = ((rgb555 >> 5) & 0x1F) as u32;
+        let b5 = ((rgb555 >> 10) & 0x1F) as u32;
+
+        let (red, green, blue) = match self {
+            ColourCorrection::Off => ((r5 * 255 + 15) / 31, (g5 * 255 + 15) / 31, (b5 * 255 + 15) / 31),
+            ColourCorrection::Simple => (
+                min(960, r5 * 26 + g5 * 4 + b5 * 2) >> 2,
+                min(960, g5 * 24 + b5 * 8) >> 2,
+                min(960, r5 * 6 + g5 * 4 + b5 * 22) >> 2,
+            ),
+            ColourCorrection::Balanced => {
+                let [r, g, b] = [r5, g5, b5].map(|channel| CHANNEL_CURVE[channel as usize] as u32);
+
+                // the LCD's green subpixels let some blue through
+                let linear = |channel: u32| libm::pow(channel as f64 / 255.0, 2.2);
+                let mixed_g = if g != b {
+                    libm::pow((linear(g) * 3.0 + linear(b)) / 4.0, 1.0 / 2.2) * 255.0
+                } else {
+                    g as f64
+                };
+                let (mut new_r, mut new_g, mut new_b) = (r, libm::round(mixed_g) as u32, b);
+
+                // mixing mustn't change how bright or washed out the colour is
+                let (old_max, new_max) = (r.max(g).max(b), new_r.max(new_g).max(new_b));
+                if new_max != 0 {
+                    [new_r, new_g, new_b] = [new_r, new_g, new_b].map(|channel| channel * old_max / new_max);
+                }
+                let (old_min, new_min) = (r.min(g).min(b), new_r.min(new_g).min(new_b));
+                if new_min != 0xFF {
+                    [new_r, new_g, new_b] = [new_r, new_g, new_b]
+                        .map(|channel| 0xFF - (0xFF - channel) * (0xFF - old_min) / (0xFF - new_min));
+                }
+                (new_r, new_g, new_b)
+            },
+        };
+
+        [blue as u8, green as u8, red as u8, 0xFF]
+    }
+
+    /// Returns the display colour of every RGB555 colour, indexed by the colour.
+    pub fn table(self) -> Vec<Colour> {
+        (0..0x8000).map(|rgb555| self.colour(rgb555)).collect()
+    }
+}
+
+/// Parses `off`, `simple` or `balanced`.
+impl FromStr for ColourCorrection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ColourCorrection::Off),
+            "simple" => Ok(ColourCorrection::Simple),
+            "balanced" => Ok(ColourCorrection::Balanced),
+            _ => Err(format!("Unknown colour correction '{}' (expected off, simple or balanced)", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combo_palette_index, palette, rgb, title_palette, ColourCorrection, DmgPalette, BLUE, GREEN, PRESETS, RED};
+
+    #[test]
+    fn combo_palette_index_test() {
+        assert_eq!(combo_palette_index(0xFF), None);
+        assert_eq!(combo_palette_index(!0x04), Some(0));
+        assert_eq!(combo_palette_index(!0x14), Some(1));
+        assert_eq!(combo_palette_index(!0x22), Some(5));
+        assert_eq!(combo_palette_index(!0x21), Some(11));
+        assert_eq!(combo_palette_index(!0x06), None);
+    }
+
+    #[test]
+    fn title_palette_test() {
+        let title = |name: &str| {
+            let mut title = [0; 16];
+            title[..name.len()].copy_from_slice(name.as_bytes());
+            title
+        };
+
+        assert_eq!(title_palette(&title("SUPER MARIOLAND")).unwrap().bg, RED);
+        assert_eq!(title_palette(&title("POKEMON BLUE")).unwrap().bg, BLUE);
+        // same checksum as POKEMON BLUE, different fourth letter
+        assert_eq!(title_palette(&title("POKFMON BLUD")), None);
+        assert_eq!(title_palette(&title("NOT A GAME")), None);
+    }
+
+    #[test]
+    fn palette_parse_test() {
+        assert_eq!("pocket".parse::<DmgPalette>(), Ok(PRESETS[1].1));
+        assert_eq!("FFFFFF,AAAAAA,555555,000000".parse::<DmgPalette>(), Ok(PRESETS[4].1));
+        assert_eq!(
+            "FFFFFF,FF8484,943A3A,000000/FFFFFF,7BFF31,008400,000000/FFFFFF,63A5FF,0000FF,000000".parse(),
+            Ok(DmgPalette { bg: RED, obj0: GREEN, obj1: BLUE }),
+        );
+        assert_eq!(DmgPalette::default().bg, palette([0xFFFFE8, 0x9BD474, 0x309A80, 0x1A3D4F]));
+
+        assert!("sepia".parse::<DmgPalette>().is_err());
+        assert!("FFFFFF,AAAAAA,555555".parse::<DmgPalette>().is_err());
+        assert!("FFFFFF,AAAAAA,555555,000000/FFFFFF,AAAAAA,555555,000000".parse::<DmgPalette>().is_err());
+    }
+
+    #[test]
+    fn colour_correction_test() {
+        for correction in [ColourCorrection::Off, ColourCorrection::Simple, ColourCorrection::Balanced] {
+            assert_eq!(correction.colour(0x0000), [0x00, 0x00, 0x00, 0xFF]);
+        }
+        assert_eq!(ColourCorrection::Off.colour(0x7FFF), [0xFF; 4]);
+        assert_eq!(ColourCorrection::Balanced.colour(0x7FFF), [0xFF; 4]);
+        assert_eq!(ColourCorrection::Off.colour(0x001F), rgb(0xFF0000));
+
+        // pure red keeps its brightness, and pure blue gets some green from mixing
+        let red = ColourCorrection::Balanced.colour(0x001F);
+        assert_eq!(red, rgb(0xFF0000));
+        let blue = ColourCorrection::Balanced.colour(0x7C00);
+        assert_eq!((blue[0], blue[2]), (0xFF, 0x00));
+        assert!(blue[1] > 0);
+
+        assert_eq!("balanced".parse(), Ok(ColourCorrection::Balanced));
+        assert!("vivid".parse::<ColourCorrection>().is_err());
+    }
+
+    #[test]
+    fn rgb_byte_order_test() {
+        assert_eq!(rgb(0x123456), [0x56, 0x34, 0x12, 0xFF]);
+    }
+}
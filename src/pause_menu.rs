@@ -0,0 +1,164 @@
+use gbemulib::constants::BYTES_PER_PIXEL;
+
+// glyphs are drawn this many pixels per font pixel, with a font pixel between letters
+const TEXT_SCALE: usize = 2;
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const LINE_HEIGHT: usize = (GLYPH_HEIGHT + 3) * TEXT_SCALE;
+
+const TITLE: &str = "PAUSED";
+const SELECTED_COLOUR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const ITEM_COLOUR: [u8; 4] = [0x90, 0x90, 0x90, 0xFF];
+
+// 3x5 capital letters A-Z, one row of 3 bits per byte (the leftmost pixel is the highest bit)
+const LETTERS: [[u8; GLYPH_HEIGHT]; 26] = [
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b110, 0b100, 0b111],
+    [0b111, 0b100, 0b110, 0b100, 0b100],
+    [0b011, 0b100, 0b101, 0b101, 0b011],
+    [0b101, 0b101, 0b111, 0b101, 0b101],
+    [0b111, 0b010, 0b010, 0b010, 0b111],
+    [0b001, 0b001, 0b001, 0b101, 0b010],
+    [0b101, 0b101, 0b110, 0b101, 0b101],
+    [0b100, 0b100, 0b100, 0b100, 0b111],
+    [0b101, 0b111, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b101, 0b101, 0b101],
+    [0b010, 0b101, 0b101, 0b101, 0b010],
+    [0b110, 0b101, 0b110, 0b100, 0b100],
+    [0b010, 0b101, 0b101, 0b110, 0b011],
+    [0b110, 0b101, 0b110, 0b101, 0b101],
+    [0b011, 0b100, 0b010, 0b001, 0b110],
+    [0b111, 0b010, 0b010, 0b010, 0b010],
+    [0b101, 0b101, 0b101, 0b101, 0b111],
+    [0b101, 0b101, 0b101, 0b101, 0b010],
+    [0b101, 0b101, 0b111, 0b111, 0b101],
+    [0b101, 0b101, 0b010, 0b101, 0b101],
+    [0b101, 0b101, 0b010, 0b010, 0b010],
+    [0b111, 0b001, 0b010, 0b100, 0b111],
+];
+// points at the selected item
+const MARKER: [u8; GLYPH_HEIGHT] = [0b100, 0b010, 0b001, 0b010, 0b100];
+
+/// What can be picked in the pause menu.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuItem {
+    Resume,
+    /// Powers the game off and on again.
+    Reset,
+    /// Switches DMG games to the next palette.
+    Palette,
+    /// Moves the cartridge's clock an hour forward (only for games with one).
+    ClockAhead,
+    /// Moves the cartridge's clock an hour back (only for games with one).
+    ClockBack,
+    /// Saves and exits.
+    Quit,
+}
+
+const MENU_ITEMS: [MenuItem; 6] = [
+    MenuItem::Resume, MenuItem::Reset, MenuItem::Palette, MenuItem::ClockAhead, MenuItem::ClockBack, MenuItem::Quit,
+];
+
+impl MenuItem {
+    fn label(&self) -> &'static str {
+        match self {
+            MenuItem::Resume => "RESUME",
+            MenuItem::Reset => "RESET",
+            MenuItem::Palette => "PALETTE",
+            MenuItem::ClockAhead => "CLOCK AHEAD",
+            MenuItem::ClockBack => "CLOCK BACK",
+            MenuItem::Quit => "QUIT",
+        }
+    }
+}
+
+/// A menu drawn over a frozen frame while the game is paused, moved through with the
+/// joypad's keys so it works wherever the game's controls do.
+pub struct PauseMenu {
+    frame: Vec<u8>,
+    size: (usize, usize),
+    items: Vec<MenuItem>,
+    selected: usize,
+}
+
+impl PauseMenu {
+    /// Opens the menu over frame (in the PPU's frame buffer order, of the given width and
+    /// height), with Resume selected; the clock items are only there if has_clock.
+    pub fn open(frame: &[u8], size: (usize, usize), has_clock: bool) -> Self {
+        let items = MENU_ITEMS.iter().copied()
+            .filter(|item| has_clock || !matches!(item, MenuItem::ClockAhead | MenuItem::ClockBack))
+            .collect();
+        PauseMenu { frame: frame.to_vec(), size, items, selected: 0 }
+    }
+
+    /// Moves the selection by steps items (down for positive), wrapping around.
+    pub fn move_selection(&mut self, steps: i32) {
+        let len = self.items.len() as i32;
+        self.selected = (self.selected as i32 + steps).rem_euclid(len) as usize;
+    }
+
+    pub fn selected(&self) -> MenuItem {
+        self.items[self.selected]
+    }
+
+    /// Returns the frozen frame darkened with the menu over it, and its width and height.
+    pub fn view(&self) -> (Vec<u8>, (usize, usize)) {
+        let mut view: Vec<u8> = self.frame.chunks_exact(BYTES_PER_PIXEL)
+            .flat_map(|pixel| [pixel[0] / 3, pixel[1] / 3, pixel[2] / 3, pixel[3]])
+            .collect();
+
+        let (_, height) = self.size;
+        let lines = self.items.len() + 2;
+        let top = height.saturating_sub(lines * LINE_HEIGHT) / 2;
+        self.draw_text(&mut view, TITLE, top, SELECTED_COLOUR);
+
+        for (i, item) in self.items.iter().enumerate() {
+            let y = top + (i + 2) * LINE_HEIGHT;
+            let colour = if i == self.selected { SELECTED_COLOUR } else { ITEM_COLOUR };
+            let left = self.draw_text(&mut view, item.label(), y, colour);
+            if i == self.selected {
+                let marker_x = left.saturating_sub((GLYPH_WIDTH + 2) * TEXT_SCALE);
+                self.draw_glyph(&mut view, &MARKER, (marker_x, y), colour);
+            }
+        }
+        (view, self.size)
+    }
+
+    /// Draws text centred across the frame with its top at y; returns where it starts.
+    fn draw_text(&self, view: &mut [u8], text: &str, y: usize, colour: [u8; 4]) -> usize {
+        let advance = (GLYPH_WIDTH + 1) * TEXT_SCALE;
+        let width = text.len() * advance - TEXT_SCALE;
+        let left = self.size.0.saturating_sub(width) / 2;
+
+        for (i, letter) in text.bytes().enumerate() {
+            if letter.is_ascii_uppercase() {
+                let glyph = &LETTERS[(letter - b'A') as usize];
+                self.draw_glyph(view, glyph, (left + i * advance, y), colour);
+            }
+        }
+        left
+    }
+
+    fn draw_glyph(&self, view: &mut [u8], glyph: &[u8; GLYPH_HEIGHT], (x, y): (usize, usize), colour: [u8; 4]) {
+        let (width, height) = self.size;
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..TEXT_SCALE {
+                    for dx in 0..TEXT_SCALE {
+                        let (px, py) = (x + column * TEXT_SCALE + dx, y + row * TEXT_SCALE + dy);
+                        if px < width && py < height {
+                            let i = (py * width + px) * BYTES_PER_PIXEL;
+                            view[i..i + BYTES_PER_PIXEL].copy_from_slice(&colour);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
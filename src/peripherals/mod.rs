@@ -0,0 +1,3 @@
+// accessories plugged into the serial port in place of another Game Boy (see serial::SerialLink)
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod printer;
@@ -0,0 +1,331 @@
+use std::fs::{create_dir_all, File};
+use std::io::BufWriter;
+use std::time::SystemTime;
+
+use crate::events::CoreEvent;
+use crate::serial::SerialLink;
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+// answered in place of the first byte after a packet's checksum
+const ALIVE: u8 = 0x81;
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const BREAK: u8 = 0x08;
+const STATUS: u8 = 0x0F;
+
+// status bits
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const UNPROCESSED_DATA: u8 = 0x08;
+
+// games wait for the printing bit to clear, so it's kept set for this many status reads
+const PRINTING_POLLS: u8 = 4;
+
+// prints are always 20 tiles wide
+const TILES_PER_ROW: usize = 20;
+const TILE_BYTES: usize = 16;
+const PRINT_WIDTH: usize = TILES_PER_ROW * 8;
+const DEFAULT_PALETTE: u8 = 0xE4;
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Magic(usize),
+    Command,
+    Compression,
+    Length(usize),
+    Data,
+    Checksum(usize),
+    Alive,
+    Status,
+}
+
+/// Game Boy Printer: receives packets over the link cable and saves every print to a
+/// grayscale PNG in its output directory. Prints come out immediately (apart from the
+/// printing status games wait on); margins and exposure are ignored.
+pub struct Printer {
+    out_dir: String,
+    stage: Stage,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    // decompressed tile data received since the last print
+    image: Vec<u8>,
+    status: u8,
+    printing_polls: u8,
+    // prints saved since the last poll_event()
+    pages_ready: u32,
+}
+
+impl Printer {
+    /// Creates a printer saving its prints into out_dir.
+    pub fn new(out_dir: &str) -> Self {
+        Printer {
+            out_dir: out_dir.to_string(),
+            stage: Stage::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            image: Vec::new(),
+            status: 0,
+            printing_polls: 0,
+            pages_ready: 0,
+        }
+    }
+
+    /// Takes in the next byte sent by the Game Boy and returns the printer's reply.
+    fn exchange(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+        self.stage = match self.stage {
+            Stage::Magic(i) if byte != MAGIC[i] => Stage::Magic(0),
+            Stage::Magic(0) => Stage::Magic(1),
+            Stage::Magic(_) => Stage::Command,
+            Stage::Command => {
+                self.command = byte;
+                Stage::Compression
+            },
+            Stage::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                Stage::Length(0)
+            },
+            Stage::Length(0) => {
+                self.length = byte as usize;
+                Stage::Length(1)
+            },
+            Stage::Length(_) => {
+                self.length |= (byte as usize) << 8;
+                self.data.clear();
+                if self.length == 0 { Stage::Checksum(0) } else { Stage::Data }
+            },
+            Stage::Data => {
+                self.data.push(byte);
+                if self.data.len() == self.length { Stage::Checksum(0) } else { Stage::Data }
+            },
+            Stage::Checksum(0) => {
+                self.checksum = byte as u16;
+                Stage::Checksum(1)
+            },
+            Stage::Checksum(_) => {
+                self.checksum |= (byte as u16) << 8;
+                self.process_packet();
+                Stage::Alive
+            },
+            Stage::Alive => {
+                reply = ALIVE;
+                Stage::Status
+            },
+            Stage::Status => {
+                reply = self.status;
+                if self.printing_polls > 0 {
+                    self.printing_polls -= 1;
+                    if self.printing_polls == 0 {
+                        self.status &= !PRINTING;
+                    }
+                }
+                Stage::Magic(0)
+            },
+        };
+        reply
+    }
+
+    fn process_packet(&mut self) {
+        let sum = [self.command, self.compressed as u8, self.length as u8, (self.length >> 8) as u8].iter()
+            .chain(&self.data)
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        if sum != self.checksum {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+
+        match self.command {
+            INIT | BREAK => {
+                self.image.clear();
+                self.status = 0;
+                self.printing_polls = 0;
+            },
+            DATA => {
+                let data = std::mem::take(&mut self.data);
+                if self.compressed {
+                    decompress(&data, &mut self.image);
+                } else {
+                    self.image.extend_from_slice(&data);
+                }
+                if !self.image.is_empty() {
+                    self.status |= UNPROCESSED_DATA;
+                }
+            },
+            PRINT if self.data.len() >= 3 => {
+                let palette = match self.data[2] {
+                    0 => DEFAULT_PALETTE,
+                    palette => palette,
+                };
+                let pixels = render(&self.image, palette);
+                match self.save(&pixels) {
+                    Ok(()) => self.pages_ready += 1,
+                    Err(e) => eprintln!("Unable to save print: {}", e),
+                }
+
+                self.image.clear();
+                self.status = (self.status & !UNPROCESSED_DATA) | PRINTING;
+                self.printing_polls = PRINTING_POLLS;
+            },
+            STATUS | PRINT => {},
+            command => eprintln!("Unknown printer command: {:02X}", command),
+        }
+    }
+
+    /// Writes pixels (rendered with render()) as a PNG named after the current time.
+    fn save(&self, pixels: &[u8]) -> Result<(), String> {
+        if pixels.is_empty() {
+            return Ok(());
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        let path = format!("{}/print-{}.png", self.out_dir, timestamp);
+        create_dir_all(&self.out_dir).map_err(|e| e.to_string())?;
+
+        let file = File::create(&path).map_err(|e| e.to_string())?;
+        let height = pixels.len() / PRINT_WIDTH;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), PRINT_WIDTH as u32, height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(pixels))
+            .map_err(|e| e.to_string())?;
+
+        println!("Printed to {}", path);
+        Ok(())
+    }
+}
+
+// the printer is always ready for the Game Boy's transfers and never clocks any itself
+impl SerialLink for Printer {
+    fn clock_transfer(&mut self, byte: u8) -> Option<u8> {
+        Some(self.exchange(byte))
+    }
+
+    fn set_waiting(&mut self, _byte: Option<u8>) {}
+
+    fn receive(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn poll_event(&mut self) -> Option<CoreEvent> {
+        self.pages_ready = self.pages_ready.checked_sub(1)?;
+        Some(CoreEvent::PrinterPageReady)
+    }
+}
+
+/// Appends the printer's run-length encoding of data to out: a control byte with bit 7 set
+/// repeats the next byte (control & 0x7F) + 2 times, otherwise the next control + 1 bytes are copied.
+fn decompress(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        if control & 0x80 != 0 {
+            if let Some(&byte) = data.get(i + 1) {
+                out.extend(std::iter::repeat_n(byte, (control & 0x7F) as usize + 2));
+            }
+            i += 2;
+        } else {
+            let end = (i + 2 + control as usize).min(data.len());
+            out.extend_from_slice(&data[i + 1..end]);
+            i = end;
+        }
+    }
+}
+
+/// Turns tile data (rows of TILES_PER_ROW tiles) into grayscale pixels, PRINT_WIDTH per row.
+fn render(image: &[u8], palette: u8) -> Vec<u8> {
+    let tile_rows = image.len() / (TILES_PER_ROW * TILE_BYTES);
+    let mut pixels = vec![0; tile_rows * 8 * PRINT_WIDTH];
+
+    for (tile, bytes) in image.chunks_exact(TILE_BYTES).take(tile_rows * TILES_PER_ROW).enumerate() {
+        let (tile_x, tile_y) = (tile % TILES_PER_ROW * 8, tile / TILES_PER_ROW * 8);
+        for row in 0..8 {
+            let (lo, hi) = (bytes[row * 2], bytes[row * 2 + 1]);
+            for x in 0..8 {
+                let color = ((hi >> (7 - x)) & 1) << 1 | ((lo >> (7 - x)) & 1);
+                let shade = (palette >> (2 * color)) & 0b11;
+                pixels[(tile_y + row) * PRINT_WIDTH + tile_x + x] = SHADES[shade as usize];
+            }
+        }
+    }
+
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress, render, Printer, ALIVE, PRINTING, PRINT_WIDTH, UNPROCESSED_DATA};
+    use crate::events::CoreEvent;
+    use crate::serial::SerialLink;
+
+    /// Sends a packet and returns the printer's status reply.
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> u8 {
+        let mut packet = vec![0x88, 0x33, command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+        packet.extend_from_slice(data);
+        let checksum = packet[2..].iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        packet.extend_from_slice(&[checksum as u8, (checksum >> 8) as u8]);
+
+        for byte in packet {
+            assert_eq!(printer.exchange(byte), 0x00);
+        }
+        assert_eq!(printer.exchange(0x00), ALIVE);
+        printer.exchange(0x00)
+    }
+
+    #[test]
+    fn printer_test() {
+        let dir = std::env::temp_dir().join("melon-gb-printer-test");
+        let mut printer = Printer::new(dir.to_str().unwrap());
+
+        assert_eq!(send_packet(&mut printer, 0x01, false, &[]), 0x00);
+        // one row of 20 tiles: all color 3 (compressed), then an empty packet ending the data
+        assert_eq!(send_packet(&mut printer, 0x04, true, &[0x80 | 0x7F, 0xFF, 0x80 | 0x7F, 0xFF, 0x80 | 0x3C, 0xFF]), UNPROCESSED_DATA);
+        assert_eq!(printer.image.len(), 20 * 16);
+        assert_eq!(send_packet(&mut printer, 0x04, false, &[]), UNPROCESSED_DATA);
+
+        assert_eq!(printer.poll_event(), None);
+        assert_eq!(send_packet(&mut printer, 0x02, false, &[0x01, 0x13, 0xE4, 0x40]), PRINTING);
+        assert!(printer.image.is_empty());
+        assert_eq!(printer.poll_event(), Some(CoreEvent::PrinterPageReady));
+        assert_eq!(printer.poll_event(), None);
+        for _ in 0..4 {
+            send_packet(&mut printer, 0x0F, false, &[]);
+        }
+        assert_eq!(send_packet(&mut printer, 0x0F, false, &[]), 0x00);
+
+        // bad checksum
+        for byte in [0x88, 0x33, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00] {
+            printer.exchange(byte);
+        }
+        assert_eq!(printer.exchange(0x00), 0x01);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn printer_render_test() {
+        let mut image = Vec::new();
+        decompress(&[0x01, 0x80, 0x01, 0x80 | 0x7F, 0x00], &mut image);
+        assert_eq!(image[..2], [0x80, 0x01]);
+        assert_eq!(image.len(), 2 + 0x81);
+        image.resize(20 * 16, 0);
+
+        let pixels = render(&image, 0xE4);
+        assert_eq!(pixels.len(), 8 * PRINT_WIDTH);
+        // leftmost pixel has color 1, the rest of the first row color 0 except the 8th (color 2)
+        assert_eq!(pixels[..8], [0xAA, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x55]);
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Time played, frames run and battery saves written for one game, over every session.
+#[derive(Clone, Debug, Default)]
+pub struct PlayStats {
+    pub title: String,
+    pub play_time: Duration,
+    pub frames: u64,
+    pub saves: u64,
+}
+
+impl PlayStats {
+    /// Returns e.g. "Played 3h 12m, 41 saves".
+    pub fn summary(&self) -> String {
+        let minutes = self.play_time.as_secs() / 60;
+        let play_time = match minutes {
+            0 => "<1m".to_string(),
+            1..=59 => format!("{}m", minutes),
+            _ => format!("{}h {}m", minutes / 60, minutes % 60),
+        };
+        format!("Played {}, {} saves", play_time, self.saves)
+    }
+}
+
+/// Keeps the stats of the game being played up to date in a CSV file shared by every game,
+/// one line per game keyed by its ROM hash (see Cartridge::rom_hash):
+/// `rom_hash,seconds,frames,saves,title`.
+pub struct PlaySession {
+    path: String,
+    rom_hash: u32,
+    // totals from earlier sessions
+    previous: PlayStats,
+    pub frames: u64,
+    pub saves: u64,
+}
+
+impl PlaySession {
+    pub fn start(path: &str, rom_hash: u32, title: &str) -> Self {
+        let mut previous = read_log(path).remove(&rom_hash).unwrap_or_default();
+        previous.title = title.to_string();
+
+        PlaySession {
+            path: path.to_string(),
+            rom_hash,
+            previous,
+            frames: 0,
+            saves: 0,
+        }
+    }
+
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    pub fn title(&self) -> &str {
+        &self.previous.title
+    }
+
+    /// Returns this game's totals, counting play_time (emulated) from this session.
+    pub fn totals(&self, play_time: Duration) -> PlayStats {
+        PlayStats {
+            title: self.previous.title.clone(),
+            play_time: self.previous.play_time + play_time,
+            frames: self.previous.frames + self.frames,
+            saves: self.previous.saves + self.saves,
+        }
+    }
+
+    /// Writes this game's totals into the log; other games' lines are re-read first,
+    /// so copies of the emulator running other games don't overwrite each other.
+    pub fn save(&self, play_time: Duration) -> Result<(), String> {
+        let mut games = read_log(&self.path);
+        games.insert(self.rom_hash, self.totals(play_time));
+
+        let contents: String = games.iter().map(|(rom_hash, stats)| format!("{:08x},{},{},{},{}\n",
+            rom_hash, stats.play_time.as_secs(), stats.frames, stats.saves, stats.title)).collect();
+
+        if let Some(dir) = Path::new(&self.path).parent() {
+            create_dir_all(dir).map_err(|e| format!("Unable to create {}: {}", dir.display(), e))?;
+        }
+        write(&self.path, contents).map_err(|e| format!("Unable to write {}: {}", self.path, e))
+    }
+}
+
+/// Reads every game's stats from the log at path, skipping lines that don't parse
+/// (a missing log is just empty).
+fn read_log(path: &str) -> BTreeMap<u32, PlayStats> {
+    let contents = read_to_string(path).unwrap_or_default();
+    contents.lines().filter_map(|line| {
+        // titles go last as they may contain commas
+        let fields: Vec<&str> = line.splitn(5, ',').collect();
+        if fields.len() != 5 {
+            return None;
+        }
+        let stats = PlayStats {
+            title: fields[4].to_string(),
+            play_time: Duration::from_secs(fields[1].parse().ok()?),
+            frames: fields[2].parse().ok()?,
+            saves: fields[3].parse().ok()?,
+        };
+        Some((u32::from_str_radix(fields[0], 16).ok()?, stats))
+    }).collect()
+}
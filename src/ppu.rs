@@ -1,8 +1,12 @@
-use std::cmp::min;
+use alloc::{format, string::String, vec, vec::Vec};
+use core::cmp::min;
+use core::str::FromStr;
 
 use crate::cpu::GBModel;
 use crate::constants::{BYTES_PER_PIXEL, LCD_BYTE_WIDTH};
-use crate::config::{WITH_COLOUR_CORRECTION, COLOURS};
+use crate::config::{COLOUR_CORRECTION, LCD_GHOSTING, LCD_OFF_POLICY, OAM_BUG};
+use crate::palette::{Colour, ColourCorrection, DmgPalette, PaletteLayer};
+use crate::savestate::{snapshot_fields, Snapshot, StateError, StateReader, StateWriter};
 
 const TILE_SIZE: usize = 16;
 const TILE_ENTRIES: usize = 384;
@@ -20,9 +24,65 @@ const LCD_HEIGHT: usize = 144;
 
 const SCAN_LINE_DOTS: u32 = 456;
 const MODE_1_DOTS: u32 = SCAN_LINE_DOTS * 10;
+// a whole frame (144 lines drawn, 10 in VBlank); a dot is a T-cycle at normal speed
+pub const FRAME_DOTS: u32 = SCAN_LINE_DOTS * 154;
 const MODE_2_DOTS: u32 = 80;
 const MODE_3_MIN_DOTS: u32 = 172;
 
+// set in palette indices of pixels drawn by objects (see Ppu::palette_indices)
+pub const OBJ_PIXEL: u8 = 0x80;
+
+/// Which layer drew a pixel, for the debug tints (see Ppu::set_layer_tints).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Bg,
+    Window,
+    /// An object using OBP0 (the OAM attribute's DMG palette bit, also on CGB)
+    Obj0,
+    Obj1,
+    /// BG or window drawn over an object's pixel by BG/OAM priority
+    PriorityBg,
+}
+
+impl Layer {
+    /// The colour mixed into pixels from this layer (in the frame buffer's byte order).
+    fn tint(self) -> Colour {
+        match self {
+            Layer::Bg => [0xFF, 0x40, 0x40, 0xFF],
+            Layer::Window => [0x40, 0xFF, 0x40, 0xFF],
+            Layer::Obj0 => [0x40, 0x40, 0xFF, 0xFF],
+            Layer::Obj1 => [0x40, 0xFF, 0xFF, 0xFF],
+            Layer::PriorityBg => [0xFF, 0x40, 0xFF, 0xFF],
+        }
+    }
+}
+
+/// What the screen shows while a game has the LCD off, where the PPU draws no frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LcdOffPolicy {
+    /// Keeps showing the last frame drawn.
+    Hold,
+    /// Shows a blank screen, like hardware: the DMG palette's lightest colour, or white on CGB.
+    Blank,
+    /// Fades the last frame to blank over a few frames, which can look smoother for games
+    /// that turn the LCD off for a moment between screens.
+    Fade,
+}
+
+/// Parses `hold`, `blank` or `fade`.
+impl FromStr for LcdOffPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hold" => Ok(LcdOffPolicy::Hold),
+            "blank" => Ok(LcdOffPolicy::Blank),
+            "fade" => Ok(LcdOffPolicy::Fade),
+            _ => Err(format!("Unknown LCD-off policy '{}' (expected hold, blank or fade)", s)),
+        }
+    }
+}
+
 #[derive(PartialEq)]
 enum Mode {
     HBlank0, 
@@ -33,9 +93,35 @@ enum Mode {
 
 pub struct Ppu {
     model: GBModel,
+    dmg_palette: DmgPalette,
+    // (CGB ONLY) running a DMG game: pixels are drawn like on DMG, then coloured through CRAM
+    dmg_compat: bool,
+    // (CGB ONLY) display colour of every RGB555 colour under the current ColourCorrection
+    colour_table: Vec<Colour>,
     frame_buffer: [u8; LCD_BYTE_WIDTH * LCD_HEIGHT],
+    // how much of the last frame (out of 255) lingers in the next one, like the LCD's ghosting
+    ghosting: u8,
+    // the last frame shown (after ghosting), while ghosting is on
+    previous_frame: Vec<u8>,
+    // (DMG/SGB ONLY) shade (0-3) of every pixel in frame_buffer, which the SGB colorizes
+    shades: Vec<u8>,
+    // palette index of every pixel in frame_buffer (see palette_indices)
+    palette_indices: Vec<u8>,
+    // palette index of the pixel render_pixel() is drawing
+    pixel_index: u8,
+    // layer the pixel render_pixel() is drawing comes from
+    pixel_layer: Layer,
+    // pixels are tinted by the layer they come from (see set_layer_tints)
+    layer_tints: bool,
     stat_triggered: bool,
+    // (DMG/SGB ONLY) set by the STAT write bug, raised as a STAT interrupt on the next step
+    stat_write_triggered: bool,
     entered_vblank: bool,
+    // what's shown while the LCD is off, dots since it last drew a frame for it,
+    // and whether that frame is waiting for get_display_output()
+    lcd_off_policy: LcdOffPolicy,
+    lcd_off_dots: u32,
+    lcd_off_frame: bool,
     tile_data0: [[u8; TILE_SIZE]; TILE_ENTRIES],
     tile_map0: [u8; TILE_MAP_SIZE],
     tile_map1: [u8; TILE_MAP_SIZE],
@@ -85,6 +171,9 @@ impl Ppu {
     pub fn new(model: GBModel) -> Self {
         Ppu { 
             model,
+            dmg_palette: DmgPalette::default(),
+            dmg_compat: false,
+            colour_table: if model.is_cgb() { COLOUR_CORRECTION.table() } else { Vec::new() },
             tile_data0: [[0; TILE_SIZE]; TILE_ENTRIES],
             tile_map0: [0; TILE_MAP_SIZE],
             tile_map1: [0; TILE_MAP_SIZE],
@@ -102,8 +191,19 @@ impl Ppu {
             wy: 0,
             wx: 0,
             frame_buffer: [0; LCD_BYTE_WIDTH * LCD_HEIGHT],
+            ghosting: LCD_GHOSTING,
+            previous_frame: Vec::new(),
+            shades: vec![0; LCD_WIDTH * LCD_HEIGHT],
+            palette_indices: vec![0; LCD_WIDTH * LCD_HEIGHT],
+            pixel_index: 0,
+            pixel_layer: Layer::Bg,
+            layer_tints: false,
             stat_triggered: false,
+            stat_write_triggered: false,
             entered_vblank: false,
+            lcd_off_policy: LCD_OFF_POLICY,
+            lcd_off_dots: 0,
+            lcd_off_frame: false,
             stat_line: false,
             mode: Mode::VBlank1,
             mode_elapsed_dots: 0,
@@ -133,8 +233,11 @@ impl Ppu {
     /// Steps through the PPU over the given period (in dots).
     /// NOTE: 1 dot = 1 T-Cycle (= 1/4 M-Cycle)
     pub fn step(&mut self, dots: u32) {
-        if self.lcd_ppu_disabled() { return; }
-        self.stat_triggered = false;
+        if self.lcd_ppu_disabled() {
+            self.step_lcd_off(dots);
+            return;
+        }
+        self.stat_triggered = core::mem::take(&mut self.stat_write_triggered);
         self.entered_hblank = false;
 
         let next_dots = self.mode_elapsed_dots + dots;
@@ -173,6 +276,7 @@ impl Ppu {
                     self.win_counter = 0;
                     self.entered_vblank = true;
                     self.last_vblank_scanline = 0;
+                    self.apply_ghosting();
                     Mode::VBlank1
                 } else {        
                     self.win_counter += self.line_has_window as usize;
@@ -238,10 +342,21 @@ impl Ppu {
 
                     // future TODO (maybe): implement BG and OAM FIFO 
                     let colour = self.render_pixel(self.cur_pixel_x, self.ly as usize); 
-                    let display_colour = match self.model {
-                        GBModel::DMG => COLOURS[colour as usize],
-                        GBModel::CGB => Ppu::rgb555_to_argb8888(colour),
+                    let mut display_colour = match self.model {
+                        GBModel::DMG | GBModel::SGB => {
+                            self.shades[usize::from(self.ly) * LCD_WIDTH + self.cur_pixel_x] = (colour & 0x03) as u8;
+                            self.dmg_palette.colour(colour)
+                        },
+                        GBModel::CGB | GBModel::AGB if self.dmg_compat => self.colour_table[self.compat_colour(colour) as usize & 0x7FFF],
+                        GBModel::CGB | GBModel::AGB => self.colour_table[colour as usize & 0x7FFF],
                     };
+                    self.palette_indices[usize::from(self.ly) * LCD_WIDTH + self.cur_pixel_x] = self.pixel_index;
+                    if self.layer_tints {
+                        let tint = self.pixel_layer.tint();
+                        for (channel, &tint) in display_colour.iter_mut().zip(&tint) {
+                            *channel = ((*channel as u16 + tint as u16) / 2) as u8;
+                        }
+                    }
 
                     for i in 0..BYTES_PER_PIXEL {
                         self.frame_buffer[usize::from(self.ly) * LCD_BYTE_WIDTH
@@ -259,10 +374,16 @@ impl Ppu {
         self.update_stat();
     }
 
-    /// Returns colour index (for DMG), or RGB555 representation (for CGB) of pixel at position (lcd_x, lcd_y) 
+    /// Returns colour index and palette layer (for DMG), or RGB555 representation (for CGB) of pixel at position (lcd_x, lcd_y) 
     fn render_pixel(&mut self, lcd_x: usize, lcd_y: usize) -> u16 {
-        let (mut colour, bg_priority, bg_is_0) = self.apply_bg(lcd_x, lcd_y);
-        colour = self.render_obj(colour, lcd_x, lcd_y, bg_priority, bg_is_0);
+        let (colour, bg_priority, bg_is_0) = self.apply_bg(lcd_x, lcd_y);
+        let (colour, obj_index, obj_layer) = self.render_obj(colour, lcd_x, lcd_y, bg_priority, bg_is_0);
+        if let Some(index) = obj_index {
+            self.pixel_index = index;
+        }
+        if let Some(layer) = obj_layer {
+            self.pixel_layer = layer;
+        }
         colour
     }
 
@@ -280,39 +401,44 @@ impl Ppu {
             x = lcd_x + 7 - self.wx as usize;
             y = self.win_counter;       
         }
+        self.pixel_layer = if is_bg { Layer::Bg } else { Layer::Window };
 
         let tmap_addr = (x >> 3) + ((y >> 3) << 5);
         let tile_id = self.fetch_bgwin_tile_id(tmap_addr, is_bg);
 
-        match self.model {
-            GBModel::DMG => {
-                if self.lcdc & 0x01 == 0 {
-                    return (0, false, true);
-                }
-
-                let colour_id = self.fetch_colour_id(tile_id, false, self.lcdc & 0x10 == 0, x, y, false, false);
-                (Ppu::apply_palette_dmg(&colour_id, &self.bgp), false, colour_id == 0)
-            }
-            GBModel::CGB => {
-                let attributes = self.fetch_bgwin_attribute(tmap_addr, is_bg);
-                let palette = attributes & 0x07;
-                let bank = attributes & 0x08 != 0;
-                let x_flip = attributes & 0x20 != 0;
-                let y_flip = attributes & 0x40 != 0;
-                let priority = attributes & 0x80 != 0;
-
-                let colour_id = self.fetch_colour_id(tile_id, bank, self.lcdc & 0x10 == 0, x, y, x_flip, y_flip);
-                (Ppu::apply_palette_cgb(&colour_id, self.cram_bg, &palette), priority, colour_id == 0)
+        if !self.is_cgb() {
+            if self.lcdc & 0x01 == 0 {
+                self.pixel_index = 0;
+                return (0, false, true);
             }
+
+            let colour_id = self.fetch_colour_id(tile_id, false, self.lcdc & 0x10 == 0, x, y, false, false);
+            self.pixel_index = colour_id;
+            let colour = Ppu::apply_palette_dmg(&colour_id, &self.bgp) | (PaletteLayer::Bg as u16) << 2;
+            return (colour, false, colour_id == 0);
         }
 
+        let attributes = self.fetch_bgwin_attribute(tmap_addr, is_bg);
+        let palette = attributes & 0x07;
+        let bank = attributes & 0x08 != 0;
+        let x_flip = attributes & 0x20 != 0;
+        let y_flip = attributes & 0x40 != 0;
+        let priority = attributes & 0x80 != 0;
+
+        let colour_id = self.fetch_colour_id(tile_id, bank, self.lcdc & 0x10 == 0, x, y, x_flip, y_flip);
+        self.pixel_index = palette << 2 | colour_id;
+        (Ppu::apply_palette_cgb(&colour_id, self.cram_bg, &palette), priority, colour_id == 0)
     }
 
     /// Applies object tile (if any) to current (x, y) position in LCD.
     /// Decides if object covers BG based LCDC bit 0, Object's priority attribute, 
     /// BG tile's priority attribute (CGB Only) and if the BG tile has an id of 0.
-    fn render_obj(&self, bg_colour: u16, lcd_x: usize, lcd_y: usize, bg_priority: bool, bg_is_0: bool) -> u16 {
+    /// Also returns the palette index of the object's pixel, if it's drawn, and the layer
+    /// that won if there's an object pixel at all.
+    fn render_obj(&self, bg_colour: u16, lcd_x: usize, lcd_y: usize, bg_priority: bool, bg_is_0: bool) -> (u16, Option<u8>, Option<Layer>) {
         let mut colour = bg_colour;
+        let mut index = None;
+        let mut top_layer = None;
 
         for obj in &self.obj_buffer {
             if !self.obj_enabled() {
@@ -326,29 +452,34 @@ impl Ppu {
             let tile_id = obj.fetch_tile_id(lcd_y, self.obj_size());
             let tile_x = lcd_x + 8 - obj.x;
             let tile_y = lcd_y as usize + 16 - obj.y;
-            let use_bank_1 = obj.cgb_use_bank_1 && matches!(self.model, GBModel::CGB);
+            let use_bank_1 = obj.cgb_use_bank_1 && self.is_cgb();
             let id = self.fetch_colour_id(tile_id as u8, use_bank_1, false, tile_x, tile_y, 
                 obj.x_flip, obj.y_flip);
 
             if id != 0 { 
-                match self.model {
-                    GBModel::DMG => {
-                        if bg_is_0 || !obj.bg_priority {
-                            let palette = if !obj.dmg_palette { self.obp0 } else { self.obp1 };
-                            colour = Ppu::apply_palette_dmg(&id, &palette);
-                        }
-                    }
-                    GBModel::CGB => {
-                        if bg_is_0 || self.lcdc & 0x01 == 0 || (!obj.bg_priority && !bg_priority) {
-                            colour = Ppu::apply_palette_cgb(&id, self.cram_obj, &obj.cgb_palette)
-                        }
+                top_layer = Some(Layer::PriorityBg);
+                let obj_layer = if obj.dmg_palette { Layer::Obj1 } else { Layer::Obj0 };
+                if !self.is_cgb() {
+                    if bg_is_0 || !obj.bg_priority {
+                        let (palette, layer) = if !obj.dmg_palette { 
+                            (self.obp0, PaletteLayer::Obj0) 
+                        } else { 
+                            (self.obp1, PaletteLayer::Obj1) 
+                        };
+                        colour = Ppu::apply_palette_dmg(&id, &palette) | (layer as u16) << 2;
+                        index = Some(OBJ_PIXEL | (obj.dmg_palette as u8) << 2 | id);
+                        top_layer = Some(obj_layer);
                     }
+                } else if bg_is_0 || self.lcdc & 0x01 == 0 || (!obj.bg_priority && !bg_priority) {
+                    colour = Ppu::apply_palette_cgb(&id, self.cram_obj, &obj.cgb_palette);
+                    index = Some(OBJ_PIXEL | obj.cgb_palette << 2 | id);
+                    top_layer = Some(obj_layer);
                 }
                 break;
             }
         }
 
-        colour
+        (colour, index, top_layer)
     }
 
     /// Gets tile at tile_id from tile_data0 (or tile_data1 if bank = true and model is CGB) 
@@ -356,7 +487,7 @@ impl Ppu {
     /// then returns the tile's colour_id at (tile_x, tile_y) after applying x_flip and y_flip.
     fn fetch_colour_id(&self, tile_id: u8, bank: bool, addr_mode: bool, tile_x: usize, tile_y: usize, x_flip: bool, y_flip: bool) -> u8 {
         let mut tile_data = &self.tile_data0;
-        if bank && matches!(self.model, GBModel::CGB) {
+        if bank && self.is_cgb() {
             tile_data = &self.tile_data1;
         }
         
@@ -393,7 +524,8 @@ impl Ppu {
         }
     }
 
-    /// updates STAT register and updates stat line
+    /// Updates the STAT register and the STAT line: every enabled source (bit 3 HBlank,
+    /// bit 4 VBlank, bit 5 OAM scan, bit 6 LY=LYC) ORed together.
     fn update_stat(&mut self) {
         let stat = self.stat & 0xFC;
         self.stat = stat | Ppu::mode_to_num(&self.mode);
@@ -406,12 +538,33 @@ impl Ppu {
 
         let old_stat_line = self.stat_line;
 
+        // the mode 2 source is also checked as VBlank starts
+        let vblank_start = self.mode == Mode::VBlank1 && self.ly == LCD_HEIGHT as u8 && self.last_vblank_scanline < 4;
+
         self.stat_line = (self.lyc == self.ly && self.stat & 0x40 != 0) |
-            (self.mode == Mode::HBlank0 && self.stat & 0x20 != 0) |
+            (self.mode == Mode::HBlank0 && self.stat & 0x08 != 0) |
             (self.mode == Mode::VBlank1 && self.stat & 0x10 != 0) |
-            (self.mode == Mode::OamScan2 && self.stat & 0x08 != 0);
+            ((self.mode == Mode::OamScan2 || vblank_start) && self.stat & 0x20 != 0);
+
+        // the interrupt is only requested when the line goes high, so a source becoming true
+        // while another one already holds the line up is blocked (the "STAT IRQ blocking")
+        self.stat_triggered |= !old_stat_line && self.stat_line
+    }
 
-        self.stat_triggered = !old_stat_line && self.stat_line
+    /// (DMG/SGB ONLY) Writing STAT briefly enables every STAT source as if 0xFF was written,
+    /// so the STAT line rises during HBlank, VBlank or when LY=LYC (Road Rash and
+    /// Zerd no Densetsu rely on the resulting interrupt).
+    fn stat_write_bug(&mut self) {
+        if self.is_cgb() || self.lcd_ppu_disabled() {
+            return;
+        }
+
+        let line = self.lyc == self.ly || matches!(self.mode, Mode::HBlank0 | Mode::VBlank1);
+        if line && !self.stat_line {
+            self.stat_write_triggered = true;
+            // stays high until the next update if the written sources still hold it up
+            self.stat_line = true;
+        }
     }
 
     fn lcd_ppu_disabled(&self) -> bool {
@@ -419,7 +572,7 @@ impl Ppu {
     }
 
     fn win_enabled(&self) -> bool {
-        if matches!(self.model, GBModel::DMG) && self.lcdc & 0x01 == 0 {
+        if !self.is_cgb() && self.lcdc & 0x01 == 0 {
             false
         } else {
             self.lcdc & 0x20 != 0
@@ -437,15 +590,17 @@ impl Ppu {
     fn reset_lcd(&mut self) {
         self.stat_line = false;
         self.frame_buffer = [0; LCD_BYTE_WIDTH * LCD_HEIGHT];
+        self.shades.fill(0);
+        self.palette_indices.fill(0);
     }
 
+    /// Reads VRAM whatever the mode; the CPU can only while vram_accessible().
     pub fn read_vram(&self, addr: usize) -> u8 {
-        // Let cpu read from vram EVEN DURING MODE 3
         let mut tile_data = &self.tile_data0;
         let mut map0= &self.tile_map0;
         let mut map1 = &self.tile_map1;
 
-        if (self.vbk & 0x01) != 0 && matches!(self.model, GBModel::CGB) {
+        if (self.vbk & 0x01) != 0 && self.is_cgb() {
             tile_data = &self.tile_data1;
             map0 = &self.attr_map0;
             map1 = &self.attr_map1;
@@ -462,19 +617,19 @@ impl Ppu {
         }
     }
 
+    /// Writes VRAM whatever the mode; the CPU can only while vram_accessible().
     pub fn write_vram(&mut self, addr: usize, byte: u8) {
-        // Let cpu write from vram EVEN DURING MODE 3
+        let bank_1 = (self.vbk & 0x01) != 0 && self.is_cgb();
         let mut tile_data = &mut self.tile_data0;
         let mut map0= &mut self.tile_map0;
         let mut map1 = &mut self.tile_map1;
 
-        if (self.vbk & 0x01) != 0 && matches!(self.model, GBModel::CGB) {
+        if bank_1 {
             tile_data = &mut self.tile_data1;
             map0 = &mut self.attr_map0;
             map1 = &mut self.attr_map1;
         }
 
-        // Let cpu write to vram EVEN DURING MODE 3
         match addr {
             0x8000..=0x97FF => {
                 let index = addr - 0x8000;
@@ -486,23 +641,53 @@ impl Ppu {
         }
     }
 
+    /// Reads OAM whatever the mode; the CPU can only while oam_accessible().
     pub fn read_oam(&self, addr: usize) -> u8 {
-        if self.can_access_oam() {
-            let index = addr - 0xFE00;
-            self.oam[index / OAM_ENTRY_SIZE][index % OAM_ENTRY_SIZE]
-        } else { 
-            0xFF
-        } 
+        let index = addr - 0xFE00;
+        self.oam[index / OAM_ENTRY_SIZE][index % OAM_ENTRY_SIZE]
     }
 
+    /// Writes OAM whatever the mode (e.g. for OAM DMA); the CPU can only while oam_accessible().
     pub fn write_oam(&mut self, addr: usize, byte: u8) {
-        if self.can_access_oam() {
-            let index = addr - 0xFE00;
-            self.oam[index / OAM_ENTRY_SIZE][index % OAM_ENTRY_SIZE] = byte;
+        let index = addr - 0xFE00;
+        self.oam[index / OAM_ENTRY_SIZE][index % OAM_ENTRY_SIZE] = byte;
+    }
+
+    /// (DMG/SGB ONLY) Emulates the OAM corruption bug for addr being put on the address bus by
+    /// a 16-bit INC/DEC: while OAM is being scanned, the 8 byte row (2 objects) the PPU is reading
+    /// gets mixed with the row before it (the first row is never corrupted).
+    pub fn corrupt_oam(&mut self, addr: u16) {
+        if !OAM_BUG || self.model.is_cgb() || self.lcd_ppu_disabled() || self.mode != Mode::OamScan2
+            || !(0xFE00..=0xFEFF).contains(&addr) {
+            return;
         }
+
+        // one row is read every M-cycle
+        let row = (self.mode_elapsed_dots / 4) as usize;
+        if row == 0 || row >= OAM_ENTRIES / 2 {
+            return;
+        }
+        let (cur, prev) = (row * 2, row * 2 - 2);
+
+        // first word becomes ((a ^ c) & (b ^ c)) ^ c, with a the current row's first word and b and c
+        // the previous row's first and third words; the rest is copied from the previous row
+        for i in 0..2 {
+            let (a, b, c) = (self.oam[cur][i], self.oam[prev][i], self.oam[prev + 1][i]);
+            self.oam[cur][i] = ((a ^ c) & (b ^ c)) ^ c;
+        }
+        let prev_entry = self.oam[prev];
+        self.oam[cur][2..].copy_from_slice(&prev_entry[2..]);
+        self.oam[cur + 1] = self.oam[prev + 1];
+    }
+
+    /// Whether the CPU can access VRAM: not while mode 3 draws from it (reads return 0xFF,
+    /// writes are ignored).
+    pub fn vram_accessible(&self) -> bool {
+        self.lcd_ppu_disabled() || self.mode != Mode::Drawing3
     }
 
-    fn can_access_oam(&self) -> bool {
+    /// Whether the CPU can access OAM: not while mode 2 scans it or mode 3 draws its objects.
+    pub fn oam_accessible(&self) -> bool {
         self.lcd_ppu_disabled() ||
         (self.mode != Mode::Drawing3 && self.mode != Mode::OamScan2)
     }
@@ -524,6 +709,8 @@ impl Ppu {
 
             0xFF4F => self.vbk & 0xFE,
             0xFF68 => self.bgpi,
+            // (CGB ONLY) like VRAM, palettes are locked while mode 3 draws with them
+            0xFF69 | 0xFF6B if !self.vram_accessible() => 0xFF,
             0xFF69 => self.cram_bg[(self.bgpi & 0x3F) as usize],
             0xFF6A => self.obpi,
             0xFF6B => self.cram_obj[(self.obpi & 0x3F) as usize],
@@ -540,9 +727,13 @@ impl Ppu {
                 if self.lcdc & 0x80 == 0 && byte & 0x80 != 0 {
                     self.reset_lcd();   
                 }
+                if self.lcdc & 0x80 != 0 && byte & 0x80 == 0 {
+                    self.lcd_off_dots = 0;
+                }
                 self.lcdc = byte; 
             },
             0xFF41 => {
+                self.stat_write_bug();
                 let stat = self.stat & 0x07;
                 self.stat = (byte & 0xF8) | stat;
             },
@@ -559,8 +750,11 @@ impl Ppu {
 
             0xFF4F => self.vbk = byte & 0x01,
             0xFF68 => self.bgpi = byte,
+            // (CGB ONLY) writes while mode 3 draws are ignored, but still auto-increment
             0xFF69 => {
-                self.cram_bg[(self.bgpi & 0x3F) as usize] = byte;
+                if self.vram_accessible() {
+                    self.cram_bg[(self.bgpi & 0x3F) as usize] = byte;
+                }
                 if self.bgpi & 0x80 != 0 {
                     self.bgpi += 1;
                     self.bgpi &= 0b10111111;
@@ -568,7 +762,9 @@ impl Ppu {
             },
             0xFF6A => self.obpi = byte,
             0xFF6B => {
-                self.cram_obj[(self.obpi & 0x3F) as usize] = byte;
+                if self.vram_accessible() {
+                    self.cram_obj[(self.obpi & 0x3F) as usize] = byte;
+                }
                 if self.obpi & 0x80 != 0 {
                     self.obpi += 1;
                     self.obpi &= 0b10111111;
@@ -579,31 +775,72 @@ impl Ppu {
         };
     }
 
+    /// (DMG ONLY) Sets the colours used to display BG/Window and object pixels;
+    /// in DMG compatibility mode they're loaded into CRAM like the CGB boot ROM does.
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette;
+        if !self.dmg_compat {
+            return;
+        }
+
+        let (obj0, obj1) = self.cram_obj.split_at_mut(8);
+        for (colours, cram) in [(palette.bg, &mut self.cram_bg[..8]), (palette.obj0, obj0), (palette.obj1, &mut obj1[..8])] {
+            for (colour, bytes) in colours.iter().zip(cram.chunks_exact_mut(2)) {
+                let [b, g, r, _] = colour.map(|channel| (channel >> 3) as u16);
+                bytes.copy_from_slice(&(r | g << 5 | b << 10).to_le_bytes());
+            }
+        }
+    }
+
+    /// (CGB ONLY) Converts CRAM colours with correction from the next pixel on.
+    pub fn set_colour_correction(&mut self, correction: ColourCorrection) {
+        self.colour_table = correction.table();
+    }
+
+    /// (CGB ONLY) Turns DMG compatibility mode on or off (see Bus::lock_key0).
+    pub fn set_dmg_compat(&mut self, dmg_compat: bool) {
+        self.dmg_compat = dmg_compat;
+    }
+
     pub fn write_dma(&mut self, byte: u8) {
         self.dma = byte;
     }
 
+    /// Returns how long mode 3 lasts on this line: 172 dots, plus the SCX % 8 pixels discarded
+    /// at its start, 6 dots if the window starts on this line and 6 to 11 dots per object.
     fn calc_mode_3_dots(&self) -> u32 {
         let mut res = MODE_3_MIN_DOTS + (self.scx % 8) as u32;
 
-        if self.win_enabled() && self.wy_cond {
+        let window = self.win_enabled() && self.wy_cond && self.wx <= 166;
+        if window {
             res += 6;
         }
 
-        for obj in &self.obj_buffer {
-            let offset = if self.win_enabled() && self.wy_cond && self.wx_cond { 
-                0xFF - self.wx 
-            } else { 
-                self.scx 
+        // objects are fetched left to right (offscreen ones at X >= 168 never are), each one
+        // stalling the fetcher 6 dots; the first object over a BG/window tile also waits
+        // for that tile's fetch to finish, up to 5 more dots depending on where it starts
+        let mut xs: Vec<usize> = self.obj_buffer.iter().map(|obj| obj.x).filter(|&x| x < 168).collect();
+        xs.sort();
+        let mut tiles_fetched = 0u64;
+        for x in xs {
+            res += 6;
+
+            let (tile, pixel) = if window && x > self.wx as usize {
+                (32 + (x - self.wx as usize - 1) / 8, (x - self.wx as usize - 1) % 8)
+            } else {
+                ((x + (self.scx % 8) as usize) / 8, (x + self.scx as usize) % 8)
             };
-            
-            res += 11 - min(5, (obj.x as u16 + offset as u16) % 8) as u32;
+            if tiles_fetched & (1 << tile) == 0 {
+                tiles_fetched |= 1 << tile;
+                // objects at X = 0 always wait the longest
+                res += if x == 0 { 5 } else { 5 - min(5, pixel) as u32 };
+            }
         }
 
         res
     }
 
-    /// Returns index of colour in COLOURS (0-3)
+    /// Returns the shade (0-3) that palette gives colour_id
     fn apply_palette_dmg(colour_id: &u8, palette: &u8) -> u16 {
         let id = colour_id << 1;
         ((palette & (0x03 << id)) >> id) as u16
@@ -616,33 +853,6 @@ impl Ppu {
         cram[index_0] as u16 | ((cram[index_1] as u16) << 8)
     }
 
-    fn rgb555_to_argb8888(colour: u16) -> [u8; BYTES_PER_PIXEL] {
-        let r5 = (colour >> 0) & 0x1F;
-        let g5 = (colour >> 5) & 0x1F;
-        let b5 = (colour >> 10) & 0x1F;
-        
-        let (blue, green, red) = if WITH_COLOUR_CORRECTION {
-            // CREDITS FOR COLOUR CORRECTION ALGORITHM:
-            // https://saveweb.github.io/near.sh/articles/video/color-emulation.html
-
-            let r = r5 * 26 + g5 * 4  + b5 * 2;
-            let g =           g5 * 24 + b5 * 8;
-            let b = r5 * 6  + g5 * 4  + b5 * 22;
-
-            let red = min(960, r) >> 2;
-            let green = min(960, g) >> 2;
-            let blue = min(960, b) >> 2;
-            (blue as u8, green as u8, red as u8)
-        } else {
-            let r8 = (r5 * 255 + 15) / 31;
-            let g8 = (g5 * 255 + 15) / 31;
-            let b8 = (b5 * 255 + 15) / 31;
-            (b8 as u8, g8 as u8, r8 as u8)
-        };
-
-        [blue, green, red, 0xFF]
-    }
-
     fn mode_to_num(mode: &Mode) -> u8 {
         match mode {
             Mode::HBlank0 => 0,
@@ -652,18 +862,118 @@ impl Ppu {
         }
     } 
 
-    fn is_cgb(&mut self) -> bool {
-        matches!(self.model, GBModel::CGB)
+    // CGB features are off in DMG compatibility mode
+    fn is_cgb(&self) -> bool {
+        self.model.is_cgb() && !self.dmg_compat
+    }
+
+    /// (CGB ONLY) Returns the RGB555 colour of a DMG pixel (see DmgPalette::colour) in DMG
+    /// compatibility mode, where BGP picks from BG palette 0 and OBP0/OBP1 from OBJ palettes 0/1.
+    fn compat_colour(&self, pixel: u16) -> u16 {
+        let colour_id = (pixel & 0x03) as u8;
+        match pixel >> 2 {
+            0 => Ppu::apply_palette_cgb(&colour_id, self.cram_bg, &0),
+            1 => Ppu::apply_palette_cgb(&colour_id, self.cram_obj, &0),
+            _ => Ppu::apply_palette_cgb(&colour_id, self.cram_obj, &1),
+        }
+    }
+
+    /// Mixes in ghosting/255 of the last frame from the next finished frame on (0 turns it off).
+    /// As the last frame already had its own predecessor mixed in, older frames fade out
+    /// gradually, which some games rely on for transparency by flickering sprites.
+    pub fn set_ghosting(&mut self, ghosting: u8) {
+        self.ghosting = ghosting;
+        self.previous_frame.clear();
+    }
+
+    /// Tints every pixel from the next one on by the layer it comes from, to see at a glance what
+    /// drew it: BG blue, window green, OBP0 objects red, OBP1 objects yellow, and BG/window
+    /// hiding an object through priority magenta. Only the display changes, not what games see.
+    pub fn set_layer_tints(&mut self, on: bool) {
+        self.layer_tints = on;
+    }
+
+    pub fn layer_tints(&self) -> bool {
+        self.layer_tints
+    }
+
+    fn apply_ghosting(&mut self) {
+        if self.ghosting == 0 {
+            return;
+        }
+
+        if self.previous_frame.len() == self.frame_buffer.len() {
+            let mix = self.ghosting as u32;
+            for (byte, &previous) in self.frame_buffer.iter_mut().zip(&self.previous_frame) {
+                *byte = ((*byte as u32 * (255 - mix) + previous as u32 * mix) / 255) as u8;
+            }
+        }
+        self.previous_frame.clear();
+        self.previous_frame.extend_from_slice(&self.frame_buffer);
+    }
+
+    /// Sets what frames show while the LCD is off, from the next one drawn for it on.
+    pub fn set_lcd_off_policy(&mut self, policy: LcdOffPolicy) {
+        self.lcd_off_policy = policy;
+    }
+
+    /// Draws a frame every FRAME_DOTS while the LCD is off (there's no VBlank to finish one),
+    /// as lcd_off_policy says, so frontends don't keep showing the game's last frame.
+    /// The SGB's picture is left alone, as the SNES keeps showing it.
+    fn step_lcd_off(&mut self, dots: u32) {
+        if self.lcd_off_policy == LcdOffPolicy::Hold || matches!(self.model, GBModel::SGB) {
+            return;
+        }
+        self.lcd_off_dots += dots;
+        if self.lcd_off_dots < FRAME_DOTS {
+            return;
+        }
+        self.lcd_off_dots -= FRAME_DOTS;
+
+        let blank = match self.model {
+            GBModel::CGB | GBModel::AGB => self.colour_table[0x7FFF],
+            GBModel::DMG | GBModel::SGB => self.dmg_palette.colour(0),
+        };
+        for pixel in self.frame_buffer.chunks_exact_mut(BYTES_PER_PIXEL) {
+            for (byte, &target) in pixel.iter_mut().zip(&blank) {
+                // fading moves a quarter of the way there every frame
+                let step = (target as i32 - *byte as i32) / 4;
+                *byte = match self.lcd_off_policy {
+                    LcdOffPolicy::Fade if step != 0 => (*byte as i32 + step) as u8,
+                    _ => target,
+                };
+            }
+        }
+        self.lcd_off_frame = true;
     }
 
     pub fn get_display_output(&mut self) -> Option<&[u8; LCD_BYTE_WIDTH * LCD_HEIGHT]> {
-        if !self.entered_vblank {
+        if !self.entered_vblank && !self.lcd_off_frame {
             return None;
         }
         self.entered_vblank = false;
+        self.lcd_off_frame = false;
         Some(&self.frame_buffer)
     }
 
+    /// Returns true if a frame was drawn while the LCD is off (see set_lcd_off_policy)
+    /// and hasn't been returned by get_display_output() yet.
+    pub fn lcd_off_frame(&self) -> bool {
+        self.lcd_off_frame
+    }
+
+    /// Returns the palette index of every pixel in the last frame (LCD_WIDTH x LCD_HEIGHT):
+    /// the colour id (0-3) in bits 0-1, the palette (BGP/OBP0/OBP1 on DMG, 0-7 on CGB)
+    /// in bits 2-4, and OBJ_PIXEL set if an object drew it.
+    pub fn palette_indices(&self) -> &[u8] {
+        &self.palette_indices
+    }
+
+    /// (DMG/SGB ONLY) Returns the shade of every pixel in the last frame.
+    pub fn shades(&self) -> &[u8] {
+        &self.shades
+    }
+
     pub fn stat_triggered(&self) -> bool {
         self.stat_triggered
     }
@@ -672,11 +982,17 @@ impl Ppu {
         self.entered_vblank
     }
 
+    /// Returns true if LCDC has the LCD and PPU turned on.
+    pub fn lcd_on(&self) -> bool {
+        !self.lcd_ppu_disabled()
+    }
+
     pub fn entered_hblank(&self) -> bool {
         self.entered_hblank
     }
 }
 
+#[derive(Default)]
 struct OAMEntry {
     y: usize,
     x: usize, 
@@ -721,22 +1037,72 @@ impl OAMEntry {
     }
 }
 
+// the palette, colour correction, ghosting and other display settings stay as they are
+snapshot_fields!(Ppu,
+    dmg_compat, frame_buffer, shades, palette_indices, stat_triggered, stat_write_triggered, entered_vblank,
+    lcd_off_dots, lcd_off_frame, tile_data0, tile_map0, tile_map1, oam,
+    lcdc, stat, scy, scx, ly, lyc, dma, bgp, obp0, obp1, wy, wx,
+    stat_line, mode, mode_elapsed_dots, mode_3_dots, cur_pixel_x, wy_cond, wx_cond, line_has_window,
+    win_counter, obj_buffer_index, obj_buffer, last_vblank_scanline,
+    vbk, bgpi, obpi, opri, tile_data1, attr_map0, attr_map1, cram_bg, cram_obj, entered_hblank,
+);
+
+impl Snapshot for Mode {
+    fn save(&self, state: &mut StateWriter) {
+        let mode: u8 = match self {
+            Mode::HBlank0 => 0,
+            Mode::VBlank1 => 1,
+            Mode::OamScan2 => 2,
+            Mode::Drawing3 => 3,
+        };
+        mode.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut mode = 0u8;
+        mode.load(state)?;
+        *self = match mode {
+            0 => Mode::HBlank0,
+            1 => Mode::VBlank1,
+            2 => Mode::OamScan2,
+            3 => Mode::Drawing3,
+            _ => return Err(StateError::Invalid("PPU mode")),
+        };
+        Ok(())
+    }
+}
+
+snapshot_fields!(OAMEntry, y, x, tile_id, cgb_palette, cgb_use_bank_1, dmg_palette, x_flip, y_flip, bg_priority);
 
 #[cfg(test)]
 mod tests {
-    use crate::{cartridge::Cartridge, cpu::Cpu};
+    use crate::{cartridge::Cartridge, cpu::{Cpu, GBModel}};
+    use crate::accuracy::check_accuracy_rev;
+    use crate::cpu::test_helpers::{test_blargg_rom_memory, test_screenshot};
+    use super::{LcdOffPolicy, Layer, Mode, OAMEntry, Ppu, FRAME_DOTS};
 
     const DMG_ACID: &str = "roms/tests/dmg-acid2.gb";
 
-    // NOTE: DMG_CHECKHASH depends on COLOURS constant
+    // NOTE: DMG_CHECKHASH depends on the default palette (config::DMG_PALETTE)
     const DMG_CHECKHASH: u64 = 17255647646328982741;
+    // the accuracy revision the hashes were recorded on: re-check them when it's bumped
+    const CHECKHASH_REV: u32 = 4;
 
     const CGB_ACID: &str = "roms/tests/cgb-acid2.gbc";
+
+    // the acid2 tests' reference images, and how many frames they take to draw what's in them
+    const DMG_ACID_REFERENCE: &str = "roms/tests/dmg-acid2.png";
+    const CGB_ACID_REFERENCE: &str = "roms/tests/cgb-acid2.png";
+    const ACID_FRAMES: usize = 70;
+
+    // blargg's OAM corruption tests, which report in cartridge RAM
+    const OAM_BUG: &str = "roms/tests/oam_bug.gb";
     const CGB_CHECKHASH: u64 = 15571388372626634589;
 
     #[test]
     fn ppu_dmg_test() {
-        let cartridge = Cartridge::from_file(DMG_ACID, false);
+        check_accuracy_rev(CHECKHASH_REV).unwrap_or_else(|mismatch| panic!("{}", mismatch));
+        let cartridge = Cartridge::from_file(DMG_ACID, false).unwrap();
         let mut cpu = Cpu::new(cartridge, crate::cpu::GBModel::DMG);
         let mut cycles: u32 = 0;
         while cycles < 5000000 {
@@ -753,7 +1119,8 @@ mod tests {
 
     #[test]
     fn ppu_cgb_test() {
-        let cartridge = Cartridge::from_file(CGB_ACID, false);
+        check_accuracy_rev(CHECKHASH_REV).unwrap_or_else(|mismatch| panic!("{}", mismatch));
+        let cartridge = Cartridge::from_file(CGB_ACID, false).unwrap();
         let mut cpu = Cpu::new(cartridge, crate::cpu::GBModel::CGB);
         let mut cycles: u32 = 0;
         while cycles < 5000000 {
@@ -768,6 +1135,238 @@ mod tests {
         assert!(hash == CGB_CHECKHASH, "hash mismatch: got {} but expected {}", hash, CGB_CHECKHASH);
     }
 
+    #[test]
+    fn ppu_dmg_screenshot_test() {
+        test_screenshot(DMG_ACID, GBModel::DMG, ACID_FRAMES, DMG_ACID_REFERENCE);
+    }
+
+    #[test]
+    fn ppu_cgb_screenshot_test() {
+        test_screenshot(CGB_ACID, GBModel::CGB, ACID_FRAMES, CGB_ACID_REFERENCE);
+    }
+
+    #[test]
+    fn stat_write_bug_test() {
+        for model in [GBModel::DMG, GBModel::CGB] {
+            let mut ppu = Ppu::new(model);
+            ppu.write_io(0xFF45, 0x90);
+            ppu.write_io(0xFF40, 0x80);
+
+            // no STAT source holds the line up during OAM scan (LY != LYC)
+            while ppu.read_io(0xFF41) & 0x03 != 2 {
+                ppu.step(1);
+            }
+            ppu.write_io(0xFF41, 0x00);
+            ppu.step(1);
+            assert!(!ppu.stat_triggered());
+
+            while ppu.read_io(0xFF41) & 0x03 != 0 {
+                ppu.step(1);
+            }
+            ppu.write_io(0xFF41, 0x00);
+            ppu.step(1);
+            assert_eq!(ppu.stat_triggered(), matches!(model, GBModel::DMG));
+
+            // only the write itself raises the line
+            ppu.step(1);
+            assert!(!ppu.stat_triggered());
+        }
+    }
+
+    #[test]
+    fn mode_3_length_test() {
+        let mut ppu = Ppu::new(GBModel::DMG);
+        ppu.write_io(0xFF40, 0x83);
+        ppu.write_io(0xFF43, 0x03);
+        assert_eq!(ppu.calc_mode_3_dots(), 175);
+
+        // the first object over a tile waits for it, the second one over it doesn't,
+        // offscreen objects cost nothing and X = 0 always costs 11
+        for x in [8, 10, 168, 0] {
+            ppu.obj_buffer.push(OAMEntry::new([16, x, 0, 0]));
+        }
+        assert_eq!(ppu.calc_mode_3_dots(), 175 + (6 + 2) + 6 + 11);
+
+        // the window starting on this line
+        ppu.obj_buffer.clear();
+        ppu.write_io(0xFF40, 0xA3);
+        ppu.write_io(0xFF4B, 7);
+        ppu.wy_cond = true;
+        assert_eq!(ppu.calc_mode_3_dots(), 175 + 6);
+    }
+
+    #[test]
+    fn stat_blocking_test() {
+        let mut ppu = Ppu::new(GBModel::CGB);
+        ppu.write_io(0xFF40, 0x80);
+        // HBlank and OAM scan sources
+        ppu.write_io(0xFF41, 0x28);
+
+        // going from HBlank straight into OAM scan keeps the line up, so only HBlank interrupts
+        let mut interrupts = Vec::new();
+        while ppu.read_io(0xFF44) != 10 {
+            ppu.step(4);
+            if ppu.stat_triggered() {
+                interrupts.push(ppu.read_io(0xFF41) & 0x03);
+            }
+        }
+        assert!(interrupts.len() >= 9);
+        assert!(interrupts.iter().skip(1).all(|&mode| mode == 0));
+    }
+
+    #[test]
+    fn access_blocking_test() {
+        let mut ppu = Ppu::new(GBModel::CGB);
+        ppu.write_io(0xFF40, 0x80);
+        ppu.write_io(0xFF68, 0x80);
+
+        ppu.mode = Mode::OamScan2;
+        assert!(ppu.vram_accessible() && !ppu.oam_accessible());
+        ppu.write_io(0xFF69, 0x12);
+
+        // palette writes in mode 3 are dropped, but the index still moves on
+        ppu.mode = Mode::Drawing3;
+        assert!(!ppu.vram_accessible() && !ppu.oam_accessible());
+        ppu.write_io(0xFF69, 0x34);
+        assert_eq!(ppu.read_io(0xFF69), 0xFF);
+        assert_eq!(ppu.read_io(0xFF68), 0x82);
+
+        ppu.mode = Mode::HBlank0;
+        assert!(ppu.vram_accessible() && ppu.oam_accessible());
+        ppu.write_io(0xFF68, 0x00);
+        assert_eq!(ppu.read_io(0xFF69), 0x12);
+        ppu.write_io(0xFF68, 0x01);
+        assert_eq!(ppu.read_io(0xFF69), 0x00);
+
+        // everything is open with the LCD off
+        ppu.mode = Mode::Drawing3;
+        ppu.write_io(0xFF40, 0x00);
+        assert!(ppu.vram_accessible() && ppu.oam_accessible());
+    }
+
+    #[test]
+    fn oam_bug_test() {
+        let mut ppu = Ppu::new(GBModel::DMG);
+        for addr in 0xFE00..0xFEA0 {
+            ppu.write_oam(addr, addr as u8);
+        }
+        let oam = ppu.oam;
+        ppu.write_io(0xFF40, 0x80);
+        ppu.mode = Mode::OamScan2;
+
+        // outside OAM or on the first row, nothing happens
+        ppu.corrupt_oam(0xC000);
+        ppu.corrupt_oam(0xFE10);
+        assert_eq!(ppu.oam, oam);
+
+        // reading the third row (objects 4 and 5), which takes on the second row's
+        ppu.mode_elapsed_dots = 8;
+        ppu.corrupt_oam(0xFE10);
+        let (a, b, c) = (0x10, 0x08, 0x0C);
+        assert_eq!(ppu.oam[4][0], ((a ^ c) & (b ^ c)) ^ c);
+        assert_eq!(ppu.oam[4][2..], oam[2][2..]);
+        assert_eq!(ppu.oam[5], oam[3]);
+        assert_eq!(ppu.oam[6], oam[6]);
+
+        // the CGB doesn't have the bug
+        let mut ppu = Ppu::new(GBModel::CGB);
+        ppu.write_io(0xFF40, 0x80);
+        ppu.mode = Mode::OamScan2;
+        ppu.mode_elapsed_dots = 8;
+        let oam = ppu.oam;
+        ppu.corrupt_oam(0xFE10);
+        assert_eq!(ppu.oam, oam);
+    }
+
+    #[test]
+    fn oam_bug_rom_test() {
+        test_blargg_rom_memory(OAM_BUG, GBModel::DMG);
+    }
+
+    #[test]
+    fn ghosting_test() {
+        let mut ppu = Ppu::new(GBModel::DMG);
+        ppu.set_ghosting(0x80);
+
+        ppu.frame_buffer.fill(0xFF);
+        ppu.apply_ghosting();
+        assert_eq!(ppu.frame_buffer[0], 0xFF);
+
+        // a black frame keeps half of the white one, and a quarter of it one frame later
+        ppu.frame_buffer.fill(0x00);
+        ppu.apply_ghosting();
+        assert_eq!(ppu.frame_buffer[0], 0x80);
+        ppu.frame_buffer.fill(0x00);
+        ppu.apply_ghosting();
+        assert_eq!(ppu.frame_buffer[0], 0x40);
+
+        ppu.set_ghosting(0);
+        ppu.apply_ghosting();
+        assert_eq!(ppu.frame_buffer[0], 0x40);
+    }
+
+    #[test]
+    fn lcd_off_policy_test() {
+        let mut ppu = Ppu::new(GBModel::DMG);
+        let white = ppu.dmg_palette.colour(0);
+        ppu.write_io(0xFF40, 0x91);
+        ppu.write_io(0xFF40, 0x11);
+        ppu.frame_buffer.fill(0x00);
+
+        // holding draws nothing while the LCD is off
+        ppu.set_lcd_off_policy(LcdOffPolicy::Hold);
+        ppu.step(FRAME_DOTS);
+        assert!(ppu.get_display_output().is_none());
+
+        // fading moves a quarter of the way to palette colour 0 every frame
+        ppu.set_lcd_off_policy(LcdOffPolicy::Fade);
+        ppu.step(FRAME_DOTS - 1);
+        assert!(!ppu.lcd_off_frame());
+        ppu.step(1);
+        assert_eq!(ppu.get_display_output().unwrap()[0], white[0] / 4);
+        assert!(ppu.get_display_output().is_none());
+
+        ppu.set_lcd_off_policy(LcdOffPolicy::Blank);
+        ppu.step(FRAME_DOTS);
+        assert_eq!(ppu.get_display_output().unwrap()[..4], white);
+    }
+
+    #[test]
+    fn layer_tints_test() {
+        let mut ppu = Ppu::new(GBModel::DMG);
+        // LCD, window, BG/window tiles at $8000, objects and BG on
+        ppu.lcdc = 0xB3;
+        ppu.tile_data0[1] = [0xFF; 16];
+        // BG tile 1 (all colour 3) at x 8-15, the rest tile 0 (all colour 0)
+        ppu.tile_map0[1] = 1;
+        // an object at x 12-19, half over each tile
+        let layer_at = |ppu: &mut Ppu, attributes: u8, lcd_x: usize| {
+            ppu.obj_buffer = vec![OAMEntry::new([16, 20, 1, attributes])];
+            ppu.render_pixel(lcd_x, 0);
+            ppu.pixel_layer
+        };
+
+        assert_eq!(layer_at(&mut ppu, 0x00, 17), Layer::Obj0);
+        assert_eq!(layer_at(&mut ppu, 0x10, 17), Layer::Obj1);
+        assert_eq!(layer_at(&mut ppu, 0x00, 13), Layer::Obj0);
+        // behind BG colours 1-3
+        assert_eq!(layer_at(&mut ppu, 0x80, 13), Layer::PriorityBg);
+        assert_eq!(layer_at(&mut ppu, 0x80, 17), Layer::Obj0);
+        assert_eq!(layer_at(&mut ppu, 0x00, 30), Layer::Bg);
+        ppu.wx_cond = true;
+        ppu.wy_cond = true;
+        assert_eq!(layer_at(&mut ppu, 0x00, 30), Layer::Window);
+
+        // tinted pixels are halfway between their colour and the layer's
+        ppu.set_layer_tints(true);
+        ppu.mode = Mode::Drawing3;
+        ppu.cur_pixel_x = 30;
+        ppu.step_mode(1);
+        let white = ppu.dmg_palette.colour(0);
+        let tint = Layer::Window.tint();
+        assert_eq!(ppu.frame_buffer[30 * 4], ((white[0] as u16 + tint[0] as u16) / 2) as u8);
+    }
+
     fn fnv1a(bytes: &[u8]) -> u64 {
         let mut hash = 0xcbf29ce484222325;
         for byte in bytes {
@@ -0,0 +1,179 @@
+use alloc::{format, string::String, vec::Vec};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::{sync::OnceLock, time::Instant};
+
+/// Host time spent emulating one frame, per subsystem.
+#[derive(Clone, Copy, Default)]
+pub struct FrameProfile {
+    /// Instruction execution, along with the timer, serial port and cartridge stepped during it.
+    pub cpu: Duration,
+    pub ppu: Duration,
+    pub apu: Duration,
+    /// OAM DMA and CGB VRAM DMA (HDMA/GDMA) transfers.
+    pub dma: Duration,
+}
+
+impl FrameProfile {
+    /// Returns the mean of each subsystem's time over frames.
+    pub fn average(frames: &[FrameProfile]) -> FrameProfile {
+        let count = frames.len().max(1) as u32;
+        let mean = |time: fn(&FrameProfile) -> Duration| frames.iter().map(time).sum::<Duration>() / count;
+        FrameProfile {
+            cpu: mean(|frame| frame.cpu),
+            ppu: mean(|frame| frame.ppu),
+            apu: mean(|frame| frame.apu),
+            dma: mean(|frame| frame.dma),
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.cpu + self.ppu + self.apu + self.dma
+    }
+
+    /// Formats the frame's times in milliseconds, e.g. for a status line.
+    pub fn summary(&self) -> String {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        format!("CPU {:.2}ms PPU {:.2}ms APU {:.2}ms DMA {:.2}ms (total {:.2}ms)",
+            ms(self.cpu), ms(self.ppu), ms(self.apu), ms(self.dma), ms(self.total()))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Component {
+    Ppu,
+    Apu,
+    Dma,
+}
+
+/// A free-running host counter to time subsystems with, e.g. a CPU cycle counter where
+/// there's no Instant: read returns its current value, which goes up hz times a second.
+#[derive(Clone, Copy)]
+pub struct HostClock {
+    pub read: fn() -> u64,
+    pub hz: u64,
+}
+
+impl HostClock {
+    /// Counts nanoseconds with Instant.
+    #[cfg(feature = "std")]
+    pub fn instant() -> Self {
+        fn nanos() -> u64 {
+            static EPOCH: OnceLock<Instant> = OnceLock::new();
+            EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+        }
+        HostClock { read: nanos, hz: 1_000_000_000 }
+    }
+
+    fn elapsed(&self, start: u64) -> Duration {
+        let ticks = (self.read)().wrapping_sub(start) as u128;
+        Duration::from_nanos((ticks * 1_000_000_000 / self.hz.max(1) as u128) as u64)
+    }
+}
+
+/// Measures how much host time each subsystem takes per frame, for attributing slowdowns.
+/// CPU time is whatever's left of each step after the timed subsystems.
+pub struct Profiler {
+    clock: HostClock,
+    current: FrameProfile,
+    // time spent in whole steps this frame, including the timed subsystems
+    step_total: Duration,
+    frames: Vec<FrameProfile>,
+}
+
+impl Profiler {
+    pub fn new(clock: HostClock) -> Self {
+        Profiler {
+            clock,
+            current: FrameProfile::default(),
+            step_total: Duration::ZERO,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Returns the clock's reading, for when a section starts.
+    pub fn start(&self) -> u64 {
+        (self.clock.read)()
+    }
+
+    pub fn record(&mut self, component: Component, start: u64) {
+        let elapsed = self.clock.elapsed(start);
+        match component {
+            Component::Ppu => self.current.ppu += elapsed,
+            Component::Apu => self.current.apu += elapsed,
+            Component::Dma => self.current.dma += elapsed,
+        }
+    }
+
+    /// Records a whole step (CPU instruction plus everything stepped along with it) that began at start.
+    pub fn record_step(&mut self, start: u64) {
+        self.step_total += self.clock.elapsed(start);
+    }
+
+    pub fn end_frame(&mut self) {
+        let mut frame = core::mem::take(&mut self.current);
+        frame.cpu = self.step_total.saturating_sub(frame.ppu + frame.apu + frame.dma);
+        self.step_total = Duration::ZERO;
+        self.frames.push(frame);
+    }
+
+    /// Returns the frames finished since the last call, oldest first.
+    pub fn take_frames(&mut self) -> Vec<FrameProfile> {
+        core::mem::take(&mut self.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    use crate::cartridge::Cartridge;
+    use crate::cpu::{test_helpers::make_test_rom, Cpu, GBModel};
+
+    use super::HostClock;
+
+    #[test]
+    fn profiler_frames_test() {
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        assert!(cpu.take_frame_profiles().is_empty());
+
+        cpu.set_profiling(true);
+        for _ in 0..3 * 70224 / 12 {
+            cpu.step();
+            cpu.get_display_output();
+        }
+
+        let frames = cpu.take_frame_profiles();
+        assert!((2..=3).contains(&frames.len()));
+        assert!(frames.iter().all(|frame| frame.cpu > frame.dma && frame.total() > frame.ppu));
+        assert!(cpu.take_frame_profiles().is_empty());
+    }
+
+    #[test]
+    fn host_clock_test() {
+        // moves on a millisecond every time it's read
+        fn ticks() -> u64 {
+            static TICKS: AtomicU64 = AtomicU64::new(0);
+            TICKS.fetch_add(1, Ordering::Relaxed)
+        }
+
+        // JR -2
+        let rom = make_test_rom(&[0x18, 0xFE]);
+        let mut cpu = Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG);
+        cpu.set_profiling_clock(Some(HostClock { read: ticks, hz: 1000 }));
+        for _ in 0..2 * 70224 / 12 {
+            cpu.step();
+            cpu.get_display_output();
+        }
+
+        let frames = cpu.take_frame_profiles();
+        assert!(!frames.is_empty());
+        for frame in frames {
+            assert!(frame.total() > Duration::ZERO);
+            assert_eq!(frame.total().subsec_nanos() % 1_000_000, 0);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use gbemulib::constants::T_CYCLE_HZ;
+
+use crate::apu::ApuWrite;
+use crate::cpu::Cpu;
+use crate::events::Timestamp;
+
+// VGM files count time in samples at this rate, whatever the sound chip
+const VGM_SAMPLE_RATE: u64 = 44100;
+const VGM_HEADER_SIZE: usize = 0x100;
+
+/// What an audio recording is saved as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// The audio as heard: 16-bit stereo PCM at the device's sample rate.
+    Wav,
+    /// The game's writes to the APU with their timing, which chiptune players (and archives)
+    /// play back on their own Game Boy sound emulation; a fraction of the size of a WAV.
+    Vgm,
+}
+
+/// Parses `wav` or `vgm`.
+impl FromStr for RecordFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wav" => Ok(RecordFormat::Wav),
+            "vgm" => Ok(RecordFormat::Vgm),
+            _ => Err(format!("Unknown recording format '{}' (expected wav or vgm)", s)),
+        }
+    }
+}
+
+enum Output {
+    Wav {
+        file: BufWriter<File>,
+        sample_rate: u32,
+        frames: u32,
+    },
+    Vgm {
+        // when the recording started, and the VGM samples waited for so far
+        start: Timestamp,
+        waited: u64,
+        commands: Vec<u8>,
+    },
+}
+
+/// Records the main core's audio to a new file in a directory, from start() until finish().
+pub struct Recorder {
+    path: String,
+    output: Output,
+}
+
+impl Recorder {
+    /// Starts recording cpu's audio as format into a timestamped file in root.
+    pub fn start(root: &str, format: RecordFormat, cpu: &mut Cpu) -> Result<Self, String> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        create_dir_all(root).map_err(|e| format!("Unable to create {}: {}", root, e))?;
+
+        let extension = match format {
+            RecordFormat::Wav => "wav",
+            RecordFormat::Vgm => "vgm",
+        };
+        let path = format!("{}/recording-{}.{}", root, timestamp, extension);
+
+        let output = match format {
+            RecordFormat::Wav => {
+                let file = File::create(&path).map_err(|e| format!("Unable to create {}: {}", path, e))?;
+                let mut file = BufWriter::new(file);
+                let sample_rate = cpu.audio_rate();
+                write_wav_header(&mut file, sample_rate, 0).map_err(|e| format!("Unable to write {}: {}", path, e))?;
+                Output::Wav { file, sample_rate, frames: 0 }
+            }
+            RecordFormat::Vgm => {
+                cpu.set_apu_write_log(true);
+                let mut output = Output::Vgm { start: cpu.timestamp(), waited: 0, commands: Vec::new() };
+                // the APU comes up powered off in players, so it's set up like the game left it
+                add_vgm_writes(&mut output, &cpu.apu_setup_writes());
+                output
+            }
+        };
+
+        println!("Recording audio to {}", path);
+        Ok(Recorder { path, output })
+    }
+
+    /// Adds samples the main core produced (WAV only).
+    pub fn add_audio(&mut self, samples: &[[f32; 2]]) -> Result<(), String> {
+        if let Output::Wav { file, frames, .. } = &mut self.output {
+            for sample in samples {
+                for channel in sample {
+                    let value = (channel.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                    file.write_all(&value.to_le_bytes()).map_err(|e| format!("Unable to write {}: {}", self.path, e))?;
+                }
+            }
+            *frames += samples.len() as u32;
+        }
+        Ok(())
+    }
+
+    /// Adds the APU writes logged by cpu since the last call (VGM only).
+    pub fn add_apu_writes(&mut self, cpu: &mut Cpu) {
+        let writes = cpu.take_apu_writes();
+        add_vgm_writes(&mut self.output, &writes);
+    }
+
+    /// Ends the recording up to now and finishes the file; returns its path.
+    pub fn finish(mut self, cpu: &mut Cpu) -> Result<String, String> {
+        self.add_apu_writes(cpu);
+        let result = match &mut self.output {
+            Output::Wav { file, sample_rate, frames } => {
+                file.seek(SeekFrom::Start(0))
+                    .and_then(|_| write_wav_header(file, *sample_rate, *frames))
+                    .and_then(|_| file.flush())
+            }
+            Output::Vgm { start, waited, commands } => {
+                cpu.set_apu_write_log(false);
+                vgm_wait(commands, waited, vgm_samples(*start, cpu.timestamp()));
+                commands.push(0x66);
+                File::create(&self.path).and_then(|mut file| {
+                    file.write_all(&vgm_header(commands.len(), *waited))?;
+                    file.write_all(commands)
+                })
+            }
+        };
+        result.map_err(|e| format!("Unable to write {}: {}", self.path, e))?;
+        println!("Saved recording to {}", self.path);
+        Ok(self.path)
+    }
+}
+
+fn write_wav_header(file: &mut impl Write, sample_rate: u32, frames: u32) -> std::io::Result<()> {
+    let data_size = frames * 4;
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_size).to_le_bytes())?;
+    file.write_all(b"WAVEfmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    // PCM, 2 channels, 16 bits
+    file.write_all(&1u16.to_le_bytes())?;
+    file.write_all(&2u16.to_le_bytes())?;
+    file.write_all(&sample_rate.to_le_bytes())?;
+    file.write_all(&(sample_rate * 4).to_le_bytes())?;
+    file.write_all(&4u16.to_le_bytes())?;
+    file.write_all(&16u16.to_le_bytes())?;
+    file.write_all(b"data")?;
+    file.write_all(&data_size.to_le_bytes())
+}
+
+fn add_vgm_writes(output: &mut Output, writes: &[ApuWrite]) {
+    if let Output::Vgm { start, waited, commands } = output {
+        for write in writes {
+            vgm_wait(commands, waited, vgm_samples(*start, write.time));
+            // write to a Game Boy register, numbered from NR10
+            commands.extend_from_slice(&[0xB3, (write.addr - 0xFF10) as u8, write.value]);
+        }
+    }
+}
+
+/// Returns how many VGM samples into a recording started at start time is.
+fn vgm_samples(start: Timestamp, time: Timestamp) -> u64 {
+    time.t_cycles().saturating_sub(start.t_cycles()) * VGM_SAMPLE_RATE / T_CYCLE_HZ as u64
+}
+
+/// Adds commands that wait until until VGM samples into the recording.
+fn vgm_wait(commands: &mut Vec<u8>, waited: &mut u64, until: u64) {
+    while *waited < until {
+        let samples = (until - *waited).min(u16::MAX as u64);
+        if samples <= 16 {
+            commands.push(0x70 + samples as u8 - 1);
+        } else {
+            commands.push(0x61);
+            commands.extend_from_slice(&(samples as u16).to_le_bytes());
+        }
+        *waited += samples;
+    }
+}
+
+/// Returns a VGM 1.71 header for data_size bytes of commands lasting total_samples.
+fn vgm_header(data_size: usize, total_samples: u64) -> Vec<u8> {
+    let mut header = vec![0; VGM_HEADER_SIZE];
+    let mut put = |offset: usize, value: u32| header[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    put(0x04, (VGM_HEADER_SIZE + data_size - 4) as u32);
+    put(0x08, 0x171);
+    put(0x18, total_samples as u32);
+    // commands start right after the header (offsets are relative to their own field)
+    put(0x34, (VGM_HEADER_SIZE - 0x34) as u32);
+    put(0x80, T_CYCLE_HZ);
+    header[..4].copy_from_slice(b"Vgm ");
+    header
+}
@@ -0,0 +1,337 @@
+use std::str::FromStr;
+
+use gbemulib::constants::BYTES_PER_PIXEL;
+use gbemulib::filters::ColourLut;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, GLProfile, Window, WindowContext};
+use sdl2::Sdl;
+
+use crate::shaders::{GlRenderer, Shader};
+
+const PIXEL_FORMAT: PixelFormatEnum = PixelFormatEnum::ARGB8888;
+
+/// Presents emulator frames on screen; a window holds one screen per running core, side by side.
+pub trait Renderer {
+    /// Draws frame (bytes in the PPU's frame buffer order, of the given width and height) into
+    /// the given screen, stretching it to fit (frames may have been scaled up by filters). It's
+    /// shown on the next present.
+    fn draw_frame(&mut self, screen: usize, frame: &[u8], size: (usize, usize)) -> Result<(), String>;
+
+    /// Shows every screen as last drawn, waiting for the display's vblank if the window was
+    /// built with vsync.
+    fn present(&mut self);
+
+    /// Returns where in the window (in its coordinates) the given screen's frames are drawn,
+    /// or None if nothing's been drawn there.
+    fn frame_area(&self, screen: usize) -> Option<Rect>;
+
+    /// Switches the window between full screen and windowed.
+    fn toggle_fullscreen(&mut self);
+
+    /// Switches between stretching frames over their screens and keeping their aspect ratio;
+    /// returns whether they're stretched now.
+    fn toggle_stretch(&mut self) -> bool;
+
+    /// Shows a status message (e.g. a warning) alongside the game title.
+    fn show_status(&mut self, status: &str);
+}
+
+/// Which SDL renderer draws the window, selected via --renderer (see RenderOptions::shader for
+/// drawing through OpenGL instead).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RendererKind {
+    /// SDL's software renderer; works without any GPU drivers.
+    Software,
+    /// Lets the GPU scale frames.
+    Gpu,
+}
+
+impl FromStr for RendererKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "software" => Ok(RendererKind::Software),
+            "gpu" => Ok(RendererKind::Gpu),
+            _ => Err(format!("Unknown renderer '{}' (expected 'software' or 'gpu')", s)),
+        }
+    }
+}
+
+/// How frames are filtered as they're scaled up to the screen, selected via --scale-filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScaleFilter {
+    /// Keeps pixels sharp (they're uneven sizes unless scaled by a whole number).
+    Nearest,
+    /// Blends neighbouring pixels, which smooths uneven scaling at the cost of sharpness.
+    Linear,
+}
+
+impl FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(ScaleFilter::Nearest),
+            "linear" => Ok(ScaleFilter::Linear),
+            _ => Err(format!("Unknown scale filter '{}' (expected 'nearest' or 'linear')", s)),
+        }
+    }
+}
+
+/// How the window's drawn.
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub kind: RendererKind,
+    pub filter: ScaleFilter,
+    /// Scales frames by the largest whole number that fits their screen (centred, with black
+    /// borders) instead of by however much fits.
+    pub integer_scale: bool,
+    /// Stretches frames over their whole screen instead of keeping their aspect ratio (unless
+    /// integer_scale).
+    pub stretch: bool,
+    /// Starts the window full screen.
+    pub fullscreen: bool,
+    /// Draws frames through OpenGL with this post-processing shader (see shaders::GlRenderer)
+    /// instead of through an SDL renderer; needs kind to be Gpu.
+    pub shader: Option<Shader>,
+    /// Grades colours with this LUT in the shader (ignored without one).
+    pub lut: Option<ColourLut>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            kind: RendererKind::Gpu,
+            filter: ScaleFilter::Nearest,
+            integer_scale: false,
+            stretch: false,
+            fullscreen: false,
+            shader: None,
+            lut: None,
+        }
+    }
+}
+
+/// Creates a resizable window fitting screens side by side (each showing frames of frame_size,
+/// see Cpu::display_size, scaled up by scale to begin with) and the renderer drawing into it
+/// as options say, presenting in step with the display's vblank if vsync.
+pub fn build_renderer(
+    sdl_context: &Sdl,
+    options: RenderOptions,
+    vsync: bool,
+    scale: u32,
+    screens: u32,
+    frame_size: (usize, usize),
+    title: &str
+) -> Result<Box<dyn Renderer>, String> {
+    if options.shader.is_some() && options.kind == RendererKind::Software {
+        return Err(String::from("Shaders need the GPU renderer (--renderer gpu)"));
+    }
+    let video_subsystem = sdl_context.video()?;
+    let (width, height) = frame_size;
+    let window_width = width as u32 * scale * screens;
+    let window_height = height as u32 * scale;
+
+    let mut window_builder = video_subsystem.window("Gameboy Emulator", window_width, window_height);
+    window_builder.position_centered().resizable();
+    if options.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    if options.kind == RendererKind::Gpu {
+        window_builder.opengl();
+    }
+    if options.shader.is_some() {
+        let gl_attr = video_subsystem.gl_attr();
+        gl_attr.set_context_profile(GLProfile::Core);
+        gl_attr.set_context_version(3, 3);
+    }
+    let mut window = window_builder.build().map_err(|e| e.to_string())?;
+    if let Err(e) = window.set_minimum_size(width as u32 * screens, height as u32) {
+        eprintln!("Unable to set the window's minimum size: {}", e);
+    }
+
+    println!("Created window of width {} and height {}", window_width, window_height);
+
+    let title = format!("MelonBoy | Playing: {}", title);
+    if let Some(shader) = options.shader {
+        window.set_title(&title).unwrap();
+        let scaling = (options.integer_scale, options.stretch);
+        let renderer = GlRenderer::new(&video_subsystem, window, shader, options.lut.as_ref(), options.filter, vsync, screens, title, scaling)?;
+        return Ok(Box::new(renderer));
+    }
+
+    let mut canvas_builder = window.into_canvas();
+    if vsync {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let mut canvas = match options.kind {
+        RendererKind::Software => canvas_builder.software(),
+        RendererKind::Gpu => canvas_builder.accelerated(),
+    }.build().map_err(|e| e.to_string())?;
+
+    // applies to textures created from here on
+    let scale_quality = match options.filter {
+        ScaleFilter::Nearest => "nearest",
+        ScaleFilter::Linear => "linear",
+    };
+    if !sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", scale_quality) {
+        eprintln!("Unable to set the scale filter to {}", scale_quality);
+    }
+
+    canvas.window_mut().set_title(&title).unwrap();
+
+    let creator = canvas.texture_creator();
+    Ok(Box::new(TextureRenderer {
+        canvas,
+        creator,
+        textures: Vec::new(),
+        title,
+        screens,
+        integer_scale: options.integer_scale,
+        stretch: options.stretch,
+    }))
+}
+
+/// Returns the part of a window of window_size that the given screen (out of screens side by
+/// side) takes up.
+pub fn screen_rect(screen: usize, screens: u32, (width, height): (u32, u32)) -> Rect {
+    let width = (width / screens).max(1);
+    Rect::new((screen as u32 * width) as i32, 0, width, height.max(1))
+}
+
+/// Returns where in screen a frame of frame_size is drawn: scaled by the largest whole number
+/// that fits (at least 1) if integer_scale, otherwise stretched over all of it if stretch or
+/// scaled as far as its aspect ratio allows, and centred.
+pub fn frame_rect(screen: Rect, (width, height): (u32, u32), integer_scale: bool, stretch: bool) -> Rect {
+    let (frame_width, frame_height) = if integer_scale {
+        let factor = (screen.width() / width).min(screen.height() / height).max(1);
+        (width * factor, height * factor)
+    } else if stretch {
+        return screen;
+    } else if screen.width() as u64 * height as u64 > screen.height() as u64 * width as u64 {
+        // wider than the frame, so it's bordered left and right
+        ((screen.height() as u64 * width as u64 / height as u64) as u32, screen.height())
+    } else {
+        (screen.width(), (screen.width() as u64 * height as u64 / width as u64) as u32)
+    };
+    let mut rect = Rect::new(0, 0, frame_width.max(1), frame_height.max(1));
+    rect.center_on(screen.center());
+    rect
+}
+
+/// Streams each screen's frames into a texture, which the renderer scales up in one copy.
+pub struct TextureRenderer {
+    canvas: Canvas<Window>,
+    creator: TextureCreator<WindowContext>,
+    // one streaming texture per screen, (re)created when the size of frames drawn changes
+    textures: Vec<Option<Texture>>,
+    title: String,
+    // how many screens the window's split into, side by side
+    screens: u32,
+    integer_scale: bool,
+    stretch: bool,
+}
+
+impl TextureRenderer {
+    fn window_size(&self) -> (u32, u32) {
+        self.canvas.output_size().unwrap_or_else(|_| self.canvas.window().size())
+    }
+}
+
+impl Renderer for TextureRenderer {
+    fn draw_frame(&mut self, screen: usize, frame: &[u8], (width, height): (usize, usize)) -> Result<(), String> {
+        while self.textures.len() <= screen {
+            self.textures.push(None);
+        }
+
+        let size_changed = self.textures[screen].as_ref().is_none_or(|texture| {
+            let query = texture.query();
+            (query.width, query.height) != (width as u32, height as u32)
+        });
+        if size_changed {
+            let texture = self.creator
+                .create_texture_streaming(PIXEL_FORMAT, width as u32, height as u32)
+                .map_err(|e| e.to_string())?;
+            self.textures[screen] = Some(texture);
+        }
+
+        let texture = self.textures[screen].as_mut().unwrap();
+        texture
+            .update(None, frame, width * BYTES_PER_PIXEL)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn present(&mut self) {
+        // what's left in the back buffer after presenting is undefined, so every screen is copied again
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        for (screen, texture) in self.textures.iter().enumerate() {
+            if let (Some(texture), Some(rect)) = (texture, self.frame_area(screen)) {
+                if let Err(e) = self.canvas.copy(texture, None, rect) {
+                    eprintln!("Unable to draw screen {}: {}", screen, e);
+                }
+            }
+        }
+        self.canvas.present();
+    }
+
+    fn frame_area(&self, screen: usize) -> Option<Rect> {
+        let query = self.textures.get(screen)?.as_ref()?.query();
+        let screen_rect = screen_rect(screen, self.screens, self.window_size());
+        Some(frame_rect(screen_rect, (query.width, query.height), self.integer_scale, self.stretch))
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let fullscreen = match self.canvas.window().fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(e) = self.canvas.window_mut().set_fullscreen(fullscreen) {
+            eprintln!("Unable to switch full screen: {}", e);
+        }
+    }
+
+    fn toggle_stretch(&mut self) -> bool {
+        self.stretch = !self.stretch;
+        self.stretch
+    }
+
+    fn show_status(&mut self, status: &str) {
+        set_status_title(self.canvas.window_mut(), &self.title, status);
+    }
+}
+
+pub fn set_status_title(window: &mut Window, title: &str, status: &str) {
+    let title = format!("{} | {}", title, status);
+    if let Err(e) = window.set_title(&title) {
+        eprintln!("Unable to set window title: {}", e);
+    }
+}
+
+/// Draws nothing, for running without a window (e.g. music player mode).
+pub struct NoRenderer;
+
+impl Renderer for NoRenderer {
+    fn draw_frame(&mut self, _screen: usize, _frame: &[u8], _size: (usize, usize)) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn present(&mut self) {}
+
+    fn frame_area(&self, _screen: usize) -> Option<Rect> {
+        None
+    }
+
+    fn toggle_fullscreen(&mut self) {}
+
+    fn toggle_stretch(&mut self) -> bool {
+        false
+    }
+
+    // statuses are already printed to the terminal by whoever shows them
+    fn show_status(&mut self, _status: &str) {}
+}
@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+
+use crate::cpu::Cpu;
+
+/// How snapshots are taken at a given speed: every interval frames, compressed at level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Granularity {
+    pub interval: u32,
+    pub level: u8,
+}
+
+/// Snapshots of the main core (see Cpu::save_state) taken as it runs, compressed and kept
+/// within a memory budget, the oldest dropped first; rewinding loads them back newest first.
+///
+/// At normal speed a snapshot is taken every `normal.interval` frames. While fast-forwarding,
+/// snapshots would pile up as fast as the core runs, so the interval grows with the speed
+/// measured (keeping about as many snapshots per second of wall time as at normal speed) and
+/// they're compressed harder, as there's more time to spare per snapshot; once back at normal
+/// speed, snapshots are taken every `normal.interval` frames again.
+pub struct Rewind {
+    snapshots: VecDeque<Vec<u8>>,
+    // compressed bytes held in snapshots, which are kept within budget
+    size: usize,
+    budget: usize,
+    normal: Granularity,
+    fast_level: u8,
+    // frames since the last snapshot
+    frames: u32,
+    // when the last snapshot was taken, and the main core's emulated time then,
+    // to measure the speed while fast-forwarding
+    last_snapshot: Option<(Instant, Duration)>,
+}
+
+impl Rewind {
+    pub fn new(budget: usize, normal: Granularity, fast_level: u8) -> Self {
+        Rewind {
+            snapshots: VecDeque::new(),
+            size: 0,
+            budget,
+            normal,
+            fast_level,
+            frames: 0,
+            last_snapshot: None,
+        }
+    }
+
+    /// Returns how snapshots are taken at speed (emulated time over wall time) while
+    /// fast-forwarding, or at normal speed otherwise.
+    fn granularity(&self, fast_forward: bool, speed: f64) -> Granularity {
+        if !fast_forward || speed < 1.5 {
+            return self.normal;
+        }
+        Granularity {
+            interval: (self.normal.interval as f64 * speed).round() as u32,
+            level: self.fast_level,
+        }
+    }
+
+    /// Counts a frame of cpu's, taking a snapshot once enough frames passed for the speed
+    /// (fast_forward tells whether frames are being paced).
+    pub fn end_frame(&mut self, cpu: &Cpu, fast_forward: bool) {
+        self.frames += 1;
+        let now = (Instant::now(), cpu.timestamp().as_duration());
+        let speed = match self.last_snapshot {
+            Some((instant, emulated)) => {
+                now.1.saturating_sub(emulated).as_secs_f64() / now.0.duration_since(instant).as_secs_f64().max(1e-3)
+            },
+            None => 1.0,
+        };
+        let granularity = self.granularity(fast_forward, speed);
+        if self.frames < granularity.interval {
+            return;
+        }
+
+        self.frames = 0;
+        self.last_snapshot = Some(now);
+        let snapshot = compress_to_vec(&cpu.save_state(), granularity.level);
+        self.size += snapshot.len();
+        self.snapshots.push_back(snapshot);
+        while self.size > self.budget {
+            match self.snapshots.pop_front() {
+                Some(oldest) => self.size -= oldest.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Loads the newest snapshot into cpu, dropping it; returns false if there's none left.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> Result<bool, String> {
+        let snapshot = match self.snapshots.pop_back() {
+            Some(snapshot) => snapshot,
+            None => return Ok(false),
+        };
+        self.size -= snapshot.len();
+        // frames run after rewinding count towards the next snapshot from scratch
+        self.frames = 0;
+        self.last_snapshot = None;
+
+        let state = decompress_to_vec(&snapshot).map_err(|e| format!("Rewind snapshot is corrupted: {:?}", e))?;
+        cpu.load_state(&state).map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+
+    /// Drops every snapshot, e.g. once the core's been reset and they're of another run.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.size = 0;
+        self.frames = 0;
+        self.last_snapshot = None;
+    }
+}
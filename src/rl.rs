@@ -0,0 +1,166 @@
+//! A Gym-style environment for reinforcement learning (behind the `rl` feature): every step
+//! holds down an action's buttons for a few frames and returns what the agent observes,
+//! along with whether the episode is over.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::cartridge::CartridgeError;
+use crate::cpu::GBModel;
+use crate::gameboy::{Buttons, GameBoy};
+
+// tells whether the game has reached the end of an episode (e.g. a game over screen)
+type DoneCheck = Box<dyn Fn(&GameBoy) -> bool>;
+
+/// What the agent gets to see after every step.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Observe {
+    /// The screen as BGRA bytes (see Frame).
+    Pixels,
+    /// The bytes at these addresses (e.g. the player's position and the score in WRAM),
+    /// in the order given.
+    Ram(Vec<u16>),
+}
+
+/// A Game Boy running one ROM in episodes. Episodes start from power on, so they're
+/// the same every time for games that don't read the clock.
+pub struct Env {
+    rom: Vec<u8>,
+    model: Option<GBModel>,
+    observe: Observe,
+    // frames each action is held for
+    frame_skip: u32,
+    // frames after which an episode is over no matter what
+    max_frames: Option<u32>,
+    done: Option<DoneCheck>,
+    gameboy: GameBoy,
+    frames: u32,
+}
+
+impl Env {
+    pub fn new(rom: &[u8], observe: Observe) -> Result<Self, CartridgeError> {
+        Ok(Env {
+            rom: rom.to_vec(),
+            model: None,
+            observe,
+            frame_skip: 1,
+            max_frames: None,
+            done: None,
+            gameboy: GameBoy::builder().rom(rom).build()?,
+            frames: 0,
+        })
+    }
+
+    /// Runs the game on model (see GameBoyBuilder::model) from the next reset() on.
+    pub fn with_model(mut self, model: GBModel) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Holds every action for frames frames (at least 1, the default).
+    pub fn with_frame_skip(mut self, frames: u32) -> Self {
+        self.frame_skip = frames.max(1);
+        self
+    }
+
+    /// Ends episodes after frames frames.
+    pub fn with_max_frames(mut self, frames: u32) -> Self {
+        self.max_frames = Some(frames);
+        self
+    }
+
+    /// Ends episodes once done returns true, checked after every step; it can look at
+    /// memory through gameboy.peek().
+    pub fn with_done(mut self, done: impl Fn(&GameBoy) -> bool + 'static) -> Self {
+        self.done = Some(Box::new(done));
+        self
+    }
+
+    /// Powers the Game Boy back on for a new episode and returns the first observation.
+    pub fn reset(&mut self) -> Vec<u8> {
+        let mut builder = GameBoy::builder().rom(&self.rom);
+        if let Some(model) = self.model {
+            builder = builder.model(model);
+        }
+        self.gameboy = builder.build().expect("ROM already loaded once");
+        self.frames = 0;
+        self.observation()
+    }
+
+    /// Holds down action's buttons (and only those) for the frame skip, then returns the
+    /// observation and whether the episode is over (after which reset() starts another).
+    pub fn step(&mut self, action: Buttons) -> (Vec<u8>, bool) {
+        self.gameboy.set_buttons(action);
+        for _ in 0..self.frame_skip {
+            self.gameboy.run_frame();
+            self.frames += 1;
+        }
+
+        let timed_out = self.max_frames.is_some_and(|max_frames| self.frames >= max_frames);
+        let done = timed_out || self.done.as_ref().is_some_and(|done| done(&self.gameboy));
+        (self.observation(), done)
+    }
+
+    /// Returns how many frames the current episode has run for.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Gives access to the Game Boy, e.g. to work out rewards from memory.
+    pub fn gameboy(&self) -> &GameBoy {
+        &self.gameboy
+    }
+
+    fn observation(&self) -> Vec<u8> {
+        match &self.observe {
+            Observe::Pixels => self.gameboy.screen().pixels.clone(),
+            Observe::Ram(addrs) => addrs.iter().map(|&addr| self.gameboy.peek(addr)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Env, Observe};
+    use crate::constants::{LCD_HEIGHT, LCD_WIDTH};
+    use crate::cpu::test_helpers::make_test_rom;
+    use crate::gameboy::{Button, Buttons};
+
+    //       LD A, $20
+    //       LDH (P1), A     ; selects the d-pad
+    // .loop LDH A, (P1)     ; RIGHT is bit 0 (0 while held)
+    //       LD ($C000), A
+    //       LD HL, $C001
+    //       INC (HL)        ; counts how often the loop ran
+    //       JR .loop
+    const COUNTER_PROGRAM: [u8; 15] = [
+        0x3E, 0x20, 0xE0, 0x00,
+        0xF0, 0x00, 0xEA, 0x00, 0xC0, 0x21, 0x01, 0xC0, 0x34, 0x18, 0xF5,
+    ];
+
+    #[test]
+    fn env_test() {
+        let rom = make_test_rom(&COUNTER_PROGRAM);
+        let mut env = Env::new(&rom, Observe::Ram(vec![0xC000, 0xC001])).unwrap()
+            .with_frame_skip(2)
+            .with_max_frames(10);
+
+        let first = env.reset();
+        for step in 1..=5 {
+            let (observation, done) = env.step(Buttons::from(Button::Right));
+            assert_eq!(env.frames(), 2 * step);
+            // the button is latched at the VBlank ending the first frame
+            assert_eq!(observation[0] & 0x01, 0);
+            assert_eq!(done, step == 5);
+        }
+
+        // episodes start over from power on
+        assert_eq!(env.reset(), first);
+        assert_eq!(env.frames(), 0);
+
+        let mut env = Env::new(&rom, Observe::Pixels).unwrap().with_done(|gameboy| gameboy.peek(0xC001) >= 0x80);
+        env.reset();
+        let (observation, _) = env.step(Buttons::NONE);
+        assert_eq!(observation.len(), LCD_WIDTH * LCD_HEIGHT * 4);
+        assert!((0..1000).any(|_| env.step(Buttons::NONE).1));
+    }
+}
@@ -0,0 +1,330 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::cpu::GBModel;
+
+// starts every save state, followed by its version
+const STATE_MAGIC: &[u8; 4] = b"MGSS";
+// bumped whenever what a component saves changes, as older states would load wrongly
+const STATE_VERSION: u8 = 1;
+
+/// Why a save state couldn't be loaded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateError {
+    /// It isn't a save state.
+    NotAState,
+    /// It's from a version of the core that saved something else.
+    UnsupportedVersion(u8),
+    /// It was saved from another game (whose ROM has this CRC-32), or on another model.
+    WrongGame(u32),
+    WrongModel,
+    /// It ends before everything's been read.
+    Truncated,
+    /// It holds something no core could be in (which part of it).
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "Save state version {} isn't supported", version),
+            StateError::WrongGame(hash) => write!(f, "Save state is from another game (ROM hash {:08X})", hash),
+            StateError::WrongModel => write!(f, "Save state is from another model"),
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::Invalid(what) => write!(f, "Save state holds an invalid {}", what),
+        }
+    }
+}
+
+/// Part of the core that save states capture: save() appends its state to a StateWriter and
+/// load() reads it back, in the same order, from a StateReader. Only what the game can
+/// observe is saved; settings (palettes, audio rate...) and what's plugged in (link cables,
+/// clock sources, battery files) stay as they are in the core loading the state.
+pub trait Snapshot {
+    fn save(&self, state: &mut StateWriter);
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+/// Implements Snapshot for a struct by saving and loading the given fields in order.
+macro_rules! snapshot_fields {
+    ($type:ty, $($field:ident),+ $(,)?) => {
+        impl $crate::savestate::Snapshot for $type {
+            fn save(&self, state: &mut $crate::savestate::StateWriter) {
+                $($crate::savestate::Snapshot::save(&self.$field, state);)+
+            }
+
+            fn load(&mut self, state: &mut $crate::savestate::StateReader) -> Result<(), $crate::savestate::StateError> {
+                $($crate::savestate::Snapshot::load(&mut self.$field, state)?;)+
+                Ok(())
+            }
+        }
+    };
+}
+pub(crate) use snapshot_fields;
+
+/// Builds a save state (see Cpu::save_state).
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Starts a state of the game whose ROM has rom_hash (see Cartridge::rom_hash), on model.
+    pub fn new(rom_hash: u32, model: GBModel) -> Self {
+        let mut state = StateWriter { data: Vec::new() };
+        state.write(STATE_MAGIC);
+        STATE_VERSION.save(&mut state);
+        rom_hash.save(&mut state);
+        model_id(model).save(&mut state);
+        state
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads a save state back (see Cpu::load_state).
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Starts reading data, checking it's a state of the game whose ROM has rom_hash, on model.
+    pub fn new(data: &'a [u8], rom_hash: u32, model: GBModel) -> Result<Self, StateError> {
+        let mut state = StateReader { data };
+        if state.read(STATE_MAGIC.len()).ok() != Some(STATE_MAGIC) {
+            return Err(StateError::NotAState);
+        }
+        let version = state.u8()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let hash = state.u32()?;
+        if hash != rom_hash {
+            return Err(StateError::WrongGame(hash));
+        }
+        if state.u8()? != model_id(model) {
+            return Err(StateError::WrongModel);
+        }
+        Ok(state)
+    }
+
+    pub fn read(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if len > self.data.len() {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.read(4)?.try_into().unwrap()))
+    }
+
+    /// Reads the length of a slice, which can't be longer than what's left to read.
+    fn len(&mut self) -> Result<usize, StateError> {
+        let len = self.u32()? as usize;
+        if len > self.data.len() {
+            return Err(StateError::Truncated);
+        }
+        Ok(len)
+    }
+
+    /// Checks everything was read, as a state with anything left over isn't one this core saved.
+    pub fn finish(self) -> Result<(), StateError> {
+        match self.data.is_empty() {
+            true => Ok(()),
+            false => Err(StateError::Invalid("size")),
+        }
+    }
+}
+
+fn model_id(model: GBModel) -> u8 {
+    match model {
+        GBModel::DMG => 0,
+        GBModel::CGB => 1,
+        GBModel::SGB => 2,
+        GBModel::AGB => 3,
+    }
+}
+
+macro_rules! snapshot_number {
+    ($($type:ty),+) => {
+        $(impl Snapshot for $type {
+            fn save(&self, state: &mut StateWriter) {
+                state.write(&self.to_le_bytes());
+            }
+
+            fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+                *self = <$type>::from_le_bytes(state.read(core::mem::size_of::<$type>())?.try_into().unwrap());
+                Ok(())
+            }
+        })+
+    };
+}
+snapshot_number!(u8, u16, u32, u64, i32, i64, f32, f64);
+
+// saved as 64 bits whatever the platform, so states move between them
+impl Snapshot for usize {
+    fn save(&self, state: &mut StateWriter) {
+        (*self as u64).save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut value = 0u64;
+        value.load(state)?;
+        *self = usize::try_from(value).map_err(|_| StateError::Invalid("size"))?;
+        Ok(())
+    }
+}
+
+impl Snapshot for bool {
+    fn save(&self, state: &mut StateWriter) {
+        (*self as u8).save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        *self = match state.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(StateError::Invalid("flag")),
+        };
+        Ok(())
+    }
+}
+
+impl<T: Snapshot, const N: usize> Snapshot for [T; N] {
+    fn save(&self, state: &mut StateWriter) {
+        self.as_slice().save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.as_mut_slice().load(state)
+    }
+}
+
+// slices keep their length, which has to match when loading (e.g. cartridge RAM banks)
+impl<T: Snapshot> Snapshot for [T] {
+    fn save(&self, state: &mut StateWriter) {
+        (self.len() as u32).save(state);
+        self.iter().for_each(|item| item.save(state));
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if state.len()? != self.len() {
+            return Err(StateError::Invalid("memory size"));
+        }
+        self.iter_mut().try_for_each(|item| item.load(state))
+    }
+}
+
+// unlike slices, vectors take the length they were saved with
+impl<T: Snapshot + Default> Snapshot for Vec<T> {
+    fn save(&self, state: &mut StateWriter) {
+        self.as_slice().save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let len = state.len()?;
+        self.clear();
+        self.resize_with(len, T::default);
+        self.iter_mut().try_for_each(|item| item.load(state))
+    }
+}
+
+impl<T: Snapshot + Default> Snapshot for Option<T> {
+    fn save(&self, state: &mut StateWriter) {
+        self.is_some().save(state);
+        if let Some(value) = self {
+            value.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut some = false;
+        some.load(state)?;
+        match some {
+            true => self.get_or_insert_with(T::default).load(state),
+            false => {
+                *self = None;
+                Ok(())
+            },
+        }
+    }
+}
+
+impl<A: Snapshot, B: Snapshot> Snapshot for (A, B) {
+    fn save(&self, state: &mut StateWriter) {
+        self.0.save(state);
+        self.1.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.0.load(state)?;
+        self.1.load(state)
+    }
+}
+
+impl Snapshot for String {
+    fn save(&self, state: &mut StateWriter) {
+        self.as_bytes().save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let len = state.len()?;
+        let text = core::str::from_utf8(state.read(len)?).map_err(|_| StateError::Invalid("text"))?;
+        self.clear();
+        self.push_str(text);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Snapshot, StateError, StateReader, StateWriter};
+    use crate::cpu::GBModel;
+
+    #[test]
+    fn state_round_trip_test() {
+        let mut state = StateWriter::new(0x1234, GBModel::DMG);
+        (0xABu8, 0x1234_5678u32).save(&mut state);
+        [true, false].save(&mut state);
+        vec![1u16, 2, 3].save(&mut state);
+        Some(usize::MAX >> 1).save(&mut state);
+        let data = state.finish();
+
+        let (mut pair, mut flags, mut list, mut size) = ((0u8, 0u32), [false; 2], Vec::<u16>::new(), None::<usize>);
+        let mut state = StateReader::new(&data, 0x1234, GBModel::DMG).unwrap();
+        pair.load(&mut state).unwrap();
+        flags.load(&mut state).unwrap();
+        list.load(&mut state).unwrap();
+        size.load(&mut state).unwrap();
+        state.finish().unwrap();
+        assert_eq!((pair, flags, &list, size), ((0xAB, 0x1234_5678), [true, false], &vec![1, 2, 3], Some(usize::MAX >> 1)));
+
+        // states of other games or models don't load
+        assert_eq!(StateReader::new(&data, 0x4321, GBModel::DMG).err(), Some(StateError::WrongGame(0x1234)));
+        assert_eq!(StateReader::new(&data, 0x1234, GBModel::CGB).err(), Some(StateError::WrongModel));
+        assert_eq!(StateReader::new(b"MGS", 0x1234, GBModel::DMG).err(), Some(StateError::NotAState));
+
+        // nor do arrays of another size, or truncated states
+        let mut state = StateReader::new(&data, 0x1234, GBModel::DMG).unwrap();
+        pair.load(&mut state).unwrap();
+        assert_eq!([false; 3].load(&mut state), Err(StateError::Invalid("memory size")));
+        let mut state = StateReader::new(&data[..data.len() - 4], 0x1234, GBModel::DMG).unwrap();
+        pair.load(&mut state).unwrap();
+        flags.load(&mut state).unwrap();
+        list.load(&mut state).unwrap();
+        assert_eq!(size.load(&mut state), Err(StateError::Truncated));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::constants::BYTES_PER_PIXEL;
+
+/// Encodes frame (BGRA bytes, as the PPU draws them) of the given width and height as a PNG.
+/// With paletted (for DMG games, whose frames only have a few shades), it's an indexed PNG of
+/// the frame's colours, packed as tightly as they allow; otherwise, or if the frame has more
+/// than 256 colours (e.g. an SGB border), it's RGB.
+pub fn encode_png(frame: &[u8], (width, height): (usize, usize), paletted: bool) -> Result<Vec<u8>, String> {
+    let pixels: Vec<[u8; 3]> = frame.chunks_exact(BYTES_PER_PIXEL)
+        .map(|pixel| [pixel[2], pixel[1], pixel[0]])
+        .collect();
+    if pixels.len() != width * height || pixels.is_empty() {
+        return Err(format!("Expected a {}x{} frame, got {} pixels", width, height, pixels.len()));
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+    let data = match paletted.then(|| colours(&pixels)).flatten() {
+        Some(palette) => {
+            let depth = match palette.len() {
+                0..=2 => png::BitDepth::One,
+                3..=4 => png::BitDepth::Two,
+                5..=16 => png::BitDepth::Four,
+                _ => png::BitDepth::Eight,
+            };
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_depth(depth);
+            encoder.set_palette(palette.concat());
+            let indices: Vec<u8> = pixels.iter()
+                .map(|pixel| palette.iter().position(|colour| colour == pixel).unwrap() as u8)
+                .collect();
+            pack(&indices, width, depth as usize)
+        },
+        None => {
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            pixels.concat()
+        },
+    };
+
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+/// Returns the file name for a screenshot of the game titled title taken at timestamp
+/// (milliseconds since the Unix epoch), e.g. "TETRIS-1700000000000.png".
+pub fn file_name(title: &str, timestamp: u128) -> String {
+    let title: String = title.trim().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    let title = if title.is_empty() { "screenshot" } else { &title };
+    format!("{}-{}.png", title, timestamp)
+}
+
+/// Returns every colour in pixels in the order they first show up, or None if there are
+/// more than an indexed PNG can hold.
+fn colours(pixels: &[[u8; 3]]) -> Option<Vec<[u8; 3]>> {
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    for pixel in pixels {
+        if !palette.contains(pixel) {
+            if palette.len() == 256 {
+                return None;
+            }
+            palette.push(*pixel);
+        }
+    }
+    Some(palette)
+}
+
+/// Packs indices (width to a row) into rows of depth bits per pixel, as PNG lays them out.
+fn pack(indices: &[u8], width: usize, depth: usize) -> Vec<u8> {
+    let per_byte = 8 / depth;
+    indices.chunks_exact(width)
+        .flat_map(|row| row.chunks(per_byte).map(|pixels| {
+            pixels.iter().enumerate()
+                .fold(0, |byte, (i, &index)| byte | index << (8 - depth * (i + 1)))
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_png, file_name};
+
+    fn decode(png: &[u8]) -> (png::OutputInfo, Vec<u8>) {
+        let mut decoder = png::Decoder::new(png);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        data.truncate(info.buffer_size());
+        (info, data)
+    }
+
+    #[test]
+    fn screenshot_test() {
+        // 3x2 BGRA, in four shades
+        let shades = [[0x0F, 0xBC, 0x9B], [0x0F, 0xAC, 0x8B], [0x30, 0x62, 0x30], [0x0F, 0x38, 0x0F]];
+        let frame: Vec<u8> = [shades[3], shades[2], shades[1], shades[0], shades[0], shades[3]].iter()
+            .flat_map(|&[b, g, r]| [b, g, r, 0xFF])
+            .collect();
+        let rgb: Vec<u8> = frame.chunks_exact(4).flat_map(|pixel| [pixel[2], pixel[1], pixel[0]]).collect();
+
+        let paletted = encode_png(&frame, (3, 2), true).unwrap();
+        let (info, data) = decode(&paletted);
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(png::Decoder::new(paletted.as_slice()).read_info().unwrap().info().bit_depth, png::BitDepth::Two);
+        assert_eq!(data, rgb);
+
+        let (info, data) = decode(&encode_png(&frame, (3, 2), false).unwrap());
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(data, rgb);
+
+        assert!(encode_png(&frame, (4, 2), true).is_err());
+        assert_eq!(file_name("POKEMON RED", 42), "POKEMON_RED-42.png");
+        assert_eq!(file_name("", 42), "screenshot-42.png");
+    }
+}
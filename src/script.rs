@@ -0,0 +1,214 @@
+use std::cell::RefCell;
+use std::fs::read_to_string;
+use std::rc::Rc;
+
+use mlua::{Function, Lua, Table};
+
+use gbemulib::constants::BYTES_PER_PIXEL;
+
+use crate::cpu::Cpu;
+
+// in the same order as emulator::KEYMAPPINGS
+const BUTTON_NAMES: [&str; 8] = ["start", "select", "b", "a", "down", "up", "left", "right"];
+
+// where the hooks the script registers are dispatched from (see PRELUDE)
+const DISPATCH_KEY: &str = "melon_gb_dispatch";
+
+// keeps the script's callbacks in Lua tables, so only calling them needs the Rust side
+const PRELUDE: &str = r#"
+local hooks = { frame = {}, read = {}, write = {} }
+
+local function add_hook(list, addr, callback)
+    list[addr] = list[addr] or {}
+    table.insert(list[addr], callback)
+end
+
+function emu.on_frame(callback) table.insert(hooks.frame, callback) end
+function emu.on_read(addr, callback) add_hook(hooks.read, addr, callback) end
+function emu.on_write(addr, callback) add_hook(hooks.write, addr, callback) end
+
+return {
+    frame = function()
+        for _, callback in ipairs(hooks.frame) do callback() end
+    end,
+    access = function(addr, value, write)
+        local list = write and hooks.write or hooks.read
+        for _, callback in ipairs(list[addr] or {}) do callback(addr, value) end
+    end,
+    watched = function()
+        local addrs = {}
+        for addr in pairs(hooks.read) do addrs[addr] = true end
+        for addr in pairs(hooks.write) do addrs[addr] = true end
+        local list = {}
+        for addr in pairs(addrs) do table.insert(list, addr) end
+        return list
+    end,
+}
+"#;
+
+/// What the script's functions leave for the emulator.
+#[derive(Default)]
+struct ScriptState {
+    // buttons the script holds down, as set bits at their places in the emulator's key status
+    held: u8,
+    // rectangles drawn over frames until the next frame's callbacks run:
+    // (x, y, width, height, BGRA colour)
+    rects: Vec<(usize, usize, usize, usize, [u8; 4])>,
+    // text to show next to the title
+    status: Option<String>,
+    frames: u64,
+}
+
+/// A Lua script run alongside the main core, BizHawk-style, for bots, auto-splitters and
+/// debugging overlays. Scripts get an `emu` table with:
+/// - `emu.on_frame(f)`: calls f() at every VBlank
+/// - `emu.on_read(addr, f)`, `emu.on_write(addr, f)`: calls f(addr, value) right after the CPU
+///   reads or writes addr
+/// - `emu.peek(addr)`, `emu.poke(addr, value)`: see Cpu::peek and Cpu::poke
+/// - `emu.hold(button)`, `emu.release(button)`: holds buttons ("a", "b", "start", "select",
+///   "up", "down", "left" or "right") down on top of the keyboard's until released
+/// - `emu.rect(x, y, width, height, 0xRRGGBB)`: draws over the next frame shown
+/// - `emu.status(text)`: shows text next to the title
+/// - `emu.frame_count()`: the frames since the script was loaded
+pub struct Script {
+    lua: Lua,
+    state: Rc<RefCell<ScriptState>>,
+}
+
+impl Script {
+    /// Loads the script at path and runs it against cpu (registering its hooks).
+    pub fn load(path: &str, cpu: &mut Cpu) -> Result<Self, String> {
+        let source = read_to_string(path).map_err(|e| format!("Unable to read script {}: {}", path, e))?;
+        let script = Script::new().map_err(|e| e.to_string())?;
+        script.with_cpu(cpu, |lua| lua.load(&source).set_name(path).exec())?;
+        script.sync_watches(cpu)?;
+        Ok(script)
+    }
+
+    fn new() -> mlua::Result<Self> {
+        let lua = Lua::new();
+        let state = Rc::new(RefCell::new(ScriptState::default()));
+        let emu = lua.create_table()?;
+
+        let held = Rc::clone(&state);
+        emu.set("hold", lua.create_function(move |_, button: String| {
+            held.borrow_mut().held |= button_mask(&button)?;
+            Ok(())
+        })?)?;
+        let released = Rc::clone(&state);
+        emu.set("release", lua.create_function(move |_, button: String| {
+            released.borrow_mut().held &= !button_mask(&button)?;
+            Ok(())
+        })?)?;
+        let rects = Rc::clone(&state);
+        emu.set("rect", lua.create_function(move |_, (x, y, width, height, colour): (usize, usize, usize, usize, u32)| {
+            let [_, r, g, b] = colour.to_be_bytes();
+            rects.borrow_mut().rects.push((x, y, width, height, [b, g, r, 0xFF]));
+            Ok(())
+        })?)?;
+        let status = Rc::clone(&state);
+        emu.set("status", lua.create_function(move |_, text: String| {
+            status.borrow_mut().status = Some(text);
+            Ok(())
+        })?)?;
+        let frames = Rc::clone(&state);
+        emu.set("frame_count", lua.create_function(move |_, ()| Ok(frames.borrow().frames))?)?;
+        lua.globals().set("emu", emu)?;
+
+        let dispatch: Table = lua.load(PRELUDE).set_name("prelude").eval()?;
+        lua.set_named_registry_value(DISPATCH_KEY, dispatch)?;
+        Ok(Script { lua, state })
+    }
+
+    /// Runs the script's frame callbacks; call at every VBlank.
+    pub fn on_frame(&mut self, cpu: &mut Cpu) -> Result<(), String> {
+        {
+            let mut state = self.state.borrow_mut();
+            state.frames += 1;
+            state.rects.clear();
+        }
+        self.with_cpu(cpu, |lua| dispatch(lua, "frame")?.call(()))?;
+        self.sync_watches(cpu)
+    }
+
+    /// Runs the script's read and write callbacks for the accesses cpu made since this was
+    /// last called; call after every step.
+    pub fn on_memory_accesses(&mut self, cpu: &mut Cpu) -> Result<(), String> {
+        let accesses = cpu.take_memory_accesses();
+        if accesses.is_empty() {
+            return Ok(());
+        }
+        self.with_cpu(cpu, |lua| {
+            let access = dispatch(lua, "access")?;
+            for access_made in accesses {
+                access.call::<_, ()>((access_made.addr, access_made.value, access_made.write))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the buttons the script holds down, as set bits at their places in the
+    /// emulator's key status.
+    pub fn held_buttons(&self) -> u8 {
+        self.state.borrow().held
+    }
+
+    pub fn take_status(&mut self) -> Option<String> {
+        self.state.borrow_mut().status.take()
+    }
+
+    /// Returns frame (in the PPU's frame buffer order, of the given width and height) with
+    /// what the script drew over it, or None if it didn't draw anything.
+    pub fn draw_overlay(&self, frame: &[u8], (width, height): (usize, usize)) -> Option<Vec<u8>> {
+        let state = self.state.borrow();
+        if state.rects.is_empty() {
+            return None;
+        }
+
+        let mut overlaid = frame.to_vec();
+        for &(x, y, rect_width, rect_height, colour) in &state.rects {
+            for py in y..(y + rect_height).min(height) {
+                for px in x..(x + rect_width).min(width) {
+                    let i = (py * width + px) * BYTES_PER_PIXEL;
+                    overlaid[i..i + BYTES_PER_PIXEL].copy_from_slice(&colour);
+                }
+            }
+        }
+        Some(overlaid)
+    }
+
+    /// Runs run with emu.peek and emu.poke pointed at cpu.
+    fn with_cpu(&self, cpu: &mut Cpu, run: impl FnOnce(&Lua) -> mlua::Result<()>) -> Result<(), String> {
+        let cpu = RefCell::new(cpu);
+        self.lua.scope(|scope| {
+            let emu: Table = self.lua.globals().get("emu")?;
+            emu.set("peek", scope.create_function(|_, addr: u16| Ok(cpu.borrow().peek(addr)))?)?;
+            emu.set("poke", scope.create_function(|_, (addr, value): (u16, u8)| Ok(cpu.borrow_mut().poke(addr, value)))?)?;
+            run(&self.lua)
+        }).map_err(|e| e.to_string())
+    }
+
+    /// Has cpu record accesses to every address the script has read or write hooks on.
+    fn sync_watches(&self, cpu: &mut Cpu) -> Result<(), String> {
+        let watched: Vec<u16> = dispatch(&self.lua, "watched")
+            .and_then(|watched| watched.call(()))
+            .map_err(|e| e.to_string())?;
+        for addr in watched {
+            cpu.watch_memory(addr, true);
+        }
+        Ok(())
+    }
+}
+
+fn dispatch<'lua>(lua: &'lua Lua, name: &str) -> mlua::Result<Function<'lua>> {
+    lua.named_registry_value::<Table>(DISPATCH_KEY)?.get(name)
+}
+
+/// Returns button's bit in the emulator's key status.
+fn button_mask(button: &str) -> mlua::Result<u8> {
+    let name = button.to_ascii_lowercase();
+    match BUTTON_NAMES.iter().position(|&button_name| button_name == name) {
+        Some(i) => Ok(1 << (7 - i)),
+        None => Err(mlua::Error::RuntimeError(format!("Unknown button '{}' (expected one of {})", button, BUTTON_NAMES.join(", ")))),
+    }
+}
@@ -0,0 +1,369 @@
+use alloc::{boxed::Box, collections::VecDeque, rc::Rc};
+use core::cell::RefCell;
+
+use crate::cpu::GBModel;
+use crate::events::CoreEvent;
+use crate::timer::Stepper;
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+// internal clock runs at 8192 Hz, or 262144 Hz with the CGB's high speed SC bit 1 set;
+// both are measured in CPU T-cycles, so they double along with the CPU in double speed mode
+const T_CYCLES_PER_BIT: u32 = 512;
+const FAST_T_CYCLES_PER_BIT: u32 = 16;
+
+// with no link partner, the serial input line stays high
+const DISCONNECTED_BIT: u8 = 1;
+
+/// The other end of the link cable; transfers are exchanged a byte at a time.
+pub trait SerialLink {
+    /// Called when this side finishes an internally clocked transfer of byte; returns the byte
+    /// the other side shifted back, or None if it wasn't waiting on a transfer.
+    fn clock_transfer(&mut self, byte: u8) -> Option<u8>;
+
+    /// Tells the other side whether this one is waiting on an externally clocked transfer,
+    /// and with which byte in SB.
+    fn set_waiting(&mut self, byte: Option<u8>);
+
+    /// Returns the byte the other side sent if it clocked a transfer we were waiting on.
+    fn receive(&mut self) -> Option<u8>;
+
+    /// Called whenever this side's game finishes a frame, for links that keep both sides in
+    /// step (see link::tcp::LinkMode::Lockstep).
+    fn end_frame(&mut self) {}
+
+    /// Returns the oldest thing that happened on the link (e.g. the cable dropped) that hasn't
+    /// been polled yet, for the core's event queue.
+    fn poll_event(&mut self) -> Option<CoreEvent> {
+        None
+    }
+}
+
+#[derive(Default)]
+struct CableEnd {
+    waiting: Option<u8>,
+    received: Option<u8>,
+}
+
+/// One end of a link cable between two emulators in the same process (see LinkPort::pair()).
+pub struct LinkPort {
+    cable: Rc<RefCell<[CableEnd; 2]>>,
+    side: usize,
+}
+
+impl LinkPort {
+    /// Returns both ends of a new cable, to be given to two Cpus with connect_serial().
+    pub fn pair() -> (LinkPort, LinkPort) {
+        let cable = Rc::new(RefCell::new(Default::default()));
+        (LinkPort { cable: cable.clone(), side: 0 }, LinkPort { cable, side: 1 })
+    }
+}
+
+impl SerialLink for LinkPort {
+    fn clock_transfer(&mut self, byte: u8) -> Option<u8> {
+        let other = &mut self.cable.borrow_mut()[1 - self.side];
+        let reply = other.waiting.take()?;
+        other.received = Some(byte);
+        Some(reply)
+    }
+
+    fn set_waiting(&mut self, byte: Option<u8>) {
+        self.cable.borrow_mut()[self.side].waiting = byte;
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.cable.borrow_mut()[self.side].received.take()
+    }
+}
+
+/// Makes the frontend the link partner, for PC-side tools talking to homebrew over the link port
+/// (e.g. debug consoles or asset streaming): every byte the game sends goes to on_receive, and
+/// bytes queued with HostSender::send() go back to the game, one per transfer. The game can clock
+/// transfers itself (getting 0xFF while nothing is queued) or wait for queued bytes to clock them.
+#[allow(dead_code)]
+pub struct HostLink {
+    queue: Rc<RefCell<VecDeque<u8>>>,
+    on_receive: Box<dyn FnMut(u8)>,
+    waiting: Option<u8>,
+}
+
+/// Queues bytes for the game on the other end of a HostLink.
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct HostSender(Rc<RefCell<VecDeque<u8>>>);
+
+#[allow(dead_code)]
+impl HostLink {
+    pub fn new(on_receive: Box<dyn FnMut(u8)>) -> (HostLink, HostSender) {
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        (HostLink { queue: queue.clone(), on_receive, waiting: None }, HostSender(queue))
+    }
+}
+
+#[allow(dead_code)]
+impl HostSender {
+    pub fn send(&self, byte: u8) {
+        self.0.borrow_mut().push_back(byte);
+    }
+}
+
+impl SerialLink for HostLink {
+    fn clock_transfer(&mut self, byte: u8) -> Option<u8> {
+        (self.on_receive)(byte);
+        self.queue.borrow_mut().pop_front()
+    }
+
+    fn set_waiting(&mut self, byte: Option<u8>) {
+        self.waiting = byte;
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.waiting?;
+        let byte = self.queue.borrow_mut().pop_front()?;
+        (self.on_receive)(self.waiting.take()?);
+        Some(byte)
+    }
+}
+
+pub struct Serial {
+    model: GBModel,
+    sb: u8,
+    sc: u8,
+    bits_left: u8,
+    bit_stepper: Stepper,
+    // SB when the current internally clocked transfer started
+    outgoing: u8,
+    link: Option<Box<dyn SerialLink>>,
+}
+
+impl Serial {
+    pub fn new(model: GBModel) -> Self {
+        Serial {
+            model,
+            sb: 0,
+            sc: 0,
+            bits_left: 0,
+            bit_stepper: Stepper::new(0, T_CYCLES_PER_BIT),
+            outgoing: 0,
+            link: None,
+        }
+    }
+
+    /// Plugs in the link cable; with none, transfers shift in 1s and external clocks never tick.
+    pub fn connect(&mut self, link: Box<dyn SerialLink>) {
+        self.link = Some(link);
+        self.update_waiting();
+    }
+
+    /// Shifts out bits of an internally clocked transfer over the given period (in T-cycles),
+    /// or picks up a transfer clocked by the link partner;
+    /// returns true if a transfer finished (which requests a serial interrupt).
+    pub fn step(&mut self, t_cycles: u32) -> bool {
+        if !self.transferring() {
+            return false;
+        }
+
+        if self.sc & 0x01 == 0 {
+            return match self.link.as_mut().and_then(|link| link.receive()) {
+                Some(byte) => {
+                    self.sb = byte;
+                    self.sc &= 0x7F;
+                    true
+                },
+                None => false,
+            };
+        }
+
+        let bits = self.bit_stepper.step(t_cycles);
+        for _ in 0..bits {
+            self.sb = (self.sb << 1) | DISCONNECTED_BIT;
+            self.bits_left -= 1;
+
+            if self.bits_left == 0 {
+                if let Some(byte) = self.link.as_mut().and_then(|link| link.clock_transfer(self.outgoing)) {
+                    self.sb = byte;
+                }
+                self.sc &= 0x7F;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn read_io(&self, addr: usize) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            0xFF02 => match self.model {
+                GBModel::DMG | GBModel::SGB => 0x7E | self.sc,
+                GBModel::CGB | GBModel::AGB => 0x7C | self.sc,
+            },
+            _ => unreachable!()
+        }
+    }
+
+    pub fn write_io(&mut self, addr: usize, byte: u8) {
+        match addr {
+            0xFF01 => self.sb = byte,
+            0xFF02 => {
+                self.sc = match self.model {
+                    GBModel::DMG | GBModel::SGB => byte & 0x81,
+                    GBModel::CGB | GBModel::AGB => byte & 0x83,
+                };
+
+                if self.transferring() {
+                    self.bits_left = 8;
+                    self.bit_stepper = Stepper::new(0, self.t_cycles_per_bit());
+                    self.outgoing = self.sb;
+                }
+            },
+            _ => unreachable!()
+        }
+        self.update_waiting();
+    }
+
+    /// Lets the link cable know the game finished a frame (see SerialLink::end_frame).
+    pub fn end_frame(&mut self) {
+        if let Some(link) = &mut self.link {
+            link.end_frame();
+        }
+    }
+
+    /// Returns the link cable's oldest event that hasn't been polled yet (see SerialLink::poll_event).
+    pub fn poll_event(&mut self) -> Option<CoreEvent> {
+        self.link.as_mut()?.poll_event()
+    }
+
+    /// Lets the link partner know if we're waiting on it to clock a transfer.
+    fn update_waiting(&mut self) {
+        let waiting = (self.transferring() && self.sc & 0x01 == 0).then_some(self.sb);
+        if let Some(link) = &mut self.link {
+            link.set_waiting(waiting);
+        }
+    }
+
+    fn transferring(&self) -> bool {
+        self.sc & 0x80 != 0
+    }
+
+    fn t_cycles_per_bit(&self) -> u32 {
+        if self.sc & 0x02 != 0 {
+            FAST_T_CYCLES_PER_BIT
+        } else {
+            T_CYCLES_PER_BIT
+        }
+    }
+}
+
+impl Snapshot for Serial {
+    fn save(&self, state: &mut StateWriter) {
+        self.sb.save(state);
+        self.sc.save(state);
+        self.bits_left.save(state);
+        self.bit_stepper.save(state);
+        self.outgoing.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.sb.load(state)?;
+        self.sc.load(state)?;
+        self.bits_left.load(state)?;
+        self.bit_stepper.load(state)?;
+        self.outgoing.load(state)?;
+        // the link partner hears whether the game's waiting on it now
+        self.update_waiting();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{HostLink, LinkPort, Serial, FAST_T_CYCLES_PER_BIT, T_CYCLES_PER_BIT};
+    use crate::cartridge::Cartridge;
+    use crate::cpu::{test_helpers::make_test_rom, Cpu, GBModel};
+
+    fn cycles_until_done(serial: &mut Serial) -> u32 {
+        let mut cycles = 0;
+        while !serial.step(4) {
+            cycles += 4;
+        }
+        cycles + 4
+    }
+
+    #[test]
+    fn serial_clock_rates_test() {
+        let mut serial = Serial::new(GBModel::DMG);
+        serial.write_io(0xFF01, 0x42);
+        serial.write_io(0xFF02, 0x83);
+        assert_eq!(cycles_until_done(&mut serial), 8 * T_CYCLES_PER_BIT);
+        assert_eq!(serial.read_io(0xFF01), 0xFF);
+        assert_eq!(serial.read_io(0xFF02), 0x7F);
+
+        let mut serial = Serial::new(GBModel::CGB);
+        serial.write_io(0xFF02, 0x83);
+        assert_eq!(cycles_until_done(&mut serial), 8 * FAST_T_CYCLES_PER_BIT);
+        assert_eq!(serial.read_io(0xFF02), 0x7F);
+    }
+
+    #[test]
+    fn serial_external_clock_test() {
+        let mut serial = Serial::new(GBModel::CGB);
+        serial.write_io(0xFF02, 0x80);
+        for _ in 0..1000 {
+            assert!(!serial.step(T_CYCLES_PER_BIT));
+        }
+    }
+
+    #[test]
+    fn serial_link_test() {
+        // LD A,byte; LDH (SB),A; LD A,sc; LDH (SC),A; JR -2
+        let make_cpu = |byte: u8, sc: u8| {
+            let rom = make_test_rom(&[0x3E, byte, 0xE0, 0x01, 0x3E, sc, 0xE0, 0x02, 0x18, 0xFE]);
+            Cpu::new(Cartridge::from_bytes(&rom).unwrap(), GBModel::DMG)
+        };
+        let mut master = make_cpu(0x11, 0x81);
+        let mut slave = make_cpu(0x22, 0x80);
+
+        let (port_a, port_b) = LinkPort::pair();
+        master.connect_serial(Box::new(port_a));
+        slave.connect_serial(Box::new(port_b));
+
+        for _ in 0..2000 {
+            master.step();
+            slave.step();
+        }
+
+        assert_eq!(master.read_byte(0xFF01), 0x22);
+        assert_eq!(slave.read_byte(0xFF01), 0x11);
+        assert_eq!(master.read_byte(0xFF02) & 0x80, 0);
+        assert_eq!(slave.read_byte(0xFF02) & 0x80, 0);
+        assert_ne!(master.read_byte(0xFF0F) & 0x08, 0);
+        assert_ne!(slave.read_byte(0xFF0F) & 0x08, 0);
+    }
+
+    #[test]
+    fn host_link_test() {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+        let (link, sender) = HostLink::new(Box::new(move |byte| sink.borrow_mut().push(byte)));
+        let mut serial = Serial::new(GBModel::DMG);
+        serial.connect(Box::new(link));
+
+        // the game clocks a transfer with nothing queued
+        serial.write_io(0xFF01, 0x11);
+        serial.write_io(0xFF02, 0x81);
+        cycles_until_done(&mut serial);
+        assert_eq!(serial.read_io(0xFF01), 0xFF);
+
+        // the game waits until a byte gets queued
+        serial.write_io(0xFF01, 0x22);
+        serial.write_io(0xFF02, 0x80);
+        assert!(!serial.step(4));
+        sender.send(0x33);
+        assert!(serial.step(4));
+        assert_eq!(serial.read_io(0xFF01), 0x33);
+        assert_eq!(*received.borrow(), [0x11, 0x22]);
+    }
+}
@@ -0,0 +1,626 @@
+use alloc::{vec, vec::Vec};
+use crate::constants::{BYTES_PER_PIXEL, LCD_HEIGHT, LCD_WIDTH};
+use crate::savestate::{snapshot_fields, Snapshot, StateError, StateReader, StateWriter};
+
+/// Size of the picture the SNES outputs: the game's LCD framed by the border.
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+
+// where the LCD sits within the border
+pub const LCD_X: usize = 48;
+pub const LCD_Y: usize = 40;
+
+const PACKET_SIZE: usize = 16;
+const PACKET_BITS: usize = PACKET_SIZE * 8;
+
+// attributes (palette numbers) are set per 8x8 cell of the LCD
+const ATTR_WIDTH: usize = LCD_WIDTH / 8;
+const ATTR_HEIGHT: usize = LCD_HEIGHT / 8;
+const ATTR_FILE_SIZE: usize = ATTR_WIDTH * ATTR_HEIGHT / 4;
+const ATTR_FILES: usize = 45;
+
+// every VRAM transfer sends 256 tiles' worth of data, read off the screen
+const TRANSFER_SIZE: usize = 0x1000;
+const SYSTEM_PALETTES: usize = 512;
+
+// border tiles are in the SNES's 4bpp format
+const BORDER_TILE_SIZE: usize = 32;
+const BORDER_TILES: usize = 256;
+const BORDER_MAP_WIDTH: usize = 32;
+const BORDER_MAP_SIZE: usize = 0x800;
+
+// command numbers (bits 3-7 of a command's first byte)
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+const MLT_REQ: u8 = 0x11;
+
+// RGB555, until the game sends its own palettes
+const DEFAULT_PALETTE: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+/// Data the game is sending through the screen, taken from the next frame drawn.
+#[derive(Clone, Copy, Default)]
+enum Transfer {
+    #[default]
+    SystemPalettes,
+    // which half of the border's tiles
+    BorderTiles(usize),
+    Border,
+    AttributeFiles,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mask {
+    None,
+    // keeps showing the last frame
+    Freeze,
+    Black,
+    Colour0,
+}
+
+/// Super Game Boy: receives command packets the game sends over the joypad lines and
+/// turns frames (as shades) into what the SNES shows: colorized with the game's palettes
+/// and framed by its border. NOTE: the SGB BIOS's own border isn't included, so games
+/// without a border of their own show a blank one. Sound commands are ignored.
+///
+/// With MLT_REQ, the game can read 2 or 4 controllers through P1: the SGB moves on to the
+/// next one every time P15 goes high (outside of packets), see Joypad::select_player.
+pub struct Sgb {
+    // P14/P15 as last written
+    lines: u8,
+    receiving: bool,
+    bits: usize,
+    packet: [u8; PACKET_SIZE],
+    // packets of the command being received
+    command: Vec<u8>,
+    packets_left: usize,
+    // controllers enabled with MLT_REQ (1, 2 or 4) and the one P1 reads
+    players: usize,
+    current_player: usize,
+
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<u16>,
+    // palette number of every cell
+    attributes: [u8; ATTR_WIDTH * ATTR_HEIGHT],
+    attribute_files: Vec<u8>,
+    mask: Mask,
+    transfer: Option<Transfer>,
+
+    border_tiles: Vec<u8>,
+    border_map: Vec<u8>,
+    // palettes 4-7, used by the border
+    border_palettes: [[u16; 16]; 4],
+    border_changed: bool,
+
+    frame: Vec<u8>,
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Sgb {
+            lines: 0x30,
+            receiving: false,
+            bits: 0,
+            packet: [0; PACKET_SIZE],
+            command: Vec::new(),
+            packets_left: 0,
+            players: 1,
+            current_player: 0,
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![0; SYSTEM_PALETTES * 4],
+            attributes: [0; ATTR_WIDTH * ATTR_HEIGHT],
+            attribute_files: vec![0; ATTR_FILES * ATTR_FILE_SIZE],
+            mask: Mask::None,
+            transfer: None,
+            border_tiles: vec![0; BORDER_TILES * BORDER_TILE_SIZE],
+            border_map: vec![0; BORDER_MAP_SIZE],
+            border_palettes: [[0; 16]; 4],
+            border_changed: true,
+            frame: vec![0; SGB_WIDTH * SGB_HEIGHT * BYTES_PER_PIXEL],
+        }
+    }
+
+    /// Takes in a write to P1. Pulling both lines low starts a packet, then every pulse on
+    /// P14 sends a 0 bit and every pulse on P15 a 1 bit (LSB first), with 128 bits per packet
+    /// and a stop bit after them.
+    pub fn write_p1(&mut self, byte: u8) {
+        let lines = byte & 0x30;
+        let released = self.lines == 0x30;
+        let p15_rising = self.lines & 0x20 == 0 && lines & 0x20 != 0;
+        self.lines = lines;
+
+        if p15_rising && !self.receiving && self.players > 1 {
+            self.current_player = (self.current_player + 1) % self.players;
+        }
+
+        match lines {
+            0x00 => {
+                self.receiving = true;
+                self.bits = 0;
+                self.packet = [0; PACKET_SIZE];
+            },
+            // the stop bit ends the packet
+            0x10 | 0x20 if self.receiving && released && self.bits == PACKET_BITS => self.receiving = false,
+            0x10 | 0x20 if self.receiving && released => {
+                if lines == 0x10 {
+                    self.packet[self.bits / 8] |= 1 << (self.bits % 8);
+                }
+                self.bits += 1;
+                if self.bits == PACKET_BITS {
+                    self.receive_packet();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Commands are made of 1 to 7 packets; the first byte holds the command number and packet count.
+    fn receive_packet(&mut self) {
+        if self.packets_left == 0 {
+            self.command.clear();
+            self.packets_left = (self.packet[0] & 0x07).max(1) as usize;
+        }
+
+        self.command.extend_from_slice(&self.packet);
+        self.packets_left -= 1;
+        if self.packets_left == 0 {
+            self.run_command();
+        }
+    }
+
+    fn run_command(&mut self) {
+        let data = core::mem::take(&mut self.command);
+        match data[0] >> 3 {
+            PAL01 => self.set_palettes(&data, 0, 1),
+            PAL23 => self.set_palettes(&data, 2, 3),
+            PAL03 => self.set_palettes(&data, 0, 3),
+            PAL12 => self.set_palettes(&data, 1, 2),
+            ATTR_BLK => self.attr_blk(&data),
+            ATTR_LIN => self.attr_lin(&data),
+            ATTR_DIV => self.attr_div(&data),
+            ATTR_CHR => self.attr_chr(&data),
+            PAL_SET => {
+                for (palette, id) in data[1..9].chunks_exact(2).enumerate() {
+                    let id = (u16::from_le_bytes([id[0], id[1]]) & 0x1FF) as usize;
+                    self.palettes[palette].copy_from_slice(&self.system_palettes[id * 4..id * 4 + 4]);
+                }
+                self.share_colour_0(self.palettes[0][0]);
+                if data[9] & 0x80 != 0 {
+                    self.set_attribute_file(data[9] & 0x3F);
+                }
+                if data[9] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            },
+            PAL_TRN => self.transfer = Some(Transfer::SystemPalettes),
+            CHR_TRN => self.transfer = Some(Transfer::BorderTiles((data[1] & 0x01) as usize)),
+            PCT_TRN => self.transfer = Some(Transfer::Border),
+            ATTR_TRN => self.transfer = Some(Transfer::AttributeFiles),
+            ATTR_SET => {
+                self.set_attribute_file(data[1] & 0x3F);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            },
+            MLT_REQ => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.current_player = 0;
+            },
+            MASK_EN => {
+                self.mask = match data[1] & 0x03 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Colour0,
+                    _ => Mask::None,
+                };
+            },
+            _ => {},
+        }
+
+        self.command = data;
+    }
+
+    /// Returns the controller P1 reads and whether more than one is enabled (see MLT_REQ).
+    pub fn player(&self) -> (usize, bool) {
+        (self.current_player, self.players > 1)
+    }
+
+    /// PAL01/23/03/12: sets colours 1-3 of palettes a and b, and the colour 0 they all share.
+    fn set_palettes(&mut self, data: &[u8], a: usize, b: usize) {
+        let colour = |i: usize| u16::from_le_bytes([data[1 + 2 * i], data[2 + 2 * i]]);
+        self.share_colour_0(colour(0));
+        for i in 1..4 {
+            self.palettes[a][i] = colour(i);
+            self.palettes[b][i] = colour(i + 3);
+        }
+    }
+
+    fn share_colour_0(&mut self, colour: u16) {
+        for palette in &mut self.palettes {
+            palette[0] = colour;
+        }
+        // the backdrop behind the border is colour 0 too
+        self.border_changed = true;
+    }
+
+    /// ATTR_BLK: colours the inside, border and outside of rectangles of cells.
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for block in data[2..].chunks_exact(6).take(count) {
+            let control = block[0] & 0x07;
+            let (inside, edge, outside) = (block[1] & 0x03, (block[1] >> 2) & 0x03, (block[1] >> 4) & 0x03);
+            // with only the inside or outside set, the border goes along with it
+            let edge = match control {
+                0x01 => Some(inside),
+                0x04 => Some(outside),
+                control if control & 0x02 != 0 => Some(edge),
+                _ => None,
+            };
+            let inside = (control & 0x01 != 0).then_some(inside);
+            let outside = (control & 0x04 != 0).then_some(outside);
+            let (x1, y1, x2, y2) = (block[2] & 0x1F, block[3] & 0x1F, block[4] & 0x1F, block[5] & 0x1F);
+
+            for y in 0..ATTR_HEIGHT as u8 {
+                for x in 0..ATTR_WIDTH as u8 {
+                    let palette = if x > x1 && x < x2 && y > y1 && y < y2 {
+                        inside
+                    } else if x >= x1 && x <= x2 && y >= y1 && y <= y2 {
+                        edge
+                    } else {
+                        outside
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y as usize * ATTR_WIDTH + x as usize] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    /// ATTR_LIN: colours whole rows or columns of cells.
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &line in data[2..].iter().take(count) {
+            let (number, palette) = ((line & 0x1F) as usize, (line >> 5) & 0x03);
+            if line & 0x80 != 0 {
+                if number < ATTR_HEIGHT {
+                    self.attributes[number * ATTR_WIDTH..(number + 1) * ATTR_WIDTH].fill(palette);
+                }
+            } else if number < ATTR_WIDTH {
+                for y in 0..ATTR_HEIGHT {
+                    self.attributes[y * ATTR_WIDTH + number] = palette;
+                }
+            }
+        }
+    }
+
+    /// ATTR_DIV: splits the screen in two along a row or column of cells.
+    fn attr_div(&mut self, data: &[u8]) {
+        let (after, before, on) = (data[1] & 0x03, (data[1] >> 2) & 0x03, (data[1] >> 4) & 0x03);
+        let horizontal = data[1] & 0x40 != 0;
+        let line = (data[2] & 0x1F) as usize;
+
+        for y in 0..ATTR_HEIGHT {
+            for x in 0..ATTR_WIDTH {
+                let position = if horizontal { y } else { x };
+                self.attributes[y * ATTR_WIDTH + x] = match position.cmp(&line) {
+                    core::cmp::Ordering::Less => before,
+                    core::cmp::Ordering::Equal => on,
+                    core::cmp::Ordering::Greater => after,
+                };
+            }
+        }
+    }
+
+    /// ATTR_CHR: colours cells one by one from a starting cell, 4 per byte (MSB first).
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+        let vertical = data[5] & 0x01 != 0;
+
+        for i in 0..count.min((data.len() - 6) * 4) {
+            if x >= ATTR_WIDTH || y >= ATTR_HEIGHT {
+                break;
+            }
+            self.attributes[y * ATTR_WIDTH + x] = (data[6 + i / 4] >> (6 - 2 * (i % 4))) & 0x03;
+
+            if vertical {
+                y += 1;
+                if y == ATTR_HEIGHT {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == ATTR_WIDTH {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    fn set_attribute_file(&mut self, file: u8) {
+        let file = file as usize;
+        if file >= ATTR_FILES {
+            return;
+        }
+
+        let bytes = &self.attribute_files[file * ATTR_FILE_SIZE..(file + 1) * ATTR_FILE_SIZE];
+        for (i, attribute) in self.attributes.iter_mut().enumerate() {
+            *attribute = (bytes[i / 4] >> (6 - 2 * (i % 4))) & 0x03;
+        }
+    }
+
+    /// Finishes a VRAM transfer with data read off shades, the frame showing it: the first
+    /// 256 tiles on screen (left to right, top to bottom) turned back into 2bpp tile data.
+    fn receive_transfer(&mut self, transfer: Transfer, shades: &[u8]) {
+        let mut data = vec![0; TRANSFER_SIZE];
+        for (tile, bytes) in data.chunks_exact_mut(16).enumerate() {
+            let (tile_x, tile_y) = (tile % ATTR_WIDTH * 8, tile / ATTR_WIDTH * 8);
+            for row in 0..8 {
+                for x in 0..8 {
+                    let shade = shades[(tile_y + row) * LCD_WIDTH + tile_x + x];
+                    bytes[row * 2] |= (shade & 0x01) << (7 - x);
+                    bytes[row * 2 + 1] |= ((shade >> 1) & 0x01) << (7 - x);
+                }
+            }
+        }
+
+        match transfer {
+            Transfer::SystemPalettes => {
+                for (colour, bytes) in self.system_palettes.iter_mut().zip(data.chunks_exact(2)) {
+                    *colour = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+            },
+            Transfer::BorderTiles(half) => {
+                let start = half * TRANSFER_SIZE;
+                self.border_tiles[start..start + TRANSFER_SIZE].copy_from_slice(&data);
+                self.border_changed = true;
+            },
+            Transfer::Border => {
+                self.border_map.copy_from_slice(&data[..BORDER_MAP_SIZE]);
+                for (i, bytes) in data[BORDER_MAP_SIZE..BORDER_MAP_SIZE + 128].chunks_exact(2).enumerate() {
+                    self.border_palettes[i / 16][i % 16] = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+                self.border_changed = true;
+            },
+            Transfer::AttributeFiles => {
+                let size = self.attribute_files.len();
+                self.attribute_files.copy_from_slice(&data[..size]);
+            },
+        }
+    }
+
+    /// Draws the border (32x28 tiles), with colour 0 of every border palette showing the backdrop.
+    fn draw_border(&mut self) {
+        let backdrop = rgb555_to_bgra(self.palettes[0][0]);
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                let i = ((y / 8) * BORDER_MAP_WIDTH + x / 8) * 2;
+                let entry = u16::from_le_bytes([self.border_map[i], self.border_map[i + 1]]);
+                let tile = (entry & 0xFF) as usize * BORDER_TILE_SIZE;
+                let palette = ((entry >> 10) & 0x03) as usize;
+                let row = if entry & 0x8000 != 0 { 7 - y % 8 } else { y % 8 };
+                let bit = if entry & 0x4000 != 0 { x % 8 } else { 7 - x % 8 };
+
+                let colour = [0, 1, 16, 17].iter().enumerate().fold(0, |colour, (plane, offset)| {
+                    colour | ((self.border_tiles[tile + row * 2 + offset] >> bit) & 0x01) << plane
+                });
+                let pixel = match colour {
+                    0 => backdrop,
+                    colour => rgb555_to_bgra(self.border_palettes[palette][colour as usize]),
+                };
+                let start = (y * SGB_WIDTH + x) * BYTES_PER_PIXEL;
+                self.frame[start..start + BYTES_PER_PIXEL].copy_from_slice(&pixel);
+            }
+        }
+    }
+
+    /// Returns the picture for a finished frame, given its shades (0-3 for every LCD pixel).
+    pub fn update_frame(&mut self, shades: &[u8]) -> &[u8] {
+        if let Some(transfer) = self.transfer.take() {
+            self.receive_transfer(transfer, shades);
+        }
+        if self.border_changed {
+            self.border_changed = false;
+            self.draw_border();
+        }
+
+        if self.mask != Mask::Freeze {
+            let palettes = self.palettes.map(|palette| palette.map(rgb555_to_bgra));
+            for y in 0..LCD_HEIGHT {
+                for x in 0..LCD_WIDTH {
+                    let pixel = match self.mask {
+                        Mask::Black => [0x00, 0x00, 0x00, 0xFF],
+                        Mask::Colour0 => palettes[0][0],
+                        _ => {
+                            let palette = self.attributes[(y / 8) * ATTR_WIDTH + x / 8] as usize;
+                            palettes[palette][shades[y * LCD_WIDTH + x] as usize]
+                        },
+                    };
+                    let start = ((LCD_Y + y) * SGB_WIDTH + LCD_X + x) * BYTES_PER_PIXEL;
+                    self.frame[start..start + BYTES_PER_PIXEL].copy_from_slice(&pixel);
+                }
+            }
+        }
+
+        &self.frame
+    }
+}
+
+/// The SNES outputs to a TV, so unlike the CGB's LCD, no colour correction is needed.
+fn rgb555_to_bgra(colour: u16) -> [u8; BYTES_PER_PIXEL] {
+    let channel = |shift: u16| (((colour >> shift) & 0x1F) * 255 / 31) as u8;
+    [channel(10), channel(5), channel(0), 0xFF]
+}
+
+snapshot_fields!(Sgb,
+    lines, receiving, bits, packet, command, packets_left, players, current_player,
+    palettes, system_palettes, attributes, attribute_files, mask, transfer,
+    border_tiles, border_map, border_palettes, border_changed, frame,
+);
+
+impl Snapshot for Transfer {
+    fn save(&self, state: &mut StateWriter) {
+        match *self {
+            Transfer::SystemPalettes => 0u8.save(state),
+            Transfer::BorderTiles(half) => (1u8, half).save(state),
+            Transfer::Border => 2u8.save(state),
+            Transfer::AttributeFiles => 3u8.save(state),
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut transfer = 0u8;
+        transfer.load(state)?;
+        *self = match transfer {
+            0 => Transfer::SystemPalettes,
+            1 => {
+                let mut half = 0;
+                half.load(state)?;
+                Transfer::BorderTiles(half)
+            },
+            2 => Transfer::Border,
+            3 => Transfer::AttributeFiles,
+            _ => return Err(StateError::Invalid("SGB transfer")),
+        };
+        Ok(())
+    }
+}
+
+impl Snapshot for Mask {
+    fn save(&self, state: &mut StateWriter) {
+        let mask: u8 = match self {
+            Mask::None => 0,
+            Mask::Freeze => 1,
+            Mask::Black => 2,
+            Mask::Colour0 => 3,
+        };
+        mask.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut mask = 0u8;
+        mask.load(state)?;
+        *self = match mask {
+            0 => Mask::None,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Colour0,
+            _ => return Err(StateError::Invalid("SGB mask")),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rgb555_to_bgra, Sgb, LCD_X, LCD_Y, SGB_WIDTH};
+    use crate::constants::{BYTES_PER_PIXEL, LCD_HEIGHT, LCD_WIDTH};
+
+    fn send_packet(sgb: &mut Sgb, packet: [u8; 16]) {
+        sgb.write_p1(0x00);
+        sgb.write_p1(0x30);
+        for i in 0..128 {
+            let bit = (packet[i / 8] >> (i % 8)) & 0x01;
+            sgb.write_p1(if bit == 1 { 0x10 } else { 0x20 });
+            sgb.write_p1(0x30);
+        }
+        // stop bit
+        sgb.write_p1(0x20);
+        sgb.write_p1(0x30);
+    }
+
+    fn lcd_pixel(frame: &[u8], x: usize, y: usize) -> [u8; 4] {
+        let start = ((LCD_Y + y) * SGB_WIDTH + LCD_X + x) * BYTES_PER_PIXEL;
+        frame[start..start + BYTES_PER_PIXEL].try_into().unwrap()
+    }
+
+    #[test]
+    fn sgb_test() {
+        let mut sgb = Sgb::new();
+        let shades = [3; LCD_WIDTH * LCD_HEIGHT];
+
+        // PAL01: colour 0 red, palette 0 colour 3 green, palette 1 colour 3 blue
+        let mut packet = [0; 16];
+        packet[0] = 0x01;
+        packet[1..3].copy_from_slice(&0x001Fu16.to_le_bytes());
+        packet[7..9].copy_from_slice(&0x03E0u16.to_le_bytes());
+        packet[13..15].copy_from_slice(&0x7C00u16.to_le_bytes());
+        send_packet(&mut sgb, packet);
+        assert_eq!(sgb.palettes[1], [0x001F, 0, 0, 0x7C00]);
+
+        // ATTR_DIV: columns left of 10 use palette 0, column 10 and the rest palette 1
+        send_packet(&mut sgb, [0x31, 0x11, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let frame = sgb.update_frame(&shades);
+        assert_eq!(lcd_pixel(frame, 0, 0), rgb555_to_bgra(0x03E0));
+        assert_eq!(lcd_pixel(frame, 159, 143), rgb555_to_bgra(0x7C00));
+        // the blank border shows the backdrop
+        assert_eq!(frame[..4], rgb555_to_bgra(0x001F));
+
+        // ATTR_BLK: inside (and border) of cells (1,1)-(3,3) use palette 1
+        send_packet(&mut sgb, [0x21, 0x01, 0x01, 0x01, 1, 1, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(sgb.attributes[21], 1);
+        assert_eq!(sgb.attributes[84], 0);
+
+        // MASK_EN black
+        send_packet(&mut sgb, [0xB9, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(lcd_pixel(sgb.update_frame(&shades), 0, 0), [0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn sgb_multiplayer_test() {
+        let mut sgb = Sgb::new();
+        // MLT_REQ: 2 players
+        send_packet(&mut sgb, [0x89, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(sgb.player(), (0, true));
+
+        // reading the d-pad then the buttons moves on to the next controller
+        for player in [1, 0, 1] {
+            sgb.write_p1(0x20);
+            sgb.write_p1(0x10);
+            sgb.write_p1(0x30);
+            assert_eq!(sgb.player(), (player, true));
+        }
+
+        // back to 1 player, the first controller is read for good
+        send_packet(&mut sgb, [0x89, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(sgb.player(), (0, false));
+        sgb.write_p1(0x10);
+        sgb.write_p1(0x30);
+        assert_eq!(sgb.player(), (0, false));
+    }
+
+    #[test]
+    fn sgb_transfer_test() {
+        let mut sgb = Sgb::new();
+        // PAL_TRN, then a screen whose first tile's first row has shades 1,0,0,0,0,0,0,0
+        // (so the first colour of system palette 0 is 0x0080)
+        send_packet(&mut sgb, [0x59, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut shades = [0; LCD_WIDTH * LCD_HEIGHT];
+        shades[0] = 1;
+        sgb.update_frame(&shades);
+        assert_eq!(sgb.system_palettes[..2], [0x0080, 0x0000]);
+
+        // PAL_SET palette 0 to all four
+        send_packet(&mut sgb, [0x51, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(sgb.palettes[3], [0x0080, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,373 @@
+use std::ffi::CString;
+use std::ptr;
+use std::str::FromStr;
+
+use gbemulib::constants::BYTES_PER_PIXEL;
+use gbemulib::filters::ColourLut;
+use gl::types::{GLenum, GLint, GLsizei, GLuint};
+use sdl2::rect::Rect;
+use sdl2::video::{FullscreenType, GLContext, SwapInterval, Window};
+use sdl2::VideoSubsystem;
+
+use crate::renderer::{frame_rect, screen_rect, set_status_title, Renderer, ScaleFilter};
+
+/// Draws a quad covering the viewport (as a strip, its corners picked by gl_VertexID), with
+/// frames' first row at the top.
+const VERTEX_SHADER: &str = r#"
+#version 330 core
+const vec2 corners[4] = vec2[](vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(-1.0, 1.0), vec2(1.0, 1.0));
+out vec2 uv;
+
+void main() {
+    vec2 corner = corners[gl_VertexID];
+    uv = vec2(corner.x + 1.0, 1.0 - corner.y) / 2.0;
+    gl_Position = vec4(corner, 0.0, 1.0);
+}
+"#;
+
+/// Grades a frame's colours through the LUT (if any), then draws the effect (see Shader) over
+/// every frame pixel, however many of the window's pixels it covers.
+const FRAGMENT_SHADER: &str = r#"
+#version 330 core
+in vec2 uv;
+out vec4 colour;
+uniform sampler2D frame;
+uniform sampler3D lut;
+uniform bool use_lut;
+uniform int effect;
+// the frame's width and height in its own pixels
+uniform vec2 frame_size;
+
+void main() {
+    vec3 rgb = texture(frame, uv).rgb;
+    if (use_lut) {
+        // the LUT's entries sit at the centres of its texels
+        float size = float(textureSize(lut, 0).x);
+        rgb = texture(lut, rgb * ((size - 1.0) / size) + 0.5 / size).rgb;
+    }
+
+    // where in its frame pixel this fragment is, from 0 to 1 across and down
+    vec2 cell = fract(uv * frame_size);
+    if (effect == 1) {
+        // red, green then blue columns, letting a third of the others through, over a darkened row
+        vec3 mask = vec3(1.0 / 3.0);
+        mask[min(int(cell.x * 3.0), 2)] = 1.0;
+        rgb *= mask;
+        if (cell.y >= 2.0 / 3.0) {
+            rgb *= 0.5;
+        }
+    } else if (effect == 2) {
+        // a dot over the top left, with the gaps right of and below it showing the lightened backing
+        if (cell.x >= 2.0 / 3.0 || cell.y >= 2.0 / 3.0) {
+            rgb = mix(rgb, vec3(1.0), 0.75);
+        }
+    } else if (effect == 3) {
+        // the bottom half of every row of frame pixels is darkened
+        if (cell.y >= 0.5) {
+            rgb *= 191.0 / 255.0;
+        }
+    }
+    colour = vec4(rgb, 1.0);
+}
+"#;
+
+/// What the GPU renderer's fragment shader draws over frames as it scales them up, selected via
+/// --shader. Unlike the filters of the same names, these draw at the window's resolution, so
+/// they look right at any scale and cost the CPU nothing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shader {
+    /// Only scales frames up (and grades their colours, given a --lut).
+    Plain,
+    /// The subpixel columns of the CGB's LCD, with a dark gap below every row (see filters::LcdGrid).
+    LcdGrid,
+    /// The dots of the DMG's LCD over its lightened backing (see filters::DotMatrix).
+    DotMatrix,
+    /// Darkens the bottom half of every row, like a CRT's scanlines (see filters::Scanlines).
+    Scanlines,
+}
+
+impl FromStr for Shader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Shader::Plain),
+            "lcd" => Ok(Shader::LcdGrid),
+            "dotmatrix" => Ok(Shader::DotMatrix),
+            "scanlines" => Ok(Shader::Scanlines),
+            _ => Err(format!("Unknown shader '{}' (expected none, lcd, dotmatrix or scanlines)", s)),
+        }
+    }
+}
+
+/// A screen's texture, and the size of the frames last drawn into it.
+struct ScreenTexture {
+    id: GLuint,
+    size: (u32, u32),
+}
+
+/// Draws each screen's frames through OpenGL, running a post-processing shader (and colour LUT)
+/// as they're scaled up.
+pub struct GlRenderer {
+    // declared before the window so it's dropped first
+    _context: GLContext,
+    window: Window,
+    program: GLuint,
+    vertex_array: GLuint,
+    frame_size_uniform: GLint,
+    // one texture per screen, resized when the size of frames drawn changes
+    textures: Vec<Option<ScreenTexture>>,
+    lut: Option<GLuint>,
+    filter: GLenum,
+    title: String,
+    // how many screens the window's split into, side by side
+    screens: u32,
+    integer_scale: bool,
+    stretch: bool,
+}
+
+impl GlRenderer {
+    /// Creates an OpenGL 3.3 context for window (built with a core profile requested, see
+    /// build_renderer) and compiles shader, grading colours through lut if there is one.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        video_subsystem: &VideoSubsystem,
+        window: Window,
+        shader: Shader,
+        lut: Option<&ColourLut>,
+        filter: ScaleFilter,
+        vsync: bool,
+        screens: u32,
+        title: String,
+        (integer_scale, stretch): (bool, bool)
+    ) -> Result<Self, String> {
+        let context = window.gl_create_context()?;
+        gl::load_with(|name| video_subsystem.gl_get_proc_address(name) as *const _);
+        let interval = if vsync { SwapInterval::VSync } else { SwapInterval::Immediate };
+        if let Err(e) = video_subsystem.gl_set_swap_interval(interval) {
+            eprintln!("Unable to set the swap interval: {}", e);
+        }
+
+        // SAFETY: the context was just made current on this thread, and gl's functions loaded for it
+        unsafe {
+            let program = link_program(VERTEX_SHADER, FRAGMENT_SHADER)?;
+            // the quad's corners come from the vertex shader, but a core profile still needs a vertex array bound
+            let mut vertex_array = 0;
+            gl::GenVertexArrays(1, &mut vertex_array);
+            gl::BindVertexArray(vertex_array);
+
+            // the program's the only one, so it stays in use, with frames on texture unit 0 and the LUT on 1
+            gl::UseProgram(program);
+            gl::Uniform1i(uniform(program, "frame"), 0);
+            gl::Uniform1i(uniform(program, "lut"), 1);
+            gl::Uniform1i(uniform(program, "use_lut"), lut.is_some() as GLint);
+            gl::Uniform1i(uniform(program, "effect"), shader as GLint);
+            let lut = lut.map(|lut| upload_lut(lut));
+
+            Ok(GlRenderer {
+                _context: context,
+                window,
+                program,
+                vertex_array,
+                frame_size_uniform: uniform(program, "frame_size"),
+                textures: Vec::new(),
+                lut,
+                filter: match filter {
+                    ScaleFilter::Nearest => gl::NEAREST,
+                    ScaleFilter::Linear => gl::LINEAR,
+                },
+                title,
+                screens,
+                integer_scale,
+                stretch,
+            })
+        }
+    }
+}
+
+impl Renderer for GlRenderer {
+    fn draw_frame(&mut self, screen: usize, frame: &[u8], (width, height): (usize, usize)) -> Result<(), String> {
+        if frame.len() < width * height * BYTES_PER_PIXEL {
+            return Err(format!("A {}x{} frame needs {} bytes, but has {}", width, height, width * height * BYTES_PER_PIXEL, frame.len()));
+        }
+        while self.textures.len() <= screen {
+            self.textures.push(None);
+        }
+        if self.textures[screen].is_none() {
+            // SAFETY: the context stays current for as long as the renderer lives
+            let id = unsafe { create_texture(self.filter) };
+            self.textures[screen] = Some(ScreenTexture { id, size: (0, 0) });
+        }
+
+        let texture = self.textures[screen].as_mut().unwrap();
+        let size = (width as u32, height as u32);
+        // SAFETY: frame holds width * height pixels, as checked above; frames are stored B, G, R, A
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture.id);
+            let pixels = frame.as_ptr() as *const _;
+            if texture.size == size {
+                gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, width as GLsizei, height as GLsizei, gl::BGRA, gl::UNSIGNED_BYTE, pixels);
+            } else {
+                gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, width as GLsizei, height as GLsizei, 0, gl::BGRA, gl::UNSIGNED_BYTE, pixels);
+                texture.size = size;
+            }
+        }
+        Ok(())
+    }
+
+    fn present(&mut self) {
+        let (window_width, window_height) = self.window.drawable_size();
+        // SAFETY: the context stays current for as long as the renderer lives
+        unsafe {
+            // what's left in the back buffer after swapping is undefined, so every screen is drawn again
+            gl::Viewport(0, 0, window_width as GLsizei, window_height as GLsizei);
+            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            for (screen, texture) in self.textures.iter().enumerate() {
+                if let (Some(texture), Some(rect)) = (texture, self.frame_area(screen)) {
+                    // viewports count up from the window's bottom
+                    gl::Viewport(rect.x(), window_height as i32 - rect.bottom(), rect.width() as GLsizei, rect.height() as GLsizei);
+                    gl::BindTexture(gl::TEXTURE_2D, texture.id);
+                    gl::Uniform2f(self.frame_size_uniform, texture.size.0 as f32, texture.size.1 as f32);
+                    gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+                }
+            }
+        }
+        self.window.gl_swap_window();
+    }
+
+    fn frame_area(&self, screen: usize) -> Option<Rect> {
+        let texture = self.textures.get(screen)?.as_ref()?;
+        let screen_rect = screen_rect(screen, self.screens, self.window.drawable_size());
+        Some(frame_rect(screen_rect, texture.size, self.integer_scale, self.stretch))
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let fullscreen = match self.window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(e) = self.window.set_fullscreen(fullscreen) {
+            eprintln!("Unable to switch full screen: {}", e);
+        }
+    }
+
+    fn toggle_stretch(&mut self) -> bool {
+        self.stretch = !self.stretch;
+        self.stretch
+    }
+
+    fn show_status(&mut self, status: &str) {
+        set_status_title(&mut self.window, &self.title, status);
+    }
+}
+
+impl Drop for GlRenderer {
+    fn drop(&mut self) {
+        // SAFETY: the context is still current, as it's only dropped after this
+        unsafe {
+            for texture in self.textures.iter().flatten() {
+                gl::DeleteTextures(1, &texture.id);
+            }
+            if let Some(lut) = &self.lut {
+                gl::DeleteTextures(1, lut);
+            }
+            gl::DeleteVertexArrays(1, &self.vertex_array);
+            gl::DeleteProgram(self.program);
+        }
+    }
+}
+
+/// Returns the location of the program's uniform called name (-1 if it has none, which GL ignores).
+unsafe fn uniform(program: GLuint, name: &str) -> GLint {
+    let name = CString::new(name).unwrap();
+    gl::GetUniformLocation(program, name.as_ptr())
+}
+
+/// Compiles and links a program from the sources of its vertex and fragment shaders.
+unsafe fn link_program(vertex_source: &str, fragment_source: &str) -> Result<GLuint, String> {
+    let vertex = compile_shader(gl::VERTEX_SHADER, vertex_source)?;
+    let fragment = match compile_shader(gl::FRAGMENT_SHADER, fragment_source) {
+        Ok(fragment) => fragment,
+        Err(e) => {
+            gl::DeleteShader(vertex);
+            return Err(e);
+        }
+    };
+
+    let program = gl::CreateProgram();
+    gl::AttachShader(program, vertex);
+    gl::AttachShader(program, fragment);
+    gl::LinkProgram(program);
+    // the program keeps what it needs from them once linked
+    gl::DeleteShader(vertex);
+    gl::DeleteShader(fragment);
+
+    let mut linked = 0;
+    gl::GetProgramiv(program, gl::LINK_STATUS, &mut linked);
+    if linked == 0 {
+        let mut length = 0;
+        gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut length);
+        let mut log = vec![0u8; length.max(1) as usize];
+        gl::GetProgramInfoLog(program, length, ptr::null_mut(), log.as_mut_ptr() as *mut _);
+        gl::DeleteProgram(program);
+        return Err(format!("Unable to link shaders: {}", info_log(&log)));
+    }
+    Ok(program)
+}
+
+unsafe fn compile_shader(kind: GLenum, source: &str) -> Result<GLuint, String> {
+    let shader = gl::CreateShader(kind);
+    let source = CString::new(source).unwrap();
+    gl::ShaderSource(shader, 1, &source.as_ptr(), ptr::null());
+    gl::CompileShader(shader);
+
+    let mut compiled = 0;
+    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut compiled);
+    if compiled == 0 {
+        let mut length = 0;
+        gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut length);
+        let mut log = vec![0u8; length.max(1) as usize];
+        gl::GetShaderInfoLog(shader, length, ptr::null_mut(), log.as_mut_ptr() as *mut _);
+        gl::DeleteShader(shader);
+        return Err(format!("Unable to compile shader: {}", info_log(&log)));
+    }
+    Ok(shader)
+}
+
+/// Returns a (nul-terminated) info log as text.
+fn info_log(log: &[u8]) -> String {
+    let end = log.iter().position(|&byte| byte == 0).unwrap_or(log.len());
+    String::from_utf8_lossy(&log[..end]).trim().to_string()
+}
+
+/// Creates a texture for frames, scaled up with filter, and leaves it bound.
+unsafe fn create_texture(filter: GLenum) -> GLuint {
+    let mut texture = 0;
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+    texture
+}
+
+/// Uploads lut as a 3D texture (indexed by red, green and blue) on texture unit 1, which the
+/// GPU blends between entries of as ColourLut does.
+unsafe fn upload_lut(lut: &ColourLut) -> GLuint {
+    let mut texture = 0;
+    gl::GenTextures(1, &mut texture);
+    gl::ActiveTexture(gl::TEXTURE1);
+    gl::BindTexture(gl::TEXTURE_3D, texture);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    for wrap in [gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+        gl::TexParameteri(gl::TEXTURE_3D, wrap, gl::CLAMP_TO_EDGE as GLint);
+    }
+    let size = lut.size() as GLsizei;
+    gl::TexImage3D(gl::TEXTURE_3D, 0, gl::RGB32F as GLint, size, size, size, 0, gl::RGB, gl::FLOAT, lut.table().as_ptr() as *const _);
+    // frames are drawn from unit 0
+    gl::ActiveTexture(gl::TEXTURE0);
+    texture
+}
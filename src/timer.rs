@@ -1,3 +1,5 @@
+use crate::savestate::{snapshot_fields, Snapshot, StateError, StateReader, StateWriter};
+
 // div is incremented every 256 T-cycles / 64 M-cycles 
 const T_CYCLES_PER_DIV_INC: u32 = 256;
 
@@ -80,7 +82,7 @@ impl Timer {
             0xFF04 => self.div,
             0xFF05 => self.tima,
             0xFF06 => self.tma,
-            0xFF07 => self.tac,
+            0xFF07 => 0xF8 | self.tac,
             _ => unreachable!()
         }
     }
@@ -90,7 +92,7 @@ impl Timer {
             0xFF04 => self.div = 0x00,
             0xFF05 => self.tima = byte,
             0xFF06 => self.next_tma = byte as i32,
-            0xFF07 => self.tac = byte,
+            0xFF07 => self.tac = byte & 0x07,
             _ => unreachable!()
         };
     }
@@ -102,6 +104,12 @@ impl Timer {
     pub fn reset_div(&mut self) {
         self.div = 0;
     }
+
+    /// Sets DIV's whole internal counter (DIV is its upper byte).
+    pub fn set_div_counter(&mut self, counter: u16) {
+        self.div = (counter >> 8) as u8;
+        self.div_stepper = Stepper::new((counter & 0xFF) as u32, T_CYCLES_PER_DIV_INC);
+    }
 }
 
 
@@ -137,4 +145,23 @@ impl Stepper {
     pub fn set_period(&mut self, period: u32) {
         self.period = period;
     }
-}
\ No newline at end of file
+}
+
+snapshot_fields!(Timer, div, tima, tma, tac, div_stepper, tima_stepper, next_tma);
+
+impl Snapshot for Stepper {
+    fn save(&self, state: &mut StateWriter) {
+        (self.steps_so_far, self.period).save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut fields = (0, 0);
+        fields.load(state)?;
+        // a stepper that never finishes a period would hang step()
+        if fields.1 == 0 {
+            return Err(StateError::Invalid("timer period"));
+        }
+        (self.steps_so_far, self.period) = fields;
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+use std::fs::{create_dir_all, remove_file, File};
+use std::io::{BufWriter, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use gbemulib::constants::{BYTES_PER_PIXEL, T_CYCLE_HZ};
+
+use crate::pacing::FRAME_T_CYCLES;
+
+// GIFs keep one frame out of this many, as players slow down delays under 2/100ths of a second
+const GIF_FRAME_STEP: u64 = 2;
+// how much ffmpeg scales frames up (with nearest neighbour) before encoding them, so chroma
+// subsampling doesn't smear pixel art
+const FFMPEG_SCALE: usize = 4;
+
+/// What a video recording is saved as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// An animated GIF at half the frame rate, without sound; encoded in-process.
+    Gif,
+    /// VP9 (with Opus sound), encoded by piping frames to an ffmpeg on the PATH.
+    WebM,
+    /// H.264 (with AAC sound), encoded by piping frames to an ffmpeg on the PATH.
+    Mp4,
+}
+
+/// Parses `gif`, `webm` or `mp4`.
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gif" => Ok(VideoFormat::Gif),
+            "webm" => Ok(VideoFormat::WebM),
+            "mp4" => Ok(VideoFormat::Mp4),
+            _ => Err(format!("Unknown video format '{}' (expected gif, webm or mp4)", s)),
+        }
+    }
+}
+
+impl VideoFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::WebM => "webm",
+            VideoFormat::Mp4 => "mp4",
+        }
+    }
+
+    /// Returns an encoder writing frames of the given width and height (and, if the format
+    /// has sound, audio at audio_rate if given) to path.
+    pub fn encoder(self, path: &str, size: (usize, usize), audio_rate: Option<u32>) -> Result<Box<dyn VideoEncoder>, String> {
+        Ok(match self {
+            VideoFormat::Gif => Box::new(GifEncoder::new(path, size)?),
+            VideoFormat::WebM => Box::new(FfmpegEncoder::new(path, size, audio_rate, &["-c:v", "libvpx-vp9", "-crf", "20", "-b:v", "0"], "libopus")?),
+            VideoFormat::Mp4 => Box::new(FfmpegEncoder::new(path, size, audio_rate, &["-c:v", "libx264", "-crf", "18"], "aac")?),
+        })
+    }
+}
+
+/// Turns the frames (and audio) the main core produces into a video file, a frame at a time.
+pub trait VideoEncoder {
+    /// Adds the next frame emulated (BGRA bytes, as the PPU draws them).
+    fn add_frame(&mut self, frame: &[u8]) -> Result<(), String>;
+
+    /// Adds samples produced alongside the frames; encoders without sound drop them.
+    fn add_audio(&mut self, _samples: &[[f32; 2]]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Finishes the file.
+    fn finish(self: Box<Self>) -> Result<(), String>;
+}
+
+/// Records the main core's frames (and audio, if its encoder takes any) to a new file in a
+/// directory, from start() until finish(). Frames are timed by emulated time, so clips play
+/// at the game's speed even if they were recorded fast-forwarding.
+pub struct VideoRecorder {
+    path: String,
+    encoder: Box<dyn VideoEncoder>,
+}
+
+impl VideoRecorder {
+    /// Starts recording frames of the given width and height as format into a timestamped
+    /// file in root, with audio at audio_rate if it's given (and the format has sound).
+    pub fn start(root: &str, format: VideoFormat, size: (usize, usize), audio_rate: Option<u32>) -> Result<Self, String> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        create_dir_all(root).map_err(|e| format!("Unable to create {}: {}", root, e))?;
+
+        let path = format!("{}/clip-{}.{}", root, timestamp, format.extension());
+        let encoder = format.encoder(&path, size, audio_rate)?;
+        println!("Recording video to {}", path);
+        Ok(VideoRecorder::new(path, encoder))
+    }
+
+    /// Records to path through encoder, for formats VideoFormat doesn't cover.
+    pub fn new(path: String, encoder: Box<dyn VideoEncoder>) -> Self {
+        VideoRecorder { path, encoder }
+    }
+
+    pub fn add_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        self.encoder.add_frame(frame)
+    }
+
+    pub fn add_audio(&mut self, samples: &[[f32; 2]]) -> Result<(), String> {
+        self.encoder.add_audio(samples)
+    }
+
+    /// Ends the recording and finishes the file; returns its path.
+    pub fn finish(self) -> Result<String, String> {
+        self.encoder.finish().map_err(|e| format!("Unable to write {}: {}", self.path, e))?;
+        println!("Saved video to {}", self.path);
+        Ok(self.path)
+    }
+}
+
+/// Returns the seconds one emulated frame lasts.
+fn frame_seconds() -> f64 {
+    FRAME_T_CYCLES as f64 / T_CYCLE_HZ as f64
+}
+
+/// Encodes an animated GIF that loops forever, each frame with the exact colours it has
+/// (quantized down to 256 if it has more, e.g. CGB games with many palettes).
+struct GifEncoder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    size: (usize, usize),
+    // frames added so far, and hundredths of a second the frames written so far are shown for
+    frames: u64,
+    shown: u64,
+}
+
+impl GifEncoder {
+    fn new(path: &str, (width, height): (usize, usize)) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path, e))?;
+        let mut encoder = gif::Encoder::new(BufWriter::new(file), width as u16, height as u16, &[])
+            .map_err(|e| e.to_string())?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+        Ok(GifEncoder { encoder, size: (width, height), frames: 0, shown: 0 })
+    }
+}
+
+impl VideoEncoder for GifEncoder {
+    fn add_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        self.frames += 1;
+        if !(self.frames - 1).is_multiple_of(GIF_FRAME_STEP) {
+            return Ok(());
+        }
+
+        let (width, height) = (self.size.0 as u16, self.size.1 as u16);
+        let mut rgba: Vec<u8> = frame.chunks_exact(BYTES_PER_PIXEL)
+            .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], 0xFF])
+            .collect();
+        let mut gif_frame = match palette_pixels(&rgba) {
+            Some((palette, pixels)) => gif::Frame::from_palette_pixels(width, height, pixels, palette, None),
+            None => gif::Frame::from_rgba_speed(width, height, &mut rgba, 10),
+        };
+
+        // rounding each frame's end rather than its length keeps delays from drifting
+        let end = ((self.frames - 1 + GIF_FRAME_STEP) as f64 * frame_seconds() * 100.0).round() as u64;
+        gif_frame.delay = (end - self.shown) as u16;
+        self.shown = end;
+        self.encoder.write_frame(&gif_frame).map_err(|e| e.to_string())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), String> {
+        let mut file = self.encoder.into_inner().map_err(|e| e.to_string())?;
+        file.flush().map_err(|e| e.to_string())
+    }
+}
+
+/// Returns rgba's colours (as RGB) and every pixel's index into them, or None if there are
+/// more than a GIF frame can hold.
+fn palette_pixels(rgba: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut colours: Vec<&[u8]> = Vec::new();
+    let mut pixels = Vec::with_capacity(rgba.len() / 4);
+    for pixel in rgba.chunks_exact(4) {
+        let colour = &pixel[..3];
+        let index = match colours.iter().position(|&known| known == colour) {
+            Some(index) => index,
+            None if colours.len() < 256 => {
+                colours.push(colour);
+                colours.len() - 1
+            },
+            None => return None,
+        };
+        pixels.push(index as u8);
+    }
+    Some((colours.concat(), pixels))
+}
+
+/// Pipes raw frames into an ffmpeg process encoding them with video_codec (ffmpeg arguments);
+/// audio is kept in a temporary file next to the video and muxed in with audio_codec once
+/// the video's done, as ffmpeg can only take one input through its stdin.
+struct FfmpegEncoder {
+    path: String,
+    ffmpeg: Child,
+    stdin: ChildStdin,
+    // the video before the audio's muxed in (path itself if there's no audio)
+    video_path: String,
+    // the temporary file of raw 32-bit float samples, its path, their rate and the codec
+    audio: Option<(BufWriter<File>, String, u32, &'static str)>,
+}
+
+impl FfmpegEncoder {
+    fn new(path: &str, (width, height): (usize, usize), audio_rate: Option<u32>, video_codec: &[&str], audio_codec: &'static str) -> Result<Self, String> {
+        let (video_path, audio) = match audio_rate {
+            Some(rate) => {
+                let audio_path = format!("{}.f32", path);
+                let file = File::create(&audio_path).map_err(|e| format!("Unable to create {}: {}", audio_path, e))?;
+                let extension = path.rsplit('.').next().unwrap_or_default();
+                (format!("{}.video.{}", path, extension), Some((BufWriter::new(file), audio_path, rate, audio_codec)))
+            },
+            None => (path.to_string(), None),
+        };
+
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-y", "-f", "rawvideo", "-pix_fmt", "bgra"])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-framerate", &format!("{:.4}", 1.0 / frame_seconds())])
+            .args(["-i", "-", "-vf", &format!("scale=iw*{0}:ih*{0}:flags=neighbor", FFMPEG_SCALE), "-pix_fmt", "yuv420p"])
+            .args(video_codec)
+            .arg(&video_path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Unable to run ffmpeg (needed to record WebM and MP4): {}", e))?;
+        let stdin = ffmpeg.stdin.take().unwrap();
+        Ok(FfmpegEncoder { path: path.to_string(), ffmpeg, stdin, video_path, audio })
+    }
+}
+
+impl VideoEncoder for FfmpegEncoder {
+    fn add_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        self.stdin.write_all(frame).map_err(|e| format!("ffmpeg stopped taking frames: {}", e))
+    }
+
+    fn add_audio(&mut self, samples: &[[f32; 2]]) -> Result<(), String> {
+        if let Some((file, audio_path, _, _)) = &mut self.audio {
+            for channel in samples.iter().flatten() {
+                file.write_all(&channel.to_le_bytes()).map_err(|e| format!("Unable to write {}: {}", audio_path, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), String> {
+        let FfmpegEncoder { path, mut ffmpeg, stdin, video_path, audio } = *self;
+        // closing its stdin is what tells ffmpeg the video's over
+        drop(stdin);
+        wait(&mut ffmpeg)?;
+
+        let (mut file, audio_path, rate, codec) = match audio {
+            Some(audio) => audio,
+            None => return Ok(()),
+        };
+        file.flush().map_err(|e| e.to_string())?;
+        let mut mux = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-y", "-i", &video_path])
+            .args(["-f", "f32le", "-ar", &rate.to_string(), "-ac", "2", "-i", &audio_path])
+            .args(["-c:v", "copy", "-c:a", codec, "-shortest", &path])
+            .spawn()
+            .map_err(|e| format!("Unable to run ffmpeg: {}", e))?;
+        let result = wait(&mut mux);
+        for temporary in [&video_path, &audio_path] {
+            let _ = remove_file(temporary);
+        }
+        result
+    }
+}
+
+fn wait(ffmpeg: &mut Child) -> Result<(), String> {
+    match ffmpeg.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("ffmpeg failed ({})", status)),
+        Err(e) => Err(format!("ffmpeg failed: {}", e)),
+    }
+}
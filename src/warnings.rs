@@ -0,0 +1,90 @@
+use alloc::{string::{String, ToString}, vec::Vec};
+use core::fmt::Write;
+
+use crate::events::Timestamp;
+
+// distinct warnings kept per session, later ones are only counted
+const MAX_WARNINGS: usize = 100;
+
+/// A non-fatal emulation problem, e.g. a write to an unmapped register.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub message: String,
+    /// How many times it happened this session.
+    pub count: u64,
+    /// When it first happened.
+    pub first_seen: Timestamp,
+}
+
+/// Non-fatal warnings raised while a game runs, kept once each (with how often they happened)
+/// so they can be looked at whenever, instead of flooding the console.
+#[derive(Default)]
+pub struct Warnings {
+    warnings: Vec<Warning>,
+    // warnings not kept once MAX_WARNINGS was reached
+    dropped: u64,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Warnings::default()
+    }
+
+    /// Records message, happening at now.
+    pub fn warn(&mut self, message: &str, now: Timestamp) {
+        if let Some(warning) = self.warnings.iter_mut().find(|warning| warning.message == message) {
+            warning.count += 1;
+        } else if self.warnings.len() < MAX_WARNINGS {
+            self.warnings.push(Warning { message: message.to_string(), count: 1, first_seen: now });
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    /// Returns every warning, oldest first.
+    pub fn list(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Returns the warnings as text, one per line with when it first happened and how often.
+    pub fn report(&self) -> String {
+        if self.warnings.is_empty() {
+            return String::from("No emulation warnings this session");
+        }
+
+        let mut report = String::new();
+        for warning in &self.warnings {
+            let seconds = warning.first_seen.as_duration().as_secs_f64();
+            let _ = writeln!(report, "[{:9.3}s] {} (x{})", seconds, warning.message, warning.count);
+        }
+        if self.dropped > 0 {
+            let _ = writeln!(report, "{} more warnings were dropped", self.dropped);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Warnings, MAX_WARNINGS};
+    use crate::events::Timestamp;
+
+    #[test]
+    fn warnings_test() {
+        let mut warnings = Warnings::new();
+        warnings.warn("Write to unmapped register FF7F", Timestamp(4));
+        warnings.warn("Write to unmapped register FF7F", Timestamp(8));
+        warnings.warn("Write to unmapped register FF03", Timestamp(12));
+
+        assert_eq!(warnings.list().len(), 2);
+        assert_eq!(warnings.list()[0].count, 2);
+        assert_eq!(warnings.list()[0].first_seen, Timestamp(4));
+        assert!(warnings.report().lines().next().unwrap().ends_with("FF7F (x2)"));
+
+        for i in 0..MAX_WARNINGS {
+            warnings.warn(&i.to_string(), Timestamp(16));
+        }
+        assert_eq!(warnings.list().len(), MAX_WARNINGS);
+        assert!(warnings.report().ends_with("2 more warnings were dropped\n"));
+    }
+}
@@ -10,11 +10,7 @@ const initializeAutoSave = () => {
     const autoSaveToggle = document.getElementById("auto-save-toggle");
 
     const enableAutoSave = () => {
-        autoSave = setInterval(() => {
-            if (window.emulator != null) {
-                window.emulator.save_game();
-            }
-        }, SAVE_INTERVAL_MS);
+        autoSave = setInterval(() => GBEmulator.autoSave(), SAVE_INTERVAL_MS);
         autoSaveToggle.textContent = "Autosave: Enabled";
     }
 
@@ -64,9 +60,10 @@ const initializeButtons = () => {
         GBEmulator.loadRom(fileInput.files[0]);
     });
 
-    document.getElementById("export-save-button").addEventListener("click", () => {
-        if (window.emulator != null) {
-            exportSaveFromDB(window.emulator.fetch_game_id());
+    document.getElementById("export-save-button").addEventListener("click", async () => {
+        const gameId = await GBEmulator.fetchGameId();
+        if (gameId != null) {
+            exportSaveFromDB(gameId);
         }
     })
 
@@ -54,6 +54,13 @@ export const GBAudio = (() => {
             audioNode.port.postMessage(audioOutput.map(sample => sample * audioVolume)); 
         },
 
+        // audioOutput is a Float32Array of interleaved stereo samples (see Emulator::take_audio)
+        pushAudioArray: (audioOutput) => {
+            if (audioNode != null && audioOutput.length > 0) {
+                audioNode.port.postMessage(audioOutput.map(sample => sample * audioVolume));
+            }
+        },
+
         clearAudio: () => {
             if (audioNode != null) {
                 audioNode.port.postMessage('clearBuffer');
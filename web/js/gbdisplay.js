@@ -19,6 +19,17 @@ export const GBDisplay = (() => {
     ctx.imageSmoothingEnabled = false;
     ctx.scale(CANVAS_SCALE, CANVAS_SCALE);
 
+    const drawImageData = (imageData) => {
+        let tempCanvas = document.createElement('canvas');
+        tempCanvas.width = WIDTH;
+        tempCanvas.height = HEIGHT;
+        let tempCtx = tempCanvas.getContext('2d');
+
+        tempCtx.putImageData(imageData, 0, 0);
+        ctx.clearRect(0, 0, ctx.canvas.width, ctx.canvas.height);
+        ctx.drawImage(tempCanvas, 0, 0);
+    };
+
     return {
         updateCanvas: (displayOutputPtr) => {
             const display_output = new Uint8Array(
@@ -27,10 +38,6 @@ export const GBDisplay = (() => {
                 WIDTH * HEIGHT * DISPLAY_BYTE_LEN
             );
             
-            let tempCanvas = document.createElement('canvas');
-            tempCanvas.width = WIDTH;
-            tempCanvas.height = HEIGHT;
-            let tempCtx = tempCanvas.getContext('2d');
             let imageData = ctx.createImageData(WIDTH, HEIGHT);
             let data = imageData.data;
     
@@ -45,9 +52,12 @@ export const GBDisplay = (() => {
                 }
             }
     
-            tempCtx.putImageData(imageData, 0, 0);
-            ctx.clearRect(0, 0, ctx.canvas.width, ctx.canvas.height);
-            ctx.drawImage(tempCanvas, 0, 0);
+            drawImageData(imageData);
+        },
+
+        // frame is already in RGBA order (see Emulator::run_frame)
+        drawFrame: (frame) => {
+            drawImageData(new ImageData(new Uint8ClampedArray(frame.buffer), WIDTH, HEIGHT));
         },
 
         clearCanvas: () => {   
@@ -5,11 +5,47 @@ import { GBAudio } from "./gbaudio.js";
 
 export const DEFAULT_GAME_SPEED = 0.3;
 
+// open the page with ?worker to run the emulator inside a Web Worker,
+// keeping the main thread free for rendering and input
+export const USE_WORKER = new URLSearchParams(window.location.search).has("worker");
+
 export const GBEmulator = (() => {
     let stopMainLoop = true;
     let paused = false;
     let gameSpeed = DEFAULT_GAME_SPEED;
 
+    let worker = null;
+    let gameIdRequests = [];
+
+    if (USE_WORKER) {
+        worker = new Worker(new URL("./gbworker.js", import.meta.url));
+        worker.postMessage({ type: "speed", speed: gameSpeed });
+
+        worker.onmessage = (e) => {
+            const message = e.data;
+
+            switch (message.type) {
+                case "frame":
+                    GBDisplay.drawFrame(message.frame);
+                    GBAudio.pushAudioArray(message.audio);
+                    break;
+                case "gameId":
+                    gameIdRequests.forEach((resolve) => resolve(message.gameId));
+                    gameIdRequests = [];
+                    break;
+                case "error":
+                    console.error('Error instantiating Emulator:', message.error);
+                    alert(`Unable to load ROM file: ${message.error}`);
+                    break;
+            }
+        };
+
+        GBInput.setHandlers({
+            onKeyStatus: (keyStatus) => worker.postMessage({ type: "joypad", status: keyStatus }),
+            onPaletteHotkey: () => worker.postMessage({ type: "palette" }),
+        });
+    }
+
     const mainLoop = () => {
         if (stopMainLoop) {
             return;
@@ -57,6 +93,12 @@ export const GBEmulator = (() => {
             let reader = new FileReader();
             reader.readAsArrayBuffer(rom_file);
             reader.onload = (e) => {
+                if (worker != null) {
+                    let byteArray = new Uint8Array(e.target.result);
+                    worker.postMessage({ type: "load", rom: byteArray }, [byteArray.buffer]);
+                    return;
+                }
+
                 if (window.emulator != null) {
                     window.emulator.save_game();
                 }
@@ -68,7 +110,7 @@ export const GBEmulator = (() => {
                     window.emulator = Emulator.new(byteArray);
                 } catch (error) {
                     console.error('Error instantiating Emulator:', error);
-                    alert("Unable to load ROM file :(")
+                    alert(`Unable to load ROM file: ${error}`);
                     return;
                 }
 
@@ -79,10 +121,41 @@ export const GBEmulator = (() => {
 
         setPaused: (newPaused) => {
             paused = newPaused;
+            worker?.postMessage({ type: "paused", paused });
         },
 
         setGameSpeed: (newGameSpeed) => {
             gameSpeed = newGameSpeed;
+            worker?.postMessage({ type: "speed", speed: gameSpeed });
+        },
+
+        saveGame: () => {
+            if (worker != null) {
+                worker.postMessage({ type: "save" });
+            } else if (window.emulator != null) {
+                window.emulator.save_game();
+            }
+        },
+
+        // saves only if the game wrote to its save since the last autosave
+        autoSave: () => {
+            if (worker != null) {
+                worker.postMessage({ type: "autosave" });
+            } else if (window.emulator?.take_ram_dirty()) {
+                window.emulator.save_game();
+            }
+        },
+
+        // resolves to the current ROM's save id (undefined if no ROM is loaded)
+        fetchGameId: () => {
+            if (worker == null) {
+                return Promise.resolve(window.emulator?.fetch_game_id());
+            }
+
+            return new Promise((resolve) => {
+                gameIdRequests.push(resolve);
+                worker.postMessage({ type: "gameId" });
+            });
         }
     }
 })();
\ No newline at end of file
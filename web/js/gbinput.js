@@ -11,14 +11,31 @@ export const GBInput = (() => {
         'ArrowRight',
     ];
 
+    // switches to the next DMG palette
+    const PALETTE_HOTKEY = 'p';
+
     let keyStatus = 0xFF;
 
+    let handlers = {
+        onKeyStatus: (_keyStatus) => {},
+        onPaletteHotkey: () => {
+            if (window.emulator != null) {
+                window.emulator.cycle_palette();
+            }
+        },
+    };
+
     window.addEventListener('keydown', (event) => {
+        if (event.key == PALETTE_HOTKEY && !event.repeat) {
+            handlers.onPaletteHotkey();
+        }
+
         for (let i = 0; i < 8; i++) {
             if (event.key == KEYMAPPINGS[i]) {
                 keyStatus &= ~(1 << (7 - i));
             }
         }
+        handlers.onKeyStatus(keyStatus);
     });
 
     window.addEventListener('keyup', (event) => {
@@ -27,11 +44,17 @@ export const GBInput = (() => {
                 keyStatus |= 1 << (7 - i)
             }
         }
+        handlers.onKeyStatus(keyStatus);
     });
 
     return {
         getKeyStatus: () => {
             return keyStatus;
         },
+
+        // replaces any of onKeyStatus(keyStatus) and onPaletteHotkey()
+        setHandlers: (newHandlers) => {
+            handlers = { ...handlers, ...newHandlers };
+        },
     }
 })();
\ No newline at end of file
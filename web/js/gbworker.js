@@ -0,0 +1,69 @@
+// Runs the emulator off the main thread; frames and audio are posted back as transferable buffers.
+import { Emulator } from "gbemulib";
+import "./persistence.js";
+
+let stopMainLoop = true;
+let paused = false;
+let gameSpeed = 0;
+let keyStatus = 0xFF;
+
+const mainLoop = () => {
+    if (stopMainLoop) {
+        return;
+    }
+
+    if (!paused) {
+        const frame = self.emulator.run_frame(keyStatus);
+        const audio = self.emulator.take_audio();
+        self.postMessage({ type: "frame", frame, audio }, [frame.buffer, audio.buffer]);
+    }
+
+    setTimeout(mainLoop, (1000 / 60) * (1 - gameSpeed));
+};
+
+self.onmessage = (e) => {
+    const message = e.data;
+
+    switch (message.type) {
+        case "load":
+            if (self.emulator != null) {
+                self.emulator.save_game();
+            }
+
+            try {
+                self.emulator = Emulator.new(message.rom);
+            } catch (error) {
+                self.postMessage({ type: "error", error: String(error) });
+                return;
+            }
+
+            if (stopMainLoop) {
+                stopMainLoop = false;
+                mainLoop();
+            }
+            break;
+        case "joypad":
+            keyStatus = message.status;
+            break;
+        case "paused":
+            paused = message.paused;
+            break;
+        case "speed":
+            gameSpeed = message.speed;
+            break;
+        case "save":
+            self.emulator?.save_game();
+            break;
+        case "autosave":
+            if (self.emulator?.take_ram_dirty()) {
+                self.emulator.save_game();
+            }
+            break;
+        case "palette":
+            self.emulator?.cycle_palette();
+            break;
+        case "gameId":
+            self.postMessage({ type: "gameId", gameId: self.emulator?.fetch_game_id() });
+            break;
+    }
+};
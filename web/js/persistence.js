@@ -67,12 +67,12 @@ const writeToSaveDB = async (gameId, saveType, saveData) => {
 }
 
 
-window.Persistence = {
+globalThis.Persistence = {
     load_from_db: async (gameId, saveType) => {
         try {
             const romSave = await readFromSaveDB(gameId, saveType);
             console.log("loading from: ", parseKeyName(gameId, saveType), romSave);
-            window.emulator.load_save(new Uint8Array(romSave), saveType);
+            globalThis.emulator.load_save(new Uint8Array(romSave), saveType);
         } catch (error) {
             console.log(error);
         }
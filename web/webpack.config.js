@@ -8,6 +8,13 @@ module.exports = {
     filename: "index.js",
   },
   mode: "development",
+  resolve: {
+    // the bindings `npm run wasm` generates in ../pkg
+    alias: {
+      gbemulib$: path.resolve(__dirname, "../pkg/gbemulib.js"),
+      gbemulib: path.resolve(__dirname, "../pkg"),
+    },
+  },
   experiments: {
     asyncWebAssembly: true,
   },